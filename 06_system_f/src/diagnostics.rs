@@ -1,4 +1,4 @@
-use util::span::Span;
+use util::span::{SourceMap, Span};
 #[derive(Debug, Copy, Clone)]
 pub enum Level {
     Warn,
@@ -14,6 +14,9 @@ pub struct Annotation {
 #[derive(Debug, Clone)]
 pub struct Diagnostic {
     pub level: Level,
+    /// Identifies the kind of problem, for tools reading diagnostics. See
+    /// [`TypeErrorKind::code`](crate::types::TypeErrorKind::code)
+    pub code: Option<u16>,
     pub primary: Annotation,
     pub info: Vec<String>,
     pub other: Vec<Annotation>,
@@ -32,6 +35,7 @@ impl Diagnostic {
     pub fn error<S: Into<String>>(span: Span, message: S) -> Diagnostic {
         Diagnostic {
             level: Level::Error,
+            code: None,
             primary: Annotation::new(span, message),
             other: Vec::new(),
             info: Vec::new(),
@@ -41,12 +45,18 @@ impl Diagnostic {
     pub fn warn<S: Into<String>>(span: Span, message: S) -> Diagnostic {
         Diagnostic {
             level: Level::Warn,
+            code: None,
             primary: Annotation::new(span, message),
             other: Vec::new(),
             info: Vec::new(),
         }
     }
 
+    pub fn code(mut self, code: u16) -> Diagnostic {
+        self.code = Some(code);
+        self
+    }
+
     pub fn message<S: Into<String>>(mut self, span: Span, message: S) -> Diagnostic {
         self.other.push(Annotation::new(span, message));
         self
@@ -73,4 +83,98 @@ impl Diagnostic {
         }
         range
     }
+
+    /// Write the diagnostic as a single line JSON object, for tools. Lines
+    /// and columns count from 1, and each span ends just after its last
+    /// character. The secondary messages and the notes follow in `notes`,
+    /// the former with positions of their own
+    pub fn to_json(&self, files: &SourceMap) -> String {
+        let level = match self.level {
+            Level::Warn => "warning",
+            Level::Error => "error",
+        };
+        let code = match self.code {
+            Some(code) => format!("\"E{:04}\"", code),
+            None => "null".into(),
+        };
+        let notes = self
+            .other
+            .iter()
+            .map(|anno| {
+                format!(
+                    "{{{},\"message\":{}}}",
+                    json_span(files, anno.span),
+                    json_string(&anno.info)
+                )
+            })
+            .chain(
+                self.info
+                    .iter()
+                    .map(|info| format!("{{\"message\":{}}}", json_string(info))),
+            )
+            .collect::<Vec<_>>();
+        format!(
+            "{{{},\"severity\":\"{}\",\"code\":{},\"message\":{},\"notes\":[{}]}}",
+            json_span(files, self.primary.span),
+            level,
+            code,
+            json_string(&self.primary.info),
+            notes.join(",")
+        )
+    }
+}
+
+fn json_span(files: &SourceMap, span: Span) -> String {
+    format!(
+        "\"file\":{},\"start_line\":{},\"start_col\":{},\"end_line\":{},\"end_col\":{}",
+        json_string(files.name(span.file)),
+        span.start.line + 1,
+        span.start.col + 1,
+        span.end.line + 1,
+        span.end.col + 1
+    )
+}
+
+/// A JSON string literal holding `s`
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            ch if (ch as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => out.push(ch),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use util::span::Location;
+
+    #[test]
+    fn json() {
+        let mut files = SourceMap::default();
+        let file = files.add("a \"quoted\" name.sf", "succ true");
+        let span = Span::new(Location::new(0, 0, 0), Location::new(0, 9, 9)).in_file(file);
+        let arg = Span::new(Location::new(0, 5, 5), Location::new(0, 9, 9)).in_file(file);
+        let diag = Diagnostic::error(span, "bad\targument\n`x`\\")
+            .code(101)
+            .message(arg, "this")
+            .info("\u{1}");
+        assert_eq!(
+            diag.to_json(&files),
+            r#"{"file":"a \"quoted\" name.sf","start_line":1,"start_col":1,"end_line":1,"end_col":10,"severity":"error","code":"E0101","message":"bad\targument\n`x`\\","notes":[{"file":"a \"quoted\" name.sf","start_line":1,"start_col":6,"end_line":1,"end_col":10,"message":"this"},{"message":"\u0001"}]}"#
+        );
+        assert!(Diagnostic::warn(span, "w")
+            .to_json(&files)
+            .contains(r#""severity":"warning","code":null,"#));
+    }
 }
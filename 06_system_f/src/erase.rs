@@ -0,0 +1,332 @@
+//! Type erasure, compiling typechecked terms into an untyped lambda calculus
+//! (TAPL §23.7), along with an evaluator for untyped terms
+//!
+//! Type abstractions and applications, `fold`/`unfold` and existential
+//! packages have no runtime content and are erased to the terms they wrap.
+//! Let bindings are compiled into single-armed case expressions.
+use crate::match_compile::{compile, Decision, Scrutinee};
+use crate::patterns::{PatVarStack, Pattern};
+use crate::terms::{Kind, Literal, Primitive, Term};
+use std::fmt;
+use std::rc::Rc;
+
+/// Untyped term, using de Bruijn indices for variables
+#[derive(Clone, Debug, PartialEq)]
+pub enum UTerm {
+    Lit(Literal),
+    Var(usize),
+    Primitive(Primitive),
+    Abs(Box<UTerm>),
+    App(Box<UTerm>, Box<UTerm>),
+    Fix(Box<UTerm>),
+    Injection(String, Box<UTerm>),
+    Product(Vec<UTerm>),
+    Projection(Box<UTerm>, usize),
+    /// Case arms are a pattern, an optional guard, and a body. The arms are
+    /// selected by walking the decision tree compiled from their patterns
+    Case(Box<UTerm>, Vec<(Pattern, Option<UTerm>, UTerm)>, Decision),
+    /// Raise an error carrying the value of the term
+    Raise(Box<UTerm>),
+}
+
+/// Erase all type information from a term
+pub fn erase(term: &Term) -> UTerm {
+    let e = |tm: &Term| Box::new(erase(tm));
+    match &term.kind {
+        Kind::Lit(lit) => UTerm::Lit(lit.clone()),
+        Kind::Var(idx) => UTerm::Var(*idx),
+        Kind::Primitive(p) => UTerm::Primitive(*p),
+        Kind::Abs(_, body) => UTerm::Abs(e(body)),
+        Kind::App(t1, t2) => UTerm::App(e(t1), e(t2)),
+        Kind::Fix(tm) => UTerm::Fix(e(tm)),
+        Kind::Injection(label, tm, _) => UTerm::Injection(label.clone(), e(tm)),
+        Kind::Product(terms) => UTerm::Product(terms.iter().map(erase).collect()),
+        Kind::Projection(tm, idx) => UTerm::Projection(e(tm), *idx),
+        Kind::Case(tm, arms) => UTerm::Case(
+            e(tm),
+            arms.iter()
+                .map(|arm| (arm.pat.clone(), arm.guard.as_deref().map(erase), erase(&arm.term)))
+                .collect(),
+            compile(
+                &arms
+                    .iter()
+                    .map(|arm| (&arm.pat, arm.guard.is_some()))
+                    .collect::<Vec<_>>(),
+            ),
+        ),
+        Kind::Let(pat, t1, t2) => UTerm::Case(
+            e(t1),
+            vec![(*pat.clone(), None, erase(t2))],
+            compile(&[(pat.as_ref(), false)]),
+        ),
+        Kind::TyAbs(tm) | Kind::TyApp(tm, _) | Kind::Fold(_, tm) | Kind::Unfold(_, tm) | Kind::Pack(_, tm, _) => {
+            erase(tm)
+        }
+        // The package is bound as a term variable in the body
+        Kind::Unpack(package, body) => UTerm::App(Box::new(UTerm::Abs(e(body))), e(package)),
+        Kind::Raise(_, tm) => UTerm::Raise(e(tm)),
+        Kind::Share(tm) => erase(tm),
+    }
+}
+
+/// Runtime value of an untyped term
+#[derive(Clone, Debug)]
+pub enum Value {
+    Lit(Literal),
+    Closure(Env, Rc<UTerm>),
+    /// A primitive function, and the arguments it has been applied to so far
+    Primitive(Primitive, Vec<Value>),
+    Injection(String, Box<Value>),
+    Product(Vec<Value>),
+    /// Recursive binding introduced by `fix`, unrolled when it is looked up
+    Rec(Env, Rc<UTerm>),
+}
+
+/// Environment of values for bound variables. The most recently bound
+/// variable, de Bruijn index 0, is last
+pub type Env = Vec<Value>;
+
+/// Evaluate a closed untyped term using call-by-value, returning `None` if
+/// evaluation gets stuck or raises an error
+pub fn eval(term: &UTerm) -> Option<Value> {
+    eval_in(&Vec::new(), term)
+}
+
+fn eval_in(env: &Env, term: &UTerm) -> Option<Value> {
+    match term {
+        UTerm::Lit(lit) => Some(Value::Lit(lit.clone())),
+        UTerm::Var(idx) => match env.get(env.len().checked_sub(idx + 1)?)? {
+            Value::Rec(env, body) => unroll(env, body),
+            val => Some(val.clone()),
+        },
+        UTerm::Primitive(p) => Some(Value::Primitive(*p, Vec::new())),
+        UTerm::Abs(body) => Some(Value::Closure(env.clone(), Rc::new(*body.clone()))),
+        UTerm::App(t1, t2) => {
+            let f = eval_in(env, t1)?;
+            let arg = eval_in(env, t2)?;
+            apply(f, arg)
+        }
+        UTerm::Fix(tm) => match eval_in(env, tm)? {
+            Value::Closure(env, body) => unroll(&env, &body),
+            _ => None,
+        },
+        UTerm::Injection(label, tm) => Some(Value::Injection(label.clone(), Box::new(eval_in(env, tm)?))),
+        UTerm::Product(terms) => Some(Value::Product(
+            terms.iter().map(|tm| eval_in(env, tm)).collect::<Option<_>>()?,
+        )),
+        UTerm::Projection(tm, idx) => match eval_in(env, tm)? {
+            Value::Product(mut vals) if *idx < vals.len() => Some(vals.swap_remove(*idx)),
+            _ => None,
+        },
+        // Raised errors have no value
+        UTerm::Raise(tm) => {
+            eval_in(env, tm)?;
+            None
+        }
+        UTerm::Case(tm, arms, tree) => {
+            let val = eval_in(env, tm)?;
+            let mut tree = tree;
+            loop {
+                let (arm, otherwise) = tree.walk(&val)?;
+                let (pat, guard, body) = &arms[arm];
+                let mut binds = Vec::new();
+                if !bind(pat, &val, &mut binds) {
+                    return None;
+                }
+                let mut env = env.clone();
+                // The first variable in the pattern is innermost
+                env.extend(binds.into_iter().map(|(_, val)| val).rev());
+                if let Some(guard) = guard {
+                    match eval_in(&env, guard)? {
+                        Value::Lit(Literal::Bool(true)) => {}
+                        Value::Lit(Literal::Bool(false)) => {
+                            tree = otherwise?;
+                            continue;
+                        }
+                        _ => return None,
+                    }
+                }
+                return eval_in(&env, body);
+            }
+        }
+    }
+}
+
+/// Evaluate the body of `fix (λ. body)`, binding the recursive occurrence
+fn unroll(env: &Env, body: &Rc<UTerm>) -> Option<Value> {
+    let mut env = env.clone();
+    env.push(Value::Rec(env.clone(), body.clone()));
+    eval_in(&env, body)
+}
+
+fn apply(f: Value, arg: Value) -> Option<Value> {
+    match f {
+        Value::Closure(mut env, body) => {
+            env.push(arg);
+            eval_in(&env, &body)
+        }
+        Value::Primitive(p, mut args) => {
+            args.push(arg);
+            if args.len() < p.arity() {
+                return Some(Value::Primitive(p, args));
+            }
+            let lits = args
+                .into_iter()
+                .map(|v| match v {
+                    Value::Lit(lit) => Some(lit),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()?;
+            p.apply(&lits).map(Value::Lit)
+        }
+        _ => None,
+    }
+}
+
+/// Match a value against a pattern, collecting the values bound by each
+/// variable in the pattern from left to right
+pub(crate) fn bind<'p, V: Scrutinee>(pat: &'p Pattern, val: &V, binds: &mut Vec<(&'p str, V)>) -> bool {
+    match pat {
+        Pattern::Any => true,
+        Pattern::Variable(name) => {
+            binds.push((name, val.clone()));
+            true
+        }
+        Pattern::As(pat, name) => {
+            if !bind(pat, val, binds) {
+                return false;
+            }
+            binds.push((name, val.clone()));
+            true
+        }
+        Pattern::Or(alts) => {
+            let mut found = Vec::new();
+            if !alts.iter().any(|alt| {
+                found.clear();
+                bind(alt, val, &mut found)
+            }) {
+                return false;
+            }
+            // Every alternative binds the same variables, in the order of the
+            // first alternative
+            for name in PatVarStack::collect(&mut alts[0].clone()) {
+                match found.iter().position(|(n, _)| *n == name) {
+                    Some(idx) => binds.push(found.remove(idx)),
+                    None => return false,
+                }
+            }
+            true
+        }
+        Pattern::Literal(l) => val.literal() == Some(l),
+        Pattern::Succ(pat) => match val.literal() {
+            Some(Literal::Nat(n)) => *n > 0 && bind(pat, &V::nat(n - 1), binds),
+            _ => false,
+        },
+        Pattern::Product(pats) => match val.product() {
+            Some(vals) => pats.len() == vals.len() && pats.iter().zip(vals).all(|(p, v)| bind(p, v, binds)),
+            None => false,
+        },
+        Pattern::Constructor(label, pat) => match val.injection() {
+            Some((label_, val)) => label == label_ && bind(pat, val, binds),
+            None => false,
+        },
+    }
+}
+
+impl fmt::Display for UTerm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UTerm::Lit(lit) => write!(f, "{}", lit),
+            UTerm::Var(v) => write!(f, "#{}", v),
+            UTerm::Primitive(p) => write!(f, "{:?}", p),
+            UTerm::Abs(body) => write!(f, "(λ. {})", body),
+            UTerm::App(t1, t2) => write!(f, "({} {})", t1, t2),
+            UTerm::Fix(tm) => write!(f, "Fix {}", tm),
+            UTerm::Injection(label, tm) => match tm.as_ref() {
+                UTerm::Lit(Literal::Unit) => write!(f, "{}", label),
+                _ => write!(f, "{}({})", label, tm),
+            },
+            UTerm::Product(terms) => write!(
+                f,
+                "({})",
+                terms.iter().map(|t| t.to_string()).collect::<Vec<String>>().join(",")
+            ),
+            UTerm::Projection(tm, idx) => write!(f, "{}.{}", tm, idx),
+            UTerm::Raise(tm) => write!(f, "raise {}", tm),
+            UTerm::Case(tm, arms, _) => {
+                write!(f, "case {} of", tm)?;
+                for (pat, guard, arm) in arms {
+                    match guard {
+                        Some(guard) => write!(f, " | {:?} when {} => {}", pat, guard, arm)?,
+                        None => write!(f, " | {:?} => {}", pat, arm)?,
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::eval::Eval;
+    use crate::syntax::parser::{self, Parser};
+    use crate::terms::visit::InjRewriter;
+    use crate::types::Context;
+    use crate::visit::MutTermVisitor;
+
+    #[test]
+    fn erase_types() {
+        let mut p = Parser::new("(\\X \\x: X. x) [Nat] 1");
+        let tm = p.parse().unwrap();
+        let erased = erase(&tm);
+        assert_eq!(erased.to_string(), "((λ. #0) 1)");
+        match eval(&erased) {
+            Some(Value::Lit(Literal::Nat(1))) => {}
+            val => panic!("{:?}", val),
+        }
+    }
+
+    /// Evaluation before and after erasure must agree on every observable
+    /// value in the test corpus
+    #[test]
+    fn differential() {
+        let mut ctx = Context::default();
+        ctx.alias("Var".into(), crate::test_variant()).unwrap();
+        ctx.alias("NatList".into(), crate::nat_list()).unwrap();
+        ctx.alias("NB".into(), crate::nat_list2()).unwrap();
+
+        let mut p = Parser::new(include_str!("../test.sf"));
+        let mut checked = 0;
+        loop {
+            let mut term = match p.parse() {
+                Ok(term) => term,
+                Err(parser::Error {
+                    kind: parser::ErrorKind::Eof,
+                    ..
+                }) => break,
+                Err(e) => panic!("{:?}", e),
+            };
+            ctx.de_alias(&mut term);
+            InjRewriter.visit(&mut term);
+            if ctx.type_check(&term).is_err() {
+                continue;
+            }
+
+            let erased = erase(&term);
+            let ev = Eval::with_context(&ctx);
+            while let Some(next) = ev.small_step(term.clone()) {
+                term = next;
+            }
+            if let Kind::Lit(lit) = term.kind {
+                match eval(&erased) {
+                    Some(Value::Lit(lit_)) => assert_eq!(lit, lit_, "{}", erased),
+                    val => panic!("{} evaluated to {:?}, expected {}", erased, val, lit),
+                }
+                checked += 1;
+            }
+        }
+        assert!(checked > 0);
+    }
+}
@@ -0,0 +1,735 @@
+//! Compact binary encoding of closed values, so that evaluated definitions
+//! can be saved and loaded again without evaluating them
+//!
+//! A value is written as a pre-order walk of its nodes, each one a tag byte
+//! followed by its fields. Numbers and lengths are LEB128 varints, and
+//! strings are a length followed by UTF-8. Spans are not written, so every
+//! decoded node has a dummy span, and a shared term is written out in full
+//! each time it appears.
+use crate::patterns::{Pattern, PatternCount};
+use crate::terms::locate::KindTag;
+use crate::terms::visit::Closed;
+use crate::terms::{Arm, Kind, Literal, Primitive, Term};
+use crate::types::{Type, Variant};
+use std::convert::TryFrom;
+use std::fmt;
+use util::span::Span;
+
+/// Kinds of terms in the order of their tags
+const KINDS: [KindTag; 18] = [
+    KindTag::Lit,
+    KindTag::Var,
+    KindTag::Fix,
+    KindTag::Primitive,
+    KindTag::Injection,
+    KindTag::Product,
+    KindTag::Projection,
+    KindTag::Case,
+    KindTag::Let,
+    KindTag::Abs,
+    KindTag::App,
+    KindTag::TyAbs,
+    KindTag::TyApp,
+    KindTag::Fold,
+    KindTag::Unfold,
+    KindTag::Pack,
+    KindTag::Unpack,
+    KindTag::Raise,
+];
+
+const PRIMITIVES: [Primitive; 10] = [
+    Primitive::Succ,
+    Primitive::Pred,
+    Primitive::IsZero,
+    Primitive::Concat,
+    Primitive::StrLen,
+    Primitive::Add,
+    Primitive::Sub,
+    Primitive::Mul,
+    Primitive::Eq,
+    Primitive::Lt,
+];
+
+/// Terms nested deeper than this are rejected by [`decode`], rather than
+/// overflowing the stack
+pub const MAX_DEPTH: usize = 1_000;
+
+/// Reasons for which bytes do not decode to a value
+#[derive(Clone, Debug, PartialEq)]
+pub enum DecodeError {
+    /// The input ended in the middle of a value
+    UnexpectedEnd,
+    /// The byte at `offset` does not start a valid `what`
+    Invalid { offset: usize, what: &'static str },
+    /// A variable at `offset` is not bound by any enclosing binder
+    Unbound { offset: usize },
+    /// The term starting at `offset` is nested deeper than [`MAX_DEPTH`]
+    TooDeep { offset: usize },
+    /// The input holds a well-formed term that is not a value
+    NotAValue,
+    /// The value ends before the input does, at `offset`
+    TrailingBytes { offset: usize },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "unexpected end of input"),
+            DecodeError::Invalid { offset, what } => write!(f, "invalid {} at byte {}", what, offset),
+            DecodeError::Unbound { offset } => write!(f, "unbound variable at byte {}", offset),
+            DecodeError::TooDeep { offset } => write!(f, "term nested too deeply at byte {}", offset),
+            DecodeError::NotAValue => write!(f, "decoded term is not a value"),
+            DecodeError::TrailingBytes { offset } => write!(f, "unexpected bytes after the value at byte {}", offset),
+        }
+    }
+}
+
+/// Encode a closed value, or return `None` if the term is not one
+pub fn encode(term: &Term) -> Option<Vec<u8>> {
+    if !term.is_value() || !Closed::check(term) {
+        return None;
+    }
+    let mut enc = Encoder(Vec::new());
+    enc.term(term);
+    Some(enc.0)
+}
+
+/// Decode a value written by [`encode`]. The input is checked to be a single
+/// well-scoped value, but not that it is well-typed
+pub fn decode(bytes: &[u8]) -> Result<Term, DecodeError> {
+    let mut dec = Decoder {
+        bytes,
+        pos: 0,
+        depth: 0,
+        terms: 0,
+        types: 0,
+    };
+    let term = dec.term()?;
+    if dec.pos < bytes.len() {
+        return Err(DecodeError::TrailingBytes { offset: dec.pos });
+    }
+    match term.is_value() {
+        true => Ok(term),
+        false => Err(DecodeError::NotAValue),
+    }
+}
+
+struct Encoder(Vec<u8>);
+
+impl Encoder {
+    fn uint(&mut self, mut n: u64) {
+        loop {
+            let byte = (n & 0x7f) as u8;
+            n >>= 7;
+            if n == 0 {
+                self.0.push(byte);
+                return;
+            }
+            self.0.push(byte | 0x80);
+        }
+    }
+
+    fn str(&mut self, s: &str) {
+        self.uint(s.len() as u64);
+        self.0.extend_from_slice(s.as_bytes());
+    }
+
+    fn term(&mut self, term: &Term) {
+        let term = term.unshared();
+        let tag = KINDS.iter().position(|k| *k == term.kind.tag()).unwrap();
+        self.0.push(tag as u8);
+        match &term.kind {
+            Kind::Lit(lit) => self.lit(lit),
+            Kind::Var(v) => self.uint(*v as u64),
+            Kind::Primitive(p) => self.0.push(PRIMITIVES.iter().position(|q| q == p).unwrap() as u8),
+            Kind::Abs(ty, tm) | Kind::Fold(ty, tm) | Kind::Unfold(ty, tm) | Kind::Raise(ty, tm) => {
+                self.ty(ty);
+                self.term(tm);
+            }
+            Kind::Fix(tm) | Kind::TyAbs(tm) => self.term(tm),
+            Kind::Injection(label, tm, ty) => {
+                self.str(label);
+                self.term(tm);
+                self.ty(ty);
+            }
+            Kind::Product(terms) => {
+                self.uint(terms.len() as u64);
+                terms.iter().for_each(|tm| self.term(tm));
+            }
+            Kind::Projection(tm, idx) => {
+                self.term(tm);
+                self.uint(*idx as u64);
+            }
+            Kind::Case(expr, arms) => {
+                self.term(expr);
+                self.uint(arms.len() as u64);
+                for arm in arms {
+                    self.pat(&arm.pat);
+                    match &arm.guard {
+                        Some(guard) => {
+                            self.0.push(1);
+                            self.term(guard);
+                        }
+                        None => self.0.push(0),
+                    }
+                    self.term(&arm.term);
+                }
+            }
+            Kind::Let(pat, t1, t2) => {
+                self.pat(pat);
+                self.term(t1);
+                self.term(t2);
+            }
+            Kind::App(t1, t2) | Kind::Unpack(t1, t2) => {
+                self.term(t1);
+                self.term(t2);
+            }
+            Kind::TyApp(tm, ty) => {
+                self.term(tm);
+                self.ty(ty);
+            }
+            Kind::Pack(wit, tm, sig) => {
+                self.ty(wit);
+                self.term(tm);
+                self.ty(sig);
+            }
+            Kind::Share(_) => unreachable!("shared terms are written out"),
+        }
+    }
+
+    fn lit(&mut self, lit: &Literal) {
+        match lit {
+            Literal::Unit => self.0.push(0),
+            Literal::Bool(false) => self.0.push(1),
+            Literal::Bool(true) => self.0.push(2),
+            Literal::Nat(n) => {
+                self.0.push(3);
+                self.uint(*n as u64);
+            }
+            Literal::String(s) => {
+                self.0.push(4);
+                self.str(s);
+            }
+        }
+    }
+
+    fn ty(&mut self, ty: &Type) {
+        match ty {
+            Type::Unit => self.0.push(0),
+            Type::Nat => self.0.push(1),
+            Type::Bool => self.0.push(2),
+            Type::String => self.0.push(3),
+            Type::Alias(name) => {
+                self.0.push(4);
+                self.str(name);
+            }
+            Type::Var(v) => {
+                self.0.push(5);
+                self.uint(*v as u64);
+            }
+            Type::Variant(vs) => {
+                self.0.push(6);
+                self.uint(vs.len() as u64);
+                for v in vs {
+                    self.str(&v.label);
+                    self.ty(&v.ty);
+                }
+            }
+            Type::Product(tys) => {
+                self.0.push(7);
+                self.uint(tys.len() as u64);
+                tys.iter().for_each(|ty| self.ty(ty));
+            }
+            Type::Arrow(t1, t2) => {
+                self.0.push(8);
+                self.ty(t1);
+                self.ty(t2);
+            }
+            Type::Universal(ty) => {
+                self.0.push(9);
+                self.ty(ty);
+            }
+            Type::Existential(ty) => {
+                self.0.push(10);
+                self.ty(ty);
+            }
+            Type::Rec(ty) => {
+                self.0.push(11);
+                self.ty(ty);
+            }
+            Type::Error => self.0.push(12),
+            Type::Meta(m) => {
+                self.0.push(13);
+                self.uint(*m as u64);
+            }
+            Type::Hole => self.0.push(14),
+        }
+    }
+
+    fn pat(&mut self, pat: &Pattern) {
+        match pat {
+            Pattern::Any => self.0.push(0),
+            Pattern::Literal(lit) => {
+                self.0.push(1);
+                self.lit(lit);
+            }
+            Pattern::Variable(name) => {
+                self.0.push(2);
+                self.str(name);
+            }
+            Pattern::Product(pats) => {
+                self.0.push(3);
+                self.uint(pats.len() as u64);
+                pats.iter().for_each(|p| self.pat(p));
+            }
+            Pattern::Constructor(label, pat) => {
+                self.0.push(4);
+                self.str(label);
+                self.pat(pat);
+            }
+            Pattern::Or(alts) => {
+                self.0.push(5);
+                self.uint(alts.len() as u64);
+                alts.iter().for_each(|p| self.pat(p));
+            }
+            Pattern::As(pat, name) => {
+                self.0.push(6);
+                self.pat(pat);
+                self.str(name);
+            }
+            Pattern::Succ(pat) => {
+                self.0.push(7);
+                self.pat(pat);
+            }
+        }
+    }
+}
+
+struct Decoder<'b> {
+    bytes: &'b [u8],
+    pos: usize,
+    /// Number of terms, types and patterns being decoded
+    depth: usize,
+    /// Number of term and type variables in scope
+    terms: usize,
+    types: usize,
+}
+
+impl Decoder<'_> {
+    fn byte(&mut self) -> Result<u8, DecodeError> {
+        let b = *self.bytes.get(self.pos).ok_or(DecodeError::UnexpectedEnd)?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn invalid<T>(&self, offset: usize, what: &'static str) -> Result<T, DecodeError> {
+        Err(DecodeError::Invalid { offset, what })
+    }
+
+    fn uint(&mut self) -> Result<u64, DecodeError> {
+        let offset = self.pos;
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = self.byte()?;
+            n |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        self.invalid(offset, "number")
+    }
+
+    fn usize(&mut self) -> Result<usize, DecodeError> {
+        let offset = self.pos;
+        let n = self.uint()?;
+        usize::try_from(n).or_else(|_| self.invalid(offset, "number"))
+    }
+
+    /// A length of a sequence whose elements take at least a byte each
+    fn len(&mut self) -> Result<usize, DecodeError> {
+        let len = self.usize()?;
+        match len <= self.bytes.len() - self.pos {
+            true => Ok(len),
+            false => Err(DecodeError::UnexpectedEnd),
+        }
+    }
+
+    fn str(&mut self) -> Result<String, DecodeError> {
+        let offset = self.pos;
+        let len = self.len()?;
+        let bytes = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        match std::str::from_utf8(bytes) {
+            Ok(s) => Ok(s.to_string()),
+            Err(_) => self.invalid(offset, "string"),
+        }
+    }
+
+    /// Decode a nested part of the input, failing if it is too deep
+    fn nested<T, F>(&mut self, f: F) -> Result<T, DecodeError>
+    where
+        F: FnOnce(&mut Self) -> Result<T, DecodeError>,
+    {
+        if self.depth == MAX_DEPTH {
+            return Err(DecodeError::TooDeep { offset: self.pos });
+        }
+        self.depth += 1;
+        let out = f(self);
+        self.depth -= 1;
+        out
+    }
+
+    /// Decode a term under `terms` more term and `types` more type binders
+    fn under(&mut self, terms: usize, types: usize) -> Result<Term, DecodeError> {
+        self.terms += terms;
+        self.types += types;
+        let tm = self.term();
+        self.terms -= terms;
+        self.types -= types;
+        tm
+    }
+
+    fn boxed(&mut self) -> Result<Box<Term>, DecodeError> {
+        self.term().map(Box::new)
+    }
+
+    fn term(&mut self) -> Result<Term, DecodeError> {
+        self.nested(|dec| {
+            let kind = dec.kind()?;
+            Ok(Term::new(kind, Span::dummy()))
+        })
+    }
+
+    fn kind(&mut self) -> Result<Kind, DecodeError> {
+        let offset = self.pos;
+        let tag = self.byte()?;
+        let tag = match KINDS.get(tag as usize) {
+            Some(tag) => *tag,
+            None => return self.invalid(offset, "term"),
+        };
+        Ok(match tag {
+            KindTag::Lit => Kind::Lit(self.lit()?),
+            KindTag::Var => match self.usize()? {
+                v if v < self.terms => Kind::Var(v),
+                _ => return Err(DecodeError::Unbound { offset }),
+            },
+            KindTag::Fix => Kind::Fix(self.boxed()?),
+            KindTag::Primitive => {
+                let offset = self.pos;
+                match PRIMITIVES.get(self.byte()? as usize) {
+                    Some(p) => Kind::Primitive(*p),
+                    None => return self.invalid(offset, "primitive"),
+                }
+            }
+            KindTag::Injection => {
+                let label = self.str()?;
+                let tm = self.boxed()?;
+                Kind::Injection(label, tm, self.boxed_ty()?)
+            }
+            KindTag::Product => {
+                let len = self.len()?;
+                Kind::Product((0..len).map(|_| self.term()).collect::<Result<_, _>>()?)
+            }
+            KindTag::Projection => {
+                let tm = self.boxed()?;
+                Kind::Projection(tm, self.usize()?)
+            }
+            KindTag::Case => {
+                let expr = self.boxed()?;
+                let len = self.len()?;
+                let arms = (0..len).map(|_| self.arm()).collect::<Result<_, _>>()?;
+                Kind::Case(expr, arms)
+            }
+            KindTag::Let => {
+                let pat = self.pat()?;
+                let t1 = self.boxed()?;
+                let t2 = self.under(PatternCount::collect(&pat), 0)?;
+                Kind::Let(Box::new(pat), t1, Box::new(t2))
+            }
+            KindTag::Abs => {
+                let ty = self.boxed_ty()?;
+                Kind::Abs(ty, Box::new(self.under(1, 0)?))
+            }
+            KindTag::App => {
+                let t1 = self.boxed()?;
+                Kind::App(t1, self.boxed()?)
+            }
+            KindTag::TyAbs => Kind::TyAbs(Box::new(self.under(0, 1)?)),
+            KindTag::TyApp => {
+                let tm = self.boxed()?;
+                Kind::TyApp(tm, self.boxed_ty()?)
+            }
+            KindTag::Fold => {
+                let ty = self.boxed_ty()?;
+                Kind::Fold(ty, self.boxed()?)
+            }
+            KindTag::Unfold => {
+                let ty = self.boxed_ty()?;
+                Kind::Unfold(ty, self.boxed()?)
+            }
+            KindTag::Pack => {
+                let wit = self.boxed_ty()?;
+                let tm = self.boxed()?;
+                Kind::Pack(wit, tm, self.boxed_ty()?)
+            }
+            KindTag::Unpack => {
+                let package = self.boxed()?;
+                Kind::Unpack(package, Box::new(self.under(1, 1)?))
+            }
+            KindTag::Raise => {
+                let ty = self.boxed_ty()?;
+                Kind::Raise(ty, self.boxed()?)
+            }
+            KindTag::Share => unreachable!("shared terms are never written"),
+        })
+    }
+
+    fn arm(&mut self) -> Result<Arm, DecodeError> {
+        let pat = self.pat()?;
+        let binds = PatternCount::collect(&pat);
+        let offset = self.pos;
+        let guard = match self.byte()? {
+            0 => None,
+            1 => Some(Box::new(self.under(binds, 0)?)),
+            _ => return self.invalid(offset, "guard"),
+        };
+        let term = Box::new(self.under(binds, 0)?);
+        Ok(Arm {
+            span: Span::dummy(),
+            pat,
+            binders: Vec::new(),
+            guard,
+            term,
+        })
+    }
+
+    fn lit(&mut self) -> Result<Literal, DecodeError> {
+        let offset = self.pos;
+        Ok(match self.byte()? {
+            0 => Literal::Unit,
+            1 => Literal::Bool(false),
+            2 => Literal::Bool(true),
+            3 => {
+                let offset = self.pos;
+                let n = self.uint()?;
+                Literal::Nat(u32::try_from(n).or_else(|_| self.invalid(offset, "number"))?)
+            }
+            4 => Literal::String(self.str()?),
+            _ => return self.invalid(offset, "literal"),
+        })
+    }
+
+    fn boxed_ty(&mut self) -> Result<Box<Type>, DecodeError> {
+        self.ty().map(Box::new)
+    }
+
+    /// Decode a type under one more type binder
+    fn ty_under(&mut self) -> Result<Box<Type>, DecodeError> {
+        self.types += 1;
+        let ty = self.boxed_ty();
+        self.types -= 1;
+        ty
+    }
+
+    fn ty(&mut self) -> Result<Type, DecodeError> {
+        self.nested(|dec| {
+            let offset = dec.pos;
+            Ok(match dec.byte()? {
+                0 => Type::Unit,
+                1 => Type::Nat,
+                2 => Type::Bool,
+                3 => Type::String,
+                4 => Type::Alias(dec.str()?),
+                5 => match dec.usize()? {
+                    v if v < dec.types => Type::Var(v),
+                    _ => return Err(DecodeError::Unbound { offset }),
+                },
+                6 => {
+                    let len = dec.len()?;
+                    let variants = (0..len)
+                        .map(|_| {
+                            let label = dec.str()?;
+                            Ok(Variant { label, ty: dec.ty()? })
+                        })
+                        .collect::<Result<_, _>>()?;
+                    Type::Variant(variants)
+                }
+                7 => {
+                    let len = dec.len()?;
+                    Type::Product((0..len).map(|_| dec.ty()).collect::<Result<_, _>>()?)
+                }
+                8 => {
+                    let t1 = dec.boxed_ty()?;
+                    Type::Arrow(t1, dec.boxed_ty()?)
+                }
+                9 => Type::Universal(dec.ty_under()?),
+                10 => Type::Existential(dec.ty_under()?),
+                11 => Type::Rec(dec.ty_under()?),
+                12 => Type::Error,
+                13 => {
+                    let offset = dec.pos;
+                    let m = dec.uint()?;
+                    Type::Meta(u32::try_from(m).or_else(|_| dec.invalid(offset, "number"))?)
+                }
+                14 => Type::Hole,
+                _ => return dec.invalid(offset, "type"),
+            })
+        })
+    }
+
+    fn pat(&mut self) -> Result<Pattern, DecodeError> {
+        self.nested(|dec| {
+            let offset = dec.pos;
+            Ok(match dec.byte()? {
+                0 => Pattern::Any,
+                1 => Pattern::Literal(dec.lit()?),
+                2 => Pattern::Variable(dec.str()?),
+                3 => {
+                    let len = dec.len()?;
+                    Pattern::Product((0..len).map(|_| dec.pat()).collect::<Result<_, _>>()?)
+                }
+                4 => {
+                    let label = dec.str()?;
+                    Pattern::Constructor(label, Box::new(dec.pat()?))
+                }
+                5 => {
+                    let len = dec.len()?;
+                    Pattern::Or((0..len).map(|_| dec.pat()).collect::<Result<_, _>>()?)
+                }
+                6 => {
+                    let pat = dec.pat()?;
+                    Pattern::As(Box::new(pat), dec.str()?)
+                }
+                7 => Pattern::Succ(Box::new(dec.pat()?)),
+                _ => return dec.invalid(offset, "pattern"),
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::syntax::parser::Parser;
+    use crate::types::Context;
+    use crate::visit::MutTermVisitor;
+
+    fn parse(input: &str) -> Term {
+        let mut p = Parser::new(input);
+        let tm = p.parse().unwrap();
+        assert_eq!(p.diagnostic().error_count(), 0);
+        tm
+    }
+
+    /// Forget the spans of a term, which are not encoded
+    struct Unspan;
+
+    impl MutTermVisitor for Unspan {
+        fn visit(&mut self, term: &mut Term) {
+            term.span = Span::dummy();
+            if let Kind::Case(_, arms) = &mut term.kind {
+                for arm in arms {
+                    arm.span = Span::dummy();
+                    arm.binders.clear();
+                }
+            }
+            self.walk(term);
+        }
+    }
+
+    fn round_trip(mut term: Term) {
+        let bytes = encode(&term).unwrap_or_else(|| panic!("{} is not a closed value", term));
+        let decoded = decode(&bytes).unwrap_or_else(|e| panic!("{} does not decode: {}", term, e));
+        Unspan.visit(&mut term);
+        assert_eq!(decoded, term);
+    }
+
+    const VALUES: [&str; 14] = [
+        "unit",
+        "(true, false, 0, 4294967295, \"a \\\"string\\\" with λ in it\")",
+        "()",
+        "\\x: Nat. \\y: Nat. add x y",
+        "\\X \\x: X. x",
+        "(\\X (\\f: X -> X. f), \\x: (Nat, {A | B Nat}). x.1)",
+        "Some (1, \"two\") of {None | Some (Nat, String)}",
+        "fold (rec L = {Nil | Cons (Nat, L)}) Cons (1, fold (rec L = {Nil | Cons (Nat, L)}) Nil of \
+         {Nil | Cons (Nat, rec L = {Nil | Cons (Nat, L)})}) of {Nil | Cons (Nat, rec L = {Nil | Cons (Nat, L)})}",
+        "\\l: (rec L = {Nil | Cons (Nat, L)}). case unfold (rec L = {Nil | Cons (Nat, L)}) l of \
+         | Nil => 0 | Cons (n, _) when iszero n => 1 | Cons (succ n as m, _) => m | Cons (1 | 2, _) => 2",
+        "\\p: (Nat, Bool). let (a, b) = p in if b then fix (\\f: Nat -> Nat. \\n: Nat. f n) a else a",
+        "pack Nat, (\\x: Nat. x, 0) as exists X. (X -> Nat, X)",
+        "\\n: Nat. unpack (pack Nat, (\\x: Nat. x, n) as exists X. (X -> Nat, X)) as T, m in m.0 m.1",
+        "\\n: Nat. case n of | 0 => raise Nat 1 | _ => (\\X \\x: X. x) [Nat] n",
+        "(\\x: Nat. x, succ, concat \"partly\", \\s: NatList. s)",
+    ];
+
+    #[test]
+    fn value_forms() {
+        for input in &VALUES {
+            round_trip(parse(input));
+        }
+        // Evaluated values share the terms substituted into them
+        let ctx = Context::default();
+        let tm = parse("(\\f: Nat -> Nat. (f, \\x: Nat. f x, f)) (\\n: Nat. succ n)");
+        let v = crate::eval::eval_limited(&ctx, tm, 10).value().unwrap();
+        round_trip(v);
+    }
+
+    #[test]
+    fn not_values() {
+        assert_eq!(encode(&parse("(\\x: Nat. x) 1")), None);
+        // Open terms cannot be encoded
+        let open = match parse("\\x: Nat. \\y: Nat. x").kind {
+            Kind::Abs(_, body) => *body,
+            _ => unreachable!(),
+        };
+        assert_eq!(encode(&open), None);
+    }
+
+    #[test]
+    fn corrupted() {
+        let bytes = encode(&parse(VALUES[8])).unwrap();
+        // Every proper prefix ends too soon, or is a different value
+        for len in 0..bytes.len() {
+            assert!(decode(&bytes[..len]).is_err(), "prefix of length {} decoded", len);
+        }
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            decode(&trailing),
+            Err(DecodeError::TrailingBytes { offset: bytes.len() })
+        );
+        assert_eq!(
+            decode(&[200]),
+            Err(DecodeError::Invalid {
+                offset: 0,
+                what: "term"
+            })
+        );
+        // `\x: Nat. #1`
+        assert_eq!(decode(&[9, 1, 1, 1]), Err(DecodeError::Unbound { offset: 2 }));
+        // A length longer than the input
+        assert_eq!(decode(&[5, 0xff, 0xff, 0x03]), Err(DecodeError::UnexpectedEnd));
+        // `succ succ`, which is well-formed but not a value
+        assert_eq!(decode(&[10, 3, 0, 3, 0]), Err(DecodeError::NotAValue));
+        // A deep nest of `fix`, which would overflow the stack. Unoptimized
+        // builds need more than the default stack of a test to reach the limit
+        let deep = std::thread::Builder::new()
+            .stack_size(64 << 20)
+            .spawn(|| decode(&[2; 100_000]).err())
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(deep, Some(DecodeError::TooDeep { offset: MAX_DEPTH }));
+
+        // No single corrupted byte makes decoding panic
+        for i in 0..bytes.len() {
+            for b in [0, 1, 0x7f, 0x80, 0xff].iter() {
+                let mut bad = bytes.clone();
+                bad[i] = *b;
+                let _ = decode(&bad);
+            }
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! Stepping through evaluation one redex at a time
+//!
+//! A [`Debugger`] holds the term being evaluated, and takes small steps on
+//! request, reporting where each one happened so that the redex can be shown
+//! with [`Term::pretty_highlight`].
+use super::*;
+use std::cell::RefCell;
+use std::mem;
+
+/// What happened when the debugger was asked to go on
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// A step was taken, contracting the redex with this span
+    Stepped(Span),
+    /// A step was taken, in which a case dispatched on a constructor with a
+    /// breakpoint. The redex was the case expression with this span
+    Breakpoint { label: String, redex: Span },
+    /// The current term is a value, or a raised error, and takes no step
+    Done,
+    /// The current term is stuck, or [`Debugger::run_to_value`] ran out of
+    /// fuel
+    Failed(EvalError),
+}
+
+/// Hooks recording the constructor that a case dispatched on in a step
+#[derive(Debug, Default)]
+struct Dispatch(RefCell<Option<String>>);
+
+impl EvalHooks for Dispatch {
+    const MEASURE: bool = false;
+
+    fn on_case(&self, label: Option<&str>) {
+        *self.0.borrow_mut() = label.map(str::to_string);
+    }
+}
+
+/// Small-step evaluation of a term, driven one step at a time
+pub struct Debugger<'ctx> {
+    ev: Eval<'ctx, Dispatch>,
+    term: Term,
+    steps: u64,
+    breakpoints: Vec<String>,
+}
+
+impl<'ctx> Debugger<'ctx> {
+    pub fn new(ctx: &'ctx Context, term: Term) -> Debugger<'ctx> {
+        Debugger {
+            ev: Eval::with_context(ctx).with_hooks(Dispatch::default()),
+            term,
+            steps: 0,
+            breakpoints: Vec::new(),
+        }
+    }
+
+    /// The term reached so far
+    pub fn current(&self) -> &Term {
+        &self.term
+    }
+
+    /// Number of steps taken so far
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Pause whenever a case expression dispatches on a value built with
+    /// this constructor
+    pub fn breakpoint_on_label(&mut self, label: &str) {
+        if !self.breakpoints.iter().any(|l| l == label) {
+            self.breakpoints.push(label.to_string());
+        }
+    }
+
+    /// Take a single step
+    pub fn step(&mut self) -> Event {
+        if self.term.is_value() || self.term.is_raised() {
+            return Event::Done;
+        }
+        let term = mem::replace(&mut self.term, Term::new(Kind::Product(Vec::new()), Span::dummy()));
+        let redex = match self.ev.try_step(term) {
+            Ok((next, redex)) => {
+                self.term = next;
+                redex
+            }
+            Err(state) => {
+                let err = stuck(stuck_redex(&state).clone());
+                self.term = state;
+                return Event::Failed(err);
+            }
+        };
+        self.steps += 1;
+        match self.ev.hooks().0.borrow_mut().take() {
+            Some(label) if self.breakpoints.contains(&label) => Event::Breakpoint { label, redex },
+            _ => Event::Stepped(redex),
+        }
+    }
+
+    /// Take steps until the term is a value or a breakpoint is hit, giving
+    /// up after [`DEFAULT_FUEL`] steps
+    pub fn run_to_value(&mut self) -> Event {
+        for _ in 0..DEFAULT_FUEL {
+            match self.step() {
+                Event::Stepped(_) => {}
+                event => return event,
+            }
+        }
+        Event::Failed(EvalError::OutOfFuel {
+            steps: self.steps,
+            state: self.term.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::syntax::parser::Parser;
+    use crate::terms::pretty::PrintOptions;
+
+    fn parse(input: &str) -> Term {
+        let mut p = Parser::new(input);
+        let tm = p.parse().unwrap();
+        assert_eq!(p.diagnostic().error_count(), 0);
+        tm
+    }
+
+    /// The span of the first occurrence of `needle` in `source`
+    fn span_of(tm: &Term, source: &str, needle: &str) -> Span {
+        let start = source.find(needle).unwrap();
+        let end = start + needle.len();
+        tm.spans()
+            .into_iter()
+            .map(|(span, _)| span)
+            .find(|span| span.start.abs as usize == start && span.end.abs as usize == end)
+            .unwrap_or_else(|| panic!("no term spans {}", needle))
+    }
+
+    #[test]
+    fn five_steps() {
+        let ctx = Context::default();
+        let source = "succ ((\\x: Nat. case Some (pred x) of {None | Some Nat} of | None => 0 | Some n => add n x) 2)";
+        let tm = parse(source);
+        let app = match &tm.kind {
+            Kind::App(_, arg) => arg.span,
+            k => panic!("expected an application, not {:?}", k),
+        };
+
+        let mut dbg = Debugger::new(&ctx, tm.clone());
+        dbg.breakpoint_on_label("Some");
+        assert_eq!(dbg.step(), Event::Stepped(app));
+        let before = dbg.current().clone();
+        assert_eq!(dbg.step(), Event::Stepped(span_of(&tm, source, "pred x")));
+        assert_eq!(
+            before.pretty_highlight(&PrintOptions::default(), span_of(&tm, source, "pred x")),
+            "succ (case (Some pred 2 of {None | Some Nat}) of | None => 0 | Some n => add n 2)\n                 ^^^^^^"
+        );
+        // Each contracted redex takes the span of the one it came from, so
+        // the case has the span of the application, and so does the body of
+        // the arm selected
+        assert_eq!(
+            dbg.step(),
+            Event::Breakpoint {
+                label: "Some".into(),
+                redex: app
+            }
+        );
+        assert_eq!(dbg.current().pretty(&PrintOptions::default()), "succ (add 1 2)");
+        assert_eq!(dbg.step(), Event::Stepped(app));
+        assert_eq!(dbg.step(), Event::Stepped(tm.span));
+        assert_eq!(dbg.steps(), 5);
+        assert_eq!(dbg.step(), Event::Done);
+        assert_eq!(dbg.current().kind, Kind::Lit(Literal::Nat(4)));
+    }
+
+    #[test]
+    fn run_to_breakpoint() {
+        let ctx = Context::default();
+        let source =
+            "let x = (\\b: Bool. if b then Some 1 of {None | Some Nat} else None of {None | Some Nat}) true in \
+                      case x of | None => 0 | Some n => n";
+        let mut dbg = Debugger::new(&ctx, parse(source));
+        dbg.breakpoint_on_label("Some");
+        match dbg.run_to_value() {
+            Event::Breakpoint { label, .. } => assert_eq!(label, "Some"),
+            e => panic!("expected a breakpoint, not {:?}", e),
+        }
+        assert_eq!(dbg.run_to_value(), Event::Done);
+        assert_eq!(dbg.current().kind, Kind::Lit(Literal::Nat(1)));
+
+        let mut dbg = Debugger::new(&ctx, parse("iszero (\\x: Nat. x)"));
+        assert!(matches!(
+            dbg.run_to_value(),
+            Event::Failed(EvalError::PrimitiveMisuse { .. })
+        ));
+    }
+}
@@ -0,0 +1,161 @@
+//! Instrumentation of small-step evaluation
+//!
+//! [`Eval`](super::Eval) is generic over its hooks, so that evaluation with
+//! [`NoHooks`] pays nothing for them.
+use crate::terms::locate::KindTag;
+use crate::terms::Term;
+use crate::visit::TermVisitor;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use util::span::Span;
+
+/// Callbacks made by the evaluator as it reduces a term
+pub trait EvalHooks {
+    /// Whether to measure substitutions for [`on_substitution`], which takes
+    /// an extra walk over every substituted term
+    ///
+    /// [`on_substitution`]: EvalHooks::on_substitution
+    const MEASURE: bool = true;
+
+    /// A redex of the given kind was contracted: `App` for a beta step,
+    /// `Primitive` for the application of a primitive, `TyApp` for a type
+    /// beta step, and so on
+    fn on_step(&self, redex: KindTag) {}
+
+    /// Substituting into the body of a redex copied this many nodes
+    fn on_substitution(&self, nodes_copied: usize) {}
+
+    /// A case expression selected an arm for a scrutinee, which is an
+    /// injection with this label, if any
+    fn on_case(&self, label: Option<&str>) {}
+
+    /// Evaluation by [`run`](super::run) reached a term, including the one it
+    /// started from
+    fn on_state(&self, term: &Term) {}
+}
+
+/// Hooks that do nothing
+#[derive(Copy, Clone, Debug, Default)]
+pub struct NoHooks;
+
+impl EvalHooks for NoHooks {
+    const MEASURE: bool = false;
+}
+
+/// Hooks that count what happens during evaluation
+#[derive(Debug, Default)]
+pub struct CountingHooks {
+    steps: RefCell<BTreeMap<KindTag, u64>>,
+    cases: RefCell<BTreeMap<Option<String>, u64>>,
+    copied: Cell<u64>,
+    peak: Cell<usize>,
+}
+
+impl CountingHooks {
+    /// Number of contracted redexes of this kind
+    pub fn steps(&self, redex: KindTag) -> u64 {
+        self.steps.borrow().get(&redex).copied().unwrap_or(0)
+    }
+
+    /// Number of steps taken
+    pub fn total_steps(&self) -> u64 {
+        self.steps.borrow().values().sum()
+    }
+
+    /// Number of arms selected by case expressions
+    pub fn cases(&self) -> u64 {
+        self.cases.borrow().values().sum()
+    }
+
+    /// Number of nodes copied by substitution
+    pub fn copied(&self) -> u64 {
+        self.copied.get()
+    }
+
+    /// Number of nodes in the largest term reached
+    pub fn peak_size(&self) -> usize {
+        self.peak.get()
+    }
+}
+
+impl EvalHooks for CountingHooks {
+    fn on_step(&self, redex: KindTag) {
+        *self.steps.borrow_mut().entry(redex).or_insert(0) += 1;
+    }
+
+    fn on_substitution(&self, nodes_copied: usize) {
+        self.copied.set(self.copied.get() + nodes_copied as u64);
+    }
+
+    fn on_case(&self, label: Option<&str>) {
+        *self.cases.borrow_mut().entry(label.map(str::to_string)).or_insert(0) += 1;
+    }
+
+    fn on_state(&self, term: &Term) {
+        self.peak.set(self.peak.get().max(size(term)));
+    }
+}
+
+impl fmt::Display for CountingHooks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let steps = self.steps.borrow();
+        let steps = steps.iter().map(|(redex, n)| format!("{:?} {}", redex, n));
+        writeln!(f, "{} steps{}", self.total_steps(), breakdown(steps))?;
+        let cases = self.cases.borrow();
+        let cases = cases
+            .iter()
+            .map(|(label, n)| format!("{} {}", label.as_deref().unwrap_or("_"), n));
+        writeln!(f, "{} case dispatches{}", self.cases(), breakdown(cases))?;
+        writeln!(f, "{} nodes copied by substitution", self.copied())?;
+        write!(f, "peak term size {}", self.peak_size())
+    }
+}
+
+fn breakdown<I: Iterator<Item = String>>(counts: I) -> String {
+    let counts = counts.collect::<Vec<_>>();
+    match counts.is_empty() {
+        true => String::new(),
+        false => format!(": {}", counts.join(", ")),
+    }
+}
+
+/// Number of nodes in memory for a term, counting a term shared in several
+/// places once
+pub(super) fn size(term: &Term) -> usize {
+    let mut count = Count {
+        nodes: 0,
+        shared: Some(HashSet::new()),
+    };
+    count.visit(term);
+    count.nodes
+}
+
+/// Number of nodes allocated by cloning a term, which copies the pointer to
+/// a shared term but not the term itself
+pub(super) fn copy_size(term: &Term) -> usize {
+    let mut count = Count { nodes: 0, shared: None };
+    count.visit(term);
+    count.nodes
+}
+
+struct Count {
+    nodes: usize,
+    /// Shared terms already counted, or `None` to count none of them
+    shared: Option<HashSet<*const Term>>,
+}
+
+impl<'a> TermVisitor<'a> for Count {
+    fn visit(&mut self, term: &'a Term) {
+        self.nodes += 1;
+        self.walk(term);
+    }
+
+    fn visit_share(&mut self, sp: Span, term: &'a Term) {
+        if let Some(shared) = &mut self.shared {
+            if shared.insert(term) {
+                self.visit(term);
+            }
+        }
+    }
+}
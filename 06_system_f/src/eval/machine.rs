@@ -0,0 +1,414 @@
+//! A CEK-style abstract machine for erased terms
+//!
+//! Instead of substituting values into terms, the machine evaluates terms in
+//! an environment of values for their free variables, and functions evaluate
+//! to closures capturing their environment. Types play no part at runtime:
+//! the machine runs on the [`UTerm`]s produced by [`erase`](crate::erase),
+//! so type applications cost nothing, and case expressions select their arm
+//! by walking the decision tree compiled for them. The continuation is an
+//! explicit stack of frames, so deeply recursive programs do not overflow
+//! the Rust stack.
+//!
+//! Closures and subterms borrow from the term being evaluated, which must
+//! outlive the machine's values. [`read_back`] converts a value of a first
+//! order type back into a [`Term`] for display.
+use crate::erase::{bind, UTerm};
+use crate::match_compile::{Decision, Scrutinee};
+use crate::patterns::Pattern;
+use crate::terms::{Kind, Literal, Primitive, Term};
+use crate::types::{subst, Type};
+use std::rc::Rc;
+use util::span::Span;
+
+/// Runtime value of the machine
+#[derive(Clone, Debug)]
+pub enum Value<'t> {
+    Lit(Literal),
+    /// The body of an abstraction, and the environment it was evaluated in
+    Closure(Env<'t>, &'t UTerm),
+    /// A primitive function, and the arguments it has been applied to so far
+    Primitive(Primitive, Vec<Value<'t>>),
+    Injection(&'t str, Rc<Value<'t>>),
+    Product(Rc<Vec<Value<'t>>>),
+    /// Recursive binding introduced by `fix`, unrolled when it is looked up
+    Rec(Env<'t>, &'t UTerm),
+}
+
+/// Environment of values for bound variables, as a linked list whose head is
+/// de Bruijn index 0. Closures share the tail of the list with their
+/// surroundings, so capturing an environment is free
+pub type Env<'t> = Option<Rc<Binding<'t>>>;
+
+#[derive(Debug)]
+pub struct Binding<'t> {
+    val: Value<'t>,
+    next: Env<'t>,
+}
+
+fn extend<'t>(env: &Env<'t>, val: Value<'t>) -> Env<'t> {
+    Some(Rc::new(Binding { val, next: env.clone() }))
+}
+
+fn lookup<'e, 't>(env: &'e Env<'t>, idx: usize) -> Option<&'e Value<'t>> {
+    let mut node = env.as_ref()?;
+    for _ in 0..idx {
+        node = node.next.as_ref()?;
+    }
+    Some(&node.val)
+}
+
+impl<'t> Scrutinee for Value<'t> {
+    fn literal(&self) -> Option<&Literal> {
+        match self {
+            Value::Lit(lit) => Some(lit),
+            _ => None,
+        }
+    }
+
+    fn injection(&self) -> Option<(&str, &Value<'t>)> {
+        match self {
+            Value::Injection(label, payload) => Some((label, payload)),
+            _ => None,
+        }
+    }
+
+    fn product(&self) -> Option<&[Value<'t>]> {
+        match self {
+            Value::Product(vals) => Some(vals),
+            _ => None,
+        }
+    }
+
+    fn nat(n: u32) -> Value<'t> {
+        Value::Lit(Literal::Nat(n))
+    }
+}
+
+type Arms = [(Pattern, Option<UTerm>, UTerm)];
+
+/// What to do with the value of the term being evaluated
+enum Frame<'t> {
+    /// Evaluate the argument of an application
+    Arg(Env<'t>, &'t UTerm),
+    /// Apply a function to the argument
+    Apply(Value<'t>),
+    Fix,
+    Inject(&'t str),
+    /// Evaluate the remaining components of a product
+    Product(Env<'t>, &'t [UTerm], Vec<Value<'t>>),
+    Project(usize),
+    /// Select an arm for the scrutinee
+    Case(Env<'t>, &'t Arms, &'t Decision),
+    /// Abandon evaluation, as the value is raised as an error
+    Raise,
+    /// Take the arm if its guard holds, or continue with the rest of the tree
+    Guard {
+        env: Env<'t>,
+        arms: &'t Arms,
+        val: Value<'t>,
+        otherwise: Option<&'t Decision>,
+        arm_env: Env<'t>,
+        body: &'t UTerm,
+    },
+}
+
+enum State<'t> {
+    Eval(Env<'t>, &'t UTerm),
+    Return(Value<'t>),
+}
+
+/// Evaluate a closed untyped term using call-by-value, returning `None` if
+/// evaluation gets stuck or raises an error
+pub fn eval(term: &UTerm) -> Option<Value<'_>> {
+    let mut stack = Vec::new();
+    let mut state = State::Eval(None, term);
+    loop {
+        state = match state {
+            State::Eval(env, term) => match term {
+                UTerm::Lit(lit) => State::Return(Value::Lit(lit.clone())),
+                UTerm::Var(idx) => match lookup(&env, *idx)? {
+                    Value::Rec(env, body) => unroll(env, body),
+                    val => State::Return(val.clone()),
+                },
+                UTerm::Primitive(p) => State::Return(Value::Primitive(*p, Vec::new())),
+                UTerm::Abs(body) => State::Return(Value::Closure(env, body)),
+                UTerm::App(t1, t2) => {
+                    stack.push(Frame::Arg(env.clone(), t2));
+                    State::Eval(env, t1)
+                }
+                UTerm::Fix(tm) => {
+                    stack.push(Frame::Fix);
+                    State::Eval(env, tm)
+                }
+                UTerm::Injection(label, tm) => {
+                    stack.push(Frame::Inject(label));
+                    State::Eval(env, tm)
+                }
+                UTerm::Product(terms) => match terms.split_first() {
+                    Some((first, rest)) => {
+                        stack.push(Frame::Product(env.clone(), rest, Vec::with_capacity(terms.len())));
+                        State::Eval(env, first)
+                    }
+                    None => State::Return(Value::Product(Rc::new(Vec::new()))),
+                },
+                UTerm::Projection(tm, idx) => {
+                    stack.push(Frame::Project(*idx));
+                    State::Eval(env, tm)
+                }
+                UTerm::Case(tm, arms, tree) => {
+                    stack.push(Frame::Case(env.clone(), arms, tree));
+                    State::Eval(env, tm)
+                }
+                UTerm::Raise(tm) => {
+                    stack.push(Frame::Raise);
+                    State::Eval(env, tm)
+                }
+            },
+            State::Return(val) => match stack.pop() {
+                None => return Some(val),
+                Some(Frame::Arg(env, arg)) => {
+                    stack.push(Frame::Apply(val));
+                    State::Eval(env, arg)
+                }
+                Some(Frame::Apply(f)) => apply(f, val)?,
+                Some(Frame::Fix) => match val {
+                    Value::Closure(env, body) => unroll(&env, body),
+                    _ => return None,
+                },
+                Some(Frame::Inject(label)) => State::Return(Value::Injection(label, Rc::new(val))),
+                Some(Frame::Product(env, rest, mut done)) => {
+                    done.push(val);
+                    match rest.split_first() {
+                        Some((next, rest)) => {
+                            stack.push(Frame::Product(env.clone(), rest, done));
+                            State::Eval(env, next)
+                        }
+                        None => State::Return(Value::Product(Rc::new(done))),
+                    }
+                }
+                Some(Frame::Project(idx)) => match val {
+                    Value::Product(vals) => State::Return(vals.get(idx)?.clone()),
+                    _ => return None,
+                },
+                Some(Frame::Case(env, arms, tree)) => select(&mut stack, env, arms, tree, val)?,
+                Some(Frame::Raise) => return None,
+                Some(Frame::Guard {
+                    env,
+                    arms,
+                    val: scrutinee,
+                    otherwise,
+                    arm_env,
+                    body,
+                }) => match val {
+                    Value::Lit(Literal::Bool(true)) => State::Eval(arm_env, body),
+                    Value::Lit(Literal::Bool(false)) => select(&mut stack, env, arms, otherwise?, scrutinee)?,
+                    _ => return None,
+                },
+            },
+        }
+    }
+}
+
+/// Evaluate the body of `fix (λ. body)`, binding the recursive occurrence
+fn unroll<'t>(env: &Env<'t>, body: &'t UTerm) -> State<'t> {
+    let rec = Value::Rec(env.clone(), body);
+    State::Eval(extend(env, rec), body)
+}
+
+fn apply<'t>(f: Value<'t>, arg: Value<'t>) -> Option<State<'t>> {
+    match f {
+        Value::Closure(env, body) => Some(State::Eval(extend(&env, arg), body)),
+        Value::Primitive(p, mut args) => {
+            args.push(arg);
+            if args.len() < p.arity() {
+                return Some(State::Return(Value::Primitive(p, args)));
+            }
+            let lits = args
+                .into_iter()
+                .map(|v| match v {
+                    Value::Lit(lit) => Some(lit),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()?;
+            p.apply(&lits).map(|lit| State::Return(Value::Lit(lit)))
+        }
+        _ => None,
+    }
+}
+
+/// Walk the decision tree for the scrutinee, and evaluate the arm it selects
+fn select<'t>(
+    stack: &mut Vec<Frame<'t>>,
+    env: Env<'t>,
+    arms: &'t Arms,
+    tree: &'t Decision,
+    val: Value<'t>,
+) -> Option<State<'t>> {
+    let (arm, otherwise) = tree.walk(&val)?;
+    let (pat, guard, body) = &arms[arm];
+    let mut binds = Vec::new();
+    if !bind(pat, &val, &mut binds) {
+        return None;
+    }
+    // The first variable in the pattern is innermost
+    let mut arm_env = env.clone();
+    for (_, v) in binds.into_iter().rev() {
+        arm_env = extend(&arm_env, v);
+    }
+    match guard {
+        Some(guard) => {
+            stack.push(Frame::Guard {
+                env,
+                arms,
+                val,
+                otherwise,
+                arm_env: arm_env.clone(),
+                body,
+            });
+            Some(State::Eval(arm_env, guard))
+        }
+        None => Some(State::Eval(arm_env, body)),
+    }
+}
+
+/// Convert a value of type `ty` back into a term. Only values of first order
+/// types can be converted: functions and the contents of existential
+/// packages have no representation as terms once their types are erased
+pub fn read_back(val: &Value, ty: &Type) -> Option<Term> {
+    let term = |kind| Some(Term::new(kind, Span::dummy()));
+    match (ty, val) {
+        (Type::Unit, Value::Lit(lit @ Literal::Unit))
+        | (Type::Bool, Value::Lit(lit @ Literal::Bool(_)))
+        | (Type::Nat, Value::Lit(lit @ Literal::Nat(_)))
+        | (Type::String, Value::Lit(lit @ Literal::String(_))) => term(Kind::Lit(lit.clone())),
+        (Type::Product(tys), Value::Product(vals)) if tys.len() == vals.len() => term(Kind::Product(
+            tys.iter()
+                .zip(vals.iter())
+                .map(|(ty, val)| read_back(val, ty))
+                .collect::<Option<_>>()?,
+        )),
+        (Type::Variant(vs), Value::Injection(label, payload)) => {
+            let variant = vs.iter().find(|v| v.label == *label)?;
+            let payload = read_back(payload, &variant.ty)?;
+            term(Kind::Injection(
+                label.to_string(),
+                Box::new(payload),
+                Box::new(ty.clone()),
+            ))
+        }
+        (Type::Rec(inner), val) => {
+            let unrolled = subst(ty.clone(), *inner.clone());
+            term(Kind::Fold(Box::new(ty.clone()), Box::new(read_back(val, &unrolled)?)))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::erase::erase;
+    use crate::syntax::parser::{self, Parser};
+    use crate::terms::visit::InjRewriter;
+    use crate::types::Context;
+    use crate::visit::MutTermVisitor;
+    use std::time::Instant;
+
+    fn context() -> Context {
+        let mut ctx = Context::default();
+        ctx.alias("Var".into(), crate::test_variant()).unwrap();
+        ctx.alias("NatList".into(), crate::nat_list()).unwrap();
+        ctx.alias("NB".into(), crate::nat_list2()).unwrap();
+        ctx
+    }
+
+    fn parse(ctx: &mut Context, input: &str) -> Term {
+        let mut term = Parser::new(input).parse().unwrap();
+        ctx.de_alias(&mut term);
+        InjRewriter.visit(&mut term);
+        term
+    }
+
+    /// The machine and the substitution evaluator agree on every first order
+    /// value in the test corpus
+    #[test]
+    fn differential() {
+        let mut ctx = context();
+        let mut p = Parser::new(include_str!("../../test.sf"));
+        let mut checked = 0;
+        loop {
+            let mut term = match p.parse() {
+                Ok(term) => term,
+                Err(parser::Error {
+                    kind: parser::ErrorKind::Eof,
+                    ..
+                }) => break,
+                Err(e) => panic!("{:?}", e),
+            };
+            ctx.de_alias(&mut term);
+            InjRewriter.visit(&mut term);
+            let ty = match ctx.type_check(&term) {
+                Ok(ty) => ty,
+                Err(_) => continue,
+            };
+            let erased = erase(&term);
+            let val = eval(&erased).unwrap_or_else(|| panic!("{} is stuck", erased));
+            let value = crate::eval::eval_limited(&ctx, term, crate::eval::DEFAULT_FUEL)
+                .value()
+                .unwrap();
+            if let Some(back) = read_back(&val, &ty) {
+                assert_eq!(back.to_string(), value.to_string());
+                checked += 1;
+            }
+        }
+        assert!(checked > 10);
+    }
+
+    #[test]
+    fn deep_recursion() {
+        // Deep enough to overflow the stack of a recursive evaluator
+        let mut ctx = context();
+        let input =
+            "letrec count: Nat->Nat = \\n: Nat. case n of | 0 => 0 | _ => succ (count (pred n)) in count 100000";
+        let tm = parse(&mut ctx, input);
+        let erased = erase(&tm);
+        match eval(&erased) {
+            Some(Value::Lit(Literal::Nat(100000))) => {}
+            val => panic!("{:?}", val),
+        }
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture machine_speed`
+    #[test]
+    #[ignore]
+    fn machine_speed() {
+        let mut ctx = context();
+        let programs = [
+            "letrec fib: Nat->Nat = \\n: Nat. case n of | 0 => 0 | 1 => 1 | _ => add (fib (pred n)) (fib (pred (pred n))) in fib 15",
+            "letrec range: Nat->NatList = \\n: Nat. case n of
+                | 0 => Nil of NatList
+                | _ => Cons (n, range (pred n)) of NatList
+             in letrec sum: NatList->Nat = \\l: NatList. case unfold NatList l of
+                | Nil => 0
+                | Cons (x, xs) => add x (sum xs)
+             in letrec map: NatList->NatList = \\l: NatList. case unfold NatList l of
+                | Nil => Nil of NatList
+                | Cons (x, xs) => Cons (mul x 2, map xs) of NatList
+             in sum (map (range 100))",
+        ];
+        for input in &programs {
+            let tm = parse(&mut ctx, input);
+            let ty = ctx.type_check(&tm).unwrap();
+
+            let start = Instant::now();
+            let value = crate::eval::eval_limited(&ctx, tm.clone(), u64::MAX).value().unwrap();
+            println!("substitution: {:?}", start.elapsed());
+
+            let start = Instant::now();
+            let erased = erase(&tm);
+            let val = eval(&erased).unwrap();
+            println!("machine: {:?}", start.elapsed());
+            assert_eq!(read_back(&val, &ty).unwrap().to_string(), value.to_string());
+        }
+    }
+}
@@ -0,0 +1,1727 @@
+use crate::patterns::{PatVarStack, Pattern};
+use crate::terms::visit::{Closed, Shift, Subst, TyShift, TyTermSubst};
+use crate::terms::{Arm, Kind, Literal, Primitive, Term};
+use crate::types::{Context, Type};
+use crate::visit::MutTermVisitor;
+use std::collections::HashSet;
+use std::fmt;
+use util::span::Span;
+
+pub mod codec;
+pub mod debug;
+pub mod hooks;
+pub mod machine;
+mod normal;
+
+use crate::terms::locate::KindTag;
+use hooks::{EvalHooks, NoHooks};
+pub use normal::normalize;
+
+/// How type abstractions are eliminated during small-step evaluation
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ErasureMode {
+    /// Substitute the type argument of a type application, and the witness
+    /// type of an unpacked package, into the body
+    #[default]
+    Precise,
+    /// Step to the body without substituting any type into it. Types inside
+    /// the result are left stale, but evaluation never consults them, so
+    /// only the type annotations of the final value differ from `Precise`
+    Erased,
+}
+
+pub struct Eval<'ctx, H = NoHooks> {
+    _context: &'ctx Context,
+    mode: ErasureMode,
+    hooks: H,
+}
+
+impl<'ctx> Eval<'ctx> {
+    pub fn with_context(_context: &Context) -> Eval<'_> {
+        Eval::with_mode(_context, ErasureMode::Precise)
+    }
+
+    pub fn with_mode(_context: &Context, mode: ErasureMode) -> Eval<'_> {
+        Eval {
+            _context,
+            mode,
+            hooks: NoHooks,
+        }
+    }
+}
+
+impl<'ctx, H: EvalHooks> Eval<'ctx, H> {
+    /// Replace the hooks called during small-step evaluation
+    pub fn with_hooks<G: EvalHooks>(self, hooks: G) -> Eval<'ctx, G> {
+        Eval {
+            _context: self._context,
+            mode: self.mode,
+            hooks,
+        }
+    }
+
+    pub fn hooks(&self) -> &H {
+        &self.hooks
+    }
+
+    /// Evaluate a primitive applied to all of its arguments, see
+    /// [`Primitive::apply`]. The result spans the arguments
+    fn eval_primitive(&self, p: Primitive, args: &[&Term]) -> Option<Term> {
+        let lits = args
+            .iter()
+            .map(|arg| match &arg.unshared().kind {
+                Kind::Lit(lit) => Some(lit.clone()),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let span = args[0].span + args[args.len() - 1].span;
+        Some(Term::new(Kind::Lit(p.apply(&lits)?), span))
+    }
+
+    /// Take a single step of call-by-value evaluation, or return `None` if
+    /// the term is a value or is stuck.
+    ///
+    /// Evaluation is left to right, and [`big_step`] follows the same order:
+    ///
+    /// - in an application `t1 t2`, `t1` is evaluated to a value before
+    ///   `t2` is, also when `t1` is an application itself, such as a partly
+    ///   applied primitive
+    /// - the components of a product are evaluated from first to last
+    /// - the scrutinee of a case, the bound term of a let, and the package
+    ///   of an unpack are evaluated before any arm or body
+    /// - every other term with a subterm is evaluated by evaluating that
+    ///   subterm first
+    ///
+    /// Each kind of term has its own arm in [`Eval::down`] and each context
+    /// its own in [`Eval::up`], so that a new kind has to decide where it
+    /// fits in this order
+    pub fn small_step(&self, term: Term) -> Option<Term> {
+        self.try_step(term).ok().map(|(next, _)| next)
+    }
+
+    /// Take a single step, or give the term back if it is a value or stuck.
+    ///
+    /// The redex is found by walking down the term, pushing the evaluation
+    /// context around each subterm entered onto an explicit stack of
+    /// frames, and walking back up past every subterm found to be a value.
+    /// The term is rebuilt by plugging the result into the frames. Neither
+    /// walk uses the native stack, so terms of any depth can be stepped.
+    ///
+    /// Along with the next term, this returns the span of the redex that
+    /// was contracted
+    fn try_step(&self, term: Term) -> Result<(Term, Span), Term> {
+        let mut frames = Vec::new();
+        let mut redex = term.span;
+        let mut focus = self.down(term);
+        loop {
+            focus = match focus {
+                Focus::Within(frame, sub) => {
+                    frames.push(frame);
+                    self.down(sub)
+                }
+                Focus::Value(v) => match frames.pop() {
+                    Some(frame) => {
+                        redex = frame.span();
+                        self.up(frame, v)
+                    }
+                    None => return Err(v),
+                },
+                Focus::Contracted(tm) => return Ok((plug(frames, tm), redex)),
+                Focus::Stuck(tm) => return Err(plug(frames, tm)),
+                // A raised error replaces the innermost context around it,
+                // keeping the span of the raise site
+                Focus::Raised(tm) => match frames.pop() {
+                    Some(frame) => return Ok((plug(frames, tm), frame.span())),
+                    None => return Err(tm),
+                },
+            };
+        }
+    }
+
+    /// Focus on a term: enter its first subterm in evaluation order, or
+    /// find that it is a value or stuck
+    fn down(&self, term: Term) -> Focus {
+        let span = term.span;
+        match term.kind {
+            Kind::App(t1, t2) => Focus::Within(Frame::AppFun(t2, span), *t1),
+            Kind::Let(pat, bind, body) => Focus::Within(Frame::Let(pat, body, span), *bind),
+            Kind::TyApp(tm, ty) => Focus::Within(Frame::TyApp(ty, span), *tm),
+            Kind::Injection(label, tm, ty) => Focus::Within(Frame::Injection(label, ty, span), *tm),
+            Kind::Projection(tm, idx) => Focus::Within(Frame::Projection(idx, span), *tm),
+            Kind::Product(terms) => {
+                let mut rest = terms.into_iter();
+                match rest.next() {
+                    Some(first) => Focus::Within(Frame::Product(Vec::new(), rest, span), first),
+                    None => Focus::Value(Term::new(Kind::Product(Vec::new()), span)),
+                }
+            }
+            Kind::Fix(tm) => Focus::Within(Frame::Fix(span), *tm),
+            Kind::Case(expr, arms) => Focus::Within(Frame::Case(arms, span), *expr),
+            Kind::Fold(ty, tm) => Focus::Within(Frame::Fold(ty, span), *tm),
+            Kind::Unfold(ty, tm) => Focus::Within(Frame::Unfold(ty, span), *tm),
+            Kind::Pack(wit, evidence, sig) => Focus::Within(Frame::Pack(wit, sig, span), *evidence),
+            Kind::Unpack(package, body) => Focus::Within(Frame::Unpack(body, span), *package),
+            Kind::Raise(ty, tm) => Focus::Within(Frame::Raise(ty, span), *tm),
+            // Unbound variables are stuck
+            Kind::Var(_) => Focus::Stuck(term),
+            Kind::Lit(_) | Kind::Abs(..) | Kind::TyAbs(_) | Kind::Primitive(_) | Kind::Share(_) => Focus::Value(term),
+        }
+    }
+
+    /// Return a value to the context around it: enter the next subterm in
+    /// evaluation order, or contract the redex once its subterms are values
+    fn up(&self, frame: Frame, v: Term) -> Focus {
+        match frame {
+            Frame::AppFun(t2, span) => Focus::Within(Frame::AppArg(Box::new(v), span), *t2),
+            Frame::AppArg(t1, span) => self.apply_value(*t1, v, span),
+            Frame::Let(pat, mut body, span) => {
+                self.case_subst(&pat, &v, body.as_mut());
+                self.contracted(KindTag::Let, contract(*body, span))
+            }
+            Frame::TyApp(ty, span) => match v.unshare() {
+                Term {
+                    kind: Kind::TyAbs(mut body),
+                    ..
+                } => {
+                    if self.mode == ErasureMode::Precise {
+                        type_subst(*ty, &mut body);
+                    }
+                    self.contracted(KindTag::TyApp, contract(*body, span))
+                }
+                v => Focus::Stuck(Term::new(Kind::TyApp(Box::new(v), ty), span)),
+            },
+            Frame::Injection(label, ty, span) => Focus::Value(Term::new(Kind::Injection(label, Box::new(v), ty), span)),
+            Frame::Projection(idx, span) => match v.unshare() {
+                // Typechecker ensures that idx is in bounds
+                Term {
+                    kind: Kind::Product(mut terms),
+                    ..
+                } if idx < terms.len() => self.contracted(KindTag::Projection, terms.swap_remove(idx)),
+                v => Focus::Stuck(Term::new(Kind::Projection(Box::new(v), idx), span)),
+            },
+            Frame::Product(mut done, mut rest, span) => {
+                done.push(v);
+                match rest.next() {
+                    Some(next) => Focus::Within(Frame::Product(done, rest, span), next),
+                    None => Focus::Value(Term::new(Kind::Product(done), span)),
+                }
+            }
+            Frame::Fix(span) => {
+                let x = Term::new(Kind::Fix(Box::new(v.clone())), span);
+                match v.unshare() {
+                    Term {
+                        kind: Kind::Abs(_, mut body),
+                        ..
+                    } => {
+                        self.subst(x, &mut body);
+                        self.contracted(KindTag::Fix, contract(*body, span))
+                    }
+                    // Primitives are the other values of arrow type
+                    v => self.contracted(KindTag::Fix, Term::new(Kind::App(Box::new(v), Box::new(x)), span)),
+                }
+            }
+            Frame::Case(arms, span) => self.select(v, arms, span),
+            Frame::Fold(ty, span) => Focus::Value(Term::new(Kind::Fold(ty, Box::new(v)), span)),
+            Frame::Unfold(ty, span) => match v.unshare() {
+                Term {
+                    kind: Kind::Fold(_, inner),
+                    ..
+                } => self.contracted(KindTag::Unfold, contract(*inner, span)),
+                v => Focus::Stuck(Term::new(Kind::Unfold(ty, Box::new(v)), span)),
+            },
+            Frame::Pack(wit, sig, span) => Focus::Value(Term::new(Kind::Pack(wit, Box::new(v), sig), span)),
+            Frame::Unpack(mut body, span) => match v.unshare() {
+                Term {
+                    kind: Kind::Pack(wit, mut evidence, _),
+                    ..
+                } => {
+                    // The body is under the type variable bound by the unpack
+                    TyShift::new(1).visit(&mut evidence);
+                    self.subst(*evidence, &mut body);
+                    if self.mode == ErasureMode::Precise {
+                        type_subst(*wit, &mut body);
+                    }
+                    self.contracted(KindTag::Unpack, contract(*body, span))
+                }
+                v => Focus::Stuck(Term::new(Kind::Unpack(Box::new(v), body), span)),
+            },
+            // A raised error takes no step once its payload is a value
+            Frame::Raise(ty, span) => Focus::Raised(Term::new(Kind::Raise(ty, Box::new(v)), span)),
+        }
+    }
+
+    /// Apply a function value to an argument value
+    fn apply_value(&self, f: Term, arg: Term, span: Span) -> Focus {
+        let f = f.unshare();
+        let lit = match f.kind {
+            Kind::Abs(_, mut body) => {
+                self.subst(arg, body.as_mut());
+                return self.contracted(KindTag::App, contract(*body, span));
+            }
+            // Partial applications are values
+            Kind::Primitive(p) if p.arity() > 1 => {
+                return Focus::Value(Term::new(Kind::App(Box::new(f), Box::new(arg)), span))
+            }
+            Kind::Primitive(p) => self.eval_primitive(p, &[&arg]),
+            Kind::App(ref g, ref first) => match g.kind {
+                Kind::Primitive(p) => self.eval_primitive(p, &[first, &arg]),
+                _ => None,
+            },
+            _ => None,
+        };
+        match lit {
+            Some(lit) => self.contracted(KindTag::Primitive, lit),
+            None => Focus::Stuck(Term::new(Kind::App(Box::new(f), Box::new(arg)), span)),
+        }
+    }
+
+    /// Select the first arm of a case expression matching a value
+    fn select(&self, expr: Term, mut arms: Vec<Arm>, span: Span) -> Focus {
+        let idx = match arms.iter().position(|arm| arm.pat.matches(&expr)) {
+            Some(idx) => idx,
+            None => return Focus::Stuck(Term::new(Kind::Case(Box::new(expr), arms), span)),
+        };
+        self.hooks.on_case(label(&expr));
+        let rest = arms.split_off(idx + 1);
+        let mut arm = arms.remove(idx);
+        self.case_subst(&arm.pat, &expr, arm.term.as_mut());
+        let mut guard = match arm.guard {
+            Some(guard) => guard,
+            None => return self.contracted(KindTag::Case, contract(*arm.term, span)),
+        };
+        // A guarded arm steps to a case on the guard, which falls through
+        // to the remaining arms if it is false
+        self.case_subst(&arm.pat, &expr, guard.as_mut());
+        let rest = Term::new(Kind::Case(Box::new(expr), rest), span);
+        let span = arm.span;
+        let branch = |lit, term| Arm {
+            span,
+            pat: Pattern::Literal(Literal::Bool(lit)),
+            binders: Vec::new(),
+            guard: None,
+            term: Box::new(term),
+        };
+        self.contracted(
+            KindTag::Case,
+            Term::new(
+                Kind::Case(guard, vec![branch(true, *arm.term), branch(false, rest)]),
+                span,
+            ),
+        )
+    }
+
+    /// Report the contraction of a redex of this kind
+    fn contracted(&self, redex: KindTag, result: Term) -> Focus {
+        self.hooks.on_step(redex);
+        Focus::Contracted(result)
+    }
+
+    fn subst(&self, s: Term, t: &mut Term) {
+        let copied = term_subst_measured(s, t, H::MEASURE);
+        self.hooks.on_substitution(copied);
+    }
+
+    fn case_subst(&self, pat: &Pattern, expr: &Term, term: &mut Term) {
+        let mut binds = Vec::new();
+        bindings(pat, expr, &mut binds);
+        let copied = binds
+            .into_iter()
+            .map(|(_, tm)| term_subst_measured(tm, term, H::MEASURE))
+            .sum();
+        self.hooks.on_substitution(copied);
+    }
+}
+
+/// Default number of steps after which [`trace`] gives up
+pub const STEP_LIMIT: u64 = 10_000;
+
+/// Default number of steps after which evaluation is assumed to diverge
+pub const DEFAULT_FUEL: u64 = 1_000_000;
+
+/// Bounds on the evaluation of a term by [`run`]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Limits {
+    /// Number of steps that may be taken
+    pub fuel: u64,
+    /// Record the term every this many steps, and give up as soon as a term
+    /// is seen twice. Terms are compared by their printed form, so this
+    /// adds the cost of printing every sampled term
+    pub cycle_check: Option<u64>,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            fuel: DEFAULT_FUEL,
+            cycle_check: None,
+        }
+    }
+}
+
+/// Reasons for which evaluation fails to produce a value
+#[derive(Clone, Debug, PartialEq)]
+pub enum EvalError {
+    /// A term that is not a value, but cannot take a step. This can only
+    /// happen to ill-typed or open terms. The term is the redex that could
+    /// not be reduced, with all of its subterms evaluated
+    Stuck(Term),
+    /// A primitive applied to arguments it is not defined on, which the
+    /// typechecker rules out. See [`Primitive::apply`]
+    PrimitiveMisuse { prim: Primitive, args: Vec<Term> },
+    /// Evaluation ran out of fuel after `steps` steps, at `state`
+    OutOfFuel { steps: u64, state: Term },
+    /// Evaluation reached `state` after `steps` steps, and had already
+    /// reached it before, so it will never finish
+    Cycle { steps: u64, state: Term },
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalError::Stuck(tm) => write!(f, "evaluation is stuck at `{}`", tm),
+            EvalError::PrimitiveMisuse { prim, args } => {
+                let args = args.iter().map(|a| format!("`{}`", a)).collect::<Vec<_>>();
+                write!(f, "primitive {:?} cannot be applied to {}", prim, args.join(", "))
+            }
+            EvalError::OutOfFuel { steps, state } => {
+                write!(
+                    f,
+                    "evaluation did not finish within {} steps, reaching `{}`",
+                    steps, state
+                )
+            }
+            EvalError::Cycle { steps, state } => {
+                write!(f, "evaluation loops, reaching `{}` again after {} steps", state, steps)
+            }
+        }
+    }
+}
+
+/// Result of evaluating a term
+#[derive(Clone, Debug, PartialEq)]
+pub enum EvalOutcome {
+    /// The term evaluated to a value
+    Value(Term),
+    /// The program raised an error, which propagated out of every enclosing
+    /// evaluation context. This is the `raise` term, spanning the raise site,
+    /// with its payload evaluated
+    Raised(Term),
+    /// Evaluation failed, which well-typed programs only do by exhausting
+    /// their [`Limits`]
+    Stuck(EvalError),
+}
+
+impl EvalOutcome {
+    /// The value of the term, if evaluation produced one
+    pub fn value(self) -> Option<Term> {
+        match self {
+            EvalOutcome::Value(tm) => Some(tm),
+            _ => None,
+        }
+    }
+}
+
+impl From<EvalError> for EvalOutcome {
+    fn from(e: EvalError) -> EvalOutcome {
+        EvalOutcome::Stuck(e)
+    }
+}
+
+/// Take a single small step, if the term can take one
+pub fn step(ctx: &Context, term: Term) -> Option<Term> {
+    Eval::with_context(ctx).small_step(term)
+}
+
+/// Evaluate a term to a value by taking small steps, calling `observe` on
+/// every term reached along the way
+pub fn run<F: FnMut(&Term)>(ctx: &Context, term: Term, limits: Limits, observe: F) -> EvalOutcome {
+    run_with(&Eval::with_context(ctx), term, limits, observe)
+}
+
+/// [`run`], taking steps with the given evaluator
+pub fn run_with<H: EvalHooks, F: FnMut(&Term)>(
+    ev: &Eval<H>,
+    term: Term,
+    limits: Limits,
+    mut observe: F,
+) -> EvalOutcome {
+    let mut seen = HashSet::new();
+    let mut state = term;
+    let mut steps = 0;
+    ev.hooks.on_state(&state);
+    while !state.is_value() {
+        if state.is_raised() {
+            return EvalOutcome::Raised(state);
+        }
+        if steps == limits.fuel {
+            return EvalError::OutOfFuel { steps, state }.into();
+        }
+        state = match ev.try_step(state) {
+            Ok((next, _)) => next,
+            Err(state) => return stuck(stuck_redex(&state).clone()).into(),
+        };
+        steps += 1;
+        ev.hooks.on_state(&state);
+        observe(&state);
+        match limits.cycle_check {
+            Some(n) if steps % n == 0 && !seen.insert(state.to_string()) => {
+                return EvalError::Cycle { steps, state }.into()
+            }
+            _ => {}
+        }
+    }
+    EvalOutcome::Value(state)
+}
+
+/// Evaluate a term to a value, giving up after `fuel` steps
+pub fn eval_limited(ctx: &Context, term: Term, fuel: u64) -> EvalOutcome {
+    let limits = Limits {
+        fuel,
+        cycle_check: None,
+    };
+    run(ctx, term, limits, |_| {})
+}
+
+/// Evaluate a term to a value, recording every intermediate term along the
+/// way. The first element is the term itself, and the last is its value or
+/// the error it raised
+pub fn trace(ctx: &Context, term: Term) -> Result<Vec<Term>, EvalError> {
+    trace_with_limit(ctx, term, STEP_LIMIT)
+}
+
+/// [`trace`], giving up after `limit` steps
+pub fn trace_with_limit(ctx: &Context, term: Term, limit: u64) -> Result<Vec<Term>, EvalError> {
+    let mut steps = vec![term.clone()];
+    let limits = Limits {
+        fuel: limit,
+        cycle_check: None,
+    };
+    match run(ctx, term, limits, |tm| steps.push(tm.clone())) {
+        EvalOutcome::Stuck(e) => Err(e),
+        _ => Ok(steps),
+    }
+}
+
+/// The error for a stuck redex, telling apart misapplied primitives
+fn stuck(redex: Term) -> EvalError {
+    match &redex.kind {
+        Kind::App(f, arg) => match &f.unshared().kind {
+            Kind::Primitive(prim) => EvalError::PrimitiveMisuse {
+                prim: *prim,
+                args: vec![*arg.clone()],
+            },
+            Kind::App(g, first) => match g.kind {
+                Kind::Primitive(prim) => EvalError::PrimitiveMisuse {
+                    prim,
+                    args: vec![*first.clone(), *arg.clone()],
+                },
+                _ => EvalError::Stuck(redex),
+            },
+            _ => EvalError::Stuck(redex),
+        },
+        _ => EvalError::Stuck(redex),
+    }
+}
+
+/// The subterm of a stuck term that the next step would have reduced
+fn stuck_redex(mut term: &Term) -> &Term {
+    loop {
+        let next = match &term.kind {
+            Kind::App(t1, _) if !t1.is_value() => t1,
+            Kind::App(_, t2) => t2,
+            Kind::Product(terms) => match terms.iter().find(|t| !t.is_value()) {
+                Some(t) => t,
+                None => return term,
+            },
+            Kind::Let(_, t, _)
+            | Kind::TyApp(t, _)
+            | Kind::Injection(_, t, _)
+            | Kind::Projection(t, _)
+            | Kind::Fix(t)
+            | Kind::Case(t, _)
+            | Kind::Fold(_, t)
+            | Kind::Unfold(_, t)
+            | Kind::Pack(_, t, _)
+            | Kind::Unpack(t, _)
+            | Kind::Raise(_, t) => t,
+            _ => return term,
+        };
+        if next.is_value() {
+            return term;
+        }
+        term = next;
+    }
+}
+
+/// Evaluate a term to a value in a single derivation, following the natural
+/// semantics instead of taking small steps. This is an independent
+/// implementation of the same call-by-value semantics as [`step`], and
+/// fails with the same [`EvalError::Stuck`] redex. It does not return if
+/// the term diverges, and unlike [`step`] it recurses on the native stack,
+/// so it may overflow on very deep terms
+pub fn big_step(ctx: &Context, term: &Term) -> EvalOutcome {
+    let ev = Eval::with_context(ctx);
+    match ev.big_step(term) {
+        Ok(v) => EvalOutcome::Value(v),
+        Err(outcome) => outcome,
+    }
+}
+
+impl<'ctx, H: EvalHooks> Eval<'ctx, H> {
+    /// Evaluation that does not produce a value is cut short with its
+    /// outcome, which is never [`EvalOutcome::Value`]
+    fn big_step(&self, term: &Term) -> Result<Term, EvalOutcome> {
+        let stuck = |kind| Err(EvalError::Stuck(Term::new(kind, term.span)).into());
+        match &term.kind {
+            Kind::Lit(_) | Kind::Abs(..) | Kind::TyAbs(_) | Kind::Primitive(_) => Ok(term.clone()),
+            Kind::Var(_) => Err(EvalError::Stuck(term.clone()).into()),
+            Kind::Share(_) => Ok(term.clone()),
+            Kind::App(t1, t2) => {
+                let v1 = self.big_step(t1)?;
+                let v2 = self.big_step(t2)?;
+                self.apply(v1, v2, term.span)
+            }
+            Kind::Let(pat, t1, t2) => {
+                let v = self.big_step(t1)?;
+                let mut body = *t2.clone();
+                case_subst(pat, &v, &mut body);
+                self.big_step(&body).map(|v| v.respan(term.span))
+            }
+            Kind::TyApp(tm, ty) => match self.big_step(tm)?.unshare() {
+                Term {
+                    kind: Kind::TyAbs(mut body),
+                    ..
+                } => {
+                    type_subst(*ty.clone(), &mut body);
+                    self.big_step(&body).map(|v| v.respan(term.span))
+                }
+                v => stuck(Kind::TyApp(Box::new(v), ty.clone())),
+            },
+            Kind::Injection(label, tm, ty) => {
+                let v = self.big_step(tm)?;
+                Ok(Term::new(
+                    Kind::Injection(label.clone(), Box::new(v), ty.clone()),
+                    term.span,
+                ))
+            }
+            Kind::Projection(tm, idx) => match self.big_step(tm)?.unshare() {
+                Term {
+                    kind: Kind::Product(mut terms),
+                    ..
+                } if *idx < terms.len() => Ok(terms.swap_remove(*idx)),
+                v => stuck(Kind::Projection(Box::new(v), *idx)),
+            },
+            Kind::Product(terms) => {
+                let vs = terms.iter().map(|t| self.big_step(t)).collect::<Result<_, _>>()?;
+                Ok(Term::new(Kind::Product(vs), term.span))
+            }
+            Kind::Fix(tm) => {
+                let v = self.big_step(tm)?;
+                let fix = Term::new(Kind::Fix(Box::new(v.clone())), term.span);
+                let v = v.unshare();
+                match v.kind {
+                    Kind::Abs(_, mut body) => {
+                        term_subst(fix, &mut body);
+                        self.big_step(&body).map(|v| v.respan(term.span))
+                    }
+                    _ => self.apply(v, fix, term.span),
+                }
+            }
+            Kind::Case(expr, arms) => {
+                let v = self.big_step(expr)?;
+                for arm in arms {
+                    if !arm.pat.matches(&v) {
+                        continue;
+                    }
+                    if let Some(guard) = &arm.guard {
+                        let mut guard = *guard.clone();
+                        case_subst(&arm.pat, &v, &mut guard);
+                        match self.big_step(&guard)?.kind {
+                            Kind::Lit(Literal::Bool(true)) => {}
+                            Kind::Lit(Literal::Bool(false)) => continue,
+                            kind => return stuck(kind),
+                        }
+                    }
+                    let mut body = *arm.term.clone();
+                    case_subst(&arm.pat, &v, &mut body);
+                    return self.big_step(&body).map(|v| v.respan(term.span));
+                }
+                stuck(Kind::Case(Box::new(v), arms.clone()))
+            }
+            Kind::Fold(ty, tm) => {
+                let v = self.big_step(tm)?;
+                Ok(Term::new(Kind::Fold(ty.clone(), Box::new(v)), term.span))
+            }
+            Kind::Unfold(ty, tm) => match self.big_step(tm)?.unshare() {
+                Term {
+                    kind: Kind::Fold(_, inner),
+                    ..
+                } => Ok(inner.respan(term.span)),
+                v => stuck(Kind::Unfold(ty.clone(), Box::new(v))),
+            },
+            Kind::Pack(wit, evidence, sig) => {
+                let v = self.big_step(evidence)?;
+                Ok(Term::new(Kind::Pack(wit.clone(), Box::new(v), sig.clone()), term.span))
+            }
+            Kind::Unpack(package, body) => match self.big_step(package)?.unshare() {
+                Term {
+                    kind: Kind::Pack(wit, mut evidence, _),
+                    ..
+                } => {
+                    let mut body = *body.clone();
+                    TyShift::new(1).visit(&mut evidence);
+                    term_subst(*evidence, &mut body);
+                    type_subst(*wit, &mut body);
+                    self.big_step(&body).map(|v| v.respan(term.span))
+                }
+                v => stuck(Kind::Unpack(Box::new(v), body.clone())),
+            },
+            Kind::Raise(ty, tm) => {
+                let v = self.big_step(tm)?;
+                Err(EvalOutcome::Raised(Term::new(
+                    Kind::Raise(ty.clone(), Box::new(v)),
+                    term.span,
+                )))
+            }
+        }
+    }
+
+    /// Apply a function value to an argument value
+    fn apply(&self, f: Term, arg: Term, span: Span) -> Result<Term, EvalOutcome> {
+        let f = f.unshare();
+        let res = match f.kind {
+            Kind::Abs(_, mut body) => {
+                term_subst(arg, &mut body);
+                return self.big_step(&body).map(|v| v.respan(span));
+            }
+            Kind::Primitive(p) if p.arity() > 1 => return Ok(Term::new(Kind::App(Box::new(f), Box::new(arg)), span)),
+            Kind::Primitive(p) => self.eval_primitive(p, &[&arg]),
+            Kind::App(ref g, ref a) => match g.kind {
+                Kind::Primitive(p) => self.eval_primitive(p, &[a, &arg]),
+                _ => None,
+            },
+            _ => None,
+        };
+        res.ok_or_else(|| stuck(Term::new(Kind::App(Box::new(f), Box::new(arg)), span)).into())
+    }
+}
+
+/// Substitute the parts of `expr` matched by the binders of `pat` into `term`
+pub fn case_subst(pat: &Pattern, expr: &Term, term: &mut Term) {
+    let mut binds = Vec::new();
+    bindings(pat, expr, &mut binds);
+    for (_, tm) in binds {
+        term_subst(tm, term);
+    }
+}
+
+/// Label of the injection a case expression dispatches on, if it is one
+fn label(expr: &Term) -> Option<&str> {
+    match &expr.unshared().kind {
+        Kind::Injection(label, _, _) => Some(label),
+        Kind::Fold(_, inner) => label(inner),
+        _ => None,
+    }
+}
+
+/// Collect the subterms of `expr` matched by each variable of `pat`, from
+/// left to right
+fn bindings<'p>(pat: &'p Pattern, expr: &Term, binds: &mut Vec<(&'p str, Term)>) {
+    use Pattern::*;
+    match (pat, &expr.unshared().kind) {
+        // Nested patterns destructure the unfolding of a recursive type
+        (Product(_), Kind::Fold(_, inner)) | (Constructor(_, _), Kind::Fold(_, inner)) => {
+            return bindings(pat, inner, binds)
+        }
+        _ => {}
+    }
+    match pat {
+        Any => {}
+        Literal(_) => {}
+        Variable(name) => {
+            binds.push((name, expr.clone()));
+        }
+        Product(v) => {
+            if let Kind::Product(terms) = &expr.unshared().kind {
+                for (pat, tm) in v.iter().zip(terms) {
+                    bindings(pat, tm, binds);
+                }
+            } else {
+                panic!("wrong type!")
+            }
+        }
+        Constructor(label, v) => {
+            if let Kind::Injection(label_, tm, _) = &expr.unshared().kind {
+                if label == label_ {
+                    bindings(&v, &tm, binds);
+                }
+            } else {
+                panic!("wrong type!")
+            }
+        }
+        As(pat, name) => {
+            bindings(pat, expr, binds);
+            binds.push((name, expr.clone()));
+        }
+        Succ(pat) => {
+            if let Kind::Lit(crate::terms::Literal::Nat(n)) = &expr.unshared().kind {
+                let pred = Term::new(Kind::Lit(crate::terms::Literal::Nat(n - 1)), expr.span);
+                bindings(pat, &pred, binds);
+            } else {
+                panic!("wrong type!")
+            }
+        }
+        Or(alts) => {
+            // Take the first matching alternative, but bind its variables in
+            // the order they appear in the first alternative
+            let alt = alts
+                .iter()
+                .find(|alt| alt.matches(expr))
+                .expect("no alternative matches!");
+            let mut found = Vec::new();
+            bindings(alt, expr, &mut found);
+            for name in PatVarStack::collect(&mut alts[0].clone()) {
+                let idx = found
+                    .iter()
+                    .position(|(n, _)| *n == name)
+                    .expect("inconsistent or-pattern");
+                binds.push(found.remove(idx));
+            }
+        }
+    }
+}
+
+/// An evaluation context one term deep: a term with a hole in the position
+/// being evaluated, holding every other part of the term
+enum Frame {
+    /// `[] t2`
+    AppFun(Box<Term>, Span),
+    /// `v1 []`
+    AppArg(Box<Term>, Span),
+    /// `let pat = [] in body`
+    Let(Box<Pattern>, Box<Term>, Span),
+    /// `[] [ty]`
+    TyApp(Box<Type>, Span),
+    Injection(String, Box<Type>, Span),
+    Projection(usize, Span),
+    /// A product, with the components before the hole and those after it
+    Product(Vec<Term>, std::vec::IntoIter<Term>, Span),
+    Fix(Span),
+    /// `case [] of arms`
+    Case(Vec<Arm>, Span),
+    Fold(Box<Type>, Span),
+    Unfold(Box<Type>, Span),
+    /// `pack wit, [] as sig`
+    Pack(Box<Type>, Box<Type>, Span),
+    /// `unpack [] as T, x in body`
+    Unpack(Box<Term>, Span),
+    Raise(Box<Type>, Span),
+}
+
+impl Frame {
+    /// The span of the term this is the context of
+    fn span(&self) -> Span {
+        match self {
+            Frame::AppFun(_, span)
+            | Frame::AppArg(_, span)
+            | Frame::Let(_, _, span)
+            | Frame::TyApp(_, span)
+            | Frame::Injection(_, _, span)
+            | Frame::Projection(_, span)
+            | Frame::Product(_, _, span)
+            | Frame::Fix(span)
+            | Frame::Case(_, span)
+            | Frame::Fold(_, span)
+            | Frame::Unfold(_, span)
+            | Frame::Pack(_, _, span)
+            | Frame::Unpack(_, span)
+            | Frame::Raise(_, span) => *span,
+        }
+    }
+
+    fn plug(self, tm: Term) -> Term {
+        let tm = Box::new(tm);
+        let (kind, span) = match self {
+            Frame::AppFun(t2, span) => (Kind::App(tm, t2), span),
+            Frame::AppArg(t1, span) => (Kind::App(t1, tm), span),
+            Frame::Let(pat, body, span) => (Kind::Let(pat, tm, body), span),
+            Frame::TyApp(ty, span) => (Kind::TyApp(tm, ty), span),
+            Frame::Injection(label, ty, span) => (Kind::Injection(label, tm, ty), span),
+            Frame::Projection(idx, span) => (Kind::Projection(tm, idx), span),
+            Frame::Product(mut done, rest, span) => {
+                done.push(*tm);
+                done.extend(rest);
+                (Kind::Product(done), span)
+            }
+            Frame::Fix(span) => (Kind::Fix(tm), span),
+            Frame::Case(arms, span) => (Kind::Case(tm, arms), span),
+            Frame::Fold(ty, span) => (Kind::Fold(ty, tm), span),
+            Frame::Unfold(ty, span) => (Kind::Unfold(ty, tm), span),
+            Frame::Pack(wit, sig, span) => (Kind::Pack(wit, tm, sig), span),
+            Frame::Unpack(body, span) => (Kind::Unpack(tm, body), span),
+            Frame::Raise(ty, span) => (Kind::Raise(ty, tm), span),
+        };
+        Term::new(kind, span)
+    }
+}
+
+/// Plug a term into a stack of frames, innermost last
+fn plug(frames: Vec<Frame>, tm: Term) -> Term {
+    frames.into_iter().rev().fold(tm, |tm, frame| frame.plug(tm))
+}
+
+/// Where the search for a redex goes next
+enum Focus {
+    /// Search a subterm, in this context
+    Within(Frame, Term),
+    Value(Term),
+    Stuck(Term),
+    /// A redex was found and contracted to this term
+    Contracted(Term),
+    /// A raised error, whose payload is a value
+    Raised(Term),
+}
+
+/// Give the result of a reduction the span of the redex, unless it is a
+/// `raise`, which keeps the span of the raise site
+fn contract(tm: Term, span: Span) -> Term {
+    match tm.kind {
+        Kind::Raise(..) => tm,
+        _ => tm.respan(span),
+    }
+}
+
+fn term_subst(s: Term, t: &mut Term) {
+    term_subst_measured(s, t, false);
+}
+
+/// Substitute `s` for the variable 0 in `t`, returning the number of nodes
+/// copied if `measure` is set, and 0 otherwise. A closed value, which is
+/// every term substituted while evaluating a closed term, is shared between
+/// its copies instead of duplicated
+fn term_subst_measured(mut s: Term, t: &mut Term, measure: bool) -> usize {
+    let mut subst = match s.is_value() && Closed::check(&s) {
+        true => Subst::shared(s),
+        false => {
+            Shift::new(1).visit(&mut s);
+            Subst::new(s)
+        }
+    };
+    subst.visit(t);
+    Shift::new(-1).visit(t);
+    match measure {
+        true => subst.copies() * hooks::copy_size(subst.term()),
+        false => 0,
+    }
+}
+
+fn type_subst(s: Type, t: &mut Term) {
+    TyTermSubst::new(s).visit(t);
+    TyShift::new(-1).visit(t);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn literal() {
+        let ctx = crate::types::Context::default();
+        let eval = Eval::with_context(&ctx);
+        assert_eq!(eval.small_step(lit!(false)), None);
+    }
+
+    #[test]
+    fn application() {
+        let ctx = crate::types::Context::default();
+        let eval = Eval::with_context(&ctx);
+        let tm = app!(abs!(Type::Nat, app!(prim!(Primitive::Succ), var!(0))), nat!(1));
+
+        let t1 = eval.small_step(tm);
+        assert_eq!(t1, Some(app!(prim!(Primitive::Succ), nat!(1))));
+        let t2 = eval.small_step(t1.unwrap());
+        assert_eq!(t2, Some(nat!(2)));
+        let t3 = eval.small_step(t2.unwrap());
+        assert_eq!(t3, None);
+    }
+
+    #[test]
+    fn type_application() {
+        let ctx = crate::types::Context::default();
+        let eval = Eval::with_context(&ctx);
+        let tm = tyapp!(
+            tyabs!(abs!(Type::Var(0), app!(prim!(Primitive::Succ), var!(0)))),
+            Type::Nat
+        );
+
+        let t1 = eval.small_step(tm);
+        assert_eq!(t1, Some(abs!(Type::Nat, app!(prim!(Primitive::Succ), var!(0)))));
+        let t2 = eval.small_step(t1.unwrap());
+        assert_eq!(t2, None);
+    }
+
+    #[test]
+    fn projection() {
+        let ctx = crate::types::Context::default();
+        let eval = Eval::with_context(&ctx);
+        let term = app!(prim!(Primitive::Succ), proj!(tuple!(nat!(5), nat!(6), nat!(29)), 2));
+
+        let t1 = eval.small_step(term);
+        assert_eq!(t1, Some(app!(prim!(Primitive::Succ), nat!(29))));
+        let t2 = eval.small_step(t1.unwrap());
+        assert_eq!(t2, Some(nat!(30)));
+        let t3 = eval.small_step(t2.unwrap());
+        assert_eq!(t3, None);
+    }
+
+    #[test]
+    fn reduced_spans() {
+        let ctx = crate::types::Context::default();
+        let eval = Eval::with_context(&ctx);
+        let tm = crate::syntax::parser::Parser::new("(\\x: Nat. (x, 1)) 5")
+            .parse()
+            .unwrap();
+        let (body, arg) = match &tm.kind {
+            Kind::App(abs, arg) => match &abs.kind {
+                Kind::Abs(_, body) => (body.clone(), arg.clone()),
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        };
+
+        let t1 = eval.small_step(tm.clone()).unwrap();
+        assert_eq!(t1.to_string(), "(5,1)");
+        // The reduced term replaces the whole application
+        assert_eq!(t1.span, tm.span);
+        match (&t1.kind, &body.kind) {
+            (Kind::Product(after), Kind::Product(before)) => {
+                // The substituted copy keeps the argument's span, and the
+                // literal keeps its own
+                assert_eq!(after[0].span, arg.span);
+                assert_eq!(after[1].span, before[1].span);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn congruence() {
+        let ctx = crate::types::Context::default();
+        let eval = Eval::with_context(&ctx);
+
+        // Only the leftmost component of a product steps
+        let tm = tuple!(
+            nat!(1),
+            app!(prim!(Primitive::Succ), nat!(1)),
+            app!(prim!(Primitive::Succ), nat!(2))
+        );
+        let t1 = eval.small_step(tm).unwrap();
+        assert_eq!(t1, tuple!(nat!(1), nat!(2), app!(prim!(Primitive::Succ), nat!(2))));
+
+        // The payload of a fold is evaluated before it is unfolded
+        let ty = Type::Rec(Box::new(Type::Nat));
+        let tm = unfold!(ty.clone(), fold!(ty.clone(), app!(prim!(Primitive::Succ), nat!(0))));
+        let t1 = eval.small_step(tm).unwrap();
+        assert_eq!(t1, unfold!(ty.clone(), fold!(ty, nat!(1))));
+        assert_eq!(eval.small_step(t1), Some(nat!(1)));
+    }
+
+    /// A small linear congruential generator, so that the corpus is the same
+    /// on every run
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn below(&mut self, n: u64) -> u64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (self.0 >> 33) % n
+        }
+    }
+
+    fn nat_list() -> Type {
+        Type::Rec(Box::new(unrolled(Type::Var(0))))
+    }
+
+    fn unrolled(tail: Type) -> Type {
+        Type::Variant(vec![
+            variant!("Nil", Type::Unit),
+            variant!("Cons", Type::Product(vec![Type::Nat, tail])),
+        ])
+    }
+
+    /// Generate a closed term of type `ty`, where `env` holds the types of
+    /// the variables in scope, innermost first
+    fn gen(rng: &mut Lcg, ty: &Type, depth: u32, env: &mut Vec<Type>) -> Term {
+        let nat = Type::Nat;
+        let pair = Type::Product(vec![Type::Nat, Type::Bool]);
+        let list = unrolled(nat_list());
+        if let Some(idx) = env.iter().position(|t| t == ty) {
+            if rng.below(3) == 0 {
+                return var!(idx);
+            }
+        }
+        if depth > 0 {
+            // Eliminations, which work at any type
+            match rng.below(10) {
+                0 => {
+                    let arg = [&nat, &pair][rng.below(2) as usize];
+                    env.insert(0, arg.clone());
+                    let body = gen(rng, ty, depth - 1, env);
+                    env.remove(0);
+                    return app!(abs!(arg.clone(), body), gen(rng, arg, depth - 1, env));
+                }
+                1 => {
+                    let c = gen(rng, &Type::Bool, depth - 1, env);
+                    let t = gen(rng, ty, depth - 1, env);
+                    let f = gen(rng, ty, depth - 1, env);
+                    return case!(c, boolean!(true) => t, boolean!(false) => f);
+                }
+                2 => {
+                    let l = gen(rng, &list, depth - 1, env);
+                    let nil = gen(rng, ty, depth - 1, env);
+                    env.insert(0, nat_list());
+                    env.insert(0, Type::Nat);
+                    let cons = gen(rng, ty, depth - 1, env);
+                    env.drain(..2);
+                    let pat = con!(
+                        "Cons",
+                        prod!(Pattern::Variable("x".into()), Pattern::Variable("xs".into()))
+                    );
+                    return case!(l, con!("Nil", Pattern::Any) => nil, pat => cons);
+                }
+                3 => return tyapp!(tyabs!(gen(rng, ty, depth - 1, env)), Type::Bool),
+                _ => {}
+            }
+        }
+        let depth = depth.saturating_sub(1);
+        match ty {
+            Type::Nat => match rng.below(if depth > 0 { 5 } else { 1 }) {
+                0 => nat!(rng.below(4) as u32),
+                1 => app!(prim!(Primitive::Succ), gen(rng, ty, depth, env)),
+                2 => app!(
+                    gen(rng, &arrow!(Type::Nat, Type::Nat), depth, env),
+                    gen(rng, ty, depth, env)
+                ),
+                3 => proj!(gen(rng, &pair, depth, env), 0),
+                _ => {
+                    let head = app!(prim!(Primitive::Add), gen(rng, ty, depth, env));
+                    app!(head, gen(rng, ty, depth, env))
+                }
+            },
+            Type::Bool => match rng.below(if depth > 0 { 3 } else { 1 }) {
+                0 => lit!(rng.below(2) == 0),
+                1 => app!(prim!(Primitive::IsZero), gen(rng, &nat, depth, env)),
+                _ => proj!(gen(rng, &pair, depth, env), 1),
+            },
+            Type::Arrow(..) => match rng.below(3) {
+                0 => prim!(Primitive::Pred),
+                1 => app!(prim!(Primitive::Add), gen(rng, &nat, depth, env)),
+                _ => {
+                    env.insert(0, Type::Nat);
+                    let body = gen(rng, &nat, depth, env);
+                    env.remove(0);
+                    abs!(Type::Nat, body)
+                }
+            },
+            Type::Product(_) => tuple!(gen(rng, &nat, depth, env), gen(rng, &Type::Bool, depth, env)),
+            Type::Rec(_) => match rng.below(if depth > 0 { 2 } else { 1 }) {
+                0 => fold!(nat_list(), gen(rng, &list, depth, env)),
+                // Unfolding a fold whose payload is not yet a value
+                _ => fold!(nat_list(), unfold!(nat_list(), gen(rng, ty, depth, env))),
+            },
+            _ => match rng.below(if depth > 0 { 3 } else { 1 }) {
+                0 => inj!("Nil", Term::unit(), list),
+                1 => unfold!(nat_list(), gen(rng, &nat_list(), depth, env)),
+                _ => {
+                    let cons = tuple!(gen(rng, &nat, depth, env), gen(rng, &nat_list(), depth, env));
+                    inj!("Cons", cons, list)
+                }
+            },
+        }
+    }
+
+    #[test]
+    fn progress() {
+        let types = [
+            Type::Nat,
+            Type::Bool,
+            Type::Product(vec![Type::Nat, Type::Bool]),
+            nat_list(),
+            unrolled(nat_list()),
+        ];
+        let mut rng = Lcg(17);
+        let mut ctx = crate::types::Context::default();
+        let empty = crate::types::Context::default();
+        let eval = Eval::with_context(&empty);
+        for _ in 0..400 {
+            let ty = &types[rng.below(types.len() as u64) as usize];
+            let mut tm = gen(&mut rng, ty, 4, &mut Vec::new());
+            assert_eq!(&ctx.type_check(&tm).unwrap(), ty, "{}", tm);
+
+            let mut steps = 0;
+            while !tm.is_value() {
+                tm = match eval.small_step(tm.clone()) {
+                    Some(next) => next,
+                    None => panic!("well-typed term is stuck: {}", tm),
+                };
+                // Preservation, while we're at it
+                assert_eq!(&ctx.type_check(&tm).unwrap(), ty, "{}", tm);
+                steps += 1;
+                assert!(steps < 10_000);
+            }
+        }
+    }
+
+    #[test]
+    fn trace_steps() {
+        let ctx = crate::types::Context::default();
+        let parse = |input| crate::syntax::parser::Parser::new(input).parse().unwrap();
+        let show = |steps: Vec<Term>| steps.iter().map(Term::to_string).collect::<Vec<_>>();
+
+        let steps = trace(&ctx, parse("(\\X \\x: X. x) [Nat] 1")).unwrap();
+        assert_eq!(
+            show(steps),
+            ["(((λTy (λ_:TyVar(0). #0)) [Nat]) 1)", "((λ_:Nat. #0) 1)", "1"]
+        );
+
+        let tm = parse("case Some 3 of {None | Some Nat} of | None => 0 | Some n => succ n");
+        let steps = trace(&ctx, tm).unwrap();
+        assert_eq!(show(steps)[1..], ["(Succ 3)", "4"]);
+
+        let tm = parse("(\\x: Nat. succ x) (succ 0)");
+        let state = parse("(\\x: Nat. succ x) 1");
+        assert_eq!(
+            trace_with_limit(&ctx, tm, 1).map_err(|e| e.to_string()),
+            Err(format!(
+                "evaluation did not finish within 1 steps, reaching `{}`",
+                state
+            ))
+        );
+        assert_eq!(trace(&ctx, var!(0)), Err(EvalError::Stuck(var!(0))));
+    }
+
+    #[test]
+    fn big_step_agrees() {
+        let types = [
+            Type::Nat,
+            Type::Bool,
+            Type::Product(vec![Type::Nat, Type::Bool]),
+            nat_list(),
+            unrolled(nat_list()),
+        ];
+        let mut rng = Lcg(23);
+        let ctx = crate::types::Context::default();
+        for _ in 0..400 {
+            let ty = &types[rng.below(types.len() as u64) as usize];
+            let tm = gen(&mut rng, ty, 5, &mut Vec::new());
+            let small = run(&ctx, tm.clone(), Limits::default(), |_| {});
+            assert_eq!(big_step(&ctx, &tm), small, "{}", tm);
+        }
+
+        // Both report the same redex for stuck terms, and the same misuse of
+        // a primitive
+        let parse = |input| crate::syntax::parser::Parser::new(input).parse().unwrap();
+        let stuck = [
+            app!(prim!(Primitive::Succ), app!(prim!(Primitive::Pred), var!(0))),
+            parse("(1, (\\y: Nat. y) 2) 3"),
+            parse("(unit, iszero (succ true))"),
+        ];
+        for tm in &stuck {
+            let small = run(&ctx, tm.clone(), Limits::default(), |_| {});
+            assert!(
+                matches!(
+                    small,
+                    EvalOutcome::Stuck(EvalError::Stuck(_)) | EvalOutcome::Stuck(EvalError::PrimitiveMisuse { .. })
+                ),
+                "{}",
+                tm
+            );
+            assert_eq!(big_step(&ctx, tm), small, "{}", tm);
+        }
+    }
+
+    /// Run with
+    /// `cargo test --release -- --ignored --nocapture big_step_arithmetic`
+    #[test]
+    #[ignore]
+    fn big_step_arithmetic() {
+        use std::time::Instant;
+        let input = "letrec sum: Nat->Nat = \\n: Nat. case n of | 0 => 0 | _ => add n (sum (pred n)) in sum 300";
+        let tm = crate::syntax::parser::Parser::new(input).parse().unwrap();
+        let ctx = crate::types::Context::default();
+
+        let start = Instant::now();
+        let small = trace(&ctx, tm.clone()).unwrap().pop().unwrap();
+        println!("small step: {:?}", start.elapsed());
+
+        let start = Instant::now();
+        let big = big_step(&ctx, &tm).value().unwrap();
+        println!("big step: {:?}", start.elapsed());
+        assert_eq!(big.kind, small.kind);
+    }
+
+    #[test]
+    fn fuel() {
+        let ctx = crate::types::Context::default();
+        let parse = |input| crate::syntax::parser::Parser::new(input).parse().unwrap();
+
+        let tm = parse("fix (\\x: Nat. x)");
+        match eval_limited(&ctx, tm.clone(), 100) {
+            EvalOutcome::Stuck(EvalError::OutOfFuel { steps, state }) => {
+                assert_eq!(steps, 100);
+                assert_eq!(state.kind, tm.kind);
+            }
+            res => panic!("{:?}", res),
+        }
+        let limits = Limits {
+            fuel: 100,
+            cycle_check: Some(4),
+        };
+        match run(&ctx, tm, limits, |_| {}) {
+            EvalOutcome::Stuck(EvalError::Cycle { steps, .. }) => assert_eq!(steps, 8),
+            res => panic!("{:?}", res),
+        }
+
+        // Terminating programs are unaffected, even when checking for cycles
+        let tm = parse("letrec f: Nat->Nat = \\n: Nat. case n of | 0 => 0 | _ => succ (f (pred n)) in f 20");
+        assert_eq!(
+            eval_limited(&ctx, tm.clone(), DEFAULT_FUEL).value().unwrap().kind,
+            Kind::Lit(Literal::Nat(20))
+        );
+        let limits = Limits {
+            cycle_check: Some(1),
+            ..Limits::default()
+        };
+        assert_eq!(
+            run(&ctx, tm, limits, |_| {}).value().unwrap().kind,
+            Kind::Lit(Literal::Nat(20))
+        );
+    }
+
+    /// Polymorphic programs, whose type applications and packages leave type
+    /// variables behind in erasure mode
+    const POLYMORPHIC: [&str; 5] = [
+        "(\\X \\x: X. x) [Nat] 1",
+        "(\\X \\f: X -> X. \\x: X. f (f x)) [Nat -> Nat] (\\g: Nat -> Nat. \\n: Nat. g (g n)) succ 0",
+        "let twice = \\X \\f: X -> X. \\x: X. f (f x) in (twice [Bool] (\\b: Bool. if b then false else true) true, twice [Nat] pred 5)",
+        "unpack (pack Nat, (0, \\n: Nat. succ n) as exists X. (X, X -> X)) as T, p in (p.1) (p.0)",
+        "letrec f: Nat -> Nat = \\n: Nat. case n of | 0 => 0 | _ => succ ((\\X \\x: X. x) [Nat] (f (pred n))) in f 10",
+    ];
+
+    #[test]
+    fn raise_propagates() {
+        use crate::terms::locate::KindTag;
+        let ctx = crate::types::Context::default();
+        let input = "fold rec X = Nat ((\\x: Nat. x) (case 1 of | 0 => 0 | _ => raise Nat (iszero 0)))";
+        let tm = crate::syntax::parser::Parser::new(input).parse().unwrap();
+        assert_eq!(ctx.clone().type_check(&tm).unwrap().to_string(), "rec X = Nat");
+        let site = tm
+            .spans()
+            .into_iter()
+            .find(|(_, tag)| *tag == KindTag::Raise)
+            .unwrap()
+            .0;
+
+        let steps = trace(&ctx, tm.clone()).unwrap();
+        let show = steps.iter().map(Term::to_string).collect::<Vec<_>>();
+        assert_eq!(
+            show[1..],
+            [
+                "fold [rec Nat] ((λ_:Nat. #0) raise [Nat] (IsZero 0))",
+                "fold [rec Nat] ((λ_:Nat. #0) raise [Nat] true)",
+                "fold [rec Nat] raise [Nat] true",
+                "raise [Nat] true",
+            ]
+        );
+
+        let small = run(&ctx, tm.clone(), Limits::default(), |_| {});
+        assert_eq!(big_step(&ctx, &tm), small);
+        match small {
+            EvalOutcome::Raised(raise) => {
+                assert_eq!(raise.span, site);
+                assert!(raise.is_raised());
+            }
+            outcome => panic!("{:?}", outcome),
+        }
+    }
+
+    #[test]
+    fn hook_counts() {
+        use hooks::CountingHooks;
+        let ctx = crate::types::Context::default();
+        let count = |input: &str| {
+            let tm = crate::syntax::parser::Parser::new(input).parse().unwrap();
+            let ev = Eval::with_context(&ctx).with_hooks(CountingHooks::default());
+            assert!(run_with(&ev, tm, Limits::default(), |_| {}).value().is_some());
+            ev.hooks
+        };
+
+        let hooks = count("(\\x: Nat. succ x) 1");
+        assert_eq!((hooks.steps(KindTag::App), hooks.steps(KindTag::Primitive)), (1, 1));
+        assert_eq!(
+            (hooks.total_steps(), hooks.cases(), hooks.copied(), hooks.peak_size()),
+            (2, 0, 1, 6)
+        );
+
+        let hooks = count("(\\X \\x: X. x) [Nat] 1");
+        assert_eq!((hooks.steps(KindTag::TyApp), hooks.steps(KindTag::App)), (1, 1));
+        assert_eq!(
+            (hooks.total_steps(), hooks.cases(), hooks.copied(), hooks.peak_size()),
+            (2, 0, 1, 6)
+        );
+
+        let hooks = count("case Some 3 of {None | Some Nat} of | None => 0 | Some n => succ n");
+        assert_eq!(
+            hooks.to_string(),
+            "2 steps: Primitive 1, Case 1\n\
+             1 case dispatches: Some 1\n\
+             1 nodes copied by substitution\n\
+             peak term size 7"
+        );
+
+        let hooks = count("letrec f: Nat->Nat = \\n: Nat. case n of | 0 => 0 | _ => succ (f (pred n)) in f 2");
+        assert_eq!(
+            hooks.to_string(),
+            "16 steps: Fix 3, Primitive 4, Projection 2, Case 3, Let 1, App 3\n\
+             3 case dispatches: _ 3\n\
+             52 nodes copied by substitution\n\
+             peak term size 36"
+        );
+    }
+
+    #[test]
+    fn erasure_agrees() {
+        use crate::erase::erase;
+        let types = [
+            Type::Nat,
+            Type::Bool,
+            Type::Product(vec![Type::Nat, Type::Bool]),
+            nat_list(),
+            unrolled(nat_list()),
+        ];
+        let mut rng = Lcg(31);
+        let ctx = crate::types::Context::default();
+        let precise = Eval::with_context(&ctx);
+        let erased = Eval::with_mode(&ctx, ErasureMode::Erased);
+        let both = |tm: Term| {
+            let p = run_with(&precise, tm.clone(), Limits::default(), |_| {})
+                .value()
+                .unwrap();
+            let e = run_with(&erased, tm, Limits::default(), |_| {}).value().unwrap();
+            (p, e)
+        };
+        for _ in 0..400 {
+            let ty = &types[rng.below(types.len() as u64) as usize];
+            let tm = gen(&mut rng, ty, 5, &mut Vec::new());
+            let (p, e) = both(tm.clone());
+            assert_eq!(erase(&p), erase(&e), "{}", tm);
+        }
+
+        for input in &POLYMORPHIC {
+            let tm = crate::syntax::parser::Parser::new(input).parse().unwrap();
+            let (p, e) = both(tm);
+            assert_eq!(erase(&p), erase(&e), "{}", input);
+        }
+
+        // Only the stale types tell the results apart
+        let tm = crate::syntax::parser::Parser::new("(\\X \\x: X. x) [Nat]")
+            .parse()
+            .unwrap();
+        let (p, e) = both(tm);
+        assert_eq!(p.to_string(), "(λ_:Nat. #0)");
+        assert_eq!(e.to_string(), "(λ_:TyVar(0). #0)");
+    }
+
+    /// Run with
+    /// `cargo test --release -- --ignored --nocapture erasure_speed`
+    #[test]
+    #[ignore]
+    fn erasure_speed() {
+        use std::time::Instant;
+        let input = "let compose = \\X \\Y \\Z \\f: Y -> Z. \\g: X -> Y. \\x: X. f (g x) in \
+                     let twice = \\X \\f: X -> X. compose [X] [X] [X] f f in \
+                     letrec go: Nat -> Nat = \\n: Nat. case n of \
+                       | 0 => 0 \
+                       | _ => twice [Nat] (\\m: Nat. m) (succ (go (pred n))) in go 200";
+        let tm = crate::syntax::parser::Parser::new(input).parse().unwrap();
+        let ctx = crate::types::Context::default();
+
+        let start = Instant::now();
+        let precise = run(&ctx, tm.clone(), Limits::default(), |_| {}).value().unwrap();
+        println!("precise: {:?}", start.elapsed());
+
+        let start = Instant::now();
+        let ev = Eval::with_mode(&ctx, ErasureMode::Erased);
+        let erased = run_with(&ev, tm, Limits::default(), |_| {}).value().unwrap();
+        println!("erased: {:?}", start.elapsed());
+        assert_eq!(precise.kind, erased.kind);
+    }
+
+    #[test]
+    fn primitive_semantics() {
+        let ctx = crate::types::Context::default();
+        let eval = |tm: Term| (big_step(&ctx, &tm), eval_limited(&ctx, tm, 100));
+
+        let (big, small) = eval(app!(prim!(Primitive::Pred), nat!(0)));
+        assert_eq!(
+            (big.value().unwrap().kind, small.value().unwrap().kind),
+            (nat!(0).kind, nat!(0).kind)
+        );
+
+        // Partial applications are values
+        let partial = app!(prim!(Primitive::Add), nat!(1));
+        assert!(partial.is_value());
+        assert_eq!(
+            eval(partial.clone()),
+            (EvalOutcome::Value(partial.clone()), EvalOutcome::Value(partial))
+        );
+
+        // Ill-typed applications are reported the same way by both evaluators
+        let misuse = |prim, args: Vec<Term>| EvalError::PrimitiveMisuse { prim, args };
+        let tm = app!(prim!(Primitive::IsZero), lit!(true));
+        let expected = EvalOutcome::Stuck(misuse(Primitive::IsZero, vec![lit!(true)]));
+        assert_eq!(eval(tm), (expected.clone(), expected));
+
+        let tm = app!(
+            app!(prim!(Primitive::Add), nat!(1)),
+            app!(prim!(Primitive::IsZero), nat!(0))
+        );
+        let expected = misuse(Primitive::Add, vec![nat!(1), lit!(true)]);
+        let outcome = EvalOutcome::Stuck(expected.clone());
+        assert_eq!(eval(tm), (outcome.clone(), outcome));
+        assert_eq!(expected.to_string(), "primitive Add cannot be applied to `1`, `true`");
+    }
+
+    #[test]
+    fn substitution_shares() {
+        use crate::terms::visit::TermStats;
+        use hooks::CountingHooks;
+        use std::rc::Rc;
+        let ctx = crate::types::Context::default();
+        let input = "(\\x: (Nat, Nat -> Nat). (x, x, x, x)) (1, \\y: Nat. succ y)";
+        let tm = crate::syntax::parser::Parser::new(input).parse().unwrap();
+        let ev = Eval::with_context(&ctx).with_hooks(CountingHooks::default());
+        let small = run_with(&ev, tm.clone(), Limits::default(), |_| {}).value().unwrap();
+        assert_eq!(big_step(&ctx, &tm).value().unwrap(), small);
+
+        // One copy of the argument, pointed to four times
+        let mut copies = match small.kind {
+            Kind::Product(terms) => terms,
+            kind => panic!("{:?}", kind),
+        };
+        let shared = copies
+            .iter()
+            .map(|tm| match &tm.kind {
+                Kind::Share(rc) => rc.clone(),
+                kind => panic!("{:?}", kind),
+            })
+            .collect::<Vec<_>>();
+        assert!(shared.windows(2).all(|w| Rc::ptr_eq(&w[0], &w[1])));
+        assert_eq!(ev.hooks().copied(), 4);
+        let whole = Term::new(Kind::Product(copies.clone()), small.span);
+        assert_eq!(hooks::size(&whole), 1 + 4 + 6);
+        assert_eq!(TermStats::collect(&whole).total(), 1 + 4 + 4 * 6);
+
+        // Rewriting one copy leaves the others shared
+        struct Bump;
+        impl MutTermVisitor for Bump {
+            fn visit_lit(&mut self, sp: &mut Span, lit: &mut Literal) {
+                if let Literal::Nat(n) = lit {
+                    *n += 1;
+                }
+            }
+        }
+        Bump.visit(&mut copies[0]);
+        assert_eq!(copies[0].to_string(), "(2,(λ_:Nat. (Succ #0)))");
+        assert_eq!(copies[1].to_string(), "(1,(λ_:Nat. (Succ #0)))");
+        let shares = |tm: &Term| matches!(&tm.kind, Kind::Share(rc) if Rc::ptr_eq(rc, &shared[0]));
+        assert!(!shares(&copies[0]));
+        assert!(copies[1..].iter().all(shares));
+    }
+
+    /// A chain of lets, each duplicating the one before, so that copying
+    /// substitution builds a term exponential in its length. The logical
+    /// size is the number of nodes it would have copied; the physical size
+    /// is the number of nodes allocated with sharing
+    ///
+    /// `cargo test --release -- --ignored --nocapture sharing_speed`
+    #[test]
+    #[ignore]
+    fn sharing_speed() {
+        use crate::terms::visit::TermStats;
+        use std::time::Instant;
+        let ctx = crate::types::Context::default();
+        for depth in &[4, 6, 8, 10] {
+            let mut input = String::from("let x0 = \\y: Nat. succ y in ");
+            for i in 1..=*depth {
+                input += &format!("let x{0} = (x{1}, x{1}, x{1}, x{1}) in ", i, i - 1);
+            }
+            input += &format!("x{}", depth);
+            let tm = crate::syntax::parser::Parser::new(&input).parse().unwrap();
+
+            let start = Instant::now();
+            let v = run(&ctx, tm, Limits::default(), |_| {}).value().unwrap();
+            let elapsed = start.elapsed();
+            let stats = TermStats::collect(&v);
+            println!(
+                "depth {}: {:?}, {} nodes allocated, {} nodes without sharing",
+                depth,
+                elapsed,
+                hooks::size(&v),
+                stats.total() - stats.count("Share")
+            );
+        }
+    }
+
+    #[derive(Default)]
+    struct Log(std::cell::RefCell<Vec<KindTag>>);
+
+    impl EvalHooks for Log {
+        const MEASURE: bool = false;
+
+        fn on_step(&self, redex: KindTag) {
+            self.0.borrow_mut().push(redex);
+        }
+    }
+
+    #[test]
+    fn evaluation_order() {
+        use KindTag::*;
+        let ctx = crate::types::Context::default();
+        let order = |input: &str| {
+            let tm = crate::syntax::parser::Parser::new(input).parse().unwrap();
+            let ev = Eval::with_context(&ctx).with_hooks(Log::default());
+            let small = run_with(&ev, tm.clone(), Limits::default(), |_| {})
+                .value()
+                .map(|v| v.kind);
+            assert!(small.is_some(), "{}", input);
+            assert_eq!(big_step(&ctx, &tm).value().map(|v| v.kind), small, "{}", input);
+            ev.hooks.0.into_inner()
+        };
+
+        // Products, from first to last component
+        assert_eq!(
+            order("((\\x: Nat. x) 0, succ 0, (\\X \\x: X. x) [Nat] 0)"),
+            [App, Primitive, TyApp, App]
+        );
+        // The function, even when it is an application, before its argument
+        assert_eq!(
+            order("((\\x: Nat. \\y: Nat. x) (succ 0)) ((\\x: Nat. x) 1)"),
+            [Primitive, App, App, App]
+        );
+        assert_eq!(order("add ((\\x: Nat. x) 1) (succ 2)"), [App, Primitive, Primitive]);
+        // Scrutinees, bound terms and packages before the arms and bodies
+        assert_eq!(
+            order("case (\\x: Nat. x) 0 of | 0 => succ 0 | _ => 0"),
+            [App, Case, Primitive]
+        );
+        assert_eq!(
+            order("let (x, y) = (succ 0, (\\x: Nat. x) 0) in x"),
+            [Primitive, App, Let]
+        );
+        assert_eq!(
+            order("unpack (pack Nat, succ 0 as exists X. X) as T, x in (\\y: T. y) x"),
+            [Primitive, Unpack, App]
+        );
+        // Under constructors
+        assert_eq!(
+            order("fold rec X = {A Nat | B X} (A ((\\x: Nat. x) (succ 0)) of {A Nat | B rec X = {A Nat | B X}})"),
+            [Primitive, App]
+        );
+
+        // Of two errors, the leftmost is raised
+        let tm = crate::syntax::parser::Parser::new("(succ 0, raise Nat 1, raise Nat 2)")
+            .parse()
+            .unwrap();
+        let small = run(&ctx, tm.clone(), Limits::default(), |_| {});
+        assert_eq!(big_step(&ctx, &tm), small);
+        match small {
+            EvalOutcome::Raised(raise) => assert_eq!(raise.to_string(), "raise [Nat] 1"),
+            outcome => panic!("{:?}", outcome),
+        }
+    }
+
+    /// A chain of applications of the successor function nested to the
+    /// right, `(\\x. succ x) ((\\x. succ x) (... 0))`
+    fn app_chain(depth: u32) -> Term {
+        let succ = abs!(Type::Nat, app!(prim!(Primitive::Succ), var!(0)));
+        (0..depth).fold(nat!(0), |tm, _| app!(succ.clone(), tm))
+    }
+
+    /// The folded list `[succ 0, succ 1, ...]`, whose elements are all still
+    /// to be evaluated
+    fn deep_list(len: u32) -> Term {
+        let nil = fold!(nat_list(), inj!("Nil", Term::unit(), unrolled(nat_list())));
+        (0..len).rev().fold(nil, |tail, n| {
+            let pair = tuple!(app!(prim!(Primitive::Succ), nat!(n)), tail);
+            fold!(nat_list(), inj!("Cons", pair, unrolled(nat_list())))
+        })
+    }
+
+    /// Take a deep term apart one node at a time, since dropping it would
+    /// recurse once for every level
+    fn dismantle(term: Term) {
+        let mut rest = vec![term];
+        while let Some(tm) = rest.pop() {
+            match tm.kind {
+                Kind::App(t1, t2) => rest.extend([*t1, *t2]),
+                Kind::Abs(_, tm) | Kind::Fold(_, tm) | Kind::Injection(_, tm, _) => rest.push(*tm),
+                Kind::Product(terms) => rest.extend(terms),
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn deep_terms() {
+        let ctx = crate::types::Context::default();
+        // Deep enough to overflow the stack of a test thread if either the
+        // search for the redex or the check for a value recursed
+        let depth = 1_000;
+        let v = run(&ctx, app_chain(depth), Limits::default(), |_| {}).value().unwrap();
+        assert_eq!(v.kind, nat!(depth).kind);
+
+        let list = run(&ctx, deep_list(depth), Limits::default(), |_| {}).value().unwrap();
+        let mut spine = &list;
+        for n in 1..=depth {
+            let fields = match &spine.kind {
+                Kind::Fold(_, cons) => match &cons.kind {
+                    Kind::Injection(label, pair, _) if label == "Cons" => match &pair.kind {
+                        Kind::Product(fields) => fields,
+                        _ => panic!("not a pair"),
+                    },
+                    _ => panic!("not a cons"),
+                },
+                _ => panic!("not folded"),
+            };
+            assert_eq!(fields[0].kind, nat!(n).kind);
+            spine = &fields[1];
+        }
+        dismantle(list);
+    }
+
+    /// Stepping terms 100k deep takes time linear in their depth, so only a
+    /// few steps are taken
+    ///
+    /// `cargo test --release -- --ignored deep_terms_full`
+    #[test]
+    #[ignore]
+    fn deep_terms_full() {
+        let ctx = crate::types::Context::default();
+        let limits = Limits {
+            fuel: 100,
+            cycle_check: None,
+        };
+        for tm in [app_chain(100_000), deep_list(100_000)] {
+            match run(&ctx, tm, limits, |_| {}) {
+                EvalOutcome::Stuck(EvalError::OutOfFuel { steps, state }) => {
+                    assert_eq!(steps, 100);
+                    dismantle(state);
+                }
+                _ => panic!("expected to run out of fuel"),
+            }
+        }
+    }
+}
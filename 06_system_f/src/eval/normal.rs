@@ -0,0 +1,248 @@
+//! Normalization under binders
+//!
+//! [`Eval`] reduces closed terms to values, and never looks inside an
+//! abstraction. Comparing polymorphic terms needs strong reduction instead,
+//! which also contracts the redexes under `\x: T.` and `\X`, where the
+//! variables bound outside the redex are free.
+use super::*;
+use std::mem;
+
+/// Reduce a term to its normal form, giving up after `fuel` steps.
+///
+/// Reduction is normal order: each step contracts the leftmost, outermost
+/// redex, whether or not its arguments have been reduced, so the normal form
+/// is found whenever there is one. Besides the redexes of [`Eval`], this
+/// reduces inside abstractions, type abstractions, case arms and guards,
+/// shifting the variables of each substituted term by the binders it is
+/// placed under. A case only selects an arm once its scrutinee is a value,
+/// as an arm cannot be ruled out by a scrutinee with free variables, and a
+/// `raise` is left where it is.
+///
+/// Since `fix` unrolls under its abstraction, recursive functions have no
+/// normal form, and take all of the fuel
+pub fn normalize(ctx: &Context, term: Term, fuel: u64) -> Result<Term, EvalError> {
+    let ev = Eval::with_context(ctx);
+    let mut term = term;
+    let mut steps = 0;
+    loop {
+        if steps == fuel {
+            let state = term.clone();
+            return match reduce(&ev, &mut term) {
+                true => Err(EvalError::OutOfFuel { steps, state }),
+                false => Ok(state),
+            };
+        }
+        if !reduce(&ev, &mut term) {
+            return Ok(term);
+        }
+        steps += 1;
+    }
+}
+
+impl Term {
+    /// Are two terms equal once normalized, up to spans and the names of
+    /// pattern variables? Each term is given `fuel` steps, and the first to
+    /// run out of them is the error
+    pub fn beta_eq(a: &Term, b: &Term, fuel: u64) -> Result<bool, EvalError> {
+        let ctx = Context::default();
+        let mut a = normalize(&ctx, a.clone(), fuel)?;
+        let mut b = normalize(&ctx, b.clone(), fuel)?;
+        Canonical.visit(&mut a);
+        Canonical.visit(&mut b);
+        Ok(a == b)
+    }
+}
+
+/// Contract the leftmost, outermost redex in a term, returning whether
+/// there was one. This recurses natively, unlike [`Eval::small_step`]
+fn reduce<H: EvalHooks>(ev: &Eval<H>, term: &mut Term) -> bool {
+    let span = term.span;
+    let tm = mem::replace(term, Term::new(Kind::Product(Vec::new()), span));
+    match contract_root(ev, tm) {
+        Ok(next) => {
+            *term = next;
+            return true;
+        }
+        Err(tm) => *term = tm,
+    }
+    match &mut term.kind {
+        Kind::Lit(_) | Kind::Var(_) | Kind::Primitive(_) => false,
+        // A shared value is only copied if there is a redex inside it
+        Kind::Share(shared) => {
+            let mut inner = shared.as_ref().clone();
+            match reduce(ev, &mut inner) {
+                true => {
+                    *term = inner;
+                    true
+                }
+                false => false,
+            }
+        }
+        Kind::Abs(_, tm)
+        | Kind::TyAbs(tm)
+        | Kind::TyApp(tm, _)
+        | Kind::Fix(tm)
+        | Kind::Injection(_, tm, _)
+        | Kind::Projection(tm, _)
+        | Kind::Fold(_, tm)
+        | Kind::Unfold(_, tm)
+        | Kind::Pack(_, tm, _)
+        | Kind::Raise(_, tm) => reduce(ev, tm),
+        Kind::App(t1, t2) | Kind::Let(_, t1, t2) | Kind::Unpack(t1, t2) => reduce(ev, t1) || reduce(ev, t2),
+        Kind::Product(terms) => terms.iter_mut().any(|tm| reduce(ev, tm)),
+        Kind::Case(expr, arms) => {
+            reduce(ev, expr)
+                || arms.iter_mut().any(|arm| {
+                    let guard = match &mut arm.guard {
+                        Some(guard) => reduce(ev, guard),
+                        None => false,
+                    };
+                    guard || reduce(ev, &mut arm.term)
+                })
+        }
+    }
+}
+
+/// Contract a term if it is a redex, or give it back
+fn contract_root<H: EvalHooks>(ev: &Eval<H>, term: Term) -> Result<Term, Term> {
+    let span = term.span;
+    let focus = match term.kind {
+        Kind::App(f, arg) => ev.apply_value(*f, *arg, span),
+        Kind::Let(pat, bind, body) if pat.matches(&bind) => ev.up(Frame::Let(pat, body, span), *bind),
+        Kind::Case(expr, arms) if expr.is_value() => ev.select(*expr, arms, span),
+        Kind::Fix(tm) if matches!(tm.unshared().kind, Kind::Abs(..)) => ev.up(Frame::Fix(span), *tm),
+        Kind::TyApp(tm, ty) if matches!(tm.unshared().kind, Kind::TyAbs(_)) => ev.up(Frame::TyApp(ty, span), *tm),
+        Kind::Projection(tm, idx) if matches!(tm.unshared().kind, Kind::Product(_)) => {
+            ev.up(Frame::Projection(idx, span), *tm)
+        }
+        Kind::Unfold(ty, tm) if matches!(tm.unshared().kind, Kind::Fold(..)) => ev.up(Frame::Unfold(ty, span), *tm),
+        Kind::Unpack(package, body) if matches!(package.unshared().kind, Kind::Pack(..)) => {
+            ev.up(Frame::Unpack(body, span), *package)
+        }
+        kind => return Err(Term::new(kind, span)),
+    };
+    match focus {
+        Focus::Contracted(tm) => Ok(tm),
+        Focus::Value(tm) | Focus::Stuck(tm) | Focus::Raised(tm) => Err(tm),
+        Focus::Within(frame, tm) => Err(frame.plug(tm)),
+    }
+}
+
+/// Forget the spans of a term and the names of its pattern variables, which
+/// are only there for printing
+struct Canonical;
+
+impl MutTermVisitor for Canonical {
+    fn visit_let(&mut self, sp: &mut Span, pat: &mut Pattern, t1: &mut Term, t2: &mut Term) {
+        anonymize(pat);
+        self.visit(t1);
+        self.visit(t2);
+    }
+
+    fn visit_case(&mut self, sp: &mut Span, term: &mut Term, arms: &mut Vec<Arm>) {
+        self.visit(term);
+        for arm in arms {
+            arm.span = Span::dummy();
+            arm.binders.clear();
+            anonymize(&mut arm.pat);
+            if let Some(guard) = &mut arm.guard {
+                self.visit(guard);
+            }
+            self.visit(&mut arm.term);
+        }
+    }
+
+    fn visit(&mut self, term: &mut Term) {
+        term.span = Span::dummy();
+        self.walk(term);
+    }
+}
+
+fn anonymize(pat: &mut Pattern) {
+    match pat {
+        Pattern::Any | Pattern::Literal(_) => {}
+        Pattern::Variable(name) => name.clear(),
+        Pattern::Product(pats) | Pattern::Or(pats) => pats.iter_mut().for_each(anonymize),
+        Pattern::Constructor(_, pat) | Pattern::Succ(pat) => anonymize(pat),
+        Pattern::As(pat, name) => {
+            anonymize(pat);
+            name.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::syntax::parser::Parser;
+
+    fn parse(input: &str) -> Term {
+        let mut p = Parser::new(input);
+        let tm = p.parse().unwrap();
+        assert_eq!(p.diagnostic().error_count(), 0);
+        tm
+    }
+
+    #[test]
+    fn under_binders() {
+        let ctx = Context::default();
+        let cases = [
+            ("\\y: Nat. (\\x: Nat. x) y", "\\y: Nat. y"),
+            ("\\z: Nat. (\\x: Nat. \\w: Nat. x) z", "\\z: Nat. \\w: Nat. z"),
+            (
+                "\\f: Nat -> Nat. \\y: Nat. (\\g: Nat -> Nat. g (g y)) f",
+                "\\f: Nat -> Nat. \\y: Nat. f (f y)",
+            ),
+            ("\\n: Nat. (\\m: Nat. succ m) (add 1 2)", "\\n: Nat. 4"),
+            ("\\n: Nat. let m = succ n in (m, m)", "\\n: Nat. (succ n, succ n)"),
+            (
+                "\\n: Nat. case n of | 0 => (\\x: Nat. x) 1 | _ => 2",
+                "\\n: Nat. case n of | 0 => 1 | _ => 2",
+            ),
+            ("\\X \\Y (\\Z \\z: Z. z) [X]", "\\X \\Y \\z: X. z"),
+            // The argument mentions X, and is substituted under \Y
+            ("\\X (\\g: X -> X. \\Y g) (\\x: X. x)", "\\X \\Y \\x: X. x"),
+        ];
+        for (input, expected) in &cases {
+            let normal = normalize(&ctx, parse(input), 100).unwrap();
+            assert!(
+                Term::beta_eq(&normal, &parse(expected), 0).unwrap(),
+                "{} normalized to {}",
+                input,
+                normal
+            );
+        }
+    }
+
+    #[test]
+    fn beta_equality() {
+        let id = "(\\X \\x: X. x) [Nat]";
+        assert!(Term::beta_eq(&parse(&format!("{} 5", id)), &parse("5"), 10).unwrap());
+        assert!(!Term::beta_eq(&parse(&format!("{} 5", id)), &parse("6"), 10).unwrap());
+        assert!(Term::beta_eq(&parse("\\X \\x: X. (\\Y \\y: Y. y) [X] x"), &parse("\\X \\x: X. x"), 10).unwrap());
+        // Pattern variables may be named differently
+        assert!(Term::beta_eq(
+            &parse("\\p: (Nat, Nat). let (a, b) = p in a"),
+            &parse("\\q: (Nat, Nat). let (c, d) = q in c"),
+            0
+        )
+        .unwrap());
+    }
+
+    #[test]
+    fn out_of_fuel() {
+        let ctx = Context::default();
+        let tm = parse("fix (\\f: Nat -> Nat. \\n: Nat. f n)");
+        match normalize(&ctx, tm, 20) {
+            Err(EvalError::OutOfFuel { steps, .. }) => assert_eq!(steps, 20),
+            e => panic!("expected to run out of fuel, not {:?}", e),
+        }
+        // Exactly enough fuel to reach a normal form is enough
+        assert_eq!(
+            normalize(&ctx, parse("(\\x: Nat. x) ((\\x: Nat. x) 1)"), 2)
+                .unwrap()
+                .kind,
+            Kind::Lit(Literal::Nat(1))
+        );
+    }
+}
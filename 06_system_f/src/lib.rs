@@ -0,0 +1,406 @@
+#![allow(unused_variables, unused_macros)]
+#[macro_use]
+pub mod macros;
+pub mod diagnostics;
+pub mod erase;
+pub mod eval;
+pub mod loader;
+pub mod match_compile;
+pub mod patterns;
+pub mod prelude;
+pub mod syntax;
+pub mod terms;
+pub mod types;
+pub mod visit;
+
+use diagnostics::*;
+use prelude::Prelude;
+use syntax::parser;
+use syntax::Syntax;
+use terms::{
+    pretty::PrintOptions,
+    visit::{InjRewriter, SuccFolder},
+    Kind, Term,
+};
+use types::{Type, Variant};
+use util::span::{FileId, SourceMap};
+use visit::MutTermVisitor;
+
+pub fn test_variant() -> Type {
+    Type::Variant(vec![
+        Variant {
+            label: "A".into(),
+            ty: Type::Unit,
+        },
+        Variant {
+            label: "B".into(),
+            ty: Type::Nat,
+        },
+        Variant {
+            label: "C".into(),
+            ty: Type::Nat,
+        },
+    ])
+}
+
+pub fn code_format(src: &str, diag: Diagnostic) {
+    render(src, &annotations(diag));
+}
+
+/// Print a diagnostic whose spans may be in any of the files, quoting the
+/// lines of each file in turn, under the name of the file if it has one
+pub fn report(files: &SourceMap, diag: Diagnostic) {
+    let msgs = annotations(diag);
+    let mut ids = msgs.iter().map(|anno| anno.span.file).collect::<Vec<_>>();
+    ids.dedup();
+    for (idx, file) in ids.iter().enumerate() {
+        if ids[..idx].contains(file) {
+            continue;
+        }
+        if !files.name(*file).is_empty() {
+            println!("--> {}", files.name(*file));
+        }
+        let msgs = msgs
+            .iter()
+            .filter(|anno| anno.span.file == *file)
+            .cloned()
+            .collect::<Vec<_>>();
+        render(files.source(*file), &msgs);
+    }
+}
+
+/// Report a diagnostic in the format chosen on the command line
+fn emit(files: &SourceMap, diag: Diagnostic, opts: &Options) {
+    match opts.format {
+        Format::Text => report(files, diag),
+        Format::Json => println!("{}", diag.to_json(files)),
+    }
+}
+
+/// The annotations of a diagnostic, primary first
+fn annotations(diag: Diagnostic) -> Vec<Annotation> {
+    let mut msgs = diag.other;
+    let mut primary = diag.primary;
+    if let Level::Warn = diag.level {
+        primary.info = format!("warning: {}", primary.info);
+    }
+    msgs.insert(0, primary);
+    msgs
+}
+
+/// Quote the lines of `src` that the annotations cover, with each
+/// annotation marked under the line it starts on
+fn render(src: &str, msgs: &[Annotation]) {
+    let srcl = src.lines().collect::<Vec<&str>>();
+    let start = msgs.iter().map(|anno| anno.span.start.line).min().unwrap_or(0);
+    let end = msgs.iter().map(|anno| anno.span.end.line + 1).max().unwrap_or(0);
+    for line in start..end {
+        println!("| {} {}", line + 1, srcl.get(line as usize).unwrap_or(&""));
+        for anno in msgs {
+            if anno.span.start.line != line {
+                continue;
+            }
+            let empty = (0..anno.span.start.col + 3).map(|_| ' ').collect::<String>();
+            let tilde = (1..anno.span.end.col.saturating_sub(anno.span.start.col))
+                .map(|_| '~')
+                .collect::<String>();
+            println!("{}^{}^ --- {}", empty, tilde, anno.info);
+        }
+    }
+}
+
+/// How diagnostics are printed
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    /// Quoting the lines of source they point at
+    #[default]
+    Text,
+    /// One JSON object per line, see [`Diagnostic::to_json`]
+    Json,
+}
+
+/// Options set by command line flags
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// Print every step of evaluation
+    pub verbose: bool,
+    /// Print each program after type erasure
+    pub erase: bool,
+    /// Remove unreachable case arms before evaluation
+    pub optimize: bool,
+    /// Print each program after type checking
+    pub dump: bool,
+    pub print: PrintOptions,
+    /// Bounds on the number of evaluation steps
+    pub limits: eval::Limits,
+    /// Whether type applications substitute their argument
+    pub mode: eval::ErasureMode,
+    /// Print reduction statistics after evaluation
+    pub stats: bool,
+    /// File holding the definitions of the prelude
+    pub prelude_file: Option<String>,
+    /// Print only the result of each item, as `value : type`, the way the
+    /// REPL does
+    pub brief: bool,
+    /// Type check each file without evaluating it
+    pub check: bool,
+    pub format: Format,
+    /// The syntax of every file and REPL input. Otherwise, files ending in
+    /// `.ml` are read in the ML syntax, and the rest in the default one
+    pub syntax: Option<Syntax>,
+}
+
+impl Options {
+    pub fn from_flags(flags: &[String]) -> Options {
+        let mut opts = Options::default();
+        for flag in flags {
+            match flag.as_str() {
+                "--erase" => opts.erase = true,
+                "--optimize" => opts.optimize = true,
+                "--dump" => opts.dump = true,
+                "--no-annotations" => opts.print.show_annotations = false,
+                "--spans" => opts.print.show_spans = true,
+                "--no-if" => opts.print.sugar_if = false,
+                "--unicode" => opts.print.unicode = true,
+                "--stats" => opts.stats = true,
+                "--erase-types" => opts.mode = eval::ErasureMode::Erased,
+                "--cycles" => opts.limits.cycle_check = Some(64),
+                "--check" => opts.check = true,
+                "--format=text" => opts.format = Format::Text,
+                "--format=json" => opts.format = Format::Json,
+                "--syntax=lambda" => opts.syntax = Some(Syntax::Lambda),
+                "--syntax=ml" => opts.syntax = Some(Syntax::Ml),
+                flag if flag.starts_with("--prelude=") => opts.prelude_file = Some(flag["--prelude=".len()..].into()),
+                flag if flag.starts_with("--fuel=") => match flag["--fuel=".len()..].parse() {
+                    Ok(fuel) => opts.limits.fuel = fuel,
+                    Err(_) => eprintln!("invalid fuel {}", flag),
+                },
+                flag => match flag.strip_prefix("--width=").and_then(|w| w.parse().ok()) {
+                    Some(width) => opts.print.max_width = width,
+                    None => eprintln!("unknown flag {}", flag),
+                },
+            }
+        }
+        opts
+    }
+}
+
+/// Resolve the aliases in a term and type check it, readying it for
+/// evaluation
+fn check(ctx: &mut types::Context, mut term: Term, opts: &Options) -> Result<(Term, Type), Vec<Diagnostic>> {
+    ctx.de_alias(&mut term);
+    InjRewriter.visit(&mut term);
+    SuccFolder.visit(&mut term);
+    let ty = match ctx.type_check_all(&term) {
+        (Some(ty), _) => ty,
+        (None, errors) => return Err(errors),
+    };
+    ctx.annotate_injections(&mut term);
+    if opts.optimize {
+        ctx.prune_unreachable(&mut term);
+    }
+    if opts.dump {
+        println!("{}", term.pretty(&opts.print));
+    }
+    Ok((term, ty))
+}
+
+/// Evaluate a term of type `ty`, made ready by [`check`]
+fn run(ctx: &mut types::Context, term: Term, ty: &Type, opts: &Options) -> Result<Term, Vec<Diagnostic>> {
+    let span = term.span;
+    let observe = |t: &Term| {
+        if opts.verbose {
+            println!("---> {}", t.pretty(&opts.print));
+        }
+    };
+    let ev = eval::Eval::with_mode(ctx, opts.mode);
+    let outcome = if opts.stats {
+        let ev = ev.with_hooks(eval::hooks::CountingHooks::default());
+        let outcome = eval::run_with(&ev, term, opts.limits, observe);
+        println!("{}", ev.hooks());
+        outcome
+    } else {
+        eval::run_with(&ev, term, opts.limits, observe)
+    };
+    let fin = match outcome {
+        eval::EvalOutcome::Value(fin) => fin,
+        eval::EvalOutcome::Raised(raise) => {
+            let payload = match &raise.kind {
+                Kind::Raise(_, payload) => payload.pretty(&opts.print),
+                _ => raise.pretty(&opts.print),
+            };
+            return Err(vec![Diagnostic::error(
+                raise.span,
+                format!("uncaught error `{}`", payload),
+            )]);
+        }
+        eval::EvalOutcome::Stuck(e) => {
+            let span = match &e {
+                eval::EvalError::Stuck(redex) => redex.span,
+                _ => span,
+            };
+            return Err(vec![Diagnostic::error(span, e.to_string())]);
+        }
+    };
+    if opts.mode == eval::ErasureMode::Erased {
+        // The types left inside the value are stale
+        return Ok(fin);
+    }
+    let fty = ctx.type_check(&fin).map_err(|d| vec![d])?;
+    if &fty != ty {
+        panic!(
+            "Type of term after evaluation is different than before!\n1 {}\n2 {}",
+            ty, fty
+        );
+    }
+    Ok(fin)
+}
+
+/// Type check and evaluate an item, printing its type and value. `name` is
+/// the variable it is bound to, if any. With `--check`, the checked term is
+/// returned instead of its value, and only the type is printed, unless
+/// diagnostics are printed as JSON
+fn eval(ctx: &mut types::Context, term: Term, name: Option<&str>, opts: &Options) -> Result<Term, Vec<Diagnostic>> {
+    let (term, ty) = check(ctx, term, opts)?;
+    if !opts.brief && opts.format == Format::Text {
+        println!("  {}: {}", name.unwrap_or("-"), ty);
+    }
+    if opts.check {
+        return Ok(term);
+    }
+    if opts.erase {
+        println!("erased: {}", erase::erase(&term));
+    }
+    let fin = run(ctx, term, &ty, opts)?;
+    match (opts.brief, name) {
+        (true, Some(name)) => println!("{} = {} : {}", name, fin.pretty(&opts.print), ty),
+        (true, None) => println!("{} : {}", fin.pretty(&opts.print), ty),
+        (false, _) => println!("===> {}", fin.pretty(&opts.print)),
+    }
+    Ok(fin)
+}
+
+/// Codes of the errors found by the driver rather than the type checker,
+/// see [`types::TypeErrorKind::code`]
+const SYNTAX_ERROR: u16 = 1;
+const IMPORT_ORDER: u16 = 2;
+const FAILED_DECLARATION: u16 = 3;
+
+/// Evaluate every item of a file in turn, declaring type aliases into the
+/// context and let-bound values into the environment. An item that fails is
+/// reported, and the items after it are still evaluated, except those using
+/// a value whose declaration failed. The files imported at the start must
+/// have been loaded already. Values are printed in the syntax the file is
+/// written in. Returns whether every item succeeded
+pub fn parse_and_eval(
+    ctx: &mut types::Context,
+    env: &mut Prelude,
+    files: &SourceMap,
+    file: FileId,
+    opts: &Options,
+) -> bool {
+    let input = files.source(file);
+    let syntax = opts.syntax.unwrap_or_else(|| Syntax::of_file(files.name(file)));
+    let opts = &Options {
+        print: PrintOptions {
+            syntax,
+            ..opts.print.clone()
+        },
+        ..opts.clone()
+    };
+    let mut p = env.file_parser(input, file, syntax);
+    let mut ok = true;
+    let mut head = true;
+    loop {
+        let item = p.item();
+        let import = matches!(item, Ok(parser::Item::Import(..)));
+        head &= import;
+        let (name, mut term) = match item {
+            Ok(parser::Item::Term(term)) => (None, term),
+            Ok(parser::Item::Let(name, term, _)) => (Some(name), term),
+            // Its syntax errors are reported along with the others below
+            Ok(parser::Item::Invalid(name, _)) => {
+                if let Some(name) = name {
+                    env.define(name, None);
+                }
+                ok = false;
+                continue;
+            }
+            Ok(parser::Item::Type(name, ty, span)) => {
+                if let Err(diag) = ctx.declare_alias(name, ty, span) {
+                    emit(files, diag, opts);
+                    ok = false;
+                }
+                continue;
+            }
+            Ok(parser::Item::Import(..)) if head => continue,
+            Ok(parser::Item::Import(_, span)) => {
+                let msg = "imports must come before the other items of a file";
+                emit(files, Diagnostic::error(span, msg).code(IMPORT_ORDER), opts);
+                ok = false;
+                continue;
+            }
+            Err(parser::Error {
+                kind: parser::ErrorKind::Eof,
+                ..
+            }) => break,
+            Err(e) => unreachable!("items only fail at the end of the input, found {:?}", e),
+        };
+        let res = match env.failed(&term) {
+            Some(failed) => Err(vec![Diagnostic::error(
+                term.span,
+                format!("`{}` cannot be used, as its declaration failed", failed),
+            )
+            .code(FAILED_DECLARATION)]),
+            None => {
+                env.close(&mut term);
+                eval(ctx, term, name.as_deref(), opts)
+            }
+        };
+        for diag in ctx.take_warnings() {
+            emit(files, diag, opts);
+        }
+        let value = match res {
+            Ok(value) => Some(value),
+            Err(errors) => {
+                for diag in errors {
+                    emit(files, diag, opts);
+                }
+                ok = false;
+                None
+            }
+        };
+        if let Some(name) = name {
+            env.define(name, value);
+        }
+    }
+    let mut diag = p.diagnostic();
+    if diag.error_count() > 0 && opts.format == Format::Json {
+        for msg in diag.take() {
+            emit(files, Diagnostic::error(msg.span, msg.data).code(SYNTAX_ERROR), opts);
+        }
+        false
+    } else if diag.error_count() > 0 {
+        if !files.name(file).is_empty() {
+            println!("--> {}", files.name(file));
+        }
+        println!("Parsing {}", diag.emit());
+        false
+    } else {
+        ok
+    }
+}
+
+pub fn nat_list() -> Type {
+    Type::Rec(Box::new(Type::Variant(vec![
+        variant!("Nil", Type::Unit),
+        variant!("Cons", Type::Product(vec![Type::Nat, Type::Var(0)])),
+    ])))
+}
+
+pub fn nat_list2() -> Type {
+    Type::Variant(vec![
+        variant!("Nil", Type::Unit),
+        variant!("Cons", Type::Product(vec![Type::Nat, Type::Var(0)])),
+    ])
+}
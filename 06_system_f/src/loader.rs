@@ -0,0 +1,197 @@
+//! Loading source files along with the files they import
+//!
+//! A file may begin with `import "path";` directives, naming other files
+//! relative to the directory it is in. Those are loaded first, and each
+//! file only once, so that the type aliases and let declarations in them
+//! are in scope in the file importing them.
+use crate::syntax::parser::Parser;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use util::span::{FileId, SourceMap};
+
+#[derive(Debug)]
+pub enum LoadError {
+    /// A file could not be read
+    Io(PathBuf, io::Error),
+    /// A file imports itself, through each of these files in turn. The
+    /// first and last are the same file
+    Cycle(Vec<PathBuf>),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(path, e) => write!(f, "cannot read {}: {}", path.display(), e),
+            LoadError::Cycle(chain) => {
+                let chain = chain.iter().map(|p| p.display().to_string()).collect::<Vec<_>>();
+                write!(f, "import cycle: {}", chain.join(" imports "))
+            }
+        }
+    }
+}
+
+/// The paths imported at the start of a source file
+pub fn imports(source: &str) -> Vec<String> {
+    Parser::new(source)
+        .imports()
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect()
+}
+
+/// Loads files, and keeps the source of each for reporting diagnostics
+#[derive(Debug, Default)]
+pub struct Loader {
+    files: SourceMap,
+    /// Each file loaded, by its canonical path, and whether it succeeded
+    loaded: Vec<(PathBuf, bool)>,
+    /// Files whose imports are being loaded, each imported by the one before
+    loading: Vec<PathBuf>,
+}
+
+impl Loader {
+    /// The source of every file loaded so far
+    pub fn files(&self) -> &SourceMap {
+        &self.files
+    }
+
+    /// Load the file at `path`, after the files it imports. Each file not
+    /// loaded before is passed to `eval` once all of its imports succeeded,
+    /// which returns whether the file did too. Returns whether the file and
+    /// all of its imports succeeded
+    pub fn load<F>(&mut self, path: &Path, eval: &mut F) -> Result<bool, LoadError>
+    where
+        F: FnMut(&SourceMap, FileId) -> bool,
+    {
+        let depth = self.loading.len();
+        let result = self.load_from(path, eval);
+        self.loading.truncate(depth);
+        result
+    }
+
+    fn load_from<F>(&mut self, path: &Path, eval: &mut F) -> Result<bool, LoadError>
+    where
+        F: FnMut(&SourceMap, FileId) -> bool,
+    {
+        let canonical = path.canonicalize().map_err(|e| LoadError::Io(path.into(), e))?;
+        if let Some(idx) = self.loading.iter().position(|p| p == &canonical) {
+            let mut chain = self.loading[idx..].to_vec();
+            chain.push(canonical);
+            return Err(LoadError::Cycle(chain));
+        }
+        if let Some((_, ok)) = self.loaded.iter().find(|(p, _)| p == &canonical) {
+            return Ok(*ok);
+        }
+
+        let source = fs::read_to_string(&canonical).map_err(|e| LoadError::Io(path.into(), e))?;
+        let dir = canonical.parent().map(Path::to_path_buf).unwrap_or_default();
+        self.loading.push(canonical.clone());
+        let mut ok = true;
+        for import in imports(&source) {
+            ok &= self.load_from(&dir.join(import), eval)?;
+        }
+        self.loading.pop();
+
+        let file = self.files.add(path.display().to_string(), source);
+        let ok = ok && eval(&self.files, file);
+        self.loaded.push((canonical, ok));
+        Ok(ok)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prelude::Prelude;
+    use crate::types::{Context, Type};
+
+    /// A fresh directory holding the given files
+    fn dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("system_f_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for (file, source) in files {
+            fs::write(dir.join(file), source).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn import() {
+        let dir = dir(
+            "import",
+            &[
+                ("base.sf", "type Pair = (Nat, Bool);\nlet one = 1;"),
+                ("lib.sf", "import \"base.sf\";\nlet two = succ one;"),
+                (
+                    "main.sf",
+                    "import \"base.sf\";\nimport \"lib.sf\";\n(\\p: Pair. add p.0 two) (one, true)",
+                ),
+            ],
+        );
+        let (mut ctx, mut env) = (Context::default(), Prelude::default());
+        let mut loader = Loader::default();
+        let mut order = Vec::new();
+        let mut eval = |files: &SourceMap, file: FileId| {
+            order.push(files.name(file).to_string());
+            crate::parse_and_eval(&mut ctx, &mut env, files, file, &crate::Options::default())
+        };
+        assert!(loader.load(&dir.join("main.sf"), &mut eval).unwrap());
+
+        // Each file is evaluated once, after the files it imports
+        let names = order
+            .iter()
+            .map(|name| Path::new(name).file_name().unwrap().to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["base.sf", "lib.sf", "main.sf"]);
+        assert_eq!(env.names(), ["one", "two"]);
+        assert_eq!(
+            ctx.normalize(&Type::Alias("Pair".into())),
+            Type::Product(vec![Type::Nat, Type::Bool])
+        );
+        assert_eq!(loader.files().len(), 3);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn cycle() {
+        let dir = dir(
+            "cycle",
+            &[
+                ("a.sf", "import \"b.sf\";\n1"),
+                ("b.sf", "import \"c.sf\";\n2"),
+                ("c.sf", "import \"a.sf\";\n3"),
+            ],
+        );
+        let mut loader = Loader::default();
+        let mut evaluated = 0;
+        let err = loader
+            .load(&dir.join("a.sf"), &mut |_: &SourceMap, _| {
+                evaluated += 1;
+                true
+            })
+            .unwrap_err();
+        let chain = match &err {
+            LoadError::Cycle(chain) => chain
+                .iter()
+                .map(|p| p.file_name().unwrap().to_owned())
+                .collect::<Vec<_>>(),
+            e => panic!("expected a cycle, not {}", e),
+        };
+        assert_eq!(chain, ["a.sf", "b.sf", "c.sf", "a.sf"]);
+        let msg = err.to_string();
+        assert!(msg.starts_with("import cycle: "), "{}", msg);
+        assert_eq!(msg.matches(" imports ").count(), 3, "{}", msg);
+        assert_eq!(evaluated, 0);
+
+        // A missing import is reported with the path it was imported as
+        fs::write(dir.join("c.sf"), "import \"missing.sf\";\n3").unwrap();
+        match loader.load(&dir.join("a.sf"), &mut |_: &SourceMap, _| true) {
+            Err(LoadError::Io(path, _)) => assert!(path.ends_with("missing.sf")),
+            e => panic!("expected a missing file, not {:?}", e),
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
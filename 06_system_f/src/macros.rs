@@ -71,6 +71,7 @@ macro_rules! prim {
     };
 }
 
+/// Injection term
 macro_rules! inj {
     ($label:expr, $t:expr, $ty:expr) => {
         crate::terms::Term::new(
@@ -86,6 +87,55 @@ macro_rules! tuple {
     util::span::Span::dummy()) }
 }
 
+/// Projection term
+macro_rules! proj {
+    ($t:expr, $idx:expr) => {
+        crate::terms::Term::new(
+            crate::terms::Kind::Projection(Box::new($t), $idx),
+            util::span::Span::dummy(),
+        )
+    };
+}
+
+/// Case term, with unguarded arms
+macro_rules! case {
+    ($t:expr, $($pat:expr => $arm:expr),+ $(,)?) => {
+        crate::terms::Term::new(
+            crate::terms::Kind::Case(
+                Box::new($t),
+                vec![$(crate::terms::Arm {
+                    span: util::span::Span::dummy(),
+                    pat: $pat,
+                    binders: Vec::new(),
+                    guard: None,
+                    term: Box::new($arm),
+                }),+],
+            ),
+            util::span::Span::dummy(),
+        )
+    };
+}
+
+/// Fold term
+macro_rules! fold {
+    ($ty:expr, $t:expr) => {
+        crate::terms::Term::new(
+            crate::terms::Kind::Fold(Box::new($ty), Box::new($t)),
+            util::span::Span::dummy(),
+        )
+    };
+}
+
+/// Unfold term
+macro_rules! unfold {
+    ($ty:expr, $t:expr) => {
+        crate::terms::Term::new(
+            crate::terms::Kind::Unfold(Box::new($ty), Box::new($t)),
+            util::span::Span::dummy(),
+        )
+    };
+}
+
 /// Type arrow
 macro_rules! arrow {
     ($ty1:expr, $ty2:expr) => {
@@ -128,3 +178,84 @@ macro_rules! variant {
         }
     };
 }
+
+#[cfg(test)]
+mod test {
+    use crate::patterns::Pattern;
+    use crate::terms::{Arm, Kind, Literal, Primitive, Term};
+    use crate::types::Type;
+    use util::span::Span;
+
+    fn term(kind: Kind) -> Term {
+        Term::new(kind, Span::dummy())
+    }
+
+    #[test]
+    fn terms() {
+        let one = term(Kind::Lit(Literal::Nat(1)));
+        let succ = term(Kind::Primitive(Primitive::Succ));
+        assert_eq!(
+            app!(prim!(Primitive::Succ), nat!(1)),
+            term(Kind::App(Box::new(succ), Box::new(one.clone())))
+        );
+        assert_eq!(
+            tyapp!(tyabs!(abs!(Type::Var(0), var!(0))), Type::Nat),
+            term(Kind::TyApp(
+                Box::new(term(Kind::TyAbs(Box::new(term(Kind::Abs(
+                    Box::new(Type::Var(0)),
+                    Box::new(term(Kind::Var(0)))
+                )))))),
+                Box::new(Type::Nat)
+            ))
+        );
+        assert_eq!(
+            proj!(tuple!(nat!(1), lit!(true)), 0),
+            term(Kind::Projection(
+                Box::new(term(Kind::Product(vec![one, term(Kind::Lit(Literal::Bool(true)))]))),
+                0
+            ))
+        );
+    }
+
+    #[test]
+    fn recursive_terms() {
+        let list = Type::Rec(Box::new(Type::Variant(vec![
+            variant!("Nil", Type::Unit),
+            variant!("Cons", Type::Product(vec![Type::Nat, Type::Var(0)])),
+        ])));
+        let nil = inj!("Nil", Term::unit(), list.clone());
+        assert_eq!(
+            nil,
+            term(Kind::Injection(
+                "Nil".into(),
+                Box::new(Term::unit()),
+                Box::new(list.clone())
+            ))
+        );
+        assert_eq!(
+            unfold!(list.clone(), fold!(list.clone(), nil.clone())),
+            term(Kind::Unfold(
+                Box::new(list.clone()),
+                Box::new(term(Kind::Fold(Box::new(list), Box::new(nil))))
+            ))
+        );
+    }
+
+    #[test]
+    fn case_arms() {
+        let arm = |pat, tm| Arm {
+            span: Span::dummy(),
+            pat,
+            binders: Vec::new(),
+            guard: None,
+            term: Box::new(tm),
+        };
+        assert_eq!(
+            case!(var!(0), num!(0) => lit!(true), Pattern::Any => lit!(false)),
+            term(Kind::Case(
+                Box::new(var!(0)),
+                vec![arm(num!(0), lit!(true)), arm(Pattern::Any, lit!(false))]
+            ))
+        );
+    }
+}
@@ -1,160 +1,400 @@
 #![allow(unused_variables, unused_macros)]
-#[macro_use]
-pub mod macros;
-pub mod diagnostics;
-pub mod eval;
-pub mod patterns;
-pub mod syntax;
-pub mod terms;
-pub mod types;
-pub mod visit;
-
-use diagnostics::*;
 use std::env;
-use std::io::{Read, Write};
-use syntax::parser::{self, Parser};
-use terms::{visit::InjRewriter, Term};
-use types::{Type, Variant};
-use visit::MutTermVisitor;
-
-fn test_variant() -> Type {
-    Type::Variant(vec![
-        Variant {
-            label: "A".into(),
-            ty: Type::Unit,
-        },
-        Variant {
-            label: "B".into(),
-            ty: Type::Nat,
-        },
-        Variant {
-            label: "C".into(),
-            ty: Type::Nat,
-        },
-    ])
-}
+use std::io::Write;
+use std::path::Path;
+use system_f::eval::{
+    self,
+    debug::{Debugger, Event},
+};
+use system_f::loader::{self, Loader};
+use system_f::prelude::{Prelude, PreludeError};
+use system_f::syntax::lexer::Lexer;
+use system_f::syntax::parser::{self, Parser};
+use system_f::syntax::{Syntax, TokenKind, TriviaKind};
+use system_f::terms::{pretty::PrintOptions, visit::InjRewriter, Term};
+use system_f::types::{self, Type};
+use system_f::visit::MutTermVisitor;
+use system_f::{code_format, nat_list, nat_list2, parse_and_eval, test_variant, Format, Options};
+use util::span::{FileId, SourceMap};
 
-pub fn code_format(src: &str, diag: Diagnostic) {
-    // let lines = diag.ot
-    //     .iter()
-    //     .map(|(_, sp)| sp.start.line)
-    //     .collect::<std::collections::HashSet<_>>();
-    let srcl = src.lines().collect::<Vec<&str>>();
-
-    let mut msgs = diag.other.clone();
-    msgs.insert(0, diag.primary.clone());
-
-    for line in diag.lines() {
-        println!("| {} {}", line + 1, &srcl[line as usize]);
-        for anno in &msgs {
-            if anno.span.start.line != line {
-                continue;
-            }
-            let empty = (0..anno.span.start.col + 3).map(|_| ' ').collect::<String>();
-            let tilde = (1..anno.span.end.col.saturating_sub(anno.span.start.col))
-                .map(|_| '~')
-                .collect::<String>();
-            println!("{}^{}^ --- {}", empty, tilde, anno.info);
+/// Answer `:bindings` in the REPL, printing the variables bound by every
+/// case arm in the input along with their types
+fn print_bindings(ctx: &mut types::Context, input: &str, syntax: Syntax) {
+    let mut p = Parser::with_syntax(input, FileId::default(), syntax);
+    let term = p.parse();
+    let diag = p.diagnostic();
+    if diag.error_count() > 0 {
+        println!("Parsing {}", diag.emit());
+        return;
+    }
+    let mut term = match term {
+        Ok(term) => term,
+        Err(e) => {
+            code_format(input, e.into());
+            return;
         }
+    };
+    ctx.de_alias(&mut term);
+    InjRewriter.visit(&mut term);
+    let (_, errors) = ctx.type_check_all(&term);
+    for diag in errors {
+        code_format(input, diag);
+    }
+    for (idx, (span, binds)) in ctx.arm_bindings().iter().enumerate() {
+        let binds = binds
+            .iter()
+            .map(|(name, ty, _)| format!("{}: {}", name, ty))
+            .collect::<Vec<_>>()
+            .join(", ");
+        println!("arm {} (line {}): {}", idx + 1, span.start.line + 1, binds);
     }
 }
 
-fn eval(ctx: &mut types::Context, mut term: Term, verbose: bool) -> Result<Term, Diagnostic> {
+/// Answer `:type-at <offset>` in the REPL, printing the type of the
+/// innermost subterm of the input containing the offset
+fn print_type_at(ctx: &mut types::Context, input: &str, syntax: Syntax) {
+    let input = input.trim_start();
+    let (offset, input) = input.split_at(input.find(char::is_whitespace).unwrap_or(input.len()));
+    let offset = match offset.parse::<usize>() {
+        Ok(offset) => offset,
+        Err(_) => {
+            eprintln!("usage: :type-at <offset> <term>");
+            return;
+        }
+    };
+    let input = input.trim_start();
+    let mut p = Parser::with_syntax(input, FileId::default(), syntax);
+    let term = p.parse();
+    let diag = p.diagnostic();
+    if diag.error_count() > 0 {
+        println!("Parsing {}", diag.emit());
+        return;
+    }
+    let mut term = match term {
+        Ok(term) => term,
+        Err(e) => {
+            code_format(input, e.into());
+            return;
+        }
+    };
     ctx.de_alias(&mut term);
     InjRewriter.visit(&mut term);
-    let ty = ctx.type_check(&term)?;
-    println!("  -: {:?}", ty);
-
-    let ev = eval::Eval::with_context(ctx);
-    let mut t = term;
-    let fin = loop {
-        if let Some(res) = ev.small_step(t.clone()) {
-            t = res;
-        } else {
-            break t;
-        }
-        if verbose {
-            println!("---> {}", t);
+    let sub = match term.find_at(offset) {
+        Some(sub) => sub,
+        None => {
+            eprintln!("no term at offset {}", offset);
+            return;
         }
     };
-    println!("===> {}", fin);
-    let fty = ctx.type_check(&fin)?;
-    if fty != ty {
-        panic!(
-            "Type of term after evaluation is different than before!\n1 {:?}\n2 {:?}",
-            ty, fty
-        );
-    }
-    Ok(fin)
+    match ctx.type_at(&term, offset) {
+        Some(Ok(ty)) => println!("{:?} at {}: {}", sub.kind.tag(), sub.span.start, ty),
+        Some(Err(diag)) => code_format(input, diag),
+        None => {}
+    }
 }
 
-fn parse_and_eval(ctx: &mut types::Context, input: &str, verbose: bool) -> bool {
-    let mut p = Parser::new(input);
-    loop {
-        let term = match p.parse() {
-            Ok(term) => term,
-            Err(parser::Error {
-                kind: parser::ErrorKind::Eof,
-                ..
-            }) => break,
-            Err(e) => {
-                dbg!(e);
-                break;
-            }
-        };
-        if let Err(diag) = eval(ctx, term, verbose) {
+/// Parse a term entered in the REPL, in which the variables bound by the
+/// session are in scope, reporting any syntax errors
+fn parse_term(env: &Prelude, input: &str, syntax: Syntax) -> Option<Term> {
+    let mut p = env.file_parser(input, FileId::default(), syntax);
+    let term = p.parse();
+    let diag = p.diagnostic();
+    if diag.error_count() > 0 {
+        println!("Parsing {}", diag.emit());
+        return None;
+    }
+    match term {
+        Ok(term) => Some(term),
+        Err(e) => {
+            code_format(input, e.into());
+            None
+        }
+    }
+}
+
+/// Parse and type check a term entered in the REPL, substituting the values
+/// of the variables bound by the session, and reporting any errors
+fn checked(ctx: &mut types::Context, env: &Prelude, input: &str, syntax: Syntax) -> Option<(Term, Type)> {
+    let mut term = parse_term(env, input, syntax)?;
+    if let Some(failed) = env.failed(&term) {
+        eprintln!("`{}` cannot be used, as its declaration failed", failed);
+        return None;
+    }
+    env.close(&mut term);
+    ctx.de_alias(&mut term);
+    InjRewriter.visit(&mut term);
+    let ty = match ctx.type_check(&term) {
+        Ok(ty) => ty,
+        Err(diag) => {
             code_format(input, diag);
-            return false;
+            return None;
         }
+    };
+    ctx.annotate_injections(&mut term);
+    Some((term, ty))
+}
+
+/// Answer `:type` in the REPL, printing the type of the input without
+/// evaluating it
+fn print_type(ctx: &mut types::Context, env: &Prelude, input: &str, syntax: Syntax) {
+    if let Some((_, ty)) = checked(ctx, env, input, syntax) {
+        println!("{}", ty);
+    }
+}
+
+/// Answer `:ast` in the REPL, printing the tree the input parses to
+fn print_ast(env: &Prelude, input: &str, syntax: Syntax) {
+    if let Some(term) = parse_term(env, input, syntax) {
+        println!("{:#?}", term);
     }
+}
+
+/// Answer `:alias T = type` in the REPL, declaring the alias for the rest of
+/// the session
+fn declare_alias(ctx: &mut types::Context, input: &str) {
+    let mut p = Parser::new(input);
+    let item = p.alias();
     let diag = p.diagnostic();
     if diag.error_count() > 0 {
         println!("Parsing {}", diag.emit());
-        false
-    } else {
-        true
+        return;
+    }
+    match item {
+        Ok(parser::Item::Type(name, ty, span)) => {
+            if let Err(diag) = ctx.declare_alias(name, ty, span) {
+                code_format(input, diag);
+            }
+        }
+        Ok(_) => {}
+        Err(e) => code_format(input, e.into()),
     }
 }
 
-fn nat_list() -> Type {
-    Type::Rec(Box::new(Type::Variant(vec![
-        variant!("Nil", Type::Unit),
-        variant!("Cons", Type::Product(vec![Type::Nat, Type::Var(0)])),
-    ])))
+/// Answer `:steps` in the REPL, printing every step taken in evaluating
+/// the input
+fn print_steps(ctx: &mut types::Context, env: &Prelude, input: &str, opts: &Options) {
+    let term = match checked(ctx, env, input, opts.print.syntax) {
+        Some((term, _)) => term,
+        None => return,
+    };
+    match eval::trace(ctx, term) {
+        Ok(steps) => {
+            for (idx, tm) in steps.iter().enumerate() {
+                println!("{:>4}: {}", idx, tm.pretty(&opts.print));
+            }
+        }
+        Err(e) => eprintln!("{}", e),
+    }
 }
 
-fn nat_list2() -> Type {
-    Type::Variant(vec![
-        variant!("Nil", Type::Unit),
-        variant!("Cons", Type::Product(vec![Type::Nat, Type::Var(0)])),
-    ])
+/// Answer `:debug` in the REPL, stepping through the evaluation of the
+/// input. Commands are then read from stdin one line at a time: `step` (or
+/// an empty line), `continue`, `break <Label>` to pause when a case
+/// dispatches on the constructor, and `quit`
+fn debug(ctx: &mut types::Context, env: &Prelude, input: &str, opts: &Options) {
+    let term = match checked(ctx, env, input, opts.print.syntax) {
+        Some((term, _)) => term,
+        None => return,
+    };
+    let mut dbg = Debugger::new(ctx, term);
+    println!("{:>4}: {}", 0, dbg.current().pretty(&opts.print));
+    loop {
+        print!("debug: ");
+        std::io::stdout().flush().unwrap();
+        let mut line = String::new();
+        if std::io::stdin().read_line(&mut line).unwrap_or(0) == 0 {
+            break;
+        }
+        let mut words = line.split_whitespace();
+        let before = dbg.current().clone();
+        let event = match words.next() {
+            None | Some("s") | Some("step") => dbg.step(),
+            Some("c") | Some("continue") => dbg.run_to_value(),
+            Some("b") | Some("break") => {
+                match words.next() {
+                    Some(label) => dbg.breakpoint_on_label(label),
+                    None => eprintln!("usage: break <Label>"),
+                }
+                continue;
+            }
+            Some("q") | Some("quit") => break,
+            Some(cmd) => {
+                eprintln!("unknown command {}", cmd);
+                continue;
+            }
+        };
+        match event {
+            // Only a single step stops without a breakpoint, and its redex
+            // is found in the term it was contracted in
+            Event::Stepped(redex) => {
+                let shown = before.pretty_highlight(&opts.print, redex).replace('\n', "\n      ");
+                println!("{:>4}: {}", dbg.steps(), shown);
+            }
+            Event::Breakpoint { label, redex } => println!(
+                "{:>4}: case on {} at {}\n      {}",
+                dbg.steps(),
+                label,
+                redex.start,
+                dbg.current().pretty(&opts.print)
+            ),
+            Event::Done => println!("done: {}", dbg.current().pretty(&opts.print)),
+            Event::Failed(e) => eprintln!("{}", e),
+        }
+    }
 }
 
 fn main() {
     let mut ctx = types::Context::default();
 
-    ctx.alias("Var".into(), test_variant());
-    ctx.alias("NatList".into(), nat_list());
-    ctx.alias("NB".into(), nat_list2());
-
-    let args = env::args();
-    if args.len() > 1 {
-        for f in args.skip(1) {
-            println!("reading {}", f);
-            let file = std::fs::read_to_string(&f).unwrap();
-            if !parse_and_eval(&mut ctx, &file, false) {
-                panic!("test failed! {}", f);
+    ctx.alias("Var".into(), test_variant()).unwrap();
+    ctx.alias("NatList".into(), nat_list()).unwrap();
+    ctx.alias("NB".into(), nat_list2()).unwrap();
+
+    let (flags, files): (Vec<String>, Vec<String>) = env::args().skip(1).partition(|arg| arg.starts_with("--"));
+    let opts = Options::from_flags(&flags);
+    let mut env = Prelude::default();
+    if let Some(path) = &opts.prelude_file {
+        let source = std::fs::read_to_string(path).unwrap();
+        let cache = format!("{}.cache", path);
+        match Prelude::load(&mut ctx, &source, Path::new(&cache)) {
+            Ok((prelude, cached)) => {
+                let from = if cached { "cache" } else { "source" };
+                if opts.format == Format::Text {
+                    println!("prelude: {} definitions from {}", prelude.names().len(), from);
+                }
+                env = prelude;
             }
+            Err(PreludeError::Type(diag)) => {
+                code_format(&source, diag);
+                return;
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        }
+    }
+    if !files.is_empty() {
+        let mut loader = Loader::default();
+        let mut ok = true;
+        for f in files {
+            let mut eval = |files: &SourceMap, file| {
+                if opts.format == Format::Text {
+                    println!("reading {}", files.name(file));
+                }
+                parse_and_eval(&mut ctx, &mut env, files, file, &opts)
+            };
+            match loader.load(Path::new(&f), &mut eval) {
+                Ok(true) => {}
+                // Every file is checked, so that all of the errors are seen
+                Ok(false) if opts.check => ok = false,
+                Ok(false) => panic!("test failed! {}", f),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        if !ok {
+            std::process::exit(1);
         }
         return;
     }
 
+    repl(&mut ctx, &mut env, &opts);
+}
+
+/// Does the input have more opening brackets than closing ones, or an
+/// unterminated block comment, so that it goes on to the next line?
+fn unfinished(input: &str) -> bool {
+    let mut lexer = Lexer::new(input.chars());
+    let mut depth = 0i32;
+    loop {
+        let token = lexer.lex();
+        match token.kind {
+            TokenKind::LParen | TokenKind::LSquare | TokenKind::LBrace => depth += 1,
+            TokenKind::RParen | TokenKind::RSquare | TokenKind::RBrace => depth -= 1,
+            TokenKind::Eof => {
+                let comment = token
+                    .leading_trivia
+                    .iter()
+                    .any(|t| t.kind == TriviaKind::UnterminatedComment);
+                return depth > 0 || comment;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Read one input from stdin, which may continue over several lines, or
+/// `None` once stdin is closed
+fn read_input() -> Option<String> {
+    let mut input = String::new();
+    print!("repl: ");
     loop {
-        let mut buffer = String::new();
-        print!("repl: ");
         std::io::stdout().flush().unwrap();
-        std::io::stdin().read_to_string(&mut buffer).unwrap();
+        let mut line = String::new();
+        if std::io::stdin().read_line(&mut line).unwrap_or(0) == 0 {
+            return Some(input).filter(|input| !input.is_empty());
+        }
+        input.push_str(&line);
+        if !unfinished(&input) {
+            return Some(input);
+        }
+        print!("  ... ");
+    }
+}
 
-        parse_and_eval(&mut ctx, &buffer, true);
+/// Evaluate each input entered on stdin, printing its value and type. Type
+/// aliases and let bindings last for the rest of the session, and errors
+/// are shown against the input they were found in. An input may begin with
+/// imports, which are loaded first. Commands start with a
+/// colon: `:type`, `:ast`, `:steps`, `:alias`, `:bindings`, `:type-at`,
+/// `:debug` and `:quit`. Inputs are read in the syntax chosen with `--syntax`
+fn repl(ctx: &mut types::Context, env: &mut Prelude, opts: &Options) {
+    let syntax = opts.syntax.unwrap_or_default();
+    let opts = Options {
+        brief: true,
+        print: PrintOptions {
+            syntax,
+            ..opts.print.clone()
+        },
+        ..opts.clone()
+    };
+    let mut loader = Loader::default();
+    while let Some(input) = read_input() {
+        let input = input.trim();
+        let (command, rest) = match input.strip_prefix(':') {
+            Some(cmd) => {
+                let (command, rest) = cmd.split_at(cmd.find(char::is_whitespace).unwrap_or(cmd.len()));
+                (command, rest.trim_start())
+            }
+            None => ("", input),
+        };
+        match command {
+            "" if input.is_empty() => {}
+            "" => {
+                // Imported files are found from the working directory
+                let mut eval = |files: &SourceMap, file| parse_and_eval(ctx, env, files, file, &opts);
+                for path in loader::imports(input) {
+                    if let Err(e) = loader.load(Path::new(&path), &mut eval) {
+                        eprintln!("{}", e);
+                    }
+                }
+                let mut files = SourceMap::default();
+                let file = files.add("", input);
+                parse_and_eval(ctx, env, &files, file, &opts);
+            }
+            "type" => print_type(ctx, env, rest, syntax),
+            "ast" => print_ast(env, rest, syntax),
+            "steps" => print_steps(ctx, env, rest, &opts),
+            "alias" => declare_alias(ctx, rest),
+            "bindings" => print_bindings(ctx, rest, syntax),
+            "type-at" => print_type_at(ctx, rest, syntax),
+            "debug" => debug(ctx, env, rest, &opts),
+            "q" | "quit" => break,
+            cmd => eprintln!("unknown command :{}", cmd),
+        }
     }
+    println!();
 }
@@ -0,0 +1,421 @@
+//! Compilation of case expressions to decision trees
+//!
+//! Trying the arms of a case expression in order tests the same constructor
+//! over and over again: matching the last of 30 constructors compares the
+//! scrutinee against every one of them, and nested patterns repeat the tests
+//! on outer constructors for every arm. A decision tree instead switches on
+//! the constructor at one position of the scrutinee at a time, so that each
+//! part of the value is inspected at most once on the way to the first arm
+//! that matches.
+//!
+//! The tree is built by specializing the pattern matrix for each constructor
+//! in the first column, exactly as when checking usefulness (see
+//! [`crate::types::patterns`]), except that each row remembers the arm it
+//! came from, and each column the position of the value it is matched
+//! against.
+use crate::erase::Value;
+use crate::patterns::Pattern;
+use crate::terms::Literal;
+use crate::types::patterns::{expand, peano, predecessor, specialize, used, Ctor};
+
+/// Position of a sub-value within the scrutinee: the index of the field of a
+/// product to descend into at each step. The payload of an injection and the
+/// predecessor of a natural number are both field 0
+pub type Occurrence = Vec<usize>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Decision {
+    /// No arm matches
+    Fail,
+    /// Select an arm. If the arm has a guard and it evaluates to false,
+    /// matching continues with `otherwise`
+    Leaf {
+        arm: usize,
+        otherwise: Option<Box<Decision>>,
+    },
+    /// Switch on the head constructor of the value at `occurrence`, falling
+    /// back to `default` if none of the cases apply
+    Switch {
+        occurrence: Occurrence,
+        cases: Vec<(Ctor, Decision)>,
+        default: Box<Decision>,
+    },
+}
+
+/// A row of the pattern matrix, and the arm it belongs to
+#[derive(Clone)]
+struct Row<'p> {
+    pats: Vec<&'p Pattern>,
+    arm: usize,
+}
+
+/// Compile the patterns of a case expression into a decision tree. Each arm
+/// is given as its pattern, and whether it has a guard
+pub fn compile(arms: &[(&Pattern, bool)]) -> Decision {
+    let rows = arms
+        .iter()
+        .enumerate()
+        .map(|(arm, (pat, _))| Row { pats: vec![*pat], arm })
+        .collect();
+    let guarded = arms.iter().map(|(_, guarded)| *guarded).collect::<Vec<_>>();
+    build(rows, &[Vec::new()], &guarded)
+}
+
+fn build(rows: Vec<Row>, occs: &[Occurrence], guarded: &[bool]) -> Decision {
+    let first = match rows.first() {
+        Some(row) => row,
+        None => return Decision::Fail,
+    };
+    if occs.is_empty() {
+        // Every column has been matched, so the first row wins. If its guard
+        // fails, the other alternatives of the same or-pattern are skipped too
+        let otherwise = match guarded[first.arm] {
+            true => {
+                let rest = rows.iter().filter(|row| row.arm != first.arm).cloned().collect();
+                Some(Box::new(build(rest, occs, guarded)))
+            }
+            false => None,
+        };
+        return Decision::Leaf {
+            arm: first.arm,
+            otherwise,
+        };
+    }
+
+    let rows = rows
+        .into_iter()
+        .flat_map(|row| {
+            let arm = row.arm;
+            expand(&[row.pats]).into_iter().map(move |pats| Row { pats, arm })
+        })
+        .collect::<Vec<_>>();
+    let matrix = rows.iter().map(|row| row.pats.clone()).collect::<Vec<_>>();
+    let peano = peano(&matrix, &[]);
+    let mut ctors: Vec<Ctor> = Vec::new();
+    for ctor in used(&matrix, peano) {
+        if !ctors.contains(&ctor) {
+            ctors.push(ctor);
+        }
+    }
+
+    let default_rows = rows
+        .iter()
+        .filter(|row| Ctor::of(row.pats[0]).is_none())
+        .map(|row| Row {
+            pats: row.pats[1..].to_vec(),
+            arm: row.arm,
+        })
+        .collect();
+    let default = build(default_rows, &occs[1..], guarded);
+    if ctors.is_empty() {
+        return default;
+    }
+
+    let preds = rows.iter().map(|row| predecessor(row.pats[0])).collect::<Vec<_>>();
+    let cases = ctors
+        .into_iter()
+        .map(|ctor| {
+            let arity = ctor.arity();
+            let specialized = rows
+                .iter()
+                .zip(&preds)
+                .filter_map(|(row, pred)| {
+                    specialize(&row.pats, &ctor, arity, pred).map(|pats| Row { pats, arm: row.arm })
+                })
+                .collect();
+            let occs = (0..arity)
+                .map(|idx| {
+                    let mut occ = occs[0].clone();
+                    occ.push(idx);
+                    occ
+                })
+                .chain(occs[1..].iter().cloned())
+                .collect::<Vec<_>>();
+            let tree = build(specialized, &occs, guarded);
+            (ctor, tree)
+        })
+        .collect();
+    Decision::Switch {
+        occurrence: occs[0].clone(),
+        cases,
+        default: Box::new(default),
+    }
+}
+
+/// Runtime values that patterns can be matched against. Evaluators with
+/// their own representation of values implement this to share decision
+/// trees and [`crate::erase::bind`]
+pub trait Scrutinee: Clone {
+    fn literal(&self) -> Option<&Literal>;
+    fn injection(&self) -> Option<(&str, &Self)>;
+    fn product(&self) -> Option<&[Self]>;
+    /// The value of a natural number, to bind the predecessor of a value
+    /// matched against a `succ` pattern
+    fn nat(n: u32) -> Self;
+}
+
+impl Scrutinee for Value {
+    fn literal(&self) -> Option<&Literal> {
+        match self {
+            Value::Lit(lit) => Some(lit),
+            _ => None,
+        }
+    }
+
+    fn injection(&self) -> Option<(&str, &Value)> {
+        match self {
+            Value::Injection(label, payload) => Some((label, payload)),
+            _ => None,
+        }
+    }
+
+    fn product(&self) -> Option<&[Value]> {
+        match self {
+            Value::Product(vals) => Some(vals),
+            _ => None,
+        }
+    }
+
+    fn nat(n: u32) -> Value {
+        Value::Lit(Literal::Nat(n))
+    }
+}
+
+/// The value at an occurrence, along with the number of predecessors taken
+/// of it on the way there
+fn at<'v, V: Scrutinee>(val: &'v V, occ: &[usize]) -> Option<(&'v V, u32)> {
+    let mut val = val;
+    let mut preds = 0;
+    for &idx in occ {
+        if let Some(vals) = val.product() {
+            val = vals.get(idx)?;
+        } else if let Some((_, payload)) = val.injection() {
+            val = payload;
+        } else if let Some(Literal::Nat(_)) = val.literal() {
+            preds += 1;
+        } else {
+            return None;
+        }
+    }
+    Some((val, preds))
+}
+
+/// Is the head constructor of `val` - the `preds`th predecessor of it, if it
+/// is a natural number - equal to `ctor`?
+fn is_head<V: Scrutinee>(ctor: &Ctor, val: &V, preds: u32) -> bool {
+    match (ctor, val.literal()) {
+        (Ctor::Succ, Some(Literal::Nat(n))) => *n > preds,
+        (Ctor::Literal(Literal::Nat(m)), Some(Literal::Nat(n))) => n.checked_sub(preds) == Some(*m),
+        (Ctor::Literal(lit), Some(lit_)) => lit == lit_,
+        (Ctor::Label(label), _) => val.injection().is_some_and(|(label_, _)| label == label_),
+        (Ctor::Tuple(n), _) => val.product().is_some_and(|vals| *n == vals.len()),
+        _ => false,
+    }
+}
+
+impl Decision {
+    /// Walk the tree for a value, returning the index of the first arm whose
+    /// pattern matches it, and the tree to continue with if that arm's guard
+    /// fails
+    pub fn walk<V: Scrutinee>(&self, val: &V) -> Option<(usize, Option<&Decision>)> {
+        match self {
+            Decision::Fail => None,
+            Decision::Leaf { arm, otherwise } => Some((*arm, otherwise.as_deref())),
+            Decision::Switch {
+                occurrence,
+                cases,
+                default,
+            } => {
+                let (sub, preds) = at(val, occurrence)?;
+                cases
+                    .iter()
+                    .find(|(ctor, _)| is_head(ctor, sub, preds))
+                    .map_or(default.as_ref(), |(_, tree)| tree)
+                    .walk(val)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::erase::bind;
+    use std::time::Instant;
+
+    /// Index of the first arm matching a value, trying each arm in turn
+    fn naive(pats: &[Pattern], val: &Value) -> Option<usize> {
+        pats.iter().position(|pat| bind(pat, val, &mut Vec::new()))
+    }
+
+    fn unguarded(pats: &[Pattern]) -> Decision {
+        compile(&pats.iter().map(|pat| (pat, false)).collect::<Vec<_>>())
+    }
+
+    fn nat(n: u32) -> Value {
+        Value::Lit(Literal::Nat(n))
+    }
+
+    fn inj(label: &str, val: Value) -> Value {
+        Value::Injection(label.into(), Box::new(val))
+    }
+
+    /// A small linear congruential generator, so that the corpus is the same
+    /// on every run
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn below(&mut self, n: u64) -> u64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (self.0 >> 33) % n
+        }
+    }
+
+    /// A random pattern for values of type `(Opt, Nat)`, where `Opt` is
+    /// `{None | Some Nat}`
+    fn gen_pattern(rng: &mut Lcg) -> Pattern {
+        fn nat(rng: &mut Lcg, depth: u32) -> Pattern {
+            match rng.below(if depth > 0 { 5 } else { 4 }) {
+                0 => Pattern::Any,
+                1 => Pattern::Variable("n".into()),
+                2 | 3 => Pattern::Literal(Literal::Nat(rng.below(3) as u32)),
+                _ => Pattern::Succ(Box::new(nat(rng, depth - 1))),
+            }
+        }
+        fn opt(rng: &mut Lcg) -> Pattern {
+            match rng.below(5) {
+                0 => Pattern::Any,
+                1 => Pattern::Constructor("None".into(), Box::new(Pattern::Any)),
+                2 => Pattern::Or(vec![
+                    Pattern::Constructor("None".into(), Box::new(Pattern::Any)),
+                    Pattern::Constructor("Some".into(), Box::new(Pattern::Literal(Literal::Nat(0)))),
+                ]),
+                _ => Pattern::Constructor("Some".into(), Box::new(nat(rng, 2))),
+            }
+        }
+        let pat = Pattern::Product(vec![opt(rng), nat(rng, 2)]);
+        match rng.below(6) {
+            0 => Pattern::As(Box::new(pat), "p".into()),
+            _ => pat,
+        }
+    }
+
+    #[test]
+    fn select_first_arm() {
+        let pats = vec![
+            Pattern::Product(vec![Pattern::Literal(Literal::Nat(0)), Pattern::Any]),
+            Pattern::Product(vec![Pattern::Any, Pattern::Literal(Literal::Bool(true))]),
+            Pattern::Product(vec![Pattern::Succ(Box::new(Pattern::Any)), Pattern::Any]),
+        ];
+        let tree = unguarded(&pats);
+        let pair = |n, b| Value::Product(vec![nat(n), Value::Lit(Literal::Bool(b))]);
+        assert_eq!(tree.walk(&pair(0, true)).map(|(arm, _)| arm), Some(0));
+        assert_eq!(tree.walk(&pair(3, true)).map(|(arm, _)| arm), Some(1));
+        assert_eq!(tree.walk(&pair(3, false)).map(|(arm, _)| arm), Some(2));
+
+        // The first component is only inspected once, after the pair itself
+        let component = match &tree {
+            Decision::Switch { occurrence, cases, .. } if occurrence.is_empty() && cases.len() == 1 => &cases[0].1,
+            tree => panic!("{:?}", tree),
+        };
+        match component {
+            Decision::Switch { occurrence, cases, .. } => {
+                assert_eq!(occurrence, &vec![0]);
+                assert_eq!(cases.len(), 2);
+            }
+            tree => panic!("{:?}", tree),
+        }
+    }
+
+    #[test]
+    fn guarded_fallthrough() {
+        let a = Pattern::Constructor("A".into(), Box::new(Pattern::Any));
+        let pats = [
+            (&Pattern::Or(vec![a.clone(), Pattern::Variable("x".into())]), true),
+            (&a, false),
+            (&Pattern::Any, false),
+        ];
+        let tree = compile(&pats);
+        let (arm, otherwise) = tree.walk(&inj("A", nat(0))).unwrap();
+        assert_eq!(arm, 0);
+        // The second alternative of the guarded arm is not retried
+        let (arm, _) = otherwise.unwrap().walk(&inj("A", nat(0))).unwrap();
+        assert_eq!(arm, 1);
+        let (arm, otherwise) = tree.walk(&inj("B", nat(0))).unwrap();
+        assert_eq!(arm, 0);
+        assert_eq!(otherwise.unwrap().walk(&inj("B", nat(0))).unwrap().0, 2);
+    }
+
+    /// Tree-based and naive matching select the same arm for every value in
+    /// a generated corpus of case expressions
+    #[test]
+    fn differential() {
+        let opts = [inj("None", Value::Lit(Literal::Unit))]
+            .iter()
+            .cloned()
+            .chain((0..4).map(|n| inj("Some", nat(n))))
+            .collect::<Vec<_>>();
+        let values = opts
+            .iter()
+            .flat_map(|opt| (0..5).map(move |n| Value::Product(vec![opt.clone(), nat(n)])))
+            .collect::<Vec<_>>();
+
+        let mut rng = Lcg(139);
+        for _ in 0..300 {
+            let arms = 1 + rng.below(6) as usize;
+            let pats = (0..arms).map(|_| gen_pattern(&mut rng)).collect::<Vec<_>>();
+            let tree = unguarded(&pats);
+            for val in &values {
+                assert_eq!(
+                    tree.walk(val).map(|(arm, _)| arm),
+                    naive(&pats, val),
+                    "{:?} against {:?}",
+                    val,
+                    pats
+                );
+            }
+        }
+    }
+
+    /// Match pairs of constructors of a 30-constructor variant, with and
+    /// without a decision tree. Run with
+    /// `cargo test --release -- --ignored --nocapture decision_tree_matching`
+    #[test]
+    #[ignore]
+    fn decision_tree_matching() {
+        let label = |i: usize| Pattern::Constructor(format!("L{}", i), Box::new(Pattern::Any));
+        let mut pats = (0..30)
+            .flat_map(|i| (0..3).map(move |j| Pattern::Product(vec![label(i), label(j)])))
+            .collect::<Vec<_>>();
+        pats.push(Pattern::Any);
+        let values = (0..30)
+            .flat_map(|i| (0..30).map(move |j| (i, j)))
+            .map(|(i, j)| {
+                let unit = || Value::Lit(Literal::Unit);
+                Value::Product(vec![inj(&format!("L{}", i), unit()), inj(&format!("L{}", j), unit())])
+            })
+            .collect::<Vec<_>>();
+
+        let start = Instant::now();
+        let mut naive_arms = Vec::new();
+        for _ in 0..100 {
+            naive_arms = values.iter().map(|val| naive(&pats, val)).collect::<Vec<_>>();
+        }
+        println!("naive: {:?}", start.elapsed());
+
+        let start = Instant::now();
+        let tree = unguarded(&pats);
+        let mut tree_arms = Vec::new();
+        for _ in 0..100 {
+            tree_arms = values
+                .iter()
+                .map(|val| tree.walk(val).map(|(arm, _)| arm))
+                .collect::<Vec<_>>();
+        }
+        println!("decision tree: {:?}", start.elapsed());
+        assert_eq!(naive_arms, tree_arms);
+    }
+}
@@ -1,6 +1,8 @@
+use crate::syntax::ident;
 use crate::terms::{Kind, Literal, Term};
-use crate::types::{variant_field, Type};
+use crate::types::{subst, variant_field, Type};
 use crate::visit::PatternVisitor;
+use std::fmt;
 use util::span::Span;
 
 /// Patterns for case and let expressions
@@ -16,6 +18,50 @@ pub enum Pattern {
     Product(Vec<Pattern>),
     /// Algebraic datatype constructor, along with binding pattern
     Constructor(String, Box<Pattern>),
+    /// Alternatives, tried in order. Every alternative binds the same set of
+    /// variables, in the order in which they appear in the first one
+    Or(Vec<Pattern>),
+    /// Match the inner pattern, and also bind the whole value to a variable.
+    /// The variable is bound after any variables in the inner pattern
+    As(Box<Pattern>, String),
+    /// Successor of a natural number, matching the predecessor against the
+    /// inner pattern
+    Succ(Box<Pattern>),
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Pattern::Any => write!(f, "_"),
+            Pattern::Literal(lit) => write!(f, "{}", lit),
+            Pattern::Variable(var) => write!(f, "{}", ident(var)),
+            Pattern::Product(pats) => write!(
+                f,
+                "({})",
+                pats.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", ")
+            ),
+            Pattern::Or(alts) => write!(
+                f,
+                "{}",
+                alts.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(" | ")
+            ),
+            Pattern::As(pat, var) => write!(f, "{} as {}", pat, ident(var)),
+            Pattern::Constructor(label, pat) => match (ident(label), pat.as_ref()) {
+                // A bare constructor is parsed with a wildcard payload
+                (label, Pattern::Any) => write!(f, "{}", label),
+                (label, Pattern::Constructor(_, _) | Pattern::Or(_) | Pattern::As(_, _) | Pattern::Succ(_)) => {
+                    write!(f, "{} ({})", label, pat)
+                }
+                (label, _) => write!(f, "{} {}", label, pat),
+            },
+            Pattern::Succ(pat) => match pat.as_ref() {
+                Pattern::Constructor(_, _) | Pattern::Or(_) | Pattern::As(_, _) | Pattern::Succ(_) => {
+                    write!(f, "succ ({})", pat)
+                }
+                _ => write!(f, "succ {}", pat),
+            },
+        }
+    }
 }
 
 #[derive(Clone, Debug, Default)]
@@ -42,7 +88,7 @@ impl PatternVisitor for PatVarStack {
 pub struct PatternCount(usize);
 
 impl PatternCount {
-    pub fn collect(pat: &mut Pattern) -> usize {
+    pub fn collect(pat: &Pattern) -> usize {
         let mut p = PatternCount(0);
         p.visit_pattern(pat);
         p.0
@@ -58,6 +104,10 @@ impl PatternVisitor for PatternCount {
 impl Pattern {
     /// Does this pattern match the given [`Term`]?
     pub fn matches(&self, term: &Term) -> bool {
+        let term = term.unshared();
+        if let Kind::Fold(_, inner) = &term.kind {
+            return self.matches(inner);
+        }
         match self {
             Pattern::Any => return true,
             Pattern::Variable(_) => return true,
@@ -78,6 +128,13 @@ impl Pattern {
                     }
                 }
             }
+            Pattern::Or(alts) => return alts.iter().any(|p| p.matches(term)),
+            Pattern::As(pat, _) => return pat.matches(term),
+            Pattern::Succ(pat) => {
+                if let Kind::Lit(Literal::Nat(n)) = &term.kind {
+                    return *n > 0 && pat.matches(&Term::new(Kind::Lit(Literal::Nat(n - 1)), term.span));
+                }
+            }
         }
         false
     }
@@ -88,15 +145,15 @@ impl Pattern {
 ///
 /// It is the caller's responsibiliy to track stack growth and pop off
 /// types after calling this function
-pub struct PatTyStack<'ty> {
-    pub ty: &'ty Type,
-    pub inner: Vec<&'ty Type>,
+pub struct PatTyStack {
+    pub ty: Type,
+    pub inner: Vec<Type>,
 }
 
-impl<'ty> PatTyStack<'ty> {
-    pub fn collect(ty: &'ty Type, pat: &Pattern) -> Vec<&'ty Type> {
+impl PatTyStack {
+    pub fn collect(ty: &Type, pat: &Pattern) -> Vec<Type> {
         let mut p = PatTyStack {
-            ty,
+            ty: ty.clone(),
             inner: Vec::with_capacity(16),
         };
         p.visit_pattern(pat);
@@ -104,22 +161,29 @@ impl<'ty> PatTyStack<'ty> {
     }
 }
 
-impl<'ty> PatternVisitor for PatTyStack<'_> {
+impl PatternVisitor for PatTyStack {
     fn visit_product(&mut self, pats: &Vec<Pattern>) {
-        if let Type::Product(tys) = self.ty {
-            let ty = self.ty;
-            for (ty, pat) in tys.iter().zip(pats.iter()) {
+        if let Type::Product(tys) = self.ty.clone() {
+            let ty = std::mem::replace(&mut self.ty, Type::Error);
+            for (ty, pat) in tys.into_iter().zip(pats.iter()) {
                 self.ty = ty;
                 self.visit_pattern(pat);
             }
             self.ty = ty;
+        } else if let Type::Error = self.ty {
+            for pat in pats {
+                self.visit_pattern(pat);
+            }
         }
     }
 
     fn visit_constructor(&mut self, label: &String, pat: &Pattern) {
-        if let Type::Variant(vs) = self.ty {
-            let ty = self.ty;
-            self.ty = variant_field(&vs, label, Span::zero()).unwrap();
+        if let Type::Error = self.ty {
+            // Every binder under a poisoned type is also poisoned
+            self.visit_pattern(pat);
+        } else if let Type::Variant(vs) = &self.ty {
+            let field = variant_field(&vs, label, Span::zero()).unwrap().clone();
+            let ty = std::mem::replace(&mut self.ty, field);
             self.visit_pattern(pat);
             self.ty = ty;
         }
@@ -128,9 +192,27 @@ impl<'ty> PatternVisitor for PatTyStack<'_> {
     fn visit_pattern(&mut self, pattern: &Pattern) {
         match pattern {
             Pattern::Any | Pattern::Literal(_) => {}
-            Pattern::Variable(_) => self.inner.push(self.ty),
+            Pattern::Variable(_) => self.inner.push(self.ty.clone()),
+            // The whole value is bound at its own type, without unfolding
+            Pattern::As(pat, _) => {
+                self.visit_pattern(pat);
+                self.inner.push(self.ty.clone());
+            }
+            // Nested patterns match against the unfolding of a recursive type
+            _ if matches!(self.ty, Type::Rec(_)) => {
+                let unfolded = match &self.ty {
+                    Type::Rec(inner) => subst(self.ty.clone(), *inner.clone()),
+                    _ => unreachable!(),
+                };
+                let ty = std::mem::replace(&mut self.ty, unfolded);
+                self.visit_pattern(pattern);
+                self.ty = ty;
+            }
             Pattern::Constructor(label, pat) => self.visit_constructor(label, pat),
             Pattern::Product(pats) => self.visit_product(pats),
+            Pattern::Or(alts) => self.visit_or(alts),
+            // The predecessor has the same type, `Nat`
+            Pattern::Succ(pat) => self.visit_succ(pat),
         }
     }
 }
@@ -140,15 +222,15 @@ mod test {
     use super::*;
     #[test]
     fn pattern_count() {
-        let mut pat = Pattern::Variable(String::new());
-        assert_eq!(PatternCount::collect(&mut pat), 1);
+        let pat = Pattern::Variable(String::new());
+        assert_eq!(PatternCount::collect(&pat), 1);
     }
 
     #[test]
     fn pattern_ty_stack() {
         let mut pat = Pattern::Variable(String::new());
         let ty = Type::Nat;
-        assert_eq!(PatTyStack::collect(&ty, &mut pat), vec![&ty]);
+        assert_eq!(PatTyStack::collect(&ty, &mut pat), vec![ty]);
     }
 
     #[test]
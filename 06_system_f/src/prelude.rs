@@ -0,0 +1,420 @@
+//! Definitions bound around every term given to the driver
+//!
+//! A prelude is written as a chain of lets, `let x1 = t1 in ... let xn = tn
+//! in unit`, where each let binds a single variable, and the chain ends at
+//! the first term that is not such a let. Each definition is evaluated once,
+//! and its value substituted for its name in the definitions after it and in
+//! every term parsed with [`Prelude::parser`]. The driver adds the values of
+//! top-level `let x = t;` declarations in the same way, with
+//! [`Prelude::define`], so that a file or REPL session builds on them.
+//!
+//! Evaluating the definitions is the slow part, so their values are cached
+//! in a file, encoded with [`codec`] and keyed by a hash of the source of the
+//! prelude. The hash is only stable for a given build of the driver, which
+//! at worst makes a new build evaluate the prelude again.
+use crate::diagnostics::Diagnostic;
+use crate::eval::{self, codec, EvalOutcome};
+use crate::patterns::Pattern;
+use crate::syntax::parser::Parser;
+use crate::syntax::Syntax;
+use crate::terms::visit::{FreeTermVars, InjRewriter, Shift, Subst};
+use crate::terms::{Kind, Literal, Term};
+use crate::types::Context;
+use crate::visit::MutTermVisitor;
+use std::collections::hash_map::DefaultHasher;
+use std::convert::TryFrom;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use util::span::FileId;
+
+/// Start of every cache file, followed by the version of its format
+const MAGIC: &[u8] = b"system_f prelude";
+const VERSION: u8 = 1;
+
+#[derive(Clone, Debug, Default)]
+pub struct Prelude {
+    names: Vec<String>,
+    /// The value of each name, or `None` if its definition failed
+    values: Vec<Option<Term>>,
+}
+
+/// Reasons for which a prelude cannot be evaluated
+#[derive(Debug)]
+pub enum PreludeError {
+    /// The prelude does not parse, with the messages of the parser
+    Parse(String),
+    Type(Diagnostic),
+    /// The definition of a name did not evaluate to a value
+    Eval(String, EvalOutcome),
+}
+
+impl fmt::Display for PreludeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PreludeError::Parse(msg) => write!(f, "prelude does not parse: {}", msg),
+            PreludeError::Type(diag) => write!(f, "prelude does not typecheck: {}", diag.primary.info),
+            PreludeError::Eval(name, EvalOutcome::Raised(tm)) => write!(f, "`{}` raised `{}`", name, tm),
+            PreludeError::Eval(name, EvalOutcome::Stuck(e)) => write!(f, "`{}` failed: {}", name, e),
+            PreludeError::Eval(name, EvalOutcome::Value(_)) => write!(f, "`{}` failed", name),
+        }
+    }
+}
+
+impl Prelude {
+    /// The names defined, in order
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// The value of a name
+    pub fn value(&self, name: &str) -> Option<&Term> {
+        let idx = self.names.iter().rposition(|n| n == name)?;
+        self.values[idx].as_ref()
+    }
+
+    /// Parse and evaluate every definition of a prelude
+    pub fn evaluate(ctx: &mut Context, source: &str) -> Result<Prelude, PreludeError> {
+        let mut p = Parser::new(source);
+        let parsed = p.parse();
+        let diag = p.diagnostic();
+        let mut term = match parsed {
+            Ok(term) if diag.error_count() == 0 => term,
+            _ => return Err(PreludeError::Parse(diag.emit())),
+        };
+        ctx.de_alias(&mut term);
+        InjRewriter.visit(&mut term);
+        ctx.type_check(&term).map_err(PreludeError::Type)?;
+        ctx.annotate_injections(&mut term);
+
+        let mut prelude = Prelude::default();
+        while let Some((name, mut bound, body)) = definition(term) {
+            prelude.close(&mut bound);
+            match eval::eval_limited(ctx, bound, eval::DEFAULT_FUEL) {
+                EvalOutcome::Value(v) => {
+                    prelude.names.push(name);
+                    prelude.values.push(Some(v));
+                }
+                outcome => return Err(PreludeError::Eval(name, outcome)),
+            }
+            term = body;
+        }
+        Ok(prelude)
+    }
+
+    /// The prelude with the given source, read from the cache file if it
+    /// holds the values of the same source, and evaluated otherwise. The
+    /// values are then written to the cache, unless it cannot be written to,
+    /// which is not an error. Also returns whether the cache was used
+    pub fn load(ctx: &mut Context, source: &str, cache: &Path) -> Result<(Prelude, bool), PreludeError> {
+        let hash = source_hash(source);
+        if let Some(prelude) = std::fs::read(cache)
+            .ok()
+            .and_then(|bytes| Prelude::decode(&bytes, hash))
+        {
+            // A cache that decodes but does not typecheck is as good as none
+            if prelude.values.iter().flatten().all(|v| ctx.type_check(v).is_ok()) {
+                return Ok((prelude, true));
+            }
+        }
+        let prelude = Prelude::evaluate(ctx, source)?;
+        let _ = std::fs::write(cache, prelude.encode(hash));
+        Ok((prelude, false))
+    }
+
+    /// A parser for a term in which the names defined are bound
+    pub fn parser<'s>(&self, input: &'s str) -> Parser<'s> {
+        self.file_parser(input, FileId::default(), Syntax::default())
+    }
+
+    /// [`Prelude::parser`], for the contents of a file with this id, written
+    /// in the given syntax
+    pub fn file_parser<'s>(&self, input: &'s str, file: FileId, syntax: Syntax) -> Parser<'s> {
+        let mut p = Parser::with_syntax(input, file, syntax);
+        for name in &self.names {
+            p.bind(name);
+        }
+        p
+    }
+
+    /// Define a name after every other, shadowing any earlier definition of
+    /// it. A definition that failed has no value, and terms using the name
+    /// are rejected by [`Prelude::failed`]
+    pub fn define(&mut self, name: String, value: Option<Term>) {
+        self.names.push(name);
+        self.values.push(value);
+    }
+
+    /// The first name used by a term parsed with [`Prelude::parser`] whose
+    /// definition failed, if any
+    pub fn failed(&self, term: &Term) -> Option<&str> {
+        FreeTermVars::collect(term)
+            .into_iter()
+            .filter_map(|idx| self.values.len().checked_sub(idx + 1))
+            .find(|&pos| self.values[pos].is_none())
+            .map(|pos| self.names[pos].as_str())
+    }
+
+    /// Substitute the value of every name defined into a term parsed with
+    /// [`Prelude::parser`]. The term must not use a name whose definition
+    /// failed
+    pub fn close(&self, term: &mut Term) {
+        for value in self.values.iter().rev() {
+            let value = match value {
+                Some(value) => value.clone(),
+                None => Term::new(Kind::Lit(Literal::Unit), term.span),
+            };
+            Subst::shared(value).visit(term);
+            Shift::new(-1).visit(term);
+        }
+    }
+
+    /// The cache file: the magic bytes and version, the hash of the source,
+    /// the number of names, and then each name and encoded value, every
+    /// length and number a little-endian `u32` or `u64`
+    fn encode(&self, hash: u64) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+        out.extend_from_slice(&hash.to_le_bytes());
+        out.extend_from_slice(&(self.names.len() as u32).to_le_bytes());
+        for (name, value) in self.names.iter().zip(&self.values) {
+            let bytes = value
+                .as_ref()
+                .and_then(codec::encode)
+                .expect("prelude values are closed");
+            for part in [name.as_bytes(), &bytes[..]].iter() {
+                out.extend_from_slice(&(part.len() as u32).to_le_bytes());
+                out.extend_from_slice(part);
+            }
+        }
+        out
+    }
+
+    fn decode(bytes: &[u8], hash: u64) -> Option<Prelude> {
+        let mut r = Reader(bytes.strip_prefix(MAGIC)?.strip_prefix(&[VERSION])?);
+        if u64::from_le_bytes(r.array()?) != hash {
+            return None;
+        }
+        let mut prelude = Prelude::default();
+        for _ in 0..r.len()? {
+            let name = r.chunk()?;
+            prelude.names.push(String::from_utf8(name.to_vec()).ok()?);
+            let value = r.chunk()?;
+            prelude.values.push(Some(codec::decode(value).ok()?));
+        }
+        match r.0.is_empty() {
+            true => Some(prelude),
+            false => None,
+        }
+    }
+}
+
+/// The unread part of a cache file
+struct Reader<'b>(&'b [u8]);
+
+impl<'b> Reader<'b> {
+    fn take(&mut self, len: usize) -> Option<&'b [u8]> {
+        if len > self.0.len() {
+            return None;
+        }
+        let (part, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(part)
+    }
+
+    fn array<const N: usize>(&mut self) -> Option<[u8; N]> {
+        <[u8; N]>::try_from(self.take(N)?).ok()
+    }
+
+    fn len(&mut self) -> Option<usize> {
+        Some(u32::from_le_bytes(self.array()?) as usize)
+    }
+
+    /// Bytes preceded by their length
+    fn chunk(&mut self) -> Option<&'b [u8]> {
+        let len = self.len()?;
+        self.take(len)
+    }
+}
+
+/// The name, bound term and body of a let binding a single variable
+fn definition(term: Term) -> Option<(String, Term, Term)> {
+    match term.kind {
+        Kind::Let(pat, bound, body) => match *pat {
+            Pattern::Variable(name) => Some((name, *bound, *body)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn source_hash(source: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::syntax::parser::Item;
+
+    const SOURCE: &str = "let double = \\n: Nat. add n n in let four = double 2 in unit";
+
+    #[test]
+    fn cached() {
+        let cache = std::env::temp_dir().join(format!("system_f-prelude-{}.cache", std::process::id()));
+        let _ = std::fs::remove_file(&cache);
+        let mut ctx = Context::default();
+
+        let (prelude, cached) = Prelude::load(&mut ctx, SOURCE, &cache).unwrap();
+        assert!(!cached);
+        assert_eq!(prelude.names(), ["double", "four"]);
+        assert_eq!(prelude.value("four").unwrap().kind, Kind::Lit(Literal::Nat(4)));
+
+        let (loaded, cached) = Prelude::load(&mut ctx, SOURCE, &cache).unwrap();
+        assert!(cached);
+        assert_eq!(loaded.names(), prelude.names());
+        assert_eq!(
+            codec::encode(loaded.value("double").unwrap()),
+            codec::encode(prelude.value("double").unwrap())
+        );
+
+        let mut p = loaded.parser("double four");
+        let mut term = p.parse().unwrap();
+        assert_eq!(p.diagnostic().error_count(), 0);
+        loaded.close(&mut term);
+        assert!(ctx.type_check(&term).is_ok());
+        match eval::eval_limited(&ctx, term, 100) {
+            EvalOutcome::Value(v) => assert_eq!(v.kind, Kind::Lit(Literal::Nat(8))),
+            outcome => panic!("expected a value, not {:?}", outcome),
+        }
+
+        // A corrupted cache, or one for a different source, is evaluated again
+        let mut bytes = std::fs::read(&cache).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        std::fs::write(&cache, &bytes).unwrap();
+        assert!(!Prelude::load(&mut ctx, SOURCE, &cache).unwrap().1);
+        let (other, cached) = Prelude::load(&mut ctx, "let five = 5 in unit", &cache).unwrap();
+        assert!(!cached);
+        assert_eq!(other.names(), ["five"]);
+        let _ = std::fs::remove_file(&cache);
+    }
+
+    #[test]
+    fn errors() {
+        let mut ctx = Context::default();
+        assert!(matches!(
+            Prelude::evaluate(&mut ctx, "let x = succ true in unit"),
+            Err(PreludeError::Type(_))
+        ));
+        assert!(matches!(
+            Prelude::evaluate(&mut ctx, "let x = raise Nat 1 in unit"),
+            Err(PreludeError::Eval(name, EvalOutcome::Raised(_))) if name == "x"
+        ));
+    }
+
+    /// Evaluate the items of the input as the driver does, returning the
+    /// value of each term and whether each declaration succeeded. Syntax
+    /// errors must be expected with [`run_with_errors`]
+    fn run(env: &mut Prelude, input: &str) -> (Vec<Term>, Vec<bool>) {
+        let (values, declared, errors) = run_with_errors(env, input);
+        assert_eq!(errors, "");
+        (values, declared)
+    }
+
+    /// [`run`], also returning the syntax errors reported
+    fn run_with_errors(env: &mut Prelude, input: &str) -> (Vec<Term>, Vec<bool>, String) {
+        let mut ctx = Context::default();
+        let mut p = env.parser(input);
+        let (mut values, mut declared) = (Vec::new(), Vec::new());
+        while let Ok(item) = p.item() {
+            let (name, mut term) = match item {
+                Item::Let(name, term, _) => (Some(name), term),
+                Item::Term(term) => (None, term),
+                Item::Type(..) | Item::Import(..) => continue,
+                Item::Invalid(name, _) => {
+                    if let Some(name) = name {
+                        declared.push(false);
+                        env.define(name, None);
+                    }
+                    continue;
+                }
+            };
+            let value = match env.failed(&term) {
+                Some(_) => None,
+                None => {
+                    env.close(&mut term);
+                    match ctx.type_check(&term) {
+                        Ok(_) => match eval::eval_limited(&ctx, term, 1000) {
+                            EvalOutcome::Value(v) => Some(v),
+                            outcome => panic!("expected a value, not {:?}", outcome),
+                        },
+                        Err(_) => None,
+                    }
+                }
+            };
+            match name {
+                Some(name) => {
+                    declared.push(value.is_some());
+                    env.define(name, value);
+                }
+                None => values.extend(value),
+            }
+        }
+        (values, declared, p.diagnostic().emit())
+    }
+
+    #[test]
+    fn declarations() {
+        let mut env = Prelude::default();
+        let input = "let double = \\n: Nat. add n n;
+                     let inc = \\n: Nat. succ n;
+                     let twice = \\f: Nat -> Nat. \\n: Nat. f (f n);
+                     twice double (inc 1)";
+        let (values, declared) = run(&mut env, input);
+        assert_eq!(declared, [true, true, true]);
+        assert_eq!(values[0].kind, Kind::Lit(Literal::Nat(8)));
+
+        // Later declarations shadow earlier ones, which they may refer to,
+        // and a session goes on from the values declared by earlier inputs
+        let input = "let x = 1; let y = x; let x = add x 10; (x, y, double x)";
+        let (values, _) = run(&mut env, input);
+        assert_eq!(values[0].to_string(), "(11,1,22)");
+        let (values, _) = run(&mut env, "x");
+        assert_eq!(values[0].kind, Kind::Lit(Literal::Nat(11)));
+        assert_eq!(env.names().len(), 6);
+    }
+
+    #[test]
+    fn failed_declaration() {
+        let mut env = Prelude::default();
+        let input = "let bad = succ true; let good = 2; let worse = succ bad; good; bad";
+        let (values, declared) = run(&mut env, input);
+        assert_eq!(declared, [false, true, false]);
+        assert_eq!(values.len(), 1);
+        assert_eq!(values[0].kind, Kind::Lit(Literal::Nat(2)));
+        let mut p = env.parser("(good, bad)");
+        let term = p.parse().unwrap();
+        assert_eq!(env.failed(&term), Some("bad"));
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let mut env = Prelude::default();
+        let input = "let one = 1;
+let two = (1, );
+let three = add one 2;
+let four = case three of | 0 => | _ => 4;
+let five = (one, three, \\x: Nat. succ x);
+five.2 five.1";
+        let (values, declared, errors) = run_with_errors(&mut env, input);
+        assert_eq!(declared, [true, false, true, false, true]);
+        assert_eq!(values[0].kind, Kind::Lit(Literal::Nat(4)));
+        // Lines are counted from 0
+        assert_eq!(errors.matches("Error occuring").count(), 2, "{}", errors);
+        assert!(errors.contains("line 1, col: 14: expected a term"), "{}", errors);
+        assert!(errors.contains("line 3, col: 30: missing case term"), "{}", errors);
+    }
+}
@@ -1,13 +1,15 @@
-use super::{Token, TokenKind};
+use super::{Syntax, Token, TokenKind, Trivia, TriviaKind};
 use std::char;
 use std::iter::Peekable;
 use std::str::Chars;
-use util::span::{Location, Span};
+use util::span::{FileId, Location, Span};
 
 #[derive(Clone)]
 pub struct Lexer<'s> {
     input: Peekable<Chars<'s>>,
     current: Location,
+    file: FileId,
+    syntax: Syntax,
 }
 
 impl<'s> Lexer<'s> {
@@ -19,15 +21,36 @@ impl<'s> Lexer<'s> {
                 col: 0,
                 abs: 0,
             },
+            file: FileId::default(),
+            syntax: Syntax::default(),
         }
     }
 
+    /// Give the spans of the tokens lexed the id of the file they are in
+    pub fn with_file(self, file: FileId) -> Lexer<'s> {
+        Lexer { file, ..self }
+    }
+
+    /// Lex the keywords of another syntax
+    pub fn with_syntax(self, syntax: Syntax) -> Lexer<'s> {
+        Lexer { syntax, ..self }
+    }
+
     /// Peek at the next [`char`] in the input stream
     fn peek(&mut self) -> Option<char> {
         self.input.peek().cloned()
     }
 
-    /// Consume the next [`char`] and advance internal source position
+    /// Peek at the next two [`char`]s in the input stream
+    fn peek2(&self) -> (Option<char>, Option<char>) {
+        let mut input = self.input.clone();
+        (input.next(), input.next())
+    }
+
+    /// Consume the next [`char`] and advance internal source position. The
+    /// column counts characters, so that carets line up under multi-byte
+    /// characters, while the absolute position is a byte offset into the
+    /// input
     fn consume(&mut self) -> Option<char> {
         match self.input.next() {
             Some('\n') => {
@@ -38,7 +61,7 @@ impl<'s> Lexer<'s> {
             }
             Some(ch) => {
                 self.current.col += 1;
-                self.current.abs += 1;
+                self.current.abs += ch.len_utf8() as u32;
                 Some(ch)
             }
             None => None,
@@ -63,19 +86,57 @@ impl<'s> Lexer<'s> {
         (s, Span::new(start, self.current))
     }
 
-    /// Eat whitespace
-    fn consume_delimiter(&mut self) {
-        let _ = self.consume_while(char::is_whitespace);
+    /// Consume the whitespace and comments before the next token
+    fn trivia(&mut self) -> Vec<Trivia> {
+        let mut trivia = Vec::new();
+        loop {
+            let start = self.current;
+            let (text, kind) = match self.peek2() {
+                (Some(ch), _) if ch.is_whitespace() => {
+                    (self.consume_while(char::is_whitespace).0, TriviaKind::Whitespace)
+                }
+                (Some('-'), Some('-')) => (self.consume_while(|ch| ch != '\n').0, TriviaKind::LineComment),
+                (Some('{'), Some('-')) => self.block_comment(),
+                _ => return trivia,
+            };
+            trivia.push(Trivia {
+                kind,
+                span: Span::new(start, self.current),
+                text,
+            });
+        }
+    }
+
+    /// Lex a block comment, `{- ... -}`, in which block comments nest
+    fn block_comment(&mut self) -> (String, TriviaKind) {
+        let mut text = String::new();
+        let mut depth = 0;
+        loop {
+            match self.peek2() {
+                (Some('{'), Some('-')) => depth += 1,
+                (Some('-'), Some('}')) => depth -= 1,
+                (None, _) => return (text, TriviaKind::UnterminatedComment),
+                _ => {
+                    text.extend(self.consume());
+                    continue;
+                }
+            }
+            text.extend(self.consume());
+            text.extend(self.consume());
+            if depth == 0 {
+                return (text, TriviaKind::BlockComment);
+            }
+        }
     }
 
-    /// Lex a natural number
+    /// Lex a natural number. We have peeked at least one digit, so parsing
+    /// only fails if the number does not fit in a Nat
     fn number(&mut self) -> Token {
-        // Since we peeked at least one numeric char, we should always
-        // have a string containing at least 1 single digit, as such
-        // it is safe to call unwrap() on str::parse<u32>
-        let (data, span) = self.consume_while(char::is_numeric);
-        let n = data.parse::<u32>().unwrap();
-        Token::new(TokenKind::Nat(n), span)
+        let (data, span) = self.consume_while(|ch| ch.is_ascii_digit());
+        match data.parse::<u32>() {
+            Ok(n) => Token::new(TokenKind::Nat(n), span),
+            Err(_) => Token::new(TokenKind::InvalidNat(data), span),
+        }
     }
 
     /// Lex a reserved keyword or an identifier
@@ -93,34 +154,86 @@ impl<'s> Lexer<'s> {
             "zero" => TokenKind::Nat(0),
             "Bool" => TokenKind::TyBool,
             "Nat" => TokenKind::TyNat,
+            "String" => TokenKind::TyString,
+            "concat" => TokenKind::Concat,
+            "strlen" => TokenKind::StrLen,
+            "add" => TokenKind::Add,
+            "sub" => TokenKind::Sub,
+            "mul" => TokenKind::Mul,
+            "eq" => TokenKind::Eq,
+            "lt" => TokenKind::Lt,
             "Unit" => TokenKind::TyUnit,
             "unit" => TokenKind::Unit,
             "let" => TokenKind::Let,
+            "letrec" => TokenKind::LetRec,
+            "and" => TokenKind::And,
             "in" => TokenKind::In,
             "fix" => TokenKind::Fix,
             "case" => TokenKind::Case,
             "of" => TokenKind::Of,
+            "when" => TokenKind::When,
             "fold" => TokenKind::Fold,
             "unfold" => TokenKind::Unfold,
+            "raise" => TokenKind::Raise,
             "rec" => TokenKind::Rec,
+            "type" => TokenKind::Type,
+            "import" => TokenKind::Import,
             "lambda" => TokenKind::Lambda,
             "forall" => TokenKind::Forall,
             "exists" => TokenKind::Exists,
             "pack" => TokenKind::Pack,
             "unpack" => TokenKind::Unpack,
             "as" => TokenKind::As,
+            "fun" if self.syntax == Syntax::Ml => TokenKind::Fun,
 
-            _ => {
-                if data.starts_with(|ch: char| ch.is_ascii_uppercase()) {
-                    TokenKind::Uppercase(data)
-                } else {
-                    TokenKind::Lowercase(data)
-                }
-            }
+            _ => identifier(data),
         };
         Token::new(kind, span)
     }
 
+    /// Lex an identifier quoted in backticks, like `` `Nat` ``, which is
+    /// never a keyword. This lets a label be named after a type
+    fn quoted(&mut self) -> Token {
+        let start = self.current;
+        self.consume();
+        let (data, _) = self.consume_while(|ch| ch.is_ascii_alphanumeric());
+        let closed = self.peek() == Some('`');
+        if closed {
+            self.consume();
+        }
+        let kind = match closed && !data.is_empty() {
+            true => identifier(data),
+            false => TokenKind::Invalid('`'),
+        };
+        Token::new(kind, Span::new(start, self.current))
+    }
+
+    /// Lex a double-quoted string literal, handling `\n`, `\"`, and `\\`
+    /// escape sequences
+    fn string(&mut self) -> Token {
+        let start = self.current;
+        self.consume();
+        let mut s = String::new();
+        loop {
+            match self.consume() {
+                Some('"') => break,
+                Some('\\') => match self.consume() {
+                    Some('n') => s.push('\n'),
+                    Some('"') => s.push('"'),
+                    Some('\\') => s.push('\\'),
+                    Some(ch) => {
+                        s.push('\\');
+                        s.push(ch);
+                    }
+                    None => return Token::new(TokenKind::Invalid('"'), Span::new(start, self.current)),
+                },
+                Some(ch) => s.push(ch),
+                None => return Token::new(TokenKind::Invalid('"'), Span::new(start, self.current)),
+            }
+        }
+        Token::new(TokenKind::Str(s), Span::new(start, self.current))
+    }
+
     /// Consume the next input character, expecting to match `ch`.
     /// Return a [`TokenKind::Invalid`] if the next character does not match,
     /// or the argument `kind` if it does
@@ -135,14 +248,26 @@ impl<'s> Lexer<'s> {
 
     /// Return the next lexeme in the input as a [`Token`]
     pub fn lex(&mut self) -> Token {
-        self.consume_delimiter();
+        let mut trivia = self.trivia();
+        let mut token = self.token();
+        for trivia in &mut trivia {
+            trivia.span.file = self.file;
+        }
+        token.span.file = self.file;
+        token.leading_trivia = trivia;
+        token
+    }
+
+    fn token(&mut self) -> Token {
         let next = match self.peek() {
             Some(ch) => ch,
             None => return Token::new(TokenKind::Eof, Span::new(self.current, self.current)),
         };
         match next {
             x if x.is_ascii_alphabetic() => self.keyword(),
-            x if x.is_numeric() => self.number(),
+            x if x.is_ascii_digit() => self.number(),
+            '"' => self.string(),
+            '`' => self.quoted(),
             '(' => self.eat('(', TokenKind::LParen),
             ')' => self.eat(')', TokenKind::RParen),
             ';' => self.eat(';', TokenKind::Semicolon),
@@ -154,22 +279,41 @@ impl<'s> Lexer<'s> {
             ']' => self.eat(']', TokenKind::RSquare),
             '\\' => self.eat('\\', TokenKind::Lambda),
             'λ' => self.eat('λ', TokenKind::Lambda),
+            'Λ' => self.eat('Λ', TokenKind::Lambda),
             '∀' => self.eat('∀', TokenKind::Forall),
             '∃' => self.eat('∃', TokenKind::Exists),
+            '→' => self.eat('→', TokenKind::TyArrow),
+            '×' => self.eat('×', TokenKind::Times),
             '.' => self.eat('.', TokenKind::Proj),
             '=' => self.eat('=', TokenKind::Equals),
             '|' => self.eat('|', TokenKind::Bar),
             '_' => self.eat('_', TokenKind::Wildcard),
             '>' => self.eat('>', TokenKind::Gt),
-            '-' => {
-                self.consume();
-                self.eat('>', TokenKind::TyArrow)
-            }
+            '-' => match self.peek2() {
+                (_, Some(ch)) if ch.is_ascii_digit() => {
+                    let start = self.current;
+                    self.consume();
+                    let (data, span) = self.consume_while(|ch| ch.is_ascii_digit());
+                    Token::new(TokenKind::InvalidNat(format!("-{}", data)), Span::new(start, span.end))
+                }
+                _ => {
+                    self.consume();
+                    self.eat('>', TokenKind::TyArrow)
+                }
+            },
             ch => self.eat(' ', TokenKind::Invalid(ch)),
         }
     }
 }
 
+fn identifier(data: String) -> TokenKind {
+    if data.starts_with(|ch: char| ch.is_ascii_uppercase()) {
+        TokenKind::Uppercase(data)
+    } else {
+        TokenKind::Lowercase(data)
+    }
+}
+
 impl<'s> Iterator for Lexer<'s> {
     type Item = Token;
     fn next(&mut self) -> Option<Self::Item> {
@@ -186,6 +330,47 @@ impl<'s> Iterator for Lexer<'s> {
 mod test {
     use super::*;
     use TokenKind::*;
+    #[test]
+    fn quoted_identifiers() {
+        let output = Lexer::new("`Nat` `case` `Nat ``".chars())
+            .map(|t| t.kind)
+            .collect::<Vec<_>>();
+        let expected = vec![
+            Uppercase("Nat".into()),
+            Lowercase("case".into()),
+            Invalid('`'),
+            Invalid('`'),
+        ];
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn ml_keywords() {
+        let lex = |syntax| {
+            Lexer::new("fun `fun` fn".chars())
+                .with_syntax(syntax)
+                .map(|t| t.kind)
+                .collect::<Vec<_>>()
+        };
+        let name = |s: &str| Lowercase(s.into());
+        assert_eq!(lex(Syntax::Lambda), vec![name("fun"), name("fun"), name("fn")]);
+        assert_eq!(lex(Syntax::Ml), vec![Fun, name("fun"), name("fn")]);
+    }
+
+    #[test]
+    fn invalid_nats() {
+        let output = Lexer::new("4294967295 4294967296 -12 ->".chars())
+            .map(|t| t.kind)
+            .collect::<Vec<_>>();
+        let expected = vec![
+            Nat(u32::MAX),
+            InvalidNat("4294967296".into()),
+            InvalidNat("-12".into()),
+            TyArrow,
+        ];
+        assert_eq!(output, expected);
+    }
+
     #[test]
     fn nested() {
         let input = "succ(succ(succ(0)))";
@@ -197,6 +382,19 @@ mod test {
         assert_eq!(expected, output);
     }
 
+    #[test]
+    fn string() {
+        let input = r#"concat "a\nb" "\"q\" \\""#;
+        let expected = vec![Concat, Str("a\nb".into()), Str("\"q\" \\".into())];
+        let output = Lexer::new(input.chars()).map(|t| t.kind).collect::<Vec<TokenKind>>();
+        assert_eq!(expected, output);
+
+        let output = Lexer::new(r#""unterminated"#.chars())
+            .map(|t| t.kind)
+            .collect::<Vec<TokenKind>>();
+        assert_eq!(output, vec![Invalid('"')]);
+    }
+
     #[test]
     fn case() {
         let input = "case x of | A _ => true | B x => (\\y: Nat. x)";
@@ -230,4 +428,131 @@ mod test {
             .collect::<Vec<TokenKind>>();
         assert_eq!(expected, output);
     }
+
+    #[test]
+    fn unicode() {
+        let ascii = "\\X \\f: forall Y. Y -> (X, Y). \\x: X. f [Nat] 1";
+        let mixed = "ΛX λf: ∀Y. Y → X × Y. \\x: X. f [Nat] 1";
+        let kinds = |input: &str| Lexer::new(input.chars()).map(|t| t.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds(mixed)[..9],
+            [
+                Lambda,
+                Uppercase("X".into()),
+                Lambda,
+                Lowercase("f".into()),
+                Colon,
+                Forall,
+                Uppercase("Y".into()),
+                Proj,
+                Uppercase("Y".into()),
+            ]
+        );
+        assert!(kinds(mixed).contains(&Times));
+        assert_eq!(kinds(mixed).iter().filter(|&k| *k == TyArrow).count(), 1);
+
+        // Columns count characters, and absolute positions count bytes
+        let tokens = Lexer::new(mixed.chars()).collect::<Vec<_>>();
+        let arrow = tokens.iter().find(|t| t.kind == TyArrow).unwrap();
+        let start = mixed.find('→').unwrap();
+        assert_eq!(arrow.span.start.abs as usize, start);
+        assert_eq!(arrow.span.end.abs as usize, start + '→'.len_utf8());
+        assert_eq!(arrow.span.start.col as usize, mixed[..start].chars().count());
+        for tok in &tokens {
+            assert!(mixed.is_char_boundary(tok.span.start.abs as usize));
+            assert!(mixed.is_char_boundary(tok.span.end.abs as usize));
+        }
+
+        let parse = |input: &str| {
+            let mut p = crate::syntax::parser::Parser::new(input);
+            let tm = p.parse().unwrap();
+            assert_eq!(p.diagnostic().error_count(), 0);
+            tm.to_string()
+        };
+        assert_eq!(parse(mixed), parse(ascii));
+    }
+
+    #[test]
+    fn caret_after_multibyte() {
+        let input = "λx: Nat → ∀. x";
+        let mut p = crate::syntax::parser::Parser::new(input);
+        assert!(p.parse().is_err());
+        let msg = p.diagnostic().emit();
+        // The caret goes under the `.` straight after the `∀`
+        let col = input.chars().position(|c| c == '.').unwrap();
+        let caret = msg.lines().find(|l| l.trim_start().starts_with('^')).unwrap();
+        assert_eq!(caret.find('^').unwrap(), col, "{}", msg);
+        assert!(msg.contains(&format!("col: {}", col)), "{}", msg);
+    }
+
+    #[test]
+    fn comments() {
+        let input = "-- id\n\\x: Nat. {- outer {- inner -} -} x";
+        let tokens = Lexer::new(input.chars()).collect::<Vec<_>>();
+        assert_eq!(
+            tokens.iter().map(|t| t.kind.clone()).collect::<Vec<_>>(),
+            vec![Lambda, Lowercase("x".into()), Colon, TyNat, Proj, Lowercase("x".into())]
+        );
+        let kinds = |tok: &Token| tok.leading_trivia.iter().map(|t| t.kind).collect::<Vec<_>>();
+        assert_eq!(kinds(&tokens[0]), vec![TriviaKind::LineComment, TriviaKind::Whitespace]);
+        assert_eq!(tokens[0].leading_trivia[0].text, "-- id");
+        assert_eq!(
+            kinds(&tokens[5]),
+            vec![TriviaKind::Whitespace, TriviaKind::BlockComment, TriviaKind::Whitespace]
+        );
+        let block = &tokens[5].leading_trivia[1];
+        assert_eq!(block.text, "{- outer {- inner -} -}");
+        assert_eq!(
+            &input[block.span.start.abs as usize..block.span.end.abs as usize],
+            block.text
+        );
+    }
+
+    #[test]
+    fn comments_in_terms() {
+        let parse = |input: &str| {
+            let mut p = crate::syntax::parser::Parser::new(input);
+            let tm = p.parse().unwrap();
+            assert_eq!(p.diagnostic().error_count(), 0);
+            tm.to_string()
+        };
+        let cases = [
+            (
+                "case 1 of | 0 => true -- zero\n | _ => {- anything else -} false",
+                "case 1 of | 0 => true | _ => false",
+            ),
+            ("\\x: Nat. {- body -} -- of the lambda\n succ x", "\\x: Nat. succ x"),
+            ("\\x: Nat -- binder\n . x", "\\x: Nat. x"),
+        ];
+        for (commented, plain) in &cases {
+            assert_eq!(parse(commented), parse(plain));
+        }
+    }
+
+    #[test]
+    fn only_comments() {
+        let input = "-- nothing here\n{- or {- here -}\n-}\n-- at all";
+        let mut p = crate::syntax::parser::Parser::new(input);
+        assert!(matches!(
+            p.item(),
+            Err(crate::syntax::parser::Error {
+                kind: crate::syntax::parser::ErrorKind::Eof,
+                ..
+            })
+        ));
+        assert_eq!(p.diagnostic().error_count(), 0);
+    }
+
+    #[test]
+    fn unterminated_comment() {
+        let input = "succ 0 {- open {- -}";
+        let mut p = crate::syntax::parser::Parser::new(input);
+        let _ = p.parse();
+        let diag = p.diagnostic();
+        assert_eq!(diag.error_count(), 1);
+        let msg = diag.emit();
+        assert!(msg.contains("unterminated block comment"), "{}", msg);
+        let caret = msg.lines().find(|l| l.trim_start().starts_with('^')).unwrap();
+        assert_eq!(caret.find('^').unwrap(), input.find("{-").unwrap(), "{}", msg);
+    }
 }
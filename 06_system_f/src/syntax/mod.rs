@@ -1,6 +1,10 @@
 //! Lexical analysis and recursive descent parser for System F
+//!
+//! Source can be written in either of two concrete syntaxes, which parse to
+//! the same terms. See [`Syntax`]
 pub mod lexer;
 pub mod parser;
+use std::borrow::Cow;
 use util::span::Span;
 
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
@@ -8,9 +12,12 @@ pub enum TokenKind {
     Uppercase(String),
     Lowercase(String),
     Nat(u32),
+    Str(String),
     TyNat,
+    TyString,
     TyBool,
     TyArrow,
+    Times,
     TyUnit,
     Unit,
     True,
@@ -27,8 +34,17 @@ pub enum TokenKind {
     Then,
     Else,
     Let,
+    LetRec,
+    And,
     In,
     IsZero,
+    Concat,
+    StrLen,
+    Add,
+    Sub,
+    Mul,
+    Eq,
+    Lt,
     Semicolon,
     Colon,
     Comma,
@@ -45,10 +61,19 @@ pub enum TokenKind {
     Gt,
     Case,
     Of,
+    When,
     Fix,
+    /// `fun`, which is only a keyword in the ML syntax
+    Fun,
     Fold,
     Unfold,
+    Raise,
     Rec,
+    Type,
+    Import,
+    /// A number that is not a Nat literal, being negative or too large.
+    /// The parser reports which
+    InvalidNat(String),
     Invalid(char),
     Dummy,
     Eof,
@@ -58,17 +83,77 @@ pub enum TokenKind {
 pub struct Token {
     pub kind: TokenKind,
     pub span: Span,
+    /// Whitespace and comments between the previous token and this one
+    pub leading_trivia: Vec<Trivia>,
 }
 
 impl Token {
     pub const fn dummy() -> Token {
+        Token::new(TokenKind::Dummy, Span::zero())
+    }
+
+    pub const fn new(kind: TokenKind, span: Span) -> Token {
         Token {
-            kind: TokenKind::Dummy,
-            span: Span::zero(),
+            kind,
+            span,
+            leading_trivia: Vec::new(),
         }
     }
+}
 
-    pub const fn new(kind: TokenKind, span: Span) -> Token {
-        Token { kind, span }
+/// Text between tokens, which has no meaning to the parser but is kept so
+/// that source can be reformatted without losing it
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub span: Span,
+    /// The text itself, including any comment delimiters
+    pub text: String,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub enum TriviaKind {
+    Whitespace,
+    /// `-- comment`, up to the end of the line
+    LineComment,
+    /// `{- comment -}`, which may contain other block comments
+    BlockComment,
+    /// A block comment still open at the end of the input
+    UnterminatedComment,
+}
+
+/// The concrete syntax of a source
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Syntax {
+    /// Abstractions written `\x: T. t` and `\X t`, as in TaPL
+    #[default]
+    Lambda,
+    /// Abstractions written `fun x: T => t` and `fun X => t`, and recursive
+    /// bindings `let rec f: T = t in t`, as in ML. `Λ` may be written for
+    /// `fun`. Everything else is the same
+    Ml,
+}
+
+impl Syntax {
+    /// The syntax of a file, going by its name: `.ml` files are in the ML
+    /// syntax
+    pub fn of_file(name: &str) -> Syntax {
+        match name.ends_with(".ml") {
+            true => Syntax::Ml,
+            false => Syntax::Lambda,
+        }
+    }
+}
+
+/// A label or variable name as it must be written to be read back in, in
+/// either syntax, which is quoted in backticks if it would otherwise lex as a
+/// keyword
+pub fn ident(name: &str) -> Cow<'_, str> {
+    let mut lexer = lexer::Lexer::new(name.chars()).with_syntax(Syntax::Ml);
+    match (lexer.lex().kind, lexer.lex().kind) {
+        (TokenKind::Uppercase(s), TokenKind::Eof) | (TokenKind::Lowercase(s), TokenKind::Eof) if s == name => {
+            Cow::Borrowed(name)
+        }
+        _ => Cow::Owned(format!("`{}`", name)),
     }
 }
@@ -1,5 +1,5 @@
 use super::lexer::Lexer;
-use super::{Token, TokenKind};
+use super::{Syntax, Token, TokenKind, TriviaKind};
 
 use std::collections::VecDeque;
 use util::diagnostic::Diagnostic;
@@ -8,6 +8,9 @@ use util::span::*;
 use crate::patterns::{PatVarStack, Pattern};
 use crate::terms::*;
 use crate::types::*;
+use crate::visit::MutTermVisitor;
+
+mod ml;
 
 #[derive(Clone, Debug, Default)]
 pub struct DeBruijnIndexer {
@@ -37,6 +40,11 @@ impl DeBruijnIndexer {
     pub fn len(&self) -> usize {
         self.inner.len()
     }
+
+    /// Pop variables until only `len` are left
+    pub fn truncate(&mut self, len: usize) {
+        self.inner.truncate(len);
+    }
 }
 
 pub struct Parser<'s> {
@@ -46,12 +54,34 @@ pub struct Parser<'s> {
     lexer: Lexer<'s>,
     span: Span,
     token: Token,
+    /// Spans of the variables bound by the pattern being parsed
+    binders: Vec<Span>,
+    /// Whether the item being parsed had a syntax error that was recovered
+    /// from, leaving a placeholder in its place
+    recovered: bool,
+    syntax: Syntax,
+}
+
+/// A top-level item of a source file or REPL input
+#[derive(Clone, Debug, PartialEq)]
+pub enum Item {
+    /// `type Name = T;`, with the span of the whole declaration
+    Type(String, Type, Span),
+    /// `let x = t;`, which binds `x` in every later item
+    Let(String, Term, Span),
+    /// `import "file";`, which may only come before the other items of a
+    /// file
+    Import(String, Span),
+    /// An item with a syntax error, which has been reported. A let
+    /// declaration still binds its variable, if the error came after it
+    Invalid(Option<String>, Span),
+    Term(Term),
 }
 
 #[derive(Clone, Debug)]
 pub struct Error {
     pub span: Span,
-    pub tok: Token,
+    pub tok: TokenKind,
     pub kind: ErrorKind,
 }
 
@@ -66,21 +96,69 @@ pub enum ErrorKind {
     Unknown,
     Eof,
 }
+impl ErrorKind {
+    /// What the parser expected to find instead
+    fn expected(&self) -> String {
+        match self {
+            ErrorKind::ExpectedAtom => "a term".into(),
+            ErrorKind::ExpectedIdent => "an identifier".into(),
+            ErrorKind::ExpectedType => "a type".into(),
+            ErrorKind::ExpectedPattern => "a pattern".into(),
+            ErrorKind::ExpectedToken(kind) => format!("{:?}", kind),
+            ErrorKind::UnboundTypeVar | ErrorKind::Unknown => "something else".into(),
+            ErrorKind::Eof => "the end of input".into(),
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "expected {}, found {:?}", self.kind.expected(), self.tok)
+    }
+}
+
+impl From<Error> for crate::diagnostics::Diagnostic {
+    fn from(e: Error) -> crate::diagnostics::Diagnostic {
+        crate::diagnostics::Diagnostic::error(e.span, e.to_string())
+    }
+}
+
 impl<'s> Parser<'s> {
     /// Create a new [`Parser`] for the input `&str`
     pub fn new(input: &'s str) -> Parser<'s> {
+        Parser::with_file(input, FileId::default())
+    }
+
+    /// Create a new [`Parser`] for the contents of a file, whose id is given
+    /// to every span in it
+    pub fn with_file(input: &'s str, file: FileId) -> Parser<'s> {
+        Parser::with_syntax(input, file, Syntax::default())
+    }
+
+    /// Create a new [`Parser`] for the contents of a file written in the
+    /// given syntax
+    pub fn with_syntax(input: &'s str, file: FileId, syntax: Syntax) -> Parser<'s> {
         let mut p = Parser {
             tmvar: DeBruijnIndexer::default(),
             tyvar: DeBruijnIndexer::default(),
             diagnostic: Diagnostic::new(input),
-            lexer: Lexer::new(input.chars()),
+            lexer: Lexer::new(input.chars()).with_file(file).with_syntax(syntax),
             span: Span::default(),
             token: Token::dummy(),
+            binders: Vec::new(),
+            recovered: false,
+            syntax,
         };
         p.bump();
         p
     }
 
+    /// Bind a term variable around the input, as if by an enclosing
+    /// abstraction. Variables bound later are nearer to the input
+    pub fn bind(&mut self, name: &str) {
+        self.tmvar.push(name.to_string());
+    }
+
     pub fn diagnostic(self) -> Diagnostic<'s> {
         self.diagnostic
     }
@@ -117,17 +195,86 @@ impl<'s> Parser<'s> {
     }
 }
 
+/// Error recovery
+///
+/// After a syntax error, the parser skips ahead to a point from which it can
+/// go on: the end of the enclosing parentheses, type argument, or case arm,
+/// or else the start of the next top-level item. The construct that failed
+/// is replaced with a placeholder, and the item containing it is returned as
+/// [`Item::Invalid`], so that only its errors are reported
+impl<'s> Parser<'s> {
+    /// Report an error, unless a message was already pushed for it since the
+    /// diagnostic had `reported` messages, and mark the item as invalid
+    fn fail(&mut self, err: Error, reported: usize) {
+        if self.diagnostic.error_count() == reported {
+            self.diagnostic.push(err.to_string(), err.span);
+        }
+        self.recovered = true;
+    }
+
+    /// Skip tokens up to one of `until` outside of any brackets, or up to the
+    /// end of the bracket the parser is in. Also stops at the start of a top
+    /// level item: a semicolon, `type`, `import`, or a `let` at the start of
+    /// a line. None of these are consumed
+    fn skip(&mut self, until: &[TokenKind]) {
+        let mut depth = 0usize;
+        loop {
+            match self.kind() {
+                TokenKind::Eof | TokenKind::Semicolon | TokenKind::Type | TokenKind::Import => return,
+                TokenKind::Let if self.token.span.start.col == 0 => return,
+                kind if depth == 0 && until.contains(kind) => return,
+                TokenKind::LParen | TokenKind::LSquare | TokenKind::LBrace => depth += 1,
+                TokenKind::RParen | TokenKind::RSquare | TokenKind::RBrace => match depth.checked_sub(1) {
+                    Some(d) => depth = d,
+                    None => return,
+                },
+                _ => {}
+            }
+            self.bump();
+        }
+    }
+
+    /// Parse with `func`, and on an error, skip to the closing `delimiter`
+    /// and return `placeholder` instead. Variables bound before the error are
+    /// unbound again
+    fn recover<T, F>(&mut self, func: F, delimiter: TokenKind, placeholder: T) -> T
+    where
+        F: Fn(&mut Parser) -> Result<T, Error>,
+    {
+        let (reported, tmvars, tyvars) = (self.diagnostic.error_count(), self.tmvar.len(), self.tyvar.len());
+        match func(self) {
+            Ok(t) => t,
+            Err(e) => {
+                self.fail(e, reported);
+                self.tmvar.truncate(tmvars);
+                self.tyvar.truncate(tyvars);
+                self.skip(&[delimiter]);
+                placeholder
+            }
+        }
+    }
+}
+
 impl<'s> Parser<'s> {
     fn error<T>(&self, kind: ErrorKind) -> Result<T, Error> {
         Err(Error {
             span: self.token.span,
-            tok: self.token.clone(),
+            tok: self.token.kind.clone(),
             kind,
         })
     }
 
     fn bump(&mut self) -> TokenKind {
         let prev = std::mem::replace(&mut self.token, self.lexer.lex());
+        for trivia in &self.token.leading_trivia {
+            if trivia.kind == TriviaKind::UnterminatedComment {
+                let mut end = trivia.span.start;
+                end.col += 2;
+                end.abs += 2;
+                self.diagnostic
+                    .push("unterminated block comment", Span::new(trivia.span.start, end));
+            }
+        }
         self.span = prev.span;
         prev.kind
     }
@@ -172,7 +319,7 @@ impl<'s> Parser<'s> {
         if !self.bump_if(&TokenKind::LSquare) {
             return self.error(ErrorKind::ExpectedToken(TokenKind::LSquare));
         }
-        let ty = self.ty()?;
+        let ty = self.recover(|p| p.ty(), TokenKind::RSquare, Type::Error);
         self.expect(TokenKind::RSquare)?;
         Ok(ty)
     }
@@ -191,25 +338,16 @@ impl<'s> Parser<'s> {
                 self.bump();
                 Ok(Type::Unit)
             }
+            TokenKind::TyString => {
+                self.bump();
+                Ok(Type::String)
+            }
             TokenKind::LParen => {
                 self.bump();
                 let r = self.ty()?;
                 self.expect(TokenKind::RParen)?;
                 Ok(r)
             }
-            TokenKind::Forall => {
-                self.bump();
-                Ok(Type::Universal(Box::new(self.ty()?)))
-            }
-            TokenKind::Exists => {
-                self.bump();
-                let tvar = self.uppercase_id()?;
-                self.expect(TokenKind::Proj)?;
-                self.tyvar.push(tvar);
-                let xs = Type::Existential(Box::new(self.ty()?));
-                self.tyvar.pop();
-                Ok(xs)
-            }
             TokenKind::Uppercase(_) => {
                 let ty = self.uppercase_id()?;
                 match self.tyvar.lookup(&ty) {
@@ -219,6 +357,9 @@ impl<'s> Parser<'s> {
             }
             TokenKind::LBrace => {
                 self.bump();
+                if self.bump_if(&TokenKind::Exists) {
+                    return self.ty_package();
+                }
                 let fields = self.once_or_more(|p| p.ty_variant(), TokenKind::Bar)?;
                 self.expect(TokenKind::RBrace)?;
                 Ok(Type::Variant(fields))
@@ -242,57 +383,129 @@ impl<'s> Parser<'s> {
         }
     }
 
-    pub fn ty(&mut self) -> Result<Type, Error> {
-        if self.bump_if(&TokenKind::Rec) {
-            let name = self.uppercase_id()?;
-            self.expect(TokenKind::Equals)?;
-            self.tyvar.push(name);
-            let ty = self.ty()?;
-            self.tyvar.pop();
-            return Ok(Type::Rec(Box::new(ty)));
+    /// Parse the rest of an existential type written `{exists X, T}` or
+    /// `{∃X, T}`, which is the same as `exists X. T`
+    fn ty_package(&mut self) -> Result<Type, Error> {
+        let tvar = self.uppercase_id()?;
+        self.expect(TokenKind::Comma)?;
+        self.tyvar.push(tvar);
+        let ty = self.ty();
+        self.tyvar.pop();
+        let ty = Type::Existential(Box::new(ty?));
+        self.expect(TokenKind::RBrace)?;
+        Ok(ty)
+    }
+
+    /// Parse `forall X. T`, `exists X. T` or `rec X = T`. The body extends
+    /// as far right as possible
+    fn ty_binder(&mut self) -> Result<Type, Error> {
+        let binder = self.bump();
+        let tvar = self.uppercase_id()?;
+        match binder {
+            TokenKind::Rec => self.expect(TokenKind::Equals)?,
+            _ => self.expect(TokenKind::Proj)?,
         }
+        self.tyvar.push(tvar);
+        let body = self.ty();
+        self.tyvar.pop();
+        let body = Box::new(body?);
+        Ok(match binder {
+            TokenKind::Forall => Type::Universal(body),
+            TokenKind::Exists => Type::Existential(body),
+            _ => Type::Rec(body),
+        })
+    }
 
-        let mut lhs = self.ty_tuple()?;
-        if let TokenKind::TyArrow = self.kind() {
-            self.bump();
-            while let Ok(rhs) = self.ty() {
-                lhs = Type::Arrow(Box::new(lhs), Box::new(rhs));
-                if let TokenKind::TyArrow = self.kind() {
-                    self.bump();
-                } else {
-                    break;
-                }
+    /// Precedence of an infix type operator, and whether it is right
+    /// associative
+    fn ty_infix(kind: &TokenKind) -> Option<(u8, bool)> {
+        match kind {
+            TokenKind::TyArrow => Some((1, true)),
+            TokenKind::Times => Some((2, false)),
+            _ => None,
+        }
+    }
+
+    /// Parse a type whose infix operators have a precedence of at least
+    /// `min`, by precedence climbing
+    fn ty_prec(&mut self, min: u8) -> Result<Type, Error> {
+        let mut lhs = match self.kind() {
+            TokenKind::Forall | TokenKind::Exists | TokenKind::Rec => return self.ty_binder(),
+            _ => self.ty_tuple()?,
+        };
+        while let Some((prec, right)) = Parser::ty_infix(self.kind()) {
+            if prec < min {
+                break;
             }
+            let op = self.bump();
+            let next = if right { prec } else { prec + 1 };
+            let message = format!("expected a type after {:?}", op);
+            let rhs = self.once(|p| p.ty_prec(next), &message)?;
+            lhs = match op {
+                TokenKind::TyArrow => Type::Arrow(Box::new(lhs), Box::new(rhs)),
+                // `A × B × C` is a single product of three types
+                _ => {
+                    let mut tys = vec![lhs, rhs];
+                    while self.bump_if(&op) {
+                        tys.push(self.once(|p| p.ty_prec(next), &message)?);
+                    }
+                    Type::Product(tys)
+                }
+            };
         }
         Ok(lhs)
     }
 
-    fn tyabs(&mut self) -> Result<Term, Error> {
+    /// Parse a type. From loosest to tightest:
+    ///
+    /// - `forall X. T`, `exists X. T` and `rec X = T` extend as far right as
+    ///   possible, wherever they appear
+    /// - `T -> U` is right associative, so `A -> B -> C` is `A -> (B -> C)`
+    /// - `T × U` is a product, the same as `(T, U)`
+    /// - atoms: base types, variables, aliases, variants and parentheses
+    pub fn ty(&mut self) -> Result<Type, Error> {
+        self.ty_prec(0)
+    }
+
+    /// Parse a type abstraction, `\X t` or `ΛX. t`, where the dot is
+    /// optional
+    /// Parse the rest of a type abstraction, whose lambda has the span
+    /// `start`
+    fn tyabs(&mut self, start: Span) -> Result<Term, Error> {
         let tyvar = self.uppercase_id()?;
-        let sp = self.span;
-        let ty = Box::new(Type::Var(self.tyvar.push(tyvar)));
-        let body = self.once(|p| p.parse(), "abstraction body required")?;
-        Ok(Term::new(Kind::TyAbs(Box::new(body)), sp + self.span))
+        self.bump_if(&TokenKind::Proj);
+        self.tyvar.push(tyvar);
+        let body = self.once(|p| p.term(), "abstraction body required")?;
+        self.tyvar.pop();
+        Ok(Term::new(Kind::TyAbs(Box::new(body)), start + self.span))
     }
 
-    fn tmabs(&mut self) -> Result<Term, Error> {
+    /// Parse the rest of a lambda abstraction, whose lambda has the span
+    /// `start`
+    fn tmabs(&mut self, start: Span) -> Result<Term, Error> {
         let tmvar = self.lowercase_id()?;
-        let sp = self.span;
         self.tmvar.push(tmvar);
 
-        self.expect(TokenKind::Colon)?;
-        let ty = self.once(|p| p.ty(), "type annotation required in abstraction")?;
-        self.expect(TokenKind::Proj)?;
-        let body = self.once(|p| p.parse(), "abstraction body required")?;
+        // An unannotated binder gets a hole, to be filled in by type inference
+        let ty = match self.bump_if(&TokenKind::Proj) {
+            true => Type::Meta(0),
+            false => {
+                self.expect(TokenKind::Colon)?;
+                let ty = self.once(|p| p.ty(), "type annotation required in abstraction")?;
+                self.expect(TokenKind::Proj)?;
+                ty
+            }
+        };
+        let body = self.once(|p| p.term(), "abstraction body required")?;
         self.tmvar.pop();
-        Ok(Term::new(Kind::Abs(Box::new(ty), Box::new(body)), sp + self.span))
+        Ok(Term::new(Kind::Abs(Box::new(ty), Box::new(body)), start + self.span))
     }
 
     fn fold(&mut self) -> Result<Term, Error> {
         self.expect(TokenKind::Fold)?;
         let sp = self.span;
         let ty = self.once(|p| p.ty(), "type annotation required after `fold`")?;
-        let tm = self.once(|p| p.parse(), "term required after `fold`")?;
+        let tm = self.once(|p| p.term(), "term required after `fold`")?;
         Ok(Term::new(Kind::Fold(Box::new(ty), Box::new(tm)), sp + self.span))
     }
 
@@ -300,31 +513,49 @@ impl<'s> Parser<'s> {
         self.expect(TokenKind::Unfold)?;
         let sp = self.span;
         let ty = self.once(|p| p.ty(), "type annotation required after `unfold`")?;
-        let tm = self.once(|p| p.parse(), "term required after `unfold`")?;
+        let tm = self.once(|p| p.term(), "term required after `unfold`")?;
         Ok(Term::new(Kind::Unfold(Box::new(ty), Box::new(tm)), sp + self.span))
     }
 
-    fn fix(&mut self) -> Result<Term, Error> {
+    fn raise(&mut self) -> Result<Term, Error> {
+        self.expect(TokenKind::Raise)?;
         let sp = self.span;
+        let ty = self.once(|p| p.ty(), "type annotation required after `raise`")?;
+        let tm = self.once(|p| p.term(), "term required after `raise`")?;
+        Ok(Term::new(Kind::Raise(Box::new(ty), Box::new(tm)), sp + self.span))
+    }
+
+    fn fix(&mut self) -> Result<Term, Error> {
         self.expect(TokenKind::Fix)?;
-        let t = self.parse()?;
+        let sp = self.span;
+        let t = self.term()?;
         Ok(Term::new(Kind::Fix(Box::new(t)), sp + self.span))
     }
 
     fn letexpr(&mut self) -> Result<Term, Error> {
-        let sp = self.span;
         self.expect(TokenKind::Let)?;
-        let mut pat = self.once(|p| p.pattern(), "missing pattern")?;
+        let sp = self.span;
+        if self.syntax == Syntax::Ml && self.bump_if(&TokenKind::Rec) {
+            return self.letrec_group(sp);
+        }
+        let pat = self.once(|p| p.pattern(), "missing pattern")?;
+        let t1 = self.let_bound()?;
+        self.let_body(sp, pat, t1)
+    }
 
+    /// Parse `= t1` in a let, after the pattern
+    fn let_bound(&mut self) -> Result<Term, Error> {
         self.expect(TokenKind::Equals)?;
+        self.once(|p| p.term(), "let binder required")
+    }
 
-        let t1 = self.once(|p| p.parse(), "let binder required")?;
+    fn let_body(&mut self, sp: Span, mut pat: Pattern, t1: Term) -> Result<Term, Error> {
         let len = self.tmvar.len();
         for var in PatVarStack::collect(&mut pat).into_iter().rev() {
             self.tmvar.push(var);
         }
         self.expect(TokenKind::In)?;
-        let t2 = self.once(|p| p.parse(), "let body required")?;
+        let t2 = self.once(|p| p.term(), "let body required")?;
         while self.tmvar.len() > len {
             self.tmvar.pop();
         }
@@ -334,11 +565,98 @@ impl<'s> Parser<'s> {
         ))
     }
 
+    /// Scan ahead for the names bound by a `letrec` group, so that every
+    /// binding body can refer to all of the names in the group. The current
+    /// token should be the first binder, directly after `letrec`
+    fn letrec_names(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        if let TokenKind::Lowercase(s) = self.kind() {
+            names.push(s.clone());
+        }
+
+        let mut lexer = self.lexer.clone();
+        let mut depth = 0;
+        loop {
+            match lexer.lex().kind {
+                TokenKind::Let | TokenKind::LetRec | TokenKind::Unpack => depth += 1,
+                TokenKind::In if depth == 0 => break,
+                TokenKind::In => depth -= 1,
+                TokenKind::And if depth == 0 => {
+                    if let TokenKind::Lowercase(s) = lexer.lex().kind {
+                        names.push(s);
+                    }
+                }
+                TokenKind::Eof => break,
+                _ => {}
+            }
+        }
+        names
+    }
+
+    fn letrec_binding(&mut self) -> Result<(Type, Term), Error> {
+        self.lowercase_id()?;
+        self.expect(TokenKind::Colon)?;
+        let ty = self.once(|p| p.ty(), "type annotation required in letrec binding")?;
+        self.expect(TokenKind::Equals)?;
+        let tm = self.once(|p| p.term(), "letrec binder required")?;
+        Ok((ty, tm))
+    }
+
+    /// Parse a group of mutually recursive bindings of the form
+    /// `letrec f1: T1 = t1 and f2: T2 = t2 in body`
+    ///
+    /// This is a derived form, which is desugared into
+    /// `let (f1, f2) = fix (λfs: (T1, T2). (t1', t2')) in body`,
+    /// where every reference to `fi` inside of the binding bodies has been
+    /// replaced by the projection `fs.i`
+    fn letrec(&mut self) -> Result<Term, Error> {
+        self.expect(TokenKind::LetRec)?;
+        self.letrec_group(self.span)
+    }
+
+    /// Parse the bindings and body of a `letrec`, whose keyword has the span
+    /// `sp`. In the ML syntax, the keyword is `let rec`
+    fn letrec_group(&mut self, sp: Span) -> Result<Term, Error> {
+        let names = self.letrec_names();
+        let len = self.tmvar.len();
+        for var in names.iter().rev() {
+            self.tmvar.push(var.clone());
+        }
+
+        let bindings = self.once_or_more(|p| p.letrec_binding(), TokenKind::And)?;
+        // The terms the group desugars into span its bindings
+        let fix_sp = sp + self.span;
+        self.expect(TokenKind::In)?;
+        let body = self.once(|p| p.term(), "letrec body required")?;
+        while self.tmvar.len() > len {
+            self.tmvar.pop();
+        }
+
+        let mut tys = Vec::with_capacity(bindings.len());
+        let mut terms = Vec::with_capacity(bindings.len());
+        for (ty, mut tm) in bindings {
+            crate::terms::visit::ProjectBinders::new(names.len()).visit(&mut tm);
+            tys.push(ty);
+            terms.push(tm);
+        }
+
+        let product = Term::new(Kind::Product(terms), fix_sp);
+        let abs = Term::new(Kind::Abs(Box::new(Type::Product(tys)), Box::new(product)), fix_sp);
+        let fix = Term::new(Kind::Fix(Box::new(abs)), fix_sp);
+        let pat = Pattern::Product(names.into_iter().map(Pattern::Variable).collect());
+
+        Ok(Term::new(
+            Kind::Let(Box::new(pat), Box::new(fix), Box::new(body)),
+            sp + self.span,
+        ))
+    }
+
     fn lambda(&mut self) -> Result<Term, Error> {
         self.expect(TokenKind::Lambda)?;
+        let start = self.span;
         match self.kind() {
-            TokenKind::Uppercase(_) => self.tyabs(),
-            TokenKind::Lowercase(_) => self.tmabs(),
+            TokenKind::Uppercase(_) => self.tyabs(start),
+            TokenKind::Lowercase(_) => self.tmabs(start),
             _ => {
                 self.diagnostic
                     .push("expected identifier after lambda, found".to_string(), self.span);
@@ -350,7 +668,16 @@ impl<'s> Parser<'s> {
     fn paren(&mut self) -> Result<Term, Error> {
         self.expect(TokenKind::LParen)?;
         let span = self.span;
-        let mut n = self.once_or_more(|p| p.parse(), TokenKind::Comma)?;
+        // `()` is another way of writing `unit`
+        if self.bump_if(&TokenKind::RParen) {
+            return Ok(Term::new(Kind::Lit(Literal::Unit), span + self.span));
+        }
+        let placeholder = Term::new(Kind::Lit(Literal::Unit), span);
+        let mut n = self.recover(
+            |p| p.once_or_more(|p| p.term(), TokenKind::Comma),
+            TokenKind::RParen,
+            vec![placeholder],
+        );
         self.expect(TokenKind::RParen)?;
         if n.len() > 1 {
             Ok(Term::new(Kind::Product(n), span + self.span))
@@ -388,16 +715,35 @@ impl<'s> Parser<'s> {
             TokenKind::True => Literal::Bool(true),
             TokenKind::False => Literal::Bool(false),
             TokenKind::Unit => Literal::Unit,
+            TokenKind::Str(s) => Literal::String(s),
+            TokenKind::InvalidNat(n) => return self.invalid_nat(&n),
             _ => return self.error(ErrorKind::Unknown),
         };
         Ok(Term::new(Kind::Lit(lit), self.span))
     }
 
+    /// Report a number, just bumped, that the lexer could not make a Nat
+    fn invalid_nat<T>(&mut self, n: &str) -> Result<T, Error> {
+        let msg = match n.starts_with('-') {
+            true => "Nat literals cannot be negative; there is no integer type".to_string(),
+            false => format!("Nat literal {} is too large, the largest is {}", n, u32::MAX),
+        };
+        self.diagnostic.push(msg, self.span);
+        self.error(ErrorKind::ExpectedAtom)
+    }
+
     fn primitive(&mut self) -> Result<Term, Error> {
         let p = match self.bump() {
             TokenKind::IsZero => Primitive::IsZero,
             TokenKind::Succ => Primitive::Succ,
             TokenKind::Pred => Primitive::Pred,
+            TokenKind::Concat => Primitive::Concat,
+            TokenKind::StrLen => Primitive::StrLen,
+            TokenKind::Add => Primitive::Add,
+            TokenKind::Sub => Primitive::Sub,
+            TokenKind::Mul => Primitive::Mul,
+            TokenKind::Eq => Primitive::Eq,
+            TokenKind::Lt => Primitive::Lt,
             _ => return self.error(ErrorKind::Unknown),
         };
         Ok(Term::new(Kind::Primitive(p), self.span))
@@ -423,7 +769,7 @@ impl<'s> Parser<'s> {
             }
             TokenKind::Lowercase(_) => {
                 let var = self.lowercase_id()?;
-                // self.tmvar.push(var.clone());
+                self.binders.push(self.span);
                 Ok(Pattern::Variable(var))
             }
             TokenKind::True => {
@@ -438,12 +784,25 @@ impl<'s> Parser<'s> {
                 self.bump();
                 Ok(Pattern::Literal(Literal::Unit))
             }
+            TokenKind::Succ => {
+                self.bump();
+                let pat = self.once(|p| p.pattern(), "pattern required after `succ`")?;
+                Ok(Pattern::Succ(Box::new(pat)))
+            }
             TokenKind::Nat(n) => {
                 // O great borrowck, may this humble offering appease thee
                 let n = *n;
                 self.bump();
                 Ok(Pattern::Literal(Literal::Nat(n)))
             }
+            TokenKind::InvalidNat(_) => match self.bump() {
+                TokenKind::InvalidNat(n) => self.invalid_nat(&n),
+                _ => unreachable!(),
+            },
+            TokenKind::Str(_) => match self.bump() {
+                TokenKind::Str(s) => Ok(Pattern::Literal(Literal::String(s))),
+                _ => unreachable!(),
+            },
             _ => self.error(ErrorKind::ExpectedPattern),
         }
     }
@@ -452,7 +811,10 @@ impl<'s> Parser<'s> {
         match self.kind() {
             TokenKind::LParen => {
                 self.bump();
-                let mut v = self.once_or_more(|p| p.pat_atom(), TokenKind::Comma)?;
+                if self.bump_if(&TokenKind::RParen) {
+                    return Ok(Pattern::Literal(Literal::Unit));
+                }
+                let mut v = self.once_or_more(|p| p.or_pattern(), TokenKind::Comma)?;
                 self.expect(TokenKind::RParen)?;
                 if v.len() > 1 {
                     Ok(Pattern::Product(v))
@@ -466,6 +828,23 @@ impl<'s> Parser<'s> {
         }
     }
 
+    /// Parse a pattern with any number of alternatives, `p1 | p2 | p3`,
+    /// optionally binding the whole value with `as x`
+    fn or_pattern(&mut self) -> Result<Pattern, Error> {
+        let mut alts = self.once_or_more(|p| p.pattern(), TokenKind::Bar)?;
+        let mut pat = if alts.len() > 1 {
+            Pattern::Or(alts)
+        } else {
+            alts.remove(0)
+        };
+        while self.bump_if(&TokenKind::As) {
+            let var = self.once(|p| p.lowercase_id(), "expected a variable name after `as`")?;
+            self.binders.push(self.span);
+            pat = Pattern::As(Box::new(pat), var);
+        }
+        Ok(pat)
+    }
+
     fn case_arm(&mut self) -> Result<Arm, Error> {
         // match self.kind() {
         //     TokenKind::Bar => self.bump(),
@@ -479,12 +858,19 @@ impl<'s> Parser<'s> {
         let len = self.tmvar.len();
         let mut span = self.span;
 
-        let mut pat = self.once(|p| p.pattern(), "missing pattern")?;
+        self.binders.clear();
+        let mut pat = self.once(|p| p.or_pattern(), "missing pattern")?;
+        let binders = std::mem::take(&mut self.binders);
 
         for var in PatVarStack::collect(&mut pat).into_iter().rev() {
             self.tmvar.push(var);
         }
 
+        let guard = match self.bump_if(&TokenKind::When) {
+            true => Some(Box::new(self.once(|p| p.application(), "missing guard expression")?)),
+            false => None,
+        };
+
         self.expect(TokenKind::Equals)?;
         self.expect(TokenKind::Gt)?;
 
@@ -499,44 +885,106 @@ impl<'s> Parser<'s> {
 
         span = span + self.span;
 
-        Ok(Arm { span, pat, term })
+        Ok(Arm {
+            span,
+            pat,
+            binders,
+            guard,
+            term,
+        })
+    }
+
+    /// Parse a conditional, `if t1 then t2 else t3`, which is desugared into
+    /// a case expression over `Bool`. Each arm takes the span of its branch,
+    /// so that type errors point at the original branches
+    fn ifexpr(&mut self) -> Result<Term, Error> {
+        self.expect(TokenKind::If)?;
+        let span = self.span;
+        let cond = self.once(|p| p.term(), "missing condition")?;
+        self.expect(TokenKind::Then)?;
+        let t2 = self.once(|p| p.term(), "missing then branch")?;
+        self.expect(TokenKind::Else)?;
+        let t3 = self.once(|p| p.term(), "missing else branch")?;
+
+        let arm = |lit, term: Term| Arm {
+            span: term.span,
+            pat: Pattern::Literal(Literal::Bool(lit)),
+            binders: Vec::new(),
+            guard: None,
+            term: Box::new(term),
+        };
+        let arms = vec![arm(true, t2), arm(false, t3)];
+        Ok(Term::new(Kind::Case(Box::new(cond), arms), span + self.span))
     }
 
     fn case(&mut self) -> Result<Term, Error> {
         self.expect(TokenKind::Case)?;
         let span = self.span;
-        let expr = self.once(|p| p.parse(), "missing case expression")?;
+        let expr = self.once(|p| p.term(), "missing case expression")?;
         self.expect(TokenKind::Of)?;
 
         self.bump_if(&TokenKind::Bar);
-        let arms = self.once_or_more(|p| p.case_arm(), TokenKind::Bar)?;
+        // A broken arm is left out, and parsing goes on from the next one
+        let mut arms = Vec::new();
+        loop {
+            let arm = self.recover(|p| p.case_arm().map(Some), TokenKind::Bar, None);
+            arms.extend(arm);
+            if !self.bump_if(&TokenKind::Bar) {
+                break;
+            }
+        }
+        if arms.is_empty() {
+            return self.error(ErrorKind::ExpectedPattern);
+        }
 
         Ok(Term::new(Kind::Case(Box::new(expr), arms), span + self.span))
     }
 
+    /// Parse an injection into a variant type, `Label term of Type`
+    ///
+    /// Constructors carrying a `Unit` payload can be written as a bare label,
+    /// `Label of Type`, in which case the payload is recorded as elided, see
+    /// [`Term::elided`]. The annotation may be left off where the variant type can
+    /// be inferred, in which case it is parsed as a hole
     fn injection(&mut self) -> Result<Term, Error> {
         let label = self.uppercase_id()?;
         let sp = self.span;
-        let term = match self.parse() {
-            Ok(t) => t,
-            _ => Term::new(Kind::Lit(Literal::Unit), self.span),
+        let term = match self.starts_term() {
+            true => self.term()?,
+            false => Term::elided(sp),
         };
 
-        self.expect(TokenKind::Of)?;
-        let ty = self.ty()?;
+        let ty = match self.bump_if(&TokenKind::Of) {
+            true => self.ty()?,
+            false => Type::Hole,
+        };
         Ok(Term::new(
             Kind::Injection(label, Box::new(term), Box::new(ty)),
             sp + self.span,
         ))
     }
 
+    /// Parse a package of form:
+    /// pack = `pack` `{` type `,` term `}` `as` type
+    ///      | `pack` type `,` term `as` type
+    ///
+    /// A variant type must be parenthesized to be the witness of the second
+    /// form
     fn pack(&mut self) -> Result<Term, Error> {
         self.expect(TokenKind::Pack)?;
         let sp = self.span;
+        let braced = self.bump_if(&TokenKind::LBrace);
         let witness = self.ty()?;
         self.expect(TokenKind::Comma)?;
-        let evidence = self.parse()?;
-        self.expect(TokenKind::As)?;
+        let evidence = self.term()?;
+        if braced {
+            self.expect(TokenKind::RBrace)?;
+        }
+        if !self.bump_if(&TokenKind::As) {
+            self.diagnostic
+                .push("expected `as` and the type of the package", self.span);
+            return self.error(ErrorKind::ExpectedToken(TokenKind::As));
+        }
         let signature = self.ty()?;
 
         Ok(Term::new(
@@ -545,19 +993,33 @@ impl<'s> Parser<'s> {
         ))
     }
 
+    /// Parse the unpacking of a package, of form:
+    /// unpack = `unpack` `{` Uppercase `,` lowercase `}` `=` term `in` term
+    ///        | `unpack` term `as` Uppercase `,` lowercase `in` term
     fn unpack(&mut self) -> Result<Term, Error> {
         self.expect(TokenKind::Unpack)?;
         let sp = self.span;
-        let package = self.parse()?;
-        self.expect(TokenKind::As)?;
-
-        let tyvar = self.uppercase_id()?;
-        self.expect(TokenKind::Comma)?;
-        let name = self.lowercase_id()?;
+        let (package, tyvar, name) = match self.bump_if(&TokenKind::LBrace) {
+            true => {
+                let tyvar = self.uppercase_id()?;
+                self.expect(TokenKind::Comma)?;
+                let name = self.lowercase_id()?;
+                self.expect(TokenKind::RBrace)?;
+                self.expect(TokenKind::Equals)?;
+                (self.term()?, tyvar, name)
+            }
+            false => {
+                let package = self.term()?;
+                self.expect(TokenKind::As)?;
+                let tyvar = self.uppercase_id()?;
+                self.expect(TokenKind::Comma)?;
+                (package, tyvar, self.lowercase_id()?)
+            }
+        };
         self.tyvar.push(tyvar);
         self.tmvar.push(name);
         self.expect(TokenKind::In)?;
-        let expr = self.parse()?;
+        let expr = self.term()?;
         self.tmvar.pop();
         self.tyvar.pop();
         Ok(Term::new(
@@ -572,9 +1034,19 @@ impl<'s> Parser<'s> {
             TokenKind::Fix => self.fix(),
             TokenKind::Fold => self.fold(),
             TokenKind::Unfold => self.unfold(),
+            TokenKind::Raise => self.raise(),
             TokenKind::Pack => self.pack(),
             TokenKind::Unpack => self.unpack(),
-            TokenKind::IsZero | TokenKind::Succ | TokenKind::Pred => self.primitive(),
+            TokenKind::IsZero
+            | TokenKind::Succ
+            | TokenKind::Pred
+            | TokenKind::Concat
+            | TokenKind::StrLen
+            | TokenKind::Add
+            | TokenKind::Sub
+            | TokenKind::Mul
+            | TokenKind::Eq
+            | TokenKind::Lt => self.primitive(),
             TokenKind::Uppercase(_) => self.injection(),
             TokenKind::Lowercase(s) => {
                 let var = self.lowercase_id()?;
@@ -586,22 +1058,29 @@ impl<'s> Parser<'s> {
                     }
                 }
             }
-            TokenKind::Nat(_) | TokenKind::True | TokenKind::False | TokenKind::Unit => self.literal(),
+            TokenKind::Nat(_)
+            | TokenKind::InvalidNat(_)
+            | TokenKind::Str(_)
+            | TokenKind::True
+            | TokenKind::False
+            | TokenKind::Unit => self.literal(),
             TokenKind::Eof => self.error(ErrorKind::Eof),
-            TokenKind::Semicolon => {
-                self.bump();
-                self.error(ErrorKind::ExpectedAtom)
-            }
             _ => self.error(ErrorKind::ExpectedAtom),
         }
     }
 
     /// Parse a term of form:
-    /// projection = atom `.` projection
+    /// projection = projection `.` Nat
     /// projection = atom
+    ///
+    /// Projections chain to the left, so `t.0.2` is `(t.0).2`, and each one
+    /// spans both its receiver and its index
     fn projection(&mut self) -> Result<Term, Error> {
-        let atom = self.atom()?;
-        if self.bump_if(&TokenKind::Proj) {
+        // A parenthesized receiver keeps the span of the term inside the
+        // parentheses, so start from the first token instead
+        let start = self.token.span;
+        let mut atom = self.atom()?;
+        while self.bump_if(&TokenKind::Proj) {
             let idx = match self.bump() {
                 TokenKind::Nat(idx) => idx,
                 _ => {
@@ -610,21 +1089,28 @@ impl<'s> Parser<'s> {
                     return self.error(ErrorKind::ExpectedToken(TokenKind::Proj));
                 }
             };
-            let sp = atom.span + self.span;
-            Ok(Term::new(Kind::Projection(Box::new(atom), idx as usize), sp))
-        } else {
-            Ok(atom)
+            let sp = start + self.span;
+            atom = Term::new(Kind::Projection(Box::new(atom), idx as usize), sp);
         }
+        Ok(atom)
     }
 
     /// Parse an application of form:
-    /// application = atom application' | atom
-    /// application' = atom application' | empty
+    /// application = projection application' | projection
+    /// application' = projection application' | `[` type `]` application'
+    ///              | binder | empty
+    ///
+    /// Term and type application bind equally tightly, and associate to the
+    /// left together, so `f [Nat] x [Bool]` is `((f [Nat]) x) [Bool]`. A
+    /// lambda, `case`, `if` or `let` may be the last argument, as in
+    /// `f \x: Nat. x`
     fn application(&mut self) -> Result<Term, Error> {
+        // As for projections, start from the first token, in case it opens
+        // parentheses
+        let sp = self.token.span;
         let mut app = self.projection()?;
 
         loop {
-            let sp = app.span;
             if let Ok(ty) = self.ty_app() {
                 // Full type inference for System F is undecidable
                 // Additionally, even partial type reconstruction,
@@ -640,6 +1126,12 @@ impl<'s> Parser<'s> {
                 app = Term::new(Kind::TyApp(Box::new(app), Box::new(ty)), sp + self.span);
             } else if let Ok(term) = self.projection() {
                 app = Term::new(Kind::App(Box::new(app), Box::new(term)), sp + self.span);
+            } else if self.binder_argument() {
+                // These extend as far right as possible, so one can only be
+                // the last argument
+                let term = self.term()?;
+                app = Term::new(Kind::App(Box::new(app), Box::new(term)), sp + self.span);
+                break;
             } else {
                 break;
             }
@@ -647,12 +1139,740 @@ impl<'s> Parser<'s> {
         Ok(app)
     }
 
+    /// Does a lambda, `fun`, `case`, `if` or `let` come next, which can be
+    /// the last argument of an application? A `let` at the start of a line
+    /// begins the next item instead
+    fn binder_argument(&self) -> bool {
+        match self.kind() {
+            TokenKind::Lambda | TokenKind::Fun | TokenKind::Case | TokenKind::If => true,
+            TokenKind::Let | TokenKind::LetRec => self.token.span.start.col != 0,
+            _ => false,
+        }
+    }
+
+    /// Can the current token begin a term? A `let` at the start of a line
+    /// begins the next top-level definition instead
+    fn starts_term(&self) -> bool {
+        match self.kind() {
+            TokenKind::LParen
+            | TokenKind::Fix
+            | TokenKind::Fold
+            | TokenKind::Unfold
+            | TokenKind::Raise
+            | TokenKind::Pack
+            | TokenKind::Unpack
+            | TokenKind::IsZero
+            | TokenKind::Succ
+            | TokenKind::Pred
+            | TokenKind::Concat
+            | TokenKind::StrLen
+            | TokenKind::Add
+            | TokenKind::Sub
+            | TokenKind::Mul
+            | TokenKind::Eq
+            | TokenKind::Lt
+            | TokenKind::Uppercase(_)
+            | TokenKind::Lowercase(_)
+            | TokenKind::Nat(_)
+            | TokenKind::InvalidNat(_)
+            | TokenKind::Str(_)
+            | TokenKind::True
+            | TokenKind::False
+            | TokenKind::Unit => true,
+            _ => self.binder_argument(),
+        }
+    }
+
+    /// Parse a type alias declaration of form:
+    /// declaration = `type` alias `;`
+    fn type_decl(&mut self) -> Result<Item, Error> {
+        let start = self.token.span;
+        self.expect(TokenKind::Type)?;
+        let (name, ty) = self.alias_body()?;
+        self.end_item()?;
+        Ok(Item::Type(name, ty, start + self.span))
+    }
+
+    /// Parse an alias of form:
+    /// alias = Uppercase `=` type
+    fn alias_body(&mut self) -> Result<(String, Type), Error> {
+        let name = self.uppercase_id()?;
+        self.expect(TokenKind::Equals)?;
+        let ty = self.once(|p| p.ty(), "expected a type")?;
+        Ok((name, ty))
+    }
+
+    /// Parse the whole input as an alias, `T = type`, without the `type`
+    /// keyword in front of it
+    pub fn alias(&mut self) -> Result<Item, Error> {
+        let start = self.token.span;
+        let (name, ty) = self.alias_body()?;
+        self.end_item()?;
+        Ok(Item::Type(name, ty, start + self.span))
+    }
+
+    /// Expect the semicolon after a declaration, which may be left off at
+    /// the end of the input
+    fn end_item(&mut self) -> Result<(), Error> {
+        match self.kind() {
+            TokenKind::Eof => Ok(()),
+            _ => self.expect(TokenKind::Semicolon),
+        }
+    }
+
+    /// Parse a top-level let declaration of form:
+    /// declaration = `let` lowercase `=` term `;`
+    ///
+    /// The variable stays bound for the rest of the input. A let followed by
+    /// `in` is an ordinary term instead, and one at the end of the input
+    /// needs no semicolon
+    fn let_decl(&mut self) -> Result<Item, Error> {
+        let (sp, start) = (self.span, self.token.span);
+        self.expect(TokenKind::Let)?;
+        if self.syntax == Syntax::Ml && self.bump_if(&TokenKind::Rec) {
+            return self.letrec_group(start).map(Item::Term);
+        }
+        let pat = self.once(|p| p.pattern(), "missing pattern")?;
+        let reported = self.diagnostic.error_count();
+        let t1 = match self.let_bound() {
+            Ok(t1) => t1,
+            // Assume that this was a declaration, and keep its variable bound
+            Err(e) => {
+                self.fail(e, reported);
+                self.skip(&[]);
+                self.bump_if(&TokenKind::Semicolon);
+                let name = match pat {
+                    Pattern::Variable(name) => Some(name),
+                    _ => None,
+                };
+                if let Some(name) = &name {
+                    self.tmvar.push(name.clone());
+                }
+                return Ok(Item::Invalid(name, start + self.span));
+            }
+        };
+        if self.kind() != &TokenKind::Eof && !self.bump_if(&TokenKind::Semicolon) {
+            return self.let_body(sp, pat, t1).map(Item::Term);
+        }
+        match pat {
+            Pattern::Variable(name) => {
+                self.tmvar.push(name.clone());
+                Ok(Item::Let(name, t1, start + self.span))
+            }
+            _ => {
+                self.diagnostic
+                    .push("a top-level let must bind a single variable", start + self.span);
+                self.error(ErrorKind::ExpectedIdent)
+            }
+        }
+    }
+
+    /// Parse an import directive of form:
+    /// import = `import` String `;`
+    fn import(&mut self) -> Result<Item, Error> {
+        let start = self.token.span;
+        self.expect(TokenKind::Import)?;
+        let path = match self.bump() {
+            TokenKind::Str(path) => path,
+            tk => {
+                self.diagnostic.push(
+                    format!("expected a file name after `import`, found {:?}", tk),
+                    self.span,
+                );
+                return self.error(ErrorKind::ExpectedToken(TokenKind::Str(String::new())));
+            }
+        };
+        self.end_item()?;
+        Ok(Item::Import(path, start + self.span))
+    }
+
+    /// Parse the import directives at the start of the input, up to the
+    /// first other item. Errors are left for [`Parser::item`] to report
+    pub fn imports(mut self) -> Vec<(String, Span)> {
+        let mut imports = Vec::new();
+        while self.bump_if(&TokenKind::Semicolon) {}
+        while self.kind() == &TokenKind::Import {
+            match self.import() {
+                Ok(Item::Import(path, span)) => imports.push((path, span)),
+                _ => break,
+            }
+            while self.bump_if(&TokenKind::Semicolon) {}
+        }
+        let _ = self.diagnostic.emit();
+        imports
+    }
+
+    /// Parse a top-level item: an import, a type alias declaration, a let
+    /// declaration, or a term. Items are separated by semicolons.
+    ///
+    /// A syntax error is reported to the diagnostic, and then the parser
+    /// skips to the next item, returning [`Item::Invalid`] for this one. The
+    /// only error returned is [`ErrorKind::Eof`], once there are no items
+    /// left
+    pub fn item(&mut self) -> Result<Item, Error> {
+        while self.bump_if(&TokenKind::Semicolon) {}
+        let (start, reported) = (self.token.span, self.diagnostic.error_count());
+        let (tmvars, tyvars) = (self.tmvar.len(), self.tyvar.len());
+        self.recovered = false;
+        let item = match self.kind() {
+            TokenKind::Eof => return self.error(ErrorKind::Eof),
+            TokenKind::Type => self.type_decl(),
+            TokenKind::Import => self.import(),
+            TokenKind::Let => self.let_decl(),
+            _ => self.term().map(Item::Term),
+        };
+        match item {
+            Ok(item @ Item::Invalid(..)) => Ok(item),
+            Ok(item) if !self.recovered => Ok(item),
+            Ok(Item::Let(name, _, span)) => Ok(Item::Invalid(Some(name), span)),
+            Ok(_) => Ok(Item::Invalid(None, start + self.span)),
+            Err(e) => {
+                self.fail(e, reported);
+                self.tmvar.truncate(tmvars);
+                self.tyvar.truncate(tyvars);
+                // Always move on, so that an item that fails at its first
+                // token is not parsed again
+                if self.token.span == start {
+                    self.bump();
+                }
+                self.skip(&[]);
+                Ok(Item::Invalid(None, start + self.span))
+            }
+        }
+    }
+
+    /// Parse a term, skipping any semicolons separating it from the term
+    /// before it
     pub fn parse(&mut self) -> Result<Term, Error> {
+        while self.bump_if(&TokenKind::Semicolon) {}
+        self.recovered = false;
+        let term = self.term()?;
+        match self.recovered {
+            true => self.error(ErrorKind::Unknown),
+            false => Ok(term),
+        }
+    }
+
+    fn term(&mut self) -> Result<Term, Error> {
         match self.kind() {
             TokenKind::Case => self.case(),
+            TokenKind::If => self.ifexpr(),
+            TokenKind::Lambda if self.syntax == Syntax::Ml => self.fun(),
             TokenKind::Lambda => self.lambda(),
+            TokenKind::Fun => self.fun(),
             TokenKind::Let => self.letexpr(),
+            TokenKind::LetRec => self.letrec(),
             _ => self.application(),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::eval::Eval;
+
+    fn parse(input: &str) -> Term {
+        let mut p = Parser::new(input);
+        let tm = p.parse().unwrap();
+        assert_eq!(p.diagnostic().error_count(), 0);
+        tm
+    }
+
+    fn eval(ctx: &Context, term: Term) -> Term {
+        let ev = Eval::with_context(ctx);
+        let mut t = term;
+        while let Some(next) = ev.small_step(t.clone()) {
+            t = next;
+        }
+        t
+    }
+
+    #[test]
+    fn letrec_even_odd() {
+        let input = "letrec iseven: Nat->Bool = \\n: Nat. case n of | 0 => true | _ => isodd (pred n)
+                     and isodd: Nat->Bool = \\n: Nat. case n of | 0 => false | _ => iseven (pred n)
+                     in iseven 10";
+        let tm = parse(input);
+        let mut ctx = Context::default();
+        assert_eq!(ctx.type_check(&tm).unwrap(), Type::Bool);
+        assert_eq!(eval(&ctx, tm).kind, Kind::Lit(Literal::Bool(true)));
+    }
+
+    #[test]
+    fn letrec_error_names_binding() {
+        let input = "letrec f1: Nat->Bool = \\n: Nat. f2 n
+                     and f2: Nat->Bool = \\n: Nat. succ n
+                     in f1 10";
+        let tm = parse(input);
+        let mut ctx = Context::default();
+        let diag = ctx.type_check(&tm).unwrap_err();
+        let msg = format!("{} {:?}", diag.primary.info, diag.other);
+        assert!(msg.contains("f2"), "{}", msg);
+        assert!(!msg.contains(".1"), "{}", msg);
+    }
+
+    #[test]
+    fn nullary_constructors() {
+        let mut ctx = Context::default();
+        ctx.alias(
+            "Bool2".into(),
+            Type::Variant(vec![variant!("T", Type::Unit), variant!("F", Type::Unit)]),
+        )
+        .unwrap();
+
+        let tm = parse("F of Bool2");
+        assert_eq!(tm.to_string(), "F");
+        let elided = |tm: &Term| match &tm.kind {
+            Kind::Injection(_, payload, _) => payload.kind == Kind::Lit(Literal::Unit) && payload.is_elided(),
+            _ => panic!("expected an injection"),
+        };
+        assert!(elided(&tm));
+        assert!(!elided(&parse("F unit of Bool2")));
+        assert_eq!(
+            ctx.type_check(&tm).unwrap(),
+            ctx.normalize(&Type::Alias("Bool2".into()))
+        );
+
+        let tm = parse("case T of Bool2 of | T => F of Bool2 | F => T of Bool2");
+        ctx.type_check(&tm).unwrap();
+        let value = eval(&ctx, tm);
+        assert_eq!(value.to_string(), "F");
+        assert_eq!(parse(&format!("{} of Bool2", value)).kind, parse("F of Bool2").kind);
+
+        // Only constructors carrying a unit payload can be written bare
+        let tm = parse("Some of {None | Some Nat}");
+        let diag = ctx.type_check(&tm).unwrap_err();
+        assert_eq!(diag.other[0].span, tm.span);
+        assert_eq!(diag.other[0].info, "variant Some requires a payload of type `Nat`");
+    }
+
+    #[test]
+    fn strings() {
+        let mut ctx = Context::default();
+        let tm = parse(r#"strlen (concat "a\nb" "\"c\\")"#);
+        assert_eq!(ctx.type_check(&tm).unwrap(), Type::Nat);
+        assert_eq!(eval(&ctx, tm).kind, Kind::Lit(Literal::Nat(6)));
+
+        let tm = parse(r#"concat "a\nb" "\"c\\""#);
+        assert_eq!(ctx.type_check(&tm).unwrap(), Type::String);
+        let value = eval(&ctx, tm);
+        assert_eq!(value.kind, Kind::Lit(Literal::String("a\nb\"c\\".into())));
+        assert_eq!(value.to_string(), r#""a\nb\"c\\""#);
+        assert_eq!(parse(&value.to_string()).kind, value.kind);
+
+        let tm = parse(r#"\x: String. case x of | "a" => 1 | "b" => 2"#);
+        let diag = ctx.type_check(&tm).unwrap_err();
+        assert!(diag.primary.info.contains("exhaustive"));
+
+        let tm = parse(r#"case "b" of | "a" => 1 | "b" => 2 | _ => 3"#);
+        assert_eq!(ctx.type_check(&tm).unwrap(), Type::Nat);
+        assert_eq!(eval(&ctx, tm).kind, Kind::Lit(Literal::Nat(2)));
+    }
+
+    #[test]
+    fn arithmetic() {
+        let mut ctx = Context::default();
+        let tm = parse("mul 6 7");
+        assert_eq!(ctx.type_check(&tm).unwrap(), Type::Nat);
+        assert_eq!(eval(&ctx, tm).kind, Kind::Lit(Literal::Nat(42)));
+
+        let tm = parse("eq (add 1 1) 2");
+        assert_eq!(ctx.type_check(&tm).unwrap(), Type::Bool);
+        assert_eq!(eval(&ctx, tm).kind, Kind::Lit(Literal::Bool(true)));
+
+        let tm = parse("(sub 3 5, lt 3 5)");
+        assert_eq!(eval(&ctx, tm).to_string(), "(0,true)");
+
+        let tm = parse("add 1");
+        assert_eq!(ctx.type_check(&tm).unwrap(), arrow!(Type::Nat, Type::Nat));
+        assert_eq!(eval(&ctx, tm.clone()).kind, tm.kind);
+
+        assert!(ctx.type_check(&parse("add true")).is_err());
+    }
+
+    #[test]
+    fn case_guards() {
+        let mut ctx = Context::default();
+        let tm = parse("\\n: Nat. case n of | x when lt x 10 => x | _ => 10");
+        let arm = match &tm.kind {
+            Kind::Abs(_, body) => match &body.kind {
+                Kind::Case(_, arms) => arms[0].clone(),
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        };
+        // The guard sees the variables bound by the pattern
+        assert_eq!(arm.guard.unwrap().to_string(), "((Lt #0) 10)");
+        assert!(tm.to_string().contains("| Variable(\"x\") when ((Lt #0) 10) => #0,"));
+
+        let tm = parse("(\\n: Nat. case n of | x when lt x 10 => x | _ => 10) 12");
+        assert_eq!(ctx.type_check(&tm).unwrap(), Type::Nat);
+        assert_eq!(eval(&ctx, tm).kind, Kind::Lit(Literal::Nat(10)));
+    }
+
+    #[test]
+    fn if_then_else() {
+        let mut ctx = Context::default();
+        for input in &[
+            "if true then 1 else 2",
+            "if false then (1,true) else if true then (2,false) else (3,true)",
+            "if if true then false else true then unit else unit",
+        ] {
+            let tm = parse(input);
+            assert_eq!(tm.to_string(), *input);
+            assert_eq!(parse(&tm.to_string()).kind, tm.kind);
+            // Both literals of `Bool` are covered, no wildcard is needed
+            assert!(ctx.type_check(&tm).is_ok(), "{}", input);
+        }
+
+        let tm = parse("(\\n: Nat. if iszero n then 10 else n) 0");
+        assert_eq!(eval(&ctx, tm).kind, Kind::Lit(Literal::Nat(10)));
+
+        // Errors in the branches point at the original source
+        let input = "if iszero 0 then 1 else true";
+        let diag = ctx.type_check(&parse(input)).unwrap_err();
+        let span = diag.other[1].span;
+        assert_eq!(&input[span.start.abs as usize..span.end.abs as usize], "true");
+    }
+
+    #[test]
+    fn projections() {
+        let text = |input: &str, tm: &Term| input[tm.span.start.abs as usize..tm.span.end.abs as usize].to_string();
+        let body = |tm: Term| match tm.kind {
+            Kind::Abs(_, body) => *body,
+            _ => panic!("expected an abstraction"),
+        };
+
+        // Chained projections associate to the left
+        let input = "\\p: (Nat, (Bool, Nat, Nat)). p.1.2";
+        let outer = body(parse(input));
+        assert_eq!(text(input, &outer), "p.1.2");
+        let inner = match &outer.kind {
+            Kind::Projection(inner, 2) => inner,
+            _ => panic!("expected a projection, got {:?}", outer),
+        };
+        assert_eq!(text(input, inner), "p.1");
+        match &inner.kind {
+            Kind::Projection(p, 1) => assert_eq!(p.kind, Kind::Var(0)),
+            _ => panic!("expected a projection, got {:?}", inner),
+        }
+
+        // Projection binds tighter than application
+        let input = "\\f: Nat->(Nat, Nat). \\x: (Nat, Nat). (f x.1).0";
+        let tm = body(body(parse(input)));
+        assert_eq!(text(input, &tm), "(f x.1).0");
+        match &tm.kind {
+            Kind::Projection(app, 0) => match &app.kind {
+                Kind::App(f, arg) => {
+                    assert_eq!(f.kind, Kind::Var(1));
+                    assert_eq!(text(input, arg), "x.1");
+                    assert!(matches!(&arg.kind, Kind::Projection(x, 1) if x.kind == Kind::Var(0)));
+                }
+                _ => panic!("expected an application, got {:?}", app),
+            },
+            _ => panic!("expected a projection, got {:?}", tm),
+        }
+        assert_eq!(tm.to_string(), "(#1 #0.1).0");
+
+        let input = "(1, (2, 3)).7";
+        let diag = Context::default().type_check(&parse(input)).unwrap_err();
+        assert_eq!(diag.primary.info, "this projection is out of range for the product");
+        assert_eq!(
+            &input[diag.primary.span.start.abs as usize..diag.primary.span.end.abs as usize],
+            ".7"
+        );
+    }
+
+    #[test]
+    fn empty_parens() {
+        let tm = parse("case () of | () => ((), unit)");
+        assert_eq!(tm.to_string(), parse("case unit of | unit => (unit, unit)").to_string());
+    }
+
+    #[test]
+    fn type_variable_scope() {
+        // Z is out of scope in the type argument, which refers to X
+        let tm = parse("\\X \\Y (\\Z \\z: Z. z) [X]");
+        match tm.kind {
+            Kind::TyAbs(body) => match body.kind {
+                Kind::TyAbs(body) => match body.kind {
+                    Kind::TyApp(_, ty) => assert_eq!(*ty, Type::Var(1)),
+                    k => panic!("expected a type application, not {:?}", k),
+                },
+                k => panic!("expected a type abstraction, not {:?}", k),
+            },
+            k => panic!("expected a type abstraction, not {:?}", k),
+        }
+    }
+
+    /// Parse every item of the input, declaring aliases into the context,
+    /// and return the terms
+    fn items(ctx: &mut Context, input: &str) -> Result<Vec<Term>, crate::diagnostics::Diagnostic> {
+        let mut p = Parser::new(input);
+        let mut terms = Vec::new();
+        loop {
+            match p.item() {
+                Ok(Item::Type(name, ty, span)) => ctx.declare_alias(name, ty, span)?,
+                Ok(Item::Term(term)) => terms.push(term),
+                Ok(item) => panic!("unexpected item {:?}", item),
+                Err(_) => break,
+            }
+        }
+        assert_eq!(p.diagnostic().error_count(), 0);
+        Ok(terms)
+    }
+
+    #[test]
+    fn type_declarations() {
+        let mut ctx = Context::default();
+        let input = "type Pair = (Nat, Bool);
+                     type Opt = {None | Some Pair};
+                     \\x: Opt. case x of | None => (0, false) | Some p => p
+                     ;
+                     Some (1, true) of Opt";
+        let mut terms = items(&mut ctx, input).unwrap();
+        assert_eq!(terms.len(), 2);
+        let pair = Type::Product(vec![Type::Nat, Type::Bool]);
+        let opt = Type::Variant(vec![variant!("None", Type::Unit), variant!("Some", pair.clone())]);
+        for term in &mut terms {
+            ctx.de_alias(term);
+        }
+        assert_eq!(ctx.type_check(&terms[0]).unwrap(), arrow!(opt.clone(), pair));
+        assert_eq!(ctx.type_check(&terms[1]).unwrap(), opt);
+    }
+
+    #[test]
+    fn last_item_without_semicolon() {
+        let mut p = Parser::new("type N = Nat; let x = 1");
+        assert!(matches!(p.item(), Ok(Item::Type(..))));
+        assert!(matches!(p.item(), Ok(Item::Let(..))));
+        assert!(p.item().is_err());
+        assert_eq!(p.diagnostic().error_count(), 0);
+
+        let mut p = Parser::new("P = (Nat, Bool)");
+        match p.alias() {
+            Ok(Item::Type(name, ty, _)) => {
+                assert_eq!(name, "P");
+                assert_eq!(ty, Type::Product(vec![Type::Nat, Type::Bool]));
+            }
+            item => panic!("expected an alias, not {:?}", item),
+        }
+        assert_eq!(p.diagnostic().error_count(), 0);
+    }
+
+    #[test]
+    fn imports() {
+        let input = "import \"a.sf\";\nimport \"../b.sf\";\nsucc 1;\nimport \"c.sf\"";
+        let imports = Parser::new(input).imports();
+        assert_eq!(
+            imports.iter().map(|(path, _)| path.as_str()).collect::<Vec<_>>(),
+            ["a.sf", "../b.sf"]
+        );
+        assert_eq!(imports[1].1.start.line, 1);
+
+        // Every span records the file it is in
+        let mut p = Parser::with_file(input, FileId(3));
+        let mut items = Vec::new();
+        while let Ok(item) = p.item() {
+            items.push(item);
+        }
+        assert_eq!(p.diagnostic().error_count(), 0);
+        match &items[..] {
+            [Item::Import(_, a), Item::Import(..), Item::Term(tm), Item::Import(_, c)] => {
+                assert_eq!(a.file, FileId(3));
+                assert_eq!(c.file, FileId(3));
+                assert!(tm.spans().iter().all(|(span, _)| span.file == FileId(3)));
+            }
+            items => panic!("unexpected items {:?}", items),
+        }
+    }
+
+    #[test]
+    fn existentials() {
+        let ty = |input: &str| {
+            let mut p = Parser::new(input);
+            let ty = p.ty().unwrap();
+            assert_eq!(p.kind(), &TokenKind::Eof, "{}", input);
+            assert_eq!(p.diagnostic().error_count(), 0);
+            ty
+        };
+        let sig = ty("exists X. (X, X -> X)");
+        assert_eq!(ty("{exists X, (X, X -> X)}"), sig);
+        assert_eq!(ty("{∃X, (X, X -> X)}"), sig);
+        assert_eq!(ty(&sig.to_string()), sig);
+
+        let pack = "pack {Nat, (0, \\x: Nat. succ x)} as {∃X, (X, X -> X)}";
+        let forms = [
+            (
+                pack.to_string(),
+                "pack Nat, (0, \\x: Nat. succ x) as exists X. (X, X -> X)".to_string(),
+            ),
+            (
+                format!("unpack {{T, p}} = {} in p.1 p.0", pack),
+                format!("unpack {} as T, p in p.1 p.0", pack),
+            ),
+        ];
+        for (input, plain) in &forms {
+            let tm = parse(input);
+            // The span covers the whole construct
+            assert_eq!((tm.span.start.abs, tm.span.end.abs), (0, input.len() as u32));
+            let opts = crate::terms::pretty::PrintOptions::default();
+            let printed = tm.pretty(&opts);
+            assert_eq!(parse(&printed).pretty(&opts), printed, "{}", input);
+            assert_eq!(parse(plain).pretty(&opts), printed, "{}", input);
+        }
+    }
+
+    #[test]
+    fn pack_without_signature() {
+        let input = "pack {Nat, 1}\n\n";
+        let mut p = Parser::new(input);
+        assert!(p.parse().is_err());
+        let msg = p.diagnostic().emit();
+        let first = msg.lines().next().unwrap();
+        // Reported at the closing brace, rather than at the end of the input
+        assert!(first.contains("line 0, col: 12"), "{}", msg);
+        assert!(first.contains("expected `as`"), "{}", msg);
+    }
+
+    #[test]
+    fn type_redeclaration() {
+        let mut ctx = Context::default();
+        let input = "type A = Nat;\ntype A = Bool;";
+        let diag = items(&mut ctx, input).unwrap_err();
+        assert!(diag.primary.info.contains("already defined"), "{}", diag.primary.info);
+        assert_eq!(diag.primary.span.start.line, 1);
+        assert_eq!(diag.other[0].span.start.line, 0);
+        assert_eq!(ctx.normalize(&Type::Alias("A".into())), Type::Nat);
+    }
+
+    /// A type written with every operator prefix and every variable as its
+    /// index, to show how it was grouped
+    fn ty_tree(ty: &Type) -> String {
+        let list = |op: &str, tys: &[&Type]| {
+            let tys = tys.iter().map(|ty| ty_tree(ty)).collect::<Vec<_>>();
+            format!("({} {})", op, tys.join(" "))
+        };
+        match ty {
+            Type::Var(idx) => format!("#{}", idx),
+            Type::Arrow(a, b) => list("->", &[a, b]),
+            Type::Product(tys) => list("×", &tys.iter().collect::<Vec<_>>()),
+            Type::Universal(ty) => list("forall", &[ty]),
+            Type::Existential(ty) => list("exists", &[ty]),
+            Type::Rec(ty) => list("rec", &[ty]),
+            ty => ty.to_string(),
+        }
+    }
+
+    /// The same for the applications and abstractions in a term
+    fn tm_tree(tm: &Term) -> String {
+        match &tm.kind {
+            Kind::Var(idx) => format!("#{}", idx),
+            Kind::App(f, x) => format!("({} {})", tm_tree(f), tm_tree(x)),
+            Kind::TyApp(f, ty) => format!("({} [{}])", tm_tree(f), ty_tree(ty)),
+            Kind::Abs(ty, body) => format!("(\\{} {})", ty_tree(ty), tm_tree(body)),
+            Kind::TyAbs(body) => format!("(\\\\ {})", tm_tree(body)),
+            Kind::Projection(tm, idx) => format!("{}.{}", tm_tree(tm), idx),
+            _ => tm.to_string(),
+        }
+    }
+
+    #[test]
+    fn precedence() {
+        let types = [
+            ("Nat -> Nat -> Nat", "(-> Nat (-> Nat Nat))"),
+            ("(Nat -> Nat) -> Nat", "(-> (-> Nat Nat) Nat)"),
+            ("Nat × Bool -> Nat", "(-> (× Nat Bool) Nat)"),
+            ("Nat -> Nat × Bool", "(-> Nat (× Nat Bool))"),
+            ("Nat × Bool × Unit", "(× Nat Bool Unit)"),
+            ("(Nat, Bool) × Unit", "(× (× Nat Bool) Unit)"),
+            ("forall X. X -> X", "(forall (-> #0 #0))"),
+            ("(forall X. X) -> Nat", "(-> (forall #0) Nat)"),
+            ("Nat -> forall X. X -> Nat", "(-> Nat (forall (-> #0 Nat)))"),
+            ("Nat × forall X. X -> X", "(× Nat (forall (-> #0 #0)))"),
+            ("rec L = Unit -> L -> L", "(rec (-> Unit (-> #0 #0)))"),
+            ("Nat × rec L = Nat -> L", "(× Nat (rec (-> Nat #0)))"),
+            ("exists X. X × (X -> Nat)", "(exists (× #0 (-> #0 Nat)))"),
+        ];
+        for (input, expected) in &types {
+            let mut p = Parser::new(input);
+            let ty = p.ty().unwrap();
+            assert_eq!(p.kind(), &TokenKind::Eof, "{}", input);
+            assert_eq!(p.diagnostic().error_count(), 0);
+            assert_eq!(ty_tree(&ty), *expected, "{}", input);
+        }
+
+        // f, g, x and y are #3, #2, #1 and #0
+        let terms = [
+            ("f x y", "((#3 #1) #0)"),
+            ("f (g x) y", "((#3 (#2 #1)) #0)"),
+            ("f [Nat] x", "((#3 [Nat]) #1)"),
+            ("f x [Nat]", "((#3 #1) [Nat])"),
+            ("f [Nat] [Bool] x y", "((((#3 [Nat]) [Bool]) #1) #0)"),
+            ("f x.0 y", "((#3 #1.0) #0)"),
+            ("f \\x: Nat. x", "(#3 (\\Nat #0))"),
+            ("f [Nat] \\x: Nat. g x", "((#3 [Nat]) (\\Nat (#3 #0)))"),
+            ("\\X \\x: X. f [X] x", "(\\\\ (\\#0 ((#4 [#0]) #0)))"),
+            ("(\\h: Nat -> Nat. h) f", "((\\(-> Nat Nat) #0) #3)"),
+        ];
+        for (input, expected) in &terms {
+            let mut p = Parser::new(input);
+            for var in &["f", "g", "x", "y"] {
+                p.bind(var);
+            }
+            let tm = p.parse().unwrap();
+            assert_eq!(p.kind(), &TokenKind::Eof, "{}", input);
+            assert_eq!(p.diagnostic().error_count(), 0);
+            assert_eq!(tm_tree(&tm), *expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn arrow_without_result() {
+        let mut p = Parser::new("\\x: Nat -> . x");
+        assert!(p.parse().is_err());
+        let msg = p.diagnostic().emit();
+        assert!(msg.contains("expected a type after TyArrow"), "{}", msg);
+    }
+
+    #[test]
+    fn nat_literals() {
+        let mut p = Parser::new("4294967295");
+        assert_eq!(p.parse().unwrap().kind, Kind::Lit(Literal::Nat(u32::MAX)));
+
+        // An argument that fails to parse ends the application, and callers
+        // go by the errors reported
+        let mut p = Parser::new("succ 4294967296");
+        let _ = p.parse();
+        let diag = p.diagnostic();
+        assert_eq!(diag.error_count(), 1);
+        let msg = diag.emit();
+        assert!(msg.contains("Nat literal 4294967296 is too large"), "{}", msg);
+        assert!(msg.contains("line 0, col: 5"), "{}", msg);
+
+        let mut p = Parser::new("-3");
+        assert!(p.parse().is_err());
+        let msg = p.diagnostic().emit();
+        assert!(msg.contains("Nat literals cannot be negative"), "{}", msg);
+    }
+
+    #[test]
+    fn nat_literal_patterns() {
+        let mut p = Parser::new("\\n: Nat. case n of | 0 => true | 7 => true | _ => false");
+        let tm = p.parse().unwrap();
+        let arms = match &tm.kind {
+            Kind::Abs(_, body) => match &body.kind {
+                Kind::Case(_, arms) => arms,
+                k => panic!("expected a case, not {:?}", k),
+            },
+            k => panic!("expected an abstraction, not {:?}", k),
+        };
+        assert_eq!(arms[1].pat, Pattern::Literal(Literal::Nat(7)));
+
+        let mut p = Parser::new("\\n: Nat. case n of | -1 => true | _ => false");
+        assert!(p.parse().is_err());
+        let msg = p.diagnostic().emit();
+        assert!(msg.contains("Nat literals cannot be negative"), "{}", msg);
+    }
+}
@@ -0,0 +1,142 @@
+//! The productions of the ML syntax that differ from the default one
+//!
+//! Only abstractions are written differently, as `fun x: T => t` and
+//! `fun X => t`, and `letrec` is spelled `let rec`, which is handled along
+//! with `let`. Everything else is parsed by the rules of [`Parser`]
+use super::*;
+
+impl<'s> Parser<'s> {
+    /// Parse an abstraction, `fun x: T => t`, or `fun x => t` with the type
+    /// of `x` left to inference, or a type abstraction, `fun X => t`. `Λ`
+    /// may be written for `fun`. The body extends as far right as possible
+    pub(super) fn fun(&mut self) -> Result<Term, Error> {
+        let start = self.token.span;
+        self.bump();
+        match self.kind() {
+            TokenKind::Uppercase(_) => {
+                let tyvar = self.uppercase_id()?;
+                self.fat_arrow()?;
+                self.tyvar.push(tyvar);
+                let body = self.once(|p| p.term(), "abstraction body required");
+                self.tyvar.pop();
+                Ok(Term::new(Kind::TyAbs(Box::new(body?)), start + self.span))
+            }
+            TokenKind::Lowercase(_) => {
+                let tmvar = self.lowercase_id()?;
+                let ty = match self.bump_if(&TokenKind::Colon) {
+                    true => self.once(|p| p.ty(), "type annotation required in abstraction")?,
+                    false => Type::Meta(0),
+                };
+                self.fat_arrow()?;
+                self.tmvar.push(tmvar);
+                let body = self.once(|p| p.term(), "abstraction body required");
+                self.tmvar.pop();
+                Ok(Term::new(Kind::Abs(Box::new(ty), Box::new(body?)), start + self.span))
+            }
+            tk => {
+                let msg = format!("expected a variable after `fun`, found {:?}", tk);
+                self.diagnostic.push(msg, self.token.span);
+                self.error(ErrorKind::ExpectedIdent)
+            }
+        }
+    }
+
+    /// Expect the `=>` between a binder and its body
+    fn fat_arrow(&mut self) -> Result<(), Error> {
+        self.expect(TokenKind::Equals)?;
+        self.expect(TokenKind::Gt)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Forget where every part of a term came from
+    struct Unspan;
+
+    impl MutTermVisitor for Unspan {
+        fn visit(&mut self, term: &mut Term) {
+            term.span = Span::dummy();
+            if let Kind::Case(_, arms) = &mut term.kind {
+                for arm in arms {
+                    arm.span = Span::dummy();
+                    arm.binders.clear();
+                }
+            }
+            self.walk(term);
+        }
+    }
+
+    fn parse(input: &str, syntax: Syntax) -> Term {
+        let mut p = Parser::with_syntax(input, FileId::default(), syntax);
+        let tm = p.parse();
+        let diag = p.diagnostic();
+        let mut tm = match tm {
+            Ok(tm) if diag.error_count() == 0 => tm,
+            _ => panic!("{} does not parse:\n{}", input, diag.emit()),
+        };
+        Unspan.visit(&mut tm);
+        tm
+    }
+
+    #[test]
+    fn same_terms() {
+        let cases = [
+            ("\\x: Nat. succ x", "fun x: Nat => succ x"),
+            ("\\X \\x: X. x", "fun X => fun x: X => x"),
+            ("\\X \\x: X. x", "Λ X => fun x: X => x"),
+            (
+                "(\\f: Nat -> Nat. f 1) (\\x. x)",
+                "(fun f: Nat -> Nat => f 1) (fun x => x)",
+            ),
+            (
+                "(\\X \\x: X. x) [Nat -> Nat] \\x: Nat. x",
+                "(fun X => fun x: X => x) [Nat -> Nat] fun x: Nat => x",
+            ),
+            (
+                "\\f: forall X. X -> X. (f [Nat] 1, f [Bool] true)",
+                "fun f: forall X. X -> X => (f [Nat] 1, f [Bool] true)",
+            ),
+            (
+                "case Some 1 of {None | Some Nat} of | None => (\\x: Nat. x) | Some n => (\\x: Nat. add n x)",
+                "case Some 1 of {None | Some Nat} of | None => (fun x: Nat => x) | Some n => (fun x: Nat => add n x)",
+            ),
+            (
+                "letrec even: Nat -> Bool = \\n: Nat. if iszero n then true else odd (pred n) \
+                 and odd: Nat -> Bool = \\n: Nat. if iszero n then false else even (pred n) in even 4",
+                "let rec even: Nat -> Bool = fun n: Nat => if iszero n then true else odd (pred n) \
+                 and odd: Nat -> Bool = fun n: Nat => if iszero n then false else even (pred n) in even 4",
+            ),
+            ("let x = 1 in (\\y: Nat. y) x", "let x = 1 in (fun y: Nat => y) x"),
+        ];
+        for (lambda, ml) in &cases {
+            assert_eq!(parse(lambda, Syntax::Lambda), parse(ml, Syntax::Ml), "{}", ml);
+        }
+    }
+
+    #[test]
+    fn keywords() {
+        // `fun` is a variable in the default syntax, and must be quoted to
+        // be one in the ML syntax
+        assert_eq!(
+            parse("\\fun: Nat. fun", Syntax::Lambda),
+            parse("fun `fun`: Nat => `fun`", Syntax::Ml)
+        );
+        let mut p = Parser::with_syntax("fun 1 => 2", FileId::default(), Syntax::Ml);
+        assert!(p.parse().is_err());
+        let msgs = p.diagnostic().emit();
+        assert_eq!(msgs.matches("Error occuring").count(), 1, "{}", msgs);
+    }
+
+    #[test]
+    fn top_level_let_rec() {
+        let input = "let rec f: Nat -> Nat = fun n: Nat => n in f 1;\nlet g = fun x: Nat => f;";
+        let mut p = Parser::with_syntax(input, FileId::default(), Syntax::Ml);
+        assert!(matches!(p.item(), Ok(Item::Term(_))));
+        // `f` is only bound in the body of the `let rec`
+        assert!(matches!(p.item(), Ok(Item::Invalid(..))));
+        let msgs = p.diagnostic().emit();
+        assert!(msgs.contains("unbound variable f"), "{}", msgs);
+    }
+}
@@ -0,0 +1,314 @@
+//! Locating subterms by their position in the source
+//!
+//! Spans are half-open, so a term covers the offsets `start.abs..end.abs`.
+//! Subterms introduced by desugaring carry dummy spans, and are never found
+//! at any offset, though their children may be.
+use crate::terms::{Kind, Term};
+use crate::visit::TermVisitor;
+use util::span::Span;
+
+/// Discriminant of [`Kind`], without any of its fields
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum KindTag {
+    Lit,
+    Var,
+    Fix,
+    Primitive,
+    Injection,
+    Product,
+    Projection,
+    Case,
+    Let,
+    Abs,
+    App,
+    TyAbs,
+    TyApp,
+    Fold,
+    Unfold,
+    Pack,
+    Unpack,
+    Raise,
+    Share,
+}
+
+impl Kind {
+    pub fn tag(&self) -> KindTag {
+        match self {
+            Kind::Lit(_) => KindTag::Lit,
+            Kind::Var(_) => KindTag::Var,
+            Kind::Fix(_) => KindTag::Fix,
+            Kind::Primitive(_) => KindTag::Primitive,
+            Kind::Injection(..) => KindTag::Injection,
+            Kind::Product(_) => KindTag::Product,
+            Kind::Projection(..) => KindTag::Projection,
+            Kind::Case(..) => KindTag::Case,
+            Kind::Let(..) => KindTag::Let,
+            Kind::Abs(..) => KindTag::Abs,
+            Kind::App(..) => KindTag::App,
+            Kind::TyAbs(_) => KindTag::TyAbs,
+            Kind::TyApp(..) => KindTag::TyApp,
+            Kind::Fold(..) => KindTag::Fold,
+            Kind::Unfold(..) => KindTag::Unfold,
+            Kind::Pack(..) => KindTag::Pack,
+            Kind::Unpack(..) => KindTag::Unpack,
+            Kind::Raise(..) => KindTag::Raise,
+            Kind::Share(_) => KindTag::Share,
+        }
+    }
+}
+
+fn contains(span: Span, offset: usize) -> bool {
+    (span.start.abs as usize) <= offset && offset < span.end.abs as usize
+}
+
+impl Term {
+    /// The span and kind of this term and every subterm, in source order
+    pub fn spans(&self) -> Vec<(Span, KindTag)> {
+        let mut spans = Spans(Vec::new());
+        spans.visit(self);
+        spans.0
+    }
+
+    /// The innermost subterm whose span contains `offset`
+    pub fn find_at(&self, offset: usize) -> Option<&Term> {
+        self.enclosing(offset).pop()
+    }
+
+    /// Every subterm whose span contains `offset`, from this term inwards.
+    /// Each term in the path is a direct child of the one before it, unless
+    /// a subterm with a dummy span was skipped over
+    pub fn enclosing(&self, offset: usize) -> Vec<&Term> {
+        let mut locate = Locate {
+            offset,
+            depth: 0,
+            path: Vec::new(),
+        };
+        locate.visit(self);
+        locate.path
+    }
+}
+
+struct Spans(Vec<(Span, KindTag)>);
+
+impl<'a> TermVisitor<'a> for Spans {
+    fn visit(&mut self, term: &'a Term) {
+        self.0.push((term.span, term.kind.tag()));
+        self.walk(term);
+    }
+}
+
+struct Locate<'a> {
+    offset: usize,
+    /// Number of terms containing the offset that are being walked
+    depth: usize,
+    path: Vec<&'a Term>,
+}
+
+impl<'a> TermVisitor<'a> for Locate<'a> {
+    fn visit(&mut self, term: &'a Term) {
+        // Only descend into the first sibling containing the offset
+        if self.path.len() > self.depth {
+            return;
+        }
+        let found = contains(term.span, self.offset);
+        if found {
+            self.path.push(term);
+            self.depth += 1;
+        }
+        self.walk(term);
+        if found {
+            self.depth -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::syntax::parser::Parser;
+
+    const SOURCE: &str = "let f = \\x: Nat. succ x in case (f 1, true) of | (n, b) => iszero n";
+
+    fn parse(input: &str) -> Term {
+        let mut p = Parser::new(input);
+        let tm = p.parse().unwrap();
+        assert_eq!(p.diagnostic().error_count(), 0);
+        tm
+    }
+
+    #[test]
+    fn find_kinds() {
+        let tm = parse(SOURCE);
+        let cases = [
+            ("let", KindTag::Let),
+            ("x:", KindTag::Abs),
+            ("succ", KindTag::Primitive),
+            ("x in", KindTag::Var),
+            ("(f 1", KindTag::Product),
+            ("f 1", KindTag::Var),
+            ("1,", KindTag::Lit),
+            ("true", KindTag::Lit),
+            ("iszero", KindTag::Primitive),
+        ];
+        for (needle, tag) in &cases {
+            let offset = SOURCE.find(needle).unwrap();
+            let found = tm.find_at(offset).unwrap();
+            assert_eq!(found.kind.tag(), *tag, "{}", needle);
+        }
+        assert_eq!(tm.find_at(SOURCE.len() - 1).unwrap().kind.tag(), KindTag::Var);
+        assert!(tm.find_at(SOURCE.len()).is_none());
+
+        // The body of the abstraction is an application, found between its
+        // operator and operand
+        let offset = SOURCE.find("succ x").unwrap() + 4;
+        assert_eq!(tm.find_at(offset).unwrap().kind.tag(), KindTag::App);
+    }
+
+    #[test]
+    fn nested_path() {
+        let tm = parse(SOURCE);
+        let offset = SOURCE.find("succ").unwrap();
+        let path = tm.enclosing(offset).iter().map(|t| t.kind.tag()).collect::<Vec<_>>();
+        assert_eq!(path, vec![KindTag::Let, KindTag::Abs, KindTag::App, KindTag::Primitive]);
+    }
+
+    /// Assert that a span covers exactly `expected` in the source
+    fn assert_span_text(source: &str, span: Span, expected: &str) {
+        assert_eq!(
+            span.slice(source),
+            expected,
+            "span {}-{} of {:?}",
+            span.start,
+            span.end,
+            source
+        );
+    }
+
+    /// Checks that every subterm lies within the term containing it, and
+    /// that its text begins and ends the way its kind of term does
+    struct Audit<'s> {
+        source: &'s str,
+        parents: Vec<Span>,
+    }
+
+    impl<'a> TermVisitor<'a> for Audit<'_> {
+        fn visit(&mut self, term: &'a Term) {
+            // The payload of a bare label is not written at all
+            if term.is_elided() {
+                return;
+            }
+            let text = term.span.slice(self.source);
+            let starts = |prefixes: &[&str]| prefixes.iter().any(|p| text.starts_with(p));
+            // A letrec desugars into terms that take the span of its bindings
+            let derived = text.starts_with("letrec");
+            let ok = !text.is_empty()
+                && text.trim() == text
+                && (derived
+                    || match &term.kind {
+                        Kind::Lit(_) | Kind::Var(_) | Kind::Primitive(_) => !text.contains(' '),
+                        Kind::Abs(..) => starts(&["\\", "λ"]),
+                        Kind::TyAbs(_) => starts(&["\\", "Λ"]),
+                        // Either side may be in parentheses, which are not part
+                        // of its span
+                        Kind::App(t1, t2) => {
+                            term.span.start.abs <= t1.span.start.abs && term.span.end.abs >= t2.span.end.abs
+                        }
+                        Kind::TyApp(..) => text.ends_with(']'),
+                        Kind::Case(..) => starts(&["case", "if"]),
+                        Kind::Let(..) => starts(&["let"]),
+                        Kind::Product(_) => starts(&["("]) && text.ends_with(')'),
+                        // A name bound by letrec is a projection too
+                        Kind::Projection(..) => {
+                            text.ends_with(|c: char| c.is_ascii_digit())
+                                || text.chars().all(|c| c.is_ascii_alphanumeric())
+                        }
+                        Kind::Injection(..) => text.starts_with(|c: char| c.is_ascii_uppercase()),
+                        Kind::Fix(_) => starts(&["fix"]),
+                        Kind::Fold(..) => starts(&["fold"]),
+                        Kind::Unfold(..) => starts(&["unfold"]),
+                        Kind::Pack(..) => starts(&["pack"]),
+                        Kind::Unpack(..) => starts(&["unpack"]),
+                        Kind::Raise(..) => starts(&["raise"]),
+                        Kind::Share(_) => true,
+                    });
+            assert!(ok, "{:?} has span {:?} in {:?}", term.kind.tag(), text, self.source);
+            if let Some(parent) = self.parents.last() {
+                assert!(
+                    parent.start.abs <= term.span.start.abs && term.span.end.abs <= parent.end.abs,
+                    "{:?} at {:?} escapes its parent {:?}",
+                    term.kind.tag(),
+                    text,
+                    parent.slice(self.source)
+                );
+            }
+            self.parents.push(term.span);
+            self.walk(term);
+            self.parents.pop();
+        }
+    }
+
+    #[test]
+    fn span_audit() {
+        let corpus = [
+            SOURCE,
+            "\\x: Nat. \\y: Nat. add x y",
+            "λx: Nat. λy: Bool. x",
+            "(\\f: Nat -> Nat. f (f 1)) (\\n: Nat. succ n)",
+            "(ΛX. λx: X. x) [Nat] 1",
+            "\\X \\x: X. x",
+            "let (a, b) = (1, true) in if b then a else 0",
+            "case Some 1 of {None | Some Nat} of | None => 0 | Some n when iszero n => 1 | Some n => n",
+            "(\\x: {A | B Nat}. case x of | A => 0 | B n => n) (B 2 of {A | B Nat})",
+            "\\p: (Nat, (Bool, Nat)). p.1.0",
+            "letrec even: Nat -> Bool = \\n: Nat. case n of | 0 => true | _ => odd (pred n) \
+             and odd: Nat -> Bool = \\n: Nat. case n of | 0 => false | _ => even (pred n) in even 4",
+            "fix (\\f: Nat -> Nat. \\n: Nat. f n)",
+            "unfold (rec L = {Nil | Cons (Nat, L)}) (fold (rec L = {Nil | Cons (Nat, L)}) Nil of {Nil | Cons (Nat, rec L = {Nil | Cons (Nat, L)})})",
+            "unpack (pack Nat, (\\x: Nat. x, 0) as exists X. (X -> Nat, X)) as T, m in m.0 m.1",
+            "unpack {X, m} = pack {Nat, (\\x: Nat. x, 0)} as {exists X, (X -> Nat, X)} in m.0 m.1",
+            "raise Nat 1",
+            "( 1 , \"two\" ,unit )",
+            // Each construct again, after another token
+            "succ (fix (\\f: Nat -> Nat. \\n: Nat. f n) 1)",
+            "(1, let x = 2 in x)",
+            "\\x: Nat. letrec f: Nat -> Nat = \\n: Nat. f n in f x",
+            "\\x: Nat. (Some x of {None | Some Nat}, if true then x else 0)",
+            "\\x: Nat. case x of | 0 => raise Nat 1 | n => unfold (rec L = {Nil | Cons (Nat, L)}) (fold (rec L = {Nil | Cons (Nat, L)}) Nil of {Nil | Cons (Nat, rec L = {Nil | Cons (Nat, L)})})",
+        ];
+        for source in &corpus {
+            let tm = parse(source);
+            assert_span_text(source, tm.span, source);
+            Audit {
+                source,
+                parents: Vec::new(),
+            }
+            .visit(&tm);
+        }
+
+        let cases = [
+            ("(1, let x = 2 in x)", KindTag::Let, "let x = 2 in x"),
+            (
+                "(1, fix (\\f: Nat -> Nat. f))",
+                KindTag::Fix,
+                "fix (\\f: Nat -> Nat. f)",
+            ),
+            ("(\\x: Nat. x) 1", KindTag::App, "(\\x: Nat. x) 1"),
+            ("(1, \\X \\x: X. x)", KindTag::TyAbs, "\\X \\x: X. x"),
+            ("(1, λx: Nat. x)", KindTag::Abs, "λx: Nat. x"),
+        ];
+        for (source, tag, expected) in &cases {
+            let (span, _) = parse(source).spans().into_iter().find(|(_, t)| t == tag).unwrap();
+            assert_span_text(source, span, expected);
+        }
+    }
+
+    #[test]
+    fn source_order() {
+        let tm = parse(SOURCE);
+        let spans = tm.spans();
+        assert_eq!(spans[0], (tm.span, KindTag::Let));
+        assert_eq!(spans.len(), 14);
+        // Starting offsets never decrease in a pre-order walk of the source
+        assert!(spans.windows(2).all(|w| w[0].0.start.abs <= w[1].0.start.abs));
+    }
+}
@@ -2,10 +2,14 @@
 use crate::patterns::Pattern;
 use crate::types::Type;
 use std::fmt;
+use std::rc::Rc;
 use util::span::Span;
+pub mod locate;
+pub mod pretty;
+pub mod simplify;
 pub mod visit;
 
-#[derive(Clone, PartialEq, PartialOrd)]
+#[derive(Clone, PartialOrd)]
 pub struct Term {
     pub span: Span,
     pub kind: Kind,
@@ -17,6 +21,53 @@ pub enum Primitive {
     Succ,
     Pred,
     IsZero,
+    Concat,
+    StrLen,
+    Add,
+    /// Monus, truncated subtraction
+    Sub,
+    Mul,
+    Eq,
+    Lt,
+}
+
+impl Primitive {
+    /// Number of arguments that must be supplied before the primitive can
+    /// be evaluated. Partial applications of a primitive are values
+    pub fn arity(self) -> usize {
+        match self {
+            Primitive::Succ | Primitive::Pred | Primitive::IsZero | Primitive::StrLen => 1,
+            Primitive::Concat | Primitive::Add | Primitive::Sub | Primitive::Mul | Primitive::Eq | Primitive::Lt => 2,
+        }
+    }
+
+    /// Evaluate the primitive on literal arguments. This table is the only
+    /// definition of what the primitives do, and is shared by every
+    /// evaluator:
+    ///
+    /// - arithmetic saturates instead of overflowing, so `pred 0` is `0` (as
+    ///   in TAPL) and `sub` is truncated subtraction
+    /// - `iszero`, and every other primitive, only accepts literals of the
+    ///   type it is given by the typechecker
+    ///
+    /// Any other combination of arguments is a misuse of the primitive, and
+    /// `None` is returned. Such an application is stuck
+    pub fn apply(self, args: &[Literal]) -> Option<Literal> {
+        use Literal::*;
+        Some(match (self, args) {
+            (Primitive::Succ, [Nat(n)]) => Nat(n.saturating_add(1)),
+            (Primitive::Pred, [Nat(n)]) => Nat(n.saturating_sub(1)),
+            (Primitive::IsZero, [Nat(n)]) => Bool(*n == 0),
+            (Primitive::StrLen, [String(s)]) => Nat(s.chars().count() as u32),
+            (Primitive::Concat, [String(a), String(b)]) => String(a.clone() + b),
+            (Primitive::Add, [Nat(a), Nat(b)]) => Nat(a.saturating_add(*b)),
+            (Primitive::Sub, [Nat(a), Nat(b)]) => Nat(a.saturating_sub(*b)),
+            (Primitive::Mul, [Nat(a), Nat(b)]) => Nat(a.saturating_mul(*b)),
+            (Primitive::Eq, [Nat(a), Nat(b)]) => Bool(a == b),
+            (Primitive::Lt, [Nat(a), Nat(b)]) => Bool(a < b),
+            _ => return None,
+        })
+    }
 }
 
 /// Abstract syntax of the parametric polymorphic lambda calculus
@@ -64,6 +115,14 @@ pub enum Kind {
     /// open {∃X, bind} in body -- X is bound as a TyVar, and bind as Var(0)
     /// Eliminate an existential type
     Unpack(Box<Term>, Box<Term>),
+
+    /// Raise an error carrying a value, as a term of the given type
+    Raise(Box<Type>, Box<Term>),
+
+    /// A closed value shared between every place it was substituted into.
+    /// Never written in source; the evaluator inserts it so that
+    /// substitution copies a pointer instead of the whole term
+    Share(Rc<Term>),
 }
 
 /// Arm of a case expression
@@ -71,15 +130,22 @@ pub enum Kind {
 pub struct Arm {
     pub span: Span,
     pub pat: Pattern,
+    /// Spans of the variables bound by the pattern, in the order they appear
+    /// in the source. Arms that were not parsed have none
+    pub binders: Vec<Span>,
+    /// Optional `when` clause, evaluated with the pattern's bindings in
+    /// scope. The arm is only taken if the guard evaluates to `true`
+    pub guard: Option<Box<Term>>,
     pub term: Box<Term>,
 }
 
 /// Constant literal expression or pattern
-#[derive(Copy, Clone, Debug, PartialEq, PartialOrd, Eq, Hash)]
+#[derive(Clone, Debug, PartialEq, PartialOrd, Eq, Hash)]
 pub enum Literal {
     Unit,
     Bool(bool),
     Nat(u32),
+    String(String),
 }
 
 impl Term {
@@ -87,7 +153,6 @@ impl Term {
         Term { span, kind }
     }
 
-    #[allow(dead_code)]
     pub const fn unit() -> Term {
         Term {
             span: Span::dummy(),
@@ -95,6 +160,25 @@ impl Term {
         }
     }
 
+    /// The implicit `unit` payload of an injection written as a bare label
+    /// whose span is `label`. It covers no source text, and sits just after
+    /// the label
+    pub fn elided(label: Span) -> Term {
+        Term::new(
+            Kind::Lit(Literal::Unit),
+            Span {
+                start: label.end,
+                ..label
+            },
+        )
+    }
+
+    /// Is this the payload of an injection written as a bare label? See
+    /// [`Term::elided`]
+    pub fn is_elided(&self) -> bool {
+        self.kind == Kind::Lit(Literal::Unit) && self.span.start == self.span.end && self.span != Span::dummy()
+    }
+
     #[allow(dead_code)]
     #[inline]
     pub fn span(&self) -> Span {
@@ -105,6 +189,76 @@ impl Term {
     pub fn kind(&self) -> &Kind {
         &self.kind
     }
+
+    /// Is this term a value, i.e. fully evaluated? Values are literals,
+    /// abstractions, primitives (including partially applied binary
+    /// primitives), and products, injections, folds and packages of values
+    pub fn is_value(&self) -> bool {
+        // Subterms still to be checked are kept on the heap, so that deep
+        // values cannot overflow the stack
+        let mut rest = Vec::new();
+        let mut tm = self;
+        loop {
+            tm = match &tm.kind {
+                Kind::Lit(_) | Kind::Abs(_, _) | Kind::TyAbs(_) | Kind::Primitive(_) | Kind::Share(_) => {
+                    match rest.pop() {
+                        Some(next) => next,
+                        None => return true,
+                    }
+                }
+                Kind::Product(fields) if fields.is_empty() => match rest.pop() {
+                    Some(next) => next,
+                    None => return true,
+                },
+                Kind::Product(fields) => {
+                    rest.extend(fields[1..].iter().rev());
+                    &fields[0]
+                }
+                Kind::Injection(_, tm, _) => tm,
+                Kind::Fold(_, tm) => tm,
+                Kind::Pack(_, tm, _) => tm,
+                Kind::App(t1, t2) => match t1.kind {
+                    Kind::Primitive(p) if p.arity() > 1 => t2,
+                    _ => return false,
+                },
+                _ => return false,
+            };
+        }
+    }
+
+    /// Whether this term is an error raised with a value, which propagates
+    /// out of every evaluation context instead of taking a step
+    pub fn is_raised(&self) -> bool {
+        match &self.kind {
+            Kind::Raise(_, tm) => tm.is_value(),
+            _ => false,
+        }
+    }
+
+    /// This term, or the term it shares
+    pub fn unshared(&self) -> &Term {
+        match &self.kind {
+            Kind::Share(tm) => tm.unshared(),
+            _ => self,
+        }
+    }
+
+    /// Take this term out of any [`Kind::Share`] at its root, copying the
+    /// shared term only if it is still shared elsewhere
+    pub fn unshare(self) -> Term {
+        match self.kind {
+            Kind::Share(tm) => Rc::try_unwrap(tm).unwrap_or_else(|tm| (*tm).clone()).unshare(),
+            _ => self,
+        }
+    }
+
+    /// Replace the span of the root of this term, leaving the spans of its
+    /// subterms untouched. A shared root is unshared first
+    pub fn respan(self, span: Span) -> Term {
+        let mut tm = self.unshare();
+        tm.span = span;
+        tm
+    }
 }
 
 impl fmt::Display for Literal {
@@ -113,7 +267,35 @@ impl fmt::Display for Literal {
             Literal::Nat(n) => write!(f, "{}", n),
             Literal::Bool(b) => write!(f, "{}", b),
             Literal::Unit => write!(f, "unit"),
+            Literal::String(s) => {
+                write!(f, "\"")?;
+                for ch in s.chars() {
+                    match ch {
+                        '\n' => write!(f, "\\n")?,
+                        '"' => write!(f, "\\\"")?,
+                        '\\' => write!(f, "\\\\")?,
+                        ch => write!(f, "{}", ch)?,
+                    }
+                }
+                write!(f, "\"")
+            }
+        }
+    }
+}
+
+/// If the arms of a case expression are exactly those of a desugared
+/// `if t1 then t2 else t3`, return the two branches
+fn conditional(arms: &[Arm]) -> Option<(&Term, &Term)> {
+    match arms {
+        [t, f]
+            if t.guard.is_none()
+                && f.guard.is_none()
+                && t.pat == Pattern::Literal(Literal::Bool(true))
+                && f.pat == Pattern::Literal(Literal::Bool(false)) =>
+        {
+            Some((&t.term, &f.term))
         }
+        _ => None,
     }
 }
 
@@ -125,7 +307,10 @@ impl fmt::Display for Term {
             Kind::Abs(ty, term) => write!(f, "(λ_:{:?}. {})", ty, term),
             Kind::Fix(term) => write!(f, "Fix {:?}", term),
             Kind::Primitive(p) => write!(f, "{:?}", p),
-            Kind::Injection(label, tm, ty) => write!(f, "{}({})", label, tm),
+            Kind::Injection(label, tm, ty) => match tm.kind {
+                Kind::Lit(Literal::Unit) => write!(f, "{}", label),
+                _ => write!(f, "{}({})", label, tm),
+            },
             Kind::Projection(term, idx) => write!(f, "{}.{}", term, idx),
             Kind::Product(terms) => write!(
                 f,
@@ -137,9 +322,15 @@ impl fmt::Display for Term {
                     .join(",")
             ),
             Kind::Case(term, arms) => {
+                if let Some((t2, t3)) = conditional(arms) {
+                    return write!(f, "if {} then {} else {}", term, t2, t3);
+                }
                 writeln!(f, "case {} of", term)?;
                 for arm in arms {
-                    writeln!(f, "\t| {:?} => {},", arm.pat, arm.term)?;
+                    match &arm.guard {
+                        Some(guard) => writeln!(f, "\t| {:?} when {} => {},", arm.pat, guard, arm.term)?,
+                        None => writeln!(f, "\t| {:?} => {},", arm.pat, arm.term)?,
+                    }
                 }
                 write!(f, "")
             }
@@ -151,13 +342,24 @@ impl fmt::Display for Term {
             Kind::Unfold(ty, term) => write!(f, "unfold [{:?}] {}", ty, term),
             Kind::Pack(witness, body, sig) => write!(f, "[|pack {{*{:?}, {}}} as {:?} |]", witness, body, sig),
             Kind::Unpack(m, n) => write!(f, "unpack {} as {}", m, n),
+            Kind::Raise(ty, term) => write!(f, "raise [{:?}] {}", ty, term),
+            Kind::Share(term) => write!(f, "{}", term),
         }
     }
 }
 
+/// Sharing is invisible to equality: a [`Kind::Share`] equals the term it
+/// shares
+impl PartialEq for Term {
+    fn eq(&self, other: &Term) -> bool {
+        let (a, b) = (self.unshared(), other.unshared());
+        a.span == b.span && a.kind == b.kind
+    }
+}
+
 impl fmt::Debug for Term {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self.kind)
+        fmt::Debug::fmt(&self.kind, f)
     }
 }
 
@@ -193,4 +395,106 @@ mod test {
             assert_eq!(pat.matches(&b), *result, "{:?}", pat);
         }
     }
+
+    #[test]
+    fn values() {
+        let parse = |input: &str| crate::syntax::parser::Parser::new(input).parse().unwrap();
+        for input in &[
+            "1",
+            "\\x: Nat. pred x",
+            "\\X \\x: X. x",
+            "add",
+            "add 1",
+            "(1, (true, unit))",
+        ] {
+            assert!(parse(input).is_value(), "{}", input);
+        }
+        for input in &["succ 1", "add 1 2", "(1, pred 1)", "(1, 2).0", "let x = 1 in x"] {
+            assert!(!parse(input).is_value(), "{}", input);
+        }
+    }
+
+    /// Evaluate a corpus of well-typed closed terms one step at a time,
+    /// checking at every step that values don't step and that non-values
+    /// do (progress)
+    #[test]
+    fn values_agree_with_evaluator() {
+        let corpus = [
+            "(\\x: Nat. x) 1",
+            "add (succ 1) (pred 2)",
+            "((\\x: Nat. add x) 1, \\y: Nat. y).0 2",
+            "(\\X \\x: X. x) [Nat] 5",
+            "let (a, b) = (1, succ 2) in (b, a)",
+            "case Some (pred 3) of {None | Some Nat} of | None => 0 | Some n => n",
+            "case 3 of | n when iszero n => 0 | n => pred n",
+            "if iszero 0 then succ 0 else 0",
+            "letrec f: Nat->Nat = \\n: Nat. case n of | 0 => 0 | _ => f (pred n) in f 3",
+            "unfold (rec L = {Nil | Cons (Nat, L)}) (fold (rec L = {Nil | Cons (Nat, L)}) \
+             Nil of {Nil | Cons (Nat, rec L = {Nil | Cons (Nat, L)})})",
+            "let p = (pack Nat, ((\\x: Nat. succ x), 0) as exists X. (X->Nat, X)) in \
+             unpack p as T, m in m.0 m.1",
+        ];
+        for input in &corpus {
+            let mut tm = crate::syntax::parser::Parser::new(input).parse().unwrap();
+            let mut ctx = crate::types::Context::default();
+            ctx.type_check(&tm).unwrap();
+            let ev = crate::eval::Eval::with_context(&ctx);
+            loop {
+                match ev.small_step(tm.clone()) {
+                    Some(next) => {
+                        assert!(!tm.is_value(), "value `{}` steps to `{}`", tm, next);
+                        tm = next;
+                    }
+                    None => {
+                        assert!(tm.is_value(), "`{}` is stuck", tm);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn primitive_table() {
+        use Literal::*;
+        use Primitive::*;
+        let shapes = [Unit, Bool(true), Nat(0), Nat(3), String("ab".into())];
+        let unary = [
+            (Succ, [None, None, Some(Nat(1)), Some(Nat(4)), None]),
+            (Pred, [None, None, Some(Nat(0)), Some(Nat(2)), None]),
+            (IsZero, [None, None, Some(Bool(true)), Some(Bool(false)), None]),
+            (StrLen, [None, None, None, None, Some(Nat(2))]),
+        ];
+        for (prim, results) in &unary {
+            for (arg, result) in shapes.iter().zip(results) {
+                assert_eq!(prim.apply(std::slice::from_ref(arg)), *result, "{:?} {:?}", prim, arg);
+            }
+            // Wrong number of arguments
+            assert_eq!(prim.apply(&[]), None);
+            assert_eq!(prim.apply(&[Nat(1), Nat(1)]), None);
+        }
+
+        let binary = [
+            (Add, Some(Nat(3)), Some(Nat(u32::MAX))),
+            (Sub, Some(Nat(0)), Some(Nat(u32::MAX - 3))),
+            (Mul, Some(Nat(0)), Some(Nat(u32::MAX))),
+            (Eq, Some(Bool(false)), Some(Bool(false))),
+            (Lt, Some(Bool(true)), Some(Bool(false))),
+        ];
+        for (prim, small, large) in &binary {
+            assert_eq!(prim.apply(&[Nat(0), Nat(3)]), *small, "{:?}", prim);
+            assert_eq!(prim.apply(&[Nat(u32::MAX), Nat(3)]), *large, "{:?}", prim);
+            for shape in shapes.iter().filter(|l| !matches!(l, Nat(_))) {
+                assert_eq!(prim.apply(&[shape.clone(), Nat(3)]), None);
+                assert_eq!(prim.apply(&[Nat(3), shape.clone()]), None);
+            }
+            assert_eq!(prim.apply(&[Nat(3)]), None);
+        }
+        assert_eq!(
+            Concat.apply(&[String("a".into()), String("b".into())]),
+            Some(String("ab".into()))
+        );
+        assert_eq!(Concat.apply(&[String("a".into()), Nat(1)]), None);
+        assert_eq!(Succ.apply(&[Nat(u32::MAX)]), Some(Nat(u32::MAX)));
+    }
 }
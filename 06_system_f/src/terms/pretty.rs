@@ -0,0 +1,896 @@
+//! Configurable pretty printing of terms in the surface syntax
+//!
+//! Unlike the [`Display`](std::fmt::Display) implementation for [`Term`],
+//! which shows de Bruijn indices and every annotation, the pretty printer
+//! invents names for bound variables and only parenthesizes where the parser
+//! requires it. With annotations shown, its output parses back into the same
+//! term (up to spans), when read in the syntax it was printed in. Labels that
+//! would lex as keywords, like a constructor named `Nat`, are quoted in
+//! backticks.
+use super::*;
+use crate::syntax::{ident, Syntax};
+use crate::types::binder_name;
+use std::collections::HashSet;
+
+/// Options controlling the output of [`Term::pretty`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct PrintOptions {
+    /// Print the type annotations of lambda abstractions and injections
+    pub show_annotations: bool,
+    /// Follow every term with the location where it starts, as `@line:col`
+    pub show_spans: bool,
+    /// Print case expressions with a `true` and a `false` arm as `if`
+    pub sugar_if: bool,
+    /// Products that don't fit in this many columns are broken over multiple
+    /// lines, one component per line
+    pub max_width: usize,
+    /// Write binders, arrows and products as `λx: T.`, `ΛX.`, `∀X.`, `→`
+    /// and `×`
+    pub unicode: bool,
+    /// Write abstractions as `\x: T. t`, or as `fun x: T => t`
+    pub syntax: Syntax,
+}
+
+impl Default for PrintOptions {
+    fn default() -> PrintOptions {
+        PrintOptions {
+            show_annotations: true,
+            show_spans: false,
+            sugar_if: true,
+            max_width: 80,
+            unicode: false,
+            syntax: Syntax::Lambda,
+        }
+    }
+}
+
+/// Where a term appears, from the most to the least permissive. Terms that
+/// cannot appear in a position are parenthesized
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+enum Position {
+    /// Anywhere a full term is parsed: the top level, the body of a binder,
+    /// inside parentheses, etc.
+    Term,
+    /// The end of an application that is delimited by a keyword or symbol,
+    /// like the body of a case arm
+    Tail,
+    /// The function of an application
+    App,
+    /// The argument of an application, or the receiver of a projection
+    Arg,
+}
+
+impl Position {
+    /// The least permissive position a term can appear in without
+    /// parentheses
+    fn of(term: &Term, opts: &PrintOptions) -> Position {
+        match &term.kind {
+            Kind::Lit(_) | Kind::Var(_) | Kind::Primitive(_) | Kind::Product(_) | Kind::Projection(_, _) => {
+                Position::Arg
+            }
+            Kind::App(_, _) | Kind::TyApp(_, _) => Position::App,
+            // A bare label
+            Kind::Injection(_, tm, ty)
+                if tm.kind == Kind::Lit(Literal::Unit)
+                    && (!opts.show_annotations || matches!(ty.as_ref(), Type::Hole)) =>
+            {
+                Position::Arg
+            }
+            // These begin like an atom, but extend as far to the right as
+            // possible
+            Kind::Injection(_, _, _)
+            | Kind::Fix(_)
+            | Kind::Fold(_, _)
+            | Kind::Unfold(_, _)
+            | Kind::Raise(_, _)
+            | Kind::Pack(_, _, _)
+            | Kind::Unpack(_, _) => Position::Tail,
+            Kind::Abs(_, _) | Kind::TyAbs(_) | Kind::Let(_, _, _) | Kind::Case(_, _) => Position::Term,
+            Kind::Share(tm) => Position::of(tm, opts),
+        }
+    }
+}
+
+/// Delimiters of the highlighted subterm in the printer's output, which are
+/// replaced by an underline once printing is done
+const MARK_START: char = '\u{2}';
+const MARK_END: char = '\u{3}';
+
+impl Term {
+    /// Print a term in the surface syntax
+    pub fn pretty(&self, opts: &PrintOptions) -> String {
+        self.printer(opts, None).print(self, Position::Term)
+    }
+
+    /// Print a term in the surface syntax, underlining the first subterm
+    /// printed that has the given span. Each line of output with part of
+    /// the subterm on it is followed by a line with `^` under that part
+    pub fn pretty_highlight(&self, opts: &PrintOptions, span: Span) -> String {
+        let out = self.printer(opts, Some(span)).print(self, Position::Term);
+        let mut lines = Vec::new();
+        let mut inside = false;
+        for line in out.lines() {
+            let mut text = String::new();
+            let mut marks = String::new();
+            for c in line.chars() {
+                match c {
+                    MARK_START => inside = true,
+                    MARK_END => inside = false,
+                    c => {
+                        text.push(c);
+                        marks.push(if inside { '^' } else { ' ' });
+                    }
+                }
+            }
+            lines.push(text);
+            if marks.contains('^') {
+                lines.push(marks.trim_end().to_string());
+            }
+        }
+        lines.join("\n")
+    }
+
+    fn printer<'o>(&self, opts: &'o PrintOptions, highlight: Option<Span>) -> Printer<'o> {
+        let mut reserved = HashSet::new();
+        pattern_names(self, &mut reserved);
+        Printer {
+            opts,
+            names: Vec::new(),
+            reserved,
+            tyvars: 0,
+            indent: 0,
+            highlight,
+        }
+    }
+}
+
+/// Collect the names of all variables bound by patterns within a term, so
+/// that the names invented for other binders never collide with them
+fn pattern_names(term: &Term, out: &mut HashSet<String>) {
+    let mut patterns = |pat: &Pattern| {
+        out.extend(crate::patterns::PatVarStack::collect(&mut pat.clone()));
+    };
+    match &term.kind {
+        Kind::Case(_, arms) => arms.iter().for_each(|arm| patterns(&arm.pat)),
+        Kind::Let(pat, _, _) => patterns(pat),
+        _ => {}
+    }
+    let mut sub = |tm: &Term| pattern_names(tm, out);
+    match &term.kind {
+        Kind::Lit(_) | Kind::Var(_) | Kind::Primitive(_) => {}
+        Kind::Abs(_, tm)
+        | Kind::Fix(tm)
+        | Kind::Injection(_, tm, _)
+        | Kind::Projection(tm, _)
+        | Kind::TyAbs(tm)
+        | Kind::TyApp(tm, _)
+        | Kind::Fold(_, tm)
+        | Kind::Unfold(_, tm)
+        | Kind::Raise(_, tm)
+        | Kind::Pack(_, tm, _) => sub(tm),
+        Kind::Share(tm) => sub(tm),
+        Kind::App(t1, t2) | Kind::Let(_, t1, t2) | Kind::Unpack(t1, t2) => {
+            sub(t1);
+            sub(t2);
+        }
+        Kind::Product(terms) => terms.iter().for_each(sub),
+        Kind::Case(tm, arms) => {
+            sub(tm);
+            for arm in arms {
+                if let Some(guard) = &arm.guard {
+                    sub(guard);
+                }
+                sub(&arm.term);
+            }
+        }
+    }
+}
+
+struct Printer<'o> {
+    opts: &'o PrintOptions,
+    /// Names of the term variables in scope, innermost last
+    names: Vec<String>,
+    /// Names bound by patterns somewhere in the term
+    reserved: HashSet<String>,
+    /// Number of type variables in scope
+    tyvars: usize,
+    /// Column at which the current line of output starts
+    indent: usize,
+    /// Span of the subterm to mark for highlighting, until it is printed
+    highlight: Option<Span>,
+}
+
+/// A type printed under some number of type binders, and in unicode or not
+struct Annotation<'t>(&'t Type, usize, bool);
+
+impl fmt::Display for Annotation<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt_prec(f, self.1, false, self.2)
+    }
+}
+
+impl Printer<'_> {
+    /// A name for a new term binder, shadowing nothing that is in scope
+    fn fresh(&self) -> String {
+        const NAMES: [&str; 4] = ["x", "y", "z", "w"];
+        (0..)
+            .map(|n| match n / NAMES.len() {
+                0 => NAMES[n].to_string(),
+                i => format!("{}{}", NAMES[n % NAMES.len()], i),
+            })
+            .find(|name| !self.names.contains(name) && !self.reserved.contains(name))
+            .unwrap()
+    }
+
+    fn ty(&self, ty: &Type) -> String {
+        Annotation(ty, self.tyvars, self.opts.unicode).to_string()
+    }
+
+    /// Print a term with variables bound in its scope
+    fn under(&mut self, names: Vec<String>, term: &Term, pos: Position) -> String {
+        let len = self.names.len();
+        self.names.extend(names);
+        let out = self.print(term, pos);
+        self.names.truncate(len);
+        out
+    }
+
+    fn print(&mut self, term: &Term, pos: Position) -> String {
+        let marked = self.highlight == Some(term.span);
+        if marked {
+            self.highlight = None;
+        }
+        let mut out = self.print_kind(term);
+        if marked {
+            out = format!("{}{}{}", MARK_START, out, MARK_END);
+        }
+        if Position::of(term, self.opts) < pos {
+            out = format!("({})", out);
+        }
+        if self.opts.show_spans {
+            out = format!("{}@{}", out, term.span.start);
+        }
+        out
+    }
+
+    fn print_kind(&mut self, term: &Term) -> String {
+        match &term.kind {
+            Kind::Lit(lit) => lit.to_string(),
+            Kind::Var(idx) => match self.names.len().checked_sub(idx + 1) {
+                Some(level) => ident(&self.names[level]).into_owned(),
+                None => format!("#{}", idx),
+            },
+            Kind::Primitive(p) => match p {
+                Primitive::Succ => "succ",
+                Primitive::Pred => "pred",
+                Primitive::IsZero => "iszero",
+                Primitive::Concat => "concat",
+                Primitive::StrLen => "strlen",
+                Primitive::Add => "add",
+                Primitive::Sub => "sub",
+                Primitive::Mul => "mul",
+                Primitive::Eq => "eq",
+                Primitive::Lt => "lt",
+            }
+            .to_string(),
+            Kind::Abs(ty, body) => {
+                let name = self.fresh();
+                let annotate = self.opts.show_annotations && !matches!(ty.as_ref(), Type::Meta(_));
+                let lambda = if self.opts.unicode { "λ" } else { "\\" };
+                let binder = match (self.opts.syntax, annotate) {
+                    (Syntax::Lambda, true) => format!("{}{}: {}.", lambda, name, self.ty(ty)),
+                    (Syntax::Lambda, false) => format!("{}{}.", lambda, name),
+                    (Syntax::Ml, true) => format!("fun {}: {} =>", name, self.ty(ty)),
+                    (Syntax::Ml, false) => format!("fun {} =>", name),
+                };
+                format!("{} {}", binder, self.under(vec![name], body, Position::Term))
+            }
+            Kind::App(t1, t2) => format!("{} {}", self.print(t1, Position::App), self.print(t2, Position::Arg)),
+            Kind::Fix(tm) => format!("fix {}", self.print(tm, Position::Term)),
+            Kind::Injection(label, tm, ty) => {
+                let annotate = self.opts.show_annotations && !matches!(ty.as_ref(), Type::Hole);
+                let mut out = ident(label).into_owned();
+                if tm.kind != Kind::Lit(Literal::Unit) {
+                    // An annotated payload must stop before the `of`
+                    let pos = if annotate { Position::App } else { Position::Tail };
+                    out = format!("{} {}", out, self.print(tm, pos));
+                }
+                if annotate {
+                    out = format!("{} of {}", out, self.ty(ty));
+                }
+                out
+            }
+            Kind::Product(terms) => self.product(terms),
+            Kind::Projection(tm, idx) => format!("{}.{}", self.print(tm, Position::Arg), idx),
+            Kind::Case(tm, arms) => match conditional(arms) {
+                Some((t2, t3)) if self.opts.sugar_if => format!(
+                    "if {} then {} else {}",
+                    self.print(tm, Position::Term),
+                    self.print(t2, Position::Term),
+                    self.print(t3, Position::Term)
+                ),
+                _ => {
+                    let mut out = format!("case {} of", self.print(tm, Position::App));
+                    for (idx, arm) in arms.iter().enumerate() {
+                        let names = bound_names(&arm.pat);
+                        out.push_str(&format!(" | {}", arm.pat));
+                        if let Some(guard) = &arm.guard {
+                            out.push_str(&format!(" when {}", self.under(names.clone(), guard, Position::Tail)));
+                        }
+                        // An inner case would take the arms after this one
+                        let body = match ends_in_arm(&arm.term, self.opts) && idx + 1 < arms.len() {
+                            true => format!("({})", self.under(names, &arm.term, Position::Term)),
+                            false => self.under(names, &arm.term, Position::Tail),
+                        };
+                        out.push_str(&format!(" => {}", body));
+                    }
+                    out
+                }
+            },
+            Kind::Let(pat, t1, t2) => {
+                let names = bound_names(pat);
+                // A let binding parses a single pattern, not alternatives
+                let pat = match pat.as_ref() {
+                    Pattern::Or(_) | Pattern::As(_, _) => format!("({})", pat),
+                    pat => pat.to_string(),
+                };
+                let t1 = self.print(t1, Position::Term);
+                format!("let {} = {} in {}", pat, t1, self.under(names, t2, Position::Term))
+            }
+            Kind::TyAbs(tm) => {
+                let name = binder_name(self.tyvars);
+                self.tyvars += 1;
+                let body = self.print(tm, Position::Term);
+                self.tyvars -= 1;
+                match (self.opts.syntax, self.opts.unicode) {
+                    (Syntax::Lambda, true) => format!("Λ{}. {}", name, body),
+                    (Syntax::Lambda, false) => format!("\\{} {}", name, body),
+                    (Syntax::Ml, true) => format!("Λ{} => {}", name, body),
+                    (Syntax::Ml, false) => format!("fun {} => {}", name, body),
+                }
+            }
+            Kind::TyApp(tm, ty) => format!("{} [{}]", self.print(tm, Position::App), self.ty(ty)),
+            Kind::Fold(ty, tm) => format!("fold {} {}", self.ty(ty), self.print(tm, Position::Term)),
+            Kind::Unfold(ty, tm) => format!("unfold {} {}", self.ty(ty), self.print(tm, Position::Term)),
+            Kind::Raise(ty, tm) => format!("raise {} {}", self.ty(ty), self.print(tm, Position::Term)),
+            Kind::Share(tm) => self.print_kind(tm),
+            Kind::Pack(witness, tm, sig) => format!(
+                "pack {}, {} as {}",
+                self.ty(witness),
+                self.print(tm, Position::Tail),
+                self.ty(sig)
+            ),
+            Kind::Unpack(package, body) => {
+                let package = self.print(package, Position::Tail);
+                let tyname = binder_name(self.tyvars);
+                let name = self.fresh();
+                self.tyvars += 1;
+                let body = self.under(vec![name.clone()], body, Position::Term);
+                self.tyvars -= 1;
+                format!("unpack {} as {}, {} in {}", package, tyname, name, body)
+            }
+        }
+    }
+
+    /// Print the components of a product on one line if they fit, and one
+    /// per line otherwise
+    fn product(&mut self, terms: &[Term]) -> String {
+        self.indent += 2;
+        // A case arm would take the comma separating the components as its
+        // own optional terminator
+        let parts = terms
+            .iter()
+            .map(|tm| match ends_in_arm(tm, self.opts) && tm != terms.last().unwrap() {
+                true => format!("({})", self.print(tm, Position::Term)),
+                false => self.print(tm, Position::Term),
+            })
+            .collect::<Vec<_>>();
+        self.indent -= 2;
+
+        let flat = format!("({})", parts.join(", "));
+        let width = flat.chars().filter(|&c| c != MARK_START && c != MARK_END).count();
+        if parts.len() < 2 || (!flat.contains('\n') && self.indent + width <= self.opts.max_width) {
+            return flat;
+        }
+        let pad = " ".repeat(self.indent + 2);
+        let parts = parts
+            .iter()
+            .map(|part| format!("{}{}", pad, part))
+            .collect::<Vec<_>>()
+            .join(",\n");
+        format!("(\n{}\n{})", parts, " ".repeat(self.indent))
+    }
+}
+
+/// Does the printed term end with the body of a case arm?
+fn ends_in_arm(term: &Term, opts: &PrintOptions) -> bool {
+    match &term.kind {
+        Kind::Case(_, arms) => match conditional(arms) {
+            Some((_, t3)) if opts.sugar_if => ends_in_arm(t3, opts),
+            _ => true,
+        },
+        Kind::Injection(_, tm, ty) => {
+            (!opts.show_annotations || matches!(ty.as_ref(), Type::Hole)) && ends_in_arm(tm, opts)
+        }
+        Kind::Abs(_, tm)
+        | Kind::TyAbs(tm)
+        | Kind::Let(_, _, tm)
+        | Kind::Unpack(_, tm)
+        | Kind::Fix(tm)
+        | Kind::Fold(_, tm)
+        | Kind::Unfold(_, tm)
+        | Kind::Raise(_, tm) => ends_in_arm(tm, opts),
+        Kind::Share(tm) => ends_in_arm(tm, opts),
+        _ => false,
+    }
+}
+
+/// Names bound by a pattern, in the order they are pushed onto the naming
+/// context: the first variable of the pattern is innermost
+fn bound_names(pat: &Pattern) -> Vec<String> {
+    let mut names = crate::patterns::PatVarStack::collect(&mut pat.clone());
+    names.reverse();
+    names
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::syntax::parser::Parser;
+    use crate::types::Context;
+    use crate::visit::MutTermVisitor;
+    use util::span::FileId;
+
+    /// Forget where every part of a term came from, so that terms parsed
+    /// from different layouts can be compared
+    struct Unspan;
+
+    impl MutTermVisitor for Unspan {
+        fn visit(&mut self, term: &mut Term) {
+            term.span = Span::dummy();
+            if let Kind::Case(_, arms) = &mut term.kind {
+                for arm in arms {
+                    arm.span = Span::dummy();
+                    arm.binders.clear();
+                }
+            }
+            self.walk(term);
+        }
+    }
+
+    fn unspanned(mut term: Term) -> Kind {
+        Unspan.visit(&mut term);
+        term.kind
+    }
+
+    fn parse(input: &str) -> Term {
+        let mut p = Parser::new(input);
+        let tm = p.parse().unwrap();
+        assert_eq!(p.diagnostic().error_count(), 0);
+        tm
+    }
+
+    /// With annotations shown, printing and parsing again gives back the
+    /// same term
+    #[test]
+    fn round_trip() {
+        let unicode = PrintOptions {
+            unicode: true,
+            ..PrintOptions::default()
+        };
+        let corpus = [
+            "\\x: Nat. \\y: Nat. add x y",
+            "(\\f: Nat -> Nat. f (f 1)) (\\n: Nat. succ n)",
+            "\\X \\x: X. x",
+            "(\\X \\x: X. x) [Nat] 1",
+            "let (a, b) = (1, true) in if b then a else 0",
+            "case Some 1 of {None | Some Nat} of | None => 0 | Some n when iszero n => 1 | Some n => n",
+            "(\\x: {A | B Nat}. case x of | A | B 0 => 0 | B (succ n) as m => n) (B 2 of {A | B Nat})",
+            "\\p: (Nat, (Bool, Nat)). p.1.0",
+            "letrec even: Nat -> Bool = \\n: Nat. case n of | 0 => true | _ => odd (pred n) \
+             and odd: Nat -> Bool = \\n: Nat. case n of | 0 => false | _ => even (pred n) in even 4",
+            "fold (rec L = {Nil | Cons (Nat, L)}) Nil of {Nil | Cons (Nat, rec L = {Nil | Cons (Nat, L)})}",
+            "unfold (rec L = {Nil | Cons (Nat, L)}) (fold (rec L = {Nil | Cons (Nat, L)}) Nil of {Nil | Cons (Nat, rec L = {Nil | Cons (Nat, L)})})",
+            "unpack (pack Nat, (\\x: Nat. x, 0) as exists X. (X -> Nat, X)) as T, m in m.0 m.1",
+            "(\\x: Nat. \\y: Bool. x) (case 1 of | x => x) ((\\y: Bool. y) false)",
+            "concat \"a \\\"quoted\\\" string\" \"\"",
+            "case () of | () => (unit, fix (\\x: Nat. 1))",
+            "\\f: forall X. X -> (X, X). \\p: ((Nat, Bool), Nat -> Nat). f [(Nat, Nat)] (p.1 p.0.0, 0)",
+            "\\x: {A (Nat, Bool) | B forall X. X}. x",
+        ];
+        for opts in &[PrintOptions::default(), unicode] {
+            for input in &corpus {
+                let tm = parse(input);
+                let printed = tm.pretty(opts);
+                assert_eq!(
+                    unspanned(parse(&printed)),
+                    unspanned(tm.clone()),
+                    "{} printed as {}",
+                    input,
+                    printed
+                );
+            }
+        }
+    }
+
+    /// A small linear congruential generator, so that the corpus is the same
+    /// on every run
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn below(&mut self, n: u64) -> u64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (self.0 >> 33) % n
+        }
+    }
+
+    /// The types that terms are generated at
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    enum Sort {
+        Nat,
+        Bool,
+        Pair,
+        Fun,
+        Opt,
+        Poly,
+        List,
+        Package,
+    }
+
+    impl Sort {
+        const ALL: [Sort; 8] = [
+            Sort::Nat,
+            Sort::Bool,
+            Sort::Pair,
+            Sort::Fun,
+            Sort::Opt,
+            Sort::Poly,
+            Sort::List,
+            Sort::Package,
+        ];
+
+        fn ty(self) -> Type {
+            match self {
+                Sort::Nat => Type::Nat,
+                Sort::Bool => Type::Bool,
+                Sort::Pair => Type::Product(vec![Type::Nat, Type::Bool]),
+                Sort::Fun => arrow!(Type::Nat, Type::Nat),
+                // A label named after a type has to be quoted
+                Sort::Opt => Type::Variant(vec![
+                    variant!("None", Type::Unit),
+                    variant!("Some", Type::Nat),
+                    variant!("Bool", Type::Bool),
+                ]),
+                Sort::Poly => Type::Universal(Box::new(arrow!(Type::Var(0), Type::Var(0)))),
+                Sort::List => Type::Rec(Box::new(Sort::list(Type::Var(0)))),
+                Sort::Package => Type::Existential(Box::new(Type::Product(vec![
+                    Type::Var(0),
+                    arrow!(Type::Var(0), Type::Nat),
+                ]))),
+            }
+        }
+
+        fn list(tail: Type) -> Type {
+            Type::Variant(vec![
+                variant!("Nil", Type::Unit),
+                variant!("Cons", Type::Product(vec![Type::Nat, tail])),
+            ])
+        }
+    }
+
+    fn term(kind: Kind) -> Term {
+        Term::new(kind, Span::dummy())
+    }
+
+    /// Generate a term of the given sort with variables of these sorts bound
+    /// in its scope, the first innermost
+    fn under(rng: &mut Lcg, bound: &[Sort], sort: Sort, depth: u32, env: &mut Vec<Sort>) -> Term {
+        env.splice(0..0, bound.iter().copied());
+        let tm = gen(rng, sort, depth, env);
+        env.drain(..bound.len());
+        tm
+    }
+
+    /// Generate a closed, well-typed term of the given sort, where `env`
+    /// holds the sorts of the variables in scope, innermost first
+    fn gen(rng: &mut Lcg, sort: Sort, depth: u32, env: &mut Vec<Sort>) -> Term {
+        if let Some(idx) = env.iter().position(|s| *s == sort) {
+            if rng.below(3) == 0 {
+                return var!(idx);
+            }
+        }
+        if depth > 0 && rng.below(3) == 0 {
+            // Eliminations, which work at any sort
+            let depth = depth - 1;
+            match rng.below(5) {
+                0 => {
+                    let arg = Sort::ALL[rng.below(8) as usize];
+                    let ty = match arg == Sort::Opt && rng.below(2) == 0 {
+                        true => Type::Alias("Opt".into()),
+                        false => arg.ty(),
+                    };
+                    let body = under(rng, &[arg], sort, depth, env);
+                    return app!(abs!(ty, body), gen(rng, arg, depth, env));
+                }
+                1 => {
+                    let c = gen(rng, Sort::Bool, depth, env);
+                    let t = gen(rng, sort, depth, env);
+                    let f = gen(rng, sort, depth, env);
+                    return case!(c, boolean!(true) => t, boolean!(false) => f);
+                }
+                2 => {
+                    let mut tm = case!(
+                        gen(rng, Sort::Opt, depth, env),
+                        con!("None", Pattern::Any) => gen(rng, sort, depth, env),
+                        con!("Some", Pattern::Variable("n".into())) => under(rng, &[Sort::Nat], sort, depth, env),
+                        con!("Some", Pattern::Any) => gen(rng, sort, depth, env),
+                        con!("Bool", Pattern::Variable("b".into())) => under(rng, &[Sort::Bool], sort, depth, env),
+                    );
+                    if let Kind::Case(_, arms) = &mut tm.kind {
+                        arms[1].guard = Some(Box::new(under(rng, &[Sort::Nat], Sort::Bool, depth, env)));
+                    }
+                    return tm;
+                }
+                3 => {
+                    let pat = prod!(Pattern::Variable("a".into()), Pattern::Variable("b".into()));
+                    let bind = gen(rng, Sort::Pair, depth, env);
+                    let body = under(rng, &[Sort::Nat, Sort::Bool], sort, depth, env);
+                    return term(Kind::Let(Box::new(pat), Box::new(bind), Box::new(body)));
+                }
+                _ => {
+                    let list = unfold!(Sort::List.ty(), gen(rng, Sort::List, depth, env));
+                    let pat = con!(
+                        "Cons",
+                        prod!(Pattern::Variable("h".into()), Pattern::Variable("t".into()))
+                    );
+                    return case!(
+                        list,
+                        con!("Nil", Pattern::Any) => gen(rng, sort, depth, env),
+                        pat => under(rng, &[Sort::Nat, Sort::List], sort, depth, env),
+                    );
+                }
+            }
+        }
+        let depth = depth.saturating_sub(1);
+        match sort {
+            Sort::Nat => match rng.below(if depth > 0 { 8 } else { 1 }) {
+                0 => nat!(rng.below(4) as u32),
+                1 => app!(prim!(Primitive::Succ), gen(rng, sort, depth, env)),
+                2 => app!(
+                    app!(prim!(Primitive::Add), gen(rng, sort, depth, env)),
+                    gen(rng, sort, depth, env)
+                ),
+                3 => app!(gen(rng, Sort::Fun, depth, env), gen(rng, sort, depth, env)),
+                4 => proj!(gen(rng, Sort::Pair, depth, env), 0),
+                5 => app!(
+                    tyapp!(gen(rng, Sort::Poly, depth, env), Type::Nat),
+                    gen(rng, sort, depth, env)
+                ),
+                6 => {
+                    let body = app!(proj!(var!(0), 1), proj!(var!(0), 0));
+                    term(Kind::Unpack(
+                        Box::new(gen(rng, Sort::Package, depth, env)),
+                        Box::new(body),
+                    ))
+                }
+                _ => app!(
+                    prim!(Primitive::StrLen),
+                    term(Kind::Lit(Literal::String("a \"quoted\" string".into())))
+                ),
+            },
+            Sort::Bool => match rng.below(if depth > 0 { 4 } else { 1 }) {
+                0 => lit!(rng.below(2) == 0),
+                1 => app!(prim!(Primitive::IsZero), gen(rng, Sort::Nat, depth, env)),
+                2 => proj!(gen(rng, Sort::Pair, depth, env), 1),
+                _ => app!(
+                    tyapp!(gen(rng, Sort::Poly, depth, env), Type::Bool),
+                    gen(rng, sort, depth, env)
+                ),
+            },
+            Sort::Pair => tuple!(gen(rng, Sort::Nat, depth, env), gen(rng, Sort::Bool, depth, env)),
+            Sort::Fun => match rng.below(if depth > 0 { 4 } else { 2 }) {
+                0 => prim!(Primitive::Pred),
+                1 => abs!(Type::Nat, under(rng, &[Sort::Nat], Sort::Nat, depth, env)),
+                2 => app!(prim!(Primitive::Add), gen(rng, Sort::Nat, depth, env)),
+                _ => term(Kind::Fix(Box::new(abs!(
+                    Sort::Fun.ty(),
+                    under(rng, &[Sort::Fun], Sort::Fun, depth, env)
+                )))),
+            },
+            Sort::Opt => match rng.below(3) {
+                0 => inj!("None", Term::unit(), Sort::Opt.ty()),
+                1 => inj!("Some", gen(rng, Sort::Nat, depth, env), Sort::Opt.ty()),
+                _ => inj!("Bool", gen(rng, Sort::Bool, depth, env), Sort::Opt.ty()),
+            },
+            Sort::Poly => match rng.below(2) {
+                0 => tyabs!(abs!(Type::Var(0), var!(0))),
+                _ => tyabs!(abs!(Type::Var(0), app!(abs!(Type::Var(0), var!(0)), var!(0)))),
+            },
+            Sort::List => {
+                let unrolled = Sort::list(Sort::List.ty());
+                let tm = match rng.below(if depth > 0 { 2 } else { 1 }) {
+                    0 => inj!("Nil", Term::unit(), unrolled),
+                    _ => {
+                        let cons = tuple!(gen(rng, Sort::Nat, depth, env), gen(rng, Sort::List, depth, env));
+                        inj!("Cons", cons, unrolled)
+                    }
+                };
+                fold!(Sort::List.ty(), tm)
+            }
+            Sort::Package => term(Kind::Pack(
+                Box::new(Type::Nat),
+                Box::new(tuple!(gen(rng, Sort::Nat, depth, env), gen(rng, Sort::Fun, depth, env))),
+                Box::new(Sort::Package.ty()),
+            )),
+        }
+    }
+
+    /// Generated, well-typed terms print to source that parses back into the
+    /// same term, once aliases are expanded
+    #[test]
+    fn round_trip_generated() {
+        let mut ctx = Context::default();
+        ctx.alias("Opt".into(), Sort::Opt.ty()).unwrap();
+        let narrow = PrintOptions {
+            unicode: true,
+            max_width: 30,
+            ..PrintOptions::default()
+        };
+        let ml = PrintOptions {
+            syntax: Syntax::Ml,
+            ..PrintOptions::default()
+        };
+        let ml_narrow = PrintOptions {
+            syntax: Syntax::Ml,
+            ..narrow.clone()
+        };
+        let mut rng = Lcg(17);
+        for _ in 0..500 {
+            let sort = Sort::ALL[rng.below(8) as usize];
+            let mut tm = gen(&mut rng, sort, 4, &mut Vec::new());
+            ctx.de_alias(&mut tm);
+            if let Err(diag) = ctx.type_check(&tm) {
+                panic!("generated an ill-typed term {}: {}", tm, diag.primary.info);
+            }
+            for opts in &[PrintOptions::default(), narrow.clone(), ml.clone(), ml_narrow.clone()] {
+                let printed = tm.pretty(opts);
+                let mut p = Parser::with_syntax(&printed, FileId::default(), opts.syntax);
+                let parsed = p.parse();
+                let diag = p.diagnostic();
+                let mut parsed = match parsed {
+                    Ok(parsed) if diag.error_count() == 0 => parsed,
+                    _ => panic!("{} does not parse:\n{}", printed, diag.emit()),
+                };
+                ctx.de_alias(&mut parsed);
+                assert_eq!(unspanned(parsed), unspanned(tm.clone()), "{}", printed);
+            }
+        }
+    }
+
+    #[test]
+    fn unicode() {
+        let opts = PrintOptions {
+            unicode: true,
+            ..PrintOptions::default()
+        };
+        let cases = [
+            ("\\X \\x: X. x", "ΛX. λx: X. x"),
+            (
+                "\\f: forall X. X -> (X, X). \\p: ((Nat, Bool), Nat -> Nat). p",
+                "λx: ∀X. X → X × X. λy: (Nat × Bool) × (Nat → Nat). y",
+            ),
+            ("\\x: {A (Nat, Bool) | B}. x", "λx: {A (Nat × Bool) | B}. x"),
+        ];
+        for (input, expected) in &cases {
+            assert_eq!(parse(input).pretty(&opts), *expected);
+        }
+    }
+
+    #[test]
+    fn ml_syntax() {
+        let cases = [
+            ("\\X \\x: X. x", "fun X => fun x: X => x", "ΛX => fun x: X => x"),
+            ("(\\x. x) 1", "(fun x => x) 1", "(fun x => x) 1"),
+            (
+                "\\f: Nat -> Nat. case f 1 of | 0 => (\\y: Nat. y) | _ => f",
+                "fun x: Nat -> Nat => case x 1 of | 0 => (fun y: Nat => y) | _ => x",
+                "fun x: Nat → Nat => case x 1 of | 0 => (fun y: Nat => y) | _ => x",
+            ),
+            (
+                "let `fun` = 1 in `fun`",
+                "let `fun` = 1 in `fun`",
+                "let `fun` = 1 in `fun`",
+            ),
+        ];
+        for (input, ascii, unicode) in &cases {
+            let opts = PrintOptions {
+                syntax: Syntax::Ml,
+                ..PrintOptions::default()
+            };
+            assert_eq!(parse(input).pretty(&opts), *ascii);
+            let opts = PrintOptions { unicode: true, ..opts };
+            assert_eq!(parse(input).pretty(&opts), *unicode);
+        }
+    }
+
+    #[test]
+    fn elide_annotations() {
+        let opts = PrintOptions {
+            show_annotations: false,
+            ..PrintOptions::default()
+        };
+        let cases = [
+            ("\\x: Nat. \\y: Bool. x", "\\x. \\y. x"),
+            ("\\X \\x: X. x", "\\X \\x. x"),
+            ("(\\f: Nat -> Nat. f 1) (\\n: Nat. n)", "(\\x. x 1) (\\x. x)"),
+            ("Some (succ 1) of {None | Some Nat}", "Some succ 1"),
+            ("(\\x: {A | B Nat}. x) (A of {A | B Nat})", "(\\x. x) A"),
+            ("\\y: Nat. case y of | x => y", "\\y. case y of | x => y"),
+        ];
+        for (input, expected) in &cases {
+            assert_eq!(parse(input).pretty(&opts), *expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn keyword_labels() {
+        let tm = parse("\\x: {`Nat` Nat | `Unit`}. case x of | `Nat` n => n | `Unit` => 0");
+        assert_eq!(
+            tm.pretty(&PrintOptions::default()),
+            "\\x: {`Nat` Nat | `Unit`}. case x of | `Nat` n => n | `Unit` => 0"
+        );
+        let tm = parse(
+            "\\x: Nat. case x of | 0 => `Bool` true of {`Nat` | `Bool` Bool} | n => `Nat` of {`Nat` | `Bool` Bool}",
+        );
+        let printed = tm.pretty(&PrintOptions::default());
+        assert!(printed.contains("`Bool` true of"), "{}", printed);
+        assert_eq!(unspanned(parse(&printed)), unspanned(tm));
+    }
+
+    #[test]
+    fn if_sugar() {
+        let tm = parse("case iszero 0 of | true => 1 | false => 2");
+        assert_eq!(tm.pretty(&PrintOptions::default()), "if iszero 0 then 1 else 2");
+        let opts = PrintOptions {
+            sugar_if: false,
+            ..PrintOptions::default()
+        };
+        assert_eq!(tm.pretty(&opts), "case iszero 0 of | true => 1 | false => 2");
+        // The sugared form must be parenthesized as an argument
+        let tm = parse("(\\x: Nat. x) (if true then 1 else 2)");
+        assert_eq!(
+            tm.pretty(&PrintOptions::default()),
+            "(\\x: Nat. x) (if true then 1 else 2)"
+        );
+    }
+
+    #[test]
+    fn wrap_products() {
+        let opts = PrintOptions {
+            max_width: 24,
+            ..PrintOptions::default()
+        };
+        let tm = parse("(1, (\"a long string\", 2), 3)");
+        assert_eq!(tm.pretty(&opts), "(\n  1,\n  (\"a long string\", 2),\n  3\n)");
+        let tm = parse("(1, (\"a much longer string\", 2), 3)");
+        assert_eq!(
+            tm.pretty(&opts),
+            "(\n  1,\n  (\n    \"a much longer string\",\n    2\n  ),\n  3\n)"
+        );
+        // Wrapped output still parses
+        assert_eq!(unspanned(parse(&tm.pretty(&opts))), unspanned(tm));
+    }
+
+    #[test]
+    fn spans() {
+        let opts = PrintOptions {
+            show_spans: true,
+            ..PrintOptions::default()
+        };
+        let tm = parse("succ 1");
+        assert_eq!(tm.pretty(&opts), "succ@0:0 1@0:5@0:0");
+    }
+}
@@ -0,0 +1,156 @@
+//! Constant folding and simplification of terms
+//!
+//! Simplification only performs rewrites that discard values, so that no
+//! computation can be lost, and it preserves the type of the term.
+use crate::eval::case_subst;
+use crate::terms::{Kind, Literal, Primitive, Term};
+use crate::visit::MutTermVisitor;
+
+/// Simplify a term in place:
+///
+/// - `succ`, `pred` and `iszero` applied to a Nat literal are folded, and
+///   `pred (succ t)` is rewritten to `t`
+/// - projections out of a product of values select the component
+/// - case expressions on a value are replaced with the matching arm, with the
+///   bound variables substituted (`if`-style cases on a boolean literal are a
+///   special case of this)
+///
+/// A folded literal takes the span of the term it replaces, while a selected
+/// subterm keeps its own span.
+pub fn simplify(term: &mut Term) {
+    Simplify.visit(term)
+}
+
+struct Simplify;
+
+impl Simplify {
+    fn rewrite(&self, term: &Term) -> Option<Term> {
+        let lit = |lit| Some(Term::new(Kind::Lit(lit), term.span));
+        match &term.kind {
+            Kind::App(f, arg) => match (&f.kind, &arg.kind) {
+                (Kind::Primitive(Primitive::Succ), Kind::Lit(Literal::Nat(n))) => lit(Literal::Nat(n.checked_add(1)?)),
+                (Kind::Primitive(Primitive::Pred), Kind::Lit(Literal::Nat(n))) => {
+                    lit(Literal::Nat(n.saturating_sub(1)))
+                }
+                (Kind::Primitive(Primitive::IsZero), Kind::Lit(Literal::Nat(n))) => lit(Literal::Bool(*n == 0)),
+                (Kind::Primitive(Primitive::Pred), Kind::App(g, inner)) => match g.kind {
+                    Kind::Primitive(Primitive::Succ) => Some(*inner.clone()),
+                    _ => None,
+                },
+                _ => None,
+            },
+            Kind::Projection(tm, idx) if tm.is_value() => match &tm.kind {
+                Kind::Product(terms) => terms.get(*idx).cloned(),
+                _ => None,
+            },
+            Kind::Case(expr, arms) if expr.is_value() => {
+                let arm = arms.iter().find(|arm| arm.pat.matches(expr))?;
+                if arm.guard.is_some() {
+                    return None;
+                }
+                let mut body = *arm.term.clone();
+                case_subst(&arm.pat, expr, &mut body);
+                Some(body)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl MutTermVisitor for Simplify {
+    fn visit(&mut self, term: &mut Term) {
+        self.walk(term);
+        if let Some(simplified) = self.rewrite(term) {
+            *term = simplified;
+            // Substitution may expose further opportunities for simplification
+            self.visit(term);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::eval::Eval;
+    use crate::syntax::parser::Parser;
+    use crate::types::Context;
+
+    fn parse(input: &str) -> Term {
+        let mut p = Parser::new(input);
+        let tm = p.parse().unwrap();
+        assert_eq!(p.diagnostic().error_count(), 0);
+        tm
+    }
+
+    fn eval(ctx: &Context, term: Term) -> Term {
+        let ev = Eval::with_context(ctx);
+        let mut t = term;
+        while let Some(next) = ev.small_step(t.clone()) {
+            t = next;
+        }
+        t
+    }
+
+    #[test]
+    fn fold_constants() {
+        let cases = [
+            ("succ (succ 1)", "3"),
+            ("pred (succ (succ 0))", "1"),
+            ("iszero (pred 1)", "true"),
+            ("\\x: Nat. pred (succ x)", "(λ_:Nat. #0)"),
+            ("(1, true, unit).1", "true"),
+            ("case true of | true => 1 | false => 2", "1"),
+            ("case (1, succ 2) of | (x, y) => (y, x)", "(3,1)"),
+        ];
+        for (input, expected) in &cases {
+            let mut tm = parse(input);
+            simplify(&mut tm);
+            assert_eq!(tm.to_string(), *expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn keep_effects() {
+        // Components that are not values must not be discarded
+        let mut tm = parse("((\\x: Nat. x) 1, 2).1");
+        let before = tm.clone();
+        simplify(&mut tm);
+        assert_eq!(tm.kind, before.kind);
+    }
+
+    #[test]
+    fn surviving_span() {
+        let mut tm = parse("case false of | true => 1 | false => succ 41");
+        let arm = match &tm.kind {
+            Kind::Case(_, arms) => arms[1].term.span,
+            _ => unreachable!(),
+        };
+        simplify(&mut tm);
+        assert_eq!(tm.kind, Kind::Lit(Literal::Nat(42)));
+        assert_eq!(tm.span, arm);
+    }
+
+    #[test]
+    fn preserve_typing_and_evaluation() {
+        let corpus = [
+            "succ (pred (succ 10))",
+            "let x = (1, iszero 0) in case x.1 of | true => x.0 | false => 0",
+            "(\\n: Nat. case iszero n of | true => pred (succ n) | false => n) 3",
+            "case Some 3 of {None | Some Nat} of | None => 0 | Some n => succ n",
+            "(\\X \\x: X. x) [Bool] (case (true, 1) of | (b, _) => b)",
+            "add (succ 1) (pred (succ 2))",
+            "((\\x: Nat. (x, x)) 1, (2, 3).0).1",
+            "letrec f: Nat->Nat = \\n: Nat. case n of | 0 => 0 | _ => f (pred (succ (pred n))) in f 5",
+        ];
+        for input in &corpus {
+            let tm = parse(input);
+            let mut simple = tm.clone();
+            simplify(&mut simple);
+
+            let mut ctx = Context::default();
+            let ty = ctx.type_check(&tm).unwrap();
+            assert_eq!(ctx.type_check(&simple).unwrap(), ty, "{}", input);
+            assert_eq!(eval(&ctx, tm).kind, eval(&ctx, simple).kind, "{}", input);
+        }
+    }
+}
@@ -1,7 +1,9 @@
 use crate::patterns::{Pattern, PatternCount};
-use crate::terms::{Arm, Kind, Primitive, Term};
+use crate::terms::{Arm, Kind, Literal, Primitive, Term};
 use crate::types::Type;
-use crate::visit::{MutTermVisitor, MutTypeVisitor};
+use crate::visit::{MutTermVisitor, MutTypeVisitor, TermVisitor};
+use std::collections::{BTreeSet, HashMap};
+use std::rc::Rc;
 use util::span::Span;
 
 pub struct Shift {
@@ -39,8 +41,11 @@ impl MutTermVisitor for Shift {
     fn visit_case(&mut self, sp: &mut Span, term: &mut Term, arms: &mut Vec<Arm>) {
         self.visit(term);
         for arm in arms {
-            let c = PatternCount::collect(&mut arm.pat);
+            let c = PatternCount::collect(&arm.pat);
             self.cutoff += c;
+            if let Some(guard) = &mut arm.guard {
+                self.visit(guard);
+            }
             self.visit(&mut arm.term);
             self.cutoff -= c;
         }
@@ -52,16 +57,64 @@ impl MutTermVisitor for Shift {
         self.visit(term);
         self.cutoff -= 1;
     }
+
+    fn visit_share(&mut self, sp: &mut Span, term: &mut Rc<Term>) {}
 }
 
+/// Substitute a term for the variable at the cutoff
+///
+/// Spans are never rewritten here: each substituted copy keeps the span of
+/// the term being substituted (its definition site), and every other node
+/// keeps its own. Giving the reduced term the span of the redex it replaces
+/// is up to the evaluator, see [`Term::respan`]
 pub struct Subst {
     cutoff: usize,
+    /// Number of type binders the substitution is under
+    tyvars: usize,
     term: Term,
+    copies: usize,
+    closed: bool,
 }
 
 impl Subst {
     pub fn new(term: Term) -> Subst {
-        Subst { cutoff: 0, term }
+        Subst {
+            cutoff: 0,
+            tyvars: 0,
+            term,
+            copies: 0,
+            closed: false,
+        }
+    }
+
+    /// Substitute a closed value, which never needs shifting. Unless it is a
+    /// literal or primitive, which are as cheap to copy as a pointer, the
+    /// term is put in a [`Kind::Share`] that every copy points to
+    pub fn shared(term: Term) -> Subst {
+        let term = match term.kind {
+            Kind::Lit(_) | Kind::Primitive(_) | Kind::Share(_) => term,
+            _ => {
+                let span = term.span;
+                Term::new(Kind::Share(Rc::new(term)), span)
+            }
+        };
+        Subst {
+            cutoff: 0,
+            tyvars: 0,
+            term,
+            copies: 0,
+            closed: true,
+        }
+    }
+
+    /// The term each copy is made from
+    pub fn term(&self) -> &Term {
+        &self.term
+    }
+
+    /// Number of copies of the term substituted so far
+    pub fn copies(&self) -> usize {
+        self.copies
     }
 }
 
@@ -83,26 +136,107 @@ impl MutTermVisitor for Subst {
     fn visit_case(&mut self, sp: &mut Span, term: &mut Term, arms: &mut Vec<Arm>) {
         self.visit(term);
         for arm in arms {
-            let c = PatternCount::collect(&mut arm.pat);
+            let c = PatternCount::collect(&arm.pat);
             self.cutoff += c;
+            if let Some(guard) = &mut arm.guard {
+                self.visit(guard);
+            }
             self.visit(&mut arm.term);
             self.cutoff -= c;
         }
     }
 
+    fn visit_tyabs(&mut self, sp: &mut Span, term: &mut Term) {
+        self.tyvars += 1;
+        self.visit(term);
+        self.tyvars -= 1;
+    }
+
     fn visit_unpack(&mut self, _: &mut Span, package: &mut Term, term: &mut Term) {
         self.visit(package);
         self.cutoff += 1;
+        self.tyvars += 1;
         self.visit(term);
+        self.tyvars -= 1;
         self.cutoff -= 1;
     }
 
+    fn visit_share(&mut self, sp: &mut Span, term: &mut Rc<Term>) {}
+
     fn visit(&mut self, term: &mut Term) {
         let sp = &mut term.span;
         match &mut term.kind {
             Kind::Var(v) if *v == self.cutoff => {
-                Shift::new(self.cutoff as isize).visit(&mut self.term);
-                *term = self.term.clone();
+                self.copies += 1;
+                let mut copy = self.term.clone();
+                if !self.closed {
+                    Shift::new(self.cutoff as isize).visit(&mut copy);
+                    TyShift::new(self.tyvars as isize).visit(&mut copy);
+                }
+                *term = copy;
+            }
+            _ => self.walk(term),
+        }
+    }
+}
+
+/// Visitor used to desugar `letrec` bindings: the `len` mutually recursive
+/// binders directly above `cutoff` are replaced by projections out of a single
+/// product-typed binder, and any variables bound further out are shifted down
+/// to account for the removed binders
+pub struct ProjectBinders {
+    cutoff: usize,
+    len: usize,
+}
+
+impl ProjectBinders {
+    pub const fn new(len: usize) -> ProjectBinders {
+        ProjectBinders { cutoff: 0, len }
+    }
+}
+
+impl MutTermVisitor for ProjectBinders {
+    fn visit_abs(&mut self, sp: &mut Span, ty: &mut Type, term: &mut Term) {
+        self.cutoff += 1;
+        self.visit(term);
+        self.cutoff -= 1;
+    }
+
+    fn visit_let(&mut self, sp: &mut Span, pat: &mut Pattern, t1: &mut Term, t2: &mut Term) {
+        self.visit(t1);
+        let c = PatternCount::collect(pat);
+        self.cutoff += c;
+        self.visit(t2);
+        self.cutoff -= c;
+    }
+
+    fn visit_case(&mut self, sp: &mut Span, term: &mut Term, arms: &mut Vec<Arm>) {
+        self.visit(term);
+        for arm in arms {
+            let c = PatternCount::collect(&arm.pat);
+            self.cutoff += c;
+            if let Some(guard) = &mut arm.guard {
+                self.visit(guard);
+            }
+            self.visit(&mut arm.term);
+            self.cutoff -= c;
+        }
+    }
+
+    fn visit_unpack(&mut self, _: &mut Span, package: &mut Term, term: &mut Term) {
+        self.visit(package);
+        self.cutoff += 1;
+        self.visit(term);
+        self.cutoff -= 1;
+    }
+
+    fn visit(&mut self, term: &mut Term) {
+        match &mut term.kind {
+            Kind::Var(v) if *v >= self.cutoff + self.len => *v -= self.len - 1,
+            Kind::Var(v) if *v >= self.cutoff => {
+                let idx = *v - self.cutoff;
+                let binder = Term::new(Kind::Var(self.cutoff), term.span);
+                term.kind = Kind::Projection(Box::new(binder), idx);
             }
             _ => self.walk(term),
         }
@@ -177,6 +311,87 @@ impl MutTermVisitor for TyTermSubst {
         self.visit_ty(ty);
         self.visit(term);
     }
+
+    fn visit_raise(&mut self, sp: &mut Span, ty: &mut Type, term: &mut Term) {
+        self.visit_ty(ty);
+        self.visit(term);
+    }
+
+    fn visit_share(&mut self, sp: &mut Span, term: &mut Rc<Term>) {}
+}
+
+/// Shift the free type variables in the type annotations of a term, which
+/// are bound by every enclosing type abstraction and unpack
+pub struct TyShift {
+    cutoff: usize,
+    shift: isize,
+}
+
+impl TyShift {
+    pub const fn new(shift: isize) -> TyShift {
+        TyShift { cutoff: 0, shift }
+    }
+
+    fn visit_ty(&mut self, ty: &mut Type) {
+        let mut s = crate::types::visit::Shift {
+            cutoff: self.cutoff,
+            shift: self.shift,
+        };
+        s.visit(ty);
+    }
+}
+
+impl MutTermVisitor for TyShift {
+    fn visit_abs(&mut self, sp: &mut Span, ty: &mut Type, term: &mut Term) {
+        self.visit_ty(ty);
+        self.visit(term);
+    }
+
+    fn visit_tyapp(&mut self, sp: &mut Span, term: &mut Term, ty: &mut Type) {
+        self.visit_ty(ty);
+        self.visit(term);
+    }
+
+    fn visit_tyabs(&mut self, sp: &mut Span, term: &mut Term) {
+        self.cutoff += 1;
+        self.visit(term);
+        self.cutoff -= 1;
+    }
+
+    fn visit_fold(&mut self, sp: &mut Span, ty: &mut Type, term: &mut Term) {
+        self.visit_ty(ty);
+        self.visit(term);
+    }
+
+    fn visit_unfold(&mut self, sp: &mut Span, ty: &mut Type, term: &mut Term) {
+        self.visit_ty(ty);
+        self.visit(term);
+    }
+
+    fn visit_unpack(&mut self, _: &mut Span, package: &mut Term, term: &mut Term) {
+        self.visit(package);
+        self.cutoff += 1;
+        self.visit(term);
+        self.cutoff -= 1;
+    }
+
+    fn visit_pack(&mut self, _: &mut Span, wit: &mut Type, body: &mut Term, sig: &mut Type) {
+        self.visit_ty(wit);
+        self.visit(body);
+        self.visit_ty(sig);
+    }
+
+    fn visit_injection(&mut self, sp: &mut Span, label: &mut String, term: &mut Term, ty: &mut Type) {
+        self.visit_ty(ty);
+        self.visit(term);
+    }
+
+    fn visit_raise(&mut self, sp: &mut Span, ty: &mut Type, term: &mut Term) {
+        self.visit_ty(ty);
+        self.visit(term);
+    }
+
+    fn visit_share(&mut self, sp: &mut Span, term: &mut Rc<Term>) {}
 }
 
 /// Visitor for handling recursive variants automatically, by inserting a
@@ -208,3 +423,325 @@ impl MutTermVisitor for InjRewriter {
         }
     }
 }
+
+/// Visitor collecting the free variables of a term, as de Bruijn indices
+/// relative to the context the term appears in
+#[derive(Default)]
+pub struct FreeTermVars {
+    cutoff: usize,
+    free: BTreeSet<usize>,
+}
+
+impl FreeTermVars {
+    pub fn collect(term: &Term) -> BTreeSet<usize> {
+        let mut v = FreeTermVars::default();
+        v.visit(term);
+        v.free
+    }
+}
+
+impl<'a> TermVisitor<'a> for FreeTermVars {
+    fn visit_var(&mut self, sp: Span, var: usize) {
+        if var >= self.cutoff {
+            self.free.insert(var - self.cutoff);
+        }
+    }
+
+    fn visit_abs(&mut self, sp: Span, ty: &Type, term: &Term) {
+        self.cutoff += 1;
+        self.visit(term);
+        self.cutoff -= 1;
+    }
+
+    fn visit_let(&mut self, sp: Span, pat: &Pattern, t1: &Term, t2: &Term) {
+        self.visit(t1);
+        let c = PatternCount::collect(pat);
+        self.cutoff += c;
+        self.visit(t2);
+        self.cutoff -= c;
+    }
+
+    fn visit_case(&mut self, sp: Span, term: &Term, arms: &[Arm]) {
+        self.visit(term);
+        for arm in arms {
+            let c = PatternCount::collect(&arm.pat);
+            self.cutoff += c;
+            if let Some(guard) = &arm.guard {
+                self.visit(guard);
+            }
+            self.visit(&arm.term);
+            self.cutoff -= c;
+        }
+    }
+
+    fn visit_unpack(&mut self, sp: Span, package: &Term, term: &Term) {
+        self.visit(package);
+        self.cutoff += 1;
+        self.visit(term);
+        self.cutoff -= 1;
+    }
+
+    fn visit_share(&mut self, sp: Span, term: &Term) {}
+}
+
+/// Visitor checking that a term is closed: that it has no free term
+/// variables, and no free type variables in any of its annotations. Only
+/// closed values may be put in a [`Kind::Share`]
+#[derive(Default)]
+pub struct Closed {
+    terms: usize,
+    types: usize,
+    closed: bool,
+}
+
+impl Closed {
+    pub fn check(term: &Term) -> bool {
+        let mut v = Closed {
+            closed: true,
+            ..Closed::default()
+        };
+        v.visit(term);
+        v.closed
+    }
+
+    fn visit_ty(&mut self, ty: &Type) {
+        use crate::types::visit::FreeVars;
+        use crate::visit::TypeVisitor;
+        let mut fv = FreeVars {
+            cutoff: self.types,
+            vars: BTreeSet::new(),
+        };
+        fv.visit(ty);
+        self.closed &= fv.vars.is_empty();
+    }
+}
+
+impl<'a> TermVisitor<'a> for Closed {
+    fn visit_var(&mut self, sp: Span, var: usize) {
+        self.closed &= var < self.terms;
+    }
+
+    fn visit_abs(&mut self, sp: Span, ty: &Type, term: &Term) {
+        self.visit_ty(ty);
+        self.terms += 1;
+        self.visit(term);
+        self.terms -= 1;
+    }
+
+    fn visit_let(&mut self, sp: Span, pat: &Pattern, t1: &Term, t2: &Term) {
+        self.visit(t1);
+        let c = PatternCount::collect(pat);
+        self.terms += c;
+        self.visit(t2);
+        self.terms -= c;
+    }
+
+    fn visit_case(&mut self, sp: Span, term: &Term, arms: &[Arm]) {
+        self.visit(term);
+        for arm in arms {
+            let c = PatternCount::collect(&arm.pat);
+            self.terms += c;
+            if let Some(guard) = &arm.guard {
+                self.visit(guard);
+            }
+            self.visit(&arm.term);
+            self.terms -= c;
+        }
+    }
+
+    fn visit_injection(&mut self, sp: Span, label: &str, term: &Term, ty: &Type) {
+        self.visit_ty(ty);
+        self.visit(term);
+    }
+
+    fn visit_tyabs(&mut self, sp: Span, term: &Term) {
+        self.types += 1;
+        self.visit(term);
+        self.types -= 1;
+    }
+
+    fn visit_tyapp(&mut self, sp: Span, term: &Term, ty: &Type) {
+        self.visit_ty(ty);
+        self.visit(term);
+    }
+
+    fn visit_fold(&mut self, sp: Span, ty: &Type, term: &Term) {
+        self.visit_ty(ty);
+        self.visit(term);
+    }
+
+    fn visit_unfold(&mut self, sp: Span, ty: &Type, term: &Term) {
+        self.visit_ty(ty);
+        self.visit(term);
+    }
+
+    fn visit_pack(&mut self, sp: Span, wit: &Type, body: &Term, sig: &Type) {
+        self.visit_ty(wit);
+        self.visit(body);
+        self.visit_ty(sig);
+    }
+
+    fn visit_unpack(&mut self, sp: Span, package: &Term, term: &Term) {
+        self.visit(package);
+        self.terms += 1;
+        self.types += 1;
+        self.visit(term);
+        self.terms -= 1;
+        self.types -= 1;
+    }
+
+    fn visit_raise(&mut self, sp: Span, ty: &Type, term: &Term) {
+        self.visit_ty(ty);
+        self.visit(term);
+    }
+
+    fn visit_share(&mut self, sp: Span, term: &Term) {}
+
+    fn visit(&mut self, term: &'a Term) {
+        if self.closed {
+            self.walk(term);
+        }
+    }
+}
+
+/// Visitor counting the nodes of each [`Kind`] in a term
+#[derive(Debug, Default)]
+pub struct TermStats {
+    counts: HashMap<&'static str, usize>,
+}
+
+impl TermStats {
+    pub fn collect(term: &Term) -> TermStats {
+        let mut v = TermStats::default();
+        v.visit(term);
+        v
+    }
+
+    /// Number of nodes of the kind with this name, e.g. `"Abs"`
+    pub fn count(&self, kind: &str) -> usize {
+        self.counts.get(kind).copied().unwrap_or(0)
+    }
+
+    /// Total number of nodes
+    pub fn total(&self) -> usize {
+        self.counts.values().sum()
+    }
+}
+
+impl<'a> TermVisitor<'a> for TermStats {
+    fn visit(&mut self, term: &'a Term) {
+        let name = match term.kind {
+            Kind::Lit(_) => "Lit",
+            Kind::Var(_) => "Var",
+            Kind::Fix(_) => "Fix",
+            Kind::Primitive(_) => "Primitive",
+            Kind::Injection(..) => "Injection",
+            Kind::Product(_) => "Product",
+            Kind::Projection(..) => "Projection",
+            Kind::Case(..) => "Case",
+            Kind::Let(..) => "Let",
+            Kind::Abs(..) => "Abs",
+            Kind::App(..) => "App",
+            Kind::TyAbs(_) => "TyAbs",
+            Kind::TyApp(..) => "TyApp",
+            Kind::Fold(..) => "Fold",
+            Kind::Unfold(..) => "Unfold",
+            Kind::Pack(..) => "Pack",
+            Kind::Unpack(..) => "Unpack",
+            Kind::Raise(..) => "Raise",
+            Kind::Share(_) => "Share",
+        };
+        *self.counts.entry(name).or_default() += 1;
+        self.walk(term);
+    }
+}
+
+/// Visitor that folds `succ` applied to a Nat literal into a literal, so that
+/// `succ (succ 3)` becomes `5`. Partially applied `succ` is left alone
+pub struct SuccFolder;
+
+impl MutTermVisitor for SuccFolder {
+    fn visit(&mut self, term: &mut Term) {
+        self.walk(term);
+        if let Kind::App(f, arg) = &term.kind {
+            if let (Kind::Primitive(Primitive::Succ), Kind::Lit(Literal::Nat(n))) = (&f.kind, &arg.kind) {
+                if let Some(n) = n.checked_add(1) {
+                    term.kind = Kind::Lit(Literal::Nat(n));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::eval::Eval;
+    use crate::syntax::parser::Parser;
+    use crate::types::Context;
+
+    fn fold(input: &str) -> Term {
+        let mut tm = Parser::new(input).parse().unwrap();
+        SuccFolder.visit(&mut tm);
+        tm
+    }
+
+    #[test]
+    fn fold_succ_literals() {
+        let tm = fold("succ (succ 3)");
+        assert_eq!(tm.kind, Kind::Lit(Literal::Nat(5)));
+        assert_eq!(tm.to_string(), "5");
+
+        let mut ctx = Context::default();
+        assert_eq!(ctx.type_check(&tm).unwrap(), Type::Nat);
+        assert_eq!(Eval::with_context(&ctx).small_step(tm), None);
+
+        // Literal patterns now match folded terms directly
+        let tm = fold("case succ (succ 0) of | 2 => true | _ => false");
+        match &tm.kind {
+            Kind::Case(expr, _) => assert_eq!(expr.kind, Kind::Lit(Literal::Nat(2))),
+            _ => panic!("expected a case expression"),
+        }
+    }
+
+    #[test]
+    fn partial_succ() {
+        for input in &["succ", "\\x: Nat. succ x", "(\\f: Nat->Nat. f 1) succ"] {
+            let tm = Parser::new(input).parse().unwrap();
+            assert_eq!(fold(input), tm, "{}", input);
+        }
+    }
+
+    #[test]
+    fn free_term_vars() {
+        let free = |tm: &Term| FreeTermVars::collect(tm).into_iter().collect::<Vec<_>>();
+        let parse = |input: &str| Parser::new(input).parse().unwrap();
+        assert_eq!(free(&parse("\\x: Nat. \\y: Nat. (x, y)")), vec![]);
+        assert_eq!(free(&parse("let (a, b) = (1, 2) in \\X \\c: X. (a, b, c)")), vec![]);
+
+        // Variables bound outside of the term are free
+        let tm = abs!(Type::Nat, app!(var!(0), app!(var!(1), var!(3))));
+        assert_eq!(free(&tm), vec![0, 2]);
+
+        // Case arms bind their pattern variables, and type abstractions bind
+        // no term variables
+        let pair = prod!(Pattern::Variable("a".into()), Pattern::Variable("b".into()));
+        let tm = tyabs!(case!(var!(0), pair => tuple!(var!(1), var!(2)), Pattern::Any => var!(4)));
+        assert_eq!(free(&tm), vec![0, 4]);
+    }
+
+    #[test]
+    fn term_stats() {
+        let tm = Parser::new("\\X \\x: X. case (x, 1) of | (y, n) => (\\z: Nat. succ z) n")
+            .parse()
+            .unwrap();
+        let stats = TermStats::collect(&tm);
+        assert_eq!(stats.count("TyAbs"), 1);
+        assert_eq!(stats.count("Abs"), 2);
+        assert_eq!(stats.count("Case"), 1);
+        assert_eq!(stats.count("Var"), 3);
+        assert_eq!(stats.count("App"), 2);
+        assert_eq!(stats.count("Fix"), 0);
+        assert_eq!(stats.total(), 12);
+    }
+}
@@ -0,0 +1,154 @@
+//! Checking terms against an expected type
+//!
+//! Injections normally carry the full variant type, as in `Some 5 of Option`.
+//! The annotation may be left off wherever the type of the injection is
+//! already known from its surroundings: the argument of an application whose
+//! operator has an arrow type, the payload of another injection, or a
+//! component of a product in one of these positions. Such injections are
+//! parsed with a [`Type::Hole`] as their annotation, and the variant
+//! types inferred for them are recorded so that the term can be annotated
+//! with [`Context::annotate_injections`] before it is evaluated.
+use super::*;
+
+/// Is this the annotation of an injection written without `of Type`?
+pub(crate) fn is_hole(ty: &Type) -> bool {
+    matches!(ty, Type::Hole)
+}
+
+impl Context {
+    /// Type check a term in a position where its type is already known
+    pub(crate) fn check_against(&mut self, term: &Term, expected: &Type) -> Result<Type, Diagnostic> {
+        match (&term.kind, self.normalize(expected)) {
+            (Kind::Injection(label, tm, ty), expected @ Type::Variant(_)) if is_hole(ty) => {
+                let ty = self.type_check_injection(term, label, tm, &expected)?;
+                self.injections.push((term.span, ty.clone()));
+                Ok(ty)
+            }
+            (Kind::Product(terms), Type::Product(tys)) if terms.len() == tys.len() => terms
+                .iter()
+                .zip(&tys)
+                .map(|(tm, ty)| self.check_against(tm, ty))
+                .collect::<Result<_, _>>()
+                .map(Type::Product),
+            _ => self.type_check(term),
+        }
+    }
+
+    /// Fill in the annotations of unannotated injections with the variant
+    /// types inferred for them during type checking
+    pub fn annotate_injections(&mut self, term: &mut Term) {
+        let mut annotate = Annotate {
+            inferred: std::mem::take(&mut self.injections),
+        };
+        annotate.visit(term);
+    }
+}
+
+struct Annotate {
+    inferred: Vec<(Span, Type)>,
+}
+
+impl MutTermVisitor for Annotate {
+    fn visit_injection(&mut self, sp: &mut Span, label: &mut String, term: &mut Term, ty: &mut Type) {
+        if is_hole(ty) {
+            if let Some((_, inferred)) = self.inferred.iter().find(|(span, _)| span == sp) {
+                *ty = inferred.clone();
+            }
+        }
+        self.visit(term);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::syntax::parser::Parser;
+
+    fn context() -> Context {
+        let mut ctx = Context::default();
+        ctx.alias(
+            "Opt".into(),
+            Type::Variant(vec![variant!("None", Type::Unit), variant!("Some", Type::Nat)]),
+        )
+        .unwrap();
+        ctx.alias(
+            "Nested".into(),
+            Type::Variant(vec![
+                variant!("Empty", Type::Unit),
+                variant!("Full", Type::Alias("Opt".into())),
+            ]),
+        )
+        .unwrap();
+        ctx
+    }
+
+    fn parse(ctx: &mut Context, input: &str) -> Term {
+        let mut p = Parser::new(input);
+        let mut tm = p.parse().unwrap();
+        assert_eq!(p.diagnostic().error_count(), 0);
+        ctx.de_alias(&mut tm);
+        tm
+    }
+
+    fn eval(ctx: &Context, term: Term) -> Term {
+        let ev = crate::eval::Eval::with_context(ctx);
+        let mut t = term;
+        while let Some(next) = ev.small_step(t.clone()) {
+            t = next;
+        }
+        t
+    }
+
+    #[test]
+    fn inferable_positions() {
+        let mut ctx = context();
+        let cases = [
+            // Application argument, with and without a payload
+            ("(\\x: Opt. case x of | None => 0 | Some n => n) (Some 5)", "5"),
+            ("(\\x: Opt. case x of | None => 0 | Some n => n) None", "0"),
+            // Payload of an annotated injection
+            ("Full (Some 1) of Nested", "Full(Some(1))"),
+            // Component of a product argument
+            ("(\\p: (Nat, Opt). p.1) (1, Some 2)", "Some(2)"),
+        ];
+        for (input, value) in &cases {
+            let mut tm = parse(&mut ctx, input);
+            let ty = ctx.type_check(&tm).unwrap();
+            ctx.annotate_injections(&mut tm);
+            let val = eval(&ctx, tm);
+            assert_eq!(val.to_string(), *value);
+            // The evaluated term is fully annotated, and keeps its type
+            assert_eq!(ctx.type_check(&val).unwrap(), ty, "{}", input);
+        }
+    }
+
+    #[test]
+    fn cannot_infer() {
+        let mut ctx = context();
+        for (input, label) in &[("Some 5", "Some"), ("(\\x: Nat. x) (succ (None))", "None")] {
+            let tm = parse(&mut ctx, input);
+            let diag = ctx.type_check(&tm).unwrap_err();
+            assert_eq!(
+                diag.primary.info,
+                format!("cannot infer variant type for `{}`, add an annotation", label)
+            );
+        }
+
+        // The expected type must be a variant containing the label
+        let tm = parse(&mut ctx, "(\\x: Nat. x) (Some 5)");
+        assert!(ctx.type_check(&tm).is_err());
+        let tm = parse(&mut ctx, "(\\x: Opt. x) (Other 5)");
+        assert!(ctx.type_check(&tm).is_err());
+    }
+
+    #[test]
+    fn failed_check_forgets_injections() {
+        let mut ctx = context();
+        // The injection is inferred before the application fails
+        let tm = parse(&mut ctx, "(\\x: Opt. x) (Some 5) 0");
+        assert!(ctx.type_check(&tm).is_err());
+        assert!(ctx.injections.is_empty());
+        assert_eq!(ctx.type_check_all(&tm).0, None);
+        assert!(ctx.injections.is_empty());
+    }
+}
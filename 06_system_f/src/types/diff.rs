@@ -0,0 +1,186 @@
+//! Structural diffing of types, to pinpoint where two mismatched types
+//! diverge instead of printing both of them in full
+use super::*;
+
+/// One step along the path from the root of a type to a subterm
+#[derive(Clone, Debug, PartialEq)]
+pub enum Step {
+    /// Component of a product type
+    Field(usize),
+    /// Payload of a variant constructor
+    Label(String),
+    /// Parameter type of an arrow
+    Domain,
+    /// Result type of an arrow
+    Codomain,
+    /// Body of a universal, existential, or recursive type
+    Body,
+}
+
+/// A place where two types diverge
+#[derive(Clone, Debug, PartialEq)]
+pub struct Divergence {
+    pub path: Vec<Step>,
+    /// Is this divergence in a contravariant position, i.e. to the left of an
+    /// odd number of arrows?
+    pub contravariant: bool,
+    pub expected: Type,
+    pub found: Type,
+}
+
+/// Minimal description of the differences between two types
+#[derive(Clone, Debug, PartialEq)]
+pub struct TypeDiff {
+    pub divergences: Vec<Divergence>,
+}
+
+impl TypeDiff {
+    pub fn is_empty(&self) -> bool {
+        self.divergences.is_empty()
+    }
+
+    /// Do the two types share a constructor at the root? If not, the diff
+    /// isn't any more useful than printing both types
+    pub fn shared_root(&self) -> bool {
+        self.divergences.iter().all(|d| !d.path.is_empty())
+    }
+}
+
+/// Walk two types in parallel, collecting the subterms where they differ
+pub fn diff(expected: &Type, found: &Type) -> TypeDiff {
+    fn walk(expected: &Type, found: &Type, path: &mut Vec<Step>, contravariant: bool, out: &mut Vec<Divergence>) {
+        if equiv(expected, found) {
+            return;
+        }
+        let mut step = |step: Step, e: &Type, f: &Type, contravariant: bool, out: &mut Vec<Divergence>| {
+            path.push(step);
+            walk(e, f, path, contravariant, out);
+            path.pop();
+        };
+        match (expected, found) {
+            (Type::Product(a), Type::Product(b)) if a.len() == b.len() => {
+                for (idx, (e, f)) in a.iter().zip(b).enumerate() {
+                    step(Step::Field(idx), e, f, contravariant, out);
+                }
+            }
+            (Type::Variant(a), Type::Variant(b))
+                if a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.label == b.label) =>
+            {
+                for (e, f) in a.iter().zip(b) {
+                    step(Step::Label(e.label.clone()), &e.ty, &f.ty, contravariant, out);
+                }
+            }
+            (Type::Arrow(e1, e2), Type::Arrow(f1, f2)) => {
+                step(Step::Domain, e1, f1, !contravariant, out);
+                step(Step::Codomain, e2, f2, contravariant, out);
+            }
+            (Type::Universal(e), Type::Universal(f))
+            | (Type::Existential(e), Type::Existential(f))
+            | (Type::Rec(e), Type::Rec(f)) => step(Step::Body, e, f, contravariant, out),
+            _ => out.push(Divergence {
+                path: path.clone(),
+                contravariant,
+                expected: expected.clone(),
+                found: found.clone(),
+            }),
+        }
+    }
+
+    let mut divergences = Vec::new();
+    walk(expected, found, &mut Vec::new(), false, &mut divergences);
+    TypeDiff { divergences }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Step::Field(idx) => write!(f, ".{}", idx),
+            Step::Label(label) => write!(f, ".{}", label),
+            Step::Domain => write!(f, " -> domain"),
+            Step::Codomain => write!(f, " -> codomain"),
+            Step::Body => write!(f, ".body"),
+        }
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "at the root")?;
+        } else {
+            let path = self.path.iter().map(|s| s.to_string()).collect::<String>();
+            write!(f, "at `{}`", path.trim_start())?;
+        }
+        if self.contravariant {
+            write!(f, " (contravariant position)")?;
+        }
+        write!(f, ": expected `{}`, found `{}`", self.expected, self.found)
+    }
+}
+
+impl fmt::Display for TypeDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (idx, d) in self.divergences.iter().enumerate() {
+            if idx > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", d)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn arrow_domain() {
+        let expected = arrow!(arrow!(Type::Nat, Type::Bool), Type::Unit);
+        let found = arrow!(arrow!(Type::Bool, Type::Bool), Type::Unit);
+        let d = diff(&expected, &found);
+        assert!(d.shared_root());
+        assert_eq!(d.divergences.len(), 1);
+        assert_eq!(d.divergences[0].path, vec![Step::Domain, Step::Domain]);
+        // Two arrows to the left: back in a covariant position
+        assert!(!d.divergences[0].contravariant);
+        assert_eq!(d.to_string(), "at `-> domain -> domain`: expected `Nat`, found `Bool`");
+
+        let d = diff(&arrow!(Type::Nat, Type::Unit), &arrow!(Type::Bool, Type::Unit));
+        assert_eq!(
+            d.to_string(),
+            "at `-> domain` (contravariant position): expected `Nat`, found `Bool`"
+        );
+    }
+
+    #[test]
+    fn variant_payload() {
+        let option = |ty| Type::Variant(vec![variant!("None", Type::Unit), variant!("Some", ty)]);
+        let expected = Type::Product(vec![Type::Nat, Type::Bool, option(arrow!(Type::Nat, Type::Nat))]);
+        let found = Type::Product(vec![Type::Nat, Type::Bool, option(arrow!(Type::String, Type::Nat))]);
+        let d = diff(&expected, &found);
+        assert_eq!(
+            d.to_string(),
+            "at `.2.Some -> domain` (contravariant position): expected `Nat`, found `String`"
+        );
+
+        let d = diff(&option(Type::Nat), &option(Type::Nat));
+        assert!(d.is_empty());
+    }
+
+    #[test]
+    fn different_roots() {
+        let expected = Type::Product(vec![Type::Nat, Type::Bool]);
+        let found = arrow!(Type::Nat, Type::Bool);
+        let d = diff(&expected, &found);
+        assert!(!d.shared_root());
+        assert_eq!(
+            d.to_string(),
+            "at the root: expected `(Nat, Bool)`, found `Nat -> Bool`"
+        );
+
+        // Products of different lengths cannot be compared field by field
+        let d = diff(&expected, &Type::Product(vec![Type::Nat]));
+        assert!(!d.shared_root());
+    }
+}
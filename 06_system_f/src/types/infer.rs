@@ -0,0 +1,212 @@
+//! Unification-based type inference, used to support ML-style
+//! let-polymorphism as an opt-in extension of the explicitly typed calculus
+//!
+//! Unannotated lambda abstractions (`\x. t`) are parsed with a
+//! [`Type::Meta`] hole as their annotation. When
+//! [`Context::let_polymorphism`] is enabled, every hole is replaced with a
+//! fresh meta variable, which is then solved by unification. Let-bound
+//! variables are generalized over any meta variables that do not appear
+//! free in the typing context, and are instantiated with fresh meta
+//! variables at each use site. Generalized types are not wrapped in a
+//! [`Type::Universal`], since that would require an explicit type application
+//! at every use; instead, the context records which meta variables are
+//! generic. This is sound without a value restriction, as the language has no
+//! mutable references.
+//!
+//! Holes are also filled in the annotations of type applications, folds,
+//! unfolds, injections and packages. The parser never leaves these out, but terms
+//! built by other front ends may
+use super::*;
+use std::collections::HashSet;
+
+impl Context {
+    /// Generate a fresh, unsolved meta variable
+    pub(crate) fn fresh_meta(&mut self) -> Type {
+        self.metas.push(None);
+        Type::Meta(self.metas.len() as u32 - 1)
+    }
+
+    /// Replace every hole in a type annotation with a fresh meta variable
+    pub(crate) fn fill_holes(&mut self, ty: &Type) -> Type {
+        self.map_metas(ty, &mut |ctx, _| ctx.fresh_meta())
+    }
+
+    /// The type written in an annotation, normalized. When let-polymorphism
+    /// is enabled, holes may be left in it, and are filled in here
+    pub(crate) fn annotation(&mut self, ty: &Type) -> Type {
+        match has_holes(ty) && self.let_polymorphism {
+            true => self.fill_holes(&self.normalize(ty)),
+            false => self.normalize(ty),
+        }
+    }
+
+    /// Apply the current substitution to a type, replacing every solved meta
+    /// variable with its solution
+    pub fn zonk(&self, ty: &Type) -> Type {
+        match ty {
+            Type::Meta(m) => match self.metas.get(*m as usize) {
+                Some(Some(solved)) => self.zonk(solved),
+                _ => ty.clone(),
+            },
+            Type::Variant(vs) => Type::Variant(
+                vs.iter()
+                    .map(|v| Variant {
+                        label: v.label.clone(),
+                        ty: self.zonk(&v.ty),
+                    })
+                    .collect(),
+            ),
+            Type::Product(tys) => Type::Product(tys.iter().map(|ty| self.zonk(ty)).collect()),
+            Type::Arrow(t1, t2) => Type::Arrow(Box::new(self.zonk(t1)), Box::new(self.zonk(t2))),
+            Type::Universal(ty) => Type::Universal(Box::new(self.zonk(ty))),
+            Type::Existential(ty) => Type::Existential(Box::new(self.zonk(ty))),
+            Type::Rec(ty) => Type::Rec(Box::new(self.zonk(ty))),
+            _ => ty.clone(),
+        }
+    }
+
+    /// Rebuild a type, replacing each distinct meta variable using `f`
+    fn map_metas<F>(&mut self, ty: &Type, f: &mut F) -> Type
+    where
+        F: FnMut(&mut Context, u32) -> Type,
+    {
+        fn walk<F: FnMut(&mut Context, u32) -> Type>(
+            ctx: &mut Context,
+            ty: &Type,
+            f: &mut F,
+            seen: &mut HashMap<u32, Type>,
+        ) -> Type {
+            match ty {
+                Type::Meta(m) => match seen.get(m) {
+                    Some(ty) => ty.clone(),
+                    None => {
+                        let ty = f(ctx, *m);
+                        seen.insert(*m, ty.clone());
+                        ty
+                    }
+                },
+                Type::Variant(vs) => Type::Variant(
+                    vs.iter()
+                        .map(|v| Variant {
+                            label: v.label.clone(),
+                            ty: walk(ctx, &v.ty, f, seen),
+                        })
+                        .collect(),
+                ),
+                Type::Product(tys) => Type::Product(tys.iter().map(|ty| walk(ctx, ty, f, seen)).collect()),
+                Type::Arrow(t1, t2) => Type::Arrow(Box::new(walk(ctx, t1, f, seen)), Box::new(walk(ctx, t2, f, seen))),
+                Type::Universal(ty) => Type::Universal(Box::new(walk(ctx, ty, f, seen))),
+                Type::Existential(ty) => Type::Existential(Box::new(walk(ctx, ty, f, seen))),
+                Type::Rec(ty) => Type::Rec(Box::new(walk(ctx, ty, f, seen))),
+                _ => ty.clone(),
+            }
+        }
+        walk(self, ty, f, &mut HashMap::new())
+    }
+
+    /// Collect the unsolved meta variables appearing in a type
+    fn free_metas(&self, ty: &Type, acc: &mut HashSet<u32>) {
+        match self.zonk(ty) {
+            Type::Meta(m) => {
+                acc.insert(m);
+            }
+            Type::Variant(vs) => vs.iter().for_each(|v| self.free_metas(&v.ty, acc)),
+            Type::Product(tys) => tys.iter().for_each(|ty| self.free_metas(ty, acc)),
+            Type::Arrow(t1, t2) => {
+                self.free_metas(&t1, acc);
+                self.free_metas(&t2, acc);
+            }
+            Type::Universal(ty) | Type::Existential(ty) | Type::Rec(ty) => self.free_metas(&ty, acc),
+            _ => {}
+        }
+    }
+
+    /// Generalize the type of a let-bound variable over every meta variable
+    /// that is not free in the typing context
+    pub(crate) fn generalize(&mut self, ty: &Type) -> Type {
+        let ty = self.zonk(ty);
+        let mut in_ctx = HashSet::new();
+        for bound in &self.stack {
+            self.free_metas(bound, &mut in_ctx);
+        }
+        let mut metas = HashSet::new();
+        self.free_metas(&ty, &mut metas);
+        self.generic.extend(metas.difference(&in_ctx));
+        ty
+    }
+
+    /// Instantiate every generalized meta variable in a type with a fresh
+    /// meta variable
+    pub(crate) fn instantiate(&mut self, ty: &Type) -> Type {
+        let ty = self.zonk(ty);
+        self.map_metas(&ty, &mut |ctx, m| {
+            if ctx.generic.contains(&m) {
+                ctx.fresh_meta()
+            } else {
+                Type::Meta(m)
+            }
+        })
+    }
+
+    /// Does meta variable `m` occur in `ty`?
+    fn occurs(&self, m: u32, ty: &Type) -> bool {
+        let mut metas = HashSet::new();
+        self.free_metas(ty, &mut metas);
+        metas.contains(&m)
+    }
+
+    /// Attempt to unify two types, solving meta variables as needed. Returns
+    /// false if the types cannot be made equal
+    pub fn unify(&mut self, a: &Type, b: &Type) -> bool {
+        let a = self.normalize(&self.zonk(a));
+        let b = self.normalize(&self.zonk(b));
+        match (&a, &b) {
+            (Type::Error, _) | (_, Type::Error) => true,
+            (Type::Meta(x), Type::Meta(y)) if x == y => true,
+            (Type::Meta(m), ty) | (ty, Type::Meta(m)) => {
+                if self.occurs(*m, ty) {
+                    false
+                } else {
+                    self.metas[*m as usize] = Some(ty.clone());
+                    true
+                }
+            }
+            (Type::Variant(a), Type::Variant(b)) => {
+                a.len() == b.len()
+                    && a.iter()
+                        .zip(b)
+                        .all(|(a, b)| a.label == b.label && self.unify(&a.ty, &b.ty))
+            }
+            (Type::Product(a), Type::Product(b)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|(a, b)| self.unify(a, b))
+            }
+            (Type::Arrow(a1, a2), Type::Arrow(b1, b2)) => self.unify(a1, b1) && self.unify(a2, b2),
+            (Type::Universal(a), Type::Universal(b)) => self.unify(a, b),
+            (Type::Existential(a), Type::Existential(b)) => self.unify(a, b),
+            (Type::Rec(a), Type::Rec(b)) => self.unify(a, b),
+            _ => a == b,
+        }
+    }
+
+    /// Check that the type `found` is compatible with `expected`. When
+    /// let-polymorphism is enabled, this may solve meta variables
+    pub(crate) fn compatible(&mut self, expected: &Type, found: &Type) -> bool {
+        if self.let_polymorphism {
+            self.unify(expected, found)
+        } else {
+            self.type_eq(expected, found)
+        }
+    }
+}
+
+/// Does a type annotation contain a hole that needs to be inferred?
+pub(crate) fn has_holes(ty: &Type) -> bool {
+    match ty {
+        Type::Meta(_) => true,
+        Type::Variant(vs) => vs.iter().any(|v| has_holes(&v.ty)),
+        Type::Product(tys) => tys.iter().any(has_holes),
+        Type::Arrow(t1, t2) => has_holes(t1) || has_holes(t2),
+        Type::Universal(ty) | Type::Existential(ty) | Type::Rec(ty) => has_holes(ty),
+        _ => false,
+    }
+}
@@ -0,0 +1,126 @@
+//! Typing contexts at a position in the source, so that the subterm found
+//! with [`Term::find_at`] can be type checked on its own
+use super::*;
+use crate::patterns::PatTyStack;
+use crate::terms::Arm;
+use std::ptr;
+
+impl Context {
+    /// The context in which the innermost subterm of `term` containing
+    /// `offset` is type checked, with the variables bound by every enclosing
+    /// abstraction, let, case arm and unpack in scope. Terms bound elsewhere
+    /// in `term` may have to be type checked to find the types of those
+    /// variables, and any error in them is returned
+    pub fn context_at(&self, term: &Term, offset: usize) -> Result<Context, Diagnostic> {
+        let mut ctx = self.clone();
+        for pair in term.enclosing(offset).windows(2) {
+            ctx.enter(pair[0], pair[1])?;
+        }
+        Ok(ctx)
+    }
+
+    /// Type check the innermost subterm of `term` containing `offset`
+    pub fn type_at(&self, term: &Term, offset: usize) -> Option<Result<Type, Diagnostic>> {
+        let sub = term.find_at(offset)?;
+        Some(self.context_at(term, offset).and_then(|mut ctx| ctx.type_check(sub)))
+    }
+
+    /// Bind the variables that `parent` brings into scope for `child`
+    fn enter(&mut self, parent: &Term, child: &Term) -> Result<(), Diagnostic> {
+        match &parent.kind {
+            Kind::Abs(ty, _) => {
+                let ty = match infer::has_holes(ty) && self.let_polymorphism {
+                    true => self.fill_holes(&self.normalize(ty)),
+                    false => self.normalize(ty),
+                };
+                self.push(ty);
+            }
+            Kind::Let(pat, t1, t2) if ptr::eq(child, t2.as_ref()) => {
+                let ty = self.type_check_bound(pat, t1)?;
+                for b in PatTyStack::collect(&ty, pat).into_iter().rev() {
+                    self.push(b);
+                }
+            }
+            Kind::Case(expr, arms) if !ptr::eq(child, expr.as_ref()) => {
+                let in_arm = |arm: &&Arm| {
+                    ptr::eq(child, arm.term.as_ref()) || arm.guard.as_ref().is_some_and(|g| ptr::eq(child, g.as_ref()))
+                };
+                if let Some(arm) = arms.iter().find(in_arm) {
+                    let ty = self.type_check(expr)?;
+                    let ty = self.normalize(&ty);
+                    for b in PatTyStack::collect(&ty, &arm.pat).into_iter().rev() {
+                        self.push(b);
+                    }
+                }
+            }
+            Kind::TyAbs(_) => {
+                for ty in self.stack.iter_mut() {
+                    if let Type::Var(v) = ty {
+                        *v += 1;
+                    }
+                }
+                self.tyvars += 1;
+            }
+            Kind::Unpack(package, body) if ptr::eq(child, body.as_ref()) => {
+                match self.type_check(package)? {
+                    Type::Existential(xst) => self.push(*xst),
+                    _ => self.push(Type::Error),
+                }
+                self.tyvars += 1;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::syntax::parser::Parser;
+
+    const SOURCE: &str = "let f = \\x: Nat. succ x in \
+                          case (f 1, true) of | (n, b) => (if b then n else f n)";
+
+    fn parse(input: &str) -> Term {
+        let mut p = Parser::new(input);
+        let tm = p.parse().unwrap();
+        assert_eq!(p.diagnostic().error_count(), 0);
+        tm
+    }
+
+    #[test]
+    fn types_at_offsets() {
+        let ctx = Context::default();
+        let tm = parse(SOURCE);
+        let cases = [
+            ("x in", "Nat"),
+            ("f 1", "Nat -> Nat"),
+            ("(f 1", "(Nat, Bool)"),
+            ("b then", "Bool"),
+            ("n else", "Nat"),
+            ("f n", "Nat -> Nat"),
+        ];
+        for (needle, ty) in &cases {
+            let offset = SOURCE.find(needle).unwrap();
+            let found = ctx.type_at(&tm, offset).unwrap().unwrap();
+            assert_eq!(found.to_string(), *ty, "{}", needle);
+        }
+
+        // Only the variables in scope at the offset are bound
+        let offset = SOURCE.find("x in").unwrap();
+        assert_eq!(ctx.context_at(&tm, offset).unwrap().stack.len(), 1);
+        let offset = SOURCE.rfind('n').unwrap();
+        assert_eq!(ctx.context_at(&tm, offset).unwrap().stack.len(), 3);
+        assert!(ctx.type_at(&tm, SOURCE.len()).is_none());
+    }
+
+    #[test]
+    fn type_variables() {
+        let ctx = Context::default();
+        let source = "\\X \\x: X. x";
+        let tm = parse(source);
+        let ty = ctx.type_at(&tm, source.len() - 1).unwrap().unwrap();
+        assert_eq!(ty, Type::Var(0));
+    }
+}
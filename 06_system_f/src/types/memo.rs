@@ -0,0 +1,257 @@
+//! Memoization of type equality checks
+//!
+//! Programs that use large types tend to compare the same types over and
+//! over again, e.g. a big variant type at every call site of a function that
+//! accepts it. Normalizing and comparing such types each time is expensive,
+//! so the results of [`Context::type_eq`] are cached, keyed by the structural
+//! hashes of the types being compared. The cache only lives for a single
+//! top-level [`Context::type_check`], and is emptied if it grows too large.
+use super::*;
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+/// A type along with its structural hash, which is computed once when the
+/// type is wrapped. Clones share the type, so that comparing a type with a
+/// clone of itself is a pointer comparison
+#[derive(Clone, Debug)]
+pub struct HashedType {
+    hash: u64,
+    ty: Rc<Type>,
+}
+
+impl HashedType {
+    pub fn new(ty: Type) -> HashedType {
+        HashedType {
+            hash: structural_hash(&ty),
+            ty: Rc::new(ty),
+        }
+    }
+
+    /// Is this the type `ty`, whose hash is `hash`? Types with different
+    /// hashes are never the same, and a type is always the same as itself,
+    /// so the types are only compared in depth when their hashes collide
+    fn is(&self, ty: &Type, hash: u64) -> bool {
+        self.hash == hash && (std::ptr::eq(&*self.ty, ty) || *self.ty == *ty)
+    }
+}
+
+/// Structural hash of a type
+fn structural_hash(ty: &Type) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    ty.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl std::ops::Deref for HashedType {
+    type Target = Type;
+
+    fn deref(&self) -> &Type {
+        &self.ty
+    }
+}
+
+impl PartialEq for HashedType {
+    fn eq(&self, other: &HashedType) -> bool {
+        self.is(&other.ty, other.hash)
+    }
+}
+
+impl Eq for HashedType {}
+
+impl Hash for HashedType {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.hash)
+    }
+}
+
+/// A pair of types that have been compared, and whether they are equal
+type Entry = (HashedType, HashedType, bool);
+
+/// Number of results kept before the cache is emptied
+const CAPACITY: usize = 4096;
+
+/// Cache of type equality results. Since equality is checked up to alias
+/// expansion, the cache must be invalidated whenever an alias is defined or
+/// removed
+#[derive(Clone, Debug)]
+pub struct EqCache {
+    pub(super) enabled: bool,
+    /// Results keyed by the hashes of both types, which are stored with the
+    /// types so that they are never computed twice for the same type
+    table: RefCell<HashMap<(u64, u64), Vec<Entry>>>,
+    /// Number of results found in the table, rather than computed
+    hits: Cell<usize>,
+}
+
+impl Default for EqCache {
+    fn default() -> EqCache {
+        EqCache {
+            enabled: true,
+            table: RefCell::new(HashMap::new()),
+            hits: Cell::new(0),
+        }
+    }
+}
+
+impl EqCache {
+    pub fn invalidate(&mut self) {
+        self.table.get_mut().clear();
+    }
+}
+
+impl Context {
+    /// Are two types equal, after normalization?
+    pub fn type_eq(&self, a: &Type, b: &Type) -> bool {
+        if !self.eq_cache.enabled {
+            return equiv(&self.normalize(a), &self.normalize(b));
+        }
+        let (hash_a, hash_b) = (structural_hash(a), structural_hash(b));
+        self.memo_eq(a, hash_a, b, hash_b, || {
+            (
+                HashedType {
+                    hash: hash_a,
+                    ty: Rc::new(a.clone()),
+                },
+                HashedType {
+                    hash: hash_b,
+                    ty: Rc::new(b.clone()),
+                },
+            )
+        })
+    }
+
+    /// [`Context::type_eq`] for types that have already been hashed. A type
+    /// compared again while it is still shared with the cache is found there
+    /// by pointer
+    pub fn hashed_type_eq(&self, a: &HashedType, b: &HashedType) -> bool {
+        match self.eq_cache.enabled {
+            true => self.memo_eq(a, a.hash, b, b.hash, || (a.clone(), b.clone())),
+            false => equiv(&self.normalize(a), &self.normalize(b)),
+        }
+    }
+
+    /// Look up the result for `a` and `b` in the cache, or compute it and
+    /// store it along with the types made by `entry`
+    fn memo_eq<F>(&self, a: &Type, hash_a: u64, b: &Type, hash_b: u64, entry: F) -> bool
+    where
+        F: FnOnce() -> (HashedType, HashedType),
+    {
+        let key = (hash_a, hash_b);
+        if let Some(bucket) = self.eq_cache.table.borrow().get(&key) {
+            if let Some((_, _, eq)) = bucket.iter().find(|(a_, b_, _)| a_.is(a, hash_a) && b_.is(b, hash_b)) {
+                self.eq_cache.hits.set(self.eq_cache.hits.get() + 1);
+                return *eq;
+            }
+        }
+        let eq = equiv(&self.normalize(a), &self.normalize(b));
+        let mut table = self.eq_cache.table.borrow_mut();
+        if table.len() >= CAPACITY {
+            table.clear();
+        }
+        let (a, b) = entry();
+        table.entry(key).or_default().push((a, b, eq));
+        eq
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::syntax::parser::Parser;
+    use std::time::Instant;
+
+    #[test]
+    fn invalidate_on_alias_change() {
+        let mut ctx = Context::default();
+        let alias = Type::Alias("A".into());
+        ctx.alias("A".into(), Type::Nat).unwrap();
+        assert!(ctx.type_eq(&alias, &Type::Nat));
+
+        let checkpoint = ctx.checkpoint();
+        ctx.alias("A".into(), Type::Bool).unwrap();
+        assert!(!ctx.type_eq(&alias, &Type::Nat));
+        assert!(ctx.type_eq(&alias, &Type::Bool));
+
+        ctx.rollback(checkpoint);
+        assert!(ctx.type_eq(&alias, &Type::Nat));
+        assert!(!ctx.type_eq(&alias, &Type::Bool));
+    }
+
+    #[test]
+    fn hits() {
+        let mut ctx = Context::default();
+        let alias = HashedType::new(Type::Alias("A".into()));
+        let nat = HashedType::new(Type::Nat);
+        ctx.alias("A".into(), Type::Nat).unwrap();
+        assert!(ctx.hashed_type_eq(&alias, &nat));
+        // The cache keeps the types themselves, so they are found by pointer
+        assert_eq!(Rc::strong_count(&alias.ty), 2);
+        assert!(ctx.hashed_type_eq(&alias, &nat));
+        // Equal types that are not shared are found as well
+        assert!(ctx.type_eq(&Type::Alias("A".into()), &Type::Nat));
+        assert_eq!(ctx.eq_cache.hits.get(), 2);
+
+        // Defining an alias forgets every result, so the pair is compared
+        // again under the new definition
+        ctx.alias("A".into(), Type::Bool).unwrap();
+        assert!(ctx.eq_cache.table.borrow().is_empty());
+        assert!(!ctx.hashed_type_eq(&alias, &nat));
+        assert_eq!(ctx.eq_cache.hits.get(), 2);
+        assert!(!ctx.hashed_type_eq(&alias, &nat));
+        assert_eq!(ctx.eq_cache.hits.get(), 3);
+    }
+
+    #[test]
+    fn bounded() {
+        let mut ctx = Context::default();
+        let vars = (0..CAPACITY + 10).map(Type::Var).collect::<Vec<_>>();
+        for ty in &vars {
+            assert!(ctx.type_eq(ty, ty));
+        }
+        assert!(ctx.eq_cache.table.borrow().len() <= CAPACITY);
+
+        // Results do not outlive the term they were found for
+        let tm = Parser::new("(\\x: Nat. x) 0").parse().unwrap();
+        ctx.type_check(&tm).unwrap();
+        assert_eq!(ctx.eq_cache.table.borrow().len(), 1);
+    }
+
+    /// Typecheck a program using a 100-field variant at 200 call sites, with
+    /// and without memoization. Run with
+    /// `cargo test --release -- --ignored --nocapture memoized_type_eq`
+    #[test]
+    #[ignore]
+    fn memoized_type_eq() {
+        let big = Type::Variant(
+            (0..100)
+                .map(|i| Variant {
+                    label: format!("L{}", i),
+                    ty: Type::Product(vec![Type::Nat, Type::Bool, arrow!(Type::Nat, Type::Nat)]),
+                })
+                .collect(),
+        );
+        let calls = (0..200)
+            .map(|i| format!("f (L{} (0, true, \\x: Nat. x) of Big)", i % 100))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let input = format!("let f = \\x: Big. x in ({})", calls);
+        let tm = Parser::new(&input).parse().unwrap();
+
+        for &enabled in &[false, true] {
+            let mut ctx = Context::default();
+            ctx.alias("Big".into(), big.clone()).unwrap();
+            ctx.eq_cache.enabled = enabled;
+            let start = Instant::now();
+            for _ in 0..10 {
+                ctx.type_check(&tm).unwrap();
+            }
+            println!(
+                "memoization {}: {:?}",
+                if enabled { "on" } else { "off" },
+                start.elapsed()
+            );
+        }
+    }
+}
@@ -1,20 +1,29 @@
 //! Typechecking of the simply typed lambda calculus with parametric
 //! polymorphism
+mod check;
+pub mod diff;
+mod infer;
+mod locate;
+mod memo;
 pub mod patterns;
 pub mod visit;
 use crate::diagnostics::*;
+use crate::patterns::Pattern;
 use crate::terms::{Kind, Literal, Primitive, Term};
-use crate::visit::{MutTermVisitor, MutTypeVisitor};
-use std::collections::{HashMap, VecDeque};
+use crate::visit::{MutTermVisitor, MutTypeVisitor, TypeVisitor};
+pub use diff::{diff, TypeDiff};
+pub use memo::HashedType;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
 use std::fmt;
 use util::span::Span;
-use visit::{Shift, Subst};
+use visit::{FreeVars, Shift, Subst};
 
 #[derive(Clone, PartialEq, PartialOrd, Eq, Hash)]
 pub enum Type {
     Unit,
     Nat,
     Bool,
+    String,
     Alias(String),
     Var(usize),
     Variant(Vec<Variant>),
@@ -23,6 +32,17 @@ pub enum Type {
     Universal(Box<Type>),
     Existential(Box<Type>),
     Rec(Box<Type>),
+    /// Poison type, assigned to terms that failed to typecheck when
+    /// errors are being accumulated. It is compatible with every other type,
+    /// so that a single mistake does not cascade into more errors
+    Error,
+    /// Meta variable standing for a type that has yet to be inferred. The
+    /// parser emits these as holes in place of missing lambda annotations,
+    /// which are only accepted when [`Context::let_polymorphism`] is enabled
+    Meta(u32),
+    /// Annotation of an injection written without `of Type`, to be replaced
+    /// by the variant type inferred for it. See the [`check`] module
+    Hole,
 }
 
 #[derive(Clone, PartialEq, PartialOrd, Eq, Hash)]
@@ -40,24 +60,139 @@ pub struct TypeError {
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub enum TypeErrorKind {
     ParameterMismatch(Box<Type>, Box<Type>, Span),
-
+    /// A term does not have the type its context requires
+    TypeMismatch,
     InvalidProjection,
     NotArrow,
     NotUniversal,
+    NotExistential,
     NotVariant,
     NotProduct,
     NotRec,
+    /// An injection with a label that its variant type does not have
+    UnknownConstructor,
     IncompatibleArms,
     InvalidPattern,
     NotExhaustive,
     UnreachablePattern,
+    OrPatternBinding(String),
+    DuplicatePatternBinding(String),
+    RefutableLetPattern,
     UnboundVariable(usize),
+    UnboundTypeVariable(usize),
+    NegativeOccurrence,
+    /// A lambda or injection without a type that cannot be inferred
+    MissingAnnotation,
+    DuplicateAlias,
+}
+
+impl TypeErrorKind {
+    /// A code for this kind of error that stays the same as messages
+    /// change, for scripts reading diagnostics. Codes are never reused, and
+    /// are shown as `E0101` and so on
+    pub fn code(&self) -> u16 {
+        match self {
+            TypeErrorKind::ParameterMismatch(..) => 101,
+            TypeErrorKind::TypeMismatch => 102,
+            TypeErrorKind::InvalidProjection => 103,
+            TypeErrorKind::NotArrow => 104,
+            TypeErrorKind::NotUniversal => 105,
+            TypeErrorKind::NotExistential => 106,
+            TypeErrorKind::NotVariant => 107,
+            TypeErrorKind::NotProduct => 108,
+            TypeErrorKind::NotRec => 109,
+            TypeErrorKind::UnknownConstructor => 110,
+            TypeErrorKind::IncompatibleArms => 111,
+            TypeErrorKind::InvalidPattern => 112,
+            TypeErrorKind::NotExhaustive => 113,
+            TypeErrorKind::UnreachablePattern => 114,
+            TypeErrorKind::OrPatternBinding(_) => 115,
+            TypeErrorKind::DuplicatePatternBinding(_) => 116,
+            TypeErrorKind::RefutableLetPattern => 117,
+            TypeErrorKind::UnboundVariable(_) => 118,
+            TypeErrorKind::UnboundTypeVariable(_) => 119,
+            TypeErrorKind::NegativeOccurrence => 120,
+            TypeErrorKind::MissingAnnotation => 121,
+            TypeErrorKind::DuplicateAlias => 122,
+        }
+    }
+}
+
+impl From<TypeError> for Diagnostic {
+    fn from(err: TypeError) -> Diagnostic {
+        Diagnostic::error(err.span, err.kind.to_string()).code(err.kind.code())
+    }
 }
 
-#[derive(Clone, Debug, Default, PartialEq)]
+#[derive(Clone, Debug, Default)]
 pub struct Context {
     stack: VecDeque<Type>,
     map: HashMap<String, Type>,
+    /// Number of type variables bound by enclosing type abstractions
+    tyvars: usize,
+    /// Reject recursive types whose bound variable occurs in a negative
+    /// position. This is off by default, since some classic examples rely on
+    /// negative recursion
+    pub strict_positivity: bool,
+    /// Infer the types of unannotated lambda abstractions, and generalize
+    /// the types of let-bound variables, ML-style. See the [`infer`] module
+    pub let_polymorphism: bool,
+    /// Solutions for meta variables, indexed by meta variable number
+    metas: Vec<Option<Type>>,
+    /// Meta variables that have been generalized at a let binding, and are
+    /// instantiated afresh at every use
+    generic: HashSet<u32>,
+    /// Errors recorded so far, if we are accumulating them instead of
+    /// stopping at the first one
+    errors: Option<Vec<Diagnostic>>,
+    /// Warnings recorded so far, such as unreachable case arms. These never
+    /// cause type checking to fail
+    warnings: Vec<Diagnostic>,
+    /// Undo log of alias definitions, storing the previous definition (if
+    /// any) of every alias that has been registered
+    alias_log: Vec<(String, Option<Type>)>,
+    /// Spans of the aliases declared in source, by name. See
+    /// [`Context::declare_alias`]
+    declared: HashMap<String, Span>,
+    /// Memoized results of type equality checks
+    eq_cache: memo::EqCache,
+    /// Variant types inferred for unannotated injections, by span. See the
+    /// [`check`] module
+    injections: Vec<(Span, Type)>,
+    /// Number of calls to [`Context::type_check`] in progress, so that state
+    /// left over from checking a previous term can be discarded at the start
+    /// of a top-level one
+    nesting: usize,
+    /// Spans of the case arms found to be unreachable, to be removed by
+    /// [`Context::prune_unreachable`]
+    unreachable: Vec<Span>,
+    /// Variables bound by the pattern of every case arm, by the span of the
+    /// arm. See [`Context::arm_bindings`]
+    bindings: Vec<(Span, Vec<patterns::Binding>)>,
+}
+
+/// Saved state of a [`Context`], which can be restored with
+/// [`Context::rollback`]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Checkpoint {
+    depth: usize,
+    tyvars: usize,
+    aliases: usize,
+}
+
+impl Type {
+    /// The type variables occurring free in this type, as de Bruijn indices
+    /// relative to the outside of the type
+    pub fn free_vars(&self) -> BTreeSet<usize> {
+        let mut fv = FreeVars::default();
+        fv.visit(self);
+        fv.vars
+    }
+
+    /// Does the type variable `k` occur free in this type?
+    pub fn occurs(&self, k: usize) -> bool {
+        self.free_vars().contains(&k)
+    }
 }
 
 impl Context {
@@ -73,12 +208,166 @@ impl Context {
         self.stack.get(idx)
     }
 
-    pub fn alias(&mut self, alias: String, ty: Type) {
-        self.map.insert(alias, ty);
+    /// Check that a type annotation is well-formed, i.e. that every type
+    /// variable is bound either by a binder within the type itself, or by an
+    /// enclosing type abstraction
+    pub fn wf(&self, ty: &Type, span: Span) -> Result<(), TypeError> {
+        match ty.free_vars().range(self.tyvars..).next() {
+            Some(idx) => Err(TypeError {
+                span,
+                kind: TypeErrorKind::UnboundTypeVariable(*idx),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Check that every recursive type appearing in `ty` is strictly
+    /// positive, i.e. that the recursive variable never appears to the left
+    /// of an arrow
+    pub fn positivity(&self, ty: &Type, span: Span) -> Result<(), TypeError> {
+        fn occurs_positively(ty: &Type, var: usize, positive: bool) -> bool {
+            match ty {
+                Type::Var(idx) => *idx != var || positive,
+                Type::Variant(vs) => vs.iter().all(|v| occurs_positively(&v.ty, var, positive)),
+                Type::Product(tys) => tys.iter().all(|ty| occurs_positively(ty, var, positive)),
+                Type::Arrow(ty1, ty2) => {
+                    occurs_positively(ty1, var, !positive) && occurs_positively(ty2, var, positive)
+                }
+                Type::Universal(ty) | Type::Existential(ty) | Type::Rec(ty) => occurs_positively(ty, var + 1, positive),
+                _ => true,
+            }
+        }
+
+        fn walk(ty: &Type) -> bool {
+            match ty {
+                Type::Rec(inner) => occurs_positively(inner, 0, true) && walk(inner),
+                Type::Variant(vs) => vs.iter().all(|v| walk(&v.ty)),
+                Type::Product(tys) => tys.iter().all(walk),
+                Type::Arrow(ty1, ty2) => walk(ty1) && walk(ty2),
+                Type::Universal(ty) | Type::Existential(ty) => walk(ty),
+                _ => true,
+            }
+        }
+
+        if walk(ty) {
+            Ok(())
+        } else {
+            Err(TypeError {
+                span,
+                kind: TypeErrorKind::NegativeOccurrence,
+            })
+        }
+    }
+
+    pub fn alias(&mut self, alias: String, ty: Type) -> Result<(), TypeError> {
+        if self.strict_positivity {
+            self.positivity(&ty, Span::zero())?;
+        }
+        let prev = self.map.insert(alias.clone(), ty);
+        self.alias_log.push((alias, prev));
+        self.eq_cache.invalidate();
+        Ok(())
+    }
+
+    /// Register an alias declared with `type Name = T;` in source. The type
+    /// is expanded first, so that it may refer to aliases declared before
+    /// it, and the name must not already be an alias
+    pub fn declare_alias(&mut self, alias: String, ty: Type, span: Span) -> Result<(), Diagnostic> {
+        if self.map.contains_key(&alias) {
+            let diag = Diagnostic::error(span, format!("type `{}` is already defined", alias))
+                .code(TypeErrorKind::DuplicateAlias.code());
+            return Err(match self.declared.get(&alias) {
+                Some(prev) => diag.message(*prev, "previously defined here"),
+                None => diag,
+            });
+        }
+        let ty = self.normalize(&ty);
+        if self.strict_positivity {
+            self.positivity(&ty, span)?;
+        }
+        self.declared.insert(alias.clone(), span);
+        self.alias(alias, ty).map_err(Diagnostic::from)
+    }
+
+    /// Save the current state of the typing context, so that speculative
+    /// changes can be thrown away with [`Context::rollback`]
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            depth: self.stack.len(),
+            tyvars: self.tyvars,
+            aliases: self.alias_log.len(),
+        }
+    }
+
+    /// Restore the typing context to the state it was in when `checkpoint`
+    /// was created, discarding any bindings and aliases added since then
+    pub fn rollback(&mut self, checkpoint: Checkpoint) {
+        while self.stack.len() > checkpoint.depth {
+            self.pop();
+        }
+        self.tyvars = checkpoint.tyvars;
+        while self.alias_log.len() > checkpoint.aliases {
+            if let Some((alias, prev)) = self.alias_log.pop() {
+                match prev {
+                    Some(ty) => self.map.insert(alias, ty),
+                    None => {
+                        self.declared.remove(&alias);
+                        self.map.remove(&alias)
+                    }
+                };
+                self.eq_cache.invalidate();
+            }
+        }
+    }
+
+    /// Run `f` speculatively, rolling back any changes it made to the
+    /// context if it returns an error
+    pub fn with_checkpoint<T, E, F>(&mut self, f: F) -> Result<T, E>
+    where
+        F: FnOnce(&mut Context) -> Result<T, E>,
+    {
+        let checkpoint = self.checkpoint();
+        let res = f(self);
+        if res.is_err() {
+            self.rollback(checkpoint);
+        }
+        res
     }
 
     fn aliaser(&self) -> Aliaser<'_> {
-        Aliaser { map: &self.map }
+        Aliaser {
+            map: &self.map,
+            expanding: Vec::new(),
+        }
+    }
+
+    /// Return the canonical form of a type, in which every alias known to
+    /// this context has been fully expanded. All type equality checks
+    /// performed by the typechecker are done on normalized types
+    pub fn normalize(&self, ty: &Type) -> Type {
+        let mut ty = ty.clone();
+        self.aliaser().visit(&mut ty);
+        ty
+    }
+
+    /// Normalize a type, additionally unfolding a top-level recursive type
+    /// once: `rec X. T` becomes `[X -> rec X. T] T`
+    pub fn normalize_unfold(&self, ty: &Type) -> Type {
+        match self.normalize(ty) {
+            Type::Rec(inner) => subst(Type::Rec(inner.clone()), *inner),
+            ty => ty,
+        }
+    }
+
+    /// Attach a description of where two mismatched types diverge to a
+    /// diagnostic, if they share a constructor at the root
+    fn explain_mismatch(&self, diag: Diagnostic, span: Span, expected: &Type, found: &Type) -> Diagnostic {
+        let diff = diff(&self.normalize(expected), &self.normalize(found));
+        if diff.shared_root() && !diff.is_empty() {
+            diag.message(span, format!("types differ {}", diff))
+        } else {
+            diag
+        }
     }
 
     pub fn de_alias(&mut self, term: &mut Term) {
@@ -93,10 +382,10 @@ pub fn variant_field<'vs>(var: &'vs [Variant], label: &str, span: Span) -> Resul
             return Ok(&f.ty);
         }
     }
-    Err(Diagnostic::error(
-        span,
-        format!("constructor {} doesn't appear in variant fields", label),
-    ))
+    Err(
+        Diagnostic::error(span, format!("constructor {} doesn't appear in variant fields", label))
+            .code(TypeErrorKind::UnknownConstructor.code()),
+    )
 
     // Err(TypeError {
     //     span,
@@ -105,128 +394,308 @@ pub fn variant_field<'vs>(var: &'vs [Variant], label: &str, span: Span) -> Resul
 }
 
 impl Context {
+    /// Type check a term, returning the first error encountered
     pub fn type_check(&mut self, term: &Term) -> Result<Type, Diagnostic> {
+        if self.nesting == 0 {
+            self.injections.clear();
+            self.eq_cache.invalidate();
+            // Meta variables are numbered afresh for every term, unless a
+            // binding in scope may still refer to them
+            if self.stack.is_empty() {
+                self.metas.clear();
+                self.generic.clear();
+            }
+        }
+        let checkpoint = self.checkpoint();
+        self.nesting += 1;
+        let res = self.type_check_term(term).map(|ty| match self.let_polymorphism {
+            true => self.zonk(&ty),
+            false => ty,
+        });
+        self.nesting -= 1;
+        debug_assert_eq!(checkpoint, self.checkpoint(), "unbalanced typing context");
+        if self.nesting == 0 && res.is_err() {
+            // The term will not be annotated
+            self.injections.clear();
+        }
+        match (res, &mut self.errors) {
+            (Err(diag), Some(errors)) => {
+                let dup = errors
+                    .iter()
+                    .any(|e| e.primary.span == diag.primary.span && e.primary.info == diag.primary.info);
+                if !dup {
+                    errors.push(diag);
+                }
+                Ok(Type::Error)
+            }
+            (res, _) => res,
+        }
+    }
+
+    /// Record a warning, unless an identical one has already been recorded
+    pub(crate) fn warn(&mut self, diag: Diagnostic) {
+        let dup = self
+            .warnings
+            .iter()
+            .any(|w| w.primary.span == diag.primary.span && w.primary.info == diag.primary.info);
+        if !dup {
+            self.warnings.push(diag);
+        }
+    }
+
+    /// Remove and return the warnings recorded since the last call
+    pub fn take_warnings(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Type check a term, recording every independent error rather than
+    /// giving up at the first one. Subterms that fail to typecheck are
+    /// assigned the poison [`Type::Error`], which is compatible with any other
+    /// type, so that errors do not cascade
+    pub fn type_check_all(&mut self, term: &Term) -> (Option<Type>, Vec<Diagnostic>) {
+        // Anything recorded while checking a previous term is stale
+        self.unreachable.clear();
+        self.bindings.clear();
+        let prev = self.errors.replace(Vec::new());
+        let res = self.type_check(term);
+        let errors = std::mem::replace(&mut self.errors, prev).unwrap_or_default();
+        match res {
+            Ok(ty) if errors.is_empty() => (Some(ty), errors),
+            _ => {
+                self.injections.clear();
+                (None, errors)
+            }
+        }
+    }
+
+    /// Type check an injection into the variant type `ty`
+    fn type_check_injection(&mut self, term: &Term, label: &str, tm: &Term, ty: &Type) -> Result<Type, Diagnostic> {
+        match self.annotation(ty) {
+            Type::Variant(fields) => {
+                for f in &fields {
+                    if label == f.label {
+                        let ty_ = self.check_against(tm, &f.ty)?;
+                        if self.compatible(&ty_, &f.ty) {
+                            return Ok(Type::Variant(fields));
+                        } else if tm.is_elided() {
+                            let d = Diagnostic::error(term.span, "Invalid associated type in variant")
+                                .code(TypeErrorKind::TypeMismatch.code())
+                                .message(
+                                    term.span,
+                                    format!("variant {} requires a payload of type `{}`", label, f.ty),
+                                );
+                            return Err(d);
+                        } else {
+                            let d = Diagnostic::error(term.span, "Invalid associated type in variant")
+                                .code(TypeErrorKind::TypeMismatch.code())
+                                .message(
+                                    tm.span,
+                                    format!("variant {} requires type `{}`, but this is `{}`", label, f.ty, ty_),
+                                );
+                            return Err(d);
+                        }
+                    }
+                }
+                Err(Diagnostic::error(
+                    term.span,
+                    format!(
+                        "constructor {} does not belong to the variant {:?}",
+                        label,
+                        fields
+                            .iter()
+                            .map(|f| f.label.clone())
+                            .collect::<Vec<String>>()
+                            .join(" | ")
+                    ),
+                )
+                .code(TypeErrorKind::UnknownConstructor.code()))
+            }
+            _ => Err(Diagnostic::error(
+                term.span,
+                format!("Cannot injection {} into non-variant type `{}`", label, ty),
+            )
+            .code(TypeErrorKind::NotVariant.code())),
+        }
+    }
+
+    fn type_check_term(&mut self, term: &Term) -> Result<Type, Diagnostic> {
         // dbg!(&self.stack);
 
+        // Type annotations must be well-formed before we can use them
+        match term.kind() {
+            Kind::Abs(ty, _)
+            | Kind::TyApp(_, ty)
+            | Kind::Injection(_, _, ty)
+            | Kind::Fold(ty, _)
+            | Kind::Unfold(ty, _)
+            | Kind::Raise(ty, _) => self.wf(ty, term.span)?,
+            Kind::Pack(witness, _, signature) => {
+                self.wf(witness, term.span)?;
+                self.wf(signature, term.span)?;
+            }
+            _ => {}
+        }
+        match term.kind() {
+            Kind::Fold(ty, _) | Kind::Unfold(ty, _) if self.strict_positivity => self.positivity(ty, term.span)?,
+            _ => {}
+        }
+
         // println!("{}", term);
         match term.kind() {
             Kind::Lit(Literal::Unit) => Ok(Type::Unit),
             Kind::Lit(Literal::Bool(_)) => Ok(Type::Bool),
             Kind::Lit(Literal::Nat(_)) => Ok(Type::Nat),
-            Kind::Var(idx) => self
-                .find(*idx)
-                .cloned()
-                .ok_or_else(|| Diagnostic::error(term.span, format!("unbound variable {}", idx))),
+            Kind::Lit(Literal::String(_)) => Ok(Type::String),
+            Kind::Var(idx) => {
+                let ty = self.find(*idx).cloned().ok_or_else(|| {
+                    Diagnostic::error(term.span, format!("unbound variable {}", idx))
+                        .code(TypeErrorKind::UnboundVariable(*idx).code())
+                })?;
+                match self.let_polymorphism {
+                    true => Ok(self.instantiate(&ty)),
+                    false => Ok(ty),
+                }
+            }
 
             Kind::Abs(ty, t2) => {
-                self.push(*ty.clone());
-                let ty2 = self.type_check(t2)?;
+                if infer::has_holes(ty) && !self.let_polymorphism {
+                    return Err(
+                        Diagnostic::error(term.span, "Type annotation required for lambda abstraction")
+                            .code(TypeErrorKind::MissingAnnotation.code()),
+                    );
+                }
+                let ty = self.annotation(ty);
+                self.push(ty.clone());
+                let ty2 = self.type_check(t2);
                 // Shift::new(-1).visit(&mut ty2);
                 self.pop();
-                Ok(Type::Arrow(ty.clone(), Box::new(ty2)))
+                Ok(Type::Arrow(Box::new(ty), Box::new(ty2?)))
             }
             Kind::App(t1, t2) => {
                 let ty1 = self.type_check(t1)?;
-                let ty2 = self.type_check(t2)?;
+                let ty2 = match &ty1 {
+                    Type::Arrow(ty11, _) => self.check_against(t2, ty11)?,
+                    _ => self.type_check(t2)?,
+                };
                 match ty1 {
+                    Type::Error => Ok(Type::Error),
+                    Type::Meta(_) if self.let_polymorphism => {
+                        let ty12 = self.fresh_meta();
+                        if self.unify(&ty1, &arrow!(ty2.clone(), ty12.clone())) {
+                            Ok(ty12)
+                        } else {
+                            Err(Diagnostic::error(term.span, "Expected arrow type!")
+                                .code(TypeErrorKind::NotArrow.code())
+                                .message(t1.span, format!("operator has type `{}`", self.zonk(&ty1))))
+                        }
+                    }
                     Type::Arrow(ty11, ty12) => {
-                        if *ty11 == ty2 {
+                        if self.compatible(&ty11, &ty2) {
                             Ok(*ty12)
                         } else {
                             let d = Diagnostic::error(term.span, "Type mismatch in application")
-                                .message(t1.span, format!("Abstraction requires type {:?}", ty11))
-                                .message(t2.span, format!("Value has a type of {:?}", ty2));
-                            Err(d)
+                                .code(
+                                    TypeErrorKind::ParameterMismatch(ty11.clone(), Box::new(ty2.clone()), t2.span)
+                                        .code(),
+                                )
+                                .message(t1.span, format!("Abstraction requires type `{}`", ty11))
+                                .message(t2.span, format!("Value has a type of `{}`", ty2));
+                            Err(self.explain_mismatch(d, t2.span, &ty11, &ty2))
                         }
                     }
                     _ => Err(Diagnostic::error(term.span, "Expected arrow type!")
-                        .message(t1.span, format!("operator has type {:?}", ty1))),
+                        .code(TypeErrorKind::NotArrow.code())
+                        .message(t1.span, format!("operator has type `{}`", ty1))),
                 }
             }
             Kind::Fix(inner) => {
                 let ty = self.type_check(inner)?;
                 match ty {
+                    Type::Error => Ok(Type::Error),
                     Type::Arrow(ty1, ty2) => {
-                        if ty1 == ty2 {
+                        if self.compatible(&ty1, &ty2) {
                             Ok(*ty1)
                         } else {
                             let d = Diagnostic::error(term.span, "Type mismatch in fix term")
-                                .message(inner.span, format!("Abstraction requires type {:?}->{:?}", ty1, ty1));
+                                .code(TypeErrorKind::TypeMismatch.code())
+                                .message(
+                                    inner.span,
+                                    format!("Abstraction requires type `{}`", arrow!(*ty1.clone(), *ty1.clone())),
+                                );
                             Err(d)
                         }
                     }
                     _ => Err(Diagnostic::error(term.span, "Expected arrow type!")
-                        .message(inner.span, format!("operator has type {:?}", ty))),
+                        .code(TypeErrorKind::NotArrow.code())
+                        .message(inner.span, format!("operator has type `{}`", ty))),
                 }
             }
-            Kind::Primitive(prim) => match prim {
-                Primitive::IsZero => Ok(Type::Arrow(Box::new(Type::Nat), Box::new(Type::Bool))),
-                _ => Ok(Type::Arrow(Box::new(Type::Nat), Box::new(Type::Nat))),
-            },
-            Kind::Injection(label, tm, ty) => match ty.as_ref() {
-                Type::Variant(fields) => {
-                    for f in fields {
-                        if label == &f.label {
-                            let ty_ = self.type_check(tm)?;
-                            if ty_ == f.ty {
-                                return Ok(*ty.clone());
-                            } else {
-                                let d = Diagnostic::error(term.span, "Invalid associated type in variant").message(
-                                    tm.span,
-                                    format!("variant {} requires type {:?}, but this is {:?}", label, f.ty, ty_),
-                                );
-                                return Err(d);
-                            }
-                        }
-                    }
-                    Err(Diagnostic::error(
-                        term.span,
-                        format!(
-                            "constructor {} does not belong to the variant {:?}",
-                            label,
-                            fields
-                                .iter()
-                                .map(|f| f.label.clone())
-                                .collect::<Vec<String>>()
-                                .join(" | ")
-                        ),
-                    ))
-                }
-                _ => Err(Diagnostic::error(
-                    term.span,
-                    format!("Cannot injection {} into non-variant type {:?}", label, ty),
-                )),
-            },
-            Kind::Projection(term, idx) => match self.type_check(term)? {
+            Kind::Primitive(prim) => Ok(match prim {
+                Primitive::Succ | Primitive::Pred => arrow!(Type::Nat, Type::Nat),
+                Primitive::IsZero => arrow!(Type::Nat, Type::Bool),
+                Primitive::StrLen => arrow!(Type::String, Type::Nat),
+                Primitive::Concat => arrow!(Type::String, arrow!(Type::String, Type::String)),
+                Primitive::Add | Primitive::Sub | Primitive::Mul => arrow!(Type::Nat, arrow!(Type::Nat, Type::Nat)),
+                Primitive::Eq | Primitive::Lt => arrow!(Type::Nat, arrow!(Type::Nat, Type::Bool)),
+            }),
+            Kind::Injection(label, _, ty) if check::is_hole(ty) => Err(Diagnostic::error(
+                term.span,
+                format!("cannot infer variant type for `{}`, add an annotation", label),
+            )
+            .code(TypeErrorKind::MissingAnnotation.code())),
+            Kind::Injection(label, tm, ty) => self.type_check_injection(term, label, tm, ty),
+            Kind::Projection(tm, idx) => match self.type_check(tm)? {
+                Type::Error => Ok(Type::Error),
                 Type::Product(types) => match types.get(*idx) {
                     Some(ty) => Ok(ty.clone()),
-                    None => Err(Diagnostic::error(
-                        term.span,
-                        format!("{} is out of range for product of length {}", idx, types.len()),
-                    )),
+                    None => {
+                        // Point at the `.idx` following the receiver
+                        let index = Span::new(tm.span.end, term.span.end);
+                        Err(Diagnostic::error(index, TypeErrorKind::InvalidProjection.to_string())
+                            .code(TypeErrorKind::InvalidProjection.code())
+                            .message(
+                                tm.span,
+                                format!("{} is out of range for product of length {}", idx, types.len()),
+                            ))
+                    }
                 },
-                ty => Err(Diagnostic::error(
-                    term.span,
-                    format!("Cannot project on non-product type {:?}", ty),
-                )),
+                ty => Err(
+                    Diagnostic::error(tm.span, format!("Cannot project on non-product type `{}`", ty))
+                        .code(TypeErrorKind::NotProduct.code()),
+                ),
             },
             Kind::Product(terms) => Ok(Type::Product(
                 terms.iter().map(|t| self.type_check(t)).collect::<Result<_, _>>()?,
             )),
             Kind::Let(pat, t1, t2) => {
-                let ty = self.type_check(t1)?;
+                let ty = self.type_check_bound(pat, t1)?;
                 if !self.pattern_type_eq(&pat, &ty) {
-                    return Err(Diagnostic::error(
-                        t1.span,
-                        format!("pattern does not match type of binder"),
-                    ));
+                    return Err(
+                        Diagnostic::error(t1.span, format!("pattern does not match type of binder"))
+                            .code(TypeErrorKind::InvalidPattern.code()),
+                    );
+                }
+                self.check_duplicate_bindings(pat, &[], t1.span)?;
+                self.check_or_bindings(pat, &ty, t1.span)?;
+
+                // The pattern must be irrefutable, as there is nothing to
+                // fall back on if the value does not match
+                let mut matrix = patterns::Matrix::new(self.normalize(&ty));
+                matrix.add_pattern(pat);
+                if matrix.expr_ty != Type::Error && !matrix.exhaustive() {
+                    let mut diag = Diagnostic::error(term.span, TypeErrorKind::RefutableLetPattern.to_string())
+                        .code(TypeErrorKind::RefutableLetPattern.code());
+                    if let Some(missing) = matrix.missing() {
+                        diag = diag.message(t1.span, format!("pattern `{}` is not covered", missing));
+                    }
+                    return Err(diag);
                 }
 
                 let height = self.stack.len();
 
                 let binds = crate::patterns::PatTyStack::collect(&ty, &pat);
                 for b in binds.into_iter().rev() {
-                    self.push(b.clone());
+                    self.push(b);
                 }
 
                 let y = self.type_check(t2);
@@ -242,104 +711,217 @@ impl Context {
                     Type::Var(v) => *v += 1,
                     _ => {}
                 });
-                let ty2 = self.type_check(term)?;
+                self.tyvars += 1;
+                let ty2 = self.type_check(term);
+                self.tyvars -= 1;
                 self.stack.iter_mut().for_each(|ty| match ty {
                     Type::Var(v) => *v -= 1,
                     _ => {}
                 });
-                Ok(Type::Universal(Box::new(ty2)))
+                Ok(Type::Universal(Box::new(ty2?)))
             }
             Kind::TyApp(term, ty) => {
-                let mut ty = ty.clone();
+                let mut ty = Box::new(self.annotation(ty));
                 let ty1 = self.type_check(term)?;
                 match ty1 {
+                    Type::Error => Ok(Type::Error),
                     Type::Universal(mut ty12) => {
                         Shift::new(1).visit(&mut ty);
                         Subst::new(*ty).visit(&mut ty12);
                         Shift::new(-1).visit(&mut ty12);
                         Ok(*ty12)
                     }
-                    _ => Err(Diagnostic::error(
-                        term.span,
-                        format!("Expected a universal type, not {:?}", ty1),
-                    )),
+                    _ => Err(
+                        Diagnostic::error(term.span, format!("Expected a universal type, not `{}`", ty1))
+                            .code(TypeErrorKind::NotUniversal.code()),
+                    ),
                 }
             }
             // See src/types/patterns.rs for exhaustiveness and typechecking
             // of case expressions
             Kind::Case(expr, arms) => self.type_check_case(expr, arms),
 
-            Kind::Unfold(rec, tm) => match rec.as_ref() {
+            Kind::Unfold(rec, tm) => match self.annotation(rec) {
                 Type::Rec(inner) => {
+                    let rec = Type::Rec(inner.clone());
                     let ty_ = self.type_check(&tm)?;
-                    if ty_ == *rec.clone() {
-                        let s = subst(*rec.clone(), *inner.clone());
+                    if self.compatible(&ty_, &rec) {
+                        let s = subst(rec, *inner);
                         Ok(s)
                     } else {
                         let d = Diagnostic::error(term.span, "Type mismatch in unfold")
-                            .message(term.span, format!("unfold requires type {:?}", rec))
-                            .message(tm.span, format!("term has a type of {:?}", ty_));
+                            .code(TypeErrorKind::TypeMismatch.code())
+                            .message(term.span, format!("unfold requires type `{}`", rec))
+                            .message(tm.span, format!("term has a type of `{}`", ty_));
                         Err(d)
                     }
                 }
-                _ => Err(Diagnostic::error(
-                    term.span,
-                    format!("Expected a recursive type, not {:?}", rec),
-                )),
+                _ => Err(
+                    Diagnostic::error(term.span, format!("Expected a recursive type, not `{}`", rec))
+                        .code(TypeErrorKind::NotRec.code()),
+                ),
             },
 
-            Kind::Fold(rec, tm) => match rec.as_ref() {
+            Kind::Fold(rec, tm) => match self.annotation(rec) {
                 Type::Rec(inner) => {
+                    let rec = Type::Rec(inner.clone());
                     let ty_ = self.type_check(&tm)?;
-                    let s = subst(*rec.clone(), *inner.clone());
-                    if ty_ == s {
-                        Ok(*rec.clone())
+                    let s = subst(rec.clone(), *inner);
+                    if self.compatible(&ty_, &s) {
+                        Ok(rec)
                     } else {
                         let d = Diagnostic::error(term.span, "Type mismatch in fold")
-                            .message(term.span, format!("unfold requires type {:?}", s))
-                            .message(tm.span, format!("term has a type of {:?}", ty_));
+                            .code(TypeErrorKind::TypeMismatch.code())
+                            .message(term.span, format!("unfold requires type `{}`", s))
+                            .message(tm.span, format!("term has a type of `{}`", ty_));
                         Err(d)
                     }
                 }
-                _ => Err(Diagnostic::error(
-                    term.span,
-                    format!("Expected a recursive type, not {:?}", rec),
-                )),
+                _ => Err(
+                    Diagnostic::error(term.span, format!("Expected a recursive type, not `{}`", rec))
+                        .code(TypeErrorKind::NotRec.code()),
+                ),
             },
             Kind::Pack(witness, evidence, signature) => {
-                if let Type::Existential(exists) = signature.as_ref() {
-                    let sig_prime = subst(*witness.clone(), *exists.clone());
+                let signature = self.annotation(signature);
+                if let Type::Existential(exists) = &signature {
+                    let sig_prime = subst(self.annotation(witness), *exists.clone());
                     let evidence_ty = self.type_check(evidence)?;
-                    if evidence_ty == sig_prime {
-                        Ok(*signature.clone())
+                    if self.compatible(&evidence_ty, &sig_prime) {
+                        Ok(signature)
                     } else {
                         let d = Diagnostic::error(term.span, "Type mismatch in pack")
-                            .message(term.span, format!("signature has type {:?}", sig_prime))
-                            .message(evidence.span, format!("but term has a type {:?}", evidence_ty));
+                            .code(TypeErrorKind::TypeMismatch.code())
+                            .message(term.span, format!("signature has type `{}`", sig_prime))
+                            .message(evidence.span, format!("but term has a type `{}`", evidence_ty));
                         Err(d)
                     }
                 } else {
                     Err(Diagnostic::error(
                         term.span,
-                        format!("Expected an existential type signature, not {:?}", signature),
-                    ))
+                        format!("Expected an existential type signature, not `{}`", signature),
+                    )
+                    .code(TypeErrorKind::NotExistential.code()))
                 }
             }
             Kind::Unpack(package, body) => {
                 let p_ty = self.type_check(package)?;
-                if let Type::Existential(xst) = p_ty {
+                if let Type::Error = p_ty {
+                    self.push(Type::Error);
+                    self.tyvars += 1;
+                    let body_ty = self.type_check(body);
+                    self.tyvars -= 1;
+                    self.pop();
+                    body_ty
+                } else if let Type::Existential(xst) = p_ty {
                     self.push(*xst);
-                    let body_ty = self.type_check(body)?;
+                    self.tyvars += 1;
+                    let body_ty = self.type_check(body);
+                    self.tyvars -= 1;
                     self.pop();
-                    Ok(body_ty)
+                    body_ty
                 } else {
                     Err(Diagnostic::error(
                         package.span,
-                        format!("Expected an existential type signature, not {:?}", p_ty),
-                    ))
+                        format!("Expected an existential type signature, not `{}`", p_ty),
+                    )
+                    .code(TypeErrorKind::NotExistential.code()))
                 }
             }
+            // The payload may have any type, and the raise stands in for a
+            // term of the annotated type
+            Kind::Raise(ty, tm) => {
+                self.type_check(tm)?;
+                Ok(self.normalize(ty))
+            }
+            // Shared terms are closed, so need nothing from the context
+            Kind::Share(tm) => self.type_check(tm),
+        }
+    }
+}
+
+impl Context {
+    /// Typing shortcut for the desugared `letrec` form, so that errors are
+    /// reported in terms of the original binding names and spans, rather than
+    /// in terms of the anonymous product that they are packed into
+    fn type_check_letrec(&mut self, names: &[&str], tys: &[Type], terms: &[Term]) -> Result<Type, Diagnostic> {
+        let product = Type::Product(tys.to_vec());
+        self.push(product.clone());
+        let res = names.iter().zip(tys).zip(terms).try_for_each(|((name, ty), tm)| {
+            let ty_ = self.type_check(tm)?;
+            if self.compatible(&ty_, ty) {
+                Ok(())
+            } else {
+                Err(
+                    Diagnostic::error(tm.span, format!("Type mismatch in letrec binding {}", name))
+                        .code(TypeErrorKind::TypeMismatch.code())
+                        .message(
+                            tm.span,
+                            format!("{} is declared with type `{}`, but has type `{}`", name, ty, ty_),
+                        ),
+                )
+            }
+        });
+        self.pop();
+        res.map(|_| product)
+    }
+
+    /// Type of the term bound by a let expression, generalized if it is
+    /// bound to a single variable under let-polymorphism
+    fn type_check_bound(&mut self, pat: &Pattern, t1: &Term) -> Result<Type, Diagnostic> {
+        let ty = match letrec_form(pat, t1) {
+            Some((names, tys, terms)) => self.type_check_letrec(&names, tys, terms)?,
+            None => self.type_check(t1)?,
+        };
+        Ok(match pat {
+            Pattern::Variable(_) if self.let_polymorphism => self.generalize(&ty),
+            _ => ty,
+        })
+    }
+}
+
+/// Recognize the shape that the parser desugars `letrec` bindings into:
+/// `let (f1, .., fn) = fix (λ_: (T1, .., Tn). (t1, .., tn)) in body`
+fn letrec_form<'a>(pat: &'a Pattern, tm: &'a Term) -> Option<(Vec<&'a str>, &'a [Type], &'a [Term])> {
+    let names = match pat {
+        Pattern::Product(pats) => pats
+            .iter()
+            .map(|p| match p {
+                Pattern::Variable(name) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?,
+        _ => return None,
+    };
+    let (ty, body) = match &tm.kind {
+        Kind::Fix(inner) => match &inner.kind {
+            Kind::Abs(ty, body) => (ty, body),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    match (ty.as_ref(), &body.kind) {
+        (Type::Product(tys), Kind::Product(terms)) if tys.len() == names.len() && terms.len() == names.len() => {
+            Some((names, tys, terms))
+        }
+        _ => None,
+    }
+}
+
+/// Structural type equality, where the poison [`Type::Error`] is considered
+/// equal to every other type
+pub fn equiv(a: &Type, b: &Type) -> bool {
+    match (a, b) {
+        (Type::Error, _) | (_, Type::Error) => true,
+        (Type::Variant(a), Type::Variant(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.label == b.label && equiv(&a.ty, &b.ty))
         }
+        (Type::Product(a), Type::Product(b)) => a.len() == b.len() && a.iter().zip(b).all(|(a, b)| equiv(a, b)),
+        (Type::Arrow(a1, a2), Type::Arrow(b1, b2)) => equiv(a1, b1) && equiv(a2, b2),
+        (Type::Universal(a), Type::Universal(b)) => equiv(a, b),
+        (Type::Existential(a), Type::Existential(b)) => equiv(a, b),
+        (Type::Rec(a), Type::Rec(b)) => equiv(a, b),
+        _ => a == b,
     }
 }
 
@@ -352,16 +934,24 @@ pub fn subst(mut s: Type, mut t: Type) -> Type {
 
 struct Aliaser<'ctx> {
     map: &'ctx HashMap<String, Type>,
+    /// Aliases currently being expanded, so that we don't loop forever on a
+    /// self-referential alias
+    expanding: Vec<String>,
 }
 
 impl<'ctx> MutTypeVisitor for Aliaser<'ctx> {
     fn visit(&mut self, ty: &mut Type) {
         match ty {
-            Type::Unit | Type::Bool | Type::Nat => {}
+            Type::Unit | Type::Bool | Type::Nat | Type::String | Type::Error | Type::Meta(_) | Type::Hole => {}
             Type::Var(v) => {}
             Type::Alias(v) => {
                 if let Some(aliased) = self.map.get(v) {
-                    *ty = aliased.clone();
+                    if !self.expanding.contains(v) {
+                        self.expanding.push(v.clone());
+                        *ty = aliased.clone();
+                        self.visit(ty);
+                        self.expanding.pop();
+                    }
                 }
             }
             Type::Variant(v) => self.visit_variant(v),
@@ -400,6 +990,185 @@ impl MutTermVisitor for Context {
         self.aliaser().visit(ty);
         self.visit(tm);
     }
+
+    fn visit_pack(&mut self, sp: &mut Span, witness: &mut Type, evidence: &mut Term, signature: &mut Type) {
+        self.aliaser().visit(witness);
+        self.aliaser().visit(signature);
+        self.visit(evidence);
+    }
+
+    fn visit_raise(&mut self, sp: &mut Span, ty: &mut Type, tm: &mut Term) {
+        self.aliaser().visit(ty);
+        self.visit(tm);
+    }
+}
+
+/// Name of the type variable introduced by a binder at the given depth
+pub(crate) fn binder_name(depth: usize) -> String {
+    const NAMES: [&str; 4] = ["X", "Y", "Z", "W"];
+    match depth / NAMES.len() {
+        0 => NAMES[depth].to_string(),
+        n => format!("{}{}", NAMES[depth % NAMES.len()], n),
+    }
+}
+
+impl Type {
+    /// Print a type with the minimal amount of parentheses. `depth` is the
+    /// number of enclosing type binders, and `atom` is true when the type
+    /// appears in a position where an arrow or binder must be parenthesized:
+    /// the left hand side of an arrow, or the argument of a constructor. With
+    /// `unicode`, binders, arrows and products are written `∀X.`, `→` and
+    /// `×`
+    pub(crate) fn fmt_prec(&self, f: &mut fmt::Formatter, depth: usize, atom: bool, unicode: bool) -> fmt::Result {
+        let compound = match self {
+            Type::Arrow(_, _) | Type::Universal(_) | Type::Existential(_) | Type::Rec(_) => true,
+            Type::Product(tys) => unicode && tys.len() > 1,
+            _ => false,
+        };
+        if atom && compound {
+            write!(f, "(")?;
+            self.fmt_prec(f, depth, false, unicode)?;
+            return write!(f, ")");
+        }
+
+        match self {
+            Type::Unit => write!(f, "Unit"),
+            Type::Bool => write!(f, "Bool"),
+            Type::Nat => write!(f, "Nat"),
+            Type::String => write!(f, "String"),
+            Type::Alias(s) => write!(f, "{}", s),
+            Type::Error => write!(f, "<error>"),
+            Type::Meta(m) => write!(f, "?{}", m),
+            Type::Hole => write!(f, "_"),
+            Type::Var(v) if *v < depth => write!(f, "{}", binder_name(depth - 1 - v)),
+            Type::Var(v) => write!(f, "#{}", v - depth),
+            Type::Variant(vs) => {
+                write!(f, "{{")?;
+                for (idx, v) in vs.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, " | ")?;
+                    }
+                    write!(f, "{}", crate::syntax::ident(&v.label))?;
+                    if v.ty != Type::Unit {
+                        write!(f, " ")?;
+                        v.ty.fmt_prec(f, depth, true, unicode)?;
+                    }
+                }
+                write!(f, "}}")
+            }
+            Type::Product(tys) if compound => {
+                for (idx, ty) in tys.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, " × ")?;
+                    }
+                    ty.fmt_prec(f, depth, true, unicode)?;
+                }
+                Ok(())
+            }
+            Type::Product(tys) => {
+                write!(f, "(")?;
+                for (idx, ty) in tys.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ", ")?;
+                    }
+                    ty.fmt_prec(f, depth, false, unicode)?;
+                }
+                write!(f, ")")
+            }
+            Type::Arrow(t1, t2) => {
+                t1.fmt_prec(f, depth, true, unicode)?;
+                write!(f, "{}", if unicode { " → " } else { " -> " })?;
+                t2.fmt_prec(f, depth, false, unicode)
+            }
+            Type::Universal(ty) => {
+                match unicode {
+                    true => write!(f, "∀{}. ", binder_name(depth))?,
+                    false => write!(f, "forall {}. ", binder_name(depth))?,
+                }
+                ty.fmt_prec(f, depth + 1, false, unicode)
+            }
+            Type::Existential(ty) => {
+                match unicode {
+                    true => write!(f, "∃{}. ", binder_name(depth))?,
+                    false => write!(f, "exists {}. ", binder_name(depth))?,
+                }
+                ty.fmt_prec(f, depth + 1, false, unicode)
+            }
+            Type::Rec(ty) => {
+                write!(f, "rec {} = ", binder_name(depth))?;
+                ty.fmt_prec(f, depth + 1, false, unicode)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_prec(f, 0, false, false)
+    }
+}
+
+impl fmt::Display for TypeErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TypeErrorKind::ParameterMismatch(expected, found, _) => {
+                write!(
+                    f,
+                    "this function expects `{}` but the argument has type `{}`",
+                    expected, found
+                )?;
+                let diff = diff(expected, found);
+                if diff.shared_root() && !diff.is_empty() {
+                    write!(f, ", which differ {}", diff)?;
+                }
+                Ok(())
+            }
+            TypeErrorKind::TypeMismatch => write!(f, "this term does not have the type required here"),
+            TypeErrorKind::InvalidProjection => write!(f, "this projection is out of range for the product"),
+            TypeErrorKind::NotExistential => write!(f, "expected an existential type"),
+            TypeErrorKind::UnknownConstructor => write!(f, "this constructor is not part of the variant type"),
+            TypeErrorKind::NotArrow => write!(f, "this term is applied to an argument, but it is not a function"),
+            TypeErrorKind::NotUniversal => write!(
+                f,
+                "this term is applied to a type, but it does not have a universal type"
+            ),
+            TypeErrorKind::NotVariant => write!(f, "expected a variant type"),
+            TypeErrorKind::NotProduct => write!(f, "this term is projected from, but it is not a product"),
+            TypeErrorKind::NotRec => write!(f, "expected a recursive type"),
+            TypeErrorKind::IncompatibleArms => write!(f, "the arms of this case expression have different types"),
+            TypeErrorKind::InvalidPattern => write!(f, "this pattern cannot match values of the scrutinee's type"),
+            TypeErrorKind::NotExhaustive => write!(f, "the patterns of this case expression are not exhaustive"),
+            TypeErrorKind::UnreachablePattern => write!(f, "this pattern is unreachable"),
+            TypeErrorKind::RefutableLetPattern => write!(
+                f,
+                "this let binding's pattern does not match every value of its type, use a case expression instead"
+            ),
+            TypeErrorKind::OrPatternBinding(var) => write!(
+                f,
+                "variable `{}` is not bound with the same type in every alternative of this or-pattern",
+                var
+            ),
+            TypeErrorKind::DuplicatePatternBinding(var) => {
+                write!(f, "variable `{}` is bound more than once in this pattern", var)
+            }
+            TypeErrorKind::UnboundVariable(idx) => write!(f, "variable #{} is not bound", idx),
+            TypeErrorKind::UnboundTypeVariable(idx) => {
+                write!(f, "type variable #{} in this type annotation is not bound", idx)
+            }
+            TypeErrorKind::NegativeOccurrence => write!(
+                f,
+                "the recursive type variable occurs in a negative position (to the left of an arrow)"
+            ),
+            TypeErrorKind::MissingAnnotation => write!(f, "a type annotation is required here"),
+            TypeErrorKind::DuplicateAlias => write!(f, "this type alias is already defined"),
+        }
+    }
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.span.start, self.kind)
+    }
 }
 
 impl fmt::Debug for Type {
@@ -408,6 +1177,7 @@ impl fmt::Debug for Type {
             Type::Unit => write!(f, "Unit"),
             Type::Bool => write!(f, "Bool"),
             Type::Nat => write!(f, "Nat"),
+            Type::String => write!(f, "String"),
             Type::Var(v) => write!(f, "TyVar({})", v),
             Type::Variant(v) => write!(
                 f,
@@ -427,6 +1197,421 @@ impl fmt::Debug for Type {
             Type::Universal(ty) => write!(f, "forall X.{:?}", ty),
             Type::Existential(ty) => write!(f, "exists X.{:?}", ty),
             Type::Rec(ty) => write!(f, "rec {:?}", ty),
+            Type::Error => write!(f, "<error>"),
+            Type::Meta(m) => write!(f, "?{}", m),
+            Type::Hole => write!(f, "_"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::syntax::parser::Parser;
+
+    fn parse(input: &str) -> Term {
+        let mut p = Parser::new(input);
+        let tm = p.parse().unwrap();
+        assert_eq!(p.diagnostic().error_count(), 0);
+        tm
+    }
+
+    #[test]
+    fn collect_independent_errors() {
+        let tm = parse("succ ((succ true, iszero unit, case 0 of | 0 => true 1 | _ => false).0)");
+        let mut ctx = Context::default();
+        let (ty, errors) = ctx.type_check_all(&tm);
+        assert_eq!(ty, None);
+        assert_eq!(errors.len(), 3, "{:?}", errors);
+        assert!(ctx.type_check(&tm).is_err());
+    }
+
+    #[test]
+    fn poison_does_not_cascade() {
+        let tm = parse("iszero (succ (succ true))");
+        let mut ctx = Context::default();
+        let (ty, errors) = ctx.type_check_all(&tm);
+        assert_eq!(ty, None);
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+    }
+
+    #[test]
+    fn well_formed_annotations() {
+        let mut ctx = Context::default();
+        let bound = tyabs!(abs!(Type::Var(0), var!(0)));
+        assert_eq!(
+            ctx.type_check(&bound).unwrap(),
+            Type::Universal(Box::new(arrow!(Type::Var(0), Type::Var(0))))
+        );
+
+        let dangling = tuple!(bound, abs!(Type::Var(0), var!(0)));
+        assert!(ctx.type_check(&dangling).is_err());
+        assert_eq!(
+            ctx.wf(&Type::Var(0), Span::zero()),
+            Err(TypeError {
+                span: Span::zero(),
+                kind: TypeErrorKind::UnboundTypeVariable(0)
+            })
+        );
+        assert_eq!(
+            ctx.wf(&Type::Rec(Box::new(arrow!(Type::Var(0), Type::Nat))), Span::zero()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn free_vars() {
+        // forall X. rec Y. {A X | B (Y, #0) | C #3 -> Y}
+        let ty = Type::Universal(Box::new(Type::Rec(Box::new(Type::Variant(vec![
+            variant!("A", Type::Var(1)),
+            variant!("B", Type::Product(vec![Type::Var(0), Type::Var(2)])),
+            variant!("C", arrow!(Type::Var(5), Type::Var(0))),
+        ])))));
+        assert_eq!(ty.free_vars().into_iter().collect::<Vec<_>>(), vec![0, 3]);
+        assert!(ty.occurs(0));
+        assert!(ty.occurs(3));
+        assert!(!ty.occurs(1));
+        assert!(!ty.occurs(2));
+
+        let closed = Type::Existential(Box::new(arrow!(Type::Var(0), Type::Nat)));
+        assert!(closed.free_vars().is_empty());
+        assert!(arrow!(Type::Var(1), closed).occurs(1));
+    }
+
+    #[test]
+    fn strict_positivity() {
+        let body = Type::Variant(vec![
+            variant!("Nil", Type::Unit),
+            variant!("Cons", Type::Product(vec![Type::Nat, Type::Var(0)])),
+        ]);
+        let list = Type::Rec(Box::new(body.clone()));
+        let negative = Type::Rec(Box::new(arrow!(Type::Var(0), Type::Nat)));
+
+        let mut ctx = Context::default();
+        assert!(ctx.alias("Neg".into(), negative.clone()).is_ok());
+
+        ctx.strict_positivity = true;
+        assert!(ctx.alias("List".into(), list.clone()).is_ok());
+        assert_eq!(
+            ctx.alias("Neg".into(), negative.clone()),
+            Err(TypeError {
+                span: Span::zero(),
+                kind: TypeErrorKind::NegativeOccurrence
+            })
+        );
+
+        let nil = inj!("Nil", Term::unit(), subst(list.clone(), body));
+        let fold = fold!(list.clone(), nil);
+        assert_eq!(ctx.type_check(&fold).unwrap(), list);
+
+        let f = abs!(negative.clone(), nat!(0));
+        let fold = fold!(negative, f);
+        assert!(ctx.type_check(&fold).is_err());
+        ctx.strict_positivity = false;
+        assert!(ctx.type_check(&fold).is_ok());
+    }
+
+    #[test]
+    fn normalize_aliases() {
+        let mut ctx = Context::default();
+        let pair = Type::Product(vec![Type::Nat, Type::Bool]);
+        let before = ctx.type_check(&abs!(pair.clone(), var!(0))).unwrap();
+
+        ctx.alias("N".into(), Type::Nat).unwrap();
+        ctx.alias("Pair".into(), Type::Product(vec![Type::Alias("N".into()), Type::Bool]))
+            .unwrap();
+        assert_eq!(ctx.normalize(&Type::Alias("Pair".into())), pair);
+
+        let aliased = abs!(Type::Alias("Pair".into()), var!(0));
+        let after = ctx.type_check(&aliased).unwrap();
+        assert!(ctx.type_eq(&before, &after));
+        assert_eq!(before, after);
+
+        let app = app!(aliased, tuple!(nat!(1), lit!(true)));
+        assert_eq!(ctx.type_check(&app).unwrap(), pair);
+    }
+
+    #[test]
+    fn normalize_unfold() {
+        let mut ctx = Context::default();
+        let body = Type::Variant(vec![variant!("Nil", Type::Unit), variant!("Cons", Type::Var(0))]);
+        let rec = Type::Rec(Box::new(body.clone()));
+        ctx.alias("L".into(), rec.clone()).unwrap();
+        assert_eq!(ctx.normalize(&Type::Alias("L".into())), rec);
+        assert_eq!(
+            ctx.normalize_unfold(&Type::Alias("L".into())),
+            Type::Variant(vec![variant!("Nil", Type::Unit), variant!("Cons", rec)])
+        );
+    }
+
+    #[test]
+    fn failed_arm_leaves_context_intact() {
+        let mut ctx = Context::default();
+        let tm = parse("\\x: Nat. case (x, true) of | (0, y) => y | (n, y) => succ y");
+        let before = ctx.checkpoint();
+        assert!(ctx.type_check(&tm).is_err());
+        assert_eq!(ctx.checkpoint(), before);
+        assert!(ctx.stack.is_empty());
+    }
+
+    #[test]
+    fn checkpoint_rollback() {
+        let mut ctx = Context::default();
+        ctx.alias("A".into(), Type::Nat).unwrap();
+        let res: Result<(), Diagnostic> = ctx.with_checkpoint(|ctx| {
+            ctx.alias("A".into(), Type::Bool).unwrap();
+            ctx.alias("B".into(), Type::Unit).unwrap();
+            ctx.push(Type::Nat);
+            ctx.type_check(&app!(lit!(true), nat!(0))).map(|_| ())
+        });
+        assert!(res.is_err());
+        assert!(ctx.stack.is_empty());
+        assert_eq!(ctx.normalize(&Type::Alias("A".into())), Type::Nat);
+        assert_eq!(ctx.normalize(&Type::Alias("B".into())), Type::Alias("B".into()));
+
+        let res: Result<(), Diagnostic> = ctx.with_checkpoint(|ctx| {
+            ctx.alias("B".into(), Type::Unit).unwrap();
+            Ok(())
+        });
+        assert!(res.is_ok());
+        assert_eq!(ctx.normalize(&Type::Alias("B".into())), Type::Unit);
+    }
+
+    #[test]
+    fn display_types() {
+        let list = Type::Rec(Box::new(Type::Variant(vec![
+            variant!("Nil", Type::Unit),
+            variant!("Cons", Type::Product(vec![Type::Nat, Type::Var(0)])),
+        ])));
+        assert_eq!(list.to_string(), "rec X = {Nil | Cons (Nat, X)}");
+
+        let ty = arrow!(arrow!(Type::Nat, Type::Bool), arrow!(Type::Nat, Type::Bool));
+        assert_eq!(ty.to_string(), "(Nat -> Bool) -> Nat -> Bool");
+
+        let ty = Type::Universal(Box::new(Type::Universal(Box::new(arrow!(
+            Type::Var(1),
+            arrow!(Type::Var(0), Type::Var(2))
+        )))));
+        assert_eq!(ty.to_string(), "forall X. forall Y. X -> Y -> #0");
+
+        let ty = Type::Existential(Box::new(Type::Product(vec![
+            arrow!(Type::Var(0), Type::Nat),
+            Type::Var(0),
+        ])));
+        assert_eq!(ty.to_string(), "exists X. (X -> Nat, X)");
+
+        let ty = Type::Variant(vec![
+            variant!("None", Type::Unit),
+            variant!("Some", arrow!(Type::String, Type::Alias("T".into()))),
+        ]);
+        assert_eq!(ty.to_string(), "{None | Some (String -> T)}");
+    }
+
+    #[test]
+    fn display_type_errors() {
+        use TypeErrorKind::*;
+        let cases = vec![
+            (
+                ParameterMismatch(Box::new(Type::Nat), Box::new(Type::Bool), Span::zero()),
+                "this function expects `Nat` but the argument has type `Bool`",
+            ),
+            (
+                ParameterMismatch(
+                    Box::new(Type::Product(vec![Type::Nat, Type::Bool])),
+                    Box::new(Type::Product(vec![Type::Nat, Type::Unit])),
+                    Span::zero(),
+                ),
+                "this function expects `(Nat, Bool)` but the argument has type `(Nat, Unit)`, which differ at `.1`: \
+                 expected `Bool`, found `Unit`",
+            ),
+            (InvalidProjection, "this projection is out of range for the product"),
+            (
+                NotArrow,
+                "this term is applied to an argument, but it is not a function",
+            ),
+            (
+                NotUniversal,
+                "this term is applied to a type, but it does not have a universal type",
+            ),
+            (NotVariant, "expected a variant type"),
+            (NotProduct, "this term is projected from, but it is not a product"),
+            (NotRec, "expected a recursive type"),
+            (
+                IncompatibleArms,
+                "the arms of this case expression have different types",
+            ),
+            (
+                InvalidPattern,
+                "this pattern cannot match values of the scrutinee's type",
+            ),
+            (NotExhaustive, "the patterns of this case expression are not exhaustive"),
+            (UnreachablePattern, "this pattern is unreachable"),
+            (
+                RefutableLetPattern,
+                "this let binding's pattern does not match every value of its type, use a case expression instead",
+            ),
+            (
+                OrPatternBinding("x".into()),
+                "variable `x` is not bound with the same type in every alternative of this or-pattern",
+            ),
+            (
+                DuplicatePatternBinding("x".into()),
+                "variable `x` is bound more than once in this pattern",
+            ),
+            (UnboundVariable(3), "variable #3 is not bound"),
+            (
+                UnboundTypeVariable(1),
+                "type variable #1 in this type annotation is not bound",
+            ),
+            (
+                NegativeOccurrence,
+                "the recursive type variable occurs in a negative position (to the left of an arrow)",
+            ),
+        ];
+        for (kind, msg) in cases {
+            assert_eq!(kind.to_string(), msg);
+        }
+
+        let err = TypeError {
+            span: Span::zero(),
+            kind: NotRec,
+        };
+        assert_eq!(err.to_string(), "0:0: expected a recursive type");
+    }
+
+    #[test]
+    fn no_errors() {
+        let tm = parse("iszero (succ 0)");
+        let mut ctx = Context::default();
+        let (ty, errors) = ctx.type_check_all(&tm);
+        assert_eq!(ty, Some(Type::Bool));
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn let_polymorphism() {
+        let tm = parse("let id = \\x. x in (id 0, id true)");
+        let mut ctx = Context::default();
+        assert!(ctx.type_check(&tm).is_err());
+
+        let mut ctx = Context {
+            let_polymorphism: true,
+            ..Context::default()
+        };
+        assert_eq!(ctx.type_check(&tm).unwrap(), Type::Product(vec![Type::Nat, Type::Bool]));
+
+        let tm = parse("let f = \\x. succ x in f 1");
+        assert_eq!(ctx.type_check(&tm).unwrap(), Type::Nat);
+
+        // Lambda-bound variables are not generalized
+        let tm = parse("(\\id. (id 0, id true)) (\\x. x)");
+        assert!(ctx.type_check(&tm).is_err());
+
+        // Explicitly typed System F terms are unaffected
+        let tm = parse("let id = \\X \\x: X. x in (id [Nat] 0, id [Bool] true)");
+        assert_eq!(ctx.type_check(&tm).unwrap(), Type::Product(vec![Type::Nat, Type::Bool]));
+
+        // Meta variables are numbered afresh for every term
+        let tm = parse("\\x. x");
+        let ty = ctx.type_check(&tm).unwrap();
+        assert_eq!(ty, arrow!(Type::Meta(0), Type::Meta(0)));
+        assert_eq!(ctx.type_check(&tm).unwrap(), ty);
+        assert_eq!(ctx.metas.len(), 1);
+        assert!(ctx.generic.is_empty());
+    }
+
+    #[test]
+    fn annotation_holes() {
+        // A list of some element type, folded without saying which
+        let variant = |elem: Type| {
+            Type::Variant(vec![
+                variant!("Nil", Type::Unit),
+                variant!("Cons", Type::Product(vec![elem, Type::Var(0)])),
+            ])
+        };
+        let list = |elem: Type| Type::Rec(Box::new(variant(elem)));
+        let unfolded = |elem: Type| subst(list(elem.clone()), variant(elem));
+        let nil = fold!(list(Type::Meta(0)), inj!("Nil", Term::unit(), unfolded(Type::Meta(1))));
+        let tm = fold!(
+            list(Type::Meta(0)),
+            inj!("Cons", tuple!(nat!(1), nil), unfolded(Type::Meta(0)))
+        );
+        let mut ctx = Context::default();
+        assert!(ctx.type_check(&tm).is_err());
+
+        let mut ctx = Context {
+            let_polymorphism: true,
+            ..Context::default()
+        };
+        assert_eq!(ctx.type_check(&tm).unwrap(), list(Type::Nat));
+        let tm = app!(tyapp!(parse("\\X \\x: X. x"), Type::Meta(0)), nat!(2));
+        assert_eq!(ctx.type_check(&tm).unwrap(), Type::Nat);
+    }
+
+    #[test]
+    fn let_patterns() {
+        let mut ctx = Context::default();
+        let ev = |ctx: &Context, tm: Term| {
+            let ev = crate::eval::Eval::with_context(ctx);
+            let mut t = tm;
+            while let Some(next) = ev.small_step(t.clone()) {
+                t = next;
+            }
+            t
+        };
+
+        let tm = parse("let (x, (y, _)) = (1, (true, unit)) in (y, x)");
+        assert_eq!(ctx.type_check(&tm).unwrap(), Type::Product(vec![Type::Bool, Type::Nat]));
+        assert_eq!(ev(&ctx, tm).to_string(), "(true,1)");
+
+        // A variant with a single constructor cannot fail to match
+        ctx.alias("Wrap".into(), Type::Variant(vec![variant!("Wrap", Type::Nat)]))
+            .unwrap();
+        let mut tm = parse("let Wrap n = Wrap 3 of Wrap in succ n");
+        ctx.de_alias(&mut tm);
+        assert_eq!(ctx.type_check(&tm).unwrap(), Type::Nat);
+        assert_eq!(ev(&ctx, tm).kind, Kind::Lit(Literal::Nat(4)));
+
+        ctx.alias(
+            "Opt".into(),
+            Type::Variant(vec![variant!("None", Type::Unit), variant!("Some", Type::Nat)]),
+        )
+        .unwrap();
+        let mut tm = parse("let Some x = Some 1 of Opt in x");
+        ctx.de_alias(&mut tm);
+        let diag = ctx.type_check(&tm).unwrap_err();
+        assert_eq!(diag.primary.info, TypeErrorKind::RefutableLetPattern.to_string());
+        assert_eq!(diag.other[0].info, "pattern `None` is not covered");
+
+        let diag = ctx.type_check(&parse("let (x, 0) = (1, 2) in x")).unwrap_err();
+        assert_eq!(diag.primary.info, TypeErrorKind::RefutableLetPattern.to_string());
+    }
+
+    #[test]
+    fn packages() {
+        let mut ctx = Context::default();
+        ctx.alias(
+            "Counter".into(),
+            Type::Existential(Box::new(Type::Product(vec![
+                Type::Var(0),
+                arrow!(Type::Var(0), Type::Nat),
+            ]))),
+        )
+        .unwrap();
+        ctx.alias("Repr".into(), Type::Nat).unwrap();
+        let mut tm = parse("unpack (pack Repr, (0, \\x: Repr. succ x) as Counter) as C, c in c.1 c.0");
+        ctx.de_alias(&mut tm);
+        let pack = match &tm.kind {
+            Kind::Unpack(pack, _) => pack,
+            _ => panic!("expected an unpack, got {:?}", tm),
+        };
+        match &pack.kind {
+            Kind::Pack(witness, _, signature) => {
+                assert_eq!(**witness, Type::Nat);
+                assert!(matches!(**signature, Type::Existential(_)), "{:?}", signature);
+            }
+            _ => panic!("expected a package, got {:?}", pack),
         }
+        assert_eq!(ctx.type_check(&tm).unwrap(), Type::Nat);
     }
 }
@@ -1,13 +1,16 @@
-//! Naive, inefficient exhaustiveness checking for pattern matching
+//! Exhaustiveness and reachability checking for pattern matching
 //!
 //! Inspired somewhat by the docs for the Rust compiler (and linked paper), we
 //! create a "usefulness" predicate. We store current patterns in a row-wise
-//! [`Matrix`], and iterate through each row in the matrix every time we want
-//! to add a new pattern. If no existing rows completely overlap the new row,
-//! then we can determine that the new row is "useful", and add it.
+//! [`Matrix`]. A new pattern is "useful" if there is some value that it
+//! matches, but that no existing row matches - only useful patterns are
+//! reachable, and can be added to the matrix.
 //!
-//! To check for exhaustiveness, we simply create a row of Wildcard matches,
-//! and see if it would be useful to add
+//! Usefulness is computed with Maranget's algorithm, which handles nested
+//! patterns by specializing the matrix for each constructor of the type of
+//! the first column, and recursing on the sub-patterns. To check for
+//! exhaustiveness, we check whether a wildcard would be useful, and if it is,
+//! construct an example of a value that is not matched.
 //!
 //! https://doc.rust-lang.org/nightly/nightly-rustc/src/rustc_mir/hair/pattern/_match.rs.html
 //! http://moscova.inria.fr/~maranget/papers/warn/index.html
@@ -15,130 +18,356 @@
 
 use super::*;
 use crate::diagnostics::*;
-use crate::patterns::{PatTyStack, Pattern};
+use crate::patterns::{PatTyStack, PatVarStack, Pattern};
 use crate::terms::*;
-use std::collections::HashSet;
+use crate::visit::MutTermVisitor;
 
-/// Return true if `existing` covers `new`, i.e. if new is a useful pattern
-/// then `overlap` will return `false`
-fn overlap(existing: &Pattern, new: &Pattern) -> bool {
-    use Pattern::*;
-    match (existing, new) {
-        (Any, _) => true,
-        (Variable(_), _) => true,
-        (Constructor(l, a), Constructor(l2, b)) => {
-            if l == l2 {
-                overlap(a, b)
-            } else {
-                false
+/// The head constructor of a pattern
+///
+/// Natural numbers are usually matched by literals, and treated as having
+/// infinitely many constructors. Once a `succ` pattern appears in a column
+/// though, the column is instead matched against the two constructors `0`
+/// and `succ`, with every other literal `n` standing for `succ (n - 1)`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Ctor {
+    Literal(Literal),
+    Label(String),
+    Tuple(usize),
+    Succ,
+}
+
+impl Ctor {
+    pub(crate) fn of(pat: &Pattern) -> Option<Ctor> {
+        match pat {
+            Pattern::Any | Pattern::Variable(_) => None,
+            Pattern::Literal(lit) => Some(Ctor::Literal(lit.clone())),
+            Pattern::Succ(_) => Some(Ctor::Succ),
+            Pattern::Constructor(label, _) => Some(Ctor::Label(label.clone())),
+            Pattern::Product(pats) => Some(Ctor::Tuple(pats.len())),
+            Pattern::Or(_) | Pattern::As(_, _) => {
+                unreachable!("or-patterns and as-patterns are expanded before inspecting their constructor")
+            }
+        }
+    }
+
+    /// Head constructor of a pattern in a column that is matched by `0` and
+    /// `succ`
+    pub(crate) fn of_peano(pat: &Pattern) -> Option<Ctor> {
+        match pat {
+            Pattern::Literal(Literal::Nat(n)) if *n > 0 => Some(Ctor::Succ),
+            pat => Ctor::of(pat),
+        }
+    }
+
+    /// Number of sub-patterns of this constructor
+    pub(crate) fn arity(&self) -> usize {
+        match self {
+            Ctor::Literal(_) => 0,
+            Ctor::Label(_) | Ctor::Succ => 1,
+            Ctor::Tuple(n) => *n,
+        }
+    }
+
+    /// Types of the sub-patterns of this constructor
+    fn fields(&self, ty: &Type) -> Vec<Type> {
+        match (self, ty) {
+            (Ctor::Succ, _) => vec![Type::Nat],
+            (Ctor::Label(label), Type::Variant(vs)) => {
+                vs.iter().filter(|v| &v.label == label).map(|v| unfold(&v.ty)).collect()
+            }
+            (Ctor::Tuple(_), Type::Product(tys)) => tys.iter().map(unfold).collect(),
+            (Ctor::Tuple(n), _) => vec![Type::Error; *n],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Rebuild a pattern from this constructor and its sub-patterns
+    fn apply(&self, mut fields: Vec<Pattern>) -> Pattern {
+        match self {
+            Ctor::Literal(lit) => Pattern::Literal(lit.clone()),
+            Ctor::Label(label) => Pattern::Constructor(label.clone(), Box::new(fields.pop().unwrap_or(Pattern::Any))),
+            Ctor::Tuple(_) => Pattern::Product(fields),
+            Ctor::Succ => Pattern::Succ(Box::new(fields.pop().unwrap_or(Pattern::Any))),
+        }
+    }
+}
+
+/// Patterns on recursive types match against the unfolded type
+fn unfold(ty: &Type) -> Type {
+    match ty {
+        Type::Rec(inner) => subst(ty.clone(), *inner.clone()),
+        ty => ty.clone(),
+    }
+}
+
+/// A pattern matching every value of a type, spelling out the components of
+/// a product so that it reads naturally as a constructor payload
+fn wildcard(ty: &Type) -> Pattern {
+    match ty {
+        Type::Product(tys) => Pattern::Product(vec![Pattern::Any; tys.len()]),
+        _ => Pattern::Any,
+    }
+}
+
+/// Every constructor of a type, if there are finitely many
+fn constructors(ty: &Type, peano: bool) -> Option<Vec<Ctor>> {
+    match ty {
+        Type::Nat if peano => Some(vec![Ctor::Literal(Literal::Nat(0)), Ctor::Succ]),
+        Type::Bool => Some(vec![
+            Ctor::Literal(Literal::Bool(true)),
+            Ctor::Literal(Literal::Bool(false)),
+        ]),
+        Type::Unit => Some(vec![Ctor::Literal(Literal::Unit)]),
+        Type::Variant(vs) => Some(vs.iter().map(|v| Ctor::Label(v.label.clone())).collect()),
+        Type::Product(tys) => Some(vec![Ctor::Tuple(tys.len())]),
+        _ => None,
+    }
+}
+
+/// Expand every row whose first column is an or-pattern into one row per
+/// alternative, and replace as-patterns with the pattern they wrap
+pub(crate) fn expand<'p>(rows: &[Vec<&'p Pattern>]) -> Vec<Vec<&'p Pattern>> {
+    let mut out = Vec::with_capacity(rows.len());
+    for row in rows {
+        match row[0] {
+            Pattern::As(pat, _) => {
+                let mut row = row.clone();
+                row[0] = pat;
+                out.extend(expand(&[row]));
+            }
+            Pattern::Or(alts) => {
+                let alts = alts
+                    .iter()
+                    .map(|alt| {
+                        let mut alt = vec![alt];
+                        alt.extend_from_slice(&row[1..]);
+                        alt
+                    })
+                    .collect::<Vec<_>>();
+                out.extend(expand(&alts));
             }
+            _ => out.push(row.clone()),
+        }
+    }
+    out
+}
+
+/// Is the first column of the matrix, or of the new row, matched by `0` and
+/// `succ`?
+pub(crate) fn peano(rows: &[Vec<&Pattern>], new: &[&Pattern]) -> bool {
+    rows.iter()
+        .map(|row| row[0])
+        .chain(new.first().copied())
+        .any(|pat| matches!(pat, Pattern::Succ(_)))
+}
+
+/// The predecessor of a positive literal pattern, as the sub-pattern of the
+/// `succ` constructor it stands for
+pub(crate) fn predecessor(pat: &Pattern) -> Pattern {
+    match pat {
+        Pattern::Literal(Literal::Nat(n)) if *n > 0 => Pattern::Literal(Literal::Nat(n - 1)),
+        _ => Pattern::Any,
+    }
+}
+
+/// Specialize a row for constructor `ctor`, i.e. the row that matches the
+/// values built with `ctor` whose sub-terms match the returned row. `pred`
+/// is the [`predecessor`] of the first pattern of the row
+pub(crate) fn specialize<'p>(
+    row: &[&'p Pattern],
+    ctor: &Ctor,
+    arity: usize,
+    pred: &'p Pattern,
+) -> Option<Vec<&'p Pattern>> {
+    let mut out = match row[0] {
+        Pattern::Any | Pattern::Variable(_) => vec![&Pattern::Any; arity],
+        Pattern::Succ(pat) if *ctor == Ctor::Succ => vec![pat.as_ref()],
+        Pattern::Literal(Literal::Nat(n)) if *n > 0 && *ctor == Ctor::Succ => vec![pred],
+        Pattern::Literal(lit) if *ctor == Ctor::Literal(lit.clone()) => Vec::new(),
+        Pattern::Constructor(label, pat) if *ctor == Ctor::Label(label.clone()) => vec![pat.as_ref()],
+        Pattern::Product(pats) if *ctor == Ctor::Tuple(pats.len()) => pats.iter().collect(),
+        _ => return None,
+    };
+    out.extend_from_slice(&row[1..]);
+    Some(out)
+}
+
+/// Is there any value matched by both patterns?
+fn overlap(a: &Pattern, b: &Pattern) -> bool {
+    use Pattern::*;
+    match (a, b) {
+        (Any, _) | (Variable(_), _) | (_, Any) | (_, Variable(_)) => true,
+        (As(a, _), b) => overlap(a, b),
+        (a, As(b, _)) => overlap(a, b),
+        (Or(alts), b) | (b, Or(alts)) => alts.iter().any(|a| overlap(a, b)),
+        (Literal(a), Literal(b)) => a == b,
+        (Succ(a), Succ(b)) => overlap(a, b),
+        (Succ(p), Literal(crate::terms::Literal::Nat(n))) | (Literal(crate::terms::Literal::Nat(n)), Succ(p)) => {
+            *n > 0 && overlap(p, &Literal(crate::terms::Literal::Nat(n - 1)))
+        }
+        (Constructor(a, p), Constructor(b, q)) => a == b && overlap(p, q),
+        (Product(ps), Product(qs)) => ps.len() == qs.len() && ps.iter().zip(qs).all(|(p, q)| overlap(p, q)),
+        _ => false,
+    }
+}
+
+/// The rows of the matrix that match any value at all for the first column
+fn default<'p>(rows: &[Vec<&'p Pattern>]) -> Vec<Vec<&'p Pattern>> {
+    rows.iter()
+        .filter(|row| Ctor::of(row[0]).is_none())
+        .map(|row| row[1..].to_vec())
+        .collect()
+}
+
+/// Constructors appearing in the first column of the matrix
+pub(crate) fn used(rows: &[Vec<&Pattern>], peano: bool) -> Vec<Ctor> {
+    let head = if peano { Ctor::of_peano } else { Ctor::of };
+    rows.iter().filter_map(|row| head(row[0])).collect()
+}
+
+/// Constructors appearing in the first column of the matrix, if they make
+/// up every constructor of the type
+fn complete_signature(rows: &[Vec<&Pattern>], ty: &Type, peano: bool) -> Option<Vec<Ctor>> {
+    let all = constructors(ty, peano)?;
+    let used = used(rows, peano);
+    if all.iter().all(|c| used.contains(c)) {
+        Some(all)
+    } else {
+        None
+    }
+}
+
+/// Is the pattern row `new` useful with respect to the matrix `rows`, where
+/// the columns have types `tys`?
+fn useful(rows: &[Vec<&Pattern>], new: &[&Pattern], tys: &[Type]) -> bool {
+    if new.is_empty() {
+        return rows.is_empty();
+    }
+    // An or-pattern is useful if any of its alternatives is
+    match new[0] {
+        Pattern::Or(alts) => {
+            return alts.iter().any(|alt| {
+                let mut new_ = vec![alt];
+                new_.extend_from_slice(&new[1..]);
+                useful(rows, &new_, tys)
+            })
+        }
+        Pattern::As(pat, _) => {
+            let mut new_ = new.to_vec();
+            new_[0] = pat;
+            return useful(rows, &new_, tys);
+        }
+        _ => {}
+    }
+    let rows = &expand(rows);
+    let ty = unfold(&tys[0]);
+    let peano = peano(rows, new);
+    let recurse = |ctor: &Ctor| {
+        let mut sub_tys = ctor.fields(&ty);
+        let arity = sub_tys.len();
+        sub_tys.extend_from_slice(&tys[1..]);
+        let preds = rows.iter().map(|row| predecessor(row[0])).collect::<Vec<_>>();
+        let rows = rows
+            .iter()
+            .zip(&preds)
+            .filter_map(|(row, pred)| specialize(row, ctor, arity, pred))
+            .collect::<Vec<_>>();
+        let pred = predecessor(new[0]);
+        match specialize(new, ctor, arity, &pred) {
+            Some(new) => useful(&rows, &new, &sub_tys),
+            None => false,
+        }
+    };
+    let head = if peano {
+        Ctor::of_peano(new[0])
+    } else {
+        Ctor::of(new[0])
+    };
+    match head {
+        Some(ctor) => recurse(&ctor),
+        None => match complete_signature(rows, &ty, peano) {
+            Some(all) => all.iter().any(recurse),
+            None => useful(&default(rows), &new[1..], &tys[1..]),
+        },
+    }
+}
+
+/// Find a vector of patterns matching values that are not matched by any row
+/// of the matrix, if one exists
+fn missing(rows: &[Vec<&Pattern>], tys: &[Type]) -> Option<Vec<Pattern>> {
+    if tys.is_empty() {
+        return if rows.is_empty() { Some(Vec::new()) } else { None };
+    }
+    let rows = &expand(rows);
+    let ty = unfold(&tys[0]);
+    let peano = peano(rows, &[]);
+    match complete_signature(rows, &ty, peano) {
+        Some(all) => all.iter().find_map(|ctor| {
+            let mut sub_tys = ctor.fields(&ty);
+            let arity = sub_tys.len();
+            sub_tys.extend_from_slice(&tys[1..]);
+            let preds = rows.iter().map(|row| predecessor(row[0])).collect::<Vec<_>>();
+            let rows = rows
+                .iter()
+                .zip(&preds)
+                .filter_map(|(row, pred)| specialize(row, ctor, arity, pred))
+                .collect::<Vec<_>>();
+            let mut witness = missing(&rows, &sub_tys)?;
+            let rest = witness.split_off(arity);
+            let mut out = vec![ctor.apply(witness)];
+            out.extend(rest);
+            Some(out)
+        }),
+        None => {
+            let mut witness = missing(&default(rows), &tys[1..])?;
+            // If some, but not all, constructors were used then name one of
+            // the missing ones
+            let used = used(rows, peano);
+            let head = match constructors(&ty, peano) {
+                Some(all) if !used.is_empty() => all
+                    .into_iter()
+                    .find(|c| !used.contains(c))
+                    .map(|c| c.apply(c.fields(&ty).iter().map(wildcard).collect()))
+                    .unwrap_or(Pattern::Any),
+                _ => Pattern::Any,
+            };
+            witness.insert(0, head);
+            Some(witness)
         }
-        (Product(a), Product(b)) => a.iter().zip(b.iter()).all(|(a, b)| overlap(a, b)),
-        (Product(a), b) => a.iter().all(|a| overlap(a, b)),
-        (x, y) => x == y,
     }
 }
 
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub struct Matrix<'pat> {
     pub expr_ty: Type,
-    len: usize,
     matrix: Vec<Vec<&'pat Pattern>>,
 }
 
 impl<'pat> Matrix<'pat> {
     /// Create a new [`Matrix`] for a given type
     pub fn new(expr_ty: Type) -> Matrix<'pat> {
-        let len = match &expr_ty {
-            Type::Product(p) => p.len(),
-            _ => 1,
-        };
-
         Matrix {
             expr_ty,
-            len,
             matrix: Vec::new(),
         }
     }
 
-    /// Is the pattern [`Matrix`] exhaustive for this type?
-    ///
-    /// For a boolean type, True, False, or a wildcard/variable match are
-    /// required For a product type (tuple), the algorithm is slightly more
-    /// complicated: We generate a tuple of length N (equal to the length of
-    /// the case expressions' tuple) filled with Wildcard patterns, and see
-    /// if addition of tuple is a useful pattern. If the pattern is not
-    /// useful (i.e. it totally `overlap's` with an existing row), then the
-    /// matrix is exhaustive
-    ///
-    /// For a sum type, a dummy constructor of pattern `Const_i _` is generated
-    /// for all `i` of the possible constructors of the type. If none of the
-    /// dummy constructors are useful, then the current patterns are exhaustive
+    /// Is the pattern [`Matrix`] exhaustive for this type? This is the case
+    /// if a wildcard pattern would not be useful
     pub fn exhaustive(&self) -> bool {
-        match &self.expr_ty {
-            Type::Variant(v) => v.iter().all(|variant| {
-                // For all constructors in the sum type, generate a constructor
-                // pattern that will match all possible inhabitants of that
-                // constructor
-                let con = Pattern::Constructor(variant.label.clone(), Box::new(Pattern::Any));
-                let temp = [&con];
-                let mut ret = false;
-                for row in &self.matrix {
-                    if row.iter().zip(&temp).all(|(a, b)| overlap(a, b)) {
-                        ret = true;
-                        break;
-                    }
-                }
-                ret
-            }),
-            Type::Product(_) | Type::Nat => {
-                // Generate a tuple of wildcard patterns. If the pattern is
-                // useful, then we do not have an exhaustive matrix
-                let filler = (0..self.len).map(|_| Pattern::Any).collect::<Vec<_>>();
-                for row in &self.matrix {
-                    if row.iter().zip(filler.iter()).all(|(a, b)| overlap(a, b)) {
-                        return true;
-                    }
-                }
-                false
-            }
-            Type::Bool => {
-                // Boolean type is one of the simplest cases: we only need
-                // to match `true` and `false`, or one of those + a wildcard,
-                // or just a wildcard
-                let tru = Pattern::Literal(Literal::Bool(true));
-                let fal = Pattern::Literal(Literal::Bool(false));
-                !(self.can_add_row(vec![&tru]) && self.can_add_row(vec![&fal]))
-            }
-            Type::Unit => {
-                // Unit is a degenerate case
-                let unit = Pattern::Literal(Literal::Unit);
-                !self.can_add_row(vec![&unit])
-            }
-            _ => false,
-        }
+        !useful(&self.matrix, &[&Pattern::Any], std::slice::from_ref(&self.expr_ty))
     }
 
-    /// Return true if a new pattern row is reachable
-    fn can_add_row(&self, new_row: Vec<&'pat Pattern>) -> bool {
-        assert_eq!(self.len, new_row.len());
-        for row in &self.matrix {
-            if row.iter().zip(new_row.iter()).all(|(a, b)| overlap(a, b)) {
-                return false;
-            }
-        }
-        true
+    /// Return a pattern matching values that are not covered by any row of
+    /// the [`Matrix`], if the matrix is not exhaustive
+    pub fn missing(&self) -> Option<Pattern> {
+        missing(&self.matrix, std::slice::from_ref(&self.expr_ty)).map(|mut w| w.remove(0))
     }
 
-    fn try_add_row(&mut self, new_row: Vec<&'pat Pattern>) -> bool {
-        assert_eq!(self.len, new_row.len());
-        for row in &self.matrix {
-            if row.iter().zip(new_row.iter()).all(|(a, b)| overlap(a, b)) {
-                return false;
-            }
-        }
-        self.matrix.push(new_row);
-        true
+    /// Does the [`Pattern`] match any value not matched by the rows of the
+    /// [`Matrix`]?
+    pub fn useful(&self, pat: &Pattern) -> bool {
+        useful(&self.matrix, &[pat], std::slice::from_ref(&self.expr_ty))
     }
 
     /// Attempt to add a new [`Pattern`] to the [`Matrix`]
@@ -146,24 +375,71 @@ impl<'pat> Matrix<'pat> {
     /// Returns true on success, and false if the new pattern is
     /// unreachable
     pub fn add_pattern(&mut self, pat: &'pat Pattern) -> bool {
-        match pat {
-            Pattern::Any | Pattern::Variable(_) => {
-                let filler = (0..self.len).map(|_| &Pattern::Any).collect::<Vec<_>>();
-                self.try_add_row(filler)
+        if self.useful(pat) {
+            self.matrix.push(vec![pat]);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// A variable bound by the pattern of a case arm, along with its type and
+/// the span of the arm
+pub type Binding = (String, Type, Span);
+
+/// Collect the variables bound by a pattern into `out`, numbering them in
+/// source order starting from `next`. Returns a variable that is bound twice,
+/// with the numbers of both occurrences
+fn duplicate_binding(
+    pat: &Pattern,
+    next: &mut usize,
+    out: &mut Vec<(String, usize)>,
+) -> Result<(), (String, usize, usize)> {
+    fn bind(name: &str, next: &mut usize, out: &mut Vec<(String, usize)>) -> Result<(), (String, usize, usize)> {
+        let idx = *next;
+        *next += 1;
+        match out.iter().find(|(n, _)| n == name) {
+            Some((_, first)) => Err((name.to_string(), *first, idx)),
+            None => {
+                out.push((name.to_string(), idx));
+                Ok(())
             }
-            Pattern::Product(tuple) => self.try_add_row(tuple.iter().collect()),
-            Pattern::Literal(lit) => {
-                if self.len == 1 {
-                    self.try_add_row(vec![pat])
-                } else {
-                    false
-                }
+        }
+    }
+    match pat {
+        Pattern::Any | Pattern::Literal(_) => Ok(()),
+        Pattern::Variable(name) => bind(name, next, out),
+        Pattern::As(pat, name) => {
+            duplicate_binding(pat, next, out)?;
+            bind(name, next, out)
+        }
+        Pattern::Constructor(_, pat) | Pattern::Succ(pat) => duplicate_binding(pat, next, out),
+        Pattern::Product(pats) => pats.iter().try_for_each(|pat| duplicate_binding(pat, next, out)),
+        // Each alternative binds its variables separately
+        Pattern::Or(alts) => {
+            let mut first = None;
+            for alt in alts {
+                let mut bound = out.clone();
+                duplicate_binding(alt, next, &mut bound)?;
+                first.get_or_insert(bound);
             }
-            Pattern::Constructor(label, inner) => self.try_add_row(vec![pat]),
+            if let Some(bound) = first {
+                *out = bound;
+            }
+            Ok(())
         }
     }
 }
 
+/// Point out the type of every variable bound by a case arm in an error
+/// raised inside the arm
+fn note_bindings(diag: Diagnostic, binds: &[Binding]) -> Diagnostic {
+    binds.iter().fold(diag, |diag, (name, ty, span)| {
+        diag.message(*span, format!("`{}` bound here with type `{}`", name, ty))
+    })
+}
+
 impl Context {
     /// Type check a case expression, returning the Type of the arms, assuming
     /// that the case expression is exhaustive and well-typed
@@ -191,52 +467,143 @@ impl Context {
     /// the arms.
     pub(crate) fn type_check_case(&mut self, expr: &Term, arms: &[Arm]) -> Result<Type, Diagnostic> {
         let ty = self.type_check(expr)?;
-        let mut matrix = patterns::Matrix::new(ty);
+        let mut matrix = patterns::Matrix::new(self.normalize(&ty));
 
-        let mut set = HashSet::new();
-        for arm in arms {
+        // The inferred type of every arm, along with its position in the case
+        // expression, for reporting which arms disagree
+        let mut arm_tys: Vec<(usize, &Arm, Type)> = Vec::with_capacity(arms.len());
+        // Reachable, unguarded arms that have been added to the matrix
+        let mut covering: Vec<(usize, &Arm)> = Vec::new();
+        for (idx, arm) in arms.iter().enumerate() {
             if self.pattern_type_eq(&arm.pat, &matrix.expr_ty) {
+                self.check_duplicate_bindings(&arm.pat, &arm.binders, arm.span)?;
+                self.check_or_bindings(&arm.pat, &matrix.expr_ty, arm.span)?;
                 let height = self.stack.len();
 
                 let binds = PatTyStack::collect(&matrix.expr_ty, &arm.pat);
+                let bindings = PatVarStack::collect(&mut arm.pat.clone())
+                    .into_iter()
+                    .zip(binds.iter().cloned())
+                    .map(|(name, ty)| (name, ty, arm.span))
+                    .collect::<Vec<_>>();
+                self.bindings.push((arm.span, bindings.clone()));
                 for b in binds.into_iter().rev() {
-                    self.push(b.clone());
+                    self.push(b);
                 }
 
-                let arm_ty = self.type_check(&arm.term)?;
+                let recorded = self.errors.as_ref().map_or(0, Vec::len);
+                let arm_ty = match &arm.guard {
+                    Some(guard) => self.type_check_guard(guard),
+                    None => Ok(()),
+                }
+                .and_then(|_| self.type_check(&arm.term));
 
                 while self.stack.len() > height {
                     self.pop();
                 }
+                if let Some(errors) = &mut self.errors {
+                    for diag in &mut errors[recorded..] {
+                        *diag = note_bindings(diag.clone(), &bindings);
+                    }
+                }
+                let arm_ty = arm_ty.map_err(|diag| note_bindings(diag, &bindings))?;
 
-                set.insert(arm_ty);
-                if !matrix.add_pattern(&arm.pat) {
-                    return Err(Diagnostic::error(arm.span, "unreachable pattern!"));
+                if arm_ty != Type::Error {
+                    arm_tys.push((idx + 1, arm, self.normalize(&arm_ty)));
+                }
+                if matrix.expr_ty == Type::Error {
+                    continue;
+                }
+                // An unreachable arm is only a warning, pointing at the earlier
+                // arms that shadow it
+                if !matrix.useful(&arm.pat) {
+                    let mut diag = Diagnostic::warn(arm.span, TypeErrorKind::UnreachablePattern.to_string())
+                        .code(TypeErrorKind::UnreachablePattern.code());
+                    for (n, prev) in &covering {
+                        if overlap(&prev.pat, &arm.pat) {
+                            diag = diag.message(prev.span, format!("arm {} matches these values first", n));
+                        }
+                    }
+                    self.warn(diag);
+                    self.unreachable.push(arm.span);
+                } else if arm.guard.is_none() {
+                    // A guarded arm may fall through, so it never covers the
+                    // values matched by its pattern
+                    matrix.add_pattern(&arm.pat);
+                    covering.push((idx + 1, arm));
                 }
             } else {
                 return Err(
-                    Diagnostic::error(expr.span, format!("case binding has a type {:?}", &matrix.expr_ty)).message(
-                        arm.span,
-                        format!("but this pattern cannot bind a value of type {:?}", &matrix.expr_ty),
-                    ),
+                    Diagnostic::error(expr.span, format!("case binding has a type `{}`", &matrix.expr_ty))
+                        .code(TypeErrorKind::InvalidPattern.code())
+                        .message(
+                            arm.span,
+                            format!("but this pattern cannot bind a value of type `{}`", &matrix.expr_ty),
+                        ),
                 );
             }
         }
 
-        if set.len() != 1 {
-            return Err(Diagnostic::error(expr.span, format!("incompatible arms! {:?}", set)));
+        // The first arm's type is the expectation for every other arm
+        let expected = arm_tys.first().map(|(_, _, ty)| ty.clone());
+        if let Some((n, first, expected)) = arm_tys.first() {
+            let mut deviating = Vec::new();
+            for (idx, arm, ty) in &arm_tys[1..] {
+                if !self.compatible(expected, ty) {
+                    deviating.push((*idx, *arm, ty));
+                }
+            }
+            if !deviating.is_empty() {
+                let mut diag = Diagnostic::error(expr.span, TypeErrorKind::IncompatibleArms.to_string())
+                    .code(TypeErrorKind::IncompatibleArms.code())
+                    .message(first.term.span, format!("arm {} has type `{}`", n, expected));
+                for (idx, arm, ty) in deviating {
+                    diag = diag.message(
+                        arm.term.span,
+                        format!(
+                            "arm {} has type `{}`, but previous arms have type `{}`",
+                            idx, ty, expected
+                        ),
+                    );
+                    diag = self.explain_mismatch(diag, arm.term.span, expected, ty);
+                }
+                return Err(diag);
+            }
         }
 
-        if matrix.exhaustive() {
-            match set.into_iter().next() {
+        if matrix.expr_ty == Type::Error {
+            // Don't report missing patterns for an expression that
+            // already failed to typecheck
+            Ok(expected.unwrap_or(Type::Error))
+        } else if matrix.exhaustive() {
+            match expected {
                 Some(s) => Ok(s),
-                None => Err(Diagnostic::error(
-                    expr.span,
-                    "probably unreachable - expected variant type!",
-                )),
+                None => Err(
+                    Diagnostic::error(expr.span, "probably unreachable - expected variant type!")
+                        .code(TypeErrorKind::NotVariant.code()),
+                ),
             }
         } else {
-            Err(Diagnostic::error(expr.span, "patterns are not exhaustive!"))
+            let mut diag =
+                Diagnostic::error(expr.span, "patterns are not exhaustive!").code(TypeErrorKind::NotExhaustive.code());
+            if let Some(pat) = matrix.missing() {
+                diag = diag.message(expr.span, format!("pattern `{}` is not covered", pat));
+            }
+            Err(diag)
+        }
+    }
+
+    /// The guard of a case arm must be a boolean
+    fn type_check_guard(&mut self, guard: &Term) -> Result<(), Diagnostic> {
+        let ty = self.type_check(guard)?;
+        if self.compatible(&Type::Bool, &ty) {
+            Ok(())
+        } else {
+            Err(Diagnostic::error(
+                guard.span,
+                format!("case guards must have type `Bool`, but this guard has type `{}`", ty),
+            )
+            .code(TypeErrorKind::TypeMismatch.code()))
         }
     }
 
@@ -254,11 +621,17 @@ impl Context {
     /// is valid for a given case expression
     pub(crate) fn pattern_type_eq(&self, pat: &Pattern, ty: &Type) -> bool {
         match pat {
+            _ if *ty == Type::Error => true,
             Pattern::Any => true,
             Pattern::Variable(_) => true,
+            Pattern::Or(alts) => alts.iter().all(|alt| self.pattern_type_eq(alt, ty)),
+            Pattern::As(pat, _) => self.pattern_type_eq(pat, ty),
+            Pattern::Succ(pat) => *ty == Type::Nat && self.pattern_type_eq(pat, ty),
+            _ if matches!(ty, Type::Rec(_)) => self.pattern_type_eq(pat, &unfold(ty)),
             Pattern::Literal(lit) => match (lit, ty) {
                 (Literal::Bool(_), Type::Bool) => true,
                 (Literal::Nat(_), Type::Nat) => true,
+                (Literal::String(_), Type::String) => true,
                 (Literal::Unit, Type::Unit) => true,
                 _ => false,
             },
@@ -285,6 +658,128 @@ impl Context {
             },
         }
     }
+
+    /// Check that no variable is bound twice by `pat`, other than by different
+    /// alternatives of an or-pattern. `binders` are the spans of the
+    /// variables in the source, if known, and `span` is used otherwise
+    pub(crate) fn check_duplicate_bindings(
+        &self,
+        pat: &Pattern,
+        binders: &[Span],
+        span: Span,
+    ) -> Result<(), Diagnostic> {
+        duplicate_binding(pat, &mut 0, &mut Vec::new()).map_err(|(var, first, second)| {
+            let at = |idx: usize| binders.get(idx).copied().unwrap_or(span);
+            let kind = TypeErrorKind::DuplicatePatternBinding(var.clone());
+            Diagnostic::error(at(second), kind.to_string())
+                .code(kind.code())
+                .message(at(first), format!("`{}` is first bound here", var))
+        })
+    }
+
+    /// Check that every alternative of each or-pattern within `pat` binds
+    /// exactly the same variables, at the same types
+    pub(crate) fn check_or_bindings(&self, pat: &Pattern, ty: &Type, span: Span) -> Result<(), Diagnostic> {
+        self.or_bindings(pat, ty, &mut Vec::new()).map_err(|var| {
+            let kind = TypeErrorKind::OrPatternBinding(var);
+            Diagnostic::error(span, kind.to_string()).code(kind.code())
+        })
+    }
+
+    /// Collect the variables bound by a pattern for a value of type `ty`,
+    /// along with their types. Returns the name of a variable that is not
+    /// bound consistently by the alternatives of an or-pattern
+    fn or_bindings(&self, pat: &Pattern, ty: &Type, out: &mut Vec<(String, Type)>) -> Result<(), String> {
+        match pat {
+            Pattern::Any | Pattern::Literal(_) => {}
+            Pattern::Variable(name) => out.push((name.clone(), ty.clone())),
+            Pattern::As(pat, name) => {
+                self.or_bindings(pat, ty, out)?;
+                out.push((name.clone(), ty.clone()));
+            }
+            Pattern::Succ(pat) => self.or_bindings(pat, ty, out)?,
+            Pattern::Product(pats) => {
+                let tys = match unfold(ty) {
+                    Type::Product(tys) => tys,
+                    _ => Vec::new(),
+                };
+                for (idx, pat) in pats.iter().enumerate() {
+                    self.or_bindings(pat, tys.get(idx).unwrap_or(&Type::Error), out)?;
+                }
+            }
+            Pattern::Constructor(label, pat) => {
+                let ty = match unfold(ty) {
+                    Type::Variant(vs) => vs.into_iter().find(|v| &v.label == label).map(|v| v.ty),
+                    _ => None,
+                };
+                self.or_bindings(pat, ty.as_ref().unwrap_or(&Type::Error), out)?;
+            }
+            Pattern::Or(alts) => {
+                let (first, rest) = match alts.split_first() {
+                    Some(split) => split,
+                    None => return Ok(()),
+                };
+                let mut expected = Vec::new();
+                self.or_bindings(first, ty, &mut expected)?;
+                // Every alternative is compared against the same types
+                let hashed = expected
+                    .iter()
+                    .map(|(_, ty)| HashedType::new(ty.clone()))
+                    .collect::<Vec<_>>();
+                for alt in rest {
+                    let mut found = Vec::new();
+                    self.or_bindings(alt, ty, &mut found)?;
+                    for ((name, _), ty) in expected.iter().zip(&hashed) {
+                        if !found
+                            .iter()
+                            .any(|(n, t)| n == name && self.hashed_type_eq(ty, &HashedType::new(t.clone())))
+                        {
+                            return Err(name.clone());
+                        }
+                    }
+                    if let Some((name, _)) = found.iter().find(|(n, _)| !expected.iter().any(|(m, _)| m == n)) {
+                        return Err(name.clone());
+                    }
+                }
+                out.extend(expected);
+            }
+        }
+        Ok(())
+    }
+
+    /// The variables bound by the pattern of each case arm checked by the
+    /// last call to [`Context::type_check_all`], with their types, in the
+    /// order the arms appear in the source
+    pub fn arm_bindings(&self) -> &[(Span, Vec<Binding>)] {
+        &self.bindings
+    }
+
+    /// Remove the case arms found to be unreachable while type checking
+    /// `term`. The warnings for these arms have already been recorded, and
+    /// since they are useless, every case expression remains exhaustive
+    pub fn prune_unreachable(&mut self, term: &mut Term) {
+        let mut prune = Prune {
+            arms: std::mem::take(&mut self.unreachable),
+        };
+        prune.visit(term);
+    }
+}
+
+struct Prune {
+    arms: Vec<Span>,
+}
+
+impl MutTermVisitor for Prune {
+    fn visit_case(&mut self, sp: &mut Span, term: &mut Term, arms: &mut Vec<Arm>) {
+        arms.retain(|arm| !self.arms.contains(&arm.span));
+        self.visit(term);
+        for arm in arms {
+            if let Some(guard) = &mut arm.guard {
+                self.visit(guard);
+            }
+            self.visit(&mut arm.term);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -424,4 +919,588 @@ mod test {
         assert!(!matrix.add_pattern(&pats[1]));
         assert!(matrix.exhaustive());
     }
+
+    #[test]
+    fn report_deviating_arm() {
+        let input = "case 1 of | 0 => 10 | 1 => true | _ => 12";
+        let mut p = crate::syntax::parser::Parser::new(input);
+        let tm = p.parse().unwrap();
+        let arm = match &tm.kind {
+            crate::terms::Kind::Case(_, arms) => arms[1].term.span,
+            _ => unreachable!(),
+        };
+
+        let diag = Context::default().type_check(&tm).unwrap_err();
+        assert_eq!(
+            diag.primary.info,
+            "the arms of this case expression have different types"
+        );
+        assert_eq!(diag.other.len(), 2, "{:?}", diag.other);
+        assert_eq!(diag.other[0].info, "arm 1 has type `Nat`");
+        assert_eq!(diag.other[1].span, arm);
+        assert_eq!(
+            diag.other[1].info,
+            "arm 2 has type `Bool`, but previous arms have type `Nat`"
+        );
+    }
+
+    #[test]
+    fn explain_deviating_arm() {
+        let input = "case 1 of | 0 => (1, true) | _ => (2, 3)";
+        let tm = crate::syntax::parser::Parser::new(input).parse().unwrap();
+        let diag = Context::default().type_check(&tm).unwrap_err();
+        assert_eq!(diag.other.len(), 3, "{:?}", diag.other);
+        assert_eq!(diag.other[2].info, "types differ at `.1`: expected `Bool`, found `Nat`");
+    }
+
+    /// Type check a case expression, which must succeed, returning the
+    /// position of every unreachable arm along with the positions of the
+    /// arms that shadow it
+    fn unreachable_arms(ctx: &mut Context, tm: &Term) -> Vec<(usize, Vec<usize>)> {
+        ctx.type_check(tm).unwrap();
+        let arms = match &tm.kind {
+            Kind::Case(_, arms) => arms,
+            _ => panic!("expected a case expression"),
+        };
+        let position = |span| arms.iter().position(|arm| arm.span == span).unwrap() + 1;
+        ctx.take_warnings()
+            .into_iter()
+            .map(|diag| {
+                assert!(matches!(diag.level, crate::diagnostics::Level::Warn));
+                assert_eq!(diag.primary.info, "this pattern is unreachable");
+                let shadows = diag.other.iter().map(|a| position(a.span)).collect();
+                (position(diag.primary.span), shadows)
+            })
+            .collect()
+    }
+
+    fn nat_list_case(arms: &str) -> (Context, Term) {
+        let mut ctx = Context::default();
+        let list = Type::Rec(Box::new(Type::Variant(vec![
+            variant!("Nil", Type::Unit),
+            variant!("Cons", Type::Product(vec![Type::Nat, Type::Var(0)])),
+        ])));
+        ctx.alias("NatList".into(), list).unwrap();
+        let input = format!(
+            "case Cons (1, Cons (2, Cons (3, Nil of NatList) of NatList) of NatList) of NatList of {}",
+            arms
+        );
+        let mut tm = crate::syntax::parser::Parser::new(&input).parse().unwrap();
+        ctx.de_alias(&mut tm);
+        crate::visit::MutTermVisitor::visit(&mut crate::terms::visit::InjRewriter, &mut tm);
+        (ctx, tm)
+    }
+
+    #[test]
+    fn nested_constructors() {
+        let (mut ctx, tm) = nat_list_case(
+            "| Cons (a, Cons (b, Cons (c, _))) => add a (add (mul 10 b) (mul 100 c)) \
+             | Cons (a, Cons (b, Nil)) => add a b \
+             | Cons (a, Nil) => a \
+             | Nil => 0",
+        );
+        assert_eq!(ctx.type_check(&tm).unwrap(), Type::Nat);
+
+        let ev = crate::eval::Eval::with_context(&ctx);
+        let mut t = tm;
+        while let Some(next) = ev.small_step(t.clone()) {
+            t = next;
+        }
+        assert_eq!(t.kind, Kind::Lit(crate::terms::Literal::Nat(321)));
+    }
+
+    #[test]
+    fn nested_missing_pattern() {
+        let (mut ctx, tm) = nat_list_case(
+            "| Cons (a, Cons (b, Cons (c, _))) => a \
+             | Cons (a, Cons (b, Nil)) => b \
+             | Nil => 0",
+        );
+        let diag = ctx.type_check(&tm).unwrap_err();
+        assert_eq!(diag.primary.info, "patterns are not exhaustive!");
+        assert_eq!(diag.other[0].info, "pattern `Cons (_, Nil)` is not covered");
+
+        let (mut ctx, tm) = nat_list_case(
+            "| Cons (_, Cons (_, Cons (0, _))) => 0 \
+             | Cons (_, Cons (_, Nil)) => 0 \
+             | Cons (_, Nil) => 0 \
+             | Nil => 0",
+        );
+        let diag = ctx.type_check(&tm).unwrap_err();
+        assert_eq!(
+            diag.other[0].info,
+            "pattern `Cons (_, Cons (_, Cons (_, _)))` is not covered"
+        );
+    }
+
+    #[test]
+    fn nested_unreachable() {
+        let (mut ctx, tm) = nat_list_case(
+            "| Cons (_, Cons (_, _)) => 0 \
+             | Cons (_, Cons (1, Nil)) => 1 \
+             | _ => 2",
+        );
+        assert_eq!(unreachable_arms(&mut ctx, &tm), vec![(2, vec![1])]);
+    }
+
+    #[test]
+    fn or_pattern_bindings() {
+        for input in &[
+            "case (1, true) of | (x, true) | (_, false) => x",
+            "case (1, true) of | (x, _) | (_, x) => 0",
+            "case (1, true) of | (_, true) | (y, false) => 0",
+        ] {
+            let tm = crate::syntax::parser::Parser::new(input).parse().unwrap();
+            let diag = Context::default().type_check(&tm).unwrap_err();
+            let var = if input.contains('y') { "y" } else { "x" };
+            assert_eq!(
+                diag.primary.info,
+                TypeErrorKind::OrPatternBinding(var.into()).to_string(),
+                "{}",
+                input
+            );
+        }
+
+        let input = "case (1, 2) of | (x, 0) | (0, x) => x | _ => 0";
+        let tm = crate::syntax::parser::Parser::new(input).parse().unwrap();
+        assert_eq!(Context::default().type_check(&tm).unwrap(), Type::Nat);
+    }
+
+    #[test]
+    fn or_pattern_exhaustive() {
+        let (mut ctx, tm) = nat_list_case(
+            "| Cons (a, Nil) | Cons (_, Cons (a, _)) => a \
+             | Nil => 0",
+        );
+        assert_eq!(ctx.type_check(&tm).unwrap(), Type::Nat);
+        let ev = crate::eval::Eval::with_context(&ctx);
+        let mut t = tm;
+        while let Some(next) = ev.small_step(t.clone()) {
+            t = next;
+        }
+        assert_eq!(t.kind, Kind::Lit(crate::terms::Literal::Nat(2)));
+
+        let (mut ctx, tm) = nat_list_case("| Cons (_, Nil | Cons (_, _)) => 1 | Nil => 0");
+        assert_eq!(ctx.type_check(&tm).unwrap(), Type::Nat);
+
+        let (mut ctx, tm) = nat_list_case("| Cons (_, Nil) | Nil => 1");
+        let diag = ctx.type_check(&tm).unwrap_err();
+        assert_eq!(diag.other[0].info, "pattern `Cons (_, Cons (_, _))` is not covered");
+    }
+
+    #[test]
+    fn or_pattern_unreachable() {
+        let input = "case 1 of | 0 | 1 | 2 => true | 1 => false | _ => false";
+        let tm = crate::syntax::parser::Parser::new(input).parse().unwrap();
+        assert_eq!(unreachable_arms(&mut Context::default(), &tm), vec![(2, vec![1])]);
+
+        let (mut ctx, tm) = nat_list_case(
+            "| Cons (_, Nil | Cons (_, _)) => 1 \
+             | Cons (_, Nil) => 2 \
+             | Nil => 0",
+        );
+        assert_eq!(unreachable_arms(&mut ctx, &tm), vec![(2, vec![1])]);
+    }
+
+    #[test]
+    fn guards() {
+        let eval = |ctx: &Context, tm: Term| {
+            let ev = crate::eval::Eval::with_context(ctx);
+            let mut t = tm;
+            while let Some(next) = ev.small_step(t.clone()) {
+                t = next;
+            }
+            t.kind
+        };
+
+        // Only exhaustive because of the final, unguarded arm, which is not
+        // shadowed by the guarded arm with the same pattern
+        let (mut ctx, tm) = nat_list_case(
+            "| Cons (a, _) when iszero a => 0 \
+             | Cons (a, Cons (b, _)) when lt a b => b \
+             | Cons (a, _) => a \
+             | Nil => 0",
+        );
+        assert_eq!(ctx.type_check(&tm).unwrap(), Type::Nat);
+        assert_eq!(eval(&ctx, tm), Kind::Lit(crate::terms::Literal::Nat(2)));
+
+        let (mut ctx, tm) = nat_list_case("| Cons (a, _) when iszero a => 0 | Nil => 0");
+        let diag = ctx.type_check(&tm).unwrap_err();
+        assert_eq!(diag.primary.info, "patterns are not exhaustive!");
+
+        let (mut ctx, tm) = nat_list_case("| Cons (a, _) when a => 0 | _ => 0");
+        let diag = ctx.type_check(&tm).unwrap_err();
+        assert_eq!(
+            diag.primary.info,
+            "case guards must have type `Bool`, but this guard has type `Nat`"
+        );
+
+        // A guarded arm is still unreachable if earlier arms cover it
+        let (mut ctx, tm) = nat_list_case("| _ => 0 | Cons (a, _) when iszero a => 1");
+        assert_eq!(unreachable_arms(&mut ctx, &tm), vec![(2, vec![1])]);
+    }
+
+    #[test]
+    fn as_pattern_binding_order() {
+        // Binders are numbered in source order: the as-binder comes after
+        // the binders of the pattern it wraps
+        let input = "case (1, true) of | (a, b) as p => (p, b, a)";
+        let tm = crate::syntax::parser::Parser::new(input).parse().unwrap();
+        match &tm.kind {
+            Kind::Case(_, arms) => assert_eq!(arms[0].term.to_string(), "(#2,#1,#0)"),
+            _ => unreachable!(),
+        }
+        let ty = Context::default().type_check(&tm).unwrap();
+        assert_eq!(
+            ty,
+            Type::Product(vec![Type::Product(vec![Type::Nat, Type::Bool]), Type::Bool, Type::Nat])
+        );
+
+        let ctx = Context::default();
+        let ev = crate::eval::Eval::with_context(&ctx);
+        let mut t = tm;
+        while let Some(next) = ev.small_step(t.clone()) {
+            t = next;
+        }
+        assert_eq!(t.to_string(), "((1,true),true,1)");
+    }
+
+    #[test]
+    fn as_pattern_recursive() {
+        // The whole value keeps its recursive type, so it can be returned
+        // without folding it again
+        let (mut ctx, tm) = nat_list_case(
+            "| Cons (_, Cons (_, _) as rest) => rest \
+             | l => l",
+        );
+        let list = match &tm.kind {
+            Kind::Case(expr, _) => expr.as_ref().clone(),
+            _ => unreachable!(),
+        };
+        let ty = ctx.type_check(&tm).unwrap();
+        assert!(matches!(ty, Type::Rec(_)), "{:?}", ty);
+
+        let ev = crate::eval::Eval::with_context(&ctx);
+        let mut t = tm;
+        while let Some(next) = ev.small_step(t.clone()) {
+            t = next;
+        }
+        let tail = match &list.kind {
+            Kind::Fold(_, inner) => match &inner.kind {
+                Kind::Injection(_, payload, _) => match &payload.kind {
+                    Kind::Product(fields) => fields[1].kind.clone(),
+                    _ => unreachable!(),
+                },
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        };
+        assert_eq!(t.kind, tail);
+    }
+
+    #[test]
+    fn product_cross_coverage() {
+        // Every column is covered on its own, but `(false, true)` is not
+        let input = "\\p: (Bool, Bool). case p of | (true, true) => 0 | (false, false) => 1 | (true, false) => 2";
+        let tm = crate::syntax::parser::Parser::new(input).parse().unwrap();
+        let diag = Context::default().type_check(&tm).unwrap_err();
+        assert_eq!(diag.primary.info, "patterns are not exhaustive!");
+        assert_eq!(diag.other[0].info, "pattern `(false, true)` is not covered");
+
+        let input = "case (true, false) of \
+                     | (true, _) => 0 | (_, true) => 1 | (true, false) => 2 | (false, false) => 3";
+        let tm = crate::syntax::parser::Parser::new(input).parse().unwrap();
+        assert_eq!(unreachable_arms(&mut Context::default(), &tm), vec![(3, vec![1])]);
+    }
+
+    #[test]
+    fn product_nested_bindings() {
+        let mut ctx = Context::default();
+        ctx.alias(
+            "Opt".into(),
+            Type::Variant(vec![variant!("None", Type::Unit), variant!("Some", Type::Nat)]),
+        )
+        .unwrap();
+        let input = "case (1, 0, Some 2 of Opt) of \
+                     | (x, 0, Some y) => add (mul 10 x) y \
+                     | (_, _, Some _) => 0 \
+                     | (x, _, None) => x";
+        let mut tm = crate::syntax::parser::Parser::new(input).parse().unwrap();
+        ctx.de_alias(&mut tm);
+        match &tm.kind {
+            Kind::Case(_, arms) => assert_eq!(arms[0].term.to_string(), "((Add ((Mul 10) #0)) #1)"),
+            _ => unreachable!(),
+        }
+        assert_eq!(ctx.type_check(&tm).unwrap(), Type::Nat);
+
+        let ev = crate::eval::Eval::with_context(&ctx);
+        let mut t = tm;
+        while let Some(next) = ev.small_step(t.clone()) {
+            t = next;
+        }
+        assert_eq!(t.kind, Kind::Lit(crate::terms::Literal::Nat(12)));
+
+        // The arity of the pattern must match the product type
+        let tm = crate::syntax::parser::Parser::new("case (1, 2) of | (x, y, z) => x")
+            .parse()
+            .unwrap();
+        assert!(Context::default().type_check(&tm).is_err());
+    }
+
+    #[test]
+    fn unreachable_arm_warnings() {
+        // Shadowed by an identical constructor pattern, and by a wildcard
+        // following arms that already cover every value
+        let (mut ctx, tm) = nat_list_case(
+            "| Nil => 0 \
+             | Cons (a, _) => a \
+             | Nil => 1 \
+             | _ => 2",
+        );
+        assert_eq!(unreachable_arms(&mut ctx, &tm), vec![(3, vec![1]), (4, vec![1, 2])]);
+
+        let (mut ctx, tm) = nat_list_case("| _ => 0 | Cons (_, Nil) => 1 | Nil => 2");
+        assert_eq!(unreachable_arms(&mut ctx, &tm), vec![(2, vec![1]), (3, vec![1])]);
+
+        // Shadowed by an or-pattern, alone and jointly with another arm
+        let (mut ctx, tm) = nat_list_case(
+            "| Cons (0, _) | Cons (_, Nil) => 0 \
+             | Cons (_, Cons (_, _)) => 1 \
+             | Cons (2, Nil) => 2 \
+             | Nil => 3",
+        );
+        assert_eq!(unreachable_arms(&mut ctx, &tm), vec![(3, vec![1])]);
+        let (mut ctx, tm) = nat_list_case(
+            "| Cons (0, _) | Nil => 0 \
+             | Cons (_, Cons (_, _)) => 1 \
+             | Cons (_, Nil) => 2 \
+             | Cons (0, Cons (1, _)) => 3",
+        );
+        assert_eq!(unreachable_arms(&mut ctx, &tm), vec![(4, vec![1, 2])]);
+    }
+
+    #[test]
+    fn prune_unreachable() {
+        let eval = |ctx: &Context, tm: Term| {
+            let ev = crate::eval::Eval::with_context(ctx);
+            let mut t = tm;
+            while let Some(next) = ev.small_step(t.clone()) {
+                t = next;
+            }
+            t.kind
+        };
+        let arm_count = |tm: &Term| match &tm.kind {
+            Kind::Case(_, arms) => arms.len(),
+            _ => panic!("expected a case expression"),
+        };
+
+        let cases = [
+            ("| Cons (a, _) => a | Nil => 0 | Cons (_, Nil) => 1", 2),
+            // The third arm is only reachable when the guard fails
+            (
+                "| Cons (a, _) when iszero a => 0 | Nil => 0 | Cons (a, _) => a | Cons (_, Nil) => 1",
+                3,
+            ),
+        ];
+        for (arms, remaining) in &cases {
+            let (mut ctx, tm) = nat_list_case(arms);
+            let ty = ctx.type_check(&tm).unwrap();
+            assert_eq!(ctx.take_warnings().len(), 1, "{}", arms);
+
+            let mut pruned = tm.clone();
+            ctx.prune_unreachable(&mut pruned);
+            assert_eq!(arm_count(&pruned), *remaining, "{}", arms);
+            assert_eq!(ctx.type_check(&pruned).unwrap(), ty);
+            assert!(ctx.take_warnings().is_empty());
+            assert_eq!(eval(&ctx, pruned), eval(&ctx, tm));
+        }
+    }
+
+    #[test]
+    fn succ_patterns() {
+        let eval = |ctx: &Context, tm: Term| {
+            let ev = crate::eval::Eval::with_context(ctx);
+            let mut t = tm;
+            while let Some(next) = ev.small_step(t.clone()) {
+                t = next;
+            }
+            t.kind
+        };
+        let parse = |input: &str| crate::syntax::parser::Parser::new(input).parse().unwrap();
+        let mut ctx = Context::default();
+
+        let cases = [
+            ("case 3 of | 0 => 0 | succ n => n", 2),
+            ("case 5 of | 0 => 0 | succ 0 => 1 | succ (succ n) => n", 3),
+            ("case 1 of | 0 | 1 => 7 | succ (succ n) => n", 7),
+            (
+                "case 4 of | succ (succ (succ 0)) => 0 | succ n as m => add n m | _ => 1",
+                7,
+            ),
+            (
+                "letrec sum: Nat->Nat = \\n: Nat. case n of | 0 => 0 | succ m => add n (sum m) in sum 4",
+                10,
+            ),
+        ];
+        for (input, value) in &cases {
+            let tm = parse(input);
+            assert_eq!(ctx.type_check(&tm).unwrap(), Type::Nat, "{}", input);
+            assert!(ctx.take_warnings().is_empty(), "{}", input);
+            assert_eq!(
+                eval(&ctx, tm.clone()),
+                Kind::Lit(crate::terms::Literal::Nat(*value)),
+                "{}",
+                input
+            );
+            let erased = crate::erase::eval(&crate::erase::erase(&tm));
+            assert!(
+                matches!(erased, Some(crate::erase::Value::Lit(crate::terms::Literal::Nat(n))) if n == *value),
+                "{}",
+                input
+            );
+        }
+
+        for (input, witness) in &[
+            ("case 3 of | succ n => n", "0"),
+            // The innermost column only has a literal, so its witness is a
+            // wildcard, as with any other literal patterns
+            ("case 3 of | 0 => 0 | succ 0 => 1 | succ (succ 0) => 2", "succ (succ _)"),
+            ("case 3 of | 0 | 1 => 0 | succ (succ (succ n)) => n", "succ (succ 0)"),
+        ] {
+            let diag = ctx.type_check(&parse(input)).unwrap_err();
+            assert_eq!(diag.primary.info, "patterns are not exhaustive!");
+            assert_eq!(diag.other[0].info, format!("pattern `{}` is not covered", witness));
+        }
+
+        // Literals stand for the corresponding number of successors
+        let tm = parse("case 3 of | succ _ => 0 | 2 => 1 | _ => 2");
+        assert_eq!(unreachable_arms(&mut ctx, &tm), vec![(2, vec![1])]);
+
+        let diag = ctx
+            .type_check(&parse("case true of | succ n => n | _ => 0"))
+            .unwrap_err();
+        assert_eq!(
+            diag.other[0].info,
+            "but this pattern cannot bind a value of type `Bool`"
+        );
+    }
+
+    #[test]
+    fn binding_notes() {
+        let notes = |diag: &Diagnostic| diag.other.iter().map(|a| a.info.clone()).collect::<Vec<_>>();
+        let (mut ctx, tm) = nat_list_case("| Nil => 0 | Cons (x, rest) as l => x rest");
+        let diag = ctx.type_check(&tm).unwrap_err();
+        let notes = notes(&diag);
+        assert!(
+            notes.contains(&"`x` bound here with type `Nat`".to_string()),
+            "{:?}",
+            notes
+        );
+        assert!(
+            notes.iter().any(|n| n.starts_with("`rest` bound here with type `rec")),
+            "{:?}",
+            notes
+        );
+        assert!(notes.iter().any(|n| n.starts_with("`l` bound here")), "{:?}", notes);
+
+        // Errors recorded while accumulating get the same notes, and the
+        // bindings of every arm are available afterwards
+        let (mut ctx, tm) = nat_list_case("| Nil => 0 | Cons (x, _) => x true");
+        let (ty, errors) = ctx.type_check_all(&tm);
+        assert!(ty.is_none());
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0]
+            .other
+            .iter()
+            .any(|a| a.info == "`x` bound here with type `Nat`"));
+        let arms = ctx
+            .arm_bindings()
+            .iter()
+            .map(|(_, binds)| binds.iter().map(|(name, _, _)| name.as_str()).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(arms, vec![vec![], vec!["x"]]);
+    }
+
+    #[test]
+    fn single_constructor_exhaustive() {
+        let parse = |input: &str| crate::syntax::parser::Parser::new(input).parse().unwrap();
+        let mut ctx = Context::default();
+        ctx.alias("One".into(), Type::Variant(vec![variant!("Only", Type::Nat)]))
+            .unwrap();
+        ctx.alias("Wrap".into(), Type::Variant(vec![variant!("Wrap", Type::Unit)]))
+            .unwrap();
+
+        let cases = [
+            "case unit of | unit => 1",
+            "case () of | () => 1",
+            "case Only 3 of One of | Only n => n",
+            "case Wrap of Wrap of | Wrap => 1",
+            "case (Only 1 of One, Wrap of Wrap) of | (Only n, Wrap) => n",
+            "case (unit, Only 2 of One) of | ((), Only n) => n",
+        ];
+        for input in &cases {
+            let mut tm = parse(input);
+            ctx.de_alias(&mut tm);
+            assert_eq!(ctx.type_check(&tm).unwrap(), Type::Nat, "{}", input);
+            assert!(ctx.take_warnings().is_empty(), "{}", input);
+
+            // A wildcard arm after the only constructor is never reached
+            let mut tm = parse(&format!("{} | _ => 0", input));
+            ctx.de_alias(&mut tm);
+            assert_eq!(ctx.type_check(&tm).unwrap(), Type::Nat, "{}", input);
+            assert_eq!(ctx.take_warnings().len(), 1, "{}", input);
+        }
+    }
+
+    #[test]
+    fn duplicate_bindings() {
+        let mut ctx = Context::default();
+        ctx.alias(
+            "Opt".into(),
+            Type::Variant(vec![variant!("None", Type::Unit), variant!("Some", Type::Nat)]),
+        )
+        .unwrap();
+        let text = |input: &str, span: Span| input[span.start.abs as usize..span.end.abs as usize].to_string();
+
+        let cases = [
+            ("case (1, 2) of | (x, x) => x", "x"),
+            ("case (1, (Some 2 of Opt, 3)) of | (y, (Some x, x)) => x", "x"),
+            ("case Some 1 of Opt of | Some x as x => x", "x"),
+            ("case (Some 1 of Opt, 2) of | (Some n, m) as n => m", "n"),
+        ];
+        for (input, var) in &cases {
+            let mut tm = crate::syntax::parser::Parser::new(input).parse().unwrap();
+            ctx.de_alias(&mut tm);
+            let diag = ctx.type_check(&tm).unwrap_err();
+            assert_eq!(
+                diag.primary.info,
+                TypeErrorKind::DuplicatePatternBinding(var.to_string()).to_string()
+            );
+            assert_eq!(text(input, diag.primary.span), *var, "{}", input);
+            assert_eq!(diag.other[0].info, format!("`{}` is first bound here", var));
+            assert_eq!(text(input, diag.other[0].span), *var);
+            assert!(diag.other[0].span.start.abs < diag.primary.span.start.abs);
+        }
+
+        // Alternatives of an or-pattern bind the same variables separately,
+        // but may not repeat a variable bound outside of the or-pattern
+        let mut tm = crate::syntax::parser::Parser::new("case (1, 2) of | (0, x) | (x, 0) => x | _ => 0")
+            .parse()
+            .unwrap();
+        assert_eq!(ctx.type_check(&tm).unwrap(), Type::Nat);
+        tm = crate::syntax::parser::Parser::new("case (1, 2) of | (x, 0 | x) => x | _ => 0")
+            .parse()
+            .unwrap();
+        assert!(ctx.type_check(&tm).is_err());
+
+        // Let bindings are checked too, pointing at the bound term
+        let tm = crate::syntax::parser::Parser::new("let (a, a) = (1, 2) in a")
+            .parse()
+            .unwrap();
+        let diag = ctx.type_check(&tm).unwrap_err();
+        assert_eq!(
+            diag.primary.info,
+            "variable `a` is bound more than once in this pattern"
+        );
+    }
 }
@@ -1,5 +1,6 @@
 use super::Type;
-use crate::visit::MutTypeVisitor;
+use crate::visit::{MutTypeVisitor, TypeVisitor};
+use std::collections::BTreeSet;
 use std::convert::TryFrom;
 
 pub struct Shift {
@@ -39,6 +40,8 @@ impl MutTypeVisitor for Shift {
     }
 }
 
+/// Substitute a type for the variable at the cutoff, shifting each copy by
+/// the number of binders it is placed under
 pub struct Subst {
     pub cutoff: usize,
     pub ty: Type,
@@ -71,10 +74,11 @@ impl MutTypeVisitor for Subst {
 
     fn visit(&mut self, ty: &mut Type) {
         match ty {
-            Type::Unit | Type::Bool | Type::Nat => {}
-            Type::Var(v) if *v >= self.cutoff => {
-                Shift::new(self.cutoff as isize).visit(&mut self.ty);
-                *ty = self.ty.clone();
+            Type::Unit | Type::Bool | Type::Nat | Type::String | Type::Error | Type::Meta(_) | Type::Hole => {}
+            Type::Var(v) if *v == self.cutoff => {
+                let mut copy = self.ty.clone();
+                Shift::new(self.cutoff as isize).visit(&mut copy);
+                *ty = copy;
             }
             Type::Var(v) => self.visit_var(v),
             Type::Variant(v) => self.visit_variant(v),
@@ -87,3 +91,37 @@ impl MutTypeVisitor for Subst {
         }
     }
 }
+
+/// Collect the free type variables of a type, adjusted so that they are
+/// relative to the outside of the type
+#[derive(Default)]
+pub struct FreeVars {
+    pub cutoff: usize,
+    pub vars: BTreeSet<usize>,
+}
+
+impl TypeVisitor for FreeVars {
+    fn visit_var(&mut self, var: usize) {
+        if var >= self.cutoff {
+            self.vars.insert(var - self.cutoff);
+        }
+    }
+
+    fn visit_universal(&mut self, inner: &Type) {
+        self.cutoff += 1;
+        self.visit(inner);
+        self.cutoff -= 1;
+    }
+
+    fn visit_existential(&mut self, inner: &Type) {
+        self.cutoff += 1;
+        self.visit(inner);
+        self.cutoff -= 1;
+    }
+
+    fn visit_rec(&mut self, ty: &Type) {
+        self.cutoff += 1;
+        self.visit(ty);
+        self.cutoff -= 1;
+    }
+}
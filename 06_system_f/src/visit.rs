@@ -2,6 +2,7 @@
 use crate::patterns::Pattern;
 use crate::terms::{Arm, Kind, Literal, Primitive, Term};
 use crate::types::{Type, Variant};
+use std::rc::Rc;
 use util::span::Span;
 
 pub trait MutTypeVisitor: Sized {
@@ -39,7 +40,7 @@ pub trait MutTypeVisitor: Sized {
 
     fn visit(&mut self, ty: &mut Type) {
         match ty {
-            Type::Unit | Type::Bool | Type::Nat => {}
+            Type::Unit | Type::Bool | Type::Nat | Type::String | Type::Error | Type::Meta(_) | Type::Hole => {}
             Type::Var(v) => self.visit_var(v),
             Type::Variant(v) => self.visit_variant(v),
             Type::Product(v) => self.visit_product(v),
@@ -52,6 +53,55 @@ pub trait MutTypeVisitor: Sized {
     }
 }
 
+/// Read-only counterpart to [`MutTypeVisitor`]
+pub trait TypeVisitor: Sized {
+    fn visit_var(&mut self, var: usize) {}
+    fn visit_alias(&mut self, alias: &str) {}
+
+    fn visit_arrow(&mut self, ty1: &Type, ty2: &Type) {
+        self.visit(ty1);
+        self.visit(ty2);
+    }
+
+    fn visit_universal(&mut self, inner: &Type) {
+        self.visit(inner);
+    }
+
+    fn visit_existential(&mut self, inner: &Type) {
+        self.visit(inner);
+    }
+
+    fn visit_variant(&mut self, variant: &[Variant]) {
+        for v in variant {
+            self.visit(&v.ty);
+        }
+    }
+
+    fn visit_product(&mut self, product: &[Type]) {
+        for v in product {
+            self.visit(v);
+        }
+    }
+
+    fn visit_rec(&mut self, ty: &Type) {
+        self.visit(ty);
+    }
+
+    fn visit(&mut self, ty: &Type) {
+        match ty {
+            Type::Unit | Type::Bool | Type::Nat | Type::String | Type::Error | Type::Meta(_) | Type::Hole => {}
+            Type::Var(v) => self.visit_var(*v),
+            Type::Variant(v) => self.visit_variant(v),
+            Type::Product(v) => self.visit_product(v),
+            Type::Alias(s) => self.visit_alias(s),
+            Type::Arrow(ty1, ty2) => self.visit_arrow(ty1, ty2),
+            Type::Universal(ty) => self.visit_universal(ty),
+            Type::Existential(ty) => self.visit_existential(ty),
+            Type::Rec(ty) => self.visit_rec(ty),
+        }
+    }
+}
+
 pub trait MutTermVisitor: Sized {
     fn visit_lit(&mut self, sp: &mut Span, lit: &mut Literal) {}
     fn visit_var(&mut self, sp: &mut Span, var: &mut usize) {}
@@ -86,6 +136,9 @@ pub trait MutTermVisitor: Sized {
     fn visit_case(&mut self, sp: &mut Span, term: &mut Term, arms: &mut Vec<Arm>) {
         self.visit(term);
         for arm in arms {
+            if let Some(guard) = &mut arm.guard {
+                self.visit(guard);
+            }
             self.visit(&mut arm.term);
         }
     }
@@ -116,6 +169,17 @@ pub trait MutTermVisitor: Sized {
         self.visit(term);
     }
 
+    fn visit_raise(&mut self, sp: &mut Span, ty: &mut Type, term: &mut Term) {
+        self.visit(term);
+    }
+
+    /// Visit a shared term, first copying it if it is shared with any other
+    /// term. Visitors that leave closed terms unchanged should override this
+    /// to keep the sharing
+    fn visit_share(&mut self, sp: &mut Span, term: &mut Rc<Term>) {
+        self.visit(Rc::make_mut(term));
+    }
+
     fn visit(&mut self, term: &mut Term) {
         self.walk(term);
     }
@@ -141,6 +205,119 @@ pub trait MutTermVisitor: Sized {
             Kind::Unfold(ty, term) => self.visit_unfold(sp, ty, term),
             Kind::Pack(wit, term, sig) => self.visit_pack(sp, wit, term, sig),
             Kind::Unpack(package, term) => self.visit_unpack(sp, package, term),
+            Kind::Raise(ty, term) => self.visit_raise(sp, ty, term),
+            Kind::Share(term) => self.visit_share(sp, term),
+        }
+    }
+}
+
+/// Read-only counterpart to [`MutTermVisitor`]. Subterms are borrowed for
+/// `'a`, so that visitors may hold on to references into the visited term
+pub trait TermVisitor<'a>: Sized {
+    fn visit_lit(&mut self, sp: Span, lit: &'a Literal) {}
+    fn visit_var(&mut self, sp: Span, var: usize) {}
+
+    fn visit_abs(&mut self, sp: Span, ty: &'a Type, term: &'a Term) {
+        self.visit(term);
+    }
+
+    fn visit_app(&mut self, sp: Span, t1: &'a Term, t2: &'a Term) {
+        self.visit(t1);
+        self.visit(t2);
+    }
+
+    fn visit_fix(&mut self, sp: Span, term: &'a Term) {
+        self.visit(term);
+    }
+
+    fn visit_let(&mut self, sp: Span, pat: &'a Pattern, t1: &'a Term, t2: &'a Term) {
+        self.visit(t1);
+        self.visit(t2);
+    }
+
+    fn visit_tyabs(&mut self, sp: Span, term: &'a Term) {
+        self.visit(term);
+    }
+
+    fn visit_tyapp(&mut self, sp: Span, term: &'a Term, ty: &'a Type) {
+        self.visit(term);
+    }
+
+    fn visit_primitive(&mut self, sp: Span, prim: Primitive) {}
+    fn visit_injection(&mut self, sp: Span, label: &'a str, term: &'a Term, ty: &'a Type) {
+        self.visit(term);
+    }
+
+    fn visit_case(&mut self, sp: Span, term: &'a Term, arms: &'a [Arm]) {
+        self.visit(term);
+        for arm in arms {
+            if let Some(guard) = &arm.guard {
+                self.visit(guard);
+            }
+            self.visit(&arm.term);
+        }
+    }
+
+    fn visit_product(&mut self, sp: Span, product: &'a [Term]) {
+        for t in product {
+            self.visit(t);
+        }
+    }
+
+    fn visit_projection(&mut self, sp: Span, term: &'a Term, index: usize) {
+        self.visit(term);
+    }
+
+    fn visit_fold(&mut self, sp: Span, ty: &'a Type, term: &'a Term) {
+        self.visit(term);
+    }
+    fn visit_unfold(&mut self, sp: Span, ty: &'a Type, term: &'a Term) {
+        self.visit(term);
+    }
+
+    fn visit_pack(&mut self, sp: Span, witness: &'a Type, evidence: &'a Term, signature: &'a Type) {
+        self.visit(evidence);
+    }
+
+    fn visit_unpack(&mut self, sp: Span, package: &'a Term, term: &'a Term) {
+        self.visit(package);
+        self.visit(term);
+    }
+
+    fn visit_raise(&mut self, sp: Span, ty: &'a Type, term: &'a Term) {
+        self.visit(term);
+    }
+
+    fn visit_share(&mut self, sp: Span, term: &'a Term) {
+        self.visit(term);
+    }
+
+    fn visit(&mut self, term: &'a Term) {
+        self.walk(term);
+    }
+
+    fn walk(&mut self, term: &'a Term) {
+        let sp = term.span;
+        match &term.kind {
+            Kind::Lit(l) => self.visit_lit(sp, l),
+            Kind::Var(v) => self.visit_var(sp, *v),
+            Kind::Abs(ty, term) => self.visit_abs(sp, ty, term),
+            Kind::App(t1, t2) => self.visit_app(sp, t1, t2),
+            Kind::Fix(term) => self.visit_fix(sp, term),
+            Kind::Primitive(p) => self.visit_primitive(sp, *p),
+            Kind::Injection(label, tm, ty) => self.visit_injection(sp, label, tm, ty),
+            Kind::Projection(term, idx) => self.visit_projection(sp, term, *idx),
+            Kind::Product(terms) => self.visit_product(sp, terms),
+            Kind::Case(term, arms) => self.visit_case(sp, term, arms),
+            Kind::Let(pat, t1, t2) => self.visit_let(sp, pat, t1, t2),
+            Kind::TyAbs(term) => self.visit_tyabs(sp, term),
+            Kind::TyApp(term, ty) => self.visit_tyapp(sp, term, ty),
+            Kind::Fold(ty, term) => self.visit_fold(sp, ty, term),
+            Kind::Unfold(ty, term) => self.visit_unfold(sp, ty, term),
+            Kind::Pack(wit, term, sig) => self.visit_pack(sp, wit, term, sig),
+            Kind::Unpack(package, term) => self.visit_unpack(sp, package, term),
+            Kind::Raise(ty, term) => self.visit_raise(sp, ty, term),
+            Kind::Share(term) => self.visit_share(sp, term),
         }
     }
 }
@@ -158,9 +335,31 @@ pub trait PatternVisitor: Sized {
         self.visit_pattern(pat);
     }
 
+    /// Every alternative binds the same variables, so by default only the
+    /// first one is visited
+    fn visit_or(&mut self, alts: &[Pattern]) {
+        if let Some(pat) = alts.first() {
+            self.visit_pattern(pat);
+        }
+    }
+
+    /// The variable of an as-pattern is bound after the variables of the
+    /// inner pattern
+    fn visit_as(&mut self, pat: &Pattern, var: &String) {
+        self.visit_pattern(pat);
+        self.visit_variable(var);
+    }
+
+    fn visit_succ(&mut self, pat: &Pattern) {
+        self.visit_pattern(pat);
+    }
+
     fn visit_pattern(&mut self, pattern: &Pattern) {
         match pattern {
             Pattern::Any => {}
+            Pattern::Succ(pat) => self.visit_succ(pat),
+            Pattern::Or(alts) => self.visit_or(alts),
+            Pattern::As(pat, var) => self.visit_as(pat, var),
             Pattern::Constructor(label, pat) => self.visit_constructor(label, pat),
             Pattern::Product(pat) => self.visit_product(pat),
             Pattern::Literal(lit) => self.visit_literal(lit),
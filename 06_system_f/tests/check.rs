@@ -0,0 +1,194 @@
+//! Run the driver with `--check --format=json` and read back its diagnostics
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Json {
+    Null,
+    Num(u64),
+    Str(String),
+    Arr(Vec<Json>),
+    Obj(BTreeMap<String, Json>),
+}
+
+impl Json {
+    fn get(&self, key: &str) -> &Json {
+        match self {
+            Json::Obj(fields) => fields
+                .get(key)
+                .unwrap_or_else(|| panic!("no field {} in {:?}", key, self)),
+            _ => panic!("{:?} is not an object", self),
+        }
+    }
+
+    fn num(&self) -> u64 {
+        match self {
+            Json::Num(n) => *n,
+            _ => panic!("{:?} is not a number", self),
+        }
+    }
+
+    fn str(&self) -> &str {
+        match self {
+            Json::Str(s) => s,
+            _ => panic!("{:?} is not a string", self),
+        }
+    }
+}
+
+/// Just enough of JSON to read what the driver prints: no floats, booleans
+/// or whitespace
+struct Reader<'a>(std::iter::Peekable<std::str::Chars<'a>>);
+
+impl Reader<'_> {
+    fn expect(&mut self, ch: char) {
+        assert_eq!(self.0.next(), Some(ch));
+    }
+
+    fn value(&mut self) -> Json {
+        match self.0.peek().copied() {
+            Some('n') => {
+                "null".chars().for_each(|ch| self.expect(ch));
+                Json::Null
+            }
+            Some('"') => Json::Str(self.string()),
+            Some('[') => {
+                self.expect('[');
+                let mut items = Vec::new();
+                while self.0.peek() != Some(&']') {
+                    items.push(self.value());
+                    if self.0.peek() == Some(&',') {
+                        self.expect(',');
+                    }
+                }
+                self.expect(']');
+                Json::Arr(items)
+            }
+            Some('{') => {
+                self.expect('{');
+                let mut fields = BTreeMap::new();
+                while self.0.peek() != Some(&'}') {
+                    let key = self.string();
+                    self.expect(':');
+                    fields.insert(key, self.value());
+                    if self.0.peek() == Some(&',') {
+                        self.expect(',');
+                    }
+                }
+                self.expect('}');
+                Json::Obj(fields)
+            }
+            _ => {
+                let mut digits = String::new();
+                while let Some(ch) = self.0.peek().copied().filter(char::is_ascii_digit) {
+                    digits.push(ch);
+                    self.0.next();
+                }
+                Json::Num(digits.parse().unwrap())
+            }
+        }
+    }
+
+    fn string(&mut self) -> String {
+        self.expect('"');
+        let mut s = String::new();
+        loop {
+            match self.0.next().unwrap() {
+                '"' => return s,
+                '\\' => match self.0.next().unwrap() {
+                    'n' => s.push('\n'),
+                    't' => s.push('\t'),
+                    'r' => s.push('\r'),
+                    'u' => {
+                        let hex = (0..4).map(|_| self.0.next().unwrap()).collect::<String>();
+                        s.push(std::char::from_u32(u32::from_str_radix(&hex, 16).unwrap()).unwrap());
+                    }
+                    ch => s.push(ch),
+                },
+                ch => s.push(ch),
+            }
+        }
+    }
+}
+
+fn parse(line: &str) -> Json {
+    let mut r = Reader(line.chars().peekable());
+    let json = r.value();
+    assert_eq!(r.0.next(), None, "trailing input in {}", line);
+    json
+}
+
+/// Check a file holding `source`, returning whether the driver succeeded
+/// and the diagnostics it printed
+fn check(name: &str, source: &str) -> (bool, Vec<Json>) {
+    let dir = std::env::temp_dir().join(format!("system_f_check_{}_{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path: PathBuf = dir.join("main.sf");
+    fs::write(&path, source).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_system_f"))
+        .args(["--check", "--format=json"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let diags = stdout.lines().map(parse).collect();
+    (output.status.success(), diags)
+}
+
+#[test]
+fn bad_file() {
+    let source = "\
+let one = succ 0;
+let bad = succ true;
+bad;
+case 2 of | 0 => true | 1 => false;
+let f = \\x: Nat. x x;
+1 +;
+";
+    let (ok, diags) = check("bad", source);
+    assert!(!ok);
+    let codes = diags.iter().map(|d| d.get("code").str()).collect::<Vec<_>>();
+    assert_eq!(codes, ["E0101", "E0003", "E0113", "E0104", "E0001"]);
+    for diag in &diags {
+        assert!(diag.get("file").str().ends_with("main.sf"));
+        assert_eq!(diag.get("severity").str(), "error");
+    }
+
+    // Positions count from 1, and end just after the span
+    let position = |d: &Json| {
+        ["start_line", "start_col", "end_line", "end_col"]
+            .iter()
+            .map(|key| d.get(key).num())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(position(&diags[0]), [2, 11, 2, 20]);
+    assert_eq!(diags[0].get("message").str(), "Type mismatch in application");
+    let notes = match diags[0].get("notes") {
+        Json::Arr(notes) => notes,
+        notes => panic!("expected an array, not {:?}", notes),
+    };
+    assert_eq!(position(&notes[1]), [2, 16, 2, 20]);
+    assert_eq!(notes[1].get("message").str(), "Value has a type of `Bool`");
+    assert_eq!(position(&diags[1]), [3, 1, 3, 4]);
+    assert_eq!(position(&diags[2]), [4, 6, 4, 7]);
+    assert_eq!(position(&diags[3]), [5, 18, 5, 21]);
+    assert_eq!(diags[4].get("start_line").num(), 6);
+}
+
+#[test]
+fn good_file() {
+    // Warnings are reported, but do not fail the check
+    let (ok, diags) = check("good", "let two = 2;\ncase two of | _ => 0 | 1 => 1;\n");
+    assert!(ok);
+    assert_eq!(diags.len(), 1);
+    assert_eq!(diags[0].get("severity").str(), "warning");
+    assert_eq!(diags[0].get("code").str(), "E0114");
+    assert_eq!(diags[0].get("start_line").num(), 2);
+    match diags[0].get("notes") {
+        Json::Arr(notes) => assert_eq!(notes[0].get("message").str(), "arm 1 matches these values first"),
+        notes => panic!("expected an array, not {:?}", notes),
+    }
+}
@@ -0,0 +1,63 @@
+//! Drive the REPL over stdin with a scripted session
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Run the REPL on `script`, returning what it printed to stdout
+fn session(script: &str) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_system_f"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(script.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn transcript() {
+    let script = "\
+succ 1
+let two = succ 1
+:type \\x: Nat. add x two
+:alias Pair = (Nat, Bool)
+(\\p: Pair. p.0) (two, true)
+let id = \\X \\x: X. x
+(\\x: Nat.
+  succ x) (id [Nat] two)
+:steps (\\x: Nat. succ x) two
+";
+    let expected = "\
+repl: 2 : Nat
+repl: two = 2 : Nat
+repl: Nat -> Nat
+repl: repl: 2 : Nat
+repl: id = \\X \\x: X. x : forall X. X -> X
+repl:   ... 3 : Nat
+repl:    0: (\\x: Nat. succ x) 2
+   1: succ 2
+   2: 3
+repl: 
+";
+    assert_eq!(session(script), expected);
+}
+
+#[test]
+fn errors() {
+    let out = session("succ true\n:type nope\n2\n");
+    let mut lines = out.lines();
+    // The diagnostic quotes the line just entered
+    assert_eq!(lines.next(), Some("repl: | 1 succ true"));
+    assert!(out.contains("Type mismatch in application"), "{}", out);
+    assert!(out.contains("unbound variable nope"), "{}", out);
+    // The session goes on after an error
+    assert!(out.contains("repl: 2 : Nat"), "{}", out);
+}
+
+#[test]
+fn ast() {
+    let out = session(":ast \\x: Nat. x\n");
+    assert_eq!(out, "repl: Abs(\n    Nat,\n    Var(\n        0,\n    ),\n)\nrepl: \n");
+}
@@ -0,0 +1,310 @@
+//! Expansion of type abbreviations
+//!
+//! `type ('a, 'b) t = ty` declares `t` as an abbreviation, which expands to
+//! `ty` with its parameters replaced by the arguments `t` is applied to:
+//! `(int, bool) t` is `[a ↦ int, b ↦ bool] ty`. An abbreviation must be
+//! applied to at least as many arguments as it has parameters, each of the
+//! kind of its parameter. Partial application is an error, as there is no
+//! type level function to expand it to without renaming its parameters, but
+//! extra arguments are applied to the expansion, so `int k` is `int list`
+//! if `type k = list`.
+//!
+//! Unlike datatypes, abbreviations may not refer to themselves, or else
+//! expanding them would never finish.
+//!
+//! Types can be expanded eagerly, normalizing every abbreviation in them
+//! with [`Abbreviations::normalize`], or lazily, only expanding the
+//! abbreviation at the head of a type with [`Abbreviations::expand_head`]
+//! when it does not otherwise match the type it is compared to
+use crate::diagnostics::Diagnostic;
+use crate::kindcheck::{KindContext, KindError};
+use crate::symbol::Symbol;
+use crate::syntax::ast::{Kind, Type, TypeKind};
+use crate::syntax::visit::{referenced, SubstNamedVar, TypeMutVisitor};
+use std::collections::HashMap;
+use std::fmt;
+use util::span::Span;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AbbrevError {
+    /// The abbreviation has the first number of parameters, but is applied
+    /// to the second number of arguments at the span
+    Arity(Symbol, usize, usize, Span),
+    /// An argument does not have the kind of its parameter
+    Kind(KindError),
+    /// Declaring the abbreviation at the span would make it refer to
+    /// itself, through each of the abbreviations in order
+    Cycle(Vec<Symbol>, Span),
+}
+
+/// The parameters of an abbreviation, with their kinds, and the type it
+/// abbreviates
+#[derive(Clone, Debug, PartialEq)]
+pub struct Abbreviation {
+    pub params: Vec<(Symbol, Kind)>,
+    pub body: Type,
+}
+
+/// The abbreviations in scope, by name
+#[derive(Default, Debug)]
+pub struct Abbreviations {
+    defs: HashMap<Symbol, Abbreviation>,
+}
+
+impl Abbreviations {
+    /// Declare the abbreviation `type tyvars name = body`, unless it would
+    /// refer to itself
+    pub fn define(&mut self, tyvars: &[Type], name: Symbol, body: &Type, span: Span) -> Result<(), AbbrevError> {
+        let mut path = vec![name];
+        if self.reaches(body, name, &mut path) {
+            return Err(AbbrevError::Cycle(path, span));
+        }
+        let params = tyvars.iter().map(|t| (t.kind.as_tyvar(), Kind::Star)).collect();
+        self.defs.insert(
+            name,
+            Abbreviation {
+                params,
+                body: body.clone(),
+            },
+        );
+        Ok(())
+    }
+
+    pub fn get(&self, name: Symbol) -> Option<&Abbreviation> {
+        self.defs.get(&name)
+    }
+
+    /// Whether `ty` refers to `target`, either directly or by expanding
+    /// abbreviations, extending `path` with the abbreviations expanded
+    fn reaches(&self, ty: &Type, target: Symbol, path: &mut Vec<Symbol>) -> bool {
+        for s in referenced(ty) {
+            if s == target {
+                path.push(s);
+                return true;
+            }
+            if let Some(def) = self.defs.get(&s) {
+                if path.contains(&s) {
+                    continue;
+                }
+                path.push(s);
+                if self.reaches(&def.body, target, path) {
+                    return true;
+                }
+                path.pop();
+            }
+        }
+        false
+    }
+
+    /// If `ty` is an abbreviation applied to its arguments, the expansion
+    /// of that abbreviation. Abbreviations within the arguments, or within
+    /// the expansion itself, are left alone
+    pub fn expand_head(&self, ty: &Type, kinds: &mut KindContext) -> Result<Option<Type>, AbbrevError> {
+        let mut args = Vec::new();
+        let mut head = ty;
+        while let TypeKind::Application(ty1, ty2) = &head.kind {
+            args.push(ty2.as_ref());
+            head = ty1;
+        }
+        args.reverse();
+        let (name, def) = match &head.kind {
+            TypeKind::Defined(s) => match self.defs.get(s) {
+                Some(def) => (*s, def),
+                None => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+        if args.len() < def.params.len() {
+            return Err(AbbrevError::Arity(name, def.params.len(), args.len(), ty.span));
+        }
+
+        for ((_, kind), arg) in def.params.iter().zip(&args) {
+            match kinds.kind_of(arg) {
+                Ok(k) if k != *kind => {
+                    return Err(AbbrevError::Kind(KindError::Mismatch(kind.clone(), k, arg.span)));
+                }
+                Err(e @ KindError::Mismatch(..)) | Err(e @ KindError::NotArrow(..)) => {
+                    return Err(AbbrevError::Kind(e))
+                }
+                // Unbound and undefined types are reported elsewhere
+                _ => {}
+            }
+        }
+
+        // Substitute for all of the parameters at once, by renaming them
+        // apart from the arguments first
+        let mut body = def.body.clone();
+        for (i, (param, _)) in def.params.iter().enumerate() {
+            let var = Type::new(TypeKind::Variable(Symbol::intern(&format!("${}", i))), body.span);
+            SubstNamedVar::new(*param, var).visit_ty(&mut body);
+        }
+        for (i, arg) in args.iter().enumerate().take(def.params.len()) {
+            SubstNamedVar::new(format!("${}", i), (*arg).clone()).visit_ty(&mut body);
+        }
+        let expanded = args[def.params.len()..].iter().fold(body, |ty, arg| {
+            let span = ty.span;
+            Type::new(TypeKind::Application(Box::new(ty), Box::new((*arg).clone())), span)
+        });
+        Ok(Some(Type::with_id(expanded.kind, ty.span, ty.id)))
+    }
+
+    /// Expand every abbreviation in `ty`, reporting the first that is
+    /// misapplied
+    pub fn normalize(&self, ty: &mut Type, kinds: &mut KindContext) -> Result<(), AbbrevError> {
+        let mut n = Normalize {
+            abbrevs: self,
+            kinds,
+            error: None,
+        };
+        n.visit_ty(ty);
+        match n.error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Expands abbreviations from the outside in, see
+/// [`Abbreviations::normalize`]
+struct Normalize<'a> {
+    abbrevs: &'a Abbreviations,
+    kinds: &'a mut KindContext,
+    error: Option<AbbrevError>,
+}
+
+impl<'a> TypeMutVisitor for Normalize<'a> {
+    fn visit_ty(&mut self, ty: &mut Type) {
+        if self.error.is_some() {
+            return;
+        }
+        match self.abbrevs.expand_head(ty, self.kinds) {
+            Ok(Some(expanded)) => {
+                *ty = expanded;
+                self.visit_ty(ty);
+            }
+            Ok(None) => self.walk_ty(ty),
+            Err(e) => self.error = Some(e),
+        }
+    }
+}
+
+impl AbbrevError {
+    pub fn span(&self) -> Span {
+        match self {
+            AbbrevError::Arity(_, _, _, sp) | AbbrevError::Cycle(_, sp) => *sp,
+            AbbrevError::Kind(e) => e.span(),
+        }
+    }
+}
+
+impl fmt::Display for AbbrevError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AbbrevError::Arity(s, expected, found, _) => write!(
+                f,
+                "type {} expects {} arguments, but is applied to {}",
+                s, expected, found
+            ),
+            AbbrevError::Kind(e) => write!(f, "{}", e),
+            AbbrevError::Cycle(path, _) => write!(
+                f,
+                "type abbreviation {} refers to itself: {}",
+                path[0],
+                path.iter().map(|s| s.as_str()).collect::<Vec<_>>().join(" -> ")
+            ),
+        }
+    }
+}
+
+impl From<AbbrevError> for Diagnostic {
+    fn from(e: AbbrevError) -> Diagnostic {
+        match e {
+            AbbrevError::Kind(e) => e.into(),
+            e => Diagnostic::error(e.span(), e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::syntax::ast::DeclKind;
+    use crate::syntax::parser::Parser;
+
+    fn ty(input: &str) -> Type {
+        Parser::new(input).parse_type().unwrap()
+    }
+
+    /// Declare each `type` of a program in order, stopping at the first
+    /// error
+    fn declare(input: &str) -> Result<Abbreviations, AbbrevError> {
+        let mut abbrevs = Abbreviations::default();
+        for d in Parser::new(input).parse_program().unwrap().decls {
+            if let DeclKind::Type(tyvars, name, body) = &d.kind {
+                abbrevs.define(tyvars, *name, body, d.span)?;
+            }
+        }
+        Ok(abbrevs)
+    }
+
+    fn normalize(abbrevs: &Abbreviations, input: &str) -> Result<String, AbbrevError> {
+        let mut t = ty(input);
+        abbrevs.normalize(&mut t, &mut KindContext::default())?;
+        Ok(t.to_string())
+    }
+
+    #[test]
+    fn nested() {
+        let abbrevs = declare("type 'a pair = 'a * 'a; type 'b quad = 'b pair pair; type ('a, 'b) fst = 'a").unwrap();
+        assert_eq!(normalize(&abbrevs, "int quad").unwrap(), "(int * int) * (int * int)");
+        assert_eq!(normalize(&abbrevs, "bool -> unit pair").unwrap(), "bool -> unit * unit");
+
+        // Arguments are substituted all at once, so the parameters of an
+        // abbreviation cannot capture each other
+        assert_eq!(normalize(&abbrevs, "('b, 'a) fst pair").unwrap(), "'b * 'b");
+
+        // Only the head is expanded lazily
+        let mut kinds = KindContext::default();
+        let head = abbrevs.expand_head(&ty("int quad"), &mut kinds).unwrap().unwrap();
+        assert_eq!(head.to_string(), "int pair pair");
+        assert_eq!(abbrevs.expand_head(&ty("int list"), &mut kinds), Ok(None));
+    }
+
+    #[test]
+    fn application() {
+        let abbrevs = declare("type ('a, 'b) either = 'a * 'b; type k = list").unwrap();
+
+        // Partial application is an error, but extra arguments are applied
+        // to the expansion
+        match normalize(&abbrevs, "unit -> int either") {
+            Err(err @ AbbrevError::Arity(..)) => assert_eq!(
+                Diagnostic::from(err).primary.info,
+                "type either expects 2 arguments, but is applied to 1"
+            ),
+            e => panic!("expected an arity error, not {:?}", e),
+        }
+        assert_eq!(normalize(&abbrevs, "int k").unwrap(), "int list");
+
+        assert!(matches!(
+            normalize(&abbrevs, "(int, fn ('a :: *) => 'a) either"),
+            Err(AbbrevError::Kind(KindError::Mismatch(Kind::Star, Kind::Arrow(..), _)))
+        ));
+    }
+
+    #[test]
+    fn cycle() {
+        match declare("type 'a t = 'a * 'a t") {
+            Err(AbbrevError::Cycle(path, _)) => assert_eq!(path, ["t", "t"]),
+            e => panic!("expected a cycle, not {:?}", e.map(|_| ())),
+        }
+
+        let mut abbrevs = declare("type a = int; type b = a * c").unwrap();
+        let c = ty("b -> unit");
+        let err = abbrevs.define(&[], "c".into(), &c, Span::default()).unwrap_err();
+        assert_eq!(
+            Diagnostic::from(err).primary.info,
+            "type abbreviation c refers to itself: c -> b -> c"
+        );
+        assert!(abbrevs.get("c".into()).is_none());
+    }
+}
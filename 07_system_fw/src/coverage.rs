@@ -0,0 +1,458 @@
+//! Exhaustiveness and redundancy checking of pattern matches
+//!
+//! This is the usefulness algorithm of Maranget's "Warnings for pattern
+//! matching": a vector of patterns is useful with respect to a matrix of
+//! rows if some value is matched by the vector, but by none of the rows.
+//! An arm is unreachable if its pattern is not useful with respect to the
+//! arms above it, and a match is exhaustive if a wildcard is not useful
+//! with respect to all of its arms. Instead of only asking whether the
+//! wildcard is useful, we collect the values it matches that no arm does,
+//! to report them as examples of what is missing.
+//!
+//! Patterns are checked after name resolution, so that each constructor is
+//! known along with the other constructors of its datatype. Arms with a
+//! guard may not match at all, so they are checked for reachability, but
+//! don't count towards covering any values
+use crate::diagnostics::Diagnostic;
+use crate::hir::HirId;
+use crate::symbol::Symbol;
+use crate::syntax::ast::{PatKind, Pattern};
+use std::collections::HashMap;
+use util::span::Span;
+
+/// The most missing patterns to list in a warning
+const MAX_MISSING: usize = 3;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Ctor {
+    /// The constructor with the tag of the datatype declared at `HirId`
+    Variant(HirId, usize),
+    /// The only constructor of tuples of this length
+    Tuple(usize),
+    /// An integer constant, one of infinitely many
+    Literal(usize),
+}
+
+/// A pattern reduced to what matters for coverage. Variables, records and
+/// unit patterns all match any value of their type, so become wildcards
+#[derive(Clone, Debug, PartialEq)]
+pub enum Pat {
+    Wild,
+    Con(Ctor, Vec<Pat>),
+    Or(Vec<Pat>),
+}
+
+/// One arm of a match, with a pattern for each value being matched
+#[derive(Clone, Debug, PartialEq)]
+pub struct Arm {
+    pub pats: Vec<Pat>,
+    pub guarded: bool,
+    pub span: Span,
+}
+
+/// The result of checking a match: the indices of unreachable arms, and
+/// examples of values that no arm matches
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Report {
+    pub unreachable: Vec<usize>,
+    pub missing: Vec<String>,
+}
+
+/// The constructors of each datatype, in tag order, along with whether
+/// they take an argument
+#[derive(Default, Debug)]
+pub struct Signatures {
+    datatypes: HashMap<HirId, Vec<(String, bool)>>,
+}
+
+type Row = Vec<Pat>;
+
+/// `row` as a row for each alternative of the or-pattern at its head
+fn expand(row: &[Pat]) -> Vec<Row> {
+    match row.first() {
+        Some(Pat::Or(alts)) => alts
+            .iter()
+            .flat_map(|alt| {
+                let row = std::iter::once(alt.clone())
+                    .chain(row[1..].iter().cloned())
+                    .collect::<Row>();
+                expand(&row)
+            })
+            .collect(),
+        _ => vec![row.to_vec()],
+    }
+}
+
+/// The rows starting with a wildcard, without it
+fn default(rows: &[Row]) -> Vec<Row> {
+    rows.iter()
+        .flat_map(|row| expand(row))
+        .filter(|row| row[0] == Pat::Wild)
+        .map(|row| row[1..].to_vec())
+        .collect()
+}
+
+/// The distinct constructors at the head of the rows
+fn head_ctors(rows: &[Row]) -> Vec<Ctor> {
+    let mut ctors = Vec::new();
+    for row in rows.iter().flat_map(|row| expand(row)) {
+        if let Pat::Con(c, _) = &row[0] {
+            if !ctors.contains(c) {
+                ctors.push(*c);
+            }
+        }
+    }
+    ctors
+}
+
+fn wilds(n: usize) -> impl Iterator<Item = Pat> {
+    std::iter::repeat_n(Pat::Wild, n)
+}
+
+impl Signatures {
+    /// Record the constructors of the datatype declared at `id`
+    pub fn declare(&mut self, id: HirId, constructors: Vec<(String, bool)>) {
+        self.datatypes.insert(id, constructors);
+    }
+
+    fn arity(&self, c: &Ctor) -> usize {
+        match c {
+            Ctor::Variant(id, tag) => self.datatypes[id][*tag].1 as usize,
+            Ctor::Tuple(n) => *n,
+            Ctor::Literal(_) => 0,
+        }
+    }
+
+    /// Every constructor of the type `c` belongs to, if there are finitely
+    /// many
+    fn siblings(&self, c: &Ctor) -> Option<Vec<Ctor>> {
+        match c {
+            Ctor::Variant(id, _) => Some(
+                (0..self.datatypes[id].len())
+                    .map(|tag| Ctor::Variant(*id, tag))
+                    .collect(),
+            ),
+            Ctor::Tuple(n) => Some(vec![Ctor::Tuple(*n)]),
+            Ctor::Literal(_) => None,
+        }
+    }
+
+    /// Convert a surface pattern, using `resolve` to find the datatype and
+    /// tag of each constructor name. Returns `None` if a constructor cannot
+    /// be resolved, which is reported elsewhere
+    pub fn lower(&self, pat: &Pattern, resolve: &dyn Fn(Symbol) -> Option<Ctor>) -> Option<Pat> {
+        match &pat.kind {
+            PatKind::Any | PatKind::Unit | PatKind::Variable(_) | PatKind::Record(_) => Some(Pat::Wild),
+            PatKind::Ascribe(pat, _) | PatKind::As(_, pat) => self.lower(pat, resolve),
+            PatKind::Literal(n) => Some(Pat::Con(Ctor::Literal(*n), Vec::new())),
+            PatKind::Constructor(s) => {
+                let c = resolve(*s)?;
+                Some(Pat::Con(c, wilds(self.arity(&c)).collect()))
+            }
+            PatKind::Application(con, arg) => {
+                let c = match &con.kind {
+                    PatKind::Constructor(s) => resolve(*s)?,
+                    _ => return None,
+                };
+                let args = match self.arity(&c) {
+                    0 => Vec::new(),
+                    _ => vec![self.lower(arg, resolve)?],
+                };
+                Some(Pat::Con(c, args))
+            }
+            PatKind::Product(pats) => pats
+                .iter()
+                .map(|p| self.lower(p, resolve))
+                .collect::<Option<Vec<_>>>()
+                .map(|pats| Pat::Con(Ctor::Tuple(pats.len()), pats)),
+            PatKind::Or(pats) => pats
+                .iter()
+                .map(|p| self.lower(p, resolve))
+                .collect::<Option<_>>()
+                .map(Pat::Or),
+        }
+    }
+
+    /// The rows that match constructor `c` at their head, with the head
+    /// replaced by the patterns for its arguments
+    fn specialize(&self, rows: &[Row], c: &Ctor) -> Vec<Row> {
+        let n = self.arity(c);
+        let mut out = Vec::new();
+        for row in rows.iter().flat_map(|row| expand(row)) {
+            match &row[0] {
+                Pat::Con(c2, args) if c2 == c => {
+                    out.push(args.iter().cloned().chain(row[1..].iter().cloned()).collect())
+                }
+                Pat::Wild => out.push(wilds(n).chain(row[1..].iter().cloned()).collect()),
+                _ => {}
+            }
+        }
+        out
+    }
+
+    /// Is there a value matched by `q`, but by none of the rows?
+    fn useful(&self, rows: &[Row], q: &[Pat]) -> bool {
+        let rest = || q[1..].iter().cloned();
+        match q.first() {
+            None => rows.is_empty(),
+            Some(Pat::Or(alts)) => alts.iter().any(|alt| {
+                let q = std::iter::once(alt.clone()).chain(rest()).collect::<Row>();
+                self.useful(rows, &q)
+            }),
+            Some(Pat::Con(c, args)) => {
+                let q = args.iter().cloned().chain(rest()).collect::<Row>();
+                self.useful(&self.specialize(rows, c), &q)
+            }
+            Some(Pat::Wild) => {
+                let heads = head_ctors(rows);
+                match heads.first().and_then(|c| self.siblings(c)) {
+                    Some(all) if all.iter().all(|c| heads.contains(c)) => all.iter().any(|c| {
+                        let q = wilds(self.arity(c)).chain(rest()).collect::<Row>();
+                        self.useful(&self.specialize(rows, c), &q)
+                    }),
+                    _ => self.useful(&default(rows), &q[1..]),
+                }
+            }
+        }
+    }
+
+    /// Vectors of `n` patterns, matching values that none of the rows do
+    fn missing(&self, rows: &[Row], n: usize) -> Vec<Row> {
+        if n == 0 {
+            return match rows.is_empty() {
+                true => vec![Vec::new()],
+                false => Vec::new(),
+            };
+        }
+        let heads = head_ctors(rows);
+        let all = heads.first().and_then(|c| self.siblings(c));
+        match all {
+            Some(all) if all.iter().all(|c| heads.contains(c)) => {
+                // Every constructor appears, so look for what is missing
+                // underneath each of them
+                let mut out = Vec::new();
+                for c in all {
+                    let arity = self.arity(&c);
+                    for mut w in self.missing(&self.specialize(rows, &c), arity + n - 1) {
+                        let rest = w.split_off(arity);
+                        out.push(std::iter::once(Pat::Con(c, w)).chain(rest).collect());
+                    }
+                }
+                out
+            }
+            _ => {
+                let ws = self.missing(&default(rows), n - 1);
+                if ws.is_empty() {
+                    return ws;
+                }
+                // Any constructor that doesn't appear is missing, or if
+                // there are infinitely many, some value other than those
+                // that do
+                let firsts = match all {
+                    Some(all) if !heads.is_empty() => all
+                        .into_iter()
+                        .filter(|c| !heads.contains(c))
+                        .map(|c| Pat::Con(c, wilds(self.arity(&c)).collect()))
+                        .collect(),
+                    _ => vec![Pat::Wild],
+                };
+                firsts
+                    .iter()
+                    .flat_map(|p| {
+                        ws.iter()
+                            .map(move |w| std::iter::once(p.clone()).chain(w.iter().cloned()).collect())
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Check the arms of a match, in order
+    pub fn check(&self, arms: &[Arm]) -> Report {
+        let mut report = Report::default();
+        let mut rows: Vec<Row> = Vec::with_capacity(arms.len());
+        for (i, arm) in arms.iter().enumerate() {
+            if !self.useful(&rows, &arm.pats) {
+                report.unreachable.push(i);
+            }
+            if !arm.guarded {
+                rows.push(arm.pats.clone());
+            }
+        }
+        // A function's arms match several arguments, shown side by side
+        let n = arms.first().map(|arm| arm.pats.len()).unwrap_or(1);
+        report.missing = self
+            .missing(&rows, n)
+            .iter()
+            .map(|w| match w.as_slice() {
+                [p] => self.show(p),
+                _ => w.iter().map(|p| self.show_atom(p)).collect::<Vec<_>>().join(" "),
+            })
+            .collect();
+        report
+    }
+
+    fn show(&self, pat: &Pat) -> String {
+        match pat {
+            Pat::Wild => "_".into(),
+            Pat::Con(Ctor::Literal(n), _) => n.to_string(),
+            Pat::Con(Ctor::Tuple(_), pats) => {
+                format!("({})", pats.iter().map(|p| self.show(p)).collect::<Vec<_>>().join(", "))
+            }
+            Pat::Con(Ctor::Variant(id, tag), args) => {
+                let name = &self.datatypes[id][*tag].0;
+                match args.first() {
+                    Some(arg) => format!("{} {}", name, self.show_atom(arg)),
+                    None => name.clone(),
+                }
+            }
+            Pat::Or(alts) => alts.iter().map(|p| self.show_atom(p)).collect::<Vec<_>>().join(" | "),
+        }
+    }
+
+    fn show_atom(&self, pat: &Pat) -> String {
+        match pat {
+            Pat::Or(_) => format!("({})", self.show(pat)),
+            Pat::Con(Ctor::Variant(..), args) if !args.is_empty() => format!("({})", self.show(pat)),
+            _ => self.show(pat),
+        }
+    }
+}
+
+impl Report {
+    /// A warning for each unreachable arm, and one for the whole match at
+    /// `span` if it is not exhaustive
+    pub fn diagnostics(&self, arms: &[Arm], span: Span) -> Vec<Diagnostic> {
+        let mut diags = self
+            .unreachable
+            .iter()
+            .map(|&i| Diagnostic::warn(arms[i].span, "unreachable pattern"))
+            .collect::<Vec<_>>();
+        if !self.missing.is_empty() {
+            let mut diag = Diagnostic::warn(span, "match is not exhaustive");
+            for pat in self.missing.iter().take(MAX_MISSING) {
+                diag = diag.info(format!("pattern {} is not matched", pat));
+            }
+            if self.missing.len() > MAX_MISSING {
+                diag = diag.info(format!("and {} more", self.missing.len() - MAX_MISSING));
+            }
+            diags.push(diag);
+        }
+        diags
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::syntax::ast::{DeclKind, ExprKind};
+    use crate::syntax::parser::Parser;
+
+    /// Declare the datatypes of `decls`, and check the case expression
+    /// `expr` against them
+    fn check(decls: &str, expr: &str) -> Report {
+        let mut sigs = Signatures::default();
+        let mut names = HashMap::new();
+        for (i, decl) in Parser::new(decls).parse_program().unwrap().decls.iter().enumerate() {
+            match &decl.kind {
+                DeclKind::Datatype(_, _, sum) => {
+                    let id = HirId(i as u32);
+                    let variants = sum.kind.variants();
+                    for (tag, v) in variants.iter().enumerate() {
+                        names.insert(v.label, Ctor::Variant(id, tag));
+                    }
+                    sigs.declare(
+                        id,
+                        variants.iter().map(|v| (v.label.to_string(), v.ty.is_some())).collect(),
+                    );
+                }
+                d => panic!("expected a datatype, not {:?}", d),
+            }
+        }
+
+        let arms = match Parser::new(expr).parse_expr().unwrap().kind {
+            ExprKind::Case(_, arms) => arms,
+            e => panic!("expected a case expression, not {:?}", e),
+        };
+        let resolve = |s: Symbol| names.get(&s).copied();
+        let arms = arms
+            .iter()
+            .map(|arm| Arm {
+                pats: vec![sigs.lower(&arm.pat, &resolve).unwrap()],
+                guarded: arm.guard.is_some(),
+                span: arm.span,
+            })
+            .collect::<Vec<_>>();
+        sigs.check(&arms)
+    }
+
+    const BOOL: &str = "datatype b = T | F";
+    const OPTION: &str = "datatype 'a option = None | Some of 'a";
+
+    #[test]
+    fn pairs() {
+        let r = check(BOOL, "case x of | (T, T) => 1 | (F, _) => 2 | (_, F) => 3 end");
+        assert_eq!(r, Report::default());
+
+        let r = check(BOOL, "case x of | (T, T) => 1 | (F, F) => 2 end");
+        assert_eq!(r.missing, ["(T, F)", "(F, T)"]);
+        assert!(r.unreachable.is_empty());
+
+        let r = check(BOOL, "case x of | (_, T) => 1 | (T, _) => 2 | (T, T) => 3 | _ => 4 end");
+        assert_eq!(r.unreachable, [2]);
+        assert_eq!(r.missing, Vec::<String>::new());
+
+        // Or-patterns cover each of their alternatives
+        let r = check(BOOL, "case x of | (T, (T | F)) => 1 | ((T | F), F) => 2 end");
+        assert_eq!(r.missing, ["(F, T)"]);
+        let r = check(BOOL, "case x of | ((T | F), _) => 1 | (F, T) => 2 end");
+        assert_eq!(r.unreachable, [1]);
+    }
+
+    #[test]
+    fn nested_options() {
+        let r = check(OPTION, "case x of | None => 1 | Some None => 2 end");
+        assert_eq!(r.missing, ["Some (Some _)"]);
+
+        let r = check(
+            OPTION,
+            "case x of | Some (Some (a, b)) => 1 | Some None => 2 | None => 3 | Some _ => 4 end",
+        );
+        assert_eq!(r.unreachable, [3]);
+        assert!(r.missing.is_empty());
+
+        let r = check(
+            &format!("{}; {}", OPTION, BOOL),
+            "case x of | (Some T, _) => 1 | (None, T) => 2 end",
+        );
+        assert_eq!(r.missing, ["(None, F)", "(Some F, _)"]);
+
+        // Integers are never covered without a wildcard
+        let r = check(OPTION, "case x of | Some 0 => 1 | Some 1 => 2 | None => 3 end");
+        assert_eq!(r.missing, ["Some _"]);
+    }
+
+    #[test]
+    fn guards() {
+        // A guard may fail, so the arm covers nothing
+        let r = check(BOOL, "case x of | T if c => 1 | F => 2 end");
+        assert_eq!(r.missing, ["T"]);
+        let r = check(BOOL, "case x of | T if c => 1 | F => 2 | T => 3 end");
+        assert_eq!(r, Report::default());
+
+        // But it can still never be reached
+        let r = check(BOOL, "case x of | _ => 1 | T if c => 2 end");
+        assert_eq!(r.unreachable, [1]);
+
+        let arms = [Arm {
+            pats: vec![Pat::Wild],
+            guarded: true,
+            span: Span::default(),
+        }];
+        let r = Signatures::default().check(&arms);
+        let diags = r.diagnostics(&arms, Span::default());
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].primary.info, "match is not exhaustive");
+        assert_eq!(diags[0].info, ["pattern _ is not matched"]);
+    }
+}
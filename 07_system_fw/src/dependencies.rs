@@ -0,0 +1,446 @@
+//! Ordering top level declarations by their dependencies
+//!
+//! A declaration may refer to names declared later in a program, so before
+//! anything else the declarations are put in an order where everything they
+//! refer to comes first. Each use of a name is resolved to the closest
+//! declaration of it that comes before the use, or if there is none, to the
+//! first one after it. Declarations that refer to each other, directly or
+//! not, form a strongly connected component of the graph of references, and
+//! are grouped together with `and`, in the order they were written. The
+//! groups are then sorted topologically, keeping them in the order they
+//! were written wherever their dependencies allow, so a program that is
+//! already in order is left as it is.
+//!
+//! Only functions and datatypes may be recursive. A group that also holds a
+//! `val`, or any other declaration, is an error, as is a group of type
+//! abbreviations that refer to each other without going through a datatype,
+//! since expanding them would never finish
+use crate::abbrev::AbbrevError;
+use crate::diagnostics::Diagnostic;
+use crate::scopecheck::{free_names, Namespace};
+use crate::symbol::Symbol;
+use crate::syntax::ast::{Decl, DeclKind, TypeKind};
+use crate::syntax::visit::{PatternBinders, PatternVisitor};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use util::span::Span;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DependencyError {
+    /// Type abbreviations that refer to themselves
+    Abbrev(AbbrevError),
+    /// The declaration at the span refers to itself through each of the
+    /// named declarations in order, but is not a function or datatype
+    Recursive(Vec<Symbol>, Span),
+}
+
+/// The names a declaration makes, in each namespace
+fn declared(d: &Decl) -> Vec<(Namespace, Symbol)> {
+    let mut names = Vec::new();
+    match &d.kind {
+        DeclKind::Type(_, name, _) => names.push((Namespace::Type, *name)),
+        DeclKind::Datatype(_, name, ty) => {
+            names.push((Namespace::Type, *name));
+            if let TypeKind::Sum(variants) = &ty.kind {
+                names.extend(variants.iter().map(|v| (Namespace::Constructor, v.label)));
+            }
+        }
+        DeclKind::Value(_, pat, _) => {
+            let mut binders = PatternBinders::default();
+            binders.visit_pat(pat);
+            names.extend(binders.names.into_iter().map(|(s, _)| (Namespace::Value, s)));
+        }
+        DeclKind::Function(_, name, _) => names.push((Namespace::Value, *name)),
+        DeclKind::And(d1, d2) => {
+            names.extend(declared(d1));
+            names.extend(declared(d2));
+        }
+        DeclKind::Expr(_) => {}
+        DeclKind::Signature(name, _) => names.push((Namespace::Signature, *name)),
+        DeclKind::Structure(name, _, _) => names.push((Namespace::Structure, *name)),
+    }
+    names
+}
+
+/// Whether `d` may refer to itself. An abbreviation may, as long as it
+/// does so through a datatype, which is checked separately
+fn recursive(d: &Decl) -> bool {
+    match &d.kind {
+        DeclKind::Function(..) | DeclKind::Datatype(..) | DeclKind::Type(..) => true,
+        DeclKind::And(d1, d2) => recursive(d1) && recursive(d2),
+        _ => false,
+    }
+}
+
+/// The graph of references between declarations, by index
+struct Graph {
+    edges: Vec<BTreeSet<usize>>,
+}
+
+impl Graph {
+    fn new(decls: &[Decl]) -> Graph {
+        let mut declarations: HashMap<(Namespace, Symbol), Vec<usize>> = HashMap::new();
+        for (i, d) in decls.iter().enumerate() {
+            for name in declared(d) {
+                declarations.entry(name).or_default().push(i);
+            }
+        }
+
+        let mut edges = vec![BTreeSet::new(); decls.len()];
+        for (i, d) in decls.iter().enumerate() {
+            for (ns, name) in free_names(d) {
+                let found = match declarations.get(&(ns, name)) {
+                    Some(found) => found,
+                    // Reported by the scope checker
+                    None => continue,
+                };
+                match found.iter().rev().find(|&&j| j < i) {
+                    Some(&j) => {
+                        edges[i].insert(j);
+                        // A later declaration of the same name must stay
+                        // after this use, or the use would refer to it
+                        for &k in found.iter().filter(|&&k| k > i) {
+                            edges[k].insert(i);
+                        }
+                    }
+                    None => {
+                        edges[i].insert(found[0]);
+                    }
+                }
+            }
+        }
+        Graph { edges }
+    }
+
+    /// The strongly connected components of the graph, with Tarjan's
+    /// algorithm. Each component comes after those it refers to
+    fn components(&self) -> Vec<Vec<usize>> {
+        struct Tarjan<'g> {
+            graph: &'g Graph,
+            index: Vec<Option<usize>>,
+            low: Vec<usize>,
+            stack: Vec<usize>,
+            on_stack: Vec<bool>,
+            next: usize,
+            components: Vec<Vec<usize>>,
+        }
+
+        impl<'g> Tarjan<'g> {
+            fn visit(&mut self, v: usize) {
+                self.index[v] = Some(self.next);
+                self.low[v] = self.next;
+                self.next += 1;
+                self.stack.push(v);
+                self.on_stack[v] = true;
+                for &w in &self.graph.edges[v] {
+                    match self.index[w] {
+                        None => {
+                            self.visit(w);
+                            self.low[v] = self.low[v].min(self.low[w]);
+                        }
+                        Some(index) if self.on_stack[w] => self.low[v] = self.low[v].min(index),
+                        Some(_) => {}
+                    }
+                }
+                if Some(self.low[v]) == self.index[v] {
+                    let mut component = Vec::new();
+                    while let Some(w) = self.stack.pop() {
+                        self.on_stack[w] = false;
+                        component.push(w);
+                        if w == v {
+                            break;
+                        }
+                    }
+                    component.sort_unstable();
+                    self.components.push(component);
+                }
+            }
+        }
+
+        let n = self.edges.len();
+        let mut t = Tarjan {
+            graph: self,
+            index: vec![None; n],
+            low: vec![0; n],
+            stack: Vec::new(),
+            on_stack: vec![false; n],
+            next: 0,
+            components: Vec::new(),
+        };
+        for v in 0..n {
+            if t.index[v].is_none() {
+                t.visit(v);
+            }
+        }
+        t.components
+    }
+
+    /// The components in an order where each comes after those it refers
+    /// to, and otherwise in the order of their first declarations
+    fn order(&self) -> Vec<Vec<usize>> {
+        let components = self.components();
+        let mut component = vec![0; self.edges.len()];
+        for (c, vs) in components.iter().enumerate() {
+            for &v in vs {
+                component[v] = c;
+            }
+        }
+        let mut waiting = components
+            .iter()
+            .enumerate()
+            .map(|(c, vs)| {
+                vs.iter()
+                    .flat_map(|&v| &self.edges[v])
+                    .map(|&w| component[w])
+                    .filter(|&d| d != c)
+                    .collect::<BTreeSet<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        // Components are ready once everything they refer to is placed, and
+        // the ready component declared first is placed next
+        let mut placed = vec![false; components.len()];
+        let mut order = Vec::with_capacity(components.len());
+        while let Some(c) = (0..components.len())
+            .filter(|&c| !placed[c] && waiting[c].is_empty())
+            .min_by_key(|&c| components[c][0])
+        {
+            placed[c] = true;
+            for w in waiting.iter_mut() {
+                w.remove(&c);
+            }
+            order.push(components[c].clone());
+        }
+        order
+    }
+
+    /// The shortest path from `from` back to itself through the vertices
+    /// for which `within` holds, if there is one
+    fn cycle<F: Fn(usize) -> bool>(&self, from: usize, within: F) -> Option<Vec<usize>> {
+        let mut parent = HashMap::new();
+        let mut queue = VecDeque::new();
+        queue.push_back(from);
+        while let Some(v) = queue.pop_front() {
+            for &w in &self.edges[v] {
+                if w == from {
+                    let mut path = Vec::new();
+                    let mut u = v;
+                    while u != from {
+                        path.push(u);
+                        u = parent[&u];
+                    }
+                    path.push(from);
+                    path.reverse();
+                    path.push(from);
+                    return Some(path);
+                }
+                if within(w) && !parent.contains_key(&w) {
+                    parent.insert(w, v);
+                    queue.push_back(w);
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Join a group of declarations that refer to each other with `and`
+fn join(group: Vec<Decl>) -> Decl {
+    let mut group = group.into_iter();
+    let first = group.next().expect("internal error: empty group of declarations");
+    group.fold(first, |d1, d2| {
+        let span = d1.span + d2.span;
+        Decl::new(DeclKind::And(Box::new(d1), Box::new(d2)), span)
+    })
+}
+
+/// Put `decls` in an order where each declaration comes after everything
+/// it refers to, joining those that refer to each other with `and`
+pub fn order(decls: &[Decl]) -> Result<Vec<Decl>, Vec<DependencyError>> {
+    let graph = Graph::new(decls);
+    let name = |v: usize| {
+        declared(&decls[v])
+            .first()
+            .map(|&(_, s)| s)
+            .unwrap_or_else(|| Symbol::intern("expression"))
+    };
+
+    let mut ordered = Vec::new();
+    let mut errors = Vec::new();
+    for group in graph.order() {
+        let cyclic = group.len() > 1 || graph.edges[group[0]].contains(&group[0]);
+        if cyclic {
+            let abbrev = |v: usize| matches!(decls[v].kind, DeclKind::Type(..));
+            let members = group.iter().copied().collect::<BTreeSet<_>>();
+
+            // Report the first abbreviation on a cycle of abbreviations, and
+            // the first declaration that may not be recursive
+            if let Some(path) = group
+                .iter()
+                .filter(|&&v| abbrev(v))
+                .find_map(|&v| graph.cycle(v, |w| abbrev(w) && members.contains(&w)))
+            {
+                let names = path.iter().map(|&v| name(v)).collect();
+                errors.push(DependencyError::Abbrev(AbbrevError::Cycle(names, decls[path[0]].span)));
+            }
+            if let Some(path) = group
+                .iter()
+                .filter(|&&v| !recursive(&decls[v]))
+                .find_map(|&v| graph.cycle(v, |w| members.contains(&w)))
+            {
+                let names = path.iter().map(|&v| name(v)).collect();
+                errors.push(DependencyError::Recursive(names, decls[path[0]].span));
+            }
+        }
+        let group = group.iter().map(|&v| decls[v].clone()).collect::<Vec<_>>();
+        match cyclic {
+            true => ordered.push(join(group)),
+            false => ordered.extend(group),
+        }
+    }
+    match errors.is_empty() {
+        true => Ok(ordered),
+        false => Err(errors),
+    }
+}
+
+impl DependencyError {
+    pub fn span(&self) -> Span {
+        match self {
+            DependencyError::Abbrev(e) => e.span(),
+            DependencyError::Recursive(_, sp) => *sp,
+        }
+    }
+}
+
+impl fmt::Display for DependencyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DependencyError::Abbrev(e) => write!(f, "{}", e),
+            DependencyError::Recursive(path, _) => {
+                write!(
+                    f,
+                    "{} is defined in terms of itself: {}",
+                    path[0],
+                    path.iter().map(|s| s.as_str()).collect::<Vec<_>>().join(" -> ")
+                )
+            }
+        }
+    }
+}
+
+impl From<DependencyError> for Diagnostic {
+    fn from(e: DependencyError) -> Diagnostic {
+        match e {
+            DependencyError::Abbrev(e) => e.into(),
+            e => Diagnostic::error(e.span(), e.to_string()).info("only functions and datatypes may be recursive"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::infer::Infer;
+    use crate::scopecheck::check;
+    use crate::syntax::ast::Program;
+    use crate::syntax::parser::Parser;
+
+    fn program(input: &str) -> Vec<Decl> {
+        Parser::new(input).parse_program().unwrap().decls
+    }
+
+    /// The names each ordered declaration makes, with those joined by `and`
+    /// separated by commas
+    fn names(decls: &[Decl]) -> Vec<String> {
+        decls
+            .iter()
+            .map(|d| {
+                declared(d)
+                    .into_iter()
+                    .filter(|(ns, _)| *ns != Namespace::Constructor)
+                    .map(|(_, s)| s.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn acyclic() {
+        let input = "val x = Some (f 1); \
+                     fun f n = g n; \
+                     datatype 'a option = None | Some of 'a; \
+                     fun g (n : count) = n; \
+                     type count = int";
+        let ordered = order(&program(input)).unwrap();
+        assert_eq!(names(&ordered), ["option", "count", "g", "f", "x"]);
+
+        // The ordered declarations are in scope, and type check
+        assert_eq!(check(&Program { decls: ordered.clone() }), []);
+        let mut infer = Infer::default();
+        for d in &ordered {
+            infer.decl(d);
+        }
+        assert!(infer.errors.is_empty(), "{:?}", infer.errors);
+
+        // Declarations already in order stay as they are, and each use refers
+        // to the closest declaration before it
+        let input = "val x = 1; val y = x; val x = true; val z = (x, y)";
+        let ordered = order(&program(input)).unwrap();
+        assert_eq!(ordered, program(input));
+        let input = "val x = 1; val y = f x; val x = true; fun f n = n";
+        assert_eq!(names(&order(&program(input)).unwrap()), ["x", "f", "y", "x"]);
+    }
+
+    #[test]
+    fn mutual() {
+        let input = "datatype 'a tree = Leaf | Node of 'a * 'a forest; \
+                     val t = Node (1, Nil); \
+                     datatype 'a forest = Nil | Cons of 'a tree * 'a forest; \
+                     fun size t = case t of Leaf => 0 | Node (_, f) => sizes f end; \
+                     fun sizes f = case f of Nil => 0 | Cons (t, f) => size t end";
+        let ordered = order(&program(input)).unwrap();
+        assert_eq!(names(&ordered), ["tree, forest", "t", "size, sizes"]);
+        assert!(matches!(ordered[0].kind, DeclKind::And(..)));
+        assert_eq!(check(&Program { decls: ordered.clone() }), []);
+        let mut infer = Infer::default();
+        for d in &ordered {
+            infer.decl(d);
+        }
+        assert!(infer.errors.is_empty(), "{:?}", infer.errors);
+
+        // A cycle through a datatype is not a cycle of abbreviations
+        let input = "type t = int tree; datatype 'a tree = Leaf | Node of 'a * t";
+        assert_eq!(names(&order(&program(input)).unwrap()), ["t, tree"]);
+
+        // Values may not be recursive, not even through functions
+        let input = "val x = f (); fun f u = x";
+        let errs = order(&program(input)).unwrap_err();
+        assert_eq!(
+            errs.into_iter()
+                .map(Diagnostic::from)
+                .map(|d| (d.primary.info, d.info))
+                .collect::<Vec<_>>(),
+            [(
+                "x is defined in terms of itself: x -> f -> x".to_string(),
+                vec!["only functions and datatypes may be recursive".to_string()]
+            )]
+        );
+    }
+
+    #[test]
+    fn abbreviations() {
+        let input = "type a = b * int; type b = c list; type c = unit -> a";
+        let errs = order(&program(input)).unwrap_err();
+        assert_eq!(
+            errs.into_iter()
+                .map(|e| Diagnostic::from(e).primary.info)
+                .collect::<Vec<_>>(),
+            ["type abbreviation a refers to itself: a -> b -> c -> a"]
+        );
+
+        let errs = order(&program("type t = t list")).unwrap_err();
+        assert!(matches!(&errs[..], [DependencyError::Abbrev(AbbrevError::Cycle(path, _))] if path == &["t", "t"]));
+    }
+}
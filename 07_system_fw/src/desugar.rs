@@ -0,0 +1,274 @@
+//! Desugaring of datatype declarations into the core type language
+//!
+//! `datatype ('a, 'b) t = A | B of ty` declares a type abbreviation for a
+//! sum, abstracted over its parameters:
+//!
+//! `t = fn ('a :: *) => fn ('b :: *) => A | B of ty`
+//!
+//! If the variants refer to `t` itself, the abbreviation is instead the
+//! fixed point of an operator taking `t` as an argument:
+//!
+//! `t = rec fn ('t :: * -> * -> *) => fn ('a :: *) => fn ('b :: *) => ...`
+//!
+//! Each constructor also gets a type, universally quantified over the
+//! parameters, e.g. `B : forall ('a :: *) of forall ('b :: *) of ty -> ('a, 'b) t`
+//!
+//! A constructor that takes a tuple, `C of ty1 * ty2`, can also be used as a
+//! curried function, `C : ty1 -> ty2 -> t`, so that it can be partially
+//! applied. Only a constructor applied directly to a tuple of the right
+//! length, `C (e1, e2)`, and constructor patterns, use the tuple form
+use crate::symbol::Symbol;
+use crate::syntax::ast::{Kind, RowVar, Type, TypeKind};
+use crate::syntax::visit::{referenced, ExpandDefined, TypeMutVisitor, TypeVisitor};
+use std::collections::{BTreeSet, HashMap};
+
+/// Collect every type variable name used in a type, bound or free
+#[derive(Default)]
+struct TyvarNames {
+    names: BTreeSet<Symbol>,
+}
+
+impl<'t> TypeVisitor<'t> for TyvarNames {
+    fn visit_variable(&mut self, s: Symbol) {
+        self.names.insert(s);
+    }
+
+    fn visit_row_variable(&mut self, var: &'t RowVar) {
+        self.names.insert(var.name);
+    }
+
+    fn visit_existential(&mut self, s: Symbol, _: &'t Kind, ty: &'t Type) {
+        self.names.insert(s);
+        self.visit_ty(ty);
+    }
+
+    fn visit_universal(&mut self, s: Symbol, _: &'t Kind, ty: &'t Type) {
+        self.names.insert(s);
+        self.visit_ty(ty);
+    }
+
+    fn visit_abstraction(&mut self, s: Symbol, _: &'t Kind, ty: &'t Type) {
+        self.names.insert(s);
+        self.visit_ty(ty);
+    }
+}
+
+/// The kind `* -> ... -> *` of a type constructor taking `arity` arguments
+pub fn constructor_kind(arity: usize) -> Kind {
+    (0..arity).fold(Kind::Star, |k, _| Kind::Arrow(Box::new(Kind::Star), Box::new(k)))
+}
+
+/// Abstract `ty` over each of the type parameters, the last innermost
+fn abstract_tyvars(tyvars: &[Type], ty: Type) -> Type {
+    tyvars.iter().rev().fold(ty, |ty, var| {
+        let span = var.span + ty.span;
+        let kind = TypeKind::Abstraction(var.kind.as_tyvar(), Box::new(Kind::Star), Box::new(ty));
+        Type::new(kind, span)
+    })
+}
+
+/// The type abbreviation declared by `datatype tyvars name = sum`
+pub fn datatype(tyvars: &[Type], name: Symbol, sum: &Type) -> Type {
+    if !referenced(sum).contains(&name) {
+        return abstract_tyvars(tyvars, sum.clone());
+    }
+
+    // Replace references to the datatype with a variable that is not
+    // already used anywhere, so that nothing can be captured
+    let mut used = TyvarNames::default();
+    used.visit_ty(sum);
+    let var = std::iter::once(name)
+        .chain((1..).map(|i| Symbol::intern(&format!("{}{}", name, i))))
+        .find(|s| !used.names.contains(s) && !tyvars.iter().any(|t| t.kind.as_tyvar() == *s))
+        .unwrap();
+    let mut env = HashMap::new();
+    env.insert(name, Type::new(TypeKind::Variable(var), sum.span));
+    let mut body = sum.clone();
+    ExpandDefined::new(&env).visit_ty(&mut body);
+
+    let span = body.span;
+    let op = TypeKind::Abstraction(
+        var,
+        Box::new(constructor_kind(tyvars.len())),
+        Box::new(abstract_tyvars(tyvars, body)),
+    );
+    Type::new(TypeKind::Recursive(Box::new(Type::new(op, span))), span)
+}
+
+/// The type of each constructor of `datatype tyvars name = sum`, in order
+pub fn constructors(tyvars: &[Type], name: Symbol, sum: &Type) -> Vec<(Symbol, Type)> {
+    // The datatype applied to its parameters, e.g. ('a, 'b) t
+    let result = tyvars
+        .iter()
+        .fold(Type::new(TypeKind::Defined(name), sum.span), |ty, var| {
+            let span = ty.span;
+            Type::new(TypeKind::Application(Box::new(ty), Box::new(var.clone())), span)
+        });
+
+    sum.kind
+        .variants()
+        .iter()
+        .map(|v| {
+            let ty = match &v.ty {
+                Some(payload) => Type::new(
+                    TypeKind::Function(Box::new(payload.clone()), Box::new(result.clone())),
+                    v.span,
+                ),
+                None => Type::new(result.kind.clone(), v.span),
+            };
+            let ty = tyvars.iter().rev().fold(ty, |ty, var| {
+                let span = ty.span;
+                let kind = TypeKind::Universal(var.kind.as_tyvar(), Box::new(Kind::Star), Box::new(ty));
+                Type::new(kind, span)
+            });
+            (v.label, ty)
+        })
+        .collect()
+}
+
+/// The types of the fields of the tuple a constructor of type `con_ty`
+/// takes, if it takes a tuple of at least two
+fn fields(con_ty: &Type) -> Option<&[Type]> {
+    match &con_ty.kind {
+        TypeKind::Universal(_, _, ty) => fields(ty),
+        TypeKind::Function(payload, _) => match &payload.kind {
+            TypeKind::Product(tys) if tys.len() > 1 => Some(tys),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The length of the tuple a constructor of type `con_ty` takes, if it
+/// takes a tuple of at least two
+pub fn tuple_arity(con_ty: &Type) -> Option<usize> {
+    fields(con_ty).map(|tys| tys.len())
+}
+
+/// The type of the curried form of a constructor of type `con_ty`, if it
+/// takes a tuple of at least two, e.g. `forall ('a :: *) of 'a -> 'a list ->
+/// 'a list` for `forall ('a :: *) of 'a * 'a list -> 'a list`
+pub fn curried(con_ty: &Type) -> Option<Type> {
+    fields(con_ty)?;
+    match &con_ty.kind {
+        TypeKind::Universal(s, k, ty) => Some(Type::new(
+            TypeKind::Universal(*s, k.clone(), Box::new(curried(ty)?)),
+            con_ty.span,
+        )),
+        TypeKind::Function(payload, result) => match &payload.kind {
+            TypeKind::Product(tys) => Some(tys.iter().rev().fold((**result).clone(), |ty, field| {
+                Type::new(TypeKind::Function(Box::new(field.clone()), Box::new(ty)), con_ty.span)
+            })),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::kindcheck::KindContext;
+    use crate::syntax::ast::DeclKind;
+    use crate::syntax::parser::Parser;
+    use crate::types::alpha_eq;
+
+    fn ty(input: &str) -> Type {
+        Parser::new(input).parse_type().unwrap()
+    }
+
+    /// Desugar a datatype declaration, returning its abbreviation and the
+    /// types of its constructors
+    fn desugar(input: &str) -> (Symbol, Type, Vec<(Symbol, Type)>) {
+        match Parser::new(input).parse_decl().unwrap().kind {
+            DeclKind::Datatype(tyvars, name, sum) => {
+                let abbrev = datatype(&tyvars, name, &sum);
+                let cons = constructors(&tyvars, name, &sum);
+                (name, abbrev, cons)
+            }
+            d => panic!("expected a datatype, not {:?}", d),
+        }
+    }
+
+    #[test]
+    fn list() {
+        let (name, abbrev, cons) = desugar("datatype 'a list = Nil | Cons of 'a * 'a list");
+        let mut ctx = KindContext::default();
+        assert_eq!(ctx.kind_of(&abbrev), Ok(constructor_kind(1)));
+        match &abbrev.kind {
+            TypeKind::Recursive(op) => match &op.kind {
+                TypeKind::Abstraction(var, kind, body) => {
+                    assert_eq!(var, "list");
+                    assert_eq!(**kind, constructor_kind(1));
+                    assert!(matches!(body.kind, TypeKind::Abstraction(..)));
+                }
+                k => panic!("expected an abstraction, not {:?}", k),
+            },
+            k => panic!("expected a recursive type, not {:?}", k),
+        }
+
+        ctx.define(name, constructor_kind(1));
+        let expected = [
+            ("Nil", "forall ('a :: *) of 'a list"),
+            ("Cons", "forall ('a :: *) of 'a * 'a list -> 'a list"),
+        ];
+        assert_eq!(cons.len(), expected.len());
+        for ((label, ty), (l, t)) in cons.iter().zip(&expected) {
+            assert_eq!(label, l);
+            assert!(alpha_eq(ty, &self::ty(t)), "{:?}", ty);
+            assert_eq!(ctx.kind_of(ty), Ok(Kind::Star));
+        }
+
+        // Only Cons takes a tuple, so only it has a curried form
+        assert_eq!(curried(&cons[0].1), None);
+        assert_eq!(tuple_arity(&cons[1].1), Some(2));
+        let cons = curried(&cons[1].1).unwrap();
+        assert!(alpha_eq(
+            &cons,
+            &self::ty("forall ('a :: *) of 'a -> 'a list -> 'a list")
+        ));
+    }
+
+    #[test]
+    fn tree() {
+        let (name, abbrev, cons) = desugar("datatype 'a tree = Leaf | Node of 'a tree * 'a * 'a tree");
+        let mut ctx = KindContext::default();
+        assert_eq!(ctx.kind_of(&abbrev), Ok(constructor_kind(1)));
+        ctx.define(name, constructor_kind(1));
+        let labels = cons.iter().map(|(l, _)| l.as_str()).collect::<Vec<_>>();
+        assert_eq!(labels, ["Leaf", "Node"]);
+        assert!(alpha_eq(
+            &cons[1].1,
+            &ty("forall ('b :: *) of 'b tree * 'b * 'b tree -> 'b tree")
+        ));
+        for (_, ty) in &cons {
+            assert_eq!(ctx.kind_of(ty), Ok(Kind::Star));
+        }
+    }
+
+    #[test]
+    fn parameters() {
+        // Not recursive, so just an abstraction over the sum
+        let (name, abbrev, cons) = desugar("datatype ('a, 'b) either = Left of 'a | Right of 'b");
+        let mut ctx = KindContext::default();
+        assert_eq!(ctx.kind_of(&abbrev), Ok(constructor_kind(2)));
+        assert!(matches!(abbrev.kind, TypeKind::Abstraction(..)));
+        ctx.define(name, constructor_kind(2));
+        assert!(alpha_eq(
+            &cons[1].1,
+            &ty("forall ('a :: *) of forall ('b :: *) of 'b -> ('a, 'b) either")
+        ));
+        for (_, ty) in &cons {
+            assert_eq!(ctx.kind_of(ty), Ok(Kind::Star));
+        }
+
+        // The variable standing for the datatype must not clash with any
+        // type variable already in use
+        let (_, abbrev, _) = desugar("datatype 't t = Leaf | Node of 't * 't t");
+        assert_eq!(KindContext::default().kind_of(&abbrev), Ok(constructor_kind(1)));
+        match &abbrev.kind {
+            TypeKind::Recursive(op) => assert!(matches!(&op.kind, TypeKind::Abstraction(var, ..) if var == "t1")),
+            k => panic!("expected a recursive type, not {:?}", k),
+        }
+    }
+}
@@ -75,6 +75,40 @@ impl Diagnostic {
         }
         range
     }
+
+    /// The diagnostic, quoting the lines of `src` that it covers, with each
+    /// annotation marked under the line it starts on, and then its notes
+    pub fn render(&self, src: &str) -> String {
+        let mut primary = self.primary.clone();
+        if self.level == Level::Warn {
+            primary.info = format!("warning: {}", primary.info);
+        }
+        let annotations = std::iter::once(&primary).chain(&self.other).collect::<Vec<_>>();
+        let lines = src.lines().collect::<Vec<_>>();
+        let mut out = String::new();
+        for line in self.lines() {
+            let text = lines.get(line as usize).copied().unwrap_or_default();
+            out.push_str(&format!("| {} {}\n", line + 1, text));
+            for anno in annotations.iter().filter(|anno| anno.span.start.line == line) {
+                // Past the line number and the bars
+                let indent = (line + 1).to_string().len() as u32 + 3;
+                let width = match anno.span.end.line == line {
+                    true => anno.span.end.col.saturating_sub(anno.span.start.col),
+                    false => 1,
+                };
+                out.push_str(&format!(
+                    "{}^{}^ --- {}\n",
+                    " ".repeat((anno.span.start.col + indent) as usize),
+                    "~".repeat(width.saturating_sub(1) as usize),
+                    anno.info
+                ));
+            }
+        }
+        for info in &self.info {
+            out.push_str(&format!("note: {}\n", info));
+        }
+        out
+    }
 }
 
 impl fmt::Debug for Diagnostic {
@@ -86,7 +120,12 @@ impl fmt::Debug for Diagnostic {
             self.primary.info,
             self.primary.span.start.line,
             self.primary.span.start.col,
-            self.other.iter().map(|a| a.info.clone()).collect::<Vec<_>>().join("\n")
+            self.other
+                .iter()
+                .map(|a| a.info.clone())
+                .chain(self.info.iter().map(|i| format!("note: {}", i)))
+                .collect::<Vec<_>>()
+                .join("\n")
         )
     }
 }
@@ -0,0 +1,183 @@
+//! Checking a program from start to finish
+//!
+//! Each check has an error type of its own, which is wrapped in an
+//! [`ElabError`], so that the errors of every check can be collected and
+//! reported together, in the order they appear in the source. A check does
+//! not stop at the first error it finds, and neither does the driver stop at
+//! the first check that finds one, as long as the checks after it can still
+//! run. Repeated names and recursive declarations are found first, since
+//! the other checks rely on the declarations being in dependency order.
+//! Scope checking and inference then run together, as inference gives any
+//! name that is not in scope a type of its own, which no other error comes
+//! from. Elaboration only runs on a program that is free of errors, as what
+//! it finds wrong with a program is already reported by scope checking
+use crate::dependencies::order;
+use crate::diagnostics::Diagnostic;
+use crate::elaborate::{ElabError, Elaborated, ElaborationContext};
+use crate::infer::Infer;
+use crate::prelude::with_prelude;
+use crate::scopecheck::unbound;
+use crate::syntax::ast::Program;
+use crate::validate::ProgramValidation;
+
+/// A program that passed every check, with its declarations in dependency
+/// order
+pub struct Checked {
+    pub program: Program,
+    pub elaborated: Elaborated,
+    pub warnings: Vec<Diagnostic>,
+}
+
+/// Check `program`, after the declarations of the prelude if `prelude` is
+/// set, returning every error found in source order
+pub fn check(program: Program, prelude: bool) -> Result<Checked, Vec<ElabError>> {
+    let validation = ProgramValidation::check(&program);
+    let mut errors = validation.errors;
+    let mut warnings = validation.warnings;
+
+    let program = if prelude { with_prelude(program) } else { program };
+    let program = match order(&program.decls) {
+        Ok(decls) if errors.is_empty() => Program { decls },
+        Ok(_) => return Err(sorted(errors)),
+        Err(es) => {
+            errors.extend(es.into_iter().map(ElabError::from));
+            return Err(sorted(errors));
+        }
+    };
+
+    errors.extend(unbound(&program).into_iter().map(ElabError::from));
+    let mut infer = Infer::default();
+    for d in &program.decls {
+        infer.decl(d);
+    }
+    let bindings = infer.bindings();
+    errors.extend(infer.errors.into_iter().map(ElabError::from));
+    if !errors.is_empty() {
+        return Err(sorted(errors));
+    }
+    warnings.extend(infer.warnings);
+
+    let mut elaborated = ElaborationContext::elaborate(&program).map_err(sorted)?;
+    warnings.append(&mut elaborated.warnings);
+    elaborated.bindings = bindings;
+    Ok(Checked {
+        program,
+        elaborated,
+        warnings,
+    })
+}
+
+/// `errors`, ordered by where they start
+fn sorted(mut errors: Vec<ElabError>) -> Vec<ElabError> {
+    errors.sort_by_key(|e| (e.span.start.line, e.span.start.col));
+    errors
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::elaborate::ElabErrorKind;
+    use crate::syntax::parser::Parser;
+
+    /// The errors for `input`, checked without the prelude, as the line
+    /// they start on and the category they are in
+    fn errors(input: &str) -> Vec<(u32, &'static str)> {
+        let program = Parser::with_spans(input).parse_program().unwrap();
+        let errors = match check(program, false) {
+            Ok(_) => return Vec::new(),
+            Err(errors) => errors,
+        };
+        errors
+            .into_iter()
+            .map(|e| {
+                let category = match e.kind {
+                    ElabErrorKind::DuplicateLabel(..) => "duplicate label",
+                    ElabErrorKind::DuplicateConstructor(..) => "duplicate constructor",
+                    ElabErrorKind::Unbound(_) => "unbound",
+                    ElabErrorKind::Recursive(_) => "recursive",
+                    ElabErrorKind::Kind(_) => "kind",
+                    ElabErrorKind::Arity(_) => "arity",
+                    ElabErrorKind::Type(_) => "type",
+                    ElabErrorKind::Module(_) => "module",
+                    _ => "elaboration",
+                };
+                (e.span.start.line, category)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn categories() {
+        let input = "type t = {x: int, x: bool}\n\
+                     datatype d = A | B | A\n\
+                     val a = 1";
+        assert_eq!(errors(input), [(0, "duplicate label"), (1, "duplicate constructor")]);
+
+        // Ordering stops at a declaration that refers to itself, along
+        // with any repeated names
+        let input = "val r = {y = 1, y = 2}\n\
+                     val x = x";
+        assert_eq!(errors(input), [(0, "duplicate label"), (1, "recursive")]);
+
+        let input = "type 'a pair = 'a * 'a\n\
+                     val f = fn (x : int pair pair) => y\n\
+                     val g = fn (p : (int int) pair) => p\n\
+                     val h = fn (q : pair) => q\n\
+                     val n = (fn (x : int) => x) ()\n\
+                     val z = undefined";
+        assert_eq!(
+            errors(input),
+            [(1, "unbound"), (2, "kind"), (3, "arity"), (4, "type"), (5, "unbound")]
+        );
+        assert_eq!(errors("val n = 1; val f = fn (x : int) => x"), []);
+    }
+
+    #[test]
+    fn render() {
+        let input = "val a = 1\nval b = c";
+        let program = Parser::with_spans(input).parse_program().unwrap();
+        let errors = check(program, false).err().expect("expected an error");
+        assert_eq!(errors.len(), 1);
+        let diag = Diagnostic::from(errors[0].clone());
+        assert_eq!(diag.primary.info, "unbound variable c");
+        assert_eq!(
+            diag.render(input),
+            "| 2 val b = c\n            ^^ --- unbound variable c\n"
+        );
+    }
+
+    #[test]
+    fn bindings() {
+        let input = "datatype 'a list = Nil | Cons of 'a * 'a list\n\
+                     val xs = Cons (1, Cons (2, Nil))\n\
+                     val n = case xs of\n\
+                     Cons (x, rest as Cons (y, _)) => x\n\
+                     | (Cons (z, Nil) | Cons (_, Cons (z, _))) => z\n\
+                     | Nil => 0\n\
+                     end\n\
+                     val p as (a, b) = (xs, ())";
+        let program = Parser::with_spans(input).parse_program().unwrap();
+        let checked = check(program, false).unwrap_or_else(|errors| panic!("{:?}", errors));
+        let bindings = checked
+            .elaborated
+            .bindings
+            .iter()
+            .map(|(name, ty, span)| (name.as_str(), ty.to_string(), span.start.line, span.start.col))
+            .collect::<Vec<_>>();
+        // The z of the or-pattern is bound once, by its first alternative
+        assert_eq!(
+            bindings,
+            [
+                ("xs", "int list".into(), 1, 4),
+                ("n", "int".into(), 2, 4),
+                ("x", "int".into(), 3, 6),
+                ("rest", "int list".into(), 3, 9),
+                ("y", "int".into(), 3, 23),
+                ("z", "int".into(), 4, 9),
+                ("p", "int list * unit".into(), 7, 4),
+                ("a", "int list".into(), 7, 10),
+                ("b", "unit".into(), 7, 13),
+            ]
+        );
+    }
+}
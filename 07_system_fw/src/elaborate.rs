@@ -1,51 +1,218 @@
+use super::abbrev::AbbrevError;
 use super::ast::*;
+use super::coverage::{self, Ctor, Signatures};
+use super::dependencies::DependencyError;
+use super::desugar;
+use super::diagnostics::Diagnostic;
 use super::hir::{self, Constructor, DeBruijn, HirId};
+use super::infer::InferError;
+use super::kindcheck::KindError;
+use super::modules::ModuleError;
+use super::scopecheck::ScopeError;
 use super::stack::Stack;
+use super::symbol::Symbol;
 use super::syntax::visit::*;
 use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::iter::IntoIterator;
+use util::span::Span;
 
 /// Validate that a [`Program`] is closed, e.g. it has no free
 /// term or type variables. We traverse the program in execution order,
 /// adding bindings for top-level declarations, and also keeping track of
 /// bindings that occur in local scopes for de Bruijn index tracking
 #[derive(Default)]
-pub struct ElaborationContext<'s> {
-    tyvars: Stack<&'s str>,
-    tmvars: Stack<&'s str>,
+pub struct ElaborationContext {
+    tyvars: Stack<Symbol>,
+    tmvars: Stack<Symbol>,
 
     namespaces: Vec<Namespace>,
     current: usize,
     constructors: HashMap<HirId, Constructor>,
     elaborated: HashMap<HirId, hir::Decl>,
     next_hir_id: HirId,
+    signatures: Signatures,
+    warnings: Vec<Diagnostic>,
 }
 
+/// Each variable bound by a pattern, with its type and the span of the
+/// pattern binding it
+pub type BindingMap = Vec<(Symbol, Type, Span)>;
+
 pub struct Elaborated {
     pub constructors: HashMap<HirId, Constructor>,
     pub elaborated: HashMap<HirId, hir::Decl>,
     pub decls: Vec<HirId>,
+    pub warnings: Vec<Diagnostic>,
+    /// The variables bound by the patterns of case arms and value
+    /// declarations, in the order they appear. Elaboration leaves this
+    /// empty, and the [`driver`](crate::driver) fills it in from inference
+    pub bindings: BindingMap,
 }
 
 #[derive(Default)]
 pub struct Namespace {
     id: usize,
     parent: Option<usize>,
-    values: HashMap<String, HirId>,
-    types: HashMap<String, HirId>,
+    values: HashMap<Symbol, HirId>,
+    types: HashMap<Symbol, HirId>,
+    /// The namespace holding the components of each structure
+    structures: HashMap<Symbol, usize>,
+}
+
+/// An error found by any of the checks a program goes through before it is
+/// run, each of which has its own error type. Elaboration wraps them all, by
+/// the sort of mistake they are about, so that the driver can collect and
+/// report them the same way
+#[derive(Clone, Debug, PartialEq)]
+pub struct ElabError {
+    pub span: Span,
+    pub kind: ElabErrorKind,
 }
 
 #[derive(Clone, Debug, PartialEq)]
+pub enum ElabErrorKind {
+    /// The label is used more than once in a record type, expression or
+    /// pattern, as named, and is first used at the span
+    DuplicateLabel(Symbol, &'static str, Span),
+    /// The constructor is declared more than once in the same datatype,
+    /// first at the span
+    DuplicateConstructor(Symbol, Span),
+    /// A name that is not in scope
+    Unbound(Box<ScopeError>),
+    /// Declarations that refer to themselves, but may not
+    Recursive(Box<DependencyError>),
+    /// A type that does not have the kind it is used at
+    Kind(Box<KindError>),
+    /// A type abbreviation applied to the wrong number of arguments
+    Arity(Box<AbbrevError>),
+    /// Types that do not agree
+    Type(Box<InferError>),
+    /// A structure that does not match its signature
+    Module(Box<ModuleError>),
+    UndefinedType(Symbol),
+    UnboundTypeVar(Symbol),
+    UndefinedValue(Symbol),
+    UndefinedConstr(Symbol),
+    UndefinedStructure(Symbol),
+    InvalidBinding(String),
+}
+
+impl ElabError {
+    pub fn new(kind: ElabErrorKind, span: Span) -> ElabError {
+        ElabError { span, kind }
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+}
+
+impl fmt::Display for ElabError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            ElabErrorKind::DuplicateLabel(s, what, _) => {
+                write!(f, "label {} is used more than once in a record {}", s, what)
+            }
+            ElabErrorKind::DuplicateConstructor(s, _) => {
+                write!(f, "constructor {} is declared more than once in the same datatype", s)
+            }
+            ElabErrorKind::Unbound(e) => write!(f, "{}", e),
+            ElabErrorKind::Recursive(e) => write!(f, "{}", e),
+            ElabErrorKind::Kind(e) => write!(f, "{}", e),
+            ElabErrorKind::Arity(e) => write!(f, "{}", e),
+            ElabErrorKind::Type(e) => write!(f, "{}", e),
+            ElabErrorKind::Module(e) => write!(f, "{}", e),
+            ElabErrorKind::UndefinedType(s) => write!(f, "undefined type {}", s),
+            ElabErrorKind::UnboundTypeVar(s) => write!(f, "unbound type variable '{}", s),
+            ElabErrorKind::UndefinedValue(s) => write!(f, "undefined value {}", s),
+            ElabErrorKind::UndefinedConstr(s) => write!(f, "undefined constructor {}", s),
+            ElabErrorKind::UndefinedStructure(s) => write!(f, "undefined structure {}", s),
+            ElabErrorKind::InvalidBinding(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl From<ScopeError> for ElabError {
+    fn from(e: ScopeError) -> ElabError {
+        let span = e.span();
+        ElabError::new(ElabErrorKind::Unbound(Box::new(e)), span)
+    }
+}
 
-pub enum ElabError {
-    UndefinedType(String, util::span::Span),
-    UndefinedValue(String, util::span::Span),
-    UndefinedConstr(String, util::span::Span),
-    InvalidBinding(String, util::span::Span),
+impl From<DependencyError> for ElabError {
+    fn from(e: DependencyError) -> ElabError {
+        match e {
+            DependencyError::Abbrev(e) => e.into(),
+            e => {
+                let span = e.span();
+                ElabError::new(ElabErrorKind::Recursive(Box::new(e)), span)
+            }
+        }
+    }
+}
+
+impl From<KindError> for ElabError {
+    fn from(e: KindError) -> ElabError {
+        let span = e.span();
+        ElabError::new(ElabErrorKind::Kind(Box::new(e)), span)
+    }
+}
+
+impl From<AbbrevError> for ElabError {
+    fn from(e: AbbrevError) -> ElabError {
+        match e {
+            AbbrevError::Kind(e) => e.into(),
+            AbbrevError::Cycle(..) => {
+                let span = e.span();
+                ElabError::new(ElabErrorKind::Recursive(Box::new(DependencyError::Abbrev(e))), span)
+            }
+            e => {
+                let span = e.span();
+                ElabError::new(ElabErrorKind::Arity(Box::new(e)), span)
+            }
+        }
+    }
+}
+
+impl From<InferError> for ElabError {
+    fn from(e: InferError) -> ElabError {
+        match e {
+            InferError::Kind(e) => e.into(),
+            InferError::Abbrev(e) => e.into(),
+            InferError::Module(e) => {
+                let span = e.span();
+                ElabError::new(ElabErrorKind::Module(Box::new(e)), span)
+            }
+            e => {
+                let span = e.span();
+                ElabError::new(ElabErrorKind::Type(Box::new(e)), span)
+            }
+        }
+    }
+}
+
+impl From<ElabError> for Diagnostic {
+    fn from(e: ElabError) -> Diagnostic {
+        let diag = Diagnostic::error(e.span, e.to_string());
+        match e.kind {
+            ElabErrorKind::DuplicateLabel(s, _, first) => diag.message(first, format!("{} is first used here", s)),
+            ElabErrorKind::DuplicateConstructor(s, first) => {
+                diag.message(first, format!("{} is first declared here", s))
+            }
+            ElabErrorKind::Unbound(e) => (*e).into(),
+            ElabErrorKind::Recursive(e) => (*e).into(),
+            ElabErrorKind::Kind(e) => (*e).into(),
+            ElabErrorKind::Arity(e) => (*e).into(),
+            ElabErrorKind::Type(e) => (*e).into(),
+            ElabErrorKind::Module(e) => (*e).into(),
+            _ => diag,
+        }
+    }
 }
 
 /// Housekeeping, namespace methods
-impl<'s> ElaborationContext<'s> {
+impl ElaborationContext {
     pub fn new() -> Self {
         let mut ec = Self::default();
         let global_ns = Namespace::default();
@@ -53,20 +220,36 @@ impl<'s> ElaborationContext<'s> {
         ec
     }
 
-    pub fn elaborate(program: &'s Program) -> Result<Elaborated, ElabError> {
+    /// Elaborate each declaration of `program`. A declaration that cannot be
+    /// elaborated is reported, and the rest are elaborated without it
+    pub fn elaborate(program: &Program) -> Result<Elaborated, Vec<ElabError>> {
         let mut ec = Self::new();
-        let decls = ec.elab_program(program)?;
+        let mut decls = Vec::with_capacity(program.decls.len());
+        let mut errors = Vec::new();
+        for d in &program.decls {
+            match ec.elab_decl(d) {
+                Ok(id) => decls.push(id),
+                Err(e) => errors.push(e),
+            }
+            ec.dump();
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        ec.warnings.extend(unused_bindings(&program.decls));
         Ok(Elaborated {
             constructors: ec.constructors,
             elaborated: ec.elaborated,
             decls,
+            warnings: ec.warnings,
+            bindings: BindingMap::new(),
         })
     }
 
     /// Keep track of the type variable stack, while executing the combinator
     /// function `f` on `self`. Any stack growth is popped off after `f`
     /// returns.
-    fn with_tyvars<T, F: Fn(&mut ElaborationContext<'s>) -> T>(&mut self, f: F) -> T {
+    fn with_tyvars<T, F: Fn(&mut ElaborationContext) -> T>(&mut self, f: F) -> T {
         let n = self.tyvars.len();
         let r = f(self);
         let to_pop = self.tyvars.len() - n;
@@ -77,7 +260,7 @@ impl<'s> ElaborationContext<'s> {
     /// Keep track of the term variable stack, while executing the combinator
     /// function `f` on `self`. Any stack growth is popped off after `f`
     /// returns.
-    fn with_tmvars<T, F: Fn(&mut ElaborationContext<'s>) -> T>(&mut self, f: F) -> T {
+    fn with_tmvars<T, F: Fn(&mut ElaborationContext) -> T>(&mut self, f: F) -> T {
         let n = self.tmvars.len();
         let r = f(self);
         let to_pop = self.tmvars.len() - n;
@@ -88,6 +271,10 @@ impl<'s> ElaborationContext<'s> {
     fn elab_kind(&self, k: &Kind) -> hir::Kind {
         match k {
             Kind::Star => hir::Kind::Star,
+            Kind::Row => hir::Kind::Row,
+            // Kinds that were never inferred default to *, as they do in
+            // `KindContext::infer_kinds`
+            Kind::Infer | Kind::Var(_) => hir::Kind::Star,
             Kind::Arrow(k1, k2) => hir::Kind::Arrow(Box::new(self.elab_kind(k1)), Box::new(self.elab_kind(k2))),
         }
     }
@@ -98,14 +285,14 @@ impl<'s> ElaborationContext<'s> {
         id
     }
 
-    fn define_value(&mut self, name: String, expr: hir::Expr) -> HirId {
+    fn define_value(&mut self, name: Symbol, expr: hir::Expr) -> HirId {
         let id = self.allocate_hir_id();
         self.elaborated.insert(id, hir::Decl::Value(expr));
         self.namespaces[self.current].values.insert(name, id);
         id
     }
 
-    fn define_type(&mut self, name: String, ty: hir::Type) -> HirId {
+    fn define_type(&mut self, name: Symbol, ty: hir::Type) -> HirId {
         let id = self.allocate_hir_id();
         self.elaborated.insert(id, hir::Decl::Type(ty));
         self.namespaces[self.current].types.insert(name, id);
@@ -132,10 +319,10 @@ impl<'s> ElaborationContext<'s> {
 
     /// Starting from the current [`Namespace`], search for a bound name.
     /// If it's not found, then recursively search parent namespaces
-    fn lexical_value(&self, s: &str) -> Option<HirId> {
+    fn lexical_value(&self, s: Symbol) -> Option<HirId> {
         let mut ptr = &self.namespaces[self.current];
         loop {
-            match ptr.values.get(s) {
+            match ptr.values.get(&s) {
                 Some(idx) => return Some(*idx),
                 None => ptr = &self.namespaces[ptr.parent?],
             }
@@ -143,35 +330,67 @@ impl<'s> ElaborationContext<'s> {
     }
 
     /// Search for a variable bound in a temporary lexical scope (i.e. a function)
-    fn debruijn_value(&self, s: &str) -> Option<hir::Expr> {
+    fn debruijn_value(&self, s: Symbol) -> Option<hir::Expr> {
         self.tmvars
             .lookup(&s)
-            .map(|idx| hir::Expr::LocalVar(DeBruijn { idx, name: s.into() }))
+            .map(|idx| hir::Expr::LocalVar(DeBruijn { idx, name: s }))
     }
 
     /// Search for a value binding, starting with any temporary lambda captures,
     /// and then working upwards through top level definitions
-    fn lookup_value(&self, s: &str) -> Option<hir::Expr> {
+    fn lookup_value(&self, s: Symbol) -> Option<hir::Expr> {
         if let Some(db) = self.debruijn_value(s) {
             return Some(db);
         }
         self.lexical_value(s).map(hir::Expr::ProgramVar)
     }
 
-    fn lexical_type(&self, s: &str) -> Option<HirId> {
+    fn lexical_type(&self, s: Symbol) -> Option<HirId> {
         let mut ptr = &self.namespaces[self.current];
         loop {
-            match ptr.types.get(s) {
+            match ptr.types.get(&s) {
                 Some(idx) => return Some(*idx),
                 None => ptr = &self.namespaces[ptr.parent?],
             }
         }
     }
 
-    fn debruijn_type(&self, s: &str) -> Option<hir::Type> {
+    fn lexical_structure(&self, s: Symbol) -> Option<usize> {
+        let mut ptr = &self.namespaces[self.current];
+        loop {
+            match ptr.structures.get(&s) {
+                Some(idx) => return Some(*idx),
+                None => ptr = &self.namespaces[ptr.parent?],
+            }
+        }
+    }
+
+    /// Resolve the component `M.x` of a structure, looking it up in the
+    /// namespace of `M` with `get`, or reporting it with `undefined`
+    fn path<F>(
+        &self,
+        m: Symbol,
+        x: Symbol,
+        span: Span,
+        get: F,
+        undefined: fn(Symbol) -> ElabErrorKind,
+    ) -> Result<HirId, ElabError>
+    where
+        F: Fn(&Namespace, Symbol) -> Option<HirId>,
+    {
+        let ns = self
+            .lexical_structure(m)
+            .ok_or_else(|| ElabError::new(ElabErrorKind::UndefinedStructure(m), span))?;
+        get(&self.namespaces[ns], x)
+            .ok_or_else(|| ElabError::new(undefined(Symbol::intern(&format!("{}.{}", m, x))), span))
+    }
+
+    fn debruijn_type(&self, s: Symbol) -> Option<hir::Type> {
         self.tyvars
-            .lookup(&s)
-            .map(|idx| hir::Type::Var(DeBruijn { idx, name: s.into() }))
+            .iter()
+            .rev()
+            .position(|&t| t == s)
+            .map(|idx| hir::Type::Var(DeBruijn { idx, name: s }))
     }
 
     fn enter_namespace(&mut self) -> usize {
@@ -181,6 +400,7 @@ impl<'s> ElaborationContext<'s> {
             parent: Some(self.current),
             types: HashMap::new(),
             values: HashMap::new(),
+            structures: HashMap::new(),
         });
         self.current = id;
         id
@@ -195,7 +415,7 @@ impl<'s> ElaborationContext<'s> {
 
     /// Perform all bindings within `f` in a fresh [`Namespace`],
     /// and then return to the current one
-    fn with_new_namespace<T, F: Fn(&mut ElaborationContext<'s>) -> T>(&mut self, f: F) -> T {
+    fn with_new_namespace<T, F: Fn(&mut ElaborationContext) -> T>(&mut self, f: F) -> T {
         self.enter_namespace();
         let t = f(self);
         self.leave_namespace();
@@ -204,74 +424,44 @@ impl<'s> ElaborationContext<'s> {
 }
 
 /// Type elaboration
-impl<'s> ElaborationContext<'s> {
-    fn elab_ty_row(&mut self, row: &'s Row) -> Result<hir::Row, ElabError> {
-        Ok(hir::Row {
-            label: row.label.clone(),
-            ty: self.elab_type(&row.ty)?,
-        })
+impl ElaborationContext {
+    /// Bind the type variable `s`, warning if it shadows an enclosing binding
+    /// of the same name, as any references to the outer one in its scope
+    /// will silently refer to the inner one instead
+    fn bind_tyvar(&mut self, s: Symbol, span: Span) {
+        if self.tyvars.iter().any(|&t| t == s) {
+            self.warnings.push(Diagnostic::warn(
+                span,
+                format!("type variable '{} shadows an enclosing binding of the same name", s),
+            ));
+        }
+        self.tyvars.push(s);
     }
 
-    fn elab_ty_inner(&mut self, tv: &'s str, ty: &'s Type) -> Result<hir::Type, ElabError> {
-        self.with_tyvars(|f| {
-            f.tyvars.push(tv);
-            f.elab_type(ty)
-        })
+    fn bind_tyvars(&mut self, tyvars: &[Type]) {
+        for t in tyvars {
+            self.bind_tyvar(t.kind.as_tyvar(), t.span);
+        }
     }
 
-    fn elab_type(&mut self, ty: &'s Type) -> Result<hir::Type, ElabError> {
-        use TypeKind::*;
-        match &ty.kind {
-            Int => Ok(hir::Type::Int),
-            Bool => Ok(hir::Type::Bool),
-            Unit => Ok(hir::Type::Unit),
-            Infer => Ok(hir::Type::Infer),
-            Defined(s) => self
-                .lexical_type(s)
-                .map(hir::Type::Defined)
-                .ok_or_else(|| ElabError::UndefinedType(s.into(), ty.span)),
-            Variable(s) => self
-                .debruijn_type(s)
-                .ok_or_else(|| ElabError::UndefinedType(s.into(), ty.span)),
-            Function(ty1, ty2) => Ok(hir::Type::Arrow(
-                Box::new(self.elab_type(ty1)?),
-                Box::new(self.elab_type(ty2)?),
-            )),
-
-            // Sum types can only be constructed through a DeclKind::Datatype
-            // so it's okay to be unreachable!() here, as it would be a fatal
-            // bug if we reached this path somehow.
-            Sum(_) => unreachable!(),
-            Product(tys) => Ok(hir::Type::Product(
-                tys.iter().map(|t| self.elab_type(t)).collect::<Result<_, _>>()?,
-            )),
-            Record(rows) => Ok(hir::Type::Record(
-                rows.iter().map(|t| self.elab_ty_row(t)).collect::<Result<_, _>>()?,
-            )),
-            Existential(s, k, ty) => Ok(hir::Type::Existential(
-                Box::new(self.elab_kind(k)),
-                Box::new(self.elab_ty_inner(s, ty)?),
-            )),
-            Universal(s, k, ty) => Ok(hir::Type::Universal(
-                Box::new(self.elab_kind(k)),
-                Box::new(self.elab_ty_inner(s, ty)?),
-            )),
-            Abstraction(s, k, ty) => Ok(hir::Type::Abstraction(
-                Box::new(self.elab_kind(k)),
-                Box::new(self.elab_ty_inner(s, ty)?),
-            )),
-            Application(ty1, ty2) => Ok(hir::Type::Application(
-                Box::new(self.elab_type(ty1)?),
-                Box::new(self.elab_type(ty2)?),
-            )),
-            Recursive(ty) => self.elab_type(ty).map(|ty| hir::Type::Recursive(Box::new(ty))),
+    fn elab_type(&mut self, ty: &Type) -> Result<hir::Type, ElabError> {
+        let mut elab = TypeElaborator {
+            ctx: self,
+            out: Vec::new(),
+            span: ty.span,
+            error: None,
+        };
+        elab.visit_ty(ty);
+        match elab.error {
+            Some(e) => Err(e),
+            None => Ok(elab.out.pop().expect("internal error: no type elaborated")),
         }
     }
 }
 
 /// Expr elaboration
-impl<'s> ElaborationContext<'s> {
-    fn elab_let(&mut self, decls: &'s [Decl], expr: &'s Expr) -> Result<hir::Expr, ElabError> {
+impl ElaborationContext {
+    fn elab_let(&mut self, decls: &[Decl], expr: &Expr) -> Result<hir::Expr, ElabError> {
         self.with_new_namespace(|f| {
             for d in decls {
                 f.elab_decl(d)?;
@@ -280,22 +470,58 @@ impl<'s> ElaborationContext<'s> {
         })
     }
 
-    fn elab_arm(&mut self, arm: &'s Arm) -> Result<hir::Arm, ElabError> {
+    fn elab_arm(&mut self, arm: &Arm) -> Result<hir::Arm, ElabError> {
+        let pat = self.elab_pattern(&arm.pat, true)?;
+        let guard = arm.guard.as_ref().map(|e| self.elab_expr(e)).transpose()?;
         Ok(hir::Arm {
-            pat: self.elab_pattern(&arm.pat, true)?,
+            pat,
+            guard,
             expr: self.elab_expr(&arm.expr)?,
         })
     }
 
-    fn elab_case(&mut self, expr: &'s Expr, arms: &'s [Arm]) -> Result<hir::Expr, ElabError> {
+    fn elab_case(&mut self, expr: &Expr, arms: &[Arm], span: Span) -> Result<hir::Expr, ElabError> {
         let ex = self.elab_expr(expr)?;
-        let arms = arms.iter().map(|a| self.elab_arm(a)).collect::<Result<_, _>>()?;
-        Ok(hir::Expr::Case(Box::new(ex), arms))
+        let harms = arms.iter().map(|a| self.elab_arm(a)).collect::<Result<_, _>>()?;
+        let rows = arms
+            .iter()
+            .map(|arm| (std::slice::from_ref(&arm.pat), arm.guard.is_some(), arm.span));
+        self.check_coverage(rows, span);
+        Ok(hir::Expr::Case(Box::new(ex), harms))
     }
 
-    fn elab_field(&mut self, field: &'s Field) -> Result<hir::Field, ElabError> {
+    /// Warn about unreachable arms of a match, and values that none of
+    /// them match. Each arm is given as its patterns, whether it has a
+    /// guard, and its span
+    fn check_coverage<'p, I>(&mut self, arms: I, span: Span)
+    where
+        I: IntoIterator<Item = (&'p [Pattern], bool, Span)>,
+    {
+        let resolve = |s: Symbol| {
+            self.lexical_value(s)
+                .and_then(|id| self.constructors.get(&id))
+                .map(|c| Ctor::Variant(c.type_id, c.tag))
+        };
+        let arms = arms
+            .into_iter()
+            .map(|(pats, guarded, span)| {
+                let pats = pats
+                    .iter()
+                    .map(|p| self.signatures.lower(p, &resolve))
+                    .collect::<Option<_>>()?;
+                Some(coverage::Arm { pats, guarded, span })
+            })
+            .collect::<Option<Vec<_>>>()
+            .filter(|arms| arms.iter().all(|arm| arm.pats.len() == arms[0].pats.len()));
+        if let Some(arms) = arms {
+            let report = self.signatures.check(&arms);
+            self.warnings.extend(report.diagnostics(&arms, span));
+        }
+    }
+
+    fn elab_field(&mut self, field: &Field) -> Result<hir::Field, ElabError> {
         Ok(hir::Field {
-            label: field.label.clone(),
+            label: field.label,
             expr: self.elab_expr(&field.expr)?,
         })
     }
@@ -303,13 +529,13 @@ impl<'s> ElaborationContext<'s> {
     /// We desugar to a case expression
     /// fn (Some x) => x + 1
     /// fn $x : Infer option => case $x of (Some x) => x + 1
-    fn elab_abs(&mut self, pat: &'s Pattern, body: &'s Expr) -> Result<hir::Expr, ElabError> {
+    fn elab_abs(&mut self, pat: &Pattern, body: &Expr) -> Result<hir::Expr, ElabError> {
         // Wow we have a lot of bindings
         self.with_tmvars(|f| {
             let pat = f.elab_pattern(pat, true)?;
             let expr = f.elab_expr(body)?;
             let ty = f.naive_type_infer(&pat)?;
-            let arm = hir::Arm { pat, expr };
+            let arm = hir::Arm { pat, guard: None, expr };
             let dummy = hir::Expr::LocalVar(DeBruijn {
                 name: "$anon".into(),
                 idx: 0,
@@ -319,32 +545,39 @@ impl<'s> ElaborationContext<'s> {
         })
     }
 
-    fn elab_expr(&mut self, expr: &'s Expr) -> Result<hir::Expr, ElabError> {
+    fn elab_expr(&mut self, expr: &Expr) -> Result<hir::Expr, ElabError> {
         use ExprKind::*;
         match &expr.kind {
             Unit => Ok(hir::Expr::Unit),
             Int(i) => Ok(hir::Expr::Int(*i)),
             Var(s) => self
-                .lookup_value(s)
-                .ok_or_else(|| ElabError::UndefinedValue(s.into(), expr.span)),
-            Constr(s) => self
-                .lexical_value(s)
-                .map(|id| self.constructors.get(&id))
-                .flatten()
-                .map(|c| hir::Expr::Constr(c.type_id, c.tag))
-                .ok_or_else(|| ElabError::UndefinedConstr(s.into(), expr.span)),
+                .lookup_value(*s)
+                .ok_or_else(|| ElabError::new(ElabErrorKind::UndefinedValue(*s), expr.span)),
+            Path(m, x) => self
+                .path(
+                    *m,
+                    *x,
+                    expr.span,
+                    |ns, x| ns.values.get(&x).copied(),
+                    ElabErrorKind::UndefinedValue,
+                )
+                .map(hir::Expr::ProgramVar),
+            Constr(s) => self.elab_constructor_value(*s, None, expr.span),
             If(e1, e2, e3) => Ok(hir::Expr::If(
                 Box::new(self.elab_expr(e1)?),
                 Box::new(self.elab_expr(e2)?),
                 Box::new(self.elab_expr(e3)?),
             )),
             Abs(pat, expr) => self.elab_abs(pat, expr),
-            App(e1, e2) => Ok(hir::Expr::App(
-                Box::new(self.elab_expr(e1)?),
-                Box::new(self.elab_expr(e2)?),
-            )),
+            App(e1, e2) => {
+                let func = match &e1.kind {
+                    Constr(s) => self.elab_constructor_value(*s, Some(e2), e1.span)?,
+                    _ => self.elab_expr(e1)?,
+                };
+                Ok(hir::Expr::App(Box::new(func), Box::new(self.elab_expr(e2)?)))
+            }
             TyAbs(s, k, e) => self.with_tyvars(|f| {
-                f.tyvars.push(s);
+                f.bind_tyvar(*s, expr.span);
                 let e = f.elab_expr(e)?;
                 Ok(hir::Expr::TyAbs(Box::new(f.elab_kind(k)), Box::new(e)))
             }),
@@ -363,21 +596,37 @@ impl<'s> ElaborationContext<'s> {
                 .collect::<Result<_, _>>()
                 .map(hir::Expr::Tuple),
             Projection(e1, e2) => match &e2.kind {
-                ExprKind::Var(label) => Ok(hir::Expr::RecordProj(Box::new(self.elab_expr(e1)?), label.clone())),
+                ExprKind::Var(label) => Ok(hir::Expr::RecordProj(Box::new(self.elab_expr(e1)?), *label)),
                 ExprKind::Int(idx) => Ok(hir::Expr::TupleProj(Box::new(self.elab_expr(e1)?), *idx)),
-                _ => Err(ElabError::InvalidBinding(
-                    format!("attempt to project using {:?}", e2),
+                _ => Err(ElabError::new(
+                    ElabErrorKind::InvalidBinding(format!("attempt to project using {:?}", e2)),
                     expr.span,
                 )),
             },
-            Case(e, arms) => self.elab_case(e, arms),
+            Case(e, arms) => self.elab_case(e, arms, expr.span),
             Let(decls, e) => self.elab_let(decls, e),
+            Pack(witness, e, sig) => Ok(hir::Expr::Pack(
+                Box::new(self.elab_type(witness)?),
+                Box::new(self.elab_expr(e)?),
+                Box::new(self.elab_type(sig)?),
+            )),
+            Open(package, tyvar, var, body) => {
+                let package = self.elab_expr(package)?;
+                let body = self.with_tyvars(|f| {
+                    f.bind_tyvar(*tyvar, expr.span);
+                    f.with_tmvars(|f| {
+                        f.tmvars.push(*var);
+                        f.elab_expr(body)
+                    })
+                })?;
+                Ok(hir::Expr::Unpack(Box::new(package), Box::new(body)))
+            }
         }
     }
 }
 
 /// Pattern elaboration
-impl<'s> ElaborationContext<'s> {
+impl ElaborationContext {
     fn naive_type_infer(&self, pat: &hir::Pattern) -> Result<hir::Type, ElabError> {
         use hir::Pattern::*;
         match pat {
@@ -404,10 +653,11 @@ impl<'s> ElaborationContext<'s> {
             Record(s) => Ok(hir::Type::Record(
                 s.into_iter()
                     .map(|s| hir::Row {
-                        label: s.clone(),
+                        label: *s,
                         ty: hir::Type::Infer,
                     })
                     .collect(),
+                None,
             )),
             Application(id, arg) => {
                 let con = self.constructors.get(&id).expect("internal error");
@@ -417,18 +667,20 @@ impl<'s> ElaborationContext<'s> {
                     .map(|ty| hir::Type::Application(Box::new(cty), Box::new(ty)))
             }
             Variable(_) => Ok(hir::Type::Infer),
+            Or(alts) => self.naive_type_infer(&alts[0]),
+            As(_, pat) => self.naive_type_infer(pat),
         }
     }
-    fn elab_pattern(&mut self, pat: &'s Pattern, bind: bool) -> Result<hir::Pattern, ElabError> {
+    fn elab_pattern(&mut self, pat: &Pattern, bind: bool) -> Result<hir::Pattern, ElabError> {
         match &pat.kind {
             PatKind::Any => Ok(hir::Pattern::Any),
             PatKind::Unit => Ok(hir::Pattern::Unit),
             PatKind::Literal(i) => Ok(hir::Pattern::Literal(*i)),
             PatKind::Variable(s) => {
                 if bind {
-                    self.tmvars.push(s);
+                    self.tmvars.push(*s);
                 }
-                Ok(hir::Pattern::Variable(s.clone()))
+                Ok(hir::Pattern::Variable(*s))
             }
             PatKind::Product(sub) => sub
                 .iter()
@@ -436,13 +688,44 @@ impl<'s> ElaborationContext<'s> {
                 .collect::<Result<_, _>>()
                 .map(hir::Pattern::Product),
             PatKind::Record(sub) => Ok(hir::Pattern::Record(sub.clone())),
+            PatKind::Or(alts) => {
+                // Only the first alternative binds, since the others must
+                // bind the same variables
+                let bound = |p| {
+                    let mut names = DeclNames::default();
+                    names.visit_pat(p);
+                    names.values.sort_unstable();
+                    names.values
+                };
+                let first = bound(&alts[0]);
+                if let Some(alt) = alts[1..].iter().find(|p| bound(p) != first) {
+                    return Err(ElabError::new(
+                        ElabErrorKind::InvalidBinding(
+                            "every alternative of an or-pattern must bind the same variables".into(),
+                        ),
+                        alt.span,
+                    ));
+                }
+                let mut v = Vec::with_capacity(alts.len());
+                for (i, alt) in alts.iter().enumerate() {
+                    v.push(self.elab_pattern(alt, bind && i == 0)?);
+                }
+                Ok(hir::Pattern::Or(v))
+            }
+            PatKind::As(s, pat) => {
+                if bind {
+                    self.tmvars.push(*s);
+                }
+                let pat = self.elab_pattern(pat, bind)?;
+                Ok(hir::Pattern::As(*s, Box::new(pat)))
+            }
             PatKind::Ascribe(pat, ty) => Ok(hir::Pattern::Ascribe(
                 Box::new(self.elab_pattern(pat, bind)?),
                 Box::new(self.elab_type(ty)?),
             )),
             PatKind::Constructor(s) => self
-                .lexical_value(s)
-                .ok_or_else(|| ElabError::UndefinedConstr(s.clone(), pat.span))
+                .lexical_value(*s)
+                .ok_or_else(|| ElabError::new(ElabErrorKind::UndefinedConstr(*s), pat.span))
                 .map(hir::Pattern::Constructor),
             PatKind::Application(con, arg) => {
                 let econ = self.elab_pattern(con, bind)?;
@@ -452,19 +735,25 @@ impl<'s> ElaborationContext<'s> {
                         let con_info = self.constructors.get(&id).unwrap();
                         if !con_info.arity {
                             let name = match &con.as_ref().kind {
-                                PatKind::Constructor(s) => s.clone(),
+                                PatKind::Constructor(s) => *s,
                                 _ => panic!("interal error!"),
                             };
-                            return Err(ElabError::InvalidBinding(
-                                format!("constructor {} doesn't accept arguments!", name),
+                            return Err(ElabError::new(
+                                ElabErrorKind::InvalidBinding(format!(
+                                    "constructor {} doesn't accept arguments!",
+                                    name
+                                )),
                                 pat.span,
                             ));
                         }
                         id
                     }
                     _ => {
-                        return Err(ElabError::InvalidBinding(
-                            format!("cannot apply {:?} to non-constructor {:?}", arg, con),
+                        return Err(ElabError::new(
+                            ElabErrorKind::InvalidBinding(format!(
+                                "cannot apply {:?} to non-constructor {:?}",
+                                arg, con
+                            )),
                             pat.span,
                         ))
                     }
@@ -476,21 +765,31 @@ impl<'s> ElaborationContext<'s> {
 }
 
 /// Decl elaboration
-impl<'s> ElaborationContext<'s> {
-    fn elab_decl_type(&mut self, tyvars: &'s [Type], name: &'s str, ty: &'s Type) -> Result<HirId, ElabError> {
-        let ty = self.elab_type(ty)?;
-        let ty = tyvars.iter().fold(ty, |ty, var| {
+impl ElaborationContext {
+    /// Wrap `ty` in a type abstraction for each of the declaration's type
+    /// parameters. The last parameter is bound innermost, matching the
+    /// de Bruijn indices it was given in `ty`
+    fn abstract_tyvars(tyvars: &[Type], ty: hir::Type) -> hir::Type {
+        tyvars.iter().rev().fold(ty, |ty, _| {
             hir::Type::Abstraction(Box::new(hir::Kind::Star), Box::new(ty))
-        });
-        Ok(self.define_type(name.into(), ty))
+        })
+    }
+
+    fn elab_decl_type(&mut self, tyvars: &[Type], name: Symbol, ty: &Type) -> Result<HirId, ElabError> {
+        let ty = self.with_tyvars(|f| {
+            f.bind_tyvars(tyvars);
+            f.elab_type(ty)
+        })?;
+        Ok(self.define_type(name, Self::abstract_tyvars(tyvars, ty)))
     }
 
     fn elab_constructor(
         &mut self,
-        name: &'s str,
+        name: Symbol,
         tag: usize,
         tyvar_arity: usize,
         type_signature: Option<&hir::Type>,
+        ty: hir::Type,
         type_id: HirId,
     ) -> HirId {
         let expr = match type_signature {
@@ -499,7 +798,7 @@ impl<'s> ElaborationContext<'s> {
                 Box::new(hir::Expr::App(
                     Box::new(hir::Expr::Constr(type_id, tag)),
                     Box::new(hir::Expr::LocalVar(DeBruijn {
-                        name: String::from("x"),
+                        name: Symbol::intern("x"),
                         idx: 0,
                     })),
                 )),
@@ -511,7 +810,17 @@ impl<'s> ElaborationContext<'s> {
 
         let arity = type_signature.is_some();
 
-        let con_id = self.define_value(name.into(), expr);
+        let con_id = self.define_value(name, expr);
+        let curried = match type_signature {
+            Some(hir::Type::Product(tys)) if tys.len() > 1 => {
+                let id = self.allocate_hir_id();
+                let expr = Self::curried_constructor(tys, type_id, tag);
+                let expr = (0..tyvar_arity).fold(expr, |e, _| hir::Expr::TyAbs(Box::new(hir::Kind::Star), Box::new(e)));
+                self.elaborated.insert(id, hir::Decl::Value(expr));
+                Some(id)
+            }
+            _ => None,
+        };
         self.constructors.insert(
             con_id,
             Constructor {
@@ -520,52 +829,93 @@ impl<'s> ElaborationContext<'s> {
                 tag,
                 arity,
                 type_arity: tyvar_arity as u8,
+                ty,
+                curried,
             },
         );
         con_id
     }
 
-    fn elab_decl_datatype(&mut self, tyvars: &'s [Type], name: &'s str, ty: &'s Type) -> Result<HirId, ElabError> {
-        // Quickly collection all names that this type points to
-        let mut coll = TyNameCollector::default();
-        coll.visit_ty(&ty);
-        let is_recur = coll.definitions.contains(name);
-
-        // Insert first, so we can be recursive if we need to
-        let id = self.allocate_hir_id();
-        self.namespaces[self.current].types.insert(name.into(), id);
+    /// The curried form of a constructor taking a tuple of `fields`, one
+    /// function for each field, applying the constructor to the tuple of
+    /// their parameters
+    fn curried_constructor(fields: &[hir::Type], type_id: HirId, tag: usize) -> hir::Expr {
+        let n = fields.len();
+        let args = (0..n)
+            .map(|i| {
+                hir::Expr::LocalVar(DeBruijn {
+                    name: Symbol::intern(&format!("x{}", i)),
+                    idx: n - 1 - i,
+                })
+            })
+            .collect();
+        let body = hir::Expr::App(
+            Box::new(hir::Expr::Constr(type_id, tag)),
+            Box::new(hir::Expr::Tuple(args)),
+        );
+        fields
+            .iter()
+            .rev()
+            .fold(body, |e, ty| hir::Expr::Abs(Box::new(ty.clone()), Box::new(e)))
+    }
 
-        // We just do all of this inside of the closure, rather than delegrating
-        // to visit_sum, because we need access to both `tyvars` for generating
-        // value-bindings for the constructors
-        let ty = self.with_tyvars(|f| {
-            f.tyvars.extend(tyvars.iter().map(|t| t.kind.as_tyvar()));
+    /// A constructor used as a value, or applied to `arg`: the curried form
+    /// of one taking a tuple, unless `arg` is a tuple of the right length
+    fn elab_constructor_value(&mut self, s: Symbol, arg: Option<&Expr>, span: Span) -> Result<hir::Expr, ElabError> {
+        let c = self
+            .lexical_value(s)
+            .and_then(|id| self.constructors.get(&id))
+            .ok_or_else(|| ElabError::new(ElabErrorKind::UndefinedConstr(s), span))?;
+        let curried = match c.curried {
+            Some(id) => id,
+            None => return Ok(hir::Expr::Constr(c.type_id, c.tag)),
+        };
+        match (
+            Self::constructor_payload(&c.ty, c.type_arity as usize),
+            arg.map(|e| &e.kind),
+        ) {
+            (hir::Type::Product(tys), Some(ExprKind::Tuple(exprs))) if tys.len() == exprs.len() => {
+                Ok(hir::Expr::Constr(c.type_id, c.tag))
+            }
+            _ => Ok(hir::Expr::ProgramVar(curried)),
+        }
+    }
 
-            let mut elab = Vec::new();
-            for (idx, v) in ty.kind.variants().iter().enumerate() {
-                let ty = v.ty.as_ref().map(|ty| f.elab_type(ty)).transpose()?;
+    /// The argument type of a constructor of type `forall ... of arg -> t`
+    fn constructor_payload(ty: &hir::Type, tyvar_arity: usize) -> hir::Type {
+        match ty {
+            hir::Type::Universal(_, ty) if tyvar_arity > 0 => Self::constructor_payload(ty, tyvar_arity - 1),
+            hir::Type::Arrow(payload, _) => *payload.clone(),
+            _ => panic!("internal error: constructor {:?} takes no argument", ty),
+        }
+    }
 
-                // Generate a function or constant value for the constructor
-                f.elab_constructor(&v.label, idx, tyvars.len(), ty.as_ref(), id);
-                elab.push(hir::Variant {
-                    label: v.label.clone(),
-                    ty,
-                });
-            }
-            Ok(hir::Type::Sum(elab))
-        })?;
+    /// See [`desugar`] for how a datatype is turned into a type abbreviation
+    /// and the types of its constructors
+    fn elab_decl_datatype(&mut self, tyvars: &[Type], name: Symbol, ty: &Type) -> Result<HirId, ElabError> {
+        let abbrev = self.elab_type(&desugar::datatype(tyvars, name, ty))?;
+        let id = self.define_type(name, abbrev);
+        self.signatures.declare(
+            id,
+            ty.kind
+                .variants()
+                .iter()
+                .map(|v| (v.label.to_string(), v.ty.is_some()))
+                .collect(),
+        );
 
-        // We have the raw sum type, so now wrap it in type abstractions
-        let ty = tyvars.iter().fold(ty, |ty, var| {
-            hir::Type::Abstraction(Box::new(hir::Kind::Star), Box::new(ty))
-        });
-        let ty = if is_recur {
-            hir::Type::Recursive(Box::new(ty))
-        } else {
-            ty
-        };
+        // Shadowed type parameters have already been reported for the
+        // abbreviation, so don't warn about them again for each constructor
+        let warnings = self.warnings.len();
+        let cons = desugar::constructors(tyvars, name, ty);
+        for (tag, ((label, con_ty), v)) in cons.iter().zip(ty.kind.variants()).enumerate() {
+            let con_ty = self.elab_type(con_ty)?;
+            let payload = v.ty.as_ref().map(|_| Self::constructor_payload(&con_ty, tyvars.len()));
 
-        self.elaborated.insert(id, hir::Decl::Type(ty));
+            // Generate a function or constant value for the constructor
+            self.elab_constructor(*label, tag, tyvars.len(), payload.as_ref(), con_ty, id);
+        }
+        self.warnings.truncate(warnings);
         Ok(id)
     }
 
@@ -579,13 +929,13 @@ impl<'s> ElaborationContext<'s> {
     ) -> Result<HirId, ElabError> {
         use hir::Pattern::*;
         match pat {
-            Any | Unit => Ok(self.define_value(String::default(), expr)),
+            Any | Unit => Ok(self.define_value(Symbol::default(), expr)),
             Variable(s) => Ok(self.define_value(s, expr)),
             Product(sub) => {
                 // No need for extra redirection
                 let id = match expr {
                     hir::Expr::ProgramVar(id) => id,
-                    _ => self.define_value(String::default(), expr),
+                    _ => self.define_value(Symbol::default(), expr),
                 };
 
                 let base = Box::new(hir::Expr::ProgramVar(id));
@@ -597,7 +947,7 @@ impl<'s> ElaborationContext<'s> {
             Record(sub) => {
                 let id = match expr {
                     hir::Expr::ProgramVar(id) => id,
-                    _ => self.define_value(String::default(), expr),
+                    _ => self.define_value(Symbol::default(), expr),
                 };
                 let base = Box::new(hir::Expr::ProgramVar(id));
 
@@ -607,8 +957,13 @@ impl<'s> ElaborationContext<'s> {
                 Ok(id)
             }
             Ascribe(pat, _) => self.deconstruct_pat_binding(*pat, expr, span),
-            Constructor(_) => Err(ElabError::InvalidBinding(
-                format!("cannot bind constructor to a value!"),
+            As(s, pat) => {
+                let id = self.define_value(s, expr);
+                self.deconstruct_pat_binding(*pat, hir::Expr::ProgramVar(id), span)?;
+                Ok(id)
+            }
+            Constructor(_) => Err(ElabError::new(
+                ElabErrorKind::InvalidBinding(format!("cannot bind constructor to a value!")),
                 span,
             )),
             Application(con, arg) => {
@@ -628,16 +983,20 @@ impl<'s> ElaborationContext<'s> {
                 let e = hir::Expr::ProgramVar(id);
                 self.deconstruct_pat_binding(*arg, e, span)
             }
-            Literal(_) => Err(ElabError::InvalidBinding(
-                format!("cannot bind a literal pattern to a value!"),
+            Literal(_) => Err(ElabError::new(
+                ElabErrorKind::InvalidBinding(format!("cannot bind a literal pattern to a value!")),
+                span,
+            )),
+            Or(_) => Err(ElabError::new(
+                ElabErrorKind::InvalidBinding("cannot bind an or-pattern to a value!".into()),
                 span,
             )),
         }
     }
 
-    fn elab_decl_value(&mut self, tyvars: &'s [Type], pat: &'s Pattern, expr: &'s Expr) -> Result<HirId, ElabError> {
+    fn elab_decl_value(&mut self, tyvars: &[Type], pat: &Pattern, expr: &Expr) -> Result<HirId, ElabError> {
         self.with_tyvars(|f| {
-            f.tyvars.extend(tyvars.iter().map(|t| t.kind.as_tyvar()));
+            f.bind_tyvars(tyvars);
             f.with_tmvars(|f| {
                 let sp = pat.span;
                 let pat = f.elab_pattern(pat, false)?;
@@ -647,7 +1006,7 @@ impl<'s> ElaborationContext<'s> {
         })
     }
 
-    fn build_pat_matrix(&mut self, arms: &'s [FnArm]) -> Result<PatternMatrix, ElabError> {
+    fn build_pat_matrix(&mut self, arms: &[FnArm]) -> Result<PatternMatrix, ElabError> {
         let rows = arms.len();
         let mut pats: Vec<Vec<hir::Pattern>> = Vec::with_capacity(rows);
         let mut exprs = Vec::with_capacity(rows);
@@ -714,13 +1073,16 @@ impl<'s> ElaborationContext<'s> {
             .collect()
     }
 
-    fn elab_decl_fun(&mut self, tyvars: &'s [Type], name: &'s str, arms: &'s [FnArm]) -> Result<HirId, ElabError> {
+    fn elab_decl_fun(&mut self, tyvars: &[Type], name: Symbol, arms: &[FnArm]) -> Result<HirId, ElabError> {
         self.with_tyvars(|f| {
-            f.tyvars.extend(tyvars.iter().map(|t| t.kind.as_tyvar()));
+            f.bind_tyvars(tyvars);
             f.with_tmvars(|f| {
                 f.tmvars.push(name);
 
                 let matrix = f.build_pat_matrix(arms)?;
+                let rows = arms.iter().map(|arm| (arm.pats.as_slice(), false, arm.span));
+                let span = arms.iter().fold(arms[0].span, |sp, arm| sp + arm.span);
+                f.check_coverage(rows, span);
                 let tys = f.infer_type_matrix(&matrix)?;
                 let tys = Self::try_unify_type_matrix(tys);
 
@@ -730,7 +1092,7 @@ impl<'s> ElaborationContext<'s> {
                         .rev()
                         .map(|idx| {
                             hir::Expr::LocalVar(DeBruijn {
-                                name: String::new(),
+                                name: Symbol::default(),
                                 idx,
                             })
                         })
@@ -752,17 +1114,30 @@ impl<'s> ElaborationContext<'s> {
                 // );
                 // let fun = hir::Expr::Fix(Box::new(fun));
 
-                Ok(f.define_value(name.into(), fun))
+                Ok(f.define_value(name, fun))
             })
         })
     }
 
-    fn elab_decl_expr(&mut self, expr: &'s Expr) -> Result<HirId, ElabError> {
+    fn elab_decl_expr(&mut self, expr: &Expr) -> Result<HirId, ElabError> {
         let e = self.elab_expr(expr)?;
-        Ok(self.define_value(String::default(), e))
+        Ok(self.define_value(Symbol::default(), e))
     }
 
-    fn elab_decl_and(&mut self, a: &'s Decl, b: &'s Decl) -> Result<HirId, ElabError> {
+    /// The components of a structure are elaborated in a namespace of their
+    /// own, which paths look into. Neither a structure nor its signature is
+    /// anything more than its components after elaboration, since matching
+    /// against the signature is checked by inference
+    fn elab_decl_structure(&mut self, name: Symbol, decls: &[Decl]) -> Result<HirId, ElabError> {
+        let ns = self.enter_namespace();
+        let result = decls.iter().try_for_each(|d| self.elab_decl(d).map(drop));
+        self.leave_namespace();
+        result?;
+        self.namespaces[self.current].structures.insert(name, ns);
+        Ok(self.allocate_hir_id())
+    }
+
+    fn elab_decl_and(&mut self, a: &Decl, b: &Decl) -> Result<HirId, ElabError> {
         let mut names = DeclNames::default();
         names.visit_decl(a);
         names.visit_decl(b);
@@ -770,24 +1145,26 @@ impl<'s> ElaborationContext<'s> {
         for name in names.values {
             // Insert first, so we can be recursive if we need to
             let id = self.allocate_hir_id();
-            self.namespaces[self.current].values.insert(name.into(), id);
+            self.namespaces[self.current].values.insert(name, id);
         }
 
         unimplemented!()
     }
 
-    fn elab_decl(&mut self, decl: &'s Decl) -> Result<HirId, ElabError> {
+    fn elab_decl(&mut self, decl: &Decl) -> Result<HirId, ElabError> {
         match &decl.kind {
-            DeclKind::Datatype(tyvars, name, ty) => self.elab_decl_datatype(tyvars, name, ty),
-            DeclKind::Type(tyvars, name, ty) => self.elab_decl_type(tyvars, name, ty),
+            DeclKind::Datatype(tyvars, name, ty) => self.elab_decl_datatype(tyvars, *name, ty),
+            DeclKind::Type(tyvars, name, ty) => self.elab_decl_type(tyvars, *name, ty),
             DeclKind::Value(tyvars, pat, expr) => self.elab_decl_value(tyvars, pat, expr),
             DeclKind::And(d1, d2) => unimplemented!(),
-            DeclKind::Function(tyvars, name, arms) => self.elab_decl_fun(tyvars, name, arms),
+            DeclKind::Function(tyvars, name, arms) => self.elab_decl_fun(tyvars, *name, arms),
             DeclKind::Expr(e) => self.elab_decl_expr(e),
+            DeclKind::Signature(..) => Ok(self.allocate_hir_id()),
+            DeclKind::Structure(name, _, decls) => self.elab_decl_structure(*name, decls),
         }
     }
 
-    pub fn elab_program(&mut self, prog: &'s Program) -> Result<Vec<HirId>, ElabError> {
+    pub fn elab_program(&mut self, prog: &Program) -> Result<Vec<HirId>, ElabError> {
         let mut v = Vec::with_capacity(prog.decls.len());
         for d in &prog.decls {
             v.push(self.elab_decl(d)?);
@@ -811,6 +1188,7 @@ impl PatternMatrix {
         for (pat, expr) in self.pats.into_iter().zip(self.exprs.into_iter()) {
             arms.push(hir::Arm {
                 pat: hir::Pattern::Product(pat),
+                guard: None,
                 expr,
             });
         }
@@ -821,15 +1199,15 @@ impl PatternMatrix {
 /// Helper struct for walking top-level declarations and extracting
 /// bound type and value names. This does no validation or checking
 #[derive(Default)]
-struct DeclNames<'s> {
-    values: Vec<&'s str>,
-    types: Vec<&'s str>,
+struct DeclNames {
+    values: Vec<Symbol>,
+    types: Vec<Symbol>,
 }
 
-impl<'s> DeclNames<'s> {
-    fn visit_pat(&mut self, pat: &'s Pattern) {
+impl DeclNames {
+    fn visit_pat(&mut self, pat: &Pattern) {
         match &pat.kind {
-            PatKind::Variable(s) => self.values.push(&s),
+            PatKind::Variable(s) => self.values.push(*s),
             PatKind::Product(sub) => {
                 for p in sub {
                     self.visit_pat(p);
@@ -837,43 +1215,507 @@ impl<'s> DeclNames<'s> {
             }
             PatKind::Record(sub) => {
                 for p in sub {
-                    self.values.push(p);
+                    self.values.push(*p);
                 }
             }
             PatKind::Ascribe(pat, ty) => self.visit_pat(&pat),
             PatKind::Application(con, arg) => self.visit_pat(&arg),
+            PatKind::Or(alts) => self.visit_pat(&alts[0]),
+            PatKind::As(s, pat) => {
+                self.values.push(*s);
+                self.visit_pat(pat);
+            }
             _ => {}
         }
     }
 
-    fn visit_decl(&mut self, d: &'s Decl) {
+    fn visit_decl(&mut self, d: &Decl) {
         match &d.kind {
-            DeclKind::Datatype(_, name, ty) => self.types.push(&name),
-            DeclKind::Type(_, name, ty) => self.types.push(&name),
+            DeclKind::Datatype(_, name, ty) => self.types.push(*name),
+            DeclKind::Type(_, name, ty) => self.types.push(*name),
             DeclKind::Value(_, pat, expr) => self.visit_pat(pat),
             DeclKind::And(d1, d2) => {
                 self.visit_decl(d1);
                 self.visit_decl(d2);
             }
-            DeclKind::Function(_, name, arms) => self.values.push(&name),
+            DeclKind::Function(_, name, arms) => self.values.push(*name),
             _ => {}
         }
     }
 }
 
-/// Collect type variables and references to defined names
-#[derive(Default, Debug, Clone)]
-pub struct TyNameCollector<'s> {
-    pub tyvars: HashSet<&'s str>,
-    pub definitions: HashSet<&'s str>,
+/// Convert a [`Type`] to a [`hir::Type`], resolving defined names to the
+/// [`HirId`] of their declaration, and type variables to de Bruijn indices.
+/// Each visit leaves exactly one converted type on `out`, so compound types
+/// are built by popping the results of visiting their components
+struct TypeElaborator<'a> {
+    ctx: &'a mut ElaborationContext,
+    out: Vec<hir::Type>,
+    /// Span of the type currently being visited
+    span: Span,
+    /// The first name that could not be resolved
+    error: Option<ElabError>,
 }
 
-impl<'s> TypeVisitor<'s> for TyNameCollector<'s> {
-    fn visit_variable(&mut self, s: &'s str) {
-        self.tyvars.insert(s);
+impl<'a> TypeElaborator<'a> {
+    fn fail(&mut self, e: ElabError) {
+        if self.error.is_none() {
+            self.error = Some(e);
+        }
+        self.out.push(hir::Type::Error);
+    }
+
+    fn pop(&mut self) -> Box<hir::Type> {
+        Box::new(self.out.pop().expect("internal error: no type elaborated"))
+    }
+
+    fn pop_n(&mut self, n: usize) -> Vec<hir::Type> {
+        self.out.split_off(self.out.len() - n)
+    }
+
+    /// Visit `ty` in the scope of a binding for type variable `s`
+    fn bind(&mut self, s: Symbol, ty: &Type) -> Box<hir::Type> {
+        let n = self.ctx.tyvars.len();
+        self.ctx.bind_tyvar(s, self.span);
+        self.visit_ty(ty);
+        self.ctx.tyvars.popn(self.ctx.tyvars.len() - n);
+        self.pop()
+    }
+}
+
+impl<'a, 't> TypeVisitor<'t> for TypeElaborator<'a> {
+    fn visit_defined(&mut self, s: Symbol) {
+        match self.ctx.lexical_type(s) {
+            Some(id) => self.out.push(hir::Type::Defined(id)),
+            None => self.fail(ElabError::new(ElabErrorKind::UndefinedType(s), self.span)),
+        }
+    }
+
+    fn visit_path(&mut self, m: Symbol, s: Symbol) {
+        let get = |ns: &Namespace, s: Symbol| ns.types.get(&s).copied();
+        match self.ctx.path(m, s, self.span, get, ElabErrorKind::UndefinedType) {
+            Ok(id) => self.out.push(hir::Type::Defined(id)),
+            Err(e) => self.fail(e),
+        }
+    }
+
+    fn visit_variable(&mut self, s: Symbol) {
+        match self.ctx.debruijn_type(s) {
+            Some(ty) => self.out.push(ty),
+            None => self.fail(ElabError::new(ElabErrorKind::UnboundTypeVar(s), self.span)),
+        }
+    }
+
+    fn visit_function(&mut self, ty1: &'t Type, ty2: &'t Type) {
+        self.visit_ty(ty1);
+        self.visit_ty(ty2);
+        let ty2 = self.pop();
+        let ty1 = self.pop();
+        self.out.push(hir::Type::Arrow(ty1, ty2));
+    }
+
+    fn visit_application(&mut self, ty1: &'t Type, ty2: &'t Type) {
+        self.visit_ty(ty1);
+        self.visit_ty(ty2);
+        let ty2 = self.pop();
+        let ty1 = self.pop();
+        self.out.push(hir::Type::Application(ty1, ty2));
+    }
+
+    fn visit_sum(&mut self, variants: &'t [Variant]) {
+        let mut sum = Vec::with_capacity(variants.len());
+        for v in variants {
+            let ty = v.ty.as_ref().map(|ty| {
+                self.visit_ty(ty);
+                *self.pop()
+            });
+            sum.push(hir::Variant { label: v.label, ty });
+        }
+        self.out.push(hir::Type::Sum(sum));
+    }
+
+    fn visit_product(&mut self, tys: &'t [Type]) {
+        for ty in tys {
+            self.visit_ty(ty);
+        }
+        let tys = self.pop_n(tys.len());
+        self.out.push(hir::Type::Product(tys));
+    }
+
+    fn visit_record(&mut self, rows: &'t [Row], tail: Option<&'t RowVar>) {
+        for row in rows {
+            self.visit_ty(&row.ty);
+        }
+        if let Some(var) = tail {
+            self.span = var.span;
+            self.visit_variable(var.name);
+        }
+        let tail = tail.map(|_| self.pop());
+        let tys = self.pop_n(rows.len());
+        let rows = rows
+            .iter()
+            .zip(tys)
+            .map(|(row, ty)| hir::Row { label: row.label, ty })
+            .collect();
+        self.out.push(hir::Type::Record(rows, tail));
+    }
+
+    fn visit_existential(&mut self, s: Symbol, k: &'t Kind, ty: &'t Type) {
+        let ty = self.bind(s, ty);
+        let k = Box::new(self.ctx.elab_kind(k));
+        self.out.push(hir::Type::Existential(k, ty));
+    }
+
+    fn visit_universal(&mut self, s: Symbol, k: &'t Kind, ty: &'t Type) {
+        let ty = self.bind(s, ty);
+        let k = Box::new(self.ctx.elab_kind(k));
+        self.out.push(hir::Type::Universal(k, ty));
+    }
+
+    fn visit_abstraction(&mut self, s: Symbol, k: &'t Kind, ty: &'t Type) {
+        let ty = self.bind(s, ty);
+        let k = Box::new(self.ctx.elab_kind(k));
+        self.out.push(hir::Type::Abstraction(k, ty));
+    }
+
+    fn visit_recursive(&mut self, ty: &'t Type) {
+        self.visit_ty(ty);
+        let ty = self.pop();
+        self.out.push(hir::Type::Recursive(ty));
     }
 
-    fn visit_defined(&mut self, s: &'s str) {
-        self.definitions.insert(s);
+    fn visit_ty(&mut self, ty: &'t Type) {
+        self.span = ty.span;
+        match &ty.kind {
+            TypeKind::Int => self.out.push(hir::Type::Int),
+            TypeKind::Bool => self.out.push(hir::Type::Bool),
+            TypeKind::Unit => self.out.push(hir::Type::Unit),
+            TypeKind::Infer => self.out.push(hir::Type::Infer),
+            _ => self.walk_ty(ty),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::syntax::parser::Parser;
+
+    fn var(idx: usize, name: &str) -> hir::Type {
+        hir::Type::Var(DeBruijn { idx, name: name.into() })
+    }
+
+    fn arrow(ty1: hir::Type, ty2: hir::Type) -> hir::Type {
+        hir::Type::Arrow(Box::new(ty1), Box::new(ty2))
+    }
+
+    fn forall(ty: hir::Type) -> hir::Type {
+        hir::Type::Universal(Box::new(hir::Kind::Star), Box::new(ty))
+    }
+
+    /// Elaborate a program, returning the type each declaration defines
+    fn types(input: &str) -> Result<(Vec<hir::Type>, Vec<Diagnostic>), ElabError> {
+        let program = Parser::new(input).parse_program().unwrap();
+        let elab = ElaborationContext::elaborate(&program).map_err(|mut errors| errors.remove(0))?;
+        let tys = elab
+            .decls
+            .iter()
+            .map(|id| match &elab.elaborated[id] {
+                hir::Decl::Type(ty) => ty.clone(),
+                hir::Decl::Value(_) => panic!("expected a type declaration"),
+            })
+            .collect();
+        Ok((tys, elab.warnings))
+    }
+
+    #[test]
+    fn nested_universals() {
+        let (tys, warnings) = types("type t = forall ('a :: *) of forall ('b :: *) of 'a -> 'b").unwrap();
+        assert_eq!(tys, [forall(forall(arrow(var(1, "a"), var(0, "b"))))]);
+        assert!(warnings.is_empty());
+
+        // The inner binding hides the outer one, and is warned about
+        let (tys, warnings) = types("type t = forall ('a :: *) of 'a -> forall ('a :: *) of 'a").unwrap();
+        assert_eq!(tys, [forall(arrow(var(0, "a"), forall(var(0, "a"))))]);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(
+            warnings[0].primary.info,
+            "type variable 'a shadows an enclosing binding of the same name"
+        );
+
+        // As does a type parameter bound twice
+        let (_, warnings) = types("type ('a, 'a) t = 'a").unwrap();
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn parameters() {
+        let (tys, _) = types("type ('a, 'b) arrow = 'a -> 'b").unwrap();
+        let abs = |ty| hir::Type::Abstraction(Box::new(hir::Kind::Star), Box::new(ty));
+        assert_eq!(tys, [abs(abs(arrow(var(1, "a"), var(0, "b"))))]);
+    }
+
+    #[test]
+    fn datatypes() {
+        let input = "datatype 'a list = Nil | Cons of 'a * 'a list; \
+                     datatype ('a, 'b) either = Left of 'a | Right of 'b";
+        let program = Parser::new(input).parse_program().unwrap();
+        let elab = ElaborationContext::elaborate(&program).unwrap();
+
+        let star = || Box::new(hir::Kind::Star);
+        let list = hir::Type::Recursive(Box::new(hir::Type::Abstraction(
+            Box::new(hir::Kind::Arrow(star(), star())),
+            Box::new(hir::Type::Abstraction(
+                star(),
+                Box::new(hir::Type::Sum(vec![
+                    hir::Variant {
+                        label: "Nil".into(),
+                        ty: None,
+                    },
+                    hir::Variant {
+                        label: "Cons".into(),
+                        ty: Some(hir::Type::Product(vec![
+                            var(0, "a"),
+                            hir::Type::Application(Box::new(var(1, "list")), Box::new(var(0, "a"))),
+                        ])),
+                    },
+                ])),
+            )),
+        )));
+        assert_eq!(elab.elaborated[&elab.decls[0]], hir::Decl::Type(list));
+
+        // Constructors, in the order they were declared
+        let mut cons = elab.constructors.values().collect::<Vec<_>>();
+        cons.sort_by_key(|c| c.con_id.0);
+        let arities = cons
+            .iter()
+            .map(|c| (c.type_id, c.tag, c.arity, c.type_arity))
+            .collect::<Vec<_>>();
+        let (list, either) = (elab.decls[0], elab.decls[1]);
+        assert_eq!(
+            arities,
+            [
+                (list, 0, false, 1),
+                (list, 1, true, 1),
+                (either, 0, true, 2),
+                (either, 1, true, 2)
+            ]
+        );
+
+        // Right : forall 'a. forall 'b. 'b -> ('a, 'b) either
+        let app = |ty1, ty2| hir::Type::Application(Box::new(ty1), Box::new(ty2));
+        let result = app(app(hir::Type::Defined(either), var(1, "a")), var(0, "b"));
+        assert_eq!(cons[3].ty, forall(forall(arrow(var(0, "b"), result))));
+        match &elab.elaborated[&cons[3].con_id] {
+            hir::Decl::Value(hir::Expr::TyAbs(_, e)) => match e.as_ref() {
+                hir::Expr::TyAbs(_, e) => assert!(matches!(e.as_ref(), hir::Expr::Abs(ty, _) if **ty == var(0, "b"))),
+                e => panic!("expected a type abstraction, not {:?}", e),
+            },
+            d => panic!("expected a type abstraction, not {:?}", d),
+        }
+    }
+
+    #[test]
+    fn curried_constructors() {
+        let input = "datatype 'a list = Nil | Cons of 'a * 'a list; \
+                     val xs = Cons (1, Nil); \
+                     val push = Cons 1; \
+                     val n = case push xs of Cons (x, _) => x | Nil => 0 end";
+        let program = Parser::new(input).parse_program().unwrap();
+        let elab = ElaborationContext::elaborate(&program).unwrap();
+        let value = |id: &HirId| match elab.elaborated.get(id) {
+            Some(hir::Decl::Value(e)) => e.clone(),
+            d => panic!("expected a value, not {:?}", d),
+        };
+        let list = elab.decls[0];
+        let cons = elab.constructors.values().find(|c| c.tag == 1).unwrap();
+        let nil = elab.constructors.values().find(|c| c.tag == 0).unwrap();
+        assert_eq!(nil.curried, None);
+        let curried = cons.curried.unwrap();
+
+        // fn x0 => fn x1 => Cons (x0, x1), under a type abstraction
+        let local = |name: &str, idx| hir::Expr::LocalVar(DeBruijn { name: name.into(), idx });
+        match value(&curried) {
+            hir::Expr::TyAbs(_, e) => match *e {
+                hir::Expr::Abs(ty, e) => {
+                    assert_eq!(*ty, var(0, "a"));
+                    match *e {
+                        hir::Expr::Abs(_, e) => assert_eq!(
+                            *e,
+                            hir::Expr::App(
+                                Box::new(hir::Expr::Constr(list, 1)),
+                                Box::new(hir::Expr::Tuple(vec![local("x0", 1), local("x1", 0)])),
+                            )
+                        ),
+                        e => panic!("expected a function, not {:?}", e),
+                    }
+                }
+                e => panic!("expected a function, not {:?}", e),
+            },
+            e => panic!("expected a type abstraction, not {:?}", e),
+        }
+
+        // Applied to a pair, the constructor keeps the tuple form
+        assert_eq!(
+            value(&elab.decls[1]),
+            hir::Expr::App(
+                Box::new(hir::Expr::Constr(list, 1)),
+                Box::new(hir::Expr::Tuple(vec![hir::Expr::Int(1), hir::Expr::Constr(list, 0)])),
+            )
+        );
+        assert_eq!(
+            value(&elab.decls[2]),
+            hir::Expr::App(Box::new(hir::Expr::ProgramVar(curried)), Box::new(hir::Expr::Int(1)))
+        );
+        match value(&elab.decls[3]) {
+            hir::Expr::Case(_, arms) => assert!(matches!(
+                &arms[0].pat,
+                hir::Pattern::Application(id, pat) if *id == cons.con_id && matches!(**pat, hir::Pattern::Product(_))
+            )),
+            e => panic!("expected a case expression, not {:?}", e),
+        }
+    }
+
+    #[test]
+    fn unbound() {
+        // Declarations are only in scope after they are made, except for
+        // a datatype within its own definition
+        assert!(types("type t = int; type u = t -> t").is_ok());
+        assert!(matches!(
+            types("type t = u; type u = int"),
+            Err(ElabError { kind: ElabErrorKind::UndefinedType(s), .. }) if s == "u"
+        ));
+        assert!(matches!(
+            types("type t = int -> t"),
+            Err(ElabError { kind: ElabErrorKind::UndefinedType(s), .. }) if s == "t"
+        ));
+        assert!(types("datatype 'a list = Nil | Cons of 'a * 'a list").is_ok());
+
+        let err = types("type t = forall ('a :: *) of 'a -> 'b").unwrap_err();
+        assert!(matches!(&err.kind, ElabErrorKind::UnboundTypeVar(s) if s == "b"));
+        assert_eq!(Diagnostic::from(err).primary.info, "unbound type variable 'b");
+    }
+
+    #[test]
+    fn packages() {
+        let input = "val counter = pack int, {new = 0, get = fn (n : int) => n} \
+                       as exists ('t :: *) of {new: 't, get: 't -> int}; \
+                     val n = open counter as 't, c in c.get c.new end";
+        let program = Parser::new(input).parse_program().unwrap();
+        let elab = ElaborationContext::elaborate(&program).unwrap();
+        let value = |id: &HirId| match elab.elaborated.get(id) {
+            Some(hir::Decl::Value(e)) => e.clone(),
+            d => panic!("expected a value, not {:?}", d),
+        };
+        match value(&elab.decls[0]) {
+            hir::Expr::Pack(witness, e, sig) => {
+                assert_eq!(*witness, hir::Type::Int);
+                assert!(matches!(*e, hir::Expr::Record(_)));
+                assert!(matches!(*sig, hir::Type::Existential(..)));
+            }
+            e => panic!("expected a package, not {:?}", e),
+        }
+        match value(&elab.decls[1]) {
+            hir::Expr::Unpack(package, body) => {
+                assert_eq!(*package, hir::Expr::ProgramVar(elab.decls[0]));
+                let c = hir::Expr::LocalVar(DeBruijn {
+                    idx: 0,
+                    name: "c".into(),
+                });
+                assert_eq!(
+                    *body,
+                    hir::Expr::App(
+                        Box::new(hir::Expr::RecordProj(Box::new(c.clone()), "get".into())),
+                        Box::new(hir::Expr::RecordProj(Box::new(c), "new".into())),
+                    )
+                );
+            }
+            e => panic!("expected an unpack, not {:?}", e),
+        }
+
+        // The abstract type is only bound in the body
+        let input = "val p = pack int, 0 as exists ('t :: *) of 't; \
+                     val n = open p as 't, x in (fn (y : 't) => y) x end; \
+                     val f = fn (y : 't) => y";
+        let program = Parser::new(input).parse_program().unwrap();
+        assert!(matches!(
+            ElaborationContext::elaborate(&program).map_err(|mut errors| errors.remove(0)),
+            Err(ElabError { kind: ElabErrorKind::UnboundTypeVar(s), .. }) if s == "t"
+        ));
+    }
+
+    #[test]
+    fn structures() {
+        let input = "signature S = sig type t val x : t end; \
+                     structure M : S = struct type t = int val x = 3 end; \
+                     val y = M.x; \
+                     val f = fn (a : M.t) => a";
+        let program = Parser::new(input).parse_program().unwrap();
+        let elab = ElaborationContext::elaborate(&program).unwrap();
+        let value = |id: &HirId| match elab.elaborated.get(id) {
+            Some(hir::Decl::Value(e)) => e.clone(),
+            d => panic!("expected a value, not {:?}", d),
+        };
+        // The components of `M` are declared after the signature, and
+        // before the structure itself
+        let (t, x) = (HirId(1), HirId(2));
+        assert_eq!(elab.elaborated.get(&t), Some(&hir::Decl::Type(hir::Type::Int)));
+        assert_eq!(value(&elab.decls[2]), hir::Expr::ProgramVar(x));
+        match value(&elab.decls[3]) {
+            hir::Expr::Abs(ty, _) => assert_eq!(*ty, hir::Type::Defined(t)),
+            e => panic!("expected a function, not {:?}", e),
+        }
+
+        // Components are not in scope outside of their structure
+        let input = "structure M = struct val x = 3 end; val y = x";
+        let program = Parser::new(input).parse_program().unwrap();
+        assert!(matches!(
+            ElaborationContext::elaborate(&program).map_err(|mut errors| errors.remove(0)),
+            Err(ElabError { kind: ElabErrorKind::UndefinedValue(s), .. }) if s == "x"
+        ));
+        let input = "structure M = struct val x = 3 end; val y = M.z; val z = N.x";
+        let program = Parser::new(input).parse_program().unwrap();
+        assert!(matches!(
+            ElaborationContext::elaborate(&program).map_err(|mut errors| errors.remove(0)),
+            Err(ElabError { kind: ElabErrorKind::UndefinedValue(s), .. }) if s == "M.z"
+        ));
+        let input = "val z = N.x";
+        let program = Parser::new(input).parse_program().unwrap();
+        assert!(matches!(
+            ElaborationContext::elaborate(&program).map_err(|mut errors| errors.remove(0)),
+            Err(ElabError { kind: ElabErrorKind::UndefinedStructure(s), .. }) if s == "N"
+        ));
+    }
+
+    #[test]
+    fn coverage() {
+        let input = "datatype 'a option = None | Some of 'a; \
+                     val f = fn x => case x of | Some y => y | Some _ => 0 end";
+        let program = Parser::new(input).parse_program().unwrap();
+        let elab = ElaborationContext::elaborate(&program).unwrap();
+        let warnings = elab
+            .warnings
+            .iter()
+            .map(|d| d.primary.info.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(warnings, ["unreachable pattern", "match is not exhaustive"]);
+        assert_eq!(elab.warnings[1].info, ["pattern None is not matched"]);
+
+        let input = "val f = fn x => let val y = x in 0 end";
+        let program = Parser::new(input).parse_program().unwrap();
+        let elab = ElaborationContext::elaborate(&program).unwrap();
+        assert_eq!(elab.warnings.len(), 1);
+        assert_eq!(elab.warnings[0].primary.info, "unused variable y");
+
+        let input = "datatype 'a option = None | Some of 'a; \
+                     val f = fn x => case x of | (Some y | None) => 0 end";
+        let program = Parser::new(input).parse_program().unwrap();
+        assert!(matches!(
+            ElaborationContext::elaborate(&program).map_err(|mut errors| errors.remove(0)),
+            Err(ElabError {
+                kind: ElabErrorKind::InvalidBinding(_),
+                ..
+            })
+        ));
     }
 }
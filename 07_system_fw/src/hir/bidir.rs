@@ -81,12 +81,12 @@ impl<'hir> Context<'hir> {
                 .iter()
                 .map(|f| {
                     Ok(Row {
-                        label: f.label.clone(),
+                        label: f.label,
                         ty: self.infer(&f.expr)?,
                     })
                 })
                 .collect::<Result<Vec<_>, _>>()
-                .map(Type::Record),
+                .map(|rows| Type::Record(rows, None)),
             Tuple(exprs) => exprs
                 .iter()
                 .map(|e| self.infer(e))
@@ -97,6 +97,8 @@ impl<'hir> Context<'hir> {
             Case(ex, arms) => unimplemented!(),
             Let(decls, ex) => unimplemented!(),
             Fix(ex) => unimplemented!(),
+            Pack(..) => unimplemented!(),
+            Unpack(..) => unimplemented!(),
         }
     }
 }
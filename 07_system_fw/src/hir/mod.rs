@@ -1,11 +1,12 @@
 pub mod bidir;
 
+use crate::symbol::Symbol;
 use std::fmt;
 
 #[derive(Clone, Debug, PartialEq, PartialOrd, Eq, Hash)]
 pub struct DeBruijn {
     pub idx: usize,
-    pub name: String,
+    pub name: Symbol,
 }
 
 #[derive(Copy, Clone, Debug, Default, PartialEq, PartialOrd, Eq, Hash)]
@@ -15,12 +16,13 @@ pub struct HirId(pub(crate) u32);
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub struct Arm {
     pub pat: Pattern,
+    pub guard: Option<Expr>,
     pub expr: Expr,
 }
 
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub struct Field {
-    pub label: String,
+    pub label: Symbol,
     pub expr: Expr,
 }
 
@@ -46,6 +48,10 @@ pub struct Constructor {
     // Whether this constr takes an argument or not
     pub arity: bool,
     pub type_arity: u8,
+    // Type of the constructor, quantified over the datatype's parameters
+    pub ty: Type,
+    // Points to the curried constructor function, if it takes a tuple
+    pub curried: Option<HirId>,
 }
 
 /// Patterns for case and let expressions
@@ -60,13 +66,17 @@ pub enum Pattern {
     /// Datatype constructor, HirId points to the constructor value binding
     Constructor(HirId),
     /// Variable binding
-    Variable(String),
+    Variable(Symbol),
     /// Tuple of pattern bindings (_, x)
     Product(Vec<Pattern>),
     /// Record pattern { label1, label2 }
-    Record(Vec<String>),
+    Record(Vec<Symbol>),
     /// Algebraic datatype constructor, along with binding pattern
     Application(HirId, Box<Pattern>),
+    /// Or-pattern, every alternative binds the same variables
+    Or(Vec<Pattern>),
+    /// Layered pattern, binding a variable to the value the pattern matches
+    As(Symbol, Box<Pattern>),
 }
 
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
@@ -90,17 +100,25 @@ pub enum Expr {
     Record(Vec<Field>),
     Tuple(Vec<Expr>),
 
-    RecordProj(Box<Expr>, String),
+    RecordProj(Box<Expr>, Symbol),
     TupleProj(Box<Expr>, usize),
     Case(Box<Expr>, Vec<Arm>),
     Let(Vec<Decl>, Box<Expr>),
     Fix(Box<Expr>),
+
+    /// Introduce an existential type, packing a witness type and a term
+    /// into the signature {∃X, T}
+    Pack(Box<Type>, Box<Expr>, Box<Type>),
+    /// Eliminate an existential type. The hidden type is bound as a type
+    /// variable in the body, and the contents of the package as LocalVar 0
+    Unpack(Box<Expr>, Box<Expr>),
 }
 
 #[derive(Clone, Debug, PartialEq, PartialOrd, Eq, Hash)]
 pub enum Kind {
     Star,
     Arrow(Box<Kind>, Box<Kind>),
+    Row,
 }
 
 #[derive(Clone, PartialEq, PartialOrd, Eq, Hash)]
@@ -121,8 +139,9 @@ pub enum Type {
     Sum(Vec<Variant>),
     /// Tuple type (ty * ty * ... tyN), invariant that N >= 1
     Product(Vec<Type>),
-    /// Record type { [label: ty],+ }, invariant that N >=1
-    Record(Vec<Row>),
+    /// Record type { [label: ty],+ }, invariant that N >=1, with the row
+    /// variable of an open record type { [label: ty],+ | 'r }
+    Record(Vec<Row>, Option<Box<Type>>),
     /// Existential type: exists (a :: K) of ty
     Existential(Box<Kind>, Box<Type>),
     /// Universal type: forall (a :: K) of ty
@@ -137,13 +156,13 @@ pub enum Type {
 
 #[derive(Clone, PartialEq, PartialOrd, Eq, Hash)]
 pub struct Variant {
-    pub label: String,
+    pub label: Symbol,
     pub ty: Option<Type>,
 }
 
 #[derive(Clone, PartialEq, PartialOrd, Eq, Hash)]
 pub struct Row {
-    pub label: String,
+    pub label: Symbol,
     pub ty: Type,
 }
 
@@ -174,13 +193,14 @@ impl fmt::Debug for Type {
                 "({})",
                 v.iter().map(|x| format!("{:?}", x)).collect::<Vec<String>>().join(",")
             ),
-            Type::Record(v) => write!(
+            Type::Record(v, tail) => write!(
                 f,
-                "{{{}}}",
+                "{{{}{}}}",
                 v.iter()
                     .map(|x| format!("{}: {:?}", x.label, x.ty))
                     .collect::<Vec<String>>()
-                    .join(", ")
+                    .join(", "),
+                tail.as_ref().map(|t| format!(" | {:?}", t)).unwrap_or_default()
             ),
             Type::Defined(s) => write!(f, "tctx#{:?}", s),
             Type::Arrow(t1, t2) => write!(f, "({:?}->{:?})", t1, t2),
@@ -0,0 +1,1565 @@
+//! Inference of the `_` holes in type annotations
+//!
+//! Each hole is first replaced by a fresh unification variable `'?N`. The
+//! program is then walked in execution order, generating an equality
+//! constraint wherever two types must agree: a function's parameter and its
+//! argument, the arms of a case expression, or a record and a field
+//! projected from it. Constraints are solved by first-order unification as
+//! they are generated, and the solution of each hole is finally written back
+//! into the annotation it came from.
+//!
+//! The types of `val` and `fun` declarations are generalized over the
+//! unification variables left in them that are not also in the types of
+//! the variables in scope, and get fresh ones at each use. Under the value
+//! restriction, only a declaration whose definition is a syntactic value is
+//! generalized: evaluating anything else may have effects that depend on
+//! the type it is first used at. Lambda-bound variables are monomorphic, as
+//! are the holes, since each is filled in with a single type.
+//! The constructors of datatypes are polymorphic in their parameters, and
+//! those taking a tuple are curried unless applied to one, see
+//! [`desugar`](crate::desugar).
+//! Type abbreviations are only expanded when the types being unified do not
+//! already have the same head, see [`abbrev`](crate::abbrev). Type level
+//! applications are beta-reduced as they are unified, so that types are
+//! compared by their normal forms, see [`normalize`](crate::normalize).
+//!
+//! Existential packages are checked against their signatures: the contents
+//! of `pack ty, e as exists ('t :: K) of sig` must have the type
+//! `[t ↦ ty] sig`, and the witness `ty` the kind `K`. Opening a package
+//! binds its hidden type to an abstract type variable, which may not escape
+//! into the type of the body.
+//!
+//! Structures are matched against their signatures, as described in
+//! [`modules`](crate::modules). Outside of a structure, its type components
+//! are the paths `M.t`, which are only equal to their definitions if the
+//! structure does not seal them
+use crate::abbrev::{AbbrevError, Abbreviations};
+use crate::desugar;
+use crate::diagnostics::Diagnostic;
+use crate::elaborate::BindingMap;
+use crate::kindcheck::{KindContext, KindError};
+use crate::modules::{self, ModuleError};
+use crate::normalize::{NormalizeError, Normalizer};
+use crate::symbol::Symbol;
+use crate::syntax::ast::{
+    Decl, DeclKind, Expr, ExprKind, FnArm, PatKind, Pattern, Row, RowVar, Sig, SigKind, Spec, SpecKind, Type, TypeKind,
+};
+use crate::syntax::visit::{
+    free_tyvars, non_value, ExpandDefined, ExprMutVisitor, PatternBinders, PatternVisitor, SubstNamedVar,
+    TypeMutVisitor,
+};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::ops::Range;
+use util::span::Span;
+
+/// The type expected by the expression at `span`, and the one found
+#[derive(Clone, Debug, PartialEq)]
+pub struct Constraint {
+    pub expected: Type,
+    pub found: Type,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum InferError {
+    /// The constraint at the span expects the first type, but finds the
+    /// second. The other spans are of the earlier constraints whose
+    /// solutions make them conflict
+    Conflict(Type, Type, Span, Vec<Span>),
+    /// Solving the constraint at the span would make the unification
+    /// variable contain itself
+    Occurs(Symbol, Type, Span),
+    /// Nothing determines the type of the hole at the span
+    Unsolved(Span),
+    /// The expression at the span is projected by an index, but its type is
+    /// not known to be a tuple with that many elements
+    Projection(usize, Span),
+    /// The type at the span is packed or opened, but is not existential
+    NotExistential(Type, Span),
+    /// The abstract type variable of an opened package appears in the type
+    /// of the body at the span
+    Escape(Symbol, Type, Span),
+    /// The witness type of a package does not have the kind its signature
+    /// requires
+    Kind(KindError),
+    /// A structure does not match its signature
+    Module(ModuleError),
+    /// A type abbreviation is misapplied, or refers to itself
+    Abbrev(AbbrevError),
+    /// A type could not be normalized to compare it
+    Normalize(NormalizeError),
+}
+
+/// The type of a value variable, generalized over the unification variables
+/// in `vars`, which are replaced by fresh ones at each use
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scheme {
+    pub vars: Vec<Symbol>,
+    pub ty: Type,
+}
+
+impl Scheme {
+    fn monomorphic(ty: Type) -> Scheme {
+        Scheme { vars: Vec::new(), ty }
+    }
+}
+
+/// Why two types could not be unified
+enum Failure {
+    Mismatch,
+    Occurs(Symbol, Box<Type>),
+    Normalize(NormalizeError),
+}
+
+fn is_unification_var(name: Symbol) -> bool {
+    name.as_str().starts_with('?')
+}
+
+fn variable(ty: &Type) -> Option<Symbol> {
+    match &ty.kind {
+        TypeKind::Variable(s) => Some(*s),
+        _ => None,
+    }
+}
+
+/// The defined type that `ty` applies to its arguments, if any
+fn head(ty: &Type) -> Option<Symbol> {
+    match &ty.kind {
+        TypeKind::Defined(s) => Some(*s),
+        TypeKind::Application(ty1, _) => head(ty1),
+        _ => None,
+    }
+}
+
+/// Solutions for unification variables, along with the typing context of
+/// the value variables in scope
+#[derive(Default, Debug)]
+pub struct Infer {
+    /// Each solved variable, with the span of the constraint solving it
+    solved: HashMap<Symbol, (Type, Span)>,
+    fresh: usize,
+    /// Value variables in scope, innermost last
+    values: Vec<(Symbol, Scheme)>,
+    /// Types of datatype constructors, quantified over their parameters
+    constructors: HashMap<Symbol, Type>,
+    /// Kinds of the type variables in scope, and of defined types
+    kinds: KindContext,
+    abbreviations: Abbreviations,
+    /// The specifications of each declared signature
+    signatures: HashMap<Symbol, Vec<Spec>>,
+    /// The types of the value components of each structure, as seen from
+    /// outside of it
+    structures: HashMap<Symbol, HashMap<Symbol, Scheme>>,
+    /// Definitions of the type components `M.t` that are not sealed
+    paths: HashMap<Symbol, Type>,
+    /// The unification variables standing for `_` holes. Each hole is
+    /// filled in with a single type, so these are never generalized
+    holes: Vec<Symbol>,
+    /// The variables bound by the patterns of case arms and value
+    /// declarations, before their types are solved
+    bindings: BindingMap,
+    /// Every constraint generated, in order
+    pub constraints: Vec<Constraint>,
+    pub errors: Vec<InferError>,
+    /// Declarations left monomorphic by the value restriction
+    pub warnings: Vec<Diagnostic>,
+    /// Spans of the solutions looked through while solving a constraint
+    trail: Vec<Span>,
+}
+
+impl Infer {
+    fn fresh_name(&mut self) -> Symbol {
+        self.fresh += 1;
+        Symbol::intern(&format!("?{}", self.fresh))
+    }
+
+    fn fresh(&mut self, span: Span) -> Type {
+        Type::new(TypeKind::Variable(self.fresh_name()), span)
+    }
+
+    fn fresh_row(&mut self, span: Span) -> RowVar {
+        RowVar {
+            name: self.fresh_name(),
+            span,
+        }
+    }
+
+    /// `ty` with every solved unification variable replaced by its solution
+    pub fn apply(&self, ty: &Type) -> Type {
+        let mut ty = ty.clone();
+        Apply(self).visit_ty(&mut ty);
+        ty
+    }
+
+    /// Look through the solutions of the outermost variable of `ty`, and
+    /// the definitions of transparent paths
+    fn shallow(&mut self, ty: &Type) -> Type {
+        let mut ty = ty.clone();
+        loop {
+            if let Some((solution, span)) = variable(&ty).and_then(|v| self.solved.get(&v)).cloned() {
+                self.trail.push(span);
+                ty = solution;
+            } else if let TypeKind::Path(m, s) = &ty.kind {
+                match self.paths.get(&Symbol::intern(&format!("{}.{}", m, s))) {
+                    Some(def) => ty = def.clone(),
+                    None => return ty,
+                }
+            } else {
+                return ty;
+            }
+        }
+    }
+
+    /// Extend the rows of a record with the solutions of its row variable
+    fn resolve(&mut self, rows: &[Row], tail: &Option<RowVar>) -> (Vec<Row>, Option<RowVar>) {
+        let mut rows = rows.to_vec();
+        let mut tail = tail.clone();
+        while let Some((solution, span)) = tail.as_ref().and_then(|v| self.solved.get(&v.name)).cloned() {
+            self.trail.push(span);
+            match solution.kind {
+                TypeKind::Record(more, rest) => {
+                    rows.extend(more);
+                    tail = rest;
+                }
+                _ => unreachable!("row variable solved to a type that is not a record"),
+            }
+        }
+        (rows, tail)
+    }
+
+    fn bind(&mut self, var: Symbol, ty: &Type, span: Span) -> Result<(), Failure> {
+        let ty = self.apply(ty);
+        if free_tyvars(&ty).contains(&var) {
+            return Err(Failure::Occurs(var, Box::new(ty)));
+        }
+        self.solved.insert(var, (ty, span));
+        Ok(())
+    }
+
+    /// Solve the row variable `var` to the record type with `rows`, ending
+    /// in `tail`
+    fn bind_rows(&mut self, var: &RowVar, rows: Vec<Row>, tail: Option<RowVar>, span: Span) -> Result<(), Failure> {
+        let record = Type::new(TypeKind::Record(rows, tail), var.span);
+        self.bind(var.name, &record, span)
+    }
+
+    fn unify_records(
+        &mut self,
+        expected: (&[Row], &Option<RowVar>),
+        found: (&[Row], &Option<RowVar>),
+        span: Span,
+    ) -> Result<(), Failure> {
+        let (rows1, tail1) = self.resolve(expected.0, expected.1);
+        let (rows2, tail2) = self.resolve(found.0, found.1);
+
+        // Rows of the expected type that were not found, and vice versa
+        let mut missing = Vec::new();
+        for row in &rows1 {
+            match rows2.iter().find(|r| r.label == row.label) {
+                Some(other) => self.unify(&row.ty, &other.ty, span)?,
+                None => missing.push(row.clone()),
+            }
+        }
+        let extra = rows2
+            .iter()
+            .filter(|row| !rows1.iter().any(|r| r.label == row.label))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let same_tail = tail1.as_ref().map(|v| &v.name) == tail2.as_ref().map(|v| &v.name);
+        let solvable = |tail: &Option<RowVar>| tail.clone().filter(|v| is_unification_var(v.name));
+        match (solvable(&tail1), solvable(&tail2)) {
+            _ if same_tail && missing.is_empty() && extra.is_empty() => Ok(()),
+            (Some(var1), Some(var2)) if var1.name == var2.name => Err(Failure::Mismatch),
+            (Some(var1), Some(_)) if missing.is_empty() => self.bind_rows(&var1, extra, tail2, span),
+            (Some(_), Some(var2)) if extra.is_empty() => self.bind_rows(&var2, missing, tail1, span),
+            (Some(var1), Some(var2)) => {
+                // Whatever neither record lists is left to a new variable
+                // shared by the two
+                let rest = self.fresh_row(var2.span);
+                self.bind_rows(&var1, extra, Some(rest.clone()), span)?;
+                self.bind_rows(&var2, missing, Some(rest), span)
+            }
+            (Some(var1), None) if missing.is_empty() => self.bind_rows(&var1, extra, tail2, span),
+            (None, Some(var2)) if extra.is_empty() => self.bind_rows(&var2, missing, tail1, span),
+            _ => Err(Failure::Mismatch),
+        }
+    }
+
+    /// Expand the abbreviations at the heads of `a` and `b`, until they have
+    /// the same head or neither is an abbreviation
+    fn expand_heads(&mut self, mut a: Type, mut b: Type) -> (Type, Type) {
+        loop {
+            if head(&a).is_some() && head(&a) == head(&b) {
+                return (a, b);
+            }
+            if let Ok(Some(ty)) = self.abbreviations.expand_head(&a, &mut self.kinds) {
+                a = self.shallow(&ty);
+            } else if let Ok(Some(ty)) = self.abbreviations.expand_head(&b, &mut self.kinds) {
+                b = self.shallow(&ty);
+            } else {
+                return (a, b);
+            }
+        }
+    }
+
+    /// Beta-reduce `ty` to weak head normal form, if its head is a redex.
+    /// Abstractions are compared by eta-expansion instead of reduction, see
+    /// [`unify_eta`](Self::unify_eta)
+    fn reduce(&self, ty: &Type) -> Result<Option<Type>, Failure> {
+        let mut n = Normalizer::default();
+        match n.beta_step(ty).map_err(Failure::Normalize)? {
+            Some(ty) => n.beta_whnf(&ty).map(Some).map_err(Failure::Normalize),
+            None => Ok(None),
+        }
+    }
+
+    /// Unify the abstraction `fn ('s :: K) => body` with `other`, which is
+    /// not an abstraction, by applying `other` to the parameter. They are
+    /// equal when `other` is the operator the abstraction eta-reduces to,
+    /// and comparing them applied lets any abbreviation at the head of
+    /// `other` be expanded. The parameter is renamed if `other` mentions it
+    fn unify_eta(&mut self, s: Symbol, body: &Type, other: &Type, flipped: bool, span: Span) -> Result<(), Failure> {
+        let mut body = body.clone();
+        let mut param = s;
+        if free_tyvars(other).contains(&s) {
+            self.fresh += 1;
+            param = Symbol::intern(&format!("{}#{}", s, self.fresh));
+            let var = Type::new(TypeKind::Variable(param), body.span);
+            SubstNamedVar::new(s, var).visit_ty(&mut body);
+        }
+        let param = Type::new(TypeKind::Variable(param), other.span);
+        let applied = Type::new(
+            TypeKind::Application(Box::new(other.clone()), Box::new(param)),
+            other.span,
+        );
+        if flipped {
+            self.unify(&applied, &body, span)
+        } else {
+            self.unify(&body, &applied, span)
+        }
+    }
+
+    fn unify(&mut self, expected: &Type, found: &Type, span: Span) -> Result<(), Failure> {
+        use TypeKind::*;
+        let a = self.shallow(expected);
+        let b = self.shallow(found);
+        let (a, b) = self.expand_heads(a, b);
+        match (self.reduce(&a)?, self.reduce(&b)?) {
+            (None, None) => {}
+            (a1, b1) => return self.unify(&a1.unwrap_or(a), &b1.unwrap_or(b), span),
+        }
+        match (&a.kind, &b.kind) {
+            (Variable(x), Variable(y)) if x == y => Ok(()),
+            (Variable(x), _) if is_unification_var(*x) => self.bind(*x, &b, span),
+            (_, Variable(y)) if is_unification_var(*y) => self.bind(*y, &a, span),
+            (Int, Int) | (Bool, Bool) | (Unit, Unit) => Ok(()),
+            (Defined(x), Defined(y)) if x == y => Ok(()),
+            (Path(m1, x), Path(m2, y)) if m1 == m2 && x == y => Ok(()),
+            (Function(a1, a2), Function(b1, b2)) | (Application(a1, a2), Application(b1, b2)) => {
+                self.unify(a1, b1, span)?;
+                self.unify(a2, b2, span)
+            }
+            (Product(xs), Product(ys)) if xs.len() == ys.len() => {
+                xs.iter().zip(ys).try_for_each(|(x, y)| self.unify(x, y, span))
+            }
+            (Sum(xs), Sum(ys)) if xs.len() == ys.len() => {
+                xs.iter().zip(ys).try_for_each(|(x, y)| match (&x.ty, &y.ty) {
+                    _ if x.label != y.label => Err(Failure::Mismatch),
+                    (Some(t1), Some(t2)) => self.unify(t1, t2, span),
+                    (None, None) => Ok(()),
+                    _ => Err(Failure::Mismatch),
+                })
+            }
+            (Record(r1, t1), Record(r2, t2)) => self.unify_records((r1, t1), (r2, t2), span),
+            (Universal(s1, k1, t1), Universal(s2, k2, t2))
+            | (Existential(s1, k1, t1), Existential(s2, k2, t2))
+            | (Abstraction(s1, k1, t1), Abstraction(s2, k2, t2))
+                if k1 == k2 =>
+            {
+                let mut body = (**t2).clone();
+                SubstNamedVar::new(*s2, Type::new(Variable(*s1), t2.span)).visit_ty(&mut body);
+                self.unify(t1, &body, span)
+            }
+            (Abstraction(s, _, t), _) if !matches!(b.kind, Abstraction(..)) => self.unify_eta(*s, t, &b, false, span),
+            (_, Abstraction(s, _, t)) if !matches!(a.kind, Abstraction(..)) => self.unify_eta(*s, t, &a, true, span),
+            (Recursive(x), Recursive(y)) => self.unify(x, y, span),
+            _ => Err(Failure::Mismatch),
+        }
+    }
+
+    /// Record the constraint that `found` is the `expected` type, and solve
+    /// it, or report why it cannot be
+    fn constrain(&mut self, expected: &Type, found: &Type, span: Span) {
+        self.constraints.push(Constraint {
+            expected: expected.clone(),
+            found: found.clone(),
+            span,
+        });
+        self.trail.clear();
+        match self.unify(expected, found, span) {
+            Ok(()) => {}
+            Err(Failure::Mismatch) => {
+                let mut trail = std::mem::take(&mut self.trail);
+                trail.dedup();
+                let err = InferError::Conflict(self.apply(expected), self.apply(found), span, trail);
+                self.errors.push(err);
+            }
+            Err(Failure::Occurs(var, ty)) => self.errors.push(InferError::Occurs(var, *ty, span)),
+            Err(Failure::Normalize(e)) => self.errors.push(InferError::Normalize(e)),
+        }
+    }
+
+    /// Replace the outermost universal quantifiers of `ty` with fresh
+    /// unification variables
+    fn instantiate(&mut self, ty: &Type) -> Type {
+        let mut ty = ty.clone();
+        while let TypeKind::Universal(s, _, body) = &ty.kind {
+            let var = self.fresh(ty.span);
+            let mut body = (**body).clone();
+            SubstNamedVar::new(*s, var).visit_ty(&mut body);
+            ty = body;
+        }
+        ty
+    }
+
+    /// Report the first misapplied abbreviation in `ty`
+    fn check_abbreviations(&mut self, ty: &Type) {
+        let mut ty = ty.clone();
+        if let Err(e) = self.abbreviations.normalize(&mut ty, &mut self.kinds) {
+            self.errors.push(InferError::Abbrev(e));
+        }
+    }
+
+    /// Replace the generalized variables of a scheme with fresh unification
+    /// variables
+    fn instantiate_scheme(&mut self, scheme: &Scheme) -> Type {
+        let mut ty = scheme.ty.clone();
+        for var in &scheme.vars {
+            let fresh = self.fresh(ty.span);
+            SubstNamedVar::new(*var, fresh).visit_ty(&mut ty);
+        }
+        ty
+    }
+
+    fn bind_value(&mut self, name: Symbol, ty: Type) {
+        self.values.push((name, Scheme::monomorphic(ty)));
+    }
+
+    /// Generalize the types of the `bound` value variables over the
+    /// unification variables that are not free in the types of the rest.
+    /// If the definition binding them has a subexpression that is not a
+    /// syntactic value, they are instead left monomorphic, with a warning
+    fn generalize(&mut self, bound: Range<usize>, non_value: Option<&Expr>, span: Span) {
+        let unification_vars = |ty: &Type| {
+            free_tyvars(ty)
+                .into_iter()
+                .filter(|&v| is_unification_var(v))
+                .collect::<BTreeSet<_>>()
+        };
+        let mut env = BTreeSet::new();
+        for hole in &self.holes {
+            env.extend(unification_vars(
+                &self.apply(&Type::new(TypeKind::Variable(*hole), span)),
+            ));
+        }
+        for (i, (_, scheme)) in self.values.iter().enumerate() {
+            if !bound.contains(&i) {
+                let vars = unification_vars(&self.apply(&scheme.ty));
+                env.extend(vars.into_iter().filter(|v| !scheme.vars.contains(v)));
+            }
+        }
+
+        for i in bound {
+            let ty = self.apply(&self.values[i].1.ty);
+            let vars = unification_vars(&ty)
+                .into_iter()
+                .filter(|v| !env.contains(v))
+                .collect::<Vec<_>>();
+            if vars.is_empty() {
+                continue;
+            }
+            match non_value {
+                None => self.values[i].1 = Scheme { vars, ty },
+                Some(e) => {
+                    let name = &self.values[i].0;
+                    let diag = Diagnostic::warn(
+                        span,
+                        format!(
+                            "the type of {} is not generalized, as its definition is not a syntactic value",
+                            name
+                        ),
+                    );
+                    self.warnings.push(diag.message(e.span, "this is not a value"));
+                    return;
+                }
+            }
+        }
+    }
+
+    fn constructor(&mut self, name: Symbol, span: Span) -> Type {
+        match self.constructors.get(&name).cloned() {
+            Some(ty) => self.instantiate(&ty),
+            None => self.fresh(span),
+        }
+    }
+
+    /// The type of a constructor used as a value: the curried form of one
+    /// taking a tuple, unless it is applied to a tuple of the right length
+    fn constructor_value(&mut self, name: Symbol, arg: Option<&Expr>, span: Span) -> Type {
+        let ty = match self.constructors.get(&name).cloned() {
+            Some(ty) => ty,
+            None => return self.fresh(span),
+        };
+        let arity = desugar::tuple_arity(&ty);
+        match arg.map(|e| &e.kind) {
+            Some(ExprKind::Tuple(exprs)) if arity == Some(exprs.len()) => self.constructor(name, span),
+            _ => match desugar::curried(&ty) {
+                Some(curried) => self.instantiate(&curried),
+                None => self.constructor(name, span),
+            },
+        }
+    }
+
+    /// Run `f`, then forget any value variables it bound
+    fn scoped<T, F: FnOnce(&mut Self) -> T>(&mut self, f: F) -> T {
+        let n = self.values.len();
+        let t = f(self);
+        self.values.truncate(n);
+        t
+    }
+
+    /// The type of values matched by a pattern, binding the variables in it
+    fn pattern(&mut self, pat: &Pattern) -> Type {
+        use PatKind::*;
+        let span = pat.span;
+        match &pat.kind {
+            Any => self.fresh(span),
+            Unit => Type::new(TypeKind::Unit, span),
+            Literal(_) => Type::new(TypeKind::Int, span),
+            Variable(s) => {
+                let ty = self.fresh(span);
+                self.bind_value(*s, ty.clone());
+                ty
+            }
+            Constructor(s) => self.constructor(*s, span),
+            Application(con, arg) => {
+                let con_ty = self.pattern(con);
+                let arg_ty = self.pattern(arg);
+                let (param, result) = (self.fresh(arg.span), self.fresh(span));
+                let func = Type::new(
+                    TypeKind::Function(Box::new(param.clone()), Box::new(result.clone())),
+                    span,
+                );
+                self.constrain(&func, &con_ty, con.span);
+                self.constrain(&param, &arg_ty, arg.span);
+                result
+            }
+            Product(pats) => {
+                let tys = pats.iter().map(|p| self.pattern(p)).collect();
+                Type::new(TypeKind::Product(tys), span)
+            }
+            Record(labels) => {
+                let rows = labels
+                    .iter()
+                    .map(|label| {
+                        let ty = self.fresh(span);
+                        self.bind_value(*label, ty.clone());
+                        Row {
+                            label: *label,
+                            ty,
+                            span,
+                        }
+                    })
+                    .collect();
+                Type::new(TypeKind::Record(rows, None), span)
+            }
+            As(s, p) => {
+                let ty = self.fresh(span);
+                self.bind_value(*s, ty.clone());
+                let found = self.pattern(p);
+                self.constrain(&ty, &found, p.span);
+                ty
+            }
+            Ascribe(p, ty) => {
+                self.check_abbreviations(ty);
+                let found = self.pattern(p);
+                self.constrain(ty, &found, p.span);
+                (**ty).clone()
+            }
+            Or(alts) => {
+                let start = self.values.len();
+                let ty = self.pattern(&alts[0]);
+                let bound = self.values[start..].to_vec();
+                for alt in &alts[1..] {
+                    self.scoped(|inf| {
+                        let n = inf.values.len();
+                        let alt_ty = inf.pattern(alt);
+                        inf.constrain(&ty, &alt_ty, alt.span);
+                        for (name, found) in inf.values.split_off(n) {
+                            if let Some((_, expected)) = bound.iter().find(|(s, _)| *s == name) {
+                                inf.constrain(&expected.ty, &found.ty, alt.span);
+                            }
+                        }
+                    });
+                }
+                ty
+            }
+        }
+    }
+
+    /// [`pattern`](Self::pattern), also recording the type of each variable
+    /// the pattern binds. Those of an or-pattern are recorded once, from its
+    /// first alternative, as the others are constrained to agree with it
+    fn binding_pattern(&mut self, pat: &Pattern) -> Type {
+        let n = self.values.len();
+        let ty = self.pattern(pat);
+        let mut binders = PatternBinders::default();
+        binders.visit_pat(pat);
+        for ((name, span), (_, scheme)) in binders.names.into_iter().zip(&self.values[n..]) {
+            self.bindings.push((name, scheme.ty.clone(), span));
+        }
+        ty
+    }
+
+    /// The variables bound by the patterns of case arms and value
+    /// declarations so far, with their types as solved, in source order
+    pub fn bindings(&self) -> BindingMap {
+        let mut bindings = self
+            .bindings
+            .iter()
+            .map(|(name, ty, span)| (*name, self.apply(ty), *span))
+            .collect::<BindingMap>();
+        bindings.sort_by_key(|(_, _, span)| (span.file, span.start.abs));
+        bindings
+    }
+
+    pub fn expr(&mut self, e: &Expr) -> Type {
+        use ExprKind::*;
+        let span = e.span;
+        match &e.kind {
+            Unit => Type::new(TypeKind::Unit, span),
+            Int(_) => Type::new(TypeKind::Int, span),
+            Var(s) => match self.values.iter().rev().find(|(name, _)| name == s).cloned() {
+                Some((_, scheme)) => self.instantiate_scheme(&scheme),
+                None => self.fresh(span),
+            },
+            Constr(s) => self.constructor_value(*s, None, span),
+            Path(m, x) => match self.structures.get(m).and_then(|values| values.get(x)).cloned() {
+                Some(scheme) => self.instantiate_scheme(&scheme),
+                None => self.fresh(span),
+            },
+            If(e1, e2, e3) => {
+                let cond = self.expr(e1);
+                self.constrain(&Type::new(TypeKind::Bool, e1.span), &cond, e1.span);
+                let ty = self.expr(e2);
+                let alt = self.expr(e3);
+                self.constrain(&ty, &alt, e3.span);
+                ty
+            }
+            Abs(pat, body) => self.scoped(|inf| {
+                let param = inf.pattern(pat);
+                let result = inf.expr(body);
+                Type::new(TypeKind::Function(Box::new(param), Box::new(result)), span)
+            }),
+            App(e1, e2) => {
+                let func_ty = match &e1.kind {
+                    Constr(s) => self.constructor_value(*s, Some(e2), e1.span),
+                    _ => self.expr(e1),
+                };
+                let arg_ty = self.expr(e2);
+                let (param, result) = (self.fresh(e2.span), self.fresh(span));
+                let func = Type::new(
+                    TypeKind::Function(Box::new(param.clone()), Box::new(result.clone())),
+                    span,
+                );
+                self.constrain(&func, &func_ty, e1.span);
+                self.constrain(&param, &arg_ty, e2.span);
+                result
+            }
+            TyAbs(s, k, body) => {
+                self.kinds.bind(*s, (**k).clone());
+                let ty = self.expr(body);
+                self.kinds.unbind();
+                Type::new(TypeKind::Universal(*s, k.clone(), Box::new(ty)), span)
+            }
+            TyApp(e1, arg) => {
+                let ty = self.expr(e1);
+                match self.apply(&ty).kind {
+                    TypeKind::Universal(s, _, mut body) => {
+                        SubstNamedVar::new(s, (**arg).clone()).visit_ty(&mut body);
+                        *body
+                    }
+                    _ => self.fresh(span),
+                }
+            }
+            Record(fields) => {
+                let rows = fields
+                    .iter()
+                    .map(|f| Row {
+                        label: f.label,
+                        ty: self.expr(&f.expr),
+                        span: f.span,
+                    })
+                    .collect();
+                Type::new(TypeKind::Record(rows, None), span)
+            }
+            Tuple(exprs) => {
+                let tys = exprs.iter().map(|e| self.expr(e)).collect();
+                Type::new(TypeKind::Product(tys), span)
+            }
+            Projection(e1, label) => {
+                let ty = self.expr(e1);
+                match &label.kind {
+                    Var(l) => {
+                        let field = self.fresh(label.span);
+                        let row = Row {
+                            label: *l,
+                            ty: field.clone(),
+                            span: label.span,
+                        };
+                        let tail = self.fresh_row(span);
+                        let record = Type::new(TypeKind::Record(vec![row], Some(tail)), span);
+                        self.constrain(&record, &ty, e1.span);
+                        field
+                    }
+                    Int(idx) => match self.apply(&ty).kind {
+                        TypeKind::Product(tys) if *idx < tys.len() => tys[*idx].clone(),
+                        _ => {
+                            self.errors.push(InferError::Projection(*idx, e1.span));
+                            self.fresh(span)
+                        }
+                    },
+                    _ => self.fresh(span),
+                }
+            }
+            Case(scrutinee, arms) => {
+                let ty = self.expr(scrutinee);
+                let result = self.fresh(span);
+                for arm in arms {
+                    self.scoped(|inf| {
+                        let pat = inf.binding_pattern(&arm.pat);
+                        inf.constrain(&pat, &ty, arm.pat.span);
+                        if let Some(guard) = &arm.guard {
+                            let cond = inf.expr(guard);
+                            inf.constrain(&Type::new(TypeKind::Bool, guard.span), &cond, guard.span);
+                        }
+                        let body = inf.expr(&arm.expr);
+                        inf.constrain(&result, &body, arm.expr.span);
+                    });
+                }
+                result
+            }
+            Let(decls, body) => self.scoped(|inf| {
+                for d in decls {
+                    inf.decl(d);
+                }
+                inf.expr(body)
+            }),
+            Pack(witness, e, sig) => self.pack(witness, e, sig),
+            Open(package, tyvar, var, body) => self.open(package, *tyvar, *var, body),
+        }
+    }
+
+    /// The package `pack witness, e as sig` has the type `sig`, if `e` has
+    /// the signature's body type with the witness in place of its variable
+    fn pack(&mut self, witness: &Type, e: &Expr, sig: &Type) -> Type {
+        let found = self.expr(e);
+        match self.apply(sig).kind {
+            TypeKind::Existential(s, k, mut body) => {
+                match self.kinds.kind_of(witness) {
+                    Ok(kind) if kind != *k => {
+                        let err = KindError::Mismatch(*k, kind, witness.span);
+                        self.errors.push(InferError::Kind(err));
+                    }
+                    Err(err @ KindError::Mismatch(..)) | Err(err @ KindError::NotArrow(..)) => {
+                        self.errors.push(InferError::Kind(err))
+                    }
+                    // Unbound and undefined types are reported by elaboration
+                    _ => {}
+                }
+                SubstNamedVar::new(s, witness.clone()).visit_ty(&mut body);
+                self.constrain(&body, &found, e.span);
+            }
+            _ => self.errors.push(InferError::NotExistential(self.apply(sig), sig.span)),
+        }
+        sig.clone()
+    }
+
+    /// Opening a package of type `exists ('t :: K) of sig` binds `var` to
+    /// `sig`, with `tyvar` as the abstract type in place of `'t`
+    fn open(&mut self, package: &Expr, tyvar: Symbol, var: Symbol, body: &Expr) -> Type {
+        let ty = self.expr(package);
+        match self.apply(&ty).kind {
+            TypeKind::Existential(s, k, mut sig) => {
+                let abstract_ty = Type::new(TypeKind::Variable(tyvar), package.span);
+                SubstNamedVar::new(s, abstract_ty).visit_ty(&mut sig);
+                self.kinds.bind(tyvar, *k);
+                let result = self.scoped(|inf| {
+                    inf.bind_value(var, *sig);
+                    inf.expr(body)
+                });
+                self.kinds.unbind();
+
+                let result = self.apply(&result);
+                if free_tyvars(&result).contains(&tyvar) {
+                    self.errors.push(InferError::Escape(tyvar, result, body.span));
+                    return self.fresh(body.span);
+                }
+                result
+            }
+            _ => {
+                self.errors
+                    .push(InferError::NotExistential(self.apply(&ty), package.span));
+                self.scoped(|inf| {
+                    let ty = inf.fresh(package.span);
+                    inf.bind_value(var, ty);
+                    inf.expr(body)
+                })
+            }
+        }
+    }
+
+    /// The specifications of a signature expression
+    fn specs(&mut self, sig: &Sig) -> Option<Vec<Spec>> {
+        match &sig.kind {
+            SigKind::Specs(specs) => Some(specs.clone()),
+            SigKind::Named(name) => {
+                let specs = self.signatures.get(name).cloned();
+                if specs.is_none() {
+                    let err = ModuleError::UndefinedSignature(*name, sig.span);
+                    self.errors.push(InferError::Module(err));
+                }
+                specs
+            }
+        }
+    }
+
+    /// Infer the components of structure `name`, and match them against its
+    /// signature if it has one. Only the components that the signature
+    /// specifies are visible from outside of a sealed structure
+    fn structure(&mut self, name: Symbol, sig: Option<&Sig>, decls: &[Decl]) {
+        let types = modules::type_components(decls);
+        let outer_kinds = types
+            .keys()
+            .map(|t| (*t, self.kinds.defined(*t).cloned()))
+            .collect::<Vec<_>>();
+        let outer_constructors = self.constructors.clone();
+        let values = self.scoped(|inf| {
+            let n = inf.values.len();
+            for d in decls {
+                inf.decl(d);
+            }
+            inf.values.split_off(n).into_iter().collect::<HashMap<_, _>>()
+        });
+
+        // Every type component is reached through its path from outside,
+        // and abbreviations are expanded while matching from inside
+        let path = |t: Symbol| Type::new(TypeKind::Path(name, t), Span::default());
+        let mut outside = HashMap::new();
+        let mut abbreviations = HashMap::new();
+        for (t, component) in &types {
+            outside.insert(*t, path(*t));
+            if let (Some(def), true) = (component.def, component.tyvars.is_empty()) {
+                abbreviations.insert(*t, def.clone());
+            }
+        }
+
+        let mut kinds = Vec::new();
+        let mut paths = Vec::new();
+        let mut components = HashMap::new();
+        match sig.and_then(|sig| self.specs(sig)) {
+            Some(specs) => {
+                for spec in &specs {
+                    self.match_spec(name, spec, &types, &values, &abbreviations);
+                    match &spec.kind {
+                        SpecKind::Type(tyvars, t, def) => {
+                            kinds.push((*t, desugar::constructor_kind(tyvars.len())));
+                            if let (Some(def), true) = (def, tyvars.is_empty()) {
+                                paths.push((*t, def.clone()));
+                            }
+                        }
+                        SpecKind::Value(x, ty) => {
+                            components.insert(*x, Scheme::monomorphic(ty.clone()));
+                        }
+                    }
+                }
+                outside = specs
+                    .iter()
+                    .filter_map(|spec| match &spec.kind {
+                        SpecKind::Type(_, t, _) => Some((*t, path(*t))),
+                        _ => None,
+                    })
+                    .collect();
+            }
+            None => {
+                for (t, def) in &abbreviations {
+                    paths.push((*t, def.clone()));
+                }
+                for t in types.keys() {
+                    if let Some(kind) = self.kinds.defined(*t) {
+                        kinds.push((*t, kind.clone()));
+                    }
+                }
+                for (x, scheme) in &values {
+                    let ty = self.apply(&scheme.ty);
+                    components.insert(*x, Scheme { ty, ..scheme.clone() });
+                }
+            }
+        }
+
+        for (t, kind) in outer_kinds {
+            match kind {
+                Some(kind) => self.kinds.define(t, kind),
+                None => self.kinds.undefine(t),
+            }
+        }
+        self.constructors = outer_constructors;
+
+        let qualify = |mut ty: Type| {
+            ExpandDefined::new(&outside).visit_ty(&mut ty);
+            ty
+        };
+        for (t, kind) in kinds {
+            self.kinds.define(Symbol::intern(&format!("{}.{}", name, t)), kind);
+        }
+        for (t, def) in paths {
+            self.paths
+                .insert(Symbol::intern(&format!("{}.{}", name, t)), qualify(def));
+        }
+        let components = components
+            .into_iter()
+            .map(|(x, scheme)| {
+                let ty = qualify(scheme.ty);
+                (x, Scheme { ty, ..scheme })
+            })
+            .collect();
+        self.structures.insert(name, components);
+    }
+
+    /// Check that a structure provides the component specified by `spec`.
+    /// Types are compared with the abbreviations the structure declares
+    /// expanded, so that the specification `val x : t` is met by `x = 3`
+    /// if the structure declares `type t = int`
+    fn match_spec(
+        &mut self,
+        name: Symbol,
+        spec: &Spec,
+        types: &HashMap<Symbol, modules::TypeComponent>,
+        values: &HashMap<Symbol, Scheme>,
+        abbreviations: &HashMap<Symbol, Type>,
+    ) {
+        let expand = |ty: &Type| {
+            let mut ty = ty.clone();
+            ExpandDefined::new(abbreviations).visit_ty(&mut ty);
+            ty
+        };
+        let err = match &spec.kind {
+            SpecKind::Type(tyvars, t, def) => match types.get(t) {
+                None => Some(ModuleError::MissingType(name, *t, spec.span)),
+                Some(component) if component.tyvars.len() != tyvars.len() => {
+                    Some(ModuleError::TypeMismatch(*t, spec.span))
+                }
+                Some(component) => def.as_ref().and_then(|def| {
+                    // The structure's definition, in terms of the parameters
+                    // of the specification
+                    let mut found = match component.def {
+                        Some(ty) => ty.clone(),
+                        None => tyvars
+                            .iter()
+                            .fold(Type::new(TypeKind::Defined(*t), spec.span), |ty, var| {
+                                Type::new(TypeKind::Application(Box::new(ty), Box::new(var.clone())), spec.span)
+                            }),
+                    };
+                    for (var, param) in component.tyvars.iter().zip(tyvars) {
+                        SubstNamedVar::new(var.kind.as_tyvar(), param.clone()).visit_ty(&mut found);
+                    }
+                    self.trail.clear();
+                    match self.unify(&expand(def), &expand(&found), spec.span) {
+                        Ok(()) => None,
+                        Err(_) => Some(ModuleError::TypeMismatch(*t, spec.span)),
+                    }
+                }),
+            },
+            SpecKind::Value(x, ty) => match values.get(x) {
+                None => Some(ModuleError::MissingValue(name, *x, spec.span)),
+                Some(scheme) => {
+                    let expected = expand(ty);
+                    let found = self.instantiate_scheme(scheme);
+                    self.trail.clear();
+                    match self.unify(&expected, &found, spec.span) {
+                        Ok(()) => None,
+                        Err(_) => Some(ModuleError::ValueMismatch(
+                            *x,
+                            self.apply(&expected),
+                            self.apply(&found),
+                            spec.span,
+                        )),
+                    }
+                }
+            },
+        };
+        if let Some(err) = err {
+            self.errors.push(InferError::Module(err));
+        }
+    }
+
+    /// Constrain the type `func` of a function to that of its arms
+    fn function(&mut self, func: &Type, arms: &[FnArm]) {
+        for arm in arms {
+            self.scoped(|inf| {
+                let params = arm.pats.iter().map(|p| inf.pattern(p)).collect::<Vec<_>>();
+                let result = inf.expr(&arm.expr);
+                let ty = params.into_iter().rev().fold(result, |ty, param| {
+                    Type::new(TypeKind::Function(Box::new(param), Box::new(ty)), arm.span)
+                });
+                inf.constrain(func, &ty, arm.span);
+            });
+        }
+    }
+
+    /// Generate constraints for a declaration, leaving its bindings in scope
+    pub fn decl(&mut self, d: &Decl) {
+        match &d.kind {
+            DeclKind::Type(tyvars, name, ty) => {
+                self.check_abbreviations(ty);
+                if let Err(e) = self.abbreviations.define(tyvars, *name, ty, d.span) {
+                    self.errors.push(InferError::Abbrev(e));
+                    return;
+                }
+                if let Ok(kind) = self.kinds.kind_of(&desugar::datatype(tyvars, *name, ty)) {
+                    self.kinds.define(*name, kind);
+                }
+            }
+            DeclKind::Datatype(tyvars, name, sum) => {
+                self.kinds.define(*name, desugar::constructor_kind(tyvars.len()));
+                self.constructors.extend(desugar::constructors(tyvars, *name, sum));
+            }
+            DeclKind::Value(_, pat, e) => {
+                let found = self.expr(e);
+                let n = self.values.len();
+                let expected = self.binding_pattern(pat);
+                self.constrain(&expected, &found, e.span);
+                self.generalize(n..self.values.len(), non_value(e), d.span);
+            }
+            DeclKind::Function(_, name, arms) => {
+                let func = self.fresh(d.span);
+                let n = self.values.len();
+                self.bind_value(*name, func.clone());
+                self.function(&func, arms);
+                self.generalize(n..n + 1, None, d.span);
+            }
+            DeclKind::And(..) => {
+                // Every function is in scope in all of the bodies
+                let mut decls = Vec::new();
+                flatten(d, &mut decls);
+                let n = self.values.len();
+                let mut funcs = Vec::new();
+                for d in &decls {
+                    if let DeclKind::Function(_, name, _) = &d.kind {
+                        let func = self.fresh(d.span);
+                        self.bind_value(*name, func.clone());
+                        funcs.push(func);
+                    }
+                }
+                let bound = n..self.values.len();
+                let mut funcs = funcs.into_iter();
+                for d in decls {
+                    match &d.kind {
+                        DeclKind::Function(_, _, arms) => self.function(&funcs.next().unwrap(), arms),
+                        _ => self.decl(d),
+                    }
+                }
+                // The functions are only generalized once all of them have
+                // been checked
+                self.generalize(bound, None, d.span);
+            }
+            DeclKind::Expr(e) => {
+                self.expr(e);
+            }
+            DeclKind::Signature(name, sig) => {
+                if let Some(specs) = self.specs(sig) {
+                    self.signatures.insert(*name, specs);
+                }
+            }
+            DeclKind::Structure(name, sig, decls) => self.structure(*name, sig.as_ref(), decls),
+        }
+    }
+}
+
+/// The declarations joined by `and`, in order
+fn flatten<'d>(d: &'d Decl, out: &mut Vec<&'d Decl>) {
+    match &d.kind {
+        DeclKind::And(a, b) => {
+            flatten(a, out);
+            flatten(b, out);
+        }
+        _ => out.push(d),
+    }
+}
+
+/// Replaces solved unification variables, see [`Infer::apply`]
+struct Apply<'i>(&'i Infer);
+
+impl<'i> TypeMutVisitor for Apply<'i> {
+    fn visit_record(&mut self, rows: &mut Vec<Row>, tail: &mut Option<RowVar>) {
+        while let Some((solution, _)) = tail.as_ref().and_then(|v| self.0.solved.get(&v.name)) {
+            match &solution.kind {
+                TypeKind::Record(more, rest) => {
+                    rows.extend(more.iter().cloned());
+                    *tail = rest.clone();
+                }
+                _ => break,
+            }
+        }
+        for row in rows {
+            self.visit_ty(&mut row.ty);
+        }
+    }
+
+    fn visit_ty(&mut self, ty: &mut Type) {
+        if let Some((solution, _)) = variable(ty).and_then(|v| self.0.solved.get(&v)) {
+            *ty = Type::with_id(solution.kind.clone(), ty.span, ty.id);
+            self.visit_ty(ty);
+        } else {
+            self.walk_ty(ty);
+        }
+    }
+}
+
+/// Replaces each `_` hole with a fresh unification variable, or each
+/// unsolved unification variable with a `_` hole
+struct Holes<'i> {
+    infer: &'i mut Infer,
+    holes: Vec<(Symbol, Span)>,
+}
+
+impl<'i> TypeMutVisitor for Holes<'i> {
+    fn visit_ty(&mut self, ty: &mut Type) {
+        match &ty.kind {
+            TypeKind::Infer => {
+                let name = self.infer.fresh_name();
+                self.holes.push((name, ty.span));
+                ty.kind = TypeKind::Variable(name);
+            }
+            TypeKind::Variable(v) if is_unification_var(*v) => ty.kind = TypeKind::Infer,
+            _ => self.walk_ty(ty),
+        }
+    }
+}
+
+/// Calls `f` on every type annotation in the declarations and expressions
+/// visited, including those in patterns. The types declared by `type` and
+/// `datatype` are not annotations, and are skipped
+struct Annotations<F>(F);
+
+impl<F: FnMut(&mut Type)> Annotations<F> {
+    fn visit_pat(&mut self, pat: &mut Pattern) {
+        match &mut pat.kind {
+            PatKind::Ascribe(p, ty) => {
+                self.visit_pat(p);
+                (self.0)(ty);
+            }
+            PatKind::Application(con, arg) => {
+                self.visit_pat(con);
+                self.visit_pat(arg);
+            }
+            PatKind::Product(pats) | PatKind::Or(pats) => pats.iter_mut().for_each(|p| self.visit_pat(p)),
+            PatKind::As(_, p) => self.visit_pat(p),
+            _ => {}
+        }
+    }
+}
+
+impl<F: FnMut(&mut Type)> ExprMutVisitor for Annotations<F> {
+    fn visit_decl(&mut self, d: &mut Decl) {
+        match &d.kind {
+            DeclKind::Type(..) | DeclKind::Datatype(..) => {}
+            _ => self.walk_decl(d),
+        }
+    }
+
+    fn visit_pattern(&mut self, pat: &mut Pattern) {
+        self.visit_pat(pat);
+    }
+
+    /// Neither are the types specified by signatures
+    fn visit_sig(&mut self, _: &mut Sig) {}
+
+    fn visit_type(&mut self, ty: &mut Type) {
+        (self.0)(ty)
+    }
+}
+
+/// Infer the type of every `_` hole in the annotations of `decls`, and fill
+/// it in. Holes that cannot be inferred are left in place, and the reasons
+/// are returned
+pub fn fill_holes(decls: &mut [Decl]) -> Vec<InferError> {
+    let mut infer = Infer::default();
+    let mut holes = Holes {
+        infer: &mut infer,
+        holes: Vec::new(),
+    };
+    let mut numbering = Annotations(|ty: &mut Type| holes.visit_ty(ty));
+    decls.iter_mut().for_each(|d| numbering.visit_decl(d));
+    let holes = holes.holes;
+    infer.holes = holes.iter().map(|(var, _)| *var).collect();
+
+    for d in decls.iter() {
+        infer.decl(d);
+    }
+
+    let mut errors = std::mem::take(&mut infer.errors);
+    for (var, span) in holes {
+        let solution = infer.apply(&Type::new(TypeKind::Variable(var), span));
+        if free_tyvars(&solution).iter().any(|v| is_unification_var(*v)) {
+            errors.push(InferError::Unsolved(span));
+        }
+    }
+
+    // Anything not solved becomes a hole again
+    let mut holes = Holes {
+        infer: &mut infer,
+        holes: Vec::new(),
+    };
+    let mut filling = Annotations(|ty: &mut Type| {
+        *ty = holes.infer.apply(ty);
+        holes.visit_ty(ty);
+    });
+    decls.iter_mut().for_each(|d| filling.visit_decl(d));
+    errors
+}
+
+impl InferError {
+    pub fn span(&self) -> Span {
+        match self {
+            InferError::Conflict(_, _, sp, _)
+            | InferError::Occurs(_, _, sp)
+            | InferError::Unsolved(sp)
+            | InferError::Projection(_, sp)
+            | InferError::NotExistential(_, sp)
+            | InferError::Escape(_, _, sp) => *sp,
+            InferError::Kind(e) => e.span(),
+            InferError::Module(e) => e.span(),
+            InferError::Abbrev(e) => e.span(),
+            InferError::Normalize(e) => e.span(),
+        }
+    }
+}
+
+impl fmt::Display for InferError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InferError::Conflict(expected, found, _, _) => {
+                write!(f, "type mismatch: expected {}, found {}", expected, found)
+            }
+            InferError::Occurs(var, ty, _) => {
+                write!(f, "type variable '{} would have to contain itself in {}", var, ty)
+            }
+            InferError::Unsolved(_) => write!(f, "cannot infer the type of this hole"),
+            InferError::Projection(idx, _) => {
+                write!(f, "cannot project element {} from a type not known to be a tuple", idx)
+            }
+            InferError::NotExistential(ty, _) => write!(f, "expected an existential type, not {}", ty),
+            InferError::Escape(s, ty, _) => write!(
+                f,
+                "the abstract type '{} of an opened package escapes into the type {}",
+                s, ty
+            ),
+            InferError::Kind(e) => write!(f, "{}", e),
+            InferError::Module(e) => write!(f, "{}", e),
+            InferError::Abbrev(e) => write!(f, "{}", e),
+            InferError::Normalize(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<InferError> for Diagnostic {
+    fn from(e: InferError) -> Diagnostic {
+        let diag = Diagnostic::error(e.span(), e.to_string());
+        match e {
+            InferError::Conflict(_, _, _, trail) => trail
+                .into_iter()
+                .fold(diag, |diag, sp| diag.message(sp, "type determined by this")),
+            InferError::Kind(e) => e.into(),
+            InferError::Module(e) => e.into(),
+            InferError::Abbrev(e) => e.into(),
+            InferError::Normalize(e) => e.into(),
+            _ => diag,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::syntax::parser::Parser;
+
+    /// Fill in the holes of a program, returning its annotations in order
+    fn fill(input: &str) -> (Vec<String>, Vec<InferError>) {
+        let mut decls = Parser::new(input).parse_program().unwrap().decls;
+        let errors = fill_holes(&mut decls);
+        let mut annotations = Vec::new();
+        let mut collect = Annotations(|ty: &mut Type| annotations.push(ty.to_string()));
+        decls.iter_mut().for_each(|d| collect.visit_decl(d));
+        (annotations, errors)
+    }
+
+    #[test]
+    fn application() {
+        let (tys, errors) = fill("val inc = fn (x : int) => x; val f = fn (y : _) => inc y");
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(tys, ["int", "int"]);
+
+        // Only part of an annotation may be a hole
+        let (tys, errors) = fill(
+            "datatype 'a option = None | Some of 'a; \
+             val f = fn (g : _ -> _ option) => g (); \
+             val x = f (fn u => Some 1)",
+        );
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(tys, ["unit -> int option"]);
+
+        // Nothing constrains the parameter of the identity function
+        let (tys, errors) = fill("val id = fn (x : _) => x");
+        assert_eq!(tys, ["_"]);
+        assert!(matches!(errors.as_slice(), [InferError::Unsolved(_)]));
+    }
+
+    #[test]
+    fn projection() {
+        let (tys, errors) = fill("val r = {x = 1, y = ()}; val f = fn (g : _ -> unit) => g r.x");
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(tys, ["int -> unit"]);
+
+        // Each projection adds a field to what the record must have, and
+        // the application closes it
+        let (tys, errors) = fill("val get = fn (p : _) => (p.x, p.y); val v = get {y = (), x = 1}");
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(tys, ["{x: int, y: unit}"]);
+
+        let (_, errors) = fill("val inc = fn (n : int) => n; val f = fn (y : _) => (inc y, y.a)");
+        match errors.as_slice() {
+            // The hole is solved by the first use of `y`, which the second
+            // conflicts with
+            [InferError::Conflict(expected, found, _, trail)] => {
+                assert_eq!(expected.to_string(), "{a: '?7 | '?8}");
+                assert_eq!(found.to_string(), "int");
+                assert_eq!(trail.len(), 1);
+            }
+            e => panic!("expected a conflict, not {:?}", e),
+        }
+    }
+
+    /// A counter whose hidden representation is an int
+    const COUNTER: &str = "val counter = pack int, {new = 0, get = fn (n : _) => n, inc = fn (n : int) => n} \
+                           as exists ('t :: *) of {new: 't, get: 't -> int, inc: 't -> 't}";
+
+    #[test]
+    fn packages() {
+        let input = format!("{}; val n = open counter as 'c, c in c.get (c.inc c.new) end", COUNTER);
+        let (tys, errors) = fill(&input);
+        assert!(errors.is_empty(), "{:?}", errors);
+        // The witness, the parameters of get and inc, and the signature
+        assert_eq!(tys[..3], ["int", "int", "int"]);
+
+        // The hidden state may not be returned from the package
+        let input = format!("{}; val s = open counter as 'c, c in c.inc c.new end", COUNTER);
+        let (_, errors) = fill(&input);
+        match errors.as_slice() {
+            [err @ InferError::Escape(s, ty, _)] => {
+                assert_eq!(s, "c");
+                assert_eq!(ty.to_string(), "'c");
+                assert_eq!(
+                    Diagnostic::from(err.clone()).primary.info,
+                    "the abstract type 'c of an opened package escapes into the type 'c"
+                );
+            }
+            e => panic!("expected an escaping type, not {:?}", e),
+        }
+
+        // The contents must match the signature with the witness in place
+        let (_, errors) = fill("val p = pack bool, {new = 0} as exists ('t :: *) of {new: 't}");
+        assert!(matches!(errors.as_slice(), [InferError::Conflict(..)]), "{:?}", errors);
+        let (_, errors) = fill("val p = pack fn ('a :: *) => 'a, () as exists ('t :: *) of unit");
+        assert!(
+            matches!(errors.as_slice(), [InferError::Kind(KindError::Mismatch(..))]),
+            "{:?}",
+            errors
+        );
+        let (_, errors) = fill("val p = open 1 as 't, x in x end");
+        assert!(matches!(errors.as_slice(), [InferError::NotExistential(..)]));
+    }
+
+    fn infer(input: &str) -> Infer {
+        let decls = Parser::new(input).parse_program().unwrap().decls;
+        let mut infer = Infer::default();
+        for d in &decls {
+            infer.decl(d);
+        }
+        infer
+    }
+
+    #[test]
+    fn generalization() {
+        let inf = infer("val id = fn x => x; val a = id 1; val b = id ()");
+        assert!(inf.errors.is_empty(), "{:?}", inf.errors);
+        assert!(inf.warnings.is_empty());
+        let inf = infer("fun const x y = x; val a = const 1 (); val b = const () 1");
+        assert!(inf.errors.is_empty(), "{:?}", inf.errors);
+
+        // A lambda-bound variable is monomorphic in the body
+        let inf = infer("val f = fn g => (g 1, g ())");
+        assert!(matches!(inf.errors.as_slice(), [InferError::Conflict(..)]));
+    }
+
+    #[test]
+    fn value_restriction() {
+        let inf = infer("val id = (fn x => x) (fn y => y); val a = id 1; val b = id ()");
+        assert!(matches!(inf.errors.as_slice(), [InferError::Conflict(..)]));
+        match inf.warnings.as_slice() {
+            [warning] => {
+                assert_eq!(
+                    warning.primary.info,
+                    "the type of id is not generalized, as its definition is not a syntactic value"
+                );
+                assert_eq!(warning.other[0].info, "this is not a value");
+            }
+            w => panic!("expected a warning, not {:?}", w),
+        }
+
+        // Nothing is lost by not generalizing a type that is already known
+        let inf = infer("val id = fn x => x; val n = id 1");
+        assert!(inf.warnings.is_empty());
+    }
+
+    #[test]
+    fn abbreviations() {
+        let inf = infer(
+            "type t = int; type 'a pair = 'a * 'a; \
+             val f = fn (p : t pair) => p; val x = f (1, 2); val y = f ((), 1)",
+        );
+        // `t pair` is only expanded as far as needed to compare it, and the
+        // conflict is reported between the whole argument types
+        match inf.errors.as_slice() {
+            [InferError::Conflict(expected, found, _, _)] => {
+                assert_eq!(expected.to_string(), "t * t");
+                assert_eq!(found.to_string(), "unit * int");
+            }
+            e => panic!("expected a conflict, not {:?}", e),
+        }
+
+        let inf = infer("type 'a pair = 'a * 'a; val f = fn (p : pair) => p");
+        assert!(matches!(
+            inf.errors.as_slice(),
+            [InferError::Abbrev(AbbrevError::Arity(..))]
+        ));
+    }
+
+    #[test]
+    fn normalization() {
+        let inf = infer(
+            "val twice = fn (f : int (fn ('a :: *) => 'a -> 'a)) => fn (x : int) => f (f x); \
+             val n = twice (fn (x : int) => x) 1",
+        );
+        assert_eq!(inf.errors, []);
+
+        let inf = infer("val g = fn (x : int ((fn ('x :: *) => 'x 'x) (fn ('x :: *) => 'x 'x))) => x 1");
+        match inf.errors.as_slice() {
+            [InferError::Normalize(NormalizeError::OutOfFuel(..))] => {}
+            e => panic!("expected to run out of fuel, not {:?}", e),
+        }
+    }
+
+    #[test]
+    fn eta() {
+        // The pointed form of an operator is the same type as the point-free
+        // one, even when its body is an abbreviation, which cannot be
+        // expanded without its argument
+        let program = |op: &str, other: &str| {
+            format!(
+                "datatype 'a list = Nil | Cons of 'a * 'a list; type 'a mylist = 'a list; \
+                 datatype ('a, 'b) either = Left of 'a | Right of 'b; \
+                 val f = fn (x : forall ('h :: {0}) of ({1}) 'h) => x; \
+                 val g = fn (y : forall ('h :: {0}) of ({2}) 'h) => f y",
+                "(* -> *) -> *", op, other
+            )
+        };
+        let inf = infer(&program("fn ('a :: *) => 'a mylist", "list"));
+        assert_eq!(inf.errors, []);
+        let inf = infer(&program("list", "fn ('b :: *) => 'b mylist"));
+        assert_eq!(inf.errors, []);
+        let inf = infer(&program("fn ('a :: *) => 'a mylist", "fn ('b :: *) => 'b list"));
+        assert_eq!(inf.errors, []);
+
+        // Nested, with the operator partially applied in between
+        let inf = infer(
+            &program("fn ('a :: *) => fn ('b :: *) => ('a, 'b) either", "either")
+                .replace("(* -> *) -> *", "(* -> * -> *) -> *"),
+        );
+        assert_eq!(inf.errors, []);
+        let inf = infer(&program("fn ('b :: *) => (int, 'b) either", "int either"));
+        assert_eq!(inf.errors, []);
+
+        // The body uses the parameter other than as the last argument
+        let inf = infer(&program("fn ('a :: *) => ('a * 'a) list", "list"));
+        assert!(
+            matches!(inf.errors.as_slice(), [InferError::Conflict(..)]),
+            "{:?}",
+            inf.errors
+        );
+        let inf = infer(&program("fn ('a :: *) => ('a, 'a) either", "int either"));
+        assert!(
+            matches!(inf.errors.as_slice(), [InferError::Conflict(..)]),
+            "{:?}",
+            inf.errors
+        );
+    }
+
+    #[test]
+    fn curried_constructors() {
+        let list = "datatype 'a list = Nil | Cons of 'a * 'a list; \
+                    fun map f xs = case xs of Nil => Nil | Cons (x, rest) => Cons (f x, map f rest) end; ";
+
+        // Partially applied and passed to a function, then matched on in the
+        // tuple form
+        let (tys, errors) = fill(&format!(
+            "{}val push = Cons 1; \
+             val ys = map (Cons 1) (Cons (Nil, Cons (push Nil, Nil))); \
+             val n = case ys of Cons (Cons (x, _), _) => x | _ => 0 end; \
+             val f = fn (g : _) => Cons 2 (g (Cons 1))",
+            list
+        ));
+        assert_eq!(errors, []);
+        assert_eq!(tys, ["(int list -> int list) -> int list"]);
+
+        // Applied to a tuple of another length, it is the curried form, so
+        // the result is a function and not a list
+        let inf = infer(&format!(
+            "{}val n = case Cons (1, Nil, Nil) of Nil => 0 | _ => 1 end",
+            list
+        ));
+        assert!(
+            matches!(inf.errors.as_slice(), [InferError::Conflict(..)]),
+            "{:?}",
+            inf.errors
+        );
+    }
+
+    #[test]
+    fn occurs() {
+        // The hole is known to be a function, but not which
+        let (tys, errors) = fill("val f = fn (x : _) => x x");
+        assert_eq!(tys, ["_ -> _"]);
+        match errors.as_slice() {
+            [err @ InferError::Occurs(..), InferError::Unsolved(_)] => {
+                assert_eq!(
+                    Diagnostic::from(err.clone()).primary.info,
+                    "type variable '?3 would have to contain itself in '?3 -> '?4"
+                );
+            }
+            e => panic!("expected an occurs check failure, not {:?}", e),
+        }
+    }
+}
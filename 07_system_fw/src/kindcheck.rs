@@ -0,0 +1,742 @@
+//! Kind checking of surface syntax types
+//!
+//! Type abstractions, applications and quantifiers all carry [`Kind`]s,
+//! and this pass makes sure they are respected before a type goes any
+//! further: a type may only be applied to an argument if it has an arrow
+//! kind, the argument must have the kind the arrow expects, and the
+//! components of functions, products, records, sums and the bodies of
+//! quantified types must all be proper types of kind `*`. Row variables
+//! have the kind `row`, and may only end an open record type
+//!
+//! The kind of a binder may be left out, as in `forall 'a of 'a -> 'a` or
+//! `fn ('f :: _) => int 'f`, to be inferred from how the variable is used
+//! in its scope. Each such binder gets a kind metavariable, which is solved
+//! by unification wherever the kind of a type is required to be something:
+//! `'f` must have the kind `* -> _` to be applied to `int`. Metavariables
+//! that nothing constrains default to `*`
+//!
+//! A binder whose kind is given is held to it instead: the first use of its
+//! variable that requires another kind, as in `forall ('a :: *) of int 'a`,
+//! is reported along with the binder
+use crate::diagnostics::Diagnostic;
+use crate::stack::Stack;
+use crate::symbol::Symbol;
+use crate::syntax::ast::{Kind, Type, TypeKind};
+use crate::syntax::visit::TypeMutVisitor;
+use std::collections::HashMap;
+use std::fmt;
+use util::span::Span;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum KindError {
+    /// A type of the first kind is required, but the type at `Span` has
+    /// the second kind
+    Mismatch(Kind, Kind, Span),
+    /// The type at `Span` is applied to an argument, but it has this kind,
+    /// which is not an arrow
+    NotArrow(Kind, Span),
+    UnboundVariable(Symbol, Span),
+    UndefinedType(Symbol, Span),
+    /// A label appears twice in the same record type, at both spans
+    DuplicateLabel(Symbol, Span, Span),
+    /// The type variable, whose kind is being inferred, is used with the
+    /// first kind at the first span, but with the second kind at the second
+    Conflict(Symbol, Box<Kind>, Span, Box<Kind>, Span),
+    /// The type variable is declared with the first kind by the binder at
+    /// the first span, but its use at the second span requires the second
+    Declared(Symbol, Box<Kind>, Span, Box<Kind>, Span),
+}
+
+/// A kind metavariable, and the kind it has been solved to along with the
+/// span of the use that solved it. Those standing for the kind of a binder
+/// have its name
+#[derive(Debug)]
+struct KindVar {
+    binder: Option<Symbol>,
+    span: Span,
+    solution: Option<(Kind, Span)>,
+}
+
+/// A kinding context, Δ, holding the kinds of the type variables bound by
+/// enclosing abstractions and quantifiers, and of defined type names
+#[derive(Debug)]
+pub struct KindContext {
+    /// Each type variable in scope, with its kind and, if the kind is
+    /// given by its binder rather than inferred, the span of the binder
+    tyvars: Stack<(Symbol, Kind, Option<Span>)>,
+    defined: HashMap<Symbol, Kind>,
+    kvars: Vec<KindVar>,
+    /// Notes on the binders whose kinds defaulted to `*`
+    pub notes: Vec<Diagnostic>,
+}
+
+impl Default for KindContext {
+    fn default() -> KindContext {
+        KindContext {
+            tyvars: Stack::with_capacity(16),
+            defined: HashMap::new(),
+            kvars: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+}
+
+impl KindContext {
+    /// Give the defined type `name` a kind, e.g. `* -> *` for `list`
+    pub fn define<S: Into<Symbol>>(&mut self, name: S, kind: Kind) {
+        self.defined.insert(name.into(), kind);
+    }
+
+    /// The kind of the defined type `name`, if it has been given one
+    pub fn defined(&self, name: Symbol) -> Option<&Kind> {
+        self.defined.get(&name)
+    }
+
+    /// Forget the kind of the defined type `name`, when it goes out of scope
+    pub fn undefine(&mut self, name: Symbol) {
+        self.defined.remove(&name);
+    }
+
+    /// Bind the type variable `name` to `kind`, until the matching `unbind`
+    pub fn bind<S: Into<Symbol>>(&mut self, name: S, kind: Kind) {
+        self.tyvars.push((name.into(), kind, None));
+    }
+
+    /// Remove the innermost type variable binding
+    pub fn unbind(&mut self) {
+        self.tyvars.pop();
+    }
+
+    /// Find the kind of the innermost type variable bound as `name`
+    fn lookup(&self, name: Symbol) -> Option<&Kind> {
+        self.tyvars.iter().rev().find(|(s, ..)| *s == name).map(|(_, k, _)| k)
+    }
+
+    /// Compute the kind of `ty` with `name` bound to `kind` by the binder at
+    /// `span`. Unless the kind is being inferred, any use of `name` that
+    /// requires another kind is reported along with the binder
+    fn with_tyvar(&mut self, name: Symbol, kind: &Kind, span: Span, ty: &Type) -> Result<Kind, KindError> {
+        let declared = if self.solved(kind) { Some(span) } else { None };
+        self.tyvars.push((name, kind.clone(), declared));
+        let k = self.kind(ty);
+        self.tyvars.pop();
+        k
+    }
+
+    /// If the binder of the type variable `name` declares its kind, the
+    /// error for using it at `span` where a type of kind `required` is
+    fn declared(&self, name: Symbol, span: Span, required: &Kind) -> Option<KindError> {
+        match self.tyvars.iter().rev().find(|(s, ..)| *s == name) {
+            Some((s, k, Some(binder))) => Some(KindError::Declared(
+                *s,
+                Box::new(k.clone()),
+                *binder,
+                Box::new(self.resolve(required)),
+                span,
+            )),
+            _ => None,
+        }
+    }
+
+    /// [`declared`](Self::declared), if `ty` is a type variable
+    fn misuse(&self, ty: &Type, required: &Kind) -> Option<KindError> {
+        match &ty.kind {
+            TypeKind::Variable(s) => self.declared(*s, ty.span, required),
+            _ => None,
+        }
+    }
+
+    fn fresh(&mut self, binder: Option<Symbol>, span: Span) -> Kind {
+        self.kvars.push(KindVar {
+            binder,
+            span,
+            solution: None,
+        });
+        Kind::Var(self.kvars.len() - 1)
+    }
+
+    /// The kind of the variable bound as `name` by the binder at `span`, a
+    /// fresh metavariable if it is left to be inferred
+    fn binder(&mut self, name: Symbol, kind: &Kind, span: Span) -> Kind {
+        match kind {
+            Kind::Infer => self.fresh(Some(name), span),
+            k => k.clone(),
+        }
+    }
+
+    /// Replace the solved metavariables in `kind` with their solutions
+    pub fn resolve(&self, kind: &Kind) -> Kind {
+        match kind {
+            Kind::Var(n) => match &self.kvars[*n].solution {
+                Some((k, _)) => self.resolve(k),
+                None => kind.clone(),
+            },
+            Kind::Arrow(k1, k2) => Kind::Arrow(Box::new(self.resolve(k1)), Box::new(self.resolve(k2))),
+            k => k.clone(),
+        }
+    }
+
+    fn occurs(&self, n: usize, kind: &Kind) -> bool {
+        match self.resolve(kind) {
+            Kind::Var(m) => n == m,
+            Kind::Arrow(k1, k2) => self.occurs(n, &k1) || self.occurs(n, &k2),
+            _ => false,
+        }
+    }
+
+    /// Solve metavariables so that `found` is the `expected` kind, for the
+    /// use at `span`, returning whether it can be
+    fn unify(&mut self, expected: &Kind, found: &Kind, span: Span) -> bool {
+        match (self.resolve(expected), self.resolve(found)) {
+            (Kind::Var(m), Kind::Var(n)) if m == n => true,
+            (Kind::Var(n), k) | (k, Kind::Var(n)) => {
+                if self.occurs(n, &k) {
+                    return false;
+                }
+                self.kvars[n].solution = Some((k, span));
+                true
+            }
+            (Kind::Arrow(a1, a2), Kind::Arrow(b1, b2)) => self.unify(&a1, &b1, span) && self.unify(&a2, &b2, span),
+            (a, b) => a == b,
+        }
+    }
+
+    /// If `kind` is the metavariable of a binder, solved by an earlier use,
+    /// the conflict between that use and the one at `span` requiring `other`
+    fn conflict(&self, kind: &Kind, other: &Kind, span: Span) -> Option<KindError> {
+        match kind {
+            Kind::Var(n) => match &self.kvars[*n] {
+                KindVar {
+                    binder: Some(s),
+                    solution: Some((_, first)),
+                    ..
+                } => Some(KindError::Conflict(
+                    *s,
+                    Box::new(self.resolve(kind)),
+                    *first,
+                    Box::new(self.resolve(other)),
+                    span,
+                )),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Require that `found`, the kind of the type at `span`, is `expected`
+    fn expect(&mut self, expected: &Kind, found: &Kind, span: Span) -> Result<(), KindError> {
+        if self.unify(expected, found, span) {
+            return Ok(());
+        }
+        Err(self
+            .conflict(found, expected, span)
+            .or_else(|| self.conflict(expected, found, span))
+            .unwrap_or_else(|| KindError::Mismatch(self.resolve(expected), self.resolve(found), span)))
+    }
+
+    /// Require that `ty` is a proper type, of kind `*`
+    fn star(&mut self, ty: &Type) -> Result<(), KindError> {
+        let k = self.kind(ty)?;
+        self.expect(&Kind::Star, &k, ty.span)
+            .map_err(|e| self.misuse(ty, &Kind::Star).unwrap_or(e))
+    }
+
+    /// The kind of `ty`, with the kinds of any of its binders that are left
+    /// to be inferred solved as far as its uses determine them
+    pub fn kind_of(&mut self, ty: &Type) -> Result<Kind, KindError> {
+        let k = self.kind(ty)?;
+        Ok(self.resolve(&k))
+    }
+
+    /// Infer the kinds of the binders in `ty` that are left to be inferred,
+    /// writing them back into `ty`, and return its kind. A kind that its
+    /// uses do not determine defaults to `*`, with a note
+    pub fn infer_kinds(&mut self, ty: &mut Type) -> Result<Kind, KindError> {
+        let first = self.kvars.len();
+        let mut fresh = FreshKinds(self);
+        fresh.visit_ty(ty);
+        let kind = self.kind(ty);
+
+        for n in first..self.kvars.len() {
+            if let KindVar {
+                binder: Some(s), span, ..
+            } = &self.kvars[n]
+            {
+                let k = self.resolve(&Kind::Var(n));
+                if kind.is_ok() && !self.solved(&k) {
+                    let note = format!(
+                        "the kind of '{} is not fully determined by its uses, so it is taken to be {}",
+                        s,
+                        default(&k)
+                    );
+                    self.notes.push(Diagnostic::warn(*span, note));
+                }
+            }
+        }
+        for n in first..self.kvars.len() {
+            let k = default(&self.resolve(&Kind::Var(n)));
+            let span = self.kvars[n].span;
+            self.kvars[n].solution = Some((k, span));
+        }
+        ResolveKinds(self).visit_ty(ty);
+        kind.map(|k| self.resolve(&k))
+    }
+
+    /// Whether no metavariables are left unsolved in `kind`
+    fn solved(&self, kind: &Kind) -> bool {
+        match self.resolve(kind) {
+            Kind::Var(_) | Kind::Infer => false,
+            Kind::Arrow(k1, k2) => self.solved(&k1) && self.solved(&k2),
+            _ => true,
+        }
+    }
+
+    fn kind(&mut self, ty: &Type) -> Result<Kind, KindError> {
+        use TypeKind::*;
+        match &ty.kind {
+            // A placeholder is only ever left for the type of a value, so
+            // it stands for a proper type
+            Int | Bool | Unit | Infer => Ok(Kind::Star),
+            Defined(s) => self
+                .defined
+                .get(s)
+                .cloned()
+                .ok_or(KindError::UndefinedType(*s, ty.span)),
+            // The kinds of the type components of structures are defined
+            // under their paths
+            Path(m, s) => {
+                let path = Symbol::intern(&format!("{}.{}", m, s));
+                match self.defined.get(&path) {
+                    Some(k) => Ok(k.clone()),
+                    None => Err(KindError::UndefinedType(path, ty.span)),
+                }
+            }
+            Variable(s) => self.lookup(*s).cloned().ok_or(KindError::UnboundVariable(*s, ty.span)),
+            Function(ty1, ty2) => {
+                self.star(ty1)?;
+                self.star(ty2)?;
+                Ok(Kind::Star)
+            }
+            Sum(variants) => {
+                for ty in variants.iter().filter_map(|v| v.ty.as_ref()) {
+                    self.star(ty)?;
+                }
+                Ok(Kind::Star)
+            }
+            Product(tys) => {
+                for ty in tys {
+                    self.star(ty)?;
+                }
+                Ok(Kind::Star)
+            }
+            Record(rows, tail) => {
+                for (i, row) in rows.iter().enumerate() {
+                    if let Some(prev) = rows[..i].iter().find(|r| r.label == row.label) {
+                        return Err(KindError::DuplicateLabel(row.label, prev.span, row.span));
+                    }
+                    self.star(&row.ty)?;
+                }
+                if let Some(var) = tail {
+                    match self.lookup(var.name).cloned() {
+                        Some(k) => self
+                            .expect(&Kind::Row, &k, var.span)
+                            .map_err(|e| self.declared(var.name, var.span, &Kind::Row).unwrap_or(e))?,
+                        None => return Err(KindError::UnboundVariable(var.name, var.span)),
+                    }
+                }
+                Ok(Kind::Star)
+            }
+            Existential(s, k, body) | Universal(s, k, body) => {
+                let k = self.binder(*s, k, ty.span);
+                let k2 = self.with_tyvar(*s, &k, ty.span, body)?;
+                self.expect(&Kind::Star, &k2, body.span)?;
+                Ok(Kind::Star)
+            }
+            Abstraction(s, k, body) => {
+                let k = self.binder(*s, k, ty.span);
+                let k2 = self.with_tyvar(*s, &k, ty.span, body)?;
+                Ok(Kind::Arrow(Box::new(k), Box::new(k2)))
+            }
+            Application(ty1, ty2) => {
+                let k = self.kind(ty1)?;
+                match self.resolve(&k) {
+                    Kind::Arrow(k1, k2) => {
+                        let k = self.kind(ty2)?;
+                        self.expect(&k1, &k, ty2.span)
+                            .map_err(|e| self.misuse(ty2, &k1).unwrap_or(e))?;
+                        Ok(*k2)
+                    }
+                    // An operator whose kind is being inferred
+                    Kind::Var(_) => {
+                        let arg = self.kind(ty2)?;
+                        let result = self.fresh(None, ty.span);
+                        let arrow = Kind::Arrow(Box::new(arg), Box::new(result.clone()));
+                        self.expect(&arrow, &k, ty1.span)?;
+                        Ok(result)
+                    }
+                    resolved => {
+                        let arg = self.kind(ty2)?;
+                        let arrow = Kind::Arrow(Box::new(arg), Box::new(Kind::Infer));
+                        Err(self
+                            .conflict(&k, &arrow, ty1.span)
+                            .or_else(|| self.misuse(ty1, &arrow))
+                            .unwrap_or(KindError::NotArrow(resolved, ty1.span)))
+                    }
+                }
+            }
+            // rec T is the fixed point of an operator T :: K -> K
+            Recursive(inner) => {
+                let k = self.kind(inner)?;
+                match self.resolve(&k) {
+                    Kind::Arrow(k1, k2) if self.unify(&k1, &k2, inner.span) => Ok(self.resolve(&k1)),
+                    Kind::Arrow(..) => Err(KindError::Mismatch(
+                        Kind::Arrow(Box::new(Kind::Star), Box::new(Kind::Star)),
+                        self.resolve(&k),
+                        inner.span,
+                    )),
+                    Kind::Var(_) => {
+                        let k1 = self.fresh(None, inner.span);
+                        let arrow = Kind::Arrow(Box::new(k1.clone()), Box::new(k1.clone()));
+                        self.expect(&arrow, &k, inner.span)?;
+                        Ok(k1)
+                    }
+                    resolved => Err(KindError::NotArrow(resolved, inner.span)),
+                }
+            }
+        }
+    }
+}
+
+/// The kind left once every unsolved metavariable in `kind` defaults to `*`
+fn default(kind: &Kind) -> Kind {
+    match kind {
+        Kind::Var(_) | Kind::Infer => Kind::Star,
+        Kind::Arrow(k1, k2) => Kind::Arrow(Box::new(default(k1)), Box::new(default(k2))),
+        k => k.clone(),
+    }
+}
+
+/// Gives each binder whose kind is to be inferred a fresh metavariable
+struct FreshKinds<'a>(&'a mut KindContext);
+
+impl<'a> TypeMutVisitor for FreshKinds<'a> {
+    fn visit_ty(&mut self, ty: &mut Type) {
+        let span = ty.span;
+        match &mut ty.kind {
+            TypeKind::Existential(s, k, _) | TypeKind::Universal(s, k, _) | TypeKind::Abstraction(s, k, _)
+                if **k == Kind::Infer =>
+            {
+                **k = self.0.fresh(Some(*s), span);
+            }
+            _ => {}
+        }
+        self.walk_ty(ty);
+    }
+}
+
+/// Replaces the metavariables of binders with their solutions
+struct ResolveKinds<'a>(&'a KindContext);
+
+impl<'a> TypeMutVisitor for ResolveKinds<'a> {
+    fn visit_existential(&mut self, _: &mut Symbol, k: &mut Kind, ty: &mut Type) {
+        *k = self.0.resolve(k);
+        self.visit_ty(ty);
+    }
+
+    fn visit_universal(&mut self, _: &mut Symbol, k: &mut Kind, ty: &mut Type) {
+        *k = self.0.resolve(k);
+        self.visit_ty(ty);
+    }
+
+    fn visit_abstraction(&mut self, _: &mut Symbol, k: &mut Kind, ty: &mut Type) {
+        *k = self.0.resolve(k);
+        self.visit_ty(ty);
+    }
+}
+
+impl KindError {
+    pub fn span(&self) -> Span {
+        match self {
+            KindError::Mismatch(_, _, sp)
+            | KindError::NotArrow(_, sp)
+            | KindError::UnboundVariable(_, sp)
+            | KindError::UndefinedType(_, sp)
+            | KindError::DuplicateLabel(_, _, sp)
+            | KindError::Conflict(_, _, _, _, sp)
+            | KindError::Declared(_, _, _, _, sp) => *sp,
+        }
+    }
+}
+
+impl fmt::Display for KindError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KindError::Mismatch(expected, found, _) => write!(
+                f,
+                "a type of kind {} is required, but a type of kind {} was supplied",
+                expected, found
+            ),
+            KindError::NotArrow(k, _) => write!(
+                f,
+                "a type of kind {} cannot be applied to an argument, an arrow kind is required",
+                k
+            ),
+            KindError::UnboundVariable(s, _) => write!(f, "unbound type variable '{}", s),
+            KindError::UndefinedType(s, _) => write!(f, "undefined type {}", s),
+            KindError::DuplicateLabel(s, _, _) => write!(f, "label {} appears more than once in a record type", s),
+            KindError::Conflict(s, _, _, k, _) => write!(
+                f,
+                "type variable '{} is used with kind {} here, but with a different kind before",
+                s, k
+            ),
+            KindError::Declared(s, _, _, k, _) => write!(
+                f,
+                "type variable '{} is used where a type of kind {} is required, but is declared with a different kind",
+                s, k
+            ),
+        }
+    }
+}
+
+impl From<KindError> for Diagnostic {
+    fn from(e: KindError) -> Diagnostic {
+        match &e {
+            KindError::DuplicateLabel(s, first, _) => {
+                Diagnostic::error(e.span(), e.to_string()).message(*first, format!("{} is first given a type here", s))
+            }
+            KindError::Conflict(s, k, first, _, _) => Diagnostic::error(e.span(), e.to_string())
+                .message(*first, format!("'{} is used with kind {} here", s, k)),
+            KindError::Declared(s, k, binder, _, _) => Diagnostic::error(e.span(), e.to_string())
+                .message(*binder, format!("'{} is declared with kind {} here", s, k)),
+            _ => Diagnostic::error(e.span(), e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::syntax::parser::Parser;
+
+    fn kind_of(ctx: &mut KindContext, input: &str) -> Result<Kind, KindError> {
+        let ty = Parser::new(input).parse_type().unwrap();
+        ctx.kind_of(&ty)
+    }
+
+    fn arrow(k1: Kind, k2: Kind) -> Kind {
+        Kind::Arrow(Box::new(k1), Box::new(k2))
+    }
+
+    #[test]
+    fn abbreviation() {
+        let mut ctx = KindContext::default();
+        let list = "fn ('a :: *) => rec fn ('l :: *) => {head: 'a, tail: 'l}";
+        let k = kind_of(&mut ctx, list).unwrap();
+        assert_eq!(k, arrow(Kind::Star, Kind::Star));
+        assert_eq!(k.to_string(), "* -> *");
+
+        ctx.define("list", k);
+        assert_eq!(kind_of(&mut ctx, "int list -> bool list"), Ok(Kind::Star));
+        assert_eq!(kind_of(&mut ctx, "forall ('f :: * -> *) of int 'f"), Ok(Kind::Star));
+        assert_eq!(
+            kind_of(&mut ctx, "fn ('f :: * -> *) => fn ('a :: *) => 'a 'f"),
+            Ok(arrow(arrow(Kind::Star, Kind::Star), arrow(Kind::Star, Kind::Star)))
+        );
+        assert_eq!(
+            kind_of(&mut ctx, "exists ('t :: *) of {new: 't, get: 't -> int}"),
+            Ok(Kind::Star)
+        );
+    }
+
+    #[test]
+    fn arity() {
+        let mut ctx = KindContext::default();
+        ctx.define("list", arrow(Kind::Star, Kind::Star));
+        assert_eq!(
+            kind_of(&mut ctx, "(int, bool) list"),
+            Err(KindError::NotArrow(Kind::Star, Span::default()))
+        );
+        assert!(matches!(
+            kind_of(&mut ctx, "bool int"),
+            Err(KindError::NotArrow(Kind::Star, _))
+        ));
+
+        // An operator is not a proper type
+        assert_eq!(
+            kind_of(&mut ctx, "list -> int"),
+            Err(KindError::Mismatch(
+                Kind::Star,
+                arrow(Kind::Star, Kind::Star),
+                Span::default()
+            ))
+        );
+    }
+
+    #[test]
+    fn mismatch() {
+        let mut ctx = KindContext::default();
+        let err = kind_of(&mut ctx, "int (fn ('f :: * -> *) => int 'f)").unwrap_err();
+        assert_eq!(
+            err,
+            KindError::Mismatch(arrow(Kind::Star, Kind::Star), Kind::Star, Span::default())
+        );
+        let diag = Diagnostic::from(err);
+        assert_eq!(
+            diag.primary.info,
+            "a type of kind * -> * is required, but a type of kind * was supplied"
+        );
+
+        assert!(matches!(
+            kind_of(&mut ctx, "forall ('a :: *) of 'b"),
+            Err(KindError::UnboundVariable(s, _)) if s == "b"
+        ));
+        assert!(matches!(
+            kind_of(&mut ctx, "int option"),
+            Err(KindError::UndefinedType(s, _)) if s == "option"
+        ));
+    }
+
+    #[test]
+    fn rows() {
+        let mut ctx = KindContext::default();
+        assert_eq!(
+            kind_of(&mut ctx, "forall ('r :: row) of {x: int | 'r} -> int"),
+            Ok(Kind::Star)
+        );
+        assert_eq!(arrow(Kind::Row, Kind::Star).to_string(), "row -> *");
+
+        // A row is not a proper type, and a proper type does not end a record
+        assert!(matches!(
+            kind_of(&mut ctx, "forall ('r :: row) of 'r -> int"),
+            Err(KindError::Declared(s, k1, _, k2, _)) if s == "r" && *k1 == Kind::Row && *k2 == Kind::Star
+        ));
+        assert!(matches!(
+            kind_of(&mut ctx, "forall ('a :: *) of {x: 'a | 'a}"),
+            Err(KindError::Declared(s, k1, _, k2, _)) if s == "a" && *k1 == Kind::Star && *k2 == Kind::Row
+        ));
+        assert_eq!(
+            kind_of(&mut ctx, "forall ('r :: row) of {x: int | 'r} -> int {y: int}"),
+            Err(KindError::NotArrow(Kind::Star, Span::default()))
+        );
+        assert!(matches!(
+            kind_of(&mut ctx, "{x: int | 'r}"),
+            Err(KindError::UnboundVariable(s, _)) if s == "r"
+        ));
+
+        let err = kind_of(&mut ctx, "{x: int, y: bool, x: bool}").unwrap_err();
+        assert!(matches!(&err, KindError::DuplicateLabel(s, _, _) if s == "x"));
+        assert_eq!(Diagnostic::from(err).other.len(), 1);
+    }
+
+    fn infer(ctx: &mut KindContext, input: &str) -> (Result<Kind, KindError>, String) {
+        let mut ty = Parser::new(input).parse_type().unwrap();
+        let k = ctx.infer_kinds(&mut ty);
+        (k, ty.to_string())
+    }
+
+    #[test]
+    fn inference() {
+        let mut ctx = KindContext::default();
+        let (k, ty) = infer(&mut ctx, "fn 'f => int 'f");
+        assert_eq!(k, Ok(arrow(arrow(Kind::Star, Kind::Star), Kind::Star)));
+        assert_eq!(ty, "fn ('f :: * -> *) => int 'f");
+        // Nothing requires the body of an abstraction to be a proper type,
+        // so the result kind of 'f defaults
+        assert_eq!(
+            ctx.notes.pop().unwrap().primary.info,
+            "the kind of 'f is not fully determined by its uses, so it is taken to be * -> *"
+        );
+
+        let (k, ty) = infer(&mut ctx, "forall 'a of 'a -> 'a");
+        assert_eq!(k, Ok(Kind::Star));
+        assert_eq!(ty, "forall ('a :: *) of 'a -> 'a");
+        assert!(ctx.notes.is_empty());
+
+        // Inferred and annotated binders mix, and the operator's kind is
+        // determined by the binder it is applied to
+        let (k, ty) = infer(&mut ctx, "fn ('f :: _) => fn ('a :: * -> *) => 'a 'f");
+        assert_eq!(k.unwrap().to_string(), "((* -> *) -> *) -> (* -> *) -> *");
+        assert_eq!(ty, "fn ('f :: (* -> *) -> *) => fn ('a :: * -> *) => 'a 'f");
+        assert_eq!(ctx.notes.len(), 1);
+        assert_eq!(
+            ctx.notes[0].primary.info,
+            "the kind of 'f is not fully determined by its uses, so it is taken to be (* -> *) -> *"
+        );
+        assert_eq!(kind_of(&mut ctx, "forall ('r) of {x: int | 'r}"), Ok(Kind::Star));
+    }
+
+    #[test]
+    fn conflict() {
+        let mut ctx = KindContext::default();
+        let (k, ty) = infer(&mut ctx, "fn 'f => int 'f -> 'f");
+        let err = k.unwrap_err();
+        assert_eq!(
+            err,
+            KindError::Conflict(
+                "f".into(),
+                Box::new(arrow(Kind::Star, Kind::Star)),
+                Span::default(),
+                Box::new(Kind::Star),
+                Span::default()
+            )
+        );
+        let diag = Diagnostic::from(err);
+        assert_eq!(
+            diag.primary.info,
+            "type variable 'f is used with kind * here, but with a different kind before"
+        );
+        assert_eq!(diag.other[0].info, "'f is used with kind * -> * here");
+        assert_eq!(ty, "fn ('f :: * -> *) => int 'f -> 'f");
+
+        // Used as a proper type before being applied
+        assert!(matches!(
+            infer(&mut ctx, "forall 'f of 'f -> int 'f").0,
+            Err(KindError::Conflict(s, k1, _, k2, _)) if s == "f" && *k1 == Kind::Star && k2.to_string() == "* -> _"
+        ));
+        // Self application has no kind
+        assert!(matches!(
+            infer(&mut ctx, "fn 'x => 'x 'x").0,
+            Err(KindError::Mismatch(..))
+        ));
+    }
+
+    #[test]
+    fn declared() {
+        // A binder whose kind is given is held to it, and the first use
+        // requiring another kind is reported along with the binder
+        let mut ctx = KindContext::default();
+        let input = "forall ('a :: *) of 'a -> int 'a";
+        let ty = Parser::with_spans(input).parse_type().unwrap();
+        let err = ctx.kind_of(&ty).unwrap_err();
+        assert!(matches!(
+            &err,
+            KindError::Declared(s, k1, _, k2, _) if s == "a" && **k1 == Kind::Star && k2.to_string() == "* -> _"
+        ));
+        let diag = Diagnostic::from(err);
+        assert_eq!(
+            diag.primary.info,
+            "type variable 'a is used where a type of kind * -> _ is required, but is declared with a different kind"
+        );
+        assert_eq!((diag.primary.span.start.col, diag.primary.span.end.col), (30, 31));
+        assert_eq!(diag.other[0].info, "'a is declared with kind * here");
+        assert_eq!(diag.other[0].span.start.col, 0);
+
+        let mut ctx = KindContext::default();
+        ctx.define("list", arrow(Kind::Star, Kind::Star));
+        assert!(matches!(
+            kind_of(&mut ctx, "fn ('f :: * -> *) => int ('f list)"),
+            Err(KindError::Declared(s, _, _, k, _)) if s == "f" && *k == Kind::Star
+        ));
+        // Shadowing binders are each held to their own kind
+        assert_eq!(
+            kind_of(&mut ctx, "forall ('a :: * -> *) of forall ('a :: *) of 'a -> 'a"),
+            Ok(Kind::Star)
+        );
+
+        // Left out, the binder's kind is inferred from the same use instead
+        let (k, ty) = infer(&mut ctx, "forall 'a of int 'a");
+        assert_eq!(k, Ok(Kind::Star));
+        assert_eq!(ty, "forall ('a :: * -> *) of int 'a");
+        assert!(ctx.notes.is_empty());
+    }
+}
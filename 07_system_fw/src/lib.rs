@@ -0,0 +1,43 @@
+#![allow(dead_code)]
+#[macro_use]
+pub mod macros;
+pub mod abbrev;
+pub mod coverage;
+pub mod dependencies;
+pub mod desugar;
+pub mod diagnostics;
+pub mod driver;
+pub mod elaborate;
+pub mod functor;
+pub mod hir;
+pub mod infer;
+pub mod kindcheck;
+pub mod lower;
+pub mod modules;
+pub mod normalize;
+pub mod prelude;
+pub mod rows;
+pub mod scopecheck;
+pub mod stack;
+pub mod symbol;
+pub mod syntax;
+pub mod terms;
+pub mod typecheck;
+pub mod types;
+pub mod validate;
+
+use syntax::ast;
+use terms::Term;
+use types::Type;
+use util::span::Span;
+
+fn unfold(ty: Type) -> Type {
+    match &ty {
+        Type::Recursive(inner) => op_app!(*inner.clone(), ty),
+        Type::App(a, b) => match a.as_ref() {
+            Type::Recursive(_) => op_app!(unfold(*a.clone()), *b.clone()),
+            _ => ty,
+        },
+        _ => ty,
+    }
+}
@@ -0,0 +1,1102 @@
+//! Lowering of programs to the core terms of the `system_f` crate
+//!
+//! A program that has been ordered, scope checked, inferred and elaborated
+//! without errors can be translated into a single System F term, which
+//! `system_f` can then type check and evaluate, as a second opinion on the
+//! checks of this crate. The lowering works on the syntax tree, so that an
+//! error can point at what it is about:
+//!
+//! - a datatype becomes the recursive variant `rec T. <C1: ty1 | ..>`, with
+//!   its parameters substituted at each use, as System F has no type
+//!   operators. A datatype or type abbreviation without parameters is
+//!   declared as an alias instead, wherever it does not depend on a type
+//!   variable in scope
+//! - a constructor becomes the fold of an injection, inside functions taking
+//!   its payload when it is not applied to the whole of it
+//! - a case becomes a case on the scrutinee, ascribed the type that its
+//!   patterns imply, since System F has to know the type of a value before
+//!   it can be matched. A function declared with `fun` becomes a fixed point
+//!   of a case over its arguments, and functions declared together with `and`
+//!   the components of the fixed point of a tuple
+//! - the declarations of a program become nested lets, around a tuple of
+//!   the value of each top level `val` of a variable and expression
+//!
+//! Types left out of a program become holes, which `system_f` infers under
+//! let-polymorphism. So do the type variables declared by a `val` or `fun`,
+//! which are generalized implicitly, with nothing in System F to stand for
+//! them. Records, rows, structures and signatures, and type operators other
+//! than datatypes and abbreviations, have no counterpart yet
+use crate::diagnostics::Diagnostic;
+use crate::symbol::Symbol;
+use crate::syntax::ast::{Decl, DeclKind, Expr, ExprKind, FnArm, Kind, PatKind, Pattern, Program, Type, TypeKind};
+use crate::syntax::visit::{SubstNamedVar, TypeMutVisitor};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use system_f::eval::{self, EvalOutcome, Limits};
+use system_f::patterns::Pattern as CorePattern;
+use system_f::terms::{self as core, Literal, Term};
+use system_f::types::{self as core_types, Context};
+use util::span::Span;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LowerError {
+    /// The construct at the span, named in the plural, has no counterpart
+    /// in System F yet
+    NotYetLowerable(&'static str, Span),
+    /// The integer at the span is too large for a natural number of System F
+    IntTooLarge(usize, Span),
+    /// The name at the span is not declared, which scope checking rules out
+    Unbound(Symbol, Span),
+}
+
+impl LowerError {
+    pub fn span(&self) -> Span {
+        match self {
+            LowerError::NotYetLowerable(_, sp) | LowerError::IntTooLarge(_, sp) | LowerError::Unbound(_, sp) => *sp,
+        }
+    }
+}
+
+impl fmt::Display for LowerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LowerError::NotYetLowerable(what, _) => write!(f, "{} are not yet lowerable to System F", what),
+            LowerError::IntTooLarge(n, _) => write!(f, "integer {} is too large to lower to System F", n),
+            LowerError::Unbound(s, _) => write!(f, "{} is not declared", s),
+        }
+    }
+}
+
+impl From<LowerError> for Diagnostic {
+    fn from(e: LowerError) -> Diagnostic {
+        Diagnostic::error(e.span(), e.to_string())
+    }
+}
+
+/// A program lowered to System F: the aliases it declares, in order, and a
+/// term evaluating to a tuple of the value of each of `results`, named by
+/// the `val` it is bound to, or `-` for an expression
+#[derive(Debug)]
+pub struct Lowered {
+    pub aliases: Vec<(String, core_types::Type)>,
+    pub term: Term,
+    pub results: Vec<String>,
+}
+
+impl Lowered {
+    /// Type check the term with `system_f`, under let-polymorphism, and then
+    /// evaluate it, returning the value of each result
+    pub fn run(self) -> Result<Vec<(String, Term)>, Diagnostic> {
+        let mut ctx = Context::default();
+        ctx.let_polymorphism = true;
+        for (name, ty) in self.aliases {
+            ctx.alias(name, ty)
+                .map_err(|e| Diagnostic::error(e.span, e.to_string()))?;
+        }
+        let mut term = self.term;
+        ctx.de_alias(&mut term);
+        ctx.type_check(&term).map_err(diagnostic)?;
+        let span = term.span;
+        match eval::run(&ctx, term, Limits::default(), |_| {}) {
+            EvalOutcome::Value(value) => match value.unshare().kind {
+                core::Kind::Product(values) => Ok(self
+                    .results
+                    .into_iter()
+                    .zip(values.into_iter().map(Term::unshare))
+                    .collect()),
+                _ => Err(Diagnostic::error(span, "the program did not evaluate to a tuple")),
+            },
+            EvalOutcome::Raised(raise) => Err(Diagnostic::error(raise.span, "uncaught error")),
+            EvalOutcome::Stuck(e) => Err(Diagnostic::error(span, e.to_string())),
+        }
+    }
+}
+
+/// A diagnostic of the `system_f` crate, as one of this crate
+fn diagnostic(d: system_f::diagnostics::Diagnostic) -> Diagnostic {
+    let mut diag = match d.level {
+        system_f::diagnostics::Level::Warn => Diagnostic::warn(d.primary.span, d.primary.info),
+        system_f::diagnostics::Level::Error => Diagnostic::error(d.primary.span, d.primary.info),
+    };
+    for anno in d.other {
+        diag = diag.message(anno.span, anno.info);
+    }
+    for info in d.info {
+        diag = diag.info(info);
+    }
+    diag
+}
+
+/// Lower `program`, which must have passed every check up to elaboration
+pub fn lower(program: &Program) -> Result<Lowered, LowerError> {
+    let mut l = Lowerer::default();
+    let mut bindings = Vec::new();
+    let mut results = Vec::new();
+    for d in &program.decls {
+        l.decl(d, &mut bindings)?;
+        let name = match &d.kind {
+            DeclKind::Value(_, pat, _) => variable(pat),
+            DeclKind::Expr(_) => Some(Symbol::intern("-")),
+            _ => None,
+        };
+        if let Some(name) = name {
+            results.push((name.to_string(), l.terms.len() - 1));
+        }
+    }
+    let values = results
+        .iter()
+        .map(|(_, pos)| Term::new(core::Kind::Var(l.terms.len() - 1 - pos), Span::default()))
+        .collect();
+    let term = wrap(bindings, Term::new(core::Kind::Product(values), Span::default()));
+    Ok(Lowered {
+        aliases: l.aliases,
+        term,
+        results: results.into_iter().map(|(name, _)| name).collect(),
+    })
+}
+
+/// The variable a pattern binds the whole value to, if it is just that
+fn variable(pat: &Pattern) -> Option<Symbol> {
+    match &pat.kind {
+        PatKind::Variable(x) => Some(*x),
+        PatKind::Ascribe(pat, _) => variable(pat),
+        _ => None,
+    }
+}
+
+/// Put `body` in the scope of each binding in turn, the first outermost
+fn wrap(bindings: Vec<(CorePattern, Term, Span)>, body: Term) -> Term {
+    bindings.into_iter().rev().fold(body, |body, (pat, bound, span)| {
+        Term::new(core::Kind::Let(Box::new(pat), Box::new(bound), Box::new(body)), span)
+    })
+}
+
+/// `head` applied to each of `args` in turn
+fn apply(head: Type, args: Vec<Type>) -> Type {
+    args.into_iter().fold(head, |ty, arg| {
+        let span = ty.span;
+        Type::new(TypeKind::Application(Box::new(ty), Box::new(arg)), span)
+    })
+}
+
+/// A type declared in scope
+struct TypeDef {
+    name: Symbol,
+    params: Vec<Symbol>,
+    /// The sum of a datatype, or the type an abbreviation stands for
+    body: Type,
+    datatype: bool,
+    /// The alias it is declared as, if any
+    alias: Option<String>,
+}
+
+struct Constructor {
+    label: Symbol,
+    /// Index of its datatype into [`Lowerer::types`]
+    datatype: usize,
+    payload: Option<Type>,
+}
+
+impl Constructor {
+    /// The number of arguments it takes as a curried function
+    fn arity(&self) -> usize {
+        match self.payload.as_ref().map(|ty| &ty.kind) {
+            None => 0,
+            Some(TypeKind::Product(tys)) if tys.len() > 1 => tys.len(),
+            Some(_) => 1,
+        }
+    }
+}
+
+/// Replaces each use of a datatype applied to exactly its own parameters,
+/// as its recursive uses in its own declaration are, with a variable
+struct Regular<'a> {
+    name: Symbol,
+    params: &'a [Symbol],
+    var: Symbol,
+}
+
+impl Regular<'_> {
+    fn matches(&self, ty: &Type) -> bool {
+        let mut args = Vec::new();
+        let mut head = ty;
+        while let TypeKind::Application(ty1, ty2) = &head.kind {
+            args.push(ty2.as_ref());
+            head = ty1;
+        }
+        args.reverse();
+        matches!(&head.kind, TypeKind::Defined(s) if *s == self.name)
+            && args.len() == self.params.len()
+            && args
+                .iter()
+                .zip(self.params)
+                .all(|(arg, param)| matches!(&arg.kind, TypeKind::Variable(s) if s == param))
+    }
+}
+
+impl TypeMutVisitor for Regular<'_> {
+    fn visit_ty(&mut self, ty: &mut Type) {
+        match self.matches(ty) {
+            true => ty.kind = TypeKind::Variable(self.var),
+            false => self.walk_ty(ty),
+        }
+    }
+}
+
+/// Names each hole in a type, so that it stays a single hole when the type
+/// is substituted in more than one place
+struct NameHoles<'a> {
+    fresh: &'a mut usize,
+}
+
+impl TypeMutVisitor for NameHoles<'_> {
+    fn visit_ty(&mut self, ty: &mut Type) {
+        match ty.kind {
+            TypeKind::Infer => {
+                *self.fresh += 1;
+                ty.kind = TypeKind::Variable(Symbol::intern(&format!("_#{}", self.fresh)));
+            }
+            _ => self.walk_ty(ty),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Lowerer {
+    /// Term variables in scope, innermost last
+    terms: Vec<Symbol>,
+    /// Type variables in scope, innermost last, including those bound
+    /// within the type being lowered
+    tyvars: Vec<Symbol>,
+    types: Vec<TypeDef>,
+    constructors: Vec<Constructor>,
+    aliases: Vec<(String, core_types::Type)>,
+    /// Datatypes being expanded, with the variable their recursive uses
+    /// are bound to
+    expanding: Vec<(usize, Symbol)>,
+    /// Number of holes made so far, each a distinct meta variable
+    holes: u32,
+    /// The hole made for each type variable that is not in scope, within
+    /// the annotation being lowered
+    named: HashMap<Symbol, u32>,
+    fresh: usize,
+}
+
+impl Lowerer {
+    fn fresh(&mut self, base: &str) -> Symbol {
+        self.fresh += 1;
+        Symbol::intern(&format!("{}#{}", base, self.fresh))
+    }
+
+    fn hole(&mut self) -> core_types::Type {
+        self.holes += 1;
+        core_types::Type::Meta(self.holes - 1)
+    }
+
+    fn nat(&self, n: usize, span: Span) -> Result<u32, LowerError> {
+        u32::try_from(n).map_err(|_| LowerError::IntTooLarge(n, span))
+    }
+
+    fn var(&self, s: Symbol, span: Span) -> Result<usize, LowerError> {
+        self.terms
+            .iter()
+            .rev()
+            .position(|v| *v == s)
+            .ok_or(LowerError::Unbound(s, span))
+    }
+
+    fn star(&self, k: &Kind, span: Span) -> Result<(), LowerError> {
+        match k {
+            Kind::Arrow(..) => Err(LowerError::NotYetLowerable("higher-kinded type variables", span)),
+            Kind::Row => Err(LowerError::NotYetLowerable("row variables", span)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Lower a type written in the program, which System F will fill the
+    /// holes of on its own
+    fn annotation(&mut self, ty: &Type) -> Result<core_types::Type, LowerError> {
+        self.named.clear();
+        self.ty(ty)
+    }
+
+    fn binder<T, F>(&mut self, s: Symbol, f: F) -> Result<T, LowerError>
+    where
+        F: FnOnce(&mut Lowerer) -> Result<T, LowerError>,
+    {
+        self.tyvars.push(s);
+        let res = f(self);
+        self.tyvars.pop();
+        res
+    }
+
+    fn ty(&mut self, ty: &Type) -> Result<core_types::Type, LowerError> {
+        use core_types::Type as T;
+        match &ty.kind {
+            TypeKind::Int => Ok(T::Nat),
+            TypeKind::Bool => Ok(T::Bool),
+            TypeKind::Unit => Ok(T::Unit),
+            TypeKind::Infer => Ok(self.hole()),
+            TypeKind::Variable(s) => match self.tyvars.iter().rev().position(|v| v == s) {
+                Some(idx) => Ok(T::Var(idx)),
+                None => match self.named.get(s) {
+                    Some(&n) => Ok(T::Meta(n)),
+                    None => {
+                        self.named.insert(*s, self.holes);
+                        Ok(self.hole())
+                    }
+                },
+            },
+            TypeKind::Function(ty1, ty2) => Ok(T::Arrow(Box::new(self.ty(ty1)?), Box::new(self.ty(ty2)?))),
+            TypeKind::Product(tys) => Ok(T::Product(tys.iter().map(|ty| self.ty(ty)).collect::<Result<_, _>>()?)),
+            TypeKind::Sum(variants) => Ok(T::Variant(
+                variants
+                    .iter()
+                    .map(|v| {
+                        Ok(core_types::Variant {
+                            label: v.label.to_string(),
+                            ty: match &v.ty {
+                                Some(ty) => self.ty(ty)?,
+                                None => T::Unit,
+                            },
+                        })
+                    })
+                    .collect::<Result<_, _>>()?,
+            )),
+            TypeKind::Record(..) => Err(LowerError::NotYetLowerable("records", ty.span)),
+            TypeKind::Path(..) => Err(LowerError::NotYetLowerable("structures", ty.span)),
+            TypeKind::Existential(s, k, body) => {
+                self.star(k, ty.span)?;
+                Ok(T::Existential(Box::new(self.binder(*s, |l| l.ty(body))?)))
+            }
+            TypeKind::Universal(s, k, body) => {
+                self.star(k, ty.span)?;
+                Ok(T::Universal(Box::new(self.binder(*s, |l| l.ty(body))?)))
+            }
+            TypeKind::Recursive(op) => match &op.kind {
+                TypeKind::Abstraction(s, k, body) => {
+                    self.star(k, op.span)?;
+                    Ok(T::Rec(Box::new(self.binder(*s, |l| l.ty(body))?)))
+                }
+                _ => Err(LowerError::NotYetLowerable("type operators", ty.span)),
+            },
+            TypeKind::Abstraction(..) => Err(LowerError::NotYetLowerable("type operators", ty.span)),
+            TypeKind::Defined(_) | TypeKind::Application(..) => self.applied(ty),
+        }
+    }
+
+    /// Lower a defined type, or an abstraction, applied to arguments
+    fn applied(&mut self, ty: &Type) -> Result<core_types::Type, LowerError> {
+        let mut args = Vec::new();
+        let mut head = ty;
+        while let TypeKind::Application(ty1, ty2) = &head.kind {
+            let mut arg = (**ty2).clone();
+            NameHoles { fresh: &mut self.fresh }.visit_ty(&mut arg);
+            args.push(arg);
+            head = ty1;
+        }
+        args.reverse();
+        match &head.kind {
+            TypeKind::Defined(s) => match self.types.iter().rposition(|def| def.name == *s) {
+                Some(idx) => self.instance(idx, args, ty.span),
+                None => Err(LowerError::Unbound(*s, head.span)),
+            },
+            TypeKind::Abstraction(s, _, body) => {
+                let mut body = (**body).clone();
+                let mut args = args.into_iter();
+                if let Some(arg) = args.next() {
+                    SubstNamedVar::new(*s, arg).visit_ty(&mut body);
+                }
+                self.ty(&apply(body, args.collect()))
+            }
+            TypeKind::Variable(_) => Err(LowerError::NotYetLowerable("higher-kinded type variables", head.span)),
+            _ => Err(LowerError::NotYetLowerable("type operators", head.span)),
+        }
+    }
+
+    /// Replace each of `params` in `body` with the argument at its position
+    fn substitute(&mut self, body: &Type, params: &[Symbol], args: Vec<Type>) -> Type {
+        // Rename the parameters first, so that an argument may use the name
+        // of another parameter
+        let mut body = body.clone();
+        let fresh = params.iter().map(|p| self.fresh(p.as_str())).collect::<Vec<_>>();
+        for (param, var) in params.iter().zip(&fresh) {
+            let var = Type::new(TypeKind::Variable(*var), body.span);
+            SubstNamedVar::new(*param, var).visit_ty(&mut body);
+        }
+        for (var, arg) in fresh.into_iter().zip(args) {
+            SubstNamedVar::new(var, arg).visit_ty(&mut body);
+        }
+        body
+    }
+
+    /// The declared type at `idx` in `types`, applied to `args`
+    fn instance(&mut self, idx: usize, mut args: Vec<Type>, span: Span) -> Result<core_types::Type, LowerError> {
+        let def = &self.types[idx];
+        if !def.datatype {
+            if args.len() < def.params.len() {
+                return Err(LowerError::NotYetLowerable("type operators", span));
+            }
+            if let (Some(alias), true) = (&def.alias, args.is_empty()) {
+                return Ok(core_types::Type::Alias(alias.clone()));
+            }
+            let rest = args.split_off(def.params.len());
+            let (body, params) = (def.body.clone(), def.params.clone());
+            let body = self.substitute(&body, &params, args);
+            return self.ty(&apply(body, rest));
+        }
+        if let Some((_, var)) = self.expanding.iter().rev().find(|(i, _)| *i == idx) {
+            // Recursive uses applied to the parameters are already replaced
+            return match args.is_empty() {
+                true => self.ty(&Type::new(TypeKind::Variable(*var), span)),
+                false => Err(LowerError::NotYetLowerable(
+                    "mutually recursive datatypes with parameters",
+                    span,
+                )),
+            };
+        }
+        if args.len() != def.params.len() {
+            return Err(LowerError::NotYetLowerable("type operators", span));
+        }
+        match &def.alias {
+            Some(alias) => Ok(core_types::Type::Alias(alias.clone())),
+            None => self.expand(idx, args),
+        }
+    }
+
+    /// The recursive variant of the datatype at `idx`, applied to `args`
+    fn expand(&mut self, idx: usize, args: Vec<Type>) -> Result<core_types::Type, LowerError> {
+        let def = &self.types[idx];
+        let (name, params) = (def.name, def.params.clone());
+        let mut body = def.body.clone();
+        let var = self.fresh(name.as_str());
+        Regular {
+            name,
+            params: &params,
+            var,
+        }
+        .visit_ty(&mut body);
+        let body = self.substitute(&body, &params, args);
+        self.expanding.push((idx, var));
+        let inner = self.binder(var, |l| l.ty(&body));
+        self.expanding.pop();
+        Ok(core_types::Type::Rec(Box::new(inner?)))
+    }
+
+    /// The type of the datatype at `idx`, with a hole for each parameter
+    fn datatype(&mut self, idx: usize, span: Span) -> Result<core_types::Type, LowerError> {
+        let args = (0..self.types[idx].params.len())
+            .map(|_| Type::new(TypeKind::Variable(self.fresh("_")), span))
+            .collect();
+        self.instance(idx, args, span)
+    }
+
+    /// The variant that a recursive type, or an alias for one, unfolds to
+    fn unfold(&self, rec: &core_types::Type) -> core_types::Type {
+        match rec {
+            core_types::Type::Alias(name) => match self.aliases.iter().find(|(alias, _)| alias == name) {
+                Some((_, ty)) => self.unfold(ty),
+                None => rec.clone(),
+            },
+            core_types::Type::Rec(inner) => core_types::subst(rec.clone(), (**inner).clone()),
+            _ => rec.clone(),
+        }
+    }
+
+    fn constructor(&self, label: Symbol, span: Span) -> Result<&Constructor, LowerError> {
+        self.constructors
+            .iter()
+            .rev()
+            .find(|con| con.label == label)
+            .ok_or(LowerError::Unbound(label, span))
+    }
+
+    /// The type a value must have to match `pat`, if `pat` says anything
+    /// about it
+    fn shape(&mut self, pat: &Pattern) -> Result<Option<core_types::Type>, LowerError> {
+        use core_types::Type as T;
+        match &pat.kind {
+            PatKind::Any | PatKind::Variable(_) => Ok(None),
+            PatKind::Unit => Ok(Some(T::Unit)),
+            PatKind::Literal(_) => Ok(Some(T::Nat)),
+            PatKind::Ascribe(_, ty) => self.ty(ty).map(Some),
+            PatKind::Record(_) => Err(LowerError::NotYetLowerable("records", pat.span)),
+            PatKind::Constructor(c) => {
+                let idx = self.constructor(*c, pat.span)?.datatype;
+                self.datatype(idx, pat.span).map(Some)
+            }
+            PatKind::Application(con, _) => self.shape(con),
+            PatKind::As(_, pat) => self.shape(pat),
+            PatKind::Product(pats) => {
+                let shapes = pats.iter().map(|p| self.shape(p)).collect::<Result<Vec<_>, _>>()?;
+                let tys = shapes
+                    .into_iter()
+                    .map(|shape| shape.unwrap_or_else(|| self.hole()))
+                    .collect();
+                Ok(Some(T::Product(tys)))
+            }
+            PatKind::Or(alts) => {
+                for alt in alts {
+                    if let Some(ty) = self.shape(alt)? {
+                        return Ok(Some(ty));
+                    }
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    /// The type implied by the first of `pats` that implies one, as an
+    /// annotation
+    fn shape_of<'p, I: IntoIterator<Item = &'p Pattern>>(
+        &mut self,
+        pats: I,
+    ) -> Result<Option<core_types::Type>, LowerError> {
+        self.named.clear();
+        for pat in pats {
+            if let Some(ty) = self.shape(pat)? {
+                return Ok(Some(ty));
+            }
+        }
+        Ok(None)
+    }
+
+    /// `term`, ascribed the type `ty` if there is one
+    fn ascribe(term: Term, ty: Option<core_types::Type>) -> Term {
+        match ty {
+            Some(ty) => {
+                let span = term.span;
+                let id = Term::new(
+                    core::Kind::Abs(Box::new(ty), Box::new(Term::new(core::Kind::Var(0), span))),
+                    span,
+                );
+                Term::new(core::Kind::App(Box::new(id), Box::new(term)), span)
+            }
+            None => term,
+        }
+    }
+
+    /// Lower a pattern, adding the variables it binds to `binders` in the
+    /// order they appear
+    fn pattern(&self, pat: &Pattern, binders: &mut Vec<Symbol>) -> Result<CorePattern, LowerError> {
+        Ok(match &pat.kind {
+            PatKind::Any => CorePattern::Any,
+            PatKind::Unit => CorePattern::Literal(Literal::Unit),
+            PatKind::Ascribe(pat, _) => self.pattern(pat, binders)?,
+            PatKind::Literal(n) => CorePattern::Literal(Literal::Nat(self.nat(*n, pat.span)?)),
+            PatKind::Constructor(c) => CorePattern::Constructor(c.to_string(), Box::new(CorePattern::Any)),
+            PatKind::Variable(x) => {
+                binders.push(*x);
+                CorePattern::Variable(x.to_string())
+            }
+            PatKind::Product(pats) => CorePattern::Product(
+                pats.iter()
+                    .map(|p| self.pattern(p, binders))
+                    .collect::<Result<_, _>>()?,
+            ),
+            PatKind::Record(_) => return Err(LowerError::NotYetLowerable("records", pat.span)),
+            PatKind::As(..) => return Err(LowerError::NotYetLowerable("layered patterns", pat.span)),
+            PatKind::Application(con, arg) => match &con.kind {
+                PatKind::Constructor(c) => {
+                    CorePattern::Constructor(c.to_string(), Box::new(self.pattern(arg, binders)?))
+                }
+                _ => return Err(LowerError::NotYetLowerable("applications of patterns", pat.span)),
+            },
+            // Every alternative binds the same variables as the first
+            PatKind::Or(alts) => CorePattern::Or(
+                alts.iter()
+                    .enumerate()
+                    .map(|(i, alt)| match i {
+                        0 => self.pattern(alt, binders),
+                        _ => self.pattern(alt, &mut Vec::new()),
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+        })
+    }
+
+    /// Lower a case arm, with the variables its pattern binds in scope of
+    /// its guard and body
+    fn arm(&mut self, pat: &Pattern, guard: Option<&Expr>, body: &Expr, span: Span) -> Result<core::Arm, LowerError> {
+        let mut binders = Vec::new();
+        let pat = self.pattern(pat, &mut binders)?;
+        let depth = self.terms.len();
+        // The first variable of a pattern is the innermost
+        self.terms.extend(binders.into_iter().rev());
+        let guard = guard.map(|g| self.expr(g)).transpose()?;
+        let term = self.expr(body)?;
+        self.terms.truncate(depth);
+        Ok(core::Arm {
+            span,
+            pat,
+            binders: Vec::new(),
+            guard: guard.map(Box::new),
+            term: Box::new(term),
+        })
+    }
+
+    /// Lower a constructor, applied to `arg` if there is one
+    fn constructor_value(&mut self, label: Symbol, arg: Option<&Expr>, span: Span) -> Result<Term, LowerError> {
+        let con = self.constructor(label, span)?;
+        let (datatype, arity) = (con.datatype, con.arity());
+        let whole = match arg.map(|e| &e.kind) {
+            Some(ExprKind::Tuple(es)) => es.len() == arity,
+            Some(_) => arity == 1,
+            None => false,
+        };
+        let arg = arg.map(|e| self.expr(e)).transpose()?;
+        let var = |idx| Term::new(core::Kind::Var(idx), span);
+        let (payload, arg) = match (arity, arg) {
+            (0, arg) => (Term::new(core::Kind::Lit(Literal::Unit), span), arg),
+            (_, Some(arg)) if whole => (arg, None),
+            (1, arg) => (var(0), arg),
+            (n, arg) => (
+                Term::new(core::Kind::Product((0..n).rev().map(var).collect()), span),
+                arg,
+            ),
+        };
+
+        self.named.clear();
+        let rec = self.datatype(datatype, span)?;
+        let unfolded = self.unfold(&rec);
+        let inj = Term::new(
+            core::Kind::Injection(label.to_string(), Box::new(payload), Box::new(unfolded)),
+            span,
+        );
+        let mut value = Term::new(core::Kind::Fold(Box::new(rec), Box::new(inj)), span);
+        if !whole && arity > 0 {
+            for _ in 0..arity {
+                value = Term::new(core::Kind::Abs(Box::new(self.hole()), Box::new(value)), span);
+            }
+        }
+        Ok(match arg {
+            Some(arg) => Term::new(core::Kind::App(Box::new(value), Box::new(arg)), span),
+            None => value,
+        })
+    }
+
+    fn expr(&mut self, e: &Expr) -> Result<Term, LowerError> {
+        let span = e.span;
+        let kind = match &e.kind {
+            ExprKind::Unit => core::Kind::Lit(Literal::Unit),
+            ExprKind::Int(n) => core::Kind::Lit(Literal::Nat(self.nat(*n, span)?)),
+            ExprKind::Var(s) => core::Kind::Var(self.var(*s, span)?),
+            ExprKind::Constr(s) => return self.constructor_value(*s, None, span),
+            ExprKind::App(e1, e2) => match &e1.kind {
+                ExprKind::Constr(s) => return self.constructor_value(*s, Some(e2), span),
+                _ => core::Kind::App(Box::new(self.expr(e1)?), Box::new(self.expr(e2)?)),
+            },
+            ExprKind::If(cond, e1, e2) => {
+                let arm = |lit, term: Term| core::Arm {
+                    span: term.span,
+                    pat: CorePattern::Literal(Literal::Bool(lit)),
+                    binders: Vec::new(),
+                    guard: None,
+                    term: Box::new(term),
+                };
+                // A case on a condition of unknown type cannot be checked
+                let cond = Lowerer::ascribe(self.expr(cond)?, Some(core_types::Type::Bool));
+                let arms = vec![arm(true, self.expr(e1)?), arm(false, self.expr(e2)?)];
+                core::Kind::Case(Box::new(cond), arms)
+            }
+            ExprKind::Abs(pat, body) => {
+                let ty = self.shape_of(Some(pat.as_ref()))?.unwrap_or_else(|| self.hole());
+                let body = match &pat.kind {
+                    PatKind::Variable(x) => {
+                        self.terms.push(*x);
+                        let body = self.expr(body);
+                        self.terms.pop();
+                        body?
+                    }
+                    _ => {
+                        self.terms.push(Symbol::default());
+                        let arm = self.arm(pat, None, body, span);
+                        self.terms.pop();
+                        let param = Term::new(core::Kind::Var(0), pat.span);
+                        Term::new(core::Kind::Case(Box::new(param), vec![arm?]), span)
+                    }
+                };
+                core::Kind::Abs(Box::new(ty), Box::new(body))
+            }
+            ExprKind::TyAbs(s, k, body) => {
+                self.star(k, span)?;
+                self.tyvars.push(*s);
+                let body = self.expr(body);
+                self.tyvars.pop();
+                core::Kind::TyAbs(Box::new(body?))
+            }
+            ExprKind::TyApp(e1, ty) => core::Kind::TyApp(Box::new(self.expr(e1)?), Box::new(self.annotation(ty)?)),
+            ExprKind::Record(_) => return Err(LowerError::NotYetLowerable("records", span)),
+            ExprKind::Tuple(es) => core::Kind::Product(es.iter().map(|e| self.expr(e)).collect::<Result<_, _>>()?),
+            ExprKind::Projection(e1, idx) => match &idx.kind {
+                ExprKind::Int(i) => core::Kind::Projection(Box::new(self.expr(e1)?), *i),
+                _ => return Err(LowerError::NotYetLowerable("records", idx.span)),
+            },
+            ExprKind::Case(scrutinee, arms) => {
+                let scrutinee = self.expr(scrutinee)?;
+                let shape = self.shape_of(arms.iter().map(|arm| &arm.pat))?;
+                let scrutinee = Lowerer::ascribe(scrutinee, shape);
+                let arms = arms
+                    .iter()
+                    .map(|arm| self.arm(&arm.pat, arm.guard.as_ref(), &arm.expr, arm.span))
+                    .collect::<Result<_, _>>()?;
+                core::Kind::Case(Box::new(scrutinee), arms)
+            }
+            ExprKind::Let(decls, body) => {
+                let depth = self.terms.len();
+                let (types, constructors) = (self.types.len(), self.constructors.len());
+                let mut bindings = Vec::new();
+                for d in decls {
+                    self.decl(d, &mut bindings)?;
+                }
+                let body = self.expr(body)?;
+                self.terms.truncate(depth);
+                self.types.truncate(types);
+                self.constructors.truncate(constructors);
+                return Ok(wrap(bindings, body));
+            }
+            ExprKind::Pack(witness, e1, sig) => {
+                let witness = self.annotation(witness)?;
+                let e1 = self.expr(e1)?;
+                core::Kind::Pack(Box::new(witness), Box::new(e1), Box::new(self.annotation(sig)?))
+            }
+            ExprKind::Open(package, t, x, body) => {
+                let package = self.expr(package)?;
+                self.tyvars.push(*t);
+                self.terms.push(*x);
+                let body = self.expr(body);
+                self.terms.pop();
+                self.tyvars.pop();
+                core::Kind::Unpack(Box::new(package), Box::new(body?))
+            }
+            ExprKind::Path(..) => return Err(LowerError::NotYetLowerable("structures", span)),
+        };
+        Ok(Term::new(kind, span))
+    }
+
+    /// Lower a function declared with `fun`, by its arms, to nested
+    /// abstractions over a case on its arguments. In a group of mutually
+    /// recursive functions, the tuple of all of them is bound outside the
+    /// arguments, and each function of the `group` is projected from it
+    fn function(&mut self, arms: &[FnArm], group: &[Symbol], span: Span) -> Result<Term, LowerError> {
+        let n = arms[0].pats.len();
+        let mut tys = Vec::new();
+        for i in 0..n {
+            let ty = self.shape_of(arms.iter().map(|arm| &arm.pats[i]))?;
+            tys.push(ty.unwrap_or_else(|| self.hole()));
+        }
+
+        let depth = self.terms.len();
+        let simple = match arms {
+            [arm] => arm.pats.iter().map(variable).collect::<Option<Vec<_>>>(),
+            _ => None,
+        };
+        match &simple {
+            Some(names) => self.terms.extend(names.iter().copied()),
+            None => self.terms.extend((0..n).map(|_| Symbol::default())),
+        }
+        self.terms.extend(group.iter().copied());
+        let body = match simple {
+            Some(_) => self.expr(&arms[0].expr)?,
+            None => {
+                let var = |idx| Term::new(core::Kind::Var(idx), span);
+                let k = group.len();
+                let scrutinee = match n {
+                    1 => var(k),
+                    _ => Term::new(core::Kind::Product((k..n + k).rev().map(var).collect()), span),
+                };
+                let arms = arms
+                    .iter()
+                    .map(|arm| {
+                        let pat = match arm.pats.as_slice() {
+                            [pat] => pat.clone(),
+                            pats => Pattern::new(PatKind::Product(pats.to_vec()), arm.span),
+                        };
+                        self.arm(&pat, None, &arm.expr, arm.span)
+                    })
+                    .collect::<Result<_, _>>()?;
+                Term::new(core::Kind::Case(Box::new(scrutinee), arms), span)
+            }
+        };
+        self.terms.truncate(depth);
+
+        let body = group.iter().enumerate().rev().fold(body, |body, (j, name)| {
+            let tuple = Term::new(core::Kind::Var(n + j), span);
+            let bound = Term::new(core::Kind::Projection(Box::new(tuple), j), span);
+            let pat = CorePattern::Variable(name.to_string());
+            Term::new(core::Kind::Let(Box::new(pat), Box::new(bound), Box::new(body)), span)
+        });
+        Ok(tys.into_iter().rev().fold(body, |body, ty| {
+            Term::new(core::Kind::Abs(Box::new(ty), Box::new(body)), span)
+        }))
+    }
+
+    /// Lower a declaration, adding what it binds to `bindings` and bringing
+    /// the names it declares into scope
+    fn decl(&mut self, d: &Decl, bindings: &mut Vec<(CorePattern, Term, Span)>) -> Result<(), LowerError> {
+        match &d.kind {
+            DeclKind::Value(_, pat, e) => {
+                let e = self.expr(e)?;
+                let shape = self.shape_of(Some(pat))?;
+                let e = Lowerer::ascribe(e, shape);
+                let mut binders = Vec::new();
+                let pat = self.pattern(pat, &mut binders)?;
+                self.terms.extend(binders.into_iter().rev());
+                bindings.push((pat, e, d.span));
+            }
+            DeclKind::Expr(e) => {
+                let e = self.expr(e)?;
+                self.terms.push(Symbol::intern("-"));
+                bindings.push((CorePattern::Variable("-".into()), e, d.span));
+            }
+            DeclKind::Signature(..) => return Err(LowerError::NotYetLowerable("signatures", d.span)),
+            DeclKind::Structure(..) => return Err(LowerError::NotYetLowerable("structures", d.span)),
+            DeclKind::Type(..) | DeclKind::Datatype(..) | DeclKind::Function(..) | DeclKind::And(..) => {
+                let mut group = Vec::new();
+                flatten(d, &mut group);
+                self.group(&group, bindings)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Lower a group of declarations joined by `and`. The types come first,
+    /// so that each is in scope in the others, then the functions, which
+    /// may call each other, and then any values
+    fn group(&mut self, group: &[&Decl], bindings: &mut Vec<(CorePattern, Term, Span)>) -> Result<(), LowerError> {
+        let first = self.types.len();
+        for d in group {
+            let (tyvars, name, body, datatype) = match &d.kind {
+                DeclKind::Type(tyvars, name, body) => (tyvars, name, body, false),
+                DeclKind::Datatype(tyvars, name, body) => (tyvars, name, body, true),
+                _ => continue,
+            };
+            if datatype {
+                for v in body.kind.variants() {
+                    self.constructors.push(Constructor {
+                        label: v.label,
+                        datatype: self.types.len(),
+                        payload: v.ty.clone(),
+                    });
+                }
+            }
+            self.types.push(TypeDef {
+                name: *name,
+                params: tyvars.iter().map(|t| t.kind.as_tyvar()).collect(),
+                body: body.clone(),
+                datatype,
+                alias: None,
+            });
+        }
+        // Only a closed type, with no holes, can be an alias
+        for idx in first..self.types.len() {
+            if !self.types[idx].params.is_empty() || !self.tyvars.is_empty() {
+                continue;
+            }
+            self.named.clear();
+            let holes = self.holes;
+            let ty = match self.types[idx].datatype {
+                true => self.expand(idx, Vec::new()),
+                false => {
+                    let body = self.types[idx].body.clone();
+                    self.ty(&body)
+                }
+            };
+            if let (Ok(ty), true) = (ty, self.holes == holes) {
+                let name = self.types[idx].name;
+                let taken = |s: &str| self.aliases.iter().any(|(alias, _)| alias == s);
+                let alias = std::iter::once(name.to_string())
+                    .chain((1..).map(|i| format!("{}#{}", name, i)))
+                    .find(|s| !taken(s))
+                    .unwrap();
+                self.aliases.push((alias.clone(), ty));
+                self.types[idx].alias = Some(alias);
+            }
+        }
+
+        let funs = group
+            .iter()
+            .filter_map(|d| match &d.kind {
+                DeclKind::Function(_, name, arms) => Some((*name, arms, d.span)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        match funs.as_slice() {
+            [] => {}
+            [(name, arms, span)] => {
+                self.terms.push(*name);
+                let f = self.function(arms, &[], *span);
+                self.terms.pop();
+                let f = Term::new(core::Kind::Abs(Box::new(self.hole()), Box::new(f?)), *span);
+                bindings.push((
+                    CorePattern::Variable(name.to_string()),
+                    Term::new(core::Kind::Fix(Box::new(f)), *span),
+                    *span,
+                ));
+                self.terms.push(*name);
+            }
+            funs => {
+                let names = funs.iter().map(|(name, _, _)| *name).collect::<Vec<_>>();
+                let span = funs.iter().fold(funs[0].2, |span, f| span + f.2);
+                self.terms.push(Symbol::default());
+                let fs = funs
+                    .iter()
+                    .map(|(_, arms, span)| self.function(arms, &names, *span))
+                    .collect::<Result<_, _>>();
+                self.terms.pop();
+                let tys = funs.iter().map(|_| self.hole()).collect();
+                let tuple = Term::new(core::Kind::Product(fs?), span);
+                let f = Term::new(
+                    core::Kind::Abs(Box::new(core_types::Type::Product(tys)), Box::new(tuple)),
+                    span,
+                );
+                let joined = names.iter().map(|s| s.as_str()).collect::<Vec<_>>().join(", ");
+                let pat = CorePattern::Variable(format!("({})", joined));
+                bindings.push((pat, Term::new(core::Kind::Fix(Box::new(f)), span), span));
+                self.terms.push(Symbol::default());
+                for (j, name) in names.into_iter().enumerate() {
+                    let tuple = Term::new(core::Kind::Var(j), span);
+                    let bound = Term::new(core::Kind::Projection(Box::new(tuple), j), span);
+                    bindings.push((CorePattern::Variable(name.to_string()), bound, span));
+                    self.terms.push(name);
+                }
+            }
+        }
+
+        for d in group {
+            if let DeclKind::Value(..) | DeclKind::Expr(_) = d.kind {
+                self.decl(d, bindings)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The declarations joined by `and` in `d`, in order
+fn flatten<'d>(d: &'d Decl, out: &mut Vec<&'d Decl>) {
+    match &d.kind {
+        DeclKind::And(d1, d2) => {
+            flatten(d1, out);
+            flatten(d2, out);
+        }
+        _ => out.push(d),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dependencies::order;
+    use crate::elaborate::ElaborationContext;
+    use crate::infer::Infer;
+    use crate::prelude::with_prelude;
+    use crate::scopecheck::check;
+    use crate::syntax::parser::Parser;
+
+    /// Check `input`, with the prelude, as the driver would, and lower it
+    fn lowered(input: &str) -> Result<Lowered, LowerError> {
+        lowered_with(input, true)
+    }
+
+    fn lowered_with(input: &str, elaborate: bool) -> Result<Lowered, LowerError> {
+        let program = with_prelude(Parser::new(input).parse_program().unwrap());
+        let program = Program {
+            decls: order(&program.decls).unwrap(),
+        };
+        assert_eq!(check(&program), []);
+        let mut infer = Infer::default();
+        for d in &program.decls {
+            infer.decl(d);
+        }
+        assert_eq!(infer.errors, []);
+        if elaborate {
+            ElaborationContext::elaborate(&program).unwrap();
+        }
+        lower(&program)
+    }
+
+    /// The natural number each result evaluates to, leaving out the others
+    fn run(input: &str) -> Vec<(String, u32)> {
+        nat_results(lowered(input).unwrap())
+    }
+
+    fn nat_results(lowered: Lowered) -> Vec<(String, u32)> {
+        lowered
+            .run()
+            .unwrap()
+            .into_iter()
+            .filter_map(|(name, value)| match value.kind {
+                core::Kind::Lit(Literal::Nat(n)) => Some((name, n)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn nats(results: &[(&str, u32)]) -> Vec<(String, u32)> {
+        results.iter().map(|(name, n)| (name.to_string(), *n)).collect()
+    }
+
+    #[test]
+    fn datatypes() {
+        let input = "val xs = Cons (1, Cons (2, Cons (3, Nil))); \
+                     val last = case rev xs of Cons (x, _) => x | Nil => 0 end; \
+                     val second = case xs of Cons (_, Cons (y, _)) => y | _ => 0 end; \
+                     val h = get_or (head (append (Cons (7, Nil)) xs)) 0; \
+                     val e = case Right 5 of Left x => x | Right y => y end; \
+                     case map (fn x => x) (rev Nil) of Nil => 4 | _ => 0 end";
+        assert_eq!(
+            run(input),
+            nats(&[("last", 3), ("second", 2), ("h", 7), ("e", 5), ("-", 4)])
+        );
+    }
+
+    #[test]
+    fn functions() {
+        let input = "fun pick 0 x = x | pick n x = 9; \
+                     val c = pick 1 4; \
+                     val d = (fn (x, y) => y) (1, 2); \
+                     val f = let datatype t = A | B of int in case B 3 of A => 0 | B n => n end end; \
+                     val id = fn x => x; \
+                     val g = case (id 1, id Nil) of (n, _) => n end";
+        assert_eq!(run(input), nats(&[("c", 9), ("d", 2), ("f", 3), ("g", 1)]));
+
+        // Elaboration does not handle groups joined by `and` yet
+        let input = "datatype nat = Z | S of nat; \
+                     fun even Z = 1 | even (S n) = odd n \
+                     and fun odd Z = 0 | odd (S n) = even n; \
+                     val a = even (S (S (S (S Z)))); \
+                     val b = odd (S (S Z))";
+        assert_eq!(
+            nat_results(lowered_with(input, false).unwrap()),
+            nats(&[("a", 1), ("b", 0)])
+        );
+    }
+
+    #[test]
+    fn packages() {
+        let input = "val p = pack int, (1, fn x => x) as exists ('t :: *) of 't * ('t -> int); \
+                     val n = open p as 't, q in (q.1) (q.0) end";
+        assert_eq!(run(input), nats(&[("n", 1)]));
+    }
+
+    #[test]
+    fn unsupported() {
+        let mut program = Parser::new("val r = {x = 1}").parse_program().unwrap();
+        let span = Span::new(util::span::Location::new(0, 8, 8), util::span::Location::new(0, 15, 15));
+        if let DeclKind::Value(_, _, e) = &mut program.decls[0].kind {
+            e.span = span;
+        }
+        let err = lower(&program).unwrap_err();
+        assert_eq!(err, LowerError::NotYetLowerable("records", span));
+        assert_eq!(err.to_string(), "records are not yet lowerable to System F");
+
+        let program = Parser::new("structure M = struct val x = 1 end")
+            .parse_program()
+            .unwrap();
+        assert!(matches!(
+            lower(&program),
+            Err(LowerError::NotYetLowerable("structures", _))
+        ));
+    }
+}
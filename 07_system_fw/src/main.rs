@@ -1,24 +1,9 @@
-#![allow(dead_code)]
-#[macro_use]
-pub mod macros;
-pub mod diagnostics;
-pub mod elaborate;
-pub mod functor;
-pub mod hir;
-pub mod stack;
-pub mod syntax;
-pub mod terms;
-pub mod typecheck;
-pub mod types;
-
 use std::io::prelude::*;
-use syntax::ast;
-use syntax::parser::{Error, ErrorKind, Parser};
-use terms::Term;
-use types::Type;
-use util::span::Span;
+use system_fw::syntax::parser::{Error, ErrorKind, Parser};
+use system_fw::{diagnostics, driver, hir};
 
 fn main() {
+    let prelude = !std::env::args().any(|arg| arg == "--no-prelude");
     loop {
         let mut buffer = String::new();
         print!("repl: ");
@@ -30,10 +15,20 @@ fn main() {
         match p.parse_program() {
             Ok(d) => {
                 println!("====> {:?}", &d.decls);
-                // println!("Validate: {:?}", validate::ProgramValidation::validate(&d));
-                let elab = elaborate::ElaborationContext::elaborate(&d).unwrap();
-                println!("-----");
-                hir::bidir::test(elab);
+                match driver::check(d, prelude) {
+                    Ok(checked) => {
+                        for warning in &checked.warnings {
+                            print!("{}", warning.render(&buffer));
+                        }
+                        println!("-----");
+                        hir::bidir::test(checked.elaborated);
+                    }
+                    Err(errors) => {
+                        for e in errors {
+                            print!("{}", diagnostics::Diagnostic::from(e).render(&buffer));
+                        }
+                    }
+                }
             }
             Err(Error {
                 kind: ErrorKind::EOF, ..
@@ -44,14 +39,3 @@ fn main() {
         }
     }
 }
-
-fn unfold(ty: Type) -> Type {
-    match &ty {
-        Type::Recursive(inner) => op_app!(*inner.clone(), ty),
-        Type::App(a, b) => match a.as_ref() {
-            Type::Recursive(_) => op_app!(unfold(*a.clone()), *b.clone()),
-            _ => ty,
-        },
-        _ => ty,
-    }
-}
@@ -0,0 +1,241 @@
+//! Signatures and structures
+//!
+//! A structure `struct decls end` is a collection of type and value
+//! components, reached from outside of it by the paths `M.t` and `M.x`. A
+//! signature `sig specs end` specifies the components a structure must
+//! have: `type t` requires some type `t`, `type t = ty` requires that `t` is
+//! `ty`, and `val x : ty` requires a value `x` of type `ty`.
+//!
+//! Ascribing a signature to a structure, `structure M : S = ...`, checks
+//! that the structure matches it, and seals the structure so that only what
+//! the signature specifies is visible from outside. A component specified
+//! as an abstract `type t` is hidden behind the path `M.t`, which is equal
+//! to no other type. This is existential packing under the hood: sealing
+//! `struct type t = int val x = 3 end` with `sig type t val x : t end` is
+//! opening `pack int, {x = 3} as exists ('t :: *) of {x: 't}`, with `M.t`
+//! as the abstract type
+use crate::diagnostics::Diagnostic;
+use crate::symbol::Symbol;
+use crate::syntax::ast::{Decl, DeclKind, Type};
+use std::collections::HashMap;
+use std::fmt;
+use util::span::Span;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ModuleError {
+    UndefinedSignature(Symbol, Span),
+    /// The structure named first has no value by the second name, which is
+    /// specified at the span
+    MissingValue(Symbol, Symbol, Span),
+    /// The structure named first has no type by the second name, which is
+    /// specified at the span
+    MissingType(Symbol, Symbol, Span),
+    /// The type component has a different number of parameters, or a
+    /// different definition, than its specification at the span
+    TypeMismatch(Symbol, Span),
+    /// The value component has the second type, but its specification at
+    /// the span requires the first
+    ValueMismatch(Symbol, Type, Type, Span),
+}
+
+/// A type declared by a structure, with its parameters, and its definition
+/// unless it is a datatype
+pub struct TypeComponent<'d> {
+    pub tyvars: &'d [Type],
+    pub def: Option<&'d Type>,
+}
+
+/// The type components declared at the top level of a structure body. A
+/// later declaration of a name shadows an earlier one
+pub fn type_components(decls: &[Decl]) -> HashMap<Symbol, TypeComponent<'_>> {
+    fn visit<'d>(d: &'d Decl, out: &mut HashMap<Symbol, TypeComponent<'d>>) {
+        match &d.kind {
+            DeclKind::Type(tyvars, name, ty) => {
+                out.insert(*name, TypeComponent { tyvars, def: Some(ty) });
+            }
+            DeclKind::Datatype(tyvars, name, _) => {
+                out.insert(*name, TypeComponent { tyvars, def: None });
+            }
+            DeclKind::And(d1, d2) => {
+                visit(d1, out);
+                visit(d2, out);
+            }
+            _ => {}
+        }
+    }
+    let mut out = HashMap::new();
+    for d in decls {
+        visit(d, &mut out);
+    }
+    out
+}
+
+impl ModuleError {
+    pub fn span(&self) -> Span {
+        match self {
+            ModuleError::UndefinedSignature(_, sp)
+            | ModuleError::MissingValue(_, _, sp)
+            | ModuleError::MissingType(_, _, sp)
+            | ModuleError::TypeMismatch(_, sp)
+            | ModuleError::ValueMismatch(_, _, _, sp) => *sp,
+        }
+    }
+}
+
+impl fmt::Display for ModuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ModuleError::UndefinedSignature(s, _) => write!(f, "undefined signature {}", s),
+            ModuleError::MissingValue(m, x, _) => {
+                write!(f, "structure {} has no value {}, which its signature specifies", m, x)
+            }
+            ModuleError::MissingType(m, t, _) => {
+                write!(f, "structure {} has no type {}, which its signature specifies", m, t)
+            }
+            ModuleError::TypeMismatch(t, _) => write!(f, "type {} does not match its specification", t),
+            ModuleError::ValueMismatch(x, expected, found, _) => write!(
+                f,
+                "value {} has type {}, but its signature specifies {}",
+                x, found, expected
+            ),
+        }
+    }
+}
+
+impl From<ModuleError> for Diagnostic {
+    fn from(e: ModuleError) -> Diagnostic {
+        Diagnostic::error(e.span(), e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::infer::{Infer, InferError};
+    use crate::syntax::parser::Parser;
+
+    const SIG: &str = "signature S = sig type t val x : t val f : t -> int end";
+
+    fn check(input: &str) -> Vec<InferError> {
+        let decls = Parser::new(input).parse_program().unwrap().decls;
+        let mut infer = Infer::default();
+        for d in &decls {
+            infer.decl(d);
+        }
+        infer.errors
+    }
+
+    fn module_errors(input: &str) -> Vec<ModuleError> {
+        check(input)
+            .into_iter()
+            .map(|e| match e {
+                InferError::Module(e) => e,
+                e => panic!("expected a module error, not {:?}", e),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn matching() {
+        let input = format!(
+            "{}; structure M : S = struct type t = int val x = 3 fun f n = n end; val y = M.f M.x",
+            SIG
+        );
+        assert_eq!(check(&input), []);
+
+        // An anonymous signature, with a datatype for the abstract type,
+        // and components that the signature does not mention
+        let input = "datatype 'a option = None | Some of 'a; \
+                     structure N : sig type 'a box val wrap : 'a box -> 'a box option end = struct \
+                       datatype 'a box = Box of 'a \
+                       val unused = () \
+                       val wrap = fn b => Some b \
+                     end";
+        assert_eq!(check(input), []);
+
+        // A transparent specification must agree with the definition
+        let input = "structure M : sig type t = int val x : t end = struct type t = int val x = 3 end";
+        assert_eq!(check(input), []);
+    }
+
+    #[test]
+    fn missing() {
+        let input = format!("{}; structure M : S = struct type t = int val x = 3 end", SIG);
+        match module_errors(&input).as_slice() {
+            [err @ ModuleError::MissingValue(m, f, _)] => {
+                assert_eq!((m.as_str(), f.as_str()), ("M", "f"));
+                assert_eq!(
+                    Diagnostic::from(err.clone()).primary.info,
+                    "structure M has no value f, which its signature specifies"
+                );
+            }
+            e => panic!("expected a missing value, not {:?}", e),
+        }
+
+        let input = format!("{}; structure M : S = struct val x = 3 fun f n = n end", SIG);
+        assert!(matches!(
+            module_errors(&input).as_slice(),
+            [ModuleError::MissingType(_, t, _), ..] if t == "t"
+        ));
+
+        let input = "structure M : T = struct val x = 3 end";
+        assert!(matches!(
+            module_errors(input).as_slice(),
+            [ModuleError::UndefinedSignature(s, _)] if s == "T"
+        ));
+    }
+
+    #[test]
+    fn mismatch() {
+        let input = format!(
+            "{}; structure M : S = struct type t = int val x = () fun f n = n end",
+            SIG
+        );
+        match module_errors(&input).as_slice() {
+            [err @ ModuleError::ValueMismatch(..)] => assert_eq!(
+                Diagnostic::from(err.clone()).primary.info,
+                "value x has type unit, but its signature specifies int"
+            ),
+            e => panic!("expected a mismatched value, not {:?}", e),
+        }
+
+        let input = "structure M : sig type t = int end = struct type t = unit end";
+        assert!(matches!(
+            module_errors(input).as_slice(),
+            [ModuleError::TypeMismatch(t, _)] if t == "t"
+        ));
+        let input = "structure M : sig type 'a t end = struct type t = unit end";
+        assert!(matches!(
+            module_errors(input).as_slice(),
+            [ModuleError::TypeMismatch(..)]
+        ));
+    }
+
+    #[test]
+    fn opacity() {
+        let structure = "struct type t = int val x = 3 fun f n = n end";
+        let uses = "val inc = fn (n : int) => n; val y = inc M.x; val z = fn (a : M.t) => inc a";
+
+        // Without a signature, or with one that defines `t`, `M.t` is int
+        let input = format!("structure M = {}; {}", structure, uses);
+        assert_eq!(check(&input), []);
+        let input = format!("structure M : sig type t = int val x : t end = {}; {}", structure, uses);
+        assert_eq!(check(&input), []);
+
+        // Sealed, `M.t` is equal only to itself
+        let input = format!("{}; structure M : S = {}; {}", SIG, structure, uses);
+        let errors = check(&input);
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        for err in errors {
+            match err {
+                InferError::Conflict(expected, found, _, _) => {
+                    assert_eq!(expected.to_string(), "int");
+                    assert_eq!(found.to_string(), "M.t");
+                }
+                e => panic!("expected a conflict, not {:?}", e),
+            }
+        }
+        let input = format!("{}; structure M : S = {}; val y = M.f M.x", SIG, structure);
+        assert_eq!(check(&input), []);
+    }
+}
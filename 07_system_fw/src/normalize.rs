@@ -0,0 +1,285 @@
+//! Normalization of type level applications
+//!
+//! A type level function applied to an argument, `int (fn ('a :: *) => 'a
+//! -> 'a)`, is a redex that beta-reduces to `[a ↦ int] ('a -> 'a)`, which
+//! is `int -> int`. A type is in weak head normal form when its head is not
+//! a redex, and in normal form when no part of it is. An abstraction that
+//! only applies a type to its parameter, `fn ('a :: *) => 'a f`, is also
+//! eta-reduced to `f`, unless `'a` is free in `f`. Two types are equivalent
+//! when their normal forms are equal up to the names of their bound
+//! variables.
+//!
+//! Only well-kinded types are sure to have a normal form: the ill-kinded
+//! `(fn ('x :: *) => 'x 'x) (fn ('x :: *) => 'x 'x)` reduces to itself. So
+//! that normalizing a type that has not been kind checked still finishes,
+//! each normalization may only perform a limited number of reductions
+//!
+//! Unification compares types by weak head normal form instead, where
+//! eta-reducing an abstraction may leave an abbreviation without the
+//! arguments it needs to be expanded: `fn ('a :: *) => 'a t` reduces to `t`.
+//! It only beta-reduces, and compares an abstraction with a type that is not
+//! one by eta-expanding the type, see [`Infer`](crate::infer::Infer). As
+//! this removes an abstraction each time, it stops even on types that are
+//! not well-kinded, but only gives meaningful answers for those that are.
+//! An abstraction over a row, `fn ('r :: row) => {x: int | 'r}`, is never an
+//! eta-redex, since a row variable may only end a record type and is never
+//! an argument
+use crate::diagnostics::Diagnostic;
+use crate::symbol::Symbol;
+use crate::syntax::ast::{Type, TypeKind};
+use crate::syntax::visit::{free_tyvars, SubstNamedVar, TypeMutVisitor};
+use crate::types::alpha_eq;
+use std::fmt;
+use util::span::Span;
+
+/// The number of reductions a normalization may perform by default
+pub const FUEL: usize = 1000;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum NormalizeError {
+    /// The type at the span was still not normalized after the number of
+    /// reductions
+    OutOfFuel(Box<Type>, usize, Span),
+}
+
+/// Reduces types, until it runs out of fuel
+pub struct Normalizer {
+    fuel: usize,
+    limit: usize,
+}
+
+impl Default for Normalizer {
+    fn default() -> Normalizer {
+        Normalizer::new(FUEL)
+    }
+}
+
+impl Normalizer {
+    pub fn new(fuel: usize) -> Normalizer {
+        Normalizer { fuel, limit: fuel }
+    }
+
+    fn tick(&mut self, ty: &Type) -> Result<(), NormalizeError> {
+        match self.fuel.checked_sub(1) {
+            Some(fuel) => {
+                self.fuel = fuel;
+                Ok(())
+            }
+            None => Err(NormalizeError::OutOfFuel(Box::new(ty.clone()), self.limit, ty.span)),
+        }
+    }
+
+    /// Perform a single reduction at the head of `ty`, if there is one
+    pub fn step(&mut self, ty: &Type) -> Result<Option<Type>, NormalizeError> {
+        use TypeKind::*;
+        match &ty.kind {
+            Application(f, arg) => match &f.kind {
+                Abstraction(s, _, body) => {
+                    self.tick(ty)?;
+                    let mut body = (**body).clone();
+                    SubstNamedVar::new(*s, (**arg).clone()).visit_ty(&mut body);
+                    Ok(Some(Type::with_id(body.kind, ty.span, ty.id)))
+                }
+                _ => Ok(self
+                    .step(f)?
+                    .map(|f| Type::with_id(Application(Box::new(f), arg.clone()), ty.span, ty.id))),
+            },
+            Abstraction(s, _, body) => match eta(*s, body) {
+                Some(f) => {
+                    self.tick(ty)?;
+                    Ok(Some(f.clone()))
+                }
+                None => Ok(None),
+            },
+            _ => Ok(None),
+        }
+    }
+
+    /// Perform a single beta-reduction at the head of `ty`, if there is one.
+    /// Unlike [`step`](Self::step), this leaves an abstraction as it is
+    pub fn beta_step(&mut self, ty: &Type) -> Result<Option<Type>, NormalizeError> {
+        match &ty.kind {
+            TypeKind::Abstraction(..) => Ok(None),
+            _ => self.step(ty),
+        }
+    }
+
+    /// Beta-reduce the head of `ty` until it is no longer a redex
+    pub fn beta_whnf(&mut self, ty: &Type) -> Result<Type, NormalizeError> {
+        let mut ty = ty.clone();
+        while let Some(reduced) = self.beta_step(&ty)? {
+            ty = reduced;
+        }
+        Ok(ty)
+    }
+
+    /// Reduce the head of `ty` until it is no longer a redex
+    pub fn whnf(&mut self, ty: &Type) -> Result<Type, NormalizeError> {
+        let mut ty = ty.clone();
+        while let Some(reduced) = self.step(&ty)? {
+            ty = reduced;
+        }
+        Ok(ty)
+    }
+
+    /// Reduce every redex in `ty`
+    pub fn normalize(&mut self, ty: &Type) -> Result<Type, NormalizeError> {
+        let mut ty = ty.clone();
+        let mut n = Normalize {
+            normalizer: self,
+            error: None,
+        };
+        n.visit_ty(&mut ty);
+        match n.error {
+            Some(e) => Err(e),
+            None => Ok(ty),
+        }
+    }
+}
+
+/// If `fn ('s :: K) => body` is an eta-redex, the type it reduces to
+fn eta(s: Symbol, body: &Type) -> Option<&Type> {
+    match &body.kind {
+        TypeKind::Application(f, arg) => match &arg.kind {
+            TypeKind::Variable(x) if *x == s && !free_tyvars(f).contains(&s) => Some(f),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Normalizes a type from the outside in, see [`Normalizer::normalize`]
+struct Normalize<'n> {
+    normalizer: &'n mut Normalizer,
+    error: Option<NormalizeError>,
+}
+
+impl<'n> TypeMutVisitor for Normalize<'n> {
+    fn visit_ty(&mut self, ty: &mut Type) {
+        if self.error.is_some() {
+            return;
+        }
+        match self.normalizer.whnf(ty) {
+            Ok(reduced) => *ty = reduced,
+            Err(e) => {
+                self.error = Some(e);
+                return;
+            }
+        }
+        self.walk_ty(ty);
+
+        // Normalizing the body of an abstraction may have made it an
+        // eta-redex, and the type it reduces to is already normal
+        if let TypeKind::Abstraction(s, _, body) = &ty.kind {
+            if let Some(f) = eta(*s, body) {
+                *ty = f.clone();
+            }
+        }
+    }
+}
+
+/// Whether `a` and `b` have the same normal form, up to the names of their
+/// bound variables
+pub fn equivalent(a: &Type, b: &Type) -> Result<bool, NormalizeError> {
+    let mut n = Normalizer::default();
+    Ok(alpha_eq(&n.normalize(a)?, &n.normalize(b)?))
+}
+
+impl NormalizeError {
+    pub fn span(&self) -> Span {
+        match self {
+            NormalizeError::OutOfFuel(_, _, sp) => *sp,
+        }
+    }
+}
+
+impl fmt::Display for NormalizeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NormalizeError::OutOfFuel(ty, limit, _) => write!(
+                f,
+                "type {} is not normalized after {} reductions, and may be ill-kinded",
+                ty, limit
+            ),
+        }
+    }
+}
+
+impl From<NormalizeError> for Diagnostic {
+    fn from(e: NormalizeError) -> Diagnostic {
+        Diagnostic::error(e.span(), e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::syntax::parser::Parser;
+
+    fn ty(input: &str) -> Type {
+        Parser::new(input).parse_type().unwrap()
+    }
+
+    fn equiv(a: &str, b: &str) -> bool {
+        equivalent(&ty(a), &ty(b)).unwrap()
+    }
+
+    #[test]
+    fn beta() {
+        assert!(equiv("int (fn ('a :: *) => 'a -> 'a)", "int -> int"));
+        assert!(equiv(
+            "(int, bool) (fn ('a :: *) => fn ('b :: *) => 'a * 'b)",
+            "int * bool"
+        ));
+        // Under binders, and with an argument that is itself applied
+        assert!(equiv(
+            "forall ('c :: *) of 'c (fn ('a :: *) => 'a list)",
+            "forall ('d :: *) of 'd list"
+        ));
+        assert!(equiv(
+            "int ((fn ('a :: *) => 'a * 'a) (fn ('f :: * -> *) => 'f))",
+            "int * int"
+        ));
+        // Substitution does not capture the free 'b
+        assert!(equiv(
+            "'b (fn ('a :: *) => forall ('b :: *) of 'a -> 'b)",
+            "forall ('c :: *) of 'b -> 'c"
+        ));
+        assert!(!equiv("int (fn ('a :: *) => 'a -> 'a)", "bool -> bool"));
+
+        // Only the head is reduced to weak head normal form
+        let whnf = Normalizer::default()
+            .whnf(&ty("(int (fn ('a :: *) => 'a)) (fn ('b :: *) => 'b * 'b)"))
+            .unwrap();
+        assert_eq!(whnf.to_string(), "int (fn ('a :: *) => 'a) * int (fn ('a :: *) => 'a)");
+    }
+
+    #[test]
+    fn eta() {
+        assert!(equiv("fn ('a :: *) => 'a list", "fn ('b :: *) => 'b list"));
+        assert!(equiv("fn ('a :: *) => 'a list", "list"));
+        // Nested, and only once the body is beta-reduced
+        assert!(equiv("fn ('a :: *) => fn ('b :: *) => ('a, 'b) either", "either"));
+        assert!(equiv("fn ('a :: *) => 'a (fn ('b :: *) => 'b list)", "list"));
+        // Not a redex, as 'a is free in the operator
+        assert!(!equiv("fn ('a :: * -> *) => 'a 'a", "'a"));
+        // Nor when the body uses 'a other than as its last argument
+        assert!(!equiv("fn ('a :: *) => ('a * 'a) list", "list"));
+        assert!(!equiv("fn ('a :: *) => ('a, 'a) either", "either"));
+    }
+
+    #[test]
+    fn fuel() {
+        let omega = ty("(fn ('x :: *) => 'x 'x) (fn ('x :: *) => 'x 'x)");
+        match Normalizer::default().normalize(&omega) {
+            Err(err @ NormalizeError::OutOfFuel(..)) => assert_eq!(
+                Diagnostic::from(err).primary.info,
+                format!(
+                    "type {} is not normalized after {} reductions, and may be ill-kinded",
+                    omega, FUEL
+                )
+            ),
+            e => panic!("expected to run out of fuel, not {:?}", e),
+        }
+    }
+}
@@ -0,0 +1,106 @@
+//! The prelude, declared before every program
+//!
+//! The prelude declares the `option`, `list` and `either` datatypes, along
+//! with functions on booleans and lists. Its source is in `prelude.fw`, and
+//! its declarations are put before those of a program, so a program can use
+//! `Cons` and `None` without declaring them first, or declare its own `list`
+//! to shadow the prelude's. There are no boolean literals, so the boolean
+//! functions are only those that can be written with `if`.
+//!
+//! The prelude only refers to its own declarations, which come before any
+//! of the program's, so whatever a program declares, no diagnostic can come
+//! from the prelude as long as it is free of them on its own. The tests
+//! check that it is, in every phase
+use crate::syntax::ast::{Decl, Program};
+use crate::syntax::parser::Parser;
+
+pub const SOURCE: &str = include_str!("prelude.fw");
+
+/// The declarations of the prelude
+pub fn decls() -> Vec<Decl> {
+    Parser::new(SOURCE)
+        .top_level()
+        .expect("internal error: the prelude does not parse")
+}
+
+/// `program`, with the declarations of the prelude before its own
+pub fn with_prelude(program: Program) -> Program {
+    let mut decls = decls();
+    decls.extend(program.decls);
+    Program { decls }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dependencies::order;
+    use crate::diagnostics::Diagnostic;
+    use crate::elaborate::ElaborationContext;
+    use crate::infer::Infer;
+    use crate::scopecheck::check;
+    use crate::syntax::visit::unused_bindings;
+
+    /// Every diagnostic for `program`, from ordering its declarations
+    /// through to elaborating them
+    fn diagnostics(program: Program) -> Vec<Diagnostic> {
+        let decls = match order(&program.decls) {
+            Ok(decls) => decls,
+            Err(errors) => return errors.into_iter().map(Diagnostic::from).collect(),
+        };
+        let program = Program { decls };
+        let mut diags = check(&program);
+        let mut infer = Infer::default();
+        for d in &program.decls {
+            infer.decl(d);
+        }
+        diags.extend(infer.errors.into_iter().map(Diagnostic::from));
+        diags.extend(unused_bindings(&program.decls));
+        if diags.is_empty() {
+            match ElaborationContext::elaborate(&program) {
+                Ok(elab) => diags.extend(elab.warnings),
+                Err(errors) => diags.extend(errors.into_iter().map(Diagnostic::from)),
+            }
+        }
+        diags
+    }
+
+    fn user(input: &str) -> Program {
+        with_prelude(Parser::new(input).parse_program().unwrap())
+    }
+
+    #[test]
+    fn clean() {
+        let prelude = Program { decls: decls() };
+        assert_eq!(prelude.decls.len(), 15);
+        assert_eq!(diagnostics(prelude), []);
+    }
+
+    #[test]
+    fn resolves() {
+        let input = "val xs = Cons (Some 1, Cons (None, Nil)); \
+                     val e = Left (map (fn o => get_or o 0) xs); \
+                     val r = rev (append xs xs)";
+        assert_eq!(diagnostics(user(input)), []);
+
+        // Without the prelude, none of them are declared
+        let program = Parser::new(input).parse_program().unwrap();
+        assert_eq!(check(&program).len(), 10);
+    }
+
+    #[test]
+    fn shadowing() {
+        let input = "datatype 'a list = Empty | Link of 'a * 'a list; \
+                     val xs = Link (1, Empty); \
+                     structure M = struct \
+                       datatype list = Leaf \
+                       val x = Leaf \
+                     end; \
+                     val ys = map (fn x => x) (Cons (1, Nil))";
+        assert_eq!(diagnostics(user(input)), []);
+
+        // The program's declarations shadow the prelude's for everything
+        // after them, so this `map` is not applied to a function
+        let input = "fun map xs = xs; val ys = map 1";
+        assert_eq!(diagnostics(user(input)), []);
+    }
+}
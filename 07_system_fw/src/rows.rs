@@ -0,0 +1,394 @@
+//! Unification of row polymorphic record types
+//!
+//! An open record type `{x: int | 'r}` has the field `x`, and whatever
+//! other fields the row variable `'r` stands for. A function taking one can
+//! be given any record with an `x: int` field, such as `{x: int, y: bool}`,
+//! by solving `'r` to the fields left over, here `{y: bool}`.
+//!
+//! Unifying two record types pairs up the fields they share, whose types
+//! must unify, and hands each side's leftover fields to the row variable of
+//! the other. A closed record type has no row variable, so it cannot take
+//! any leftovers
+use crate::diagnostics::Diagnostic;
+use crate::symbol::Symbol;
+use crate::syntax::ast::{Kind, Row, RowVar, Type, TypeKind};
+use crate::syntax::visit::{free_tyvars, SubstNamedVar, TypeMutVisitor};
+use crate::types::alpha_eq;
+use std::collections::HashMap;
+use std::fmt;
+use util::span::Span;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RowError {
+    /// The label has a different type in each record, given by the rows at
+    /// the expected and found spans
+    Conflict(Symbol, Span, Span),
+    /// The row for the label at the first span is missing from the closed
+    /// record type at the second span
+    Missing(Symbol, Span, Span),
+    /// The closed record type at the first span has no row for the label
+    /// found at the second span
+    Unexpected(Symbol, Span, Span),
+    /// The row variable would have to stand for rows mentioning itself
+    Occurs(Symbol, Span),
+    /// The expected type at the first span is not the one found at the
+    /// second
+    Mismatch(Span, Span),
+}
+
+/// The solutions found for row variables, each an extension of a record
+/// type with more rows, and possibly another row variable
+#[derive(Default, Debug)]
+pub struct RowSubst {
+    solved: HashMap<Symbol, (Vec<Row>, Option<RowVar>)>,
+    fresh: usize,
+}
+
+impl RowSubst {
+    /// A row variable that is not used anywhere else
+    fn fresh(&mut self, span: Span) -> RowVar {
+        self.fresh += 1;
+        RowVar {
+            name: Symbol::intern(&format!("ρ{}", self.fresh)),
+            span,
+        }
+    }
+
+    /// The rows for `var`, if it has been solved
+    pub fn solution(&self, var: Symbol) -> Option<&(Vec<Row>, Option<RowVar>)> {
+        self.solved.get(&var)
+    }
+
+    /// Replace the outermost universally quantified row variables of `ty`
+    /// with fresh ones, which unification may then solve. Quantifiers over
+    /// types of any other kind are left in place
+    pub fn instantiate(&mut self, ty: &Type) -> Type {
+        let mut ty = ty.clone();
+        while let TypeKind::Universal(s, k, body) = &ty.kind {
+            if **k != Kind::Row {
+                break;
+            }
+            let var = self.fresh(ty.span);
+            let mut body = (**body).clone();
+            SubstNamedVar::new(*s, Type::new(TypeKind::Variable(var.name), ty.span)).visit_ty(&mut body);
+            ty = body;
+        }
+        ty
+    }
+
+    /// Extend a record with the solutions of its row variable, until it is
+    /// either closed or ends in an unsolved variable
+    fn resolve(&self, rows: &[Row], tail: &Option<RowVar>) -> (Vec<Row>, Option<RowVar>) {
+        let mut rows = rows.to_vec();
+        let mut tail = tail.clone();
+        while let Some((more, rest)) = tail.as_ref().and_then(|var| self.solved.get(&var.name)) {
+            rows.extend(more.iter().cloned());
+            tail = rest.clone();
+        }
+        (rows, tail)
+    }
+
+    /// `ty` with every solved row variable replaced by its rows
+    pub fn apply(&self, ty: &Type) -> Type {
+        let mut ty = ty.clone();
+        Apply(self).visit_ty(&mut ty);
+        ty
+    }
+
+    /// Look up the type of the field `label` of the record type `ty`,
+    /// including fields that its row variable has been solved to
+    pub fn field(&self, ty: &Type, label: Symbol) -> Option<Type> {
+        match &ty.kind {
+            TypeKind::Record(rows, tail) => self
+                .resolve(rows, tail)
+                .0
+                .into_iter()
+                .find(|row| row.label == label)
+                .map(|row| self.apply(&row.ty)),
+            _ => None,
+        }
+    }
+
+    fn solve(&mut self, var: &RowVar, rows: Vec<Row>, tail: Option<RowVar>) -> Result<(), RowError> {
+        let occurs = tail.as_ref().map(|t| t.name == var.name).unwrap_or(false)
+            || rows
+                .iter()
+                .any(|row| free_tyvars(&self.apply(&row.ty)).contains(&var.name));
+        if occurs {
+            return Err(RowError::Occurs(var.name, var.span));
+        }
+        self.solved.insert(var.name, (rows, tail));
+        Ok(())
+    }
+
+    fn unify_records(&mut self, expected: &Type, found: &Type) -> Result<(), RowError> {
+        let ((rows1, tail1), (rows2, tail2)) = match (&expected.kind, &found.kind) {
+            (TypeKind::Record(r1, t1), TypeKind::Record(r2, t2)) => (self.resolve(r1, t1), self.resolve(r2, t2)),
+            _ => return Err(RowError::Mismatch(expected.span, found.span)),
+        };
+
+        // Rows of the expected type that were not found, and vice versa
+        let mut missing = Vec::new();
+        for row in &rows1 {
+            match rows2.iter().find(|r| r.label == row.label) {
+                Some(other) => match self.unify(&row.ty, &other.ty) {
+                    Err(RowError::Mismatch(..)) => return Err(RowError::Conflict(row.label, row.span, other.span)),
+                    r => r?,
+                },
+                None => missing.push(row.clone()),
+            }
+        }
+        let extra = rows2
+            .iter()
+            .filter(|row| !rows1.iter().any(|r| r.label == row.label))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let missing_err = |rows: &[Row]| {
+            rows.first()
+                .map(|r| Err(RowError::Missing(r.label, r.span, found.span)))
+                .unwrap_or(Ok(()))
+        };
+        let extra_err = |rows: &[Row]| {
+            rows.first()
+                .map(|r| Err(RowError::Unexpected(r.label, expected.span, r.span)))
+                .unwrap_or(Ok(()))
+        };
+
+        match (tail1, tail2) {
+            (None, None) => {
+                missing_err(&missing)?;
+                extra_err(&extra)
+            }
+            (Some(var), None) => {
+                missing_err(&missing)?;
+                self.solve(&var, extra, None)
+            }
+            (None, Some(var)) => {
+                extra_err(&extra)?;
+                self.solve(&var, missing, None)
+            }
+            (Some(var1), Some(var2)) if var1.name == var2.name => {
+                missing_err(&missing)?;
+                extra_err(&extra)
+            }
+            // If one record lists every row of the other, its variable
+            // stands for whatever more the other has
+            (Some(var1), Some(var2)) if missing.is_empty() => self.solve(&var1, extra, Some(var2)),
+            (Some(var1), Some(var2)) if extra.is_empty() => self.solve(&var2, missing, Some(var1)),
+            (Some(var1), Some(var2)) => {
+                // Both records may have more rows, so whatever neither
+                // lists is left to a new variable shared by the two
+                let rest = self.fresh(var2.span);
+                self.solve(&var1, extra, Some(rest.clone()))?;
+                self.solve(&var2, missing, Some(rest))
+            }
+        }
+    }
+
+    /// Unify the `expected` type with the one `found`, solving row
+    /// variables so that they are the same. Types other than records are
+    /// compared structurally, and binders only up to alpha-equivalence
+    pub fn unify(&mut self, expected: &Type, found: &Type) -> Result<(), RowError> {
+        use TypeKind::*;
+        match (&expected.kind, &found.kind) {
+            (Record(..), Record(..)) => self.unify_records(expected, found),
+            (Function(a1, a2), Function(b1, b2)) | (Application(a1, a2), Application(b1, b2)) => {
+                self.unify(a1, b1)?;
+                self.unify(a2, b2)
+            }
+            (Product(xs), Product(ys)) if xs.len() == ys.len() => {
+                xs.iter().zip(ys).try_for_each(|(x, y)| self.unify(x, y))
+            }
+            _ if alpha_eq(&self.apply(expected), &self.apply(found)) => Ok(()),
+            _ => Err(RowError::Mismatch(expected.span, found.span)),
+        }
+    }
+}
+
+/// Replaces solved row variables, see [`RowSubst::apply`]
+struct Apply<'s>(&'s RowSubst);
+
+impl<'s> TypeMutVisitor for Apply<'s> {
+    fn visit_record(&mut self, rows: &mut Vec<Row>, tail: &mut Option<RowVar>) {
+        let (resolved, rest) = self.0.resolve(rows, tail);
+        *rows = resolved;
+        *tail = rest;
+        for row in rows {
+            self.visit_ty(&mut row.ty);
+        }
+    }
+}
+
+impl RowError {
+    pub fn span(&self) -> Span {
+        match self {
+            RowError::Conflict(_, _, sp)
+            | RowError::Missing(_, _, sp)
+            | RowError::Unexpected(_, _, sp)
+            | RowError::Occurs(_, sp)
+            | RowError::Mismatch(_, sp) => *sp,
+        }
+    }
+}
+
+impl fmt::Display for RowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RowError::Conflict(s, _, _) => write!(f, "field {} has a different type than expected", s),
+            RowError::Missing(s, _, _) => write!(f, "record type is missing the field {}", s),
+            RowError::Unexpected(s, _, _) => write!(f, "record type has an unexpected field {}", s),
+            RowError::Occurs(s, _) => write!(f, "row variable '{} would have to contain itself", s),
+            RowError::Mismatch(_, _) => write!(f, "type mismatch"),
+        }
+    }
+}
+
+impl From<RowError> for Diagnostic {
+    fn from(e: RowError) -> Diagnostic {
+        let diag = Diagnostic::error(e.span(), e.to_string());
+        match &e {
+            RowError::Conflict(s, expected, _) => {
+                diag.message(*expected, format!("{} is expected to have this type", s))
+            }
+            RowError::Missing(s, required, _) => diag.message(*required, format!("{} is required here", s)),
+            RowError::Unexpected(_, record, _) => diag.message(*record, "by this closed record type"),
+            RowError::Mismatch(expected, _) => diag.message(*expected, "expected this type"),
+            RowError::Occurs(..) => diag,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::syntax::parser::Parser;
+    use util::span::Location;
+
+    fn ty(input: &str) -> Type {
+        Parser::new(input).parse_type().unwrap()
+    }
+
+    /// Spans are not kept by the parser in tests, so give each row of a
+    /// type a span on `line` to tell them apart
+    struct OnLine(u32);
+
+    impl TypeMutVisitor for OnLine {
+        fn visit_record(&mut self, rows: &mut Vec<Row>, tail: &mut Option<RowVar>) {
+            for row in rows {
+                row.span = line(self.0);
+                self.visit_ty(&mut row.ty);
+            }
+            if let Some(var) = tail {
+                var.span = line(self.0);
+            }
+        }
+    }
+
+    fn line(n: u32) -> Span {
+        Span::new(Location::new(n, 0, 0), Location::new(n, 1, 0))
+    }
+
+    fn ty_on(input: &str, n: u32) -> Type {
+        let mut t = ty(input);
+        OnLine(n).visit_ty(&mut t);
+        t
+    }
+
+    /// Apply a function to an argument, returning the type of the result
+    fn apply(s: &mut RowSubst, func: &Type, arg: &Type) -> Result<Type, RowError> {
+        match s.instantiate(func).kind {
+            TypeKind::Function(param, result) => {
+                s.unify(&param, arg)?;
+                Ok(s.apply(&result))
+            }
+            k => panic!("expected a function type, not {:?}", k),
+        }
+    }
+
+    #[test]
+    fn field_access() {
+        // get_x : forall ('r :: row) of {x: int | 'r} -> {x: int | 'r}
+        let get_x = ty("forall ('r :: row) of {x: int | 'r} -> {x: int | 'r}");
+        let mut s = RowSubst::default();
+        let result = apply(&mut s, &get_x, &ty("{x: int, y: bool}")).unwrap();
+        assert_eq!(s.field(&result, "x".into()), Some(ty("int")));
+        assert_eq!(s.field(&result, "y".into()), Some(ty("bool")));
+        assert_eq!(s.field(&result, "z".into()), None);
+        assert!(alpha_eq(&result, &ty("{x: int, y: bool}")));
+
+        // Each application gets its own row variable
+        let result = apply(&mut s, &get_x, &ty("{z: unit, x: int}")).unwrap();
+        assert!(alpha_eq(&result, &ty("{x: int, z: unit}")));
+        assert_eq!(s.field(&result, "y".into()), None);
+
+        // Passing an open record leaves the rest of it open
+        let result = apply(&mut s, &get_x, &ty("{x: int, w: bool | 'q}")).unwrap();
+        assert!(alpha_eq(&result, &ty("{x: int, w: bool | 'q}")));
+
+        // Both records are open, and neither has all of the other's fields
+        let mut s = RowSubst::default();
+        s.unify(&ty("{x: int | 'a}"), &ty("{y: bool | 'b}")).unwrap();
+        let a = s.apply(&ty("{x: int | 'a}"));
+        let b = s.apply(&ty("{y: bool | 'b}"));
+        assert_eq!(s.field(&a, "y".into()), Some(ty("bool")));
+        assert_eq!(s.field(&b, "x".into()), Some(ty("int")));
+        match (&a.kind, &b.kind) {
+            (TypeKind::Record(_, Some(r1)), TypeKind::Record(_, Some(r2))) => assert_eq!(r1.name, r2.name),
+            _ => panic!("expected open records, not {} and {}", a, b),
+        }
+    }
+
+    #[test]
+    fn conflicts() {
+        // Both arguments share a row, so must agree on the fields beyond x
+        let same = ty("forall ('r :: row) of {x: int | 'r} -> {x: int | 'r} -> int");
+        let mut s = RowSubst::default();
+        let rest = apply(&mut s, &same, &ty_on("{x: int, y: bool}", 1)).unwrap();
+        let err = apply(&mut s, &rest, &ty_on("{y: int, x: int}", 2)).unwrap_err();
+        assert_eq!(err, RowError::Conflict("y".into(), line(1), line(2)));
+        let diag = Diagnostic::from(err);
+        assert_eq!(diag.primary.span, line(2));
+        assert_eq!(diag.other[0].span, line(1));
+
+        let mut s = RowSubst::default();
+        assert_eq!(
+            s.unify(&ty_on("{x: int | 'r}", 1), &ty_on("{x: bool, y: int}", 2)),
+            Err(RowError::Conflict("x".into(), line(1), line(2)))
+        );
+        assert!(s.solution("r".into()).is_none());
+        assert!(matches!(
+            s.unify(&ty("{x: int, y: int}"), &ty("{x: int}")),
+            Err(RowError::Missing(l, ..)) if l == "y"
+        ));
+        assert!(matches!(
+            s.unify(&ty("{x: int}"), &ty("{x: int, y: int}")),
+            Err(RowError::Unexpected(l, ..)) if l == "y"
+        ));
+        assert!(matches!(
+            s.unify(&ty("{x: int | 'r}"), &ty("{x: int, y: {z: int | 'r}}")),
+            Err(RowError::Occurs(r, _)) if r == "r"
+        ));
+    }
+
+    #[test]
+    fn printing() {
+        for input in &[
+            "{x: int, y: bool | 'r}",
+            "forall ('r :: row) of {x: int | 'r} -> int",
+            "{p: {q: unit | 'a} | 'b} * int list",
+            "('a, 'b) either -> fn ('f :: * -> *) => int 'f",
+            "(int -> bool) -> exists ('t :: *) of {new: 't, get: 't -> int}",
+        ] {
+            let t = ty(input);
+            assert_eq!(t.to_string(), *input);
+            assert_eq!(ty(&t.to_string()), t);
+        }
+
+        let mut s = RowSubst::default();
+        s.unify(&ty("{x: int | 'r}"), &ty("{x: int, y: bool | 's}")).unwrap();
+        assert_eq!(s.apply(&ty("{x: int | 'r}")).to_string(), "{x: int, y: bool | 's}");
+        let open = s.instantiate(&ty("forall ('r :: row) of {x: int | 'r}"));
+        assert_eq!(open.to_string(), "{x: int | 'ρ1}");
+    }
+}
@@ -0,0 +1,846 @@
+//! Scope checking, before any type checking
+//!
+//! Every name in a program is looked up in one of several namespaces:
+//! values, datatype constructors, types, type variables, structures and
+//! signatures. This pass walks the declarations of a program in order,
+//! keeping track of which names each namespace has in scope, and reports
+//! each use of a name that is not in scope. Unlike elaboration, it does not
+//! stop at the first error, nor does it need any later phase to succeed, so
+//! it can report every unbound name at once.
+//!
+//! When a name is not in scope, a name in the same namespace that is only a
+//! few edits away from it is suggested, and if the name is in scope in
+//! another namespace, e.g. a type used as a value, that is noted too.
+//!
+//! Scoping follows elaboration: declarations are in scope for those that
+//! follow them, a datatype and a function are also in scope in their own
+//! definitions, as are all of the datatypes and values of declarations
+//! joined by `and`, and the type variables of a declaration must all be bound,
+//! either by the declaration itself or by an enclosing binder. The only
+//! exception is a value specification in a signature, `val x : 'a -> 'a`,
+//! which is implicitly quantified over its type variables
+//!
+//! Each use of a name that is in scope is resolved to the binding it refers
+//! to, so that later phases, and queries about a position in the source,
+//! can look it up rather than walk the scopes again
+use crate::diagnostics::Diagnostic;
+use crate::symbol::Symbol;
+use crate::syntax::ast::{
+    Arm, Decl, DeclKind, Expr, FnArm, Kind, Pattern, Program, RowVar, Sig, SigKind, SpecKind, Type, TypeKind,
+};
+use crate::syntax::visit::{free_tyvars, ExprVisitor, PatternBinders, PatternVisitor, TypeVisitor};
+use std::fmt;
+use util::span::Span;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Namespace {
+    Value,
+    Constructor,
+    Type,
+    TypeVariable,
+    Structure,
+    Signature,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScopeError {
+    /// The name at the span is not in scope in the namespace. It may be a
+    /// misspelling of the suggested name, or be in scope in each of the
+    /// other namespaces instead
+    Unbound(Namespace, Symbol, Span, Option<String>, Vec<Namespace>),
+}
+
+/// A use of a name at `span`, resolved to the binding at `binding`. A
+/// binding's span is that of the declaration, pattern, binder or
+/// specification that makes it
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Resolution {
+    pub namespace: Namespace,
+    pub name: Symbol,
+    pub span: Span,
+    pub binding: Span,
+}
+
+/// The components a structure or signature makes available through paths
+#[derive(Clone, Default, Debug)]
+struct Components {
+    values: Vec<Symbol>,
+    types: Vec<Symbol>,
+}
+
+/// Walks a program, reporting the names that are not in scope. The names in
+/// scope in each namespace are kept in order of binding, so a scope is left
+/// by truncating them
+#[derive(Default, Debug)]
+pub struct ScopeCheck {
+    values: Vec<(Symbol, Span)>,
+    constructors: Vec<(Symbol, Span)>,
+    types: Vec<(Symbol, Span)>,
+    tyvars: Vec<(Symbol, Span)>,
+    structures: Vec<(Symbol, Components, Span)>,
+    signatures: Vec<(Symbol, Components, Span)>,
+    span: Span,
+    pub errors: Vec<ScopeError>,
+    pub resolutions: Vec<Resolution>,
+}
+
+impl ScopeCheck {
+    /// Run `f`, then forget any bindings it made
+    fn scope<F: FnOnce(&mut Self)>(&mut self, f: F) {
+        let n = (
+            self.values.len(),
+            self.constructors.len(),
+            self.types.len(),
+            self.tyvars.len(),
+            self.structures.len(),
+            self.signatures.len(),
+        );
+        f(self);
+        self.values.truncate(n.0);
+        self.constructors.truncate(n.1);
+        self.types.truncate(n.2);
+        self.tyvars.truncate(n.3);
+        self.structures.truncate(n.4);
+        self.signatures.truncate(n.5);
+    }
+
+    /// The names in scope in `ns`, along with where they are bound, most
+    /// recently bound first
+    fn bindings(&self, ns: Namespace) -> Vec<(Symbol, Span)> {
+        let bindings = match ns {
+            Namespace::Value => self.values.clone(),
+            Namespace::Constructor => self.constructors.clone(),
+            Namespace::Type => self.types.clone(),
+            Namespace::TypeVariable => self.tyvars.clone(),
+            Namespace::Structure => self.structures.iter().map(|(s, _, sp)| (*s, *sp)).collect(),
+            Namespace::Signature => self.signatures.iter().map(|(s, _, sp)| (*s, *sp)).collect(),
+        };
+        bindings.into_iter().rev().collect()
+    }
+
+    /// The names in scope in `ns`, most recently bound first
+    fn names(&self, ns: Namespace) -> Vec<Symbol> {
+        self.bindings(ns).into_iter().map(|(s, _)| s).collect()
+    }
+
+    /// Resolve `name` to its binding in `ns`, or report it if it is not in
+    /// scope
+    fn check(&mut self, ns: Namespace, name: Symbol) {
+        let bindings = self.bindings(ns);
+        if let Some(&(_, binding)) = bindings.iter().find(|(s, _)| *s == name) {
+            self.resolutions.push(Resolution {
+                namespace: ns,
+                name,
+                span: self.span,
+                binding,
+            });
+            return;
+        }
+        let names = bindings.into_iter().map(|(s, _)| s).collect::<Vec<_>>();
+        // Type variables are spelled differently from every other name, so
+        // are never mistaken for one
+        let others: &[Namespace] = match ns {
+            Namespace::TypeVariable => &[],
+            _ => &[
+                Namespace::Value,
+                Namespace::Constructor,
+                Namespace::Type,
+                Namespace::Structure,
+                Namespace::Signature,
+            ],
+        };
+        let elsewhere = others
+            .iter()
+            .copied()
+            .filter(|&other| other != ns && self.names(other).contains(&name))
+            .collect();
+        let suggestion = suggest(name, names).map(String::from);
+        self.errors
+            .push(ScopeError::Unbound(ns, name, self.span, suggestion, elsewhere));
+    }
+
+    /// Report the path `m.x` to a value or type component unless it is in
+    /// scope, looking up `x` among the components of `m`
+    fn check_path(&mut self, ns: Namespace, m: Symbol, x: Symbol) {
+        self.check(Namespace::Structure, m);
+        let components = match self.structures.iter().rev().find(|(s, ..)| *s == m) {
+            Some((_, components, _)) => components,
+            None => return,
+        };
+        let (names, others) = match ns {
+            Namespace::Type => (&components.types, (Namespace::Value, &components.values)),
+            _ => (&components.values, (Namespace::Type, &components.types)),
+        };
+        if names.contains(&x) {
+            return;
+        }
+        let elsewhere = match others {
+            (other, names) if names.contains(&x) => vec![other],
+            _ => Vec::new(),
+        };
+        let suggestion = suggest(x, names.iter().rev().copied()).map(|s| format!("{}.{}", m, s));
+        self.errors.push(ScopeError::Unbound(
+            ns,
+            Symbol::intern(&format!("{}.{}", m, x)),
+            self.span,
+            suggestion,
+            elsewhere,
+        ));
+    }
+
+    /// Bind the type variables of a declaration
+    fn bind_tyvars(&mut self, tyvars: &[Type]) {
+        self.tyvars.extend(tyvars.iter().map(|t| (t.kind.as_tyvar(), t.span)));
+    }
+
+    fn bind_pattern(&mut self, pat: &Pattern) {
+        let mut binders = PatternBinders::default();
+        binders.visit_pat(pat);
+        self.values.extend(binders.names);
+    }
+
+    /// Bind the names a declaration joined by `and` makes, without visiting
+    /// it: its values, and its datatypes along with their constructors
+    fn bind_recursive(&mut self, d: &Decl) {
+        match &d.kind {
+            DeclKind::Value(_, pat, _) => self.bind_pattern(pat),
+            DeclKind::Function(_, name, _) => self.values.push((*name, d.span)),
+            DeclKind::Datatype(_, name, ty) => {
+                self.types.push((*name, d.span));
+                if let TypeKind::Sum(variants) = &ty.kind {
+                    self.constructors.extend(variants.iter().map(|v| (v.label, v.span)));
+                }
+            }
+            DeclKind::And(d1, d2) => {
+                self.bind_recursive(d1);
+                self.bind_recursive(d2);
+            }
+            _ => {}
+        }
+    }
+
+    /// Check the specifications of a signature, returning the components it
+    /// specifies. A named signature that is not in scope specifies nothing
+    fn signature(&mut self, sig: &Sig) -> Components {
+        self.span = sig.span;
+        let specs = match &sig.kind {
+            SigKind::Named(s) => {
+                self.check(Namespace::Signature, *s);
+                return match self.signatures.iter().rev().find(|(name, ..)| name == s) {
+                    Some((_, components, _)) => components.clone(),
+                    None => Components::default(),
+                };
+            }
+            SigKind::Specs(specs) => specs,
+        };
+        let mut components = Components::default();
+        self.scope(|s| {
+            for spec in specs {
+                s.span = spec.span;
+                match &spec.kind {
+                    SpecKind::Type(tyvars, name, def) => {
+                        if let Some(def) = def {
+                            s.scope(|s| {
+                                s.bind_tyvars(tyvars);
+                                s.visit_ty(def);
+                            });
+                        }
+                        s.types.push((*name, spec.span));
+                        components.types.push(*name);
+                    }
+                    SpecKind::Value(name, ty) => {
+                        s.scope(|s| {
+                            s.tyvars.extend(free_tyvars(ty).into_iter().map(|t| (t, spec.span)));
+                            s.visit_ty(ty);
+                        });
+                        components.values.push(*name);
+                    }
+                }
+            }
+        });
+        components
+    }
+}
+
+impl<'t> ExprVisitor<'t> for ScopeCheck {
+    fn visit_var(&mut self, s: Symbol) {
+        self.check(Namespace::Value, s);
+    }
+
+    fn visit_constr(&mut self, s: Symbol) {
+        self.check(Namespace::Constructor, s);
+    }
+
+    fn visit_path(&mut self, m: Symbol, s: Symbol) {
+        self.check_path(Namespace::Value, m, s);
+    }
+
+    fn visit_abs(&mut self, pat: &'t Pattern, body: &'t Expr) {
+        self.scope(|s| {
+            s.visit_pat(pat);
+            s.bind_pattern(pat);
+            s.visit_expr(body);
+        });
+    }
+
+    fn visit_tyabs(&mut self, tyvar: Symbol, _: &'t Kind, body: &'t Expr) {
+        let span = self.span;
+        self.scope(|s| {
+            s.tyvars.push((tyvar, span));
+            s.visit_expr(body);
+        });
+    }
+
+    fn visit_arm(&mut self, arm: &'t Arm) {
+        self.scope(|s| {
+            s.visit_pat(&arm.pat);
+            s.bind_pattern(&arm.pat);
+            if let Some(guard) = &arm.guard {
+                s.visit_expr(guard);
+            }
+            s.visit_expr(&arm.expr);
+        });
+    }
+
+    fn visit_fn_arm(&mut self, arm: &'t FnArm) {
+        self.scope(|s| {
+            for p in &arm.pats {
+                s.visit_pat(p);
+                s.bind_pattern(p);
+            }
+            s.visit_expr(&arm.expr);
+        });
+    }
+
+    fn visit_let(&mut self, decls: &'t [Decl], body: &'t Expr) {
+        self.scope(|s| {
+            for d in decls {
+                s.visit_decl(d);
+            }
+            s.visit_expr(body);
+        });
+    }
+
+    fn visit_open(&mut self, package: &'t Expr, tyvar: Symbol, var: Symbol, body: &'t Expr) {
+        let span = self.span;
+        self.visit_expr(package);
+        self.scope(|s| {
+            s.tyvars.push((tyvar, span));
+            s.values.push((var, span));
+            s.visit_expr(body);
+        });
+    }
+
+    fn visit_pattern(&mut self, pat: &'t Pattern) {
+        self.visit_pat(pat);
+    }
+
+    fn visit_type(&mut self, ty: &'t Type) {
+        self.visit_ty(ty);
+    }
+
+    fn visit_sig(&mut self, sig: &'t Sig) {
+        self.signature(sig);
+    }
+
+    /// The components of a structure are those of its signature, if it has
+    /// one, and otherwise those its declarations make
+    fn visit_structure(&mut self, name: Symbol, sig: Option<&'t Sig>, decls: &'t [Decl]) {
+        let span = self.span;
+        let specified = sig.map(|sig| self.signature(sig));
+        let mut declared = Components::default();
+        self.scope(|s| {
+            let (values, constructors, types) = (s.values.len(), s.constructors.len(), s.types.len());
+            for d in decls {
+                s.visit_decl(d);
+            }
+            declared.values = s.values[values..].iter().map(|(v, _)| *v).collect();
+            declared
+                .values
+                .extend(s.constructors[constructors..].iter().map(|(c, _)| *c));
+            declared.types = s.types[types..].iter().map(|(t, _)| *t).collect();
+        });
+        self.structures.push((name, specified.unwrap_or(declared), span));
+    }
+
+    fn visit_decl(&mut self, d: &'t Decl) {
+        self.span = d.span;
+        match &d.kind {
+            DeclKind::Type(tyvars, name, ty) => {
+                self.scope(|s| {
+                    s.bind_tyvars(tyvars);
+                    s.visit_ty(ty);
+                });
+                self.types.push((*name, d.span));
+            }
+            DeclKind::Datatype(tyvars, name, ty) => {
+                self.types.push((*name, d.span));
+                self.scope(|s| {
+                    s.bind_tyvars(tyvars);
+                    s.visit_ty(ty);
+                });
+                if let TypeKind::Sum(variants) = &ty.kind {
+                    self.constructors.extend(variants.iter().map(|v| (v.label, v.span)));
+                }
+            }
+            // The expression is checked before its pattern is bound
+            DeclKind::Value(tyvars, pat, e) => {
+                self.scope(|s| {
+                    s.bind_tyvars(tyvars);
+                    s.visit_expr(e);
+                    s.visit_pat(pat);
+                });
+                self.bind_pattern(pat);
+            }
+            DeclKind::Function(tyvars, name, arms) => {
+                self.values.push((*name, d.span));
+                self.scope(|s| {
+                    s.bind_tyvars(tyvars);
+                    for arm in arms {
+                        s.visit_fn_arm(arm);
+                    }
+                });
+            }
+            DeclKind::And(d1, d2) => {
+                self.bind_recursive(d1);
+                self.bind_recursive(d2);
+                self.visit_decl(d1);
+                self.visit_decl(d2);
+            }
+            DeclKind::Signature(name, sig) => {
+                let components = self.signature(sig);
+                self.signatures.push((*name, components, d.span));
+            }
+            _ => self.walk_decl(d),
+        }
+    }
+
+    fn visit_expr(&mut self, e: &'t Expr) {
+        self.span = e.span;
+        self.walk_expr(e);
+    }
+}
+
+impl<'t> PatternVisitor<'t> for ScopeCheck {
+    fn visit_constructor(&mut self, s: Symbol) {
+        self.check(Namespace::Constructor, s);
+    }
+
+    fn visit_type(&mut self, ty: &'t Type) {
+        self.visit_ty(ty);
+    }
+
+    fn visit_pat(&mut self, pat: &'t Pattern) {
+        self.span = pat.span;
+        self.walk_pat(pat);
+    }
+}
+
+impl<'t> TypeVisitor<'t> for ScopeCheck {
+    fn visit_defined(&mut self, s: Symbol) {
+        self.check(Namespace::Type, s);
+    }
+
+    fn visit_path(&mut self, m: Symbol, s: Symbol) {
+        self.check_path(Namespace::Type, m, s);
+    }
+
+    fn visit_variable(&mut self, s: Symbol) {
+        self.check(Namespace::TypeVariable, s);
+    }
+
+    fn visit_row_variable(&mut self, var: &'t RowVar) {
+        self.span = var.span;
+        self.check(Namespace::TypeVariable, var.name);
+    }
+
+    fn visit_existential(&mut self, s: Symbol, _: &'t Kind, ty: &'t Type) {
+        let span = self.span;
+        self.scope(|sc| {
+            sc.tyvars.push((s, span));
+            sc.visit_ty(ty);
+        });
+    }
+
+    fn visit_universal(&mut self, s: Symbol, _: &'t Kind, ty: &'t Type) {
+        let span = self.span;
+        self.scope(|sc| {
+            sc.tyvars.push((s, span));
+            sc.visit_ty(ty);
+        });
+    }
+
+    fn visit_abstraction(&mut self, s: Symbol, _: &'t Kind, ty: &'t Type) {
+        let span = self.span;
+        self.scope(|sc| {
+            sc.tyvars.push((s, span));
+            sc.visit_ty(ty);
+        });
+    }
+
+    fn visit_ty(&mut self, ty: &'t Type) {
+        self.span = ty.span;
+        self.walk_ty(ty);
+    }
+}
+
+/// The number of single character insertions, deletions, substitutions and
+/// swaps of adjacent characters it takes to turn `a` into `b`, where no
+/// character is edited more than once
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+    // d[i][j] is the distance between the first i characters of a and the
+    // first j characters of b
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            d[i][j] = (d[i - 1][j] + 1).min(d[i][j - 1] + 1).min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+/// The first of `candidates` closest to `name`, if it is close enough to be
+/// a likely misspelling: at most one edit for every three characters of
+/// `name`, so very short names are never suggested
+fn suggest<I: IntoIterator<Item = Symbol>>(name: Symbol, candidates: I) -> Option<&'static str> {
+    let name = name.as_str();
+    let limit = name.chars().count() / 3;
+    candidates
+        .into_iter()
+        .map(|c| (edit_distance(name, c.as_str()), c.as_str()))
+        .filter(|(d, _)| *d <= limit)
+        .fold(None, |best: Option<(usize, &str)>, (d, c)| match best {
+            Some((b, _)) if b <= d => best,
+            _ => Some((d, c)),
+        })
+        .map(|(_, c)| c)
+}
+
+/// Every use of a name in `program` that is not in scope
+pub fn check(program: &Program) -> Vec<Diagnostic> {
+    unbound(program).into_iter().map(Diagnostic::from).collect()
+}
+
+/// The errors behind [`check`]
+pub fn unbound(program: &Program) -> Vec<ScopeError> {
+    let mut sc = ScopeCheck::default();
+    for d in &program.decls {
+        sc.visit_decl(d);
+    }
+    sc.errors
+}
+
+/// The binding of each use of a name in `program` that is in scope, along
+/// with the errors for those that are not
+pub fn resolve(program: &Program) -> (Vec<Resolution>, Vec<ScopeError>) {
+    let mut sc = ScopeCheck::default();
+    for d in &program.decls {
+        sc.visit_decl(d);
+    }
+    (sc.resolutions, sc.errors)
+}
+
+/// The resolution of the innermost use of a name that spans the position at
+/// `line` and `col`, if any
+pub fn resolution_at(resolutions: &[Resolution], line: u32, col: u32) -> Option<&Resolution> {
+    let at = (line, col);
+    resolutions
+        .iter()
+        .filter(|r| (r.span.start.line, r.span.start.col) <= at && at < (r.span.end.line, r.span.end.col))
+        .min_by_key(|r| r.span.end.abs - r.span.start.abs)
+}
+
+/// The names `d` uses without binding them itself, in each namespace. The
+/// component `x` of a path `M.x` is left out, as only `M` is needed to find
+/// it
+pub fn free_names(d: &Decl) -> Vec<(Namespace, Symbol)> {
+    let mut sc = ScopeCheck::default();
+    sc.visit_decl(d);
+    sc.errors
+        .into_iter()
+        .map(|ScopeError::Unbound(ns, s, ..)| (ns, s))
+        .collect()
+}
+
+impl Namespace {
+    /// How a name in this namespace is written
+    fn spell(self, name: &str) -> String {
+        match self {
+            Namespace::TypeVariable => format!("'{}", name),
+            _ => name.into(),
+        }
+    }
+}
+
+impl fmt::Display for Namespace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Namespace::Value => write!(f, "value"),
+            Namespace::Constructor => write!(f, "constructor"),
+            Namespace::Type => write!(f, "type"),
+            Namespace::TypeVariable => write!(f, "type variable"),
+            Namespace::Structure => write!(f, "structure"),
+            Namespace::Signature => write!(f, "signature"),
+        }
+    }
+}
+
+impl ScopeError {
+    pub fn span(&self) -> Span {
+        match self {
+            ScopeError::Unbound(_, _, sp, _, _) => *sp,
+        }
+    }
+}
+
+impl fmt::Display for ScopeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            // A path `M.x` names a component, not a variable
+            ScopeError::Unbound(Namespace::Value, s, ..) if s.as_str().contains('.') => {
+                write!(f, "undefined value {}", s)
+            }
+            ScopeError::Unbound(Namespace::Value, s, ..) => write!(f, "unbound variable {}", s),
+            ScopeError::Unbound(Namespace::TypeVariable, s, ..) => write!(f, "unbound type variable '{}", s),
+            ScopeError::Unbound(ns, s, ..) => write!(f, "undefined {} {}", ns, s),
+        }
+    }
+}
+
+impl From<ScopeError> for Diagnostic {
+    fn from(e: ScopeError) -> Diagnostic {
+        let mut diag = Diagnostic::error(e.span(), e.to_string());
+        let ScopeError::Unbound(ns, s, _, suggestion, elsewhere) = e;
+        if let Some(suggestion) = suggestion {
+            diag = diag.info(format!("did you mean {}?", ns.spell(&suggestion)));
+        }
+        for other in elsewhere {
+            diag = diag.info(format!("{} is a {}, not a {}", s, other, ns));
+        }
+        diag
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::syntax::parser::Parser;
+
+    /// The message and notes of each diagnostic for `input`
+    fn diagnostics(input: &str) -> Vec<(String, Vec<String>)> {
+        let program = Parser::new(input).parse_program().unwrap();
+        check(&program).into_iter().map(|d| (d.primary.info, d.info)).collect()
+    }
+
+    fn messages(input: &str) -> Vec<String> {
+        diagnostics(input).into_iter().map(|(m, _)| m).collect()
+    }
+
+    #[test]
+    fn distance() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("map", "map"), 0);
+        assert_eq!(edit_distance("mpa", "map"), 1);
+        assert_eq!(edit_distance("lenght", "length"), 1);
+        assert_eq!(edit_distance("ab", "bca"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+
+    #[test]
+    fn bound() {
+        let input = "datatype 'a list = Nil | Cons of 'a * 'a list; \
+                     type 'a pair = 'a * 'a; \
+                     fun length xs = case xs of Nil => 0 | Cons (_, rest) => length rest end; \
+                     val 'a swap = fn (p : 'a pair) => case p of (x, y) => (y, x) end; \
+                     val p = pack int, {get = fn (n : int) => n} as exists ('t :: *) of {get: 't -> int}; \
+                     val n = open p as 't, c in let val f = fn (x : 't) => c.get x in f end end; \
+                     signature S = sig type t val x : t val f : 'a -> 'a end; \
+                     structure M : S = struct type t = int val x = 1 fun f y = y end; \
+                     val z = M.f M.x; \
+                     val w = fn (a : M.t) => a";
+        // The parser stops at the first declaration it cannot parse
+        assert_eq!(Parser::new(input).parse_program().unwrap().decls.len(), 10);
+        assert_eq!(diagnostics(input), []);
+    }
+
+    #[test]
+    fn unbound() {
+        // Every unbound name is reported, not just the first
+        let input = "val a = b; \
+                     val c = Nope; \
+                     type t = u; \
+                     type s = forall ('a :: *) of 'a -> 'b; \
+                     val d = case 1 of Some x => x end; \
+                     val e = N.x; \
+                     structure M : T = struct end";
+        assert_eq!(
+            messages(input),
+            [
+                "unbound variable b",
+                "undefined constructor Nope",
+                "undefined type u",
+                "unbound type variable 'b",
+                "undefined constructor Some",
+                "undefined structure N",
+                "undefined signature T",
+            ]
+        );
+
+        // Declarations are only in scope after they are made, except for a
+        // datatype or function in its own definition
+        let input = "type t = int -> t; \
+                     datatype l = Nil | Cons of int * l; \
+                     val x = y; val y = 1; \
+                     fun f n = f n; \
+                     val g = fn n => g n";
+        assert_eq!(
+            messages(input),
+            ["undefined type t", "unbound variable y", "unbound variable g"]
+        );
+
+        // Nothing bound in a scope leaks out of it
+        let input = "val f = fn x => let val y = x in y end; \
+                     val z = (y, x); \
+                     val p = pack int, 0 as exists ('t :: *) of 't; \
+                     val n = open p as 't, v in (fn (a : 't) => a) v end; \
+                     val g = fn (b : 't) => v; \
+                     structure M = struct val hidden = 1 end; \
+                     val h = (hidden, M.hidden, M.other)";
+        assert_eq!(
+            messages(input),
+            [
+                "unbound variable y",
+                "unbound variable x",
+                "unbound type variable 't",
+                "unbound variable v",
+                "unbound variable hidden",
+                "undefined value M.other",
+            ]
+        );
+    }
+
+    #[test]
+    fn suggestions() {
+        let input = "fun length xs = 0; val n = lenght ()";
+        assert_eq!(
+            diagnostics(input),
+            [("unbound variable lenght".into(), vec!["did you mean length?".into()])]
+        );
+
+        let input = "datatype 'a option = None | Some of 'a; \
+                     type 'a tree = unit -> 'a option; \
+                     val 'elem f = fn (t : 'elem tre) => fn (x : 'elme) => Sme x; \
+                     structure M = struct val size = 1 end; \
+                     val g = M.sise";
+        assert_eq!(
+            diagnostics(input),
+            [
+                ("undefined type tre".into(), vec!["did you mean tree?".into()]),
+                ("unbound type variable 'elme".into(), vec!["did you mean 'elem?".into()]),
+                ("undefined constructor Sme".into(), vec!["did you mean Some?".into()]),
+                ("undefined value M.sise".into(), vec!["did you mean M.size?".into()]),
+            ]
+        );
+
+        // Names too far from anything in scope get no suggestion
+        assert_eq!(
+            diagnostics("val x = 1; val y = zebra"),
+            [("unbound variable zebra".into(), vec![])]
+        );
+    }
+
+    #[test]
+    fn namespaces() {
+        let input = "datatype 'a list = Nil | Cons of 'a * 'a list; \
+                     val x = list; \
+                     val f = fn (n : x) => n; \
+                     signature S = sig type t end; \
+                     structure M : S = struct type t = int end; \
+                     val y = M; \
+                     val z = S.t; \
+                     structure N : M = struct type t = int end; \
+                     val w = M.t";
+        assert_eq!(
+            diagnostics(input),
+            [
+                (
+                    "unbound variable list".into(),
+                    vec!["list is a type, not a value".into()]
+                ),
+                ("undefined type x".into(), vec!["x is a value, not a type".into()]),
+                (
+                    "undefined constructor M".into(),
+                    vec!["M is a structure, not a constructor".into()]
+                ),
+                (
+                    "undefined structure S".into(),
+                    vec!["S is a signature, not a structure".into()]
+                ),
+                (
+                    "undefined signature M".into(),
+                    vec!["M is a structure, not a signature".into()]
+                ),
+                ("undefined value M.t".into(), vec!["M.t is a type, not a value".into()]),
+            ]
+        );
+
+        // A misspelling and a name from another namespace are both noted
+        let input = "type count = int; val counts = 1; val n = count";
+        assert_eq!(
+            diagnostics(input),
+            [(
+                "unbound variable count".into(),
+                vec!["did you mean counts?".into(), "count is a type, not a value".into()]
+            )]
+        );
+    }
+
+    #[test]
+    fn resolutions() {
+        let input = "datatype t = A | B\n\
+                     fun f x = case x of A => x | B => f A end\n\
+                     val g = fn (x : t) => let val x = 1 in x end\n\
+                     structure M = struct val y = 1 end\n\
+                     val z = (M.y, w)";
+        let program = Parser::with_spans(input).parse_program().unwrap();
+        let (resolutions, errors) = resolve(&program);
+        assert_eq!(errors.len(), 1);
+        let uses = resolutions
+            .iter()
+            .map(|r| (r.namespace, r.name.as_str(), r.span.start.line, r.binding.start.line))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            uses,
+            [
+                (Namespace::Value, "x", 1, 1),
+                (Namespace::Constructor, "A", 1, 0),
+                (Namespace::Value, "x", 1, 1),
+                (Namespace::Constructor, "B", 1, 0),
+                (Namespace::Value, "f", 1, 1),
+                (Namespace::Constructor, "A", 1, 0),
+                (Namespace::Type, "t", 2, 0),
+                (Namespace::Value, "x", 2, 2),
+                (Namespace::Structure, "M", 4, 3),
+            ]
+        );
+
+        // The x of the let shadows the parameter
+        let at = |line, col| resolution_at(&resolutions, line, col).map(|r| (r.name.as_str(), r.binding.start.col));
+        assert_eq!(at(2, 39), Some(("x", 30)));
+        assert_eq!(at(2, 16), Some(("t", 0)));
+        assert_eq!(at(2, 0), None);
+    }
+}
@@ -0,0 +1,128 @@
+//! Interned names
+//!
+//! A [`Symbol`] is a handle to a string in a table kept for each thread, so
+//! that names can be copied, compared and hashed as cheaply as an integer.
+//! Symbols print as the string they stand for, and are only meaningful on
+//! the thread that interned them. Interned strings are never freed, as the
+//! names of a program are needed for as long as it is being checked
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Symbols are ordered by the strings they stand for, not by when they
+/// were interned, so that sorting names gives the same order either way
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+#[derive(Default)]
+struct Interner {
+    symbols: HashMap<&'static str, Symbol>,
+    strings: Vec<&'static str>,
+}
+
+thread_local! {
+    static INTERNER: RefCell<Interner> = RefCell::new(Interner::default());
+}
+
+impl Symbol {
+    /// The symbol for `s`, which is the same for every string equal to it
+    pub fn intern(s: &str) -> Symbol {
+        INTERNER.with(|interner| {
+            let mut interner = interner.borrow_mut();
+            if let Some(&sym) = interner.symbols.get(s) {
+                return sym;
+            }
+            let s: &'static str = Box::leak(s.to_string().into_boxed_str());
+            let sym = Symbol(interner.strings.len() as u32);
+            interner.strings.push(s);
+            interner.symbols.insert(s, sym);
+            sym
+        })
+    }
+
+    pub fn as_str(self) -> &'static str {
+        INTERNER.with(|interner| interner.borrow().strings[self.0 as usize])
+    }
+}
+
+/// The empty name, given to bindings that have none of their own
+impl Default for Symbol {
+    fn default() -> Symbol {
+        Symbol::intern("")
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(s: &str) -> Symbol {
+        Symbol::intern(s)
+    }
+}
+
+impl From<String> for Symbol {
+    fn from(s: String) -> Symbol {
+        Symbol::intern(&s)
+    }
+}
+
+impl From<&String> for Symbol {
+    fn from(s: &String) -> Symbol {
+        Symbol::intern(s)
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl PartialOrd for Symbol {
+    fn partial_cmp(&self, other: &Symbol) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Symbol {
+    fn cmp(&self, other: &Symbol) -> Ordering {
+        match self == other {
+            true => Ordering::Equal,
+            false => self.as_str().cmp(other.as_str()),
+        }
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn interning() {
+        let (a, b) = (Symbol::intern("map"), Symbol::intern("filter"));
+        assert_ne!(a, b);
+        assert_eq!(Symbol::intern(&String::from("map")), a);
+        assert_eq!(a.as_str(), "map");
+        assert_eq!(format!("{} {:?}", b, b), "filter \"filter\"");
+        assert!(a == "map");
+        // Interned later, but ordered first
+        assert!(Symbol::intern("apply") < a);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::symbol::Symbol;
 use util::span::Span;
 
 #[derive(Copy, Clone, Debug, Default, PartialEq, PartialOrd, Eq, Hash)]
@@ -39,22 +40,25 @@ container!(Expr, ExprKind);
 container!(Type, TypeKind);
 container!(Decl, DeclKind);
 container!(Pattern, PatKind);
+container!(Sig, SigKind);
+container!(Spec, SpecKind);
 
 pub struct Program {
     pub decls: Vec<Decl>,
 }
 
-/// Arm of a case expression
+/// Arm of a case expression, `pat if guard => expr`
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub struct Arm {
     pub pat: Pattern,
+    pub guard: Option<Expr>,
     pub expr: Expr,
     pub span: Span,
 }
 
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub struct Field {
-    pub label: String,
+    pub label: Symbol,
     pub expr: Expr,
     pub span: Span,
 }
@@ -69,29 +73,34 @@ pub enum PatKind {
     /// Constant pattern
     Literal(usize),
     /// Datatype constructor
-    Constructor(String),
+    Constructor(Symbol),
     /// Variable binding
-    Variable(String),
+    Variable(Symbol),
     /// Tuple of pattern bindings (_, x)
     Product(Vec<Pattern>),
     /// Record pattern { label1, label2 }
-    Record(Vec<String>),
+    Record(Vec<Symbol>),
     /// Algebraic datatype constructor, along with binding pattern
     Application(Box<Pattern>, Box<Pattern>),
+    /// Or-pattern (pat | pat | ...), matching if any alternative does
+    Or(Vec<Pattern>),
+    /// Layered pattern `x as pat`, binding x to the whole of the value
+    /// that pat matches
+    As(Symbol, Box<Pattern>),
 }
 
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub enum ExprKind {
     Unit,
     Int(usize),
-    Var(String),
-    Constr(String),
+    Var(Symbol),
+    Constr(Symbol),
     If(Box<Expr>, Box<Expr>, Box<Expr>),
     Abs(Box<Pattern>, Box<Expr>),
     App(Box<Expr>, Box<Expr>),
 
     /// Explicit type abstraction `fn 'x value (arg: 'x) = arg`
-    TyAbs(String, Box<Kind>, Box<Expr>),
+    TyAbs(Symbol, Box<Kind>, Box<Expr>),
 
     /// Explicit type application `e @ty`
     TyApp(Box<Expr>, Box<Type>),
@@ -100,6 +109,17 @@ pub enum ExprKind {
     Projection(Box<Expr>, Box<Expr>),
     Case(Box<Expr>, Vec<Arm>),
     Let(Vec<Decl>, Box<Expr>),
+
+    /// Existential package `pack ty, e as exists ('t :: K) of ty2`, hiding
+    /// the witness type `ty` behind the signature
+    Pack(Box<Type>, Box<Expr>, Box<Type>),
+
+    /// Open a package `open e as 't, x in e2 end`, binding its hidden type
+    /// to an abstract type variable and its contents to a value variable
+    Open(Box<Expr>, Symbol, Symbol, Box<Expr>),
+
+    /// Value component `M.x` of a structure
+    Path(Symbol, Symbol),
 }
 
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
@@ -111,18 +131,46 @@ pub struct FnArm {
 
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub enum DeclKind {
-    Type(Vec<Type>, String, Type),
-    Datatype(Vec<Type>, String, Type),
+    Type(Vec<Type>, Symbol, Type),
+    Datatype(Vec<Type>, Symbol, Type),
     Value(Vec<Type>, Pattern, Expr),
-    Function(Vec<Type>, String, Vec<FnArm>),
+    Function(Vec<Type>, Symbol, Vec<FnArm>),
     And(Box<Decl>, Box<Decl>),
     Expr(Expr),
+    /// `signature S = sig ... end`
+    Signature(Symbol, Sig),
+    /// `structure M : S = struct ... end`, where the signature is optional
+    Structure(Symbol, Option<Sig>, Vec<Decl>),
+}
+
+/// Signature expression, either the name of a declared signature or
+/// `sig specs end`
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub enum SigKind {
+    Named(Symbol),
+    Specs(Vec<Spec>),
+}
+
+/// Specification of a structure component in a signature
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub enum SpecKind {
+    /// `type 'a t`, which is abstract unless it also gives a definition,
+    /// `type 'a t = ty`
+    Type(Vec<Type>, Symbol, Option<Type>),
+    /// `val x : ty`
+    Value(Symbol, Type),
 }
 
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub enum Kind {
     Star,
     Arrow(Box<Kind>, Box<Kind>),
+    /// The kind of row variables, which stand for the rest of a record
+    Row,
+    /// A kind left to be inferred, written `_` or left out of a binder
+    Infer,
+    /// A kind metavariable, standing for a kind that is being inferred
+    Var(usize),
 }
 
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
@@ -132,43 +180,54 @@ pub enum TypeKind {
     Unit,
     Infer,
     /// Defined name
-    Defined(String),
+    Defined(Symbol),
     /// Type variable 'a
-    Variable(String),
+    Variable(Symbol),
     /// Type of functions from terms to terms
     Function(Box<Type>, Box<Type>),
     /// Sum type; None | Some of 'a
     Sum(Vec<Variant>),
     /// Tuple type (ty * ty * ... tyN), invariant that N >= 1
     Product(Vec<Type>),
-    /// Record type { [label: ty],+ }, invariant that N >=1
-    Record(Vec<Row>),
+    /// Record type { [label: ty],+ }, invariant that N >=1. An open record
+    /// type { [label: ty],+ | 'r } also has the fields of the row 'r
+    Record(Vec<Row>, Option<RowVar>),
     /// Existential type: exists (a :: K) of ty
-    Existential(String, Box<Kind>, Box<Type>),
+    Existential(Symbol, Box<Kind>, Box<Type>),
     /// Universal type: forall (a :: K) of ty
-    Universal(String, Box<Kind>, Box<Type>),
+    Universal(Symbol, Box<Kind>, Box<Type>),
     /// Type level function abstraction
-    Abstraction(String, Box<Kind>, Box<Type>),
+    Abstraction(Symbol, Box<Kind>, Box<Type>),
     /// Type level function application
     Application(Box<Type>, Box<Type>),
     /// Recursive type
     Recursive(Box<Type>),
+    /// Type component `M.t` of a structure
+    Path(Symbol, Symbol),
 }
 
 #[derive(Clone, PartialEq, PartialOrd)]
 pub struct Variant {
-    pub label: String,
+    pub label: Symbol,
     pub ty: Option<Type>,
     pub span: Span,
 }
 
 #[derive(Clone, PartialEq, PartialOrd)]
 pub struct Row {
-    pub label: String,
+    pub label: Symbol,
     pub ty: Type,
     pub span: Span,
 }
 
+/// A row variable 'r, standing for the fields of an open record type that
+/// are not listed
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct RowVar {
+    pub name: Symbol,
+    pub span: Span,
+}
+
 impl TypeKind {
     pub fn variants(&self) -> &[Variant] {
         match self {
@@ -177,14 +236,14 @@ impl TypeKind {
         }
     }
 
-    pub fn as_tyvar(&self) -> &str {
+    pub fn as_tyvar(&self) -> Symbol {
         match self {
-            TypeKind::Variable(s) => s,
+            TypeKind::Variable(s) => *s,
             _ => panic!("Not a type var!"),
         }
     }
 
-    pub fn as_tyvar_d(self) -> String {
+    pub fn as_tyvar_d(self) -> Symbol {
         match self {
             TypeKind::Variable(s) => s,
             _ => panic!("Not a type var!"),
@@ -203,3 +262,123 @@ impl std::fmt::Debug for Row {
         write!(f, "{}: {:?}", self.label, self.ty)
     }
 }
+
+impl std::fmt::Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Kind::Star => write!(f, "*"),
+            Kind::Row => write!(f, "row"),
+            Kind::Infer => write!(f, "_"),
+            Kind::Var(n) => write!(f, "?{}", n),
+            Kind::Arrow(k1, k2) => match k1.as_ref() {
+                Kind::Arrow(..) => write!(f, "({}) -> {}", k1, k2),
+                _ => write!(f, "{} -> {}", k1, k2),
+            },
+        }
+    }
+}
+
+impl Type {
+    /// Print `self` in the concrete syntax, parenthesizing it if it binds
+    /// less tightly than `prec`: 0 for function types, quantifiers and
+    /// sums, 1 for products, 2 for applications and 3 for atoms
+    fn fmt_prec(&self, prec: u8, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use TypeKind::*;
+        let own = match &self.kind {
+            Product(_) => 1,
+            Application(..) => 2,
+            Int | Bool | Unit | Infer | Defined(_) | Variable(_) | Record(..) | Path(..) => 3,
+            _ => 0,
+        };
+        if own < prec {
+            write!(f, "(")?;
+            self.fmt_prec(0, f)?;
+            return write!(f, ")");
+        }
+        match &self.kind {
+            Int => write!(f, "int"),
+            Bool => write!(f, "bool"),
+            Unit => write!(f, "unit"),
+            Infer => write!(f, "_"),
+            Defined(s) => write!(f, "{}", s),
+            Variable(s) => write!(f, "'{}", s),
+            Path(m, s) => write!(f, "{}.{}", m, s),
+            Function(ty1, ty2) => {
+                ty1.fmt_prec(1, f)?;
+                write!(f, " -> ")?;
+                ty2.fmt_prec(0, f)
+            }
+            Sum(variants) => {
+                for (i, v) in variants.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " | ")?;
+                    }
+                    write!(f, "{}", v.label)?;
+                    if let Some(ty) = &v.ty {
+                        write!(f, " of ")?;
+                        ty.fmt_prec(1, f)?;
+                    }
+                }
+                Ok(())
+            }
+            Product(tys) => {
+                for (i, ty) in tys.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " * ")?;
+                    }
+                    ty.fmt_prec(2, f)?;
+                }
+                Ok(())
+            }
+            Record(rows, tail) => {
+                write!(f, "{{")?;
+                for (i, row) in rows.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", row.label, row.ty)?;
+                }
+                if let Some(var) = tail {
+                    write!(f, " | '{}", var.name)?;
+                }
+                write!(f, "}}")
+            }
+            Existential(s, k, ty) => write!(f, "exists ('{} :: {}) of {}", s, k, ty),
+            Universal(s, k, ty) => write!(f, "forall ('{} :: {}) of {}", s, k, ty),
+            Abstraction(s, k, ty) => write!(f, "fn ('{} :: {}) => {}", s, k, ty),
+            Recursive(ty) => write!(f, "rec {}", ty),
+            Application(..) => {
+                // Arguments are written before the operator, with several
+                // of them as a sequence, e.g. ('a, 'b) either
+                let mut args = Vec::new();
+                let mut head = self;
+                while let Application(ty1, ty2) = &head.kind {
+                    args.push(ty2.as_ref());
+                    head = ty1;
+                }
+                args.reverse();
+                match args.as_slice() {
+                    [arg] => arg.fmt_prec(2, f)?,
+                    _ => {
+                        write!(f, "(")?;
+                        for (i, arg) in args.iter().enumerate() {
+                            if i > 0 {
+                                write!(f, ", ")?;
+                            }
+                            arg.fmt_prec(0, f)?;
+                        }
+                        write!(f, ")")?;
+                    }
+                }
+                write!(f, " ")?;
+                head.fmt_prec(3, f)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.fmt_prec(0, f)
+    }
+}
@@ -1,4 +1,5 @@
 use super::tokens::*;
+use crate::symbol::Symbol;
 use std::char;
 use std::iter::Peekable;
 use std::str::Chars;
@@ -111,6 +112,12 @@ impl<'s> Lexer<'s> {
             "fix" => Token::Fix,
             "rec" => Token::Rec,
             "exists" => Token::Exists,
+            "pack" => Token::Pack,
+            "open" => Token::Open,
+            "signature" => Token::Signature,
+            "sig" => Token::Sig,
+            "structure" => Token::Structure,
+            "struct" => Token::Struct,
             "forall" => Token::Forall,
             "type" => Token::Type,
             "datatype" => Token::Datatype,
@@ -118,8 +125,8 @@ impl<'s> Lexer<'s> {
             "int" => Token::TyInt,
             "unit" => Token::TyUnit,
             "bool" => Token::TyBool,
-            s if s.starts_with(char::is_uppercase) => Token::UpperId(word),
-            _ => Token::LowerId(word),
+            s if s.starts_with(char::is_uppercase) => Token::UpperId(Symbol::intern(s)),
+            s => Token::LowerId(Symbol::intern(s)),
         };
         Spanned::new(sp, kind)
     }
@@ -1,5 +1,6 @@
 pub mod ast;
 pub mod lexer;
 pub mod parser;
+pub mod pretty;
 pub mod tokens;
 pub mod visit;
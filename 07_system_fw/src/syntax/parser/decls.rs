@@ -48,7 +48,7 @@ impl<'s> Parser<'s> {
         ))
     }
 
-    fn decl_fun_arm(&mut self, ident: &str) -> Result<FnArm, Error> {
+    fn decl_fun_arm(&mut self, ident: Symbol) -> Result<FnArm, Error> {
         let mut span = self.current.span;
         let id = self.expect_lower_id()?;
         if id != ident {
@@ -66,13 +66,13 @@ impl<'s> Parser<'s> {
         self.expect(Token::Function)?;
         let tyvars = self.parse_tyvar_sequence()?;
 
-        // Peek the id and clone it, since decl_fun_arm will expect it to be there
+        // Peek the id, since decl_fun_arm will expect it to be there
         let ident = match self.current() {
-            Token::LowerId(id) => id.clone(),
+            Token::LowerId(id) => *id,
             _ => return self.error(ErrorKind::ExpectedIdentifier),
         };
 
-        let arms = self.delimited(|p| p.decl_fun_arm(&ident), Token::Bar)?;
+        let arms = self.delimited(|p| p.decl_fun_arm(ident), Token::Bar)?;
         span += self.prev;
         Ok(Decl::with_id(
             DeclKind::Function(tyvars, ident, arms),
@@ -87,11 +87,105 @@ impl<'s> Parser<'s> {
         Ok(Decl::with_id(DeclKind::Expr(expr), sp, self.allocate_ast_id()))
     }
 
+    /// Parse a specification
+    /// spec ::=    type tyvars id
+    ///             type tyvars id = ty
+    ///             val id : ty
+    fn parse_spec(&mut self) -> Result<Spec, Error> {
+        let mut span = self.current.span;
+        let kind = match self.current() {
+            Token::Type => {
+                self.bump();
+                let tyvars = self.parse_tyvar_sequence()?;
+                let tyname = self.expect_lower_id()?;
+                let ty = match self.bump_if(&Token::Equals) {
+                    true => Some(self.parse_type()?),
+                    false => None,
+                };
+                SpecKind::Type(tyvars, tyname, ty)
+            }
+            Token::Val => {
+                self.bump();
+                let name = self.expect_lower_id()?;
+                self.expect(Token::Colon)?;
+                SpecKind::Value(name, self.parse_type()?)
+            }
+            _ => return self.error(ErrorKind::ExpectedSpecification),
+        };
+        span += self.prev;
+        Ok(Spec::with_id(kind, span, self.allocate_ast_id()))
+    }
+
+    /// Parse a signature expression
+    /// sig ::=     Id
+    ///             sig spec ... specN end
+    fn parse_sig(&mut self) -> Result<Sig, Error> {
+        let mut span = self.current.span;
+        let kind = match self.current() {
+            Token::UpperId(_) => SigKind::Named(self.expect_upper_id()?),
+            Token::Sig => {
+                self.bump();
+                let mut specs = Vec::new();
+                while self.current() != &Token::End {
+                    specs.push(self.parse_spec()?);
+                    self.bump_if(&Token::Semicolon);
+                }
+                self.expect(Token::End)?;
+                SigKind::Specs(specs)
+            }
+            _ => return self.error(ErrorKind::ExpectedSignature),
+        };
+        span += self.prev;
+        Ok(Sig::with_id(kind, span, self.allocate_ast_id()))
+    }
+
+    fn decl_signature(&mut self) -> Result<Decl, Error> {
+        let mut span = self.current.span;
+        self.expect(Token::Signature)?;
+        let name = self.expect_upper_id()?;
+        self.expect(Token::Equals)?;
+        let sig = self.parse_sig()?;
+        span += self.prev;
+        Ok(Decl::with_id(
+            DeclKind::Signature(name, sig),
+            span,
+            self.allocate_ast_id(),
+        ))
+    }
+
+    fn decl_structure(&mut self) -> Result<Decl, Error> {
+        let mut span = self.current.span;
+        self.expect(Token::Structure)?;
+        let name = self.expect_upper_id()?;
+        let sig = match self.bump_if(&Token::Colon) {
+            true => Some(self.parse_sig()?),
+            false => None,
+        };
+        self.expect(Token::Equals)?;
+        if !self.bump_if(&Token::Struct) {
+            return self.error(ErrorKind::ExpectedStructure);
+        }
+        let mut decls = Vec::new();
+        while self.current() != &Token::End {
+            decls.push(self.parse_decl()?);
+            self.bump_if(&Token::Semicolon);
+        }
+        self.expect(Token::End)?;
+        span += self.prev;
+        Ok(Decl::with_id(
+            DeclKind::Structure(name, sig, decls),
+            span,
+            self.allocate_ast_id(),
+        ))
+    }
+
     /// Parse a simple declaration
     /// decl ::=    type
     ///             datatype
     ///             val
     ///             fun
+    ///             signature
+    ///             structure
     ///             exp
     pub fn parse_decl_atom(&mut self) -> Result<Decl, Error> {
         match self.current() {
@@ -99,6 +193,8 @@ impl<'s> Parser<'s> {
             Token::Datatype => self.decl_datatype(),
             Token::Val => self.decl_value(),
             Token::Function => self.decl_fun(),
+            Token::Signature => self.decl_signature(),
+            Token::Structure => self.decl_structure(),
             _ => self.decl_expr(),
         }
     }
@@ -36,11 +36,16 @@ impl<'s> Parser<'s> {
     fn case_arm(&mut self) -> Result<Arm, Error> {
         let mut span = self.current.span;
         let pat = self.once(|p| p.parse_pattern(), "missing pattern in case arm")?;
+        let guard = if self.bump_if(&Token::If) {
+            Some(self.once(|p| p.parse_expr(), "missing guard expression in case arm")?)
+        } else {
+            None
+        };
         self.expect(Token::DoubleArrow)?;
         let expr = self.once(|p| p.parse_expr(), "missing expression in case arm")?;
         self.bump_if(&Token::Comma);
         span += self.prev;
-        Ok(Arm { pat, expr, span })
+        Ok(Arm { pat, guard, expr, span })
     }
 
     fn case_expr(&mut self) -> Result<Expr, Error> {
@@ -65,6 +70,42 @@ impl<'s> Parser<'s> {
         Ok(Expr::new(ExprKind::Abs(Box::new(arg), Box::new(body)), span))
     }
 
+    /// pack ::=   `pack` ty `,` exp `as` ty
+    fn pack_expr(&mut self) -> Result<Expr, Error> {
+        let mut span = self.current.span;
+        self.expect(Token::Pack)?;
+        let witness = self.once(|p| p.parse_type(), "expected witness type in package")?;
+        self.expect(Token::Comma)?;
+        let expr = self.once(|p| p.parse_expr(), "expected expression in package")?;
+        self.expect(Token::As)?;
+        let sig = self.once(|p| p.parse_type(), "expected existential type of package")?;
+        span += self.prev;
+        Ok(Expr::new(
+            ExprKind::Pack(Box::new(witness), Box::new(expr), Box::new(sig)),
+            span,
+        ))
+    }
+
+    /// open ::=   `open` exp `as` tyvar `,` id `in` exp `end`
+    fn open_expr(&mut self) -> Result<Expr, Error> {
+        let mut span = self.current.span;
+        self.expect(Token::Open)?;
+        let package = self.once(|p| p.parse_expr(), "expected package to open")?;
+        self.expect(Token::As)?;
+        self.expect(Token::Apostrophe)?;
+        let tyvar = self.expect_lower_id()?;
+        self.expect(Token::Comma)?;
+        let var = self.expect_lower_id()?;
+        self.expect(Token::In)?;
+        let body = self.once(|p| p.parse_expr(), "open body required")?;
+        self.expect(Token::End)?;
+        span += self.prev;
+        Ok(Expr::new(
+            ExprKind::Open(Box::new(package), tyvar, var, Box::new(body)),
+            span,
+        ))
+    }
+
     fn if_expr(&mut self) -> Result<Expr, Error> {
         let mut span = self.current.span;
         self.expect(Token::If)?;
@@ -82,6 +123,7 @@ impl<'s> Parser<'s> {
 
     /// atexp ::=   constant
     ///             id
+    ///             Id.id
     ///             { [label = exp] }
     ///             ()
     ///             ( exp, ... expN )
@@ -91,7 +133,15 @@ impl<'s> Parser<'s> {
         let mut span = self.current.span;
         match self.current.data {
             Token::LowerId(_) => self.expect_lower_id().map(|e| Expr::new(ExprKind::Var(e), span)),
-            Token::UpperId(_) => self.expect_upper_id().map(|e| Expr::new(ExprKind::Constr(e), span)),
+            Token::UpperId(_) => {
+                let id = self.expect_upper_id()?;
+                if self.bump_if(&Token::Dot) {
+                    let name = self.expect_lower_id()?;
+                    span += self.prev;
+                    return Ok(Expr::new(ExprKind::Path(id, name), span));
+                }
+                Ok(Expr::new(ExprKind::Constr(id), span))
+            }
             Token::LBrace => self.record_expr(),
             Token::Let => self.let_binding(),
             Token::Int(n) => {
@@ -134,8 +184,8 @@ impl<'s> Parser<'s> {
         let mut span = self.current.span;
         let mut expr = self.projection_expr()?;
         loop {
-            if let Token::LowerId(s) = &self.current() {
-                if self.infix.get(&s).is_some() {
+            if let Token::LowerId(s) = self.current() {
+                if self.infix.get(*s).is_some() {
                     break;
                 }
             }
@@ -176,12 +226,16 @@ impl<'s> Parser<'s> {
     /// exp ::=     if exp then exp2 else exp3
     ///             case exp of casearm end
     ///             fn x
+    ///             pack
+    ///             open
     ///             infix
     pub fn parse_expr(&mut self) -> Result<Expr, Error> {
         match self.current() {
             Token::Case => self.case_expr(),
             Token::If => self.if_expr(),
             Token::Lambda => self.lambda_expr(),
+            Token::Pack => self.pack_expr(),
+            Token::Open => self.open_expr(),
             _ => self.application_expr(),
         }
     }
@@ -1,16 +1,17 @@
+use crate::symbol::Symbol;
 use std::collections::HashMap;
 
 #[derive(Clone, Default, Debug)]
 pub struct Infix {
-    precedence: HashMap<String, usize>,
+    precedence: HashMap<Symbol, usize>,
 }
 
 impl Infix {
-    pub fn insert(&mut self, s: String, prec: usize) {
+    pub fn insert(&mut self, s: Symbol, prec: usize) {
         self.precedence.insert(s, prec);
     }
 
-    pub fn get(&self, s: &str) -> Option<usize> {
-        self.precedence.get(s).copied()
+    pub fn get(&self, s: Symbol) -> Option<usize> {
+        self.precedence.get(&s).copied()
     }
 }
@@ -7,6 +7,7 @@ pub mod types;
 use super::ast::*;
 use super::lexer::Lexer;
 use super::tokens::*;
+use crate::symbol::Symbol;
 use infix::Infix;
 use util::span::{Span, Spanned};
 
@@ -16,6 +17,10 @@ pub struct Parser<'s> {
     prev: Span,
     infix: Infix,
     next_ast_id: AstId,
+    /// Whether to keep the spans of tokens, which are otherwise left out in
+    /// tests, so that trees parsed from different inputs compare equal
+    #[cfg(test)]
+    spans: bool,
 }
 
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
@@ -57,11 +62,24 @@ impl<'s> Parser<'s> {
             infix: state.0,
             prev: Span::zero(),
             next_ast_id: AstId(0),
+            #[cfg(test)]
+            spans: false,
         };
         p.bump();
         p
     }
 
+    /// A parser that keeps the spans of the tokens it parses, for tests
+    /// about them
+    #[cfg(test)]
+    pub fn with_spans(input: &'s str) -> Parser<'s> {
+        let mut p = Parser::new(input);
+        p.tokens = Lexer::new(input.chars());
+        p.spans = true;
+        p.bump();
+        p
+    }
+
     pub fn top_level(&mut self) -> Result<Vec<Decl>, Error> {
         let mut v = Vec::new();
         while self.current() != &Token::EOF {
@@ -101,6 +119,10 @@ impl<'s> Parser<'s> {
             Some(t) => {
                 #[cfg(test)]
                 {
+                    if self.spans {
+                        self.prev = self.current.span;
+                        return std::mem::replace(&mut self.current, t).data();
+                    }
                     let t = std::mem::replace(&mut self.current, t).data();
                     self.current.span = Span::default();
                     self.prev = Span::default();
@@ -136,16 +158,16 @@ impl<'s> Parser<'s> {
         }
     }
 
-    fn expect_lower_id(&mut self) -> Result<String, Error> {
+    fn expect_lower_id(&mut self) -> Result<Symbol, Error> {
         match self.current() {
-            Token::LowerId(_) => Ok(self.bump().extract_string()),
+            Token::LowerId(_) => Ok(self.bump().extract_symbol()),
             _ => self.error(ErrorKind::ExpectedIdentifier),
         }
     }
 
-    fn expect_upper_id(&mut self) -> Result<String, Error> {
+    fn expect_upper_id(&mut self) -> Result<Symbol, Error> {
         match self.current() {
-            Token::UpperId(_) => Ok(self.bump().extract_string()),
+            Token::UpperId(_) => Ok(self.bump().extract_symbol()),
             _ => self.error(ErrorKind::ExpectedIdentifier),
         }
     }
@@ -3,10 +3,23 @@ use super::*;
 use PatKind::*;
 
 impl<'s> Parser<'s> {
+    /// Parse a pattern of form: `pat` | `pat | pat | ...`. Since `|` also
+    /// separates the arms of a case expression, or-patterns are only
+    /// allowed in parentheses
+    fn or_pattern(&mut self) -> Result<Pattern, Error> {
+        let mut span = self.current.span;
+        let mut v = self.delimited(|p| p.parse_pattern(), Token::Bar)?;
+        span += self.prev;
+        match v.len() {
+            1 => Ok(v.pop().unwrap()),
+            _ => Ok(Pattern::new(Or(v), span)),
+        }
+    }
+
     fn tuple_pattern(&mut self) -> Result<Pattern, Error> {
         let mut span = self.current.span;
         self.expect(Token::LParen)?;
-        let mut v = self.star(|p| p.parse_pattern(), Some(&Token::Comma));
+        let mut v = self.star(|p| p.or_pattern(), Some(&Token::Comma));
         self.expect(Token::RParen)?;
         span += self.prev;
         match v.len() {
@@ -30,6 +43,7 @@ impl<'s> Parser<'s> {
     ///             wildcard
     ///             ( pat )
     ///             ( pat, ... patN )
+    ///             ( pat | ... patN )
     ///             { [patrow] }
     pub(crate) fn atomic_pattern(&mut self) -> Result<Pattern, Error> {
         let span = self.current.span;
@@ -76,9 +90,20 @@ impl<'s> Parser<'s> {
         Ok(pat)
     }
 
+    /// pat ::=     app_pat
+    ///             app_pat : ty
+    ///             id as pat
     pub fn parse_pattern(&mut self) -> Result<Pattern, Error> {
         let mut span = self.current.span;
         let pat = self.application_pattern()?;
+        if let Variable(name) = &pat.kind {
+            if self.bump_if(&Token::As) {
+                let name = *name;
+                let layered = self.once(|p| p.parse_pattern(), "expected pattern after `id as`")?;
+                span += self.prev;
+                return Ok(Pattern::new(As(name, Box::new(layered)), span));
+            }
+        }
         if self.bump_if(&Token::Colon) {
             let ty = self.once(|p| p.parse_type(), "expected type annotation after `pat :`")?;
             span += self.prev;
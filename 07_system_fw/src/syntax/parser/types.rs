@@ -101,19 +101,30 @@ impl<'s> Parser<'s> {
         Ok(Row { label, ty, span })
     }
 
-    /// Parse a type of form `{ label: ty, label2: ty2, ...}`
+    /// Parse a type of form `{ label: ty, label2: ty2, ...}`, or an open
+    /// record type `{ label: ty, ... | 'r }`
     fn record(&mut self) -> Result<Type, Error> {
         let mut span = self.current.span;
         self.expect(Token::LBrace)?;
         let rows = self.delimited(|p| p.row(), Token::Comma)?;
+        let tail = if self.bump_if(&Token::Bar) {
+            let var = self.once(|p| p.parse_tyvar(), "open record type requires a row variable")?;
+            Some(RowVar {
+                span: var.span,
+                name: var.kind.as_tyvar_d(),
+            })
+        } else {
+            None
+        };
         self.expect(Token::RBrace)?;
         span += self.prev;
-        Ok(Type::new(Record(rows), span))
+        Ok(Type::new(Record(rows, tail), span))
     }
 
     /// Parse a type of form:
     /// ty ::=  'var
     ///         id
+    ///         Id.id
     ///         ( ty )
     ///         ( ty1, ... tyN) ty
     ///         fn (var :: kind) => ty
@@ -121,6 +132,7 @@ impl<'s> Parser<'s> {
     ///         forall (var :: kind) of ty
     ///         rec ty
     ///         { label: ty, ...}
+    ///         { label: ty, ... | 'r }
     pub(crate) fn type_atom(&mut self) -> Result<Type, Error> {
         let mut span = self.current.span;
         match self.current.data {
@@ -138,6 +150,13 @@ impl<'s> Parser<'s> {
             }
             Token::Apostrophe => self.parse_tyvar(),
             Token::LowerId(_) => self.expect_lower_id().map(|p| Type::new(Defined(p), span)),
+            Token::UpperId(_) => {
+                let id = self.expect_upper_id()?;
+                self.expect(Token::Dot)?;
+                let name = self.expect_lower_id()?;
+                span += self.prev;
+                Ok(Type::new(Path(id, name), span))
+            }
             Token::Lambda => self.abstraction(),
             Token::Exists => self.existential(),
             Token::Forall => self.universal(),
@@ -169,13 +188,20 @@ impl<'s> Parser<'s> {
         }
     }
 
-    /// Parse an argument of form: `('t :: K)`
+    /// Parse an argument of form: `('t :: K)`, or `('t)` or `'t` if its
+    /// kind is to be inferred
     fn abstraction_arg(&mut self) -> Result<(Type, Kind), Error> {
+        if self.current() == &Token::Apostrophe {
+            return Ok((self.parse_tyvar()?, Kind::Infer));
+        }
         self.expect(Token::LParen)?;
         let tyvar = self.parse_tyvar()?;
-        self.expect(Token::Colon)?;
-        self.expect(Token::Colon)?;
-        let k = self.kind()?;
+        let k = if self.bump_if(&Token::Colon) {
+            self.expect(Token::Colon)?;
+            self.kind()?
+        } else {
+            Kind::Infer
+        };
         self.expect(Token::RParen)?;
         Ok((tyvar, k))
     }
@@ -234,13 +260,19 @@ impl<'s> Parser<'s> {
         Ok(ty)
     }
 
-    /// Parse a kind of form: `* | ( K )`
+    /// Parse a kind of form: `* | row | ( K )`
     fn kind_single(&mut self) -> Result<Kind, Error> {
         if self.bump_if(&Token::LParen) {
             let k = self.kind()?;
             self.expect(Token::RParen)?;
             return Ok(k);
         }
+        if self.bump_if(&Token::LowerId("row".into())) {
+            return Ok(Kind::Row);
+        }
+        if self.bump_if(&Token::Wildcard) {
+            return Ok(Kind::Infer);
+        }
         self.expect(Token::Asterisk)?;
         Ok(Kind::Star)
     }
@@ -0,0 +1,759 @@
+//! Pretty printing of types and kinds
+//!
+//! Unlike `Display`, which spells out the kind of every binder, the pretty
+//! printer leaves out the kind `*`, so `forall ('a :: *) of 'a -> 'a` is
+//! printed as `forall 'a of 'a -> 'a`. Parentheses are only added where a
+//! type binds less tightly than its position requires: `->` associates to
+//! the right, products bind tighter than functions, and type application,
+//! which is postfix as in `int list` or `(int, bool) either`, tighter still.
+//!
+//! The [`Style::Ascii`] output is in the concrete syntax, and parses back to
+//! the same type, with the left out kinds to be inferred as `*`. The
+//! [`Style::Unicode`] output is for reading: `∀'a. 'a → 'a`
+//!
+//! Expressions, patterns and declarations are printed in the concrete
+//! syntax, whatever the style, except for their types. A constructor of a
+//! datatype declared in what is printed, applied to a tuple of all of its
+//! fields, is printed in the curried style, `Cons x xs` rather than
+//! `Cons (x, xs)`, which means the same, see [`desugar`](crate::desugar).
+//! Constructor patterns are always printed in the tuple style
+use super::ast::{
+    Decl, DeclKind, Expr, ExprKind, FnArm, Kind, PatKind, Pattern, Program, Sig, SigKind, SpecKind, Type, TypeKind,
+};
+use crate::symbol::Symbol;
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Style {
+    Ascii,
+    Unicode,
+}
+
+impl Style {
+    fn arrow(self) -> &'static str {
+        match self {
+            Style::Ascii => "->",
+            Style::Unicode => "→",
+        }
+    }
+
+    fn product(self) -> &'static str {
+        match self {
+            Style::Ascii => "*",
+            Style::Unicode => "×",
+        }
+    }
+}
+
+/// A type, kind, expression, pattern, declaration or program, displayed in
+/// a [`Style`]
+pub struct Pretty<'a, T> {
+    item: &'a T,
+    style: Style,
+}
+
+impl Type {
+    pub fn pretty(&self, style: Style) -> Pretty<'_, Type> {
+        Pretty { item: self, style }
+    }
+}
+
+impl Kind {
+    pub fn pretty(&self, style: Style) -> Pretty<'_, Kind> {
+        Pretty { item: self, style }
+    }
+}
+
+impl Expr {
+    pub fn pretty(&self, style: Style) -> Pretty<'_, Expr> {
+        Pretty { item: self, style }
+    }
+}
+
+impl Pattern {
+    pub fn pretty(&self, style: Style) -> Pretty<'_, Pattern> {
+        Pretty { item: self, style }
+    }
+}
+
+impl Decl {
+    pub fn pretty(&self, style: Style) -> Pretty<'_, Decl> {
+        Pretty { item: self, style }
+    }
+}
+
+impl Program {
+    pub fn pretty(&self, style: Style) -> Pretty<'_, Program> {
+        Pretty { item: self, style }
+    }
+}
+
+impl<'a> fmt::Display for Pretty<'a, Kind> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let arrow = self.style.arrow();
+        match self.item {
+            Kind::Arrow(k1, k2) => match k1.as_ref() {
+                Kind::Arrow(..) => write!(f, "({}) {} {}", k1.pretty(self.style), arrow, k2.pretty(self.style)),
+                _ => write!(f, "{} {} {}", k1.pretty(self.style), arrow, k2.pretty(self.style)),
+            },
+            k => write!(f, "{}", k),
+        }
+    }
+}
+
+impl<'a> fmt::Display for Pretty<'a, Type> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Printer::new(f, self.style).ty(self.item, 0)
+    }
+}
+
+impl<'a> fmt::Display for Pretty<'a, Expr> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Printer::new(f, self.style).expr(self.item, 0)
+    }
+}
+
+impl<'a> fmt::Display for Pretty<'a, Pattern> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Printer::new(f, self.style).pat(self.item, 0)
+    }
+}
+
+impl<'a> fmt::Display for Pretty<'a, Decl> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Printer::new(f, self.style).decl(self.item)
+    }
+}
+
+/// One declaration per line, each but the last followed by `;`
+impl<'a> fmt::Display for Pretty<'a, Program> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Printer::new(f, self.style).decls(&self.item.decls, ";\n")
+    }
+}
+
+struct Printer<'f, 'a> {
+    f: &'f mut fmt::Formatter<'a>,
+    style: Style,
+    /// The constructors declared so far, innermost last, with the length of
+    /// the tuple each takes, if it takes one
+    constructors: Vec<(Symbol, Option<usize>)>,
+}
+
+impl<'f, 'a> Printer<'f, 'a> {
+    fn new(f: &'f mut fmt::Formatter<'a>, style: Style) -> Printer<'f, 'a> {
+        Printer {
+            f,
+            style,
+            constructors: Vec::new(),
+        }
+    }
+
+    /// Print each of `items` with `each`, separated by `sep`
+    fn sep<T, F>(&mut self, items: &[T], sep: &str, mut each: F) -> fmt::Result
+    where
+        F: FnMut(&mut Self, &T) -> fmt::Result,
+    {
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                write!(self.f, "{}", sep)?;
+            }
+            each(self, item)?;
+        }
+        Ok(())
+    }
+
+    /// Print `e`, parenthesizing it if it binds less tightly than `prec`: 0
+    /// for expressions that extend as far to the right as possible, 1 for
+    /// applications, 2 for projections and 3 for atoms
+    fn expr(&mut self, e: &Expr, prec: u8) -> fmt::Result {
+        use ExprKind::*;
+        let own = match &e.kind {
+            If(..) | Abs(..) | Case(..) | Pack(..) | Open(..) | TyAbs(..) => 0,
+            App(..) | TyApp(..) => 1,
+            Projection(..) => 2,
+            _ => 3,
+        };
+        if own < prec {
+            write!(self.f, "(")?;
+            self.expr(e, 0)?;
+            return write!(self.f, ")");
+        }
+        match &e.kind {
+            Unit => write!(self.f, "()"),
+            Int(n) => write!(self.f, "{}", n),
+            Var(s) | Constr(s) => write!(self.f, "{}", s),
+            Path(m, x) => write!(self.f, "{}.{}", m, x),
+            If(e1, e2, e3) => {
+                write!(self.f, "if ")?;
+                self.expr(e1, 0)?;
+                write!(self.f, " then ")?;
+                self.expr(e2, 0)?;
+                write!(self.f, " else ")?;
+                self.expr(e3, 0)
+            }
+            Abs(pat, body) => {
+                write!(self.f, "fn ")?;
+                self.pat(pat, 0)?;
+                write!(self.f, " => ")?;
+                self.expr(body, 0)
+            }
+            App(e1, e2) => match (&e1.kind, &e2.kind) {
+                (Constr(c), Tuple(args)) if self.tuple_arity(*c) == Some(args.len()) => {
+                    write!(self.f, "{}", c)?;
+                    for arg in args {
+                        write!(self.f, " ")?;
+                        self.expr(arg, 2)?;
+                    }
+                    Ok(())
+                }
+                _ => {
+                    self.expr(e1, 1)?;
+                    write!(self.f, " ")?;
+                    self.expr(e2, 2)
+                }
+            },
+            // There is no syntax for type abstractions, so this is printed
+            // as for type level functions
+            TyAbs(s, k, body) => {
+                self.binder_head(("fn", "=>", "λ"), *s, k)?;
+                self.expr(body, 0)
+            }
+            TyApp(e1, ty) => {
+                self.expr(e1, 1)?;
+                write!(self.f, " @")?;
+                self.ty(ty, 3)
+            }
+            Record(fields) => {
+                write!(self.f, "{{")?;
+                self.sep(fields, ", ", |p, field| {
+                    write!(p.f, "{} = ", field.label)?;
+                    p.expr(&field.expr, 0)
+                })?;
+                write!(self.f, "}}")
+            }
+            Tuple(exprs) => {
+                write!(self.f, "(")?;
+                self.sep(exprs, ", ", |p, e| p.expr(e, 0))?;
+                write!(self.f, ")")
+            }
+            Projection(e1, label) => {
+                self.expr(e1, 2)?;
+                write!(self.f, ".")?;
+                self.expr(label, 3)
+            }
+            Case(e1, arms) => {
+                write!(self.f, "case ")?;
+                self.expr(e1, 0)?;
+                write!(self.f, " of ")?;
+                self.sep(arms, " | ", |p, arm| {
+                    p.pat(&arm.pat, 0)?;
+                    if let Some(guard) = &arm.guard {
+                        write!(p.f, " if ")?;
+                        p.expr(guard, 0)?;
+                    }
+                    write!(p.f, " => ")?;
+                    p.expr(&arm.expr, 0)
+                })?;
+                write!(self.f, " end")
+            }
+            Let(decls, body) => {
+                let n = self.constructors.len();
+                write!(self.f, "let ")?;
+                self.decls(decls, "; ")?;
+                write!(self.f, " in ")?;
+                self.expr(body, 0)?;
+                self.constructors.truncate(n);
+                write!(self.f, " end")
+            }
+            Pack(witness, e1, sig) => {
+                write!(self.f, "pack ")?;
+                self.ty(witness, 0)?;
+                write!(self.f, ", ")?;
+                self.expr(e1, 0)?;
+                write!(self.f, " as ")?;
+                self.ty(sig, 0)
+            }
+            Open(e1, tyvar, var, body) => {
+                write!(self.f, "open ")?;
+                self.expr(e1, 0)?;
+                write!(self.f, " as '{}, {} in ", tyvar, var)?;
+                self.expr(body, 0)?;
+                write!(self.f, " end")
+            }
+        }
+    }
+
+    /// The length of the tuple the innermost constructor `name` takes, if
+    /// it takes one
+    fn tuple_arity(&self, name: Symbol) -> Option<usize> {
+        self.constructors
+            .iter()
+            .rev()
+            .find(|(c, _)| *c == name)
+            .and_then(|(_, arity)| *arity)
+    }
+
+    /// Print `pat`, parenthesizing it if it binds less tightly than `prec`:
+    /// 0 for constructor applications and 1 for atoms. Ascriptions and
+    /// or-patterns are always parenthesized, as they may only be written so
+    /// everywhere but at the top of a pattern
+    fn pat(&mut self, pat: &Pattern, prec: u8) -> fmt::Result {
+        use PatKind::*;
+        match &pat.kind {
+            Any => write!(self.f, "_"),
+            Unit => write!(self.f, "()"),
+            Literal(n) => write!(self.f, "{}", n),
+            Constructor(s) | Variable(s) => write!(self.f, "{}", s),
+            Ascribe(pat, ty) => {
+                write!(self.f, "(")?;
+                self.pat(pat, 0)?;
+                write!(self.f, " : ")?;
+                self.ty(ty, 0)?;
+                write!(self.f, ")")
+            }
+            Product(pats) => {
+                write!(self.f, "(")?;
+                self.sep(pats, ", ", |p, pat| p.pat(pat, 0))?;
+                write!(self.f, ")")
+            }
+            Record(labels) => {
+                write!(self.f, "{{")?;
+                self.sep(labels, ", ", |p, label| write!(p.f, "{}", label))?;
+                write!(self.f, "}}")
+            }
+            Or(pats) => {
+                write!(self.f, "(")?;
+                self.sep(pats, " | ", |p, pat| p.pat(pat, 0))?;
+                write!(self.f, ")")
+            }
+            As(s, pat) if prec > 0 => {
+                write!(self.f, "({} as ", s)?;
+                self.pat(pat, 0)?;
+                write!(self.f, ")")
+            }
+            As(s, pat) => {
+                write!(self.f, "{} as ", s)?;
+                self.pat(pat, 0)
+            }
+            Application(con, arg) if prec > 0 => {
+                write!(self.f, "(")?;
+                self.pat(con, 1)?;
+                write!(self.f, " ")?;
+                self.pat(arg, 1)?;
+                write!(self.f, ")")
+            }
+            Application(con, arg) => {
+                self.pat(con, 1)?;
+                write!(self.f, " ")?;
+                self.pat(arg, 1)
+            }
+        }
+    }
+
+    /// Print `decls`, separated by `sep`, with the constructors of each
+    /// datatype in scope for those after it
+    fn decls(&mut self, decls: &[Decl], sep: &str) -> fmt::Result {
+        self.sep(decls, sep, |p, d| p.decl(d))
+    }
+
+    fn decl(&mut self, d: &Decl) -> fmt::Result {
+        match &d.kind {
+            DeclKind::Type(tyvars, name, ty) => {
+                write!(self.f, "type ")?;
+                self.tyvars(tyvars)?;
+                write!(self.f, "{} = ", name)?;
+                self.ty(ty, 0)
+            }
+            DeclKind::Datatype(tyvars, name, ty) => {
+                write!(self.f, "datatype ")?;
+                self.tyvars(tyvars)?;
+                write!(self.f, "{} = ", name)?;
+                self.ty(ty, 0)?;
+                for v in ty.kind.variants() {
+                    let arity = match v.ty.as_ref().map(|ty| &ty.kind) {
+                        Some(TypeKind::Product(tys)) if tys.len() > 1 => Some(tys.len()),
+                        _ => None,
+                    };
+                    self.constructors.push((v.label, arity));
+                }
+                Ok(())
+            }
+            DeclKind::Value(tyvars, pat, e) => {
+                write!(self.f, "val ")?;
+                self.tyvars(tyvars)?;
+                self.pat(pat, 0)?;
+                write!(self.f, " = ")?;
+                self.expr(e, 0)
+            }
+            DeclKind::Function(tyvars, name, arms) => {
+                write!(self.f, "fun ")?;
+                self.tyvars(tyvars)?;
+                self.sep(arms, " | ", |p, arm: &FnArm| {
+                    write!(p.f, "{}", name)?;
+                    for pat in &arm.pats {
+                        write!(p.f, " ")?;
+                        p.pat(pat, 1)?;
+                    }
+                    write!(p.f, " = ")?;
+                    p.expr(&arm.expr, 0)
+                })
+            }
+            DeclKind::And(d1, d2) => {
+                self.decl(d1)?;
+                write!(self.f, " and ")?;
+                self.decl(d2)
+            }
+            DeclKind::Expr(e) => self.expr(e, 0),
+            DeclKind::Signature(name, sig) => {
+                write!(self.f, "signature {} = ", name)?;
+                self.sig(sig)
+            }
+            DeclKind::Structure(name, sig, decls) => {
+                write!(self.f, "structure {}", name)?;
+                if let Some(sig) = sig {
+                    write!(self.f, " : ")?;
+                    self.sig(sig)?;
+                }
+                let n = self.constructors.len();
+                write!(self.f, " = struct ")?;
+                self.decls(decls, "; ")?;
+                self.constructors.truncate(n);
+                write!(self.f, " end")
+            }
+        }
+    }
+
+    fn sig(&mut self, sig: &Sig) -> fmt::Result {
+        match &sig.kind {
+            SigKind::Named(name) => write!(self.f, "{}", name),
+            SigKind::Specs(specs) => {
+                write!(self.f, "sig")?;
+                for spec in specs {
+                    match &spec.kind {
+                        SpecKind::Type(tyvars, name, ty) => {
+                            write!(self.f, " type ")?;
+                            self.tyvars(tyvars)?;
+                            write!(self.f, "{}", name)?;
+                            if let Some(ty) = ty {
+                                write!(self.f, " = ")?;
+                                self.ty(ty, 0)?;
+                            }
+                        }
+                        SpecKind::Value(name, ty) => {
+                            write!(self.f, " val {} : ", name)?;
+                            self.ty(ty, 0)?;
+                        }
+                    }
+                }
+                write!(self.f, " end")
+            }
+        }
+    }
+
+    /// Print the type parameters of a declaration, `'a ` or `('a, 'b) `
+    fn tyvars(&mut self, tyvars: &[Type]) -> fmt::Result {
+        match tyvars {
+            [] => Ok(()),
+            [tyvar] => {
+                self.ty(tyvar, 3)?;
+                write!(self.f, " ")
+            }
+            _ => {
+                write!(self.f, "(")?;
+                self.sep(tyvars, ", ", |p, tyvar| p.ty(tyvar, 0))?;
+                write!(self.f, ") ")
+            }
+        }
+    }
+
+    /// Print `ty`, parenthesizing it if it binds less tightly than `prec`:
+    /// 0 for function types, binders and sums, 1 for products, 2 for
+    /// applications and 3 for atoms
+    fn ty(&mut self, ty: &Type, prec: u8) -> fmt::Result {
+        use TypeKind::*;
+        let own = match &ty.kind {
+            Product(_) => 1,
+            Application(..) => 2,
+            Int | Bool | Unit | Infer | Defined(_) | Variable(_) | Record(..) | Path(..) => 3,
+            _ => 0,
+        };
+        if own < prec {
+            write!(self.f, "(")?;
+            self.ty(ty, 0)?;
+            return write!(self.f, ")");
+        }
+        match &ty.kind {
+            Int => write!(self.f, "int"),
+            Bool => write!(self.f, "bool"),
+            Unit => write!(self.f, "unit"),
+            Infer => write!(self.f, "_"),
+            Defined(s) => write!(self.f, "{}", s),
+            Variable(s) => write!(self.f, "'{}", s),
+            Path(m, s) => write!(self.f, "{}.{}", m, s),
+            Function(ty1, ty2) => {
+                self.ty(ty1, 1)?;
+                write!(self.f, " {} ", self.style.arrow())?;
+                self.ty(ty2, 0)
+            }
+            Sum(variants) => {
+                for (i, v) in variants.iter().enumerate() {
+                    if i > 0 {
+                        write!(self.f, " | ")?;
+                    }
+                    write!(self.f, "{}", v.label)?;
+                    if let Some(ty) = &v.ty {
+                        write!(self.f, " of ")?;
+                        self.ty(ty, 1)?;
+                    }
+                }
+                Ok(())
+            }
+            Product(tys) => {
+                for (i, ty) in tys.iter().enumerate() {
+                    if i > 0 {
+                        write!(self.f, " {} ", self.style.product())?;
+                    }
+                    self.ty(ty, 2)?;
+                }
+                Ok(())
+            }
+            Record(rows, tail) => {
+                write!(self.f, "{{")?;
+                for (i, row) in rows.iter().enumerate() {
+                    if i > 0 {
+                        write!(self.f, ", ")?;
+                    }
+                    write!(self.f, "{}: ", row.label)?;
+                    self.ty(&row.ty, 0)?;
+                }
+                if let Some(var) = tail {
+                    write!(self.f, " | '{}", var.name)?;
+                }
+                write!(self.f, "}}")
+            }
+            Existential(s, k, body) => self.binder(("exists", "of", "∃"), *s, k, body),
+            Universal(s, k, body) => self.binder(("forall", "of", "∀"), *s, k, body),
+            Abstraction(s, k, body) => self.binder(("fn", "=>", "λ"), *s, k, body),
+            Recursive(body) => {
+                match self.style {
+                    Style::Ascii => write!(self.f, "rec ")?,
+                    Style::Unicode => write!(self.f, "μ ")?,
+                }
+                self.ty(body, 0)
+            }
+            Application(..) => {
+                let mut args = Vec::new();
+                let mut head = ty;
+                while let Application(ty1, ty2) = &head.kind {
+                    args.push(ty2.as_ref());
+                    head = ty1;
+                }
+                args.reverse();
+                match args.as_slice() {
+                    [arg] => self.ty(arg, 2)?,
+                    _ => {
+                        write!(self.f, "(")?;
+                        for (i, arg) in args.iter().enumerate() {
+                            if i > 0 {
+                                write!(self.f, ", ")?;
+                            }
+                            self.ty(arg, 0)?;
+                        }
+                        write!(self.f, ")")?;
+                    }
+                }
+                write!(self.f, " ")?;
+                self.ty(head, 3)
+            }
+        }
+    }
+
+    /// Print a binder of `s`, leaving out its kind if it is `*`, in the
+    /// ASCII form `keyword ('s :: K) separator body` or the Unicode form
+    /// `symbol's::K. body`
+    fn binder(&mut self, forms: (&str, &str, &str), s: Symbol, k: &Kind, body: &Type) -> fmt::Result {
+        self.binder_head(forms, s, k)?;
+        self.ty(body, 0)
+    }
+
+    /// Print a binder of `s` up to its body
+    fn binder_head(&mut self, forms: (&str, &str, &str), s: Symbol, k: &Kind) -> fmt::Result {
+        let (keyword, separator, symbol) = forms;
+        match (self.style, k) {
+            (Style::Ascii, Kind::Star) => write!(self.f, "{} '{} {} ", keyword, s, separator),
+            (Style::Ascii, k) => write!(
+                self.f,
+                "{} ('{} :: {}) {} ",
+                keyword,
+                s,
+                k.pretty(self.style),
+                separator
+            ),
+            (Style::Unicode, Kind::Star) => write!(self.f, "{}'{}. ", symbol, s),
+            (Style::Unicode, k) => write!(self.f, "{}'{}::{}. ", symbol, s, k.pretty(self.style)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::syntax::parser::Parser;
+    use crate::syntax::visit::TypeMutVisitor;
+    use crate::types::alpha_eq;
+
+    fn ty(input: &str) -> Type {
+        Parser::new(input).parse_type().unwrap()
+    }
+
+    /// The kinds left out of binders are `*`
+    struct Elided;
+
+    impl TypeMutVisitor for Elided {
+        fn visit_ty(&mut self, ty: &mut Type) {
+            match &mut ty.kind {
+                TypeKind::Existential(_, k, _) | TypeKind::Universal(_, k, _) | TypeKind::Abstraction(_, k, _)
+                    if **k == Kind::Infer =>
+                {
+                    **k = Kind::Star
+                }
+                _ => {}
+            }
+            self.walk_ty(ty);
+        }
+    }
+
+    #[test]
+    fn snapshots() {
+        let cases = [
+            ("forall ('a :: *) of 'a -> 'a", "forall 'a of 'a -> 'a", "∀'a. 'a → 'a"),
+            (
+                "exists ('t :: *) of {new: 't, get: 't -> int}",
+                "exists 't of {new: 't, get: 't -> int}",
+                "∃'t. {new: 't, get: 't → int}",
+            ),
+            (
+                "fn ('f :: * -> *) => fn ('a :: *) => 'a 'f",
+                "fn ('f :: * -> *) => fn 'a => 'a 'f",
+                "λ'f::* → *. λ'a. 'a 'f",
+            ),
+            (
+                "exists ('f :: (* -> *) -> *) of int list 'f",
+                "exists ('f :: (* -> *) -> *) of int list 'f",
+                "∃'f::(* → *) → *. int list 'f",
+            ),
+            (
+                "(int * int) * (int, bool) either",
+                "(int * int) * (int, bool) either",
+                "(int × int) × (int, bool) either",
+            ),
+            (
+                "(forall ('a :: *) of 'a) -> (int -> int) -> int list list",
+                "(forall 'a of 'a) -> (int -> int) -> int list list",
+                "(∀'a. 'a) → (int → int) → int list list",
+            ),
+            (
+                "int (fn ('a :: *) => 'a * 'a) * {x: int | 'r}",
+                "int (fn 'a => 'a * 'a) * {x: int | 'r}",
+                "int (λ'a. 'a × 'a) × {x: int | 'r}",
+            ),
+            (
+                "rec fn ('l :: *) => {head: int, tail: 'l}",
+                "rec fn 'l => {head: int, tail: 'l}",
+                "μ λ'l. {head: int, tail: 'l}",
+            ),
+        ];
+        for (input, ascii, unicode) in cases.iter() {
+            let t = ty(input);
+            assert_eq!(t.pretty(Style::Ascii).to_string(), *ascii);
+            assert_eq!(t.pretty(Style::Unicode).to_string(), *unicode);
+        }
+
+        let k = Kind::Arrow(
+            Box::new(Kind::Arrow(Box::new(Kind::Star), Box::new(Kind::Star))),
+            Box::new(Kind::Arrow(Box::new(Kind::Row), Box::new(Kind::Star))),
+        );
+        assert_eq!(k.pretty(Style::Ascii).to_string(), "(* -> *) -> row -> *");
+        assert_eq!(k.pretty(Style::Unicode).to_string(), "(* → *) → row → *");
+    }
+
+    #[test]
+    fn round_trip() {
+        let inputs = [
+            "int -> bool -> unit",
+            "(int -> bool) -> unit",
+            "int * bool -> (unit * int) * bool",
+            "(int * bool) list option",
+            "(int list, bool -> unit) either list",
+            "int (bool (fn ('a :: *) => fn ('b :: *) => 'a * 'b))",
+            "forall ('a :: *) of forall ('f :: * -> *) of 'a 'f -> ('a * 'a) 'f",
+            "exists ('t :: *) of {new: 't, get: 't -> int, set: forall ('a :: *) of 'a -> 't}",
+            "forall ('r :: row) of {x: int | 'r} -> {y: 'a -> 'b | 'r}",
+            "(exists ('t :: *) of 't) * (fn ('a :: *) => 'a) int",
+            "rec fn ('l :: *) => {head: int, tail: unit -> 'l}",
+            "M.t -> M.t list",
+        ];
+        for input in inputs.iter() {
+            let t = ty(input);
+            let printed = t.pretty(Style::Ascii).to_string();
+            let mut parsed = ty(&printed);
+            Elided.visit_ty(&mut parsed);
+            assert!(alpha_eq(&t, &parsed), "{} printed as {}", input, printed);
+        }
+    }
+
+    fn program(input: &str) -> Program {
+        Parser::new(input).parse_program().unwrap()
+    }
+
+    #[test]
+    fn constructors() {
+        let input = "datatype 'a list = Nil | Cons of 'a * 'a list; \
+                     val xs = Cons (1, Cons (2, Nil)); \
+                     val push = Cons 1; \
+                     val ys = map (Cons 0) (Cons (xs, Nil)); \
+                     val n = case push xs of Cons (x, Cons (_, rest)) => x | _ => 0 end; \
+                     val p = let datatype pair = Cons of int * int in Cons (1, 2) end; \
+                     val q = Some (1, 2)";
+        let expected = "datatype 'a list = Nil | Cons of 'a * 'a list;\n\
+                        val xs = Cons 1 (Cons 2 Nil);\n\
+                        val push = Cons 1;\n\
+                        val ys = map (Cons 0) (Cons xs Nil);\n\
+                        val n = case push xs of Cons (x, Cons (_, rest)) => x | _ => 0 end;\n\
+                        val p = let datatype pair = Cons of int * int in Cons 1 2 end;\n\
+                        val q = Some (1, 2)";
+        let p = program(input);
+        assert_eq!(p.pretty(Style::Ascii).to_string(), expected);
+
+        // Printed on its own, a constructor is not known to take a tuple
+        match &p.decls[1].kind {
+            DeclKind::Value(_, _, e) => assert_eq!(e.pretty(Style::Ascii).to_string(), "Cons (1, Cons (2, Nil))"),
+            d => panic!("expected a value, not {:?}", d),
+        }
+    }
+
+    #[test]
+    fn expressions() {
+        let inputs = [
+            "val f = fn (x : int -> int) => fn y => x (x y)",
+            "val r = {a = if c then f x else g, b = (1, ()).1}.b",
+            "fun len (Cons (_, xs)) = len xs | len Nil = 0",
+            "val g = fn ((a : int) | b) => case a of 1 if b => 2 | _ => (fn z => z) a end",
+            "val id = fn x => x @int @(int list)",
+            "fun f (all as Cons (x, rest as Cons (_, _))) = (all, rest) | f xs = (xs, xs); \
+             val p as (a, b) = (1, 2)",
+            "val p = pack int, {new = 0} as exists ('t :: _) of {new: 't}; \
+             val n = open p as 't, c in c.new end",
+            "signature S = sig type 'a t val x : int t end; \
+             structure M : S = struct type 'a t = 'a list val x = let val y = 1 in Cons y Nil end end",
+        ];
+        for input in inputs.iter() {
+            let p = program(input);
+            let printed = p.pretty(Style::Ascii).to_string();
+            let reparsed = program(&printed);
+            assert_eq!(p.decls.len(), reparsed.decls.len(), "{} printed as {}", input, printed);
+            assert_eq!(printed, reparsed.pretty(Style::Ascii).to_string());
+        }
+    }
+}
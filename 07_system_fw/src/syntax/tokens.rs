@@ -1,3 +1,5 @@
+use crate::symbol::Symbol;
+
 #[allow(dead_code)]
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub enum Token {
@@ -37,6 +39,12 @@ pub enum Token {
     Fix,
     Rec,
     Exists,
+    Pack,
+    Open,
+    Signature,
+    Sig,
+    Structure,
+    Struct,
     Forall,
     TyInt,
     TyBool,
@@ -44,8 +52,8 @@ pub enum Token {
 
     TypeAppSigil,
 
-    LowerId(String),
-    UpperId(String),
+    LowerId(Symbol),
+    UpperId(Symbol),
 
     Comment(String),
     Int(usize),
@@ -56,9 +64,9 @@ pub enum Token {
 }
 
 impl Token {
-    pub fn extract_string(self) -> String {
+    pub fn extract_symbol(self) -> Symbol {
         match self {
-            Token::LowerId(s) | Token::UpperId(s) | Token::Comment(s) => s,
+            Token::LowerId(s) | Token::UpperId(s) => s,
             _ => panic!("Invalid token {:?}", self),
         }
     }
@@ -0,0 +1,421 @@
+use super::*;
+use ast::{Arm, Decl, DeclKind, Expr, ExprKind, Field, FnArm, Kind, PatKind, Pattern, Sig, SigKind, SpecKind, Type};
+
+/// Visit the parts of a pattern. Type annotations are handed to
+/// `visit_type`, which does nothing unless it is overridden, e.g. to
+/// delegate to a [`TypeVisitor`](super::TypeVisitor)
+pub trait PatternVisitor<'t>: Sized {
+    fn visit_literal(&mut self, _: usize) {}
+
+    fn visit_constructor(&mut self, _: Symbol) {}
+
+    fn visit_variable(&mut self, _: Symbol) {}
+
+    fn visit_product(&mut self, pats: &'t [Pattern]) {
+        for p in pats {
+            self.visit_pat(p);
+        }
+    }
+
+    /// The labels of a record pattern, each also binding a variable
+    fn visit_record(&mut self, _: &'t [Symbol]) {}
+
+    fn visit_ascribe(&mut self, pat: &'t Pattern, ty: &'t Type) {
+        self.visit_pat(pat);
+        self.visit_type(ty);
+    }
+
+    fn visit_application(&mut self, con: &'t Pattern, arg: &'t Pattern) {
+        self.visit_pat(con);
+        self.visit_pat(arg);
+    }
+
+    fn visit_or(&mut self, alts: &'t [Pattern]) {
+        for p in alts {
+            self.visit_pat(p);
+        }
+    }
+
+    /// The layered pattern `x as pat`, whose name is visited as a variable
+    fn visit_as(&mut self, name: Symbol, pat: &'t Pattern) {
+        self.visit_variable(name);
+        self.visit_pat(pat);
+    }
+
+    fn visit_type(&mut self, _: &'t Type) {}
+
+    fn visit_pat(&mut self, pat: &'t Pattern) {
+        self.walk_pat(pat);
+    }
+
+    fn walk_pat(&mut self, pat: &'t Pattern) {
+        use PatKind::*;
+        match &pat.kind {
+            Any => {}
+            Unit => {}
+            Literal(n) => self.visit_literal(*n),
+            Constructor(s) => self.visit_constructor(*s),
+            Variable(s) => self.visit_variable(*s),
+            Product(pats) => self.visit_product(pats),
+            Record(labels) => self.visit_record(labels),
+            Ascribe(pat, ty) => self.visit_ascribe(pat, ty),
+            Application(con, arg) => self.visit_application(con, arg),
+            Or(alts) => self.visit_or(alts),
+            As(name, pat) => self.visit_as(*name, pat),
+        }
+    }
+}
+
+/// Visit the parts of an expression, and the declarations within it.
+/// Patterns and type annotations are handed to `visit_pattern` and
+/// `visit_type`, which do nothing unless they are overridden, e.g. to
+/// delegate to a [`PatternVisitor`] or [`TypeVisitor`](super::TypeVisitor)
+pub trait ExprVisitor<'t>: Sized {
+    fn visit_unit(&mut self) {}
+
+    fn visit_int(&mut self, _: usize) {}
+
+    fn visit_var(&mut self, _: Symbol) {}
+
+    fn visit_constr(&mut self, _: Symbol) {}
+
+    /// The value component `M.x` of a structure
+    fn visit_path(&mut self, _: Symbol, _: Symbol) {}
+
+    fn visit_if(&mut self, e1: &'t Expr, e2: &'t Expr, e3: &'t Expr) {
+        self.visit_expr(e1);
+        self.visit_expr(e2);
+        self.visit_expr(e3);
+    }
+
+    fn visit_abs(&mut self, pat: &'t Pattern, body: &'t Expr) {
+        self.visit_pattern(pat);
+        self.visit_expr(body);
+    }
+
+    fn visit_app(&mut self, e1: &'t Expr, e2: &'t Expr) {
+        self.visit_expr(e1);
+        self.visit_expr(e2);
+    }
+
+    fn visit_tyabs(&mut self, _: Symbol, _: &'t Kind, body: &'t Expr) {
+        self.visit_expr(body);
+    }
+
+    fn visit_tyapp(&mut self, e: &'t Expr, ty: &'t Type) {
+        self.visit_expr(e);
+        self.visit_type(ty);
+    }
+
+    fn visit_record(&mut self, fields: &'t [Field]) {
+        for f in fields {
+            self.visit_expr(&f.expr);
+        }
+    }
+
+    fn visit_tuple(&mut self, exprs: &'t [Expr]) {
+        for e in exprs {
+            self.visit_expr(e);
+        }
+    }
+
+    /// The projection `e.label` or `e.0`. The label is not visited, since
+    /// it is not a variable
+    fn visit_projection(&mut self, e: &'t Expr, _: &'t Expr) {
+        self.visit_expr(e);
+    }
+
+    fn visit_case(&mut self, e: &'t Expr, arms: &'t [Arm]) {
+        self.visit_expr(e);
+        for arm in arms {
+            self.visit_arm(arm);
+        }
+    }
+
+    fn visit_arm(&mut self, arm: &'t Arm) {
+        self.visit_pattern(&arm.pat);
+        if let Some(guard) = &arm.guard {
+            self.visit_expr(guard);
+        }
+        self.visit_expr(&arm.expr);
+    }
+
+    fn visit_let(&mut self, decls: &'t [Decl], body: &'t Expr) {
+        for d in decls {
+            self.visit_decl(d);
+        }
+        self.visit_expr(body);
+    }
+
+    fn visit_pack(&mut self, witness: &'t Type, e: &'t Expr, sig: &'t Type) {
+        self.visit_type(witness);
+        self.visit_expr(e);
+        self.visit_type(sig);
+    }
+
+    /// Opening a package, binding an abstract type and a value variable in
+    /// the body
+    fn visit_open(&mut self, package: &'t Expr, _: Symbol, _: Symbol, body: &'t Expr) {
+        self.visit_expr(package);
+        self.visit_expr(body);
+    }
+
+    fn visit_fn_arm(&mut self, arm: &'t FnArm) {
+        for p in &arm.pats {
+            self.visit_pattern(p);
+        }
+        self.visit_expr(&arm.expr);
+    }
+
+    fn visit_pattern(&mut self, _: &'t Pattern) {}
+
+    fn visit_type(&mut self, _: &'t Type) {}
+
+    /// The types given by the specifications of a signature
+    fn visit_sig(&mut self, sig: &'t Sig) {
+        if let SigKind::Specs(specs) = &sig.kind {
+            for spec in specs {
+                match &spec.kind {
+                    SpecKind::Type(_, _, Some(ty)) | SpecKind::Value(_, ty) => self.visit_type(ty),
+                    SpecKind::Type(_, _, None) => {}
+                }
+            }
+        }
+    }
+
+    fn visit_structure(&mut self, _: Symbol, sig: Option<&'t Sig>, decls: &'t [Decl]) {
+        if let Some(sig) = sig {
+            self.visit_sig(sig);
+        }
+        for d in decls {
+            self.visit_decl(d);
+        }
+    }
+
+    fn visit_decl(&mut self, d: &'t Decl) {
+        self.walk_decl(d);
+    }
+
+    fn walk_decl(&mut self, d: &'t Decl) {
+        use DeclKind::*;
+        match &d.kind {
+            Type(_, _, ty) | Datatype(_, _, ty) => self.visit_type(ty),
+            Value(_, pat, e) => {
+                self.visit_pattern(pat);
+                self.visit_expr(e);
+            }
+            Function(_, _, arms) => {
+                for arm in arms {
+                    self.visit_fn_arm(arm);
+                }
+            }
+            And(d1, d2) => {
+                self.visit_decl(d1);
+                self.visit_decl(d2);
+            }
+            Expr(e) => self.visit_expr(e),
+            Signature(_, sig) => self.visit_sig(sig),
+            Structure(name, sig, decls) => self.visit_structure(*name, sig.as_ref(), decls),
+        }
+    }
+
+    fn visit_expr(&mut self, e: &'t Expr) {
+        self.walk_expr(e);
+    }
+
+    fn walk_expr(&mut self, e: &'t Expr) {
+        use ExprKind::*;
+        match &e.kind {
+            Unit => self.visit_unit(),
+            Int(n) => self.visit_int(*n),
+            Var(s) => self.visit_var(*s),
+            Constr(s) => self.visit_constr(*s),
+            If(e1, e2, e3) => self.visit_if(e1, e2, e3),
+            Abs(pat, body) => self.visit_abs(pat, body),
+            App(e1, e2) => self.visit_app(e1, e2),
+            TyAbs(s, k, body) => self.visit_tyabs(*s, k, body),
+            TyApp(e, ty) => self.visit_tyapp(e, ty),
+            Record(fields) => self.visit_record(fields),
+            Tuple(exprs) => self.visit_tuple(exprs),
+            Projection(e, label) => self.visit_projection(e, label),
+            Case(e, arms) => self.visit_case(e, arms),
+            Let(decls, body) => self.visit_let(decls, body),
+            Pack(witness, e, sig) => self.visit_pack(witness, e, sig),
+            Open(package, tyvar, var, body) => self.visit_open(package, *tyvar, *var, body),
+            Path(m, s) => self.visit_path(*m, *s),
+        }
+    }
+}
+
+/// An [`ExprVisitor`] that may change the expressions it visits in place.
+/// Whole expressions can be replaced by overriding `visit_expr`
+pub trait ExprMutVisitor: Sized {
+    fn visit_unit(&mut self) {}
+
+    fn visit_int(&mut self, _: &mut usize) {}
+
+    fn visit_var(&mut self, _: &mut Symbol) {}
+
+    fn visit_constr(&mut self, _: &mut Symbol) {}
+
+    fn visit_path(&mut self, _: &mut Symbol, _: &mut Symbol) {}
+
+    fn visit_if(&mut self, e1: &mut Expr, e2: &mut Expr, e3: &mut Expr) {
+        self.visit_expr(e1);
+        self.visit_expr(e2);
+        self.visit_expr(e3);
+    }
+
+    fn visit_abs(&mut self, pat: &mut Pattern, body: &mut Expr) {
+        self.visit_pattern(pat);
+        self.visit_expr(body);
+    }
+
+    fn visit_app(&mut self, e1: &mut Expr, e2: &mut Expr) {
+        self.visit_expr(e1);
+        self.visit_expr(e2);
+    }
+
+    fn visit_tyabs(&mut self, _: &mut Symbol, _: &mut Kind, body: &mut Expr) {
+        self.visit_expr(body);
+    }
+
+    fn visit_tyapp(&mut self, e: &mut Expr, ty: &mut Type) {
+        self.visit_expr(e);
+        self.visit_type(ty);
+    }
+
+    fn visit_record(&mut self, fields: &mut [Field]) {
+        for f in fields {
+            self.visit_expr(&mut f.expr);
+        }
+    }
+
+    fn visit_tuple(&mut self, exprs: &mut [Expr]) {
+        for e in exprs {
+            self.visit_expr(e);
+        }
+    }
+
+    fn visit_projection(&mut self, e: &mut Expr, _: &mut Expr) {
+        self.visit_expr(e);
+    }
+
+    fn visit_case(&mut self, e: &mut Expr, arms: &mut [Arm]) {
+        self.visit_expr(e);
+        for arm in arms {
+            self.visit_arm(arm);
+        }
+    }
+
+    fn visit_arm(&mut self, arm: &mut Arm) {
+        self.visit_pattern(&mut arm.pat);
+        if let Some(guard) = &mut arm.guard {
+            self.visit_expr(guard);
+        }
+        self.visit_expr(&mut arm.expr);
+    }
+
+    fn visit_let(&mut self, decls: &mut [Decl], body: &mut Expr) {
+        for d in decls {
+            self.visit_decl(d);
+        }
+        self.visit_expr(body);
+    }
+
+    fn visit_pack(&mut self, witness: &mut Type, e: &mut Expr, sig: &mut Type) {
+        self.visit_type(witness);
+        self.visit_expr(e);
+        self.visit_type(sig);
+    }
+
+    fn visit_open(&mut self, package: &mut Expr, _: &mut Symbol, _: &mut Symbol, body: &mut Expr) {
+        self.visit_expr(package);
+        self.visit_expr(body);
+    }
+
+    fn visit_fn_arm(&mut self, arm: &mut FnArm) {
+        for p in &mut arm.pats {
+            self.visit_pattern(p);
+        }
+        self.visit_expr(&mut arm.expr);
+    }
+
+    fn visit_pattern(&mut self, _: &mut Pattern) {}
+
+    fn visit_type(&mut self, _: &mut Type) {}
+
+    fn visit_sig(&mut self, sig: &mut Sig) {
+        if let SigKind::Specs(specs) = &mut sig.kind {
+            for spec in specs {
+                match &mut spec.kind {
+                    SpecKind::Type(_, _, Some(ty)) | SpecKind::Value(_, ty) => self.visit_type(ty),
+                    SpecKind::Type(_, _, None) => {}
+                }
+            }
+        }
+    }
+
+    fn visit_structure(&mut self, _: &mut Symbol, sig: Option<&mut Sig>, decls: &mut [Decl]) {
+        if let Some(sig) = sig {
+            self.visit_sig(sig);
+        }
+        for d in decls {
+            self.visit_decl(d);
+        }
+    }
+
+    fn visit_decl(&mut self, d: &mut Decl) {
+        self.walk_decl(d);
+    }
+
+    fn walk_decl(&mut self, d: &mut Decl) {
+        use DeclKind::*;
+        match &mut d.kind {
+            Type(_, _, ty) | Datatype(_, _, ty) => self.visit_type(ty),
+            Value(_, pat, e) => {
+                self.visit_pattern(pat);
+                self.visit_expr(e);
+            }
+            Function(_, _, arms) => {
+                for arm in arms {
+                    self.visit_fn_arm(arm);
+                }
+            }
+            And(d1, d2) => {
+                self.visit_decl(d1);
+                self.visit_decl(d2);
+            }
+            Expr(e) => self.visit_expr(e),
+            Signature(_, sig) => self.visit_sig(sig),
+            Structure(name, sig, decls) => self.visit_structure(name, sig.as_mut(), decls),
+        }
+    }
+
+    fn visit_expr(&mut self, e: &mut Expr) {
+        self.walk_expr(e);
+    }
+
+    fn walk_expr(&mut self, e: &mut Expr) {
+        use ExprKind::*;
+        match &mut e.kind {
+            Unit => self.visit_unit(),
+            Int(n) => self.visit_int(n),
+            Var(s) => self.visit_var(s),
+            Constr(s) => self.visit_constr(s),
+            If(e1, e2, e3) => self.visit_if(e1, e2, e3),
+            Abs(pat, body) => self.visit_abs(pat, body),
+            App(e1, e2) => self.visit_app(e1, e2),
+            TyAbs(s, k, body) => self.visit_tyabs(s, k, body),
+            TyApp(e, ty) => self.visit_tyapp(e, ty),
+            Record(fields) => self.visit_record(fields),
+            Tuple(exprs) => self.visit_tuple(exprs),
+            Projection(e, label) => self.visit_projection(e, label),
+            Case(e, arms) => self.visit_case(e, arms),
+            Let(decls, body) => self.visit_let(decls, body),
+            Pack(witness, e, sig) => self.visit_pack(witness, e, sig),
+            Open(package, tyvar, var, body) => self.visit_open(package, tyvar, var, body),
+            Path(m, s) => self.visit_path(m, s),
+        }
+    }
+}
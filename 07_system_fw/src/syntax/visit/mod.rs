@@ -1,4 +1,16 @@
 use super::*;
+use crate::symbol::Symbol;
+mod exprs;
+mod names;
+mod subst;
 mod types;
+mod values;
 
-pub use types::TypeVisitor;
+pub use exprs::{ExprMutVisitor, ExprVisitor, PatternVisitor};
+pub use names::{free_tyvars, referenced, FreeTypeVars, ReferencedDefinitions};
+pub use subst::{ExpandDefined, SubstNamedVar};
+pub use types::{TypeMutVisitor, TypeVisitor};
+pub use values::{
+    check_scope, free_vars, is_syntactic_value, non_value, unused_bindings, Bindings, FreeVars, Occurrence,
+    Occurrences, PatternBinders, ScopeChecker, Scoped,
+};
@@ -0,0 +1,115 @@
+//! Collecting the names a type refers to
+use super::types::TypeVisitor;
+use super::*;
+use ast::{Kind, RowVar, Type};
+use std::collections::BTreeSet;
+
+/// Collect the type variables that are not bound by an enclosing universal,
+/// existential or abstraction within the type
+#[derive(Default, Debug, Clone)]
+pub struct FreeTypeVars {
+    /// Type variables bound by the binders we are currently inside of
+    scope: Vec<Symbol>,
+    pub free: BTreeSet<Symbol>,
+}
+
+impl FreeTypeVars {
+    fn bind(&mut self, s: Symbol, ty: &Type) {
+        self.scope.push(s);
+        self.visit_ty(ty);
+        self.scope.pop();
+    }
+}
+
+impl<'t> TypeVisitor<'t> for FreeTypeVars {
+    fn visit_variable(&mut self, s: Symbol) {
+        if !self.scope.contains(&s) {
+            self.free.insert(s);
+        }
+    }
+
+    fn visit_row_variable(&mut self, var: &'t RowVar) {
+        self.visit_variable(var.name);
+    }
+
+    fn visit_existential(&mut self, s: Symbol, _: &'t Kind, ty: &'t Type) {
+        self.bind(s, ty);
+    }
+
+    fn visit_universal(&mut self, s: Symbol, _: &'t Kind, ty: &'t Type) {
+        self.bind(s, ty);
+    }
+
+    fn visit_abstraction(&mut self, s: Symbol, _: &'t Kind, ty: &'t Type) {
+        self.bind(s, ty);
+    }
+}
+
+/// Collect every defined type name referenced
+#[derive(Default, Debug, Clone)]
+pub struct ReferencedDefinitions {
+    pub defined: BTreeSet<Symbol>,
+}
+
+impl<'t> TypeVisitor<'t> for ReferencedDefinitions {
+    fn visit_defined(&mut self, s: Symbol) {
+        self.defined.insert(s);
+    }
+}
+
+/// The type variables occurring free in `ty`
+pub fn free_tyvars(ty: &Type) -> BTreeSet<Symbol> {
+    let mut fv = FreeTypeVars::default();
+    fv.visit_ty(ty);
+    fv.free
+}
+
+/// The defined type names that `ty` refers to
+pub fn referenced(ty: &Type) -> BTreeSet<Symbol> {
+    let mut rd = ReferencedDefinitions::default();
+    rd.visit_ty(ty);
+    rd.defined
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::syntax::ast::DeclKind;
+    use crate::syntax::parser::Parser;
+
+    fn ty(input: &str) -> Type {
+        Parser::new(input).parse_type().unwrap()
+    }
+
+    fn datatype(input: &str) -> Type {
+        match Parser::new(input).parse_decl().unwrap().kind {
+            DeclKind::Datatype(_, _, ty) => ty,
+            d => panic!("expected a datatype, not {:?}", d),
+        }
+    }
+
+    #[test]
+    fn free() {
+        let t = ty("'a -> forall ('a :: *) of 'a * 'b");
+        assert_eq!(free_tyvars(&t).into_iter().collect::<Vec<_>>(), ["a", "b"]);
+        let t = ty("forall ('a :: *) of 'a * 'b -> exists ('b :: *) of 'b");
+        assert_eq!(free_tyvars(&t).into_iter().collect::<Vec<_>>(), ["b"]);
+        let t = ty("fn ('f :: * -> *) => fn ('a :: *) => 'a 'f");
+        assert!(free_tyvars(&t).is_empty());
+        let t = ty("{x: 'a, y: forall ('c :: *) of 'c -> 'b}");
+        assert_eq!(free_tyvars(&t).into_iter().collect::<Vec<_>>(), ["a", "b"]);
+        let t = ty("forall ('r :: row) of {x: int | 'r} -> {y: bool | 's}");
+        assert_eq!(free_tyvars(&t).into_iter().collect::<Vec<_>>(), ["s"]);
+    }
+
+    #[test]
+    fn definitions() {
+        let t = ty("{head: int list, tail: unit -> stream} * bool");
+        assert_eq!(referenced(&t).into_iter().collect::<Vec<_>>(), ["list", "stream"]);
+        let t = datatype("datatype 'a tree = Leaf | Node of 'a tree * 'a * 'a forest");
+        assert_eq!(referenced(&t).into_iter().collect::<Vec<_>>(), ["forest", "tree"]);
+        assert_eq!(free_tyvars(&t).into_iter().collect::<Vec<_>>(), ["a"]);
+        let t = datatype("datatype t = A | B of {name: label}");
+        assert_eq!(referenced(&t).into_iter().collect::<Vec<_>>(), ["label"]);
+    }
+}
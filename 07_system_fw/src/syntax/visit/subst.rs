@@ -0,0 +1,215 @@
+//! Substitution of named type variables and defined types
+use super::names::free_tyvars;
+use super::types::TypeMutVisitor;
+use super::*;
+use ast::{Kind, Row, RowVar, Type, TypeKind};
+use std::collections::{HashMap, HashSet};
+
+/// Replace `ty` with `with`, keeping the span and id of `ty` so that the
+/// result still points back to where the replaced type was written
+fn replace(ty: &mut Type, with: &Type) {
+    *ty = Type::with_id(with.kind.clone(), ty.span, ty.id);
+}
+
+/// Capture-avoiding substitution `[name ↦ replacement]` of a named type
+/// variable. A binder that would capture a free variable of `replacement`
+/// is renamed to a fresh name first, e.g. `[x ↦ 'y] forall ('y :: *) of 'x`
+/// is `forall ('y1 :: *) of 'y`.
+///
+/// A row variable may be replaced by another variable, or by the rows of a
+/// record type, which extend the record it ends
+pub struct SubstNamedVar {
+    name: Symbol,
+    replacement: Type,
+    free: HashSet<Symbol>,
+    fresh: usize,
+}
+
+impl SubstNamedVar {
+    pub fn new<S: Into<Symbol>>(name: S, replacement: Type) -> SubstNamedVar {
+        SubstNamedVar {
+            name: name.into(),
+            free: free_tyvars(&replacement).into_iter().collect(),
+            replacement,
+            fresh: 0,
+        }
+    }
+
+    /// Generate a name based on `base` that is not free in either the
+    /// replacement or `body`
+    fn fresh_name(&mut self, base: Symbol, body: &Type) -> Symbol {
+        let used = free_tyvars(body);
+        loop {
+            self.fresh += 1;
+            let s = Symbol::intern(&format!("{}{}", base, self.fresh));
+            if !self.free.contains(&s) && !used.contains(&s) && s != self.name {
+                return s;
+            }
+        }
+    }
+
+    fn binder(&mut self, s: &mut Symbol, body: &mut Type) {
+        if *s == self.name {
+            // The variable is shadowed, so there is nothing to substitute
+            return;
+        }
+        if self.free.contains(s) && free_tyvars(body).contains(&self.name) {
+            let fresh = self.fresh_name(*s, body);
+            let var = Type::new(TypeKind::Variable(fresh), body.span);
+            SubstNamedVar::new(*s, var).visit_ty(body);
+            *s = fresh;
+        }
+        self.visit_ty(body);
+    }
+}
+
+impl TypeMutVisitor for SubstNamedVar {
+    fn visit_existential(&mut self, s: &mut Symbol, _: &mut Kind, ty: &mut Type) {
+        self.binder(s, ty);
+    }
+
+    fn visit_universal(&mut self, s: &mut Symbol, _: &mut Kind, ty: &mut Type) {
+        self.binder(s, ty);
+    }
+
+    fn visit_abstraction(&mut self, s: &mut Symbol, _: &mut Kind, ty: &mut Type) {
+        self.binder(s, ty);
+    }
+
+    fn visit_record(&mut self, rows: &mut Vec<Row>, tail: &mut Option<RowVar>) {
+        for row in rows.iter_mut() {
+            self.visit_ty(&mut row.ty);
+        }
+        match (tail.as_mut(), &self.replacement.kind) {
+            (Some(var), TypeKind::Variable(s)) if var.name == self.name => var.name = *s,
+            (Some(var), TypeKind::Record(more, rest)) if var.name == self.name => {
+                rows.extend(more.iter().cloned());
+                *tail = rest.clone();
+            }
+            _ => {}
+        }
+    }
+
+    fn visit_ty(&mut self, ty: &mut Type) {
+        match &ty.kind {
+            TypeKind::Variable(s) if *s == self.name => replace(ty, &self.replacement),
+            _ => self.walk_ty(ty),
+        }
+    }
+}
+
+/// Replace each `Defined` type in `env` with its definition, expanding any
+/// defined types in the definition too. Definitions are closed, so no
+/// variables can be captured. A type referring to itself, such as a
+/// datatype, is only expanded once
+pub struct ExpandDefined<'e> {
+    pub env: &'e HashMap<Symbol, Type>,
+    expanding: Vec<Symbol>,
+}
+
+impl<'e> ExpandDefined<'e> {
+    pub fn new(env: &'e HashMap<Symbol, Type>) -> ExpandDefined<'e> {
+        ExpandDefined {
+            env,
+            expanding: Vec::new(),
+        }
+    }
+}
+
+impl<'e> TypeMutVisitor for ExpandDefined<'e> {
+    fn visit_ty(&mut self, ty: &mut Type) {
+        match &ty.kind {
+            TypeKind::Defined(s) if !self.expanding.contains(s) => {
+                if let Some(def) = self.env.get(s) {
+                    let mut def = def.clone();
+                    self.expanding.push(*s);
+                    self.visit_ty(&mut def);
+                    self.expanding.pop();
+                    replace(ty, &def);
+                }
+            }
+            _ => self.walk_ty(ty),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::syntax::parser::Parser;
+
+    fn ty(input: &str) -> Type {
+        Parser::new(input).parse_type().unwrap()
+    }
+
+    fn subst(name: &str, replacement: &str, input: &str) -> Type {
+        let mut t = ty(input);
+        SubstNamedVar::new(name, ty(replacement)).visit_ty(&mut t);
+        t
+    }
+
+    #[test]
+    fn substitute() {
+        assert_eq!(subst("a", "int list", "'a -> 'b"), ty("int list -> 'b"));
+        assert_eq!(subst("r", "{y: bool}", "{x: int | 'r}"), ty("{x: int, y: bool}"));
+        assert_eq!(
+            subst("r", "{y: bool | 's}", "{x: int | 'r} -> 'a"),
+            ty("{x: int, y: bool | 's} -> 'a")
+        );
+        assert_eq!(
+            subst("a", "'b", "fn ('c :: *) => 'a * 'c"),
+            ty("fn ('c :: *) => 'b * 'c")
+        );
+        // Shadowed by the binder
+        assert_eq!(
+            subst("a", "int", "'a * forall ('a :: *) of 'a"),
+            ty("int * forall ('a :: *) of 'a")
+        );
+    }
+
+    #[test]
+    fn capture() {
+        // [x ↦ 'y] (forall y. x)
+        assert_eq!(
+            subst("x", "'y", "forall ('y :: *) of 'x"),
+            ty("forall ('y1 :: *) of 'y")
+        );
+        assert_eq!(
+            subst("x", "'y -> int", "exists ('y :: *) of 'x * 'y"),
+            ty("exists ('y1 :: *) of ('y -> int) * 'y1")
+        );
+        // The fresh name must not capture anything either
+        assert_eq!(
+            subst("x", "'y", "fn ('y :: *) => 'x -> 'y -> 'y1"),
+            ty("fn ('y2 :: *) => 'y -> 'y2 -> 'y1")
+        );
+        // No renaming is needed if the variable does not occur
+        assert_eq!(subst("x", "'y", "forall ('y :: *) of 'y"), ty("forall ('y :: *) of 'y"));
+        // Row variables are renamed along with their binder
+        assert_eq!(
+            subst("x", "{a: 'r}", "forall ('r :: row) of {b: 'x | 'r}"),
+            ty("forall ('r1 :: row) of {b: {a: 'r} | 'r1}")
+        );
+    }
+
+    #[test]
+    fn expand() {
+        let mut env = HashMap::new();
+        env.insert("pair".into(), ty("fn ('a :: *) => 'a * 'a"));
+        env.insert("ipair".into(), ty("int pair"));
+        env.insert("stream".into(), ty("{head: int, tail: unit -> stream}"));
+
+        let mut t = ty("ipair -> bool");
+        ExpandDefined::new(&env).visit_ty(&mut t);
+        assert_eq!(t, ty("int (fn ('a :: *) => 'a * 'a) -> bool"));
+
+        let mut t = ty("stream");
+        ExpandDefined::new(&env).visit_ty(&mut t);
+        assert_eq!(t, ty("{head: int, tail: unit -> stream}"));
+
+        // Undefined names are left alone
+        let mut t = ty("int option");
+        ExpandDefined::new(&env).visit_ty(&mut t);
+        assert_eq!(t, ty("int option"));
+    }
+}
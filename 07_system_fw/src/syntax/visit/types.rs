@@ -1,10 +1,16 @@
 use super::*;
-use ast::{Kind, Row, Type, TypeKind, Variant};
+use ast::{Kind, Row, RowVar, Type, TypeKind, Variant};
 
 pub trait TypeVisitor<'t>: Sized {
-    fn visit_defined(&mut self, _: &'t str) {}
+    fn visit_defined(&mut self, _: Symbol) {}
 
-    fn visit_variable(&mut self, _: &'t str) {}
+    /// The type component `M.t` of a structure
+    fn visit_path(&mut self, _: Symbol, _: Symbol) {}
+
+    fn visit_variable(&mut self, _: Symbol) {}
+
+    /// The row variable 'r of an open record type { ... | 'r }
+    fn visit_row_variable(&mut self, _: &'t RowVar) {}
 
     fn visit_function(&mut self, ty1: &'t Type, ty2: &'t Type) {
         self.visit_ty(ty1);
@@ -30,21 +36,24 @@ pub trait TypeVisitor<'t>: Sized {
         }
     }
 
-    fn visit_record(&mut self, var: &'t [Row]) {
+    fn visit_record(&mut self, var: &'t [Row], tail: Option<&'t RowVar>) {
         for v in var {
             self.visit_ty(&v.ty);
         }
+        if let Some(tail) = tail {
+            self.visit_row_variable(tail);
+        }
     }
 
-    fn visit_existential(&mut self, _: &'t str, _: &'t Kind, ty: &'t Type) {
+    fn visit_existential(&mut self, _: Symbol, _: &'t Kind, ty: &'t Type) {
         self.visit_ty(ty);
     }
 
-    fn visit_universal(&mut self, _: &'t str, _: &'t Kind, ty: &'t Type) {
+    fn visit_universal(&mut self, _: Symbol, _: &'t Kind, ty: &'t Type) {
         self.visit_ty(ty);
     }
 
-    fn visit_abstraction(&mut self, _: &'t str, _: &'t Kind, ty: &'t Type) {
+    fn visit_abstraction(&mut self, _: Symbol, _: &'t Kind, ty: &'t Type) {
         self.visit_ty(ty);
     }
 
@@ -59,6 +68,96 @@ pub trait TypeVisitor<'t>: Sized {
     fn walk_ty(&mut self, ty: &'t Type) {
         use TypeKind::*;
         match &ty.kind {
+            Int => {}
+            Bool => {}
+            Unit => {}
+            Infer => {}
+            Defined(s) => self.visit_defined(*s),
+            Variable(s) => self.visit_variable(*s),
+            Function(ty1, ty2) => self.visit_function(ty1, ty2),
+            Sum(var) => self.visit_sum(var),
+            Product(tys) => self.visit_product(tys),
+            Record(rows, tail) => self.visit_record(rows, tail.as_ref()),
+            Existential(s, k, ty) => self.visit_existential(*s, k, ty),
+            Universal(s, k, ty) => self.visit_universal(*s, k, ty),
+            Abstraction(s, k, ty) => self.visit_abstraction(*s, k, ty),
+            Application(ty1, ty2) => self.visit_application(ty1, ty2),
+            Recursive(ty) => self.visit_recursive(ty),
+            Path(m, s) => self.visit_path(*m, *s),
+        }
+    }
+}
+
+/// A [`TypeVisitor`] that may change the types it visits in place.
+/// Whole types can be replaced by overriding `visit_ty`
+pub trait TypeMutVisitor: Sized {
+    fn visit_defined(&mut self, _: &mut Symbol) {}
+
+    fn visit_path(&mut self, _: &mut Symbol, _: &mut Symbol) {}
+
+    fn visit_variable(&mut self, _: &mut Symbol) {}
+
+    fn visit_row_variable(&mut self, _: &mut RowVar) {}
+
+    fn visit_function(&mut self, ty1: &mut Type, ty2: &mut Type) {
+        self.visit_ty(ty1);
+        self.visit_ty(ty2);
+    }
+
+    fn visit_application(&mut self, ty1: &mut Type, ty2: &mut Type) {
+        self.visit_ty(ty1);
+        self.visit_ty(ty2);
+    }
+
+    fn visit_sum(&mut self, var: &mut [Variant]) {
+        for v in var {
+            if let Some(ty) = &mut v.ty {
+                self.visit_ty(ty);
+            }
+        }
+    }
+
+    fn visit_product(&mut self, var: &mut [Type]) {
+        for v in var {
+            self.visit_ty(v);
+        }
+    }
+
+    /// Both the rows and the row variable may be replaced, so that an open
+    /// record type can be extended with the fields its row variable stands
+    /// for
+    fn visit_record(&mut self, var: &mut Vec<Row>, tail: &mut Option<RowVar>) {
+        for v in var {
+            self.visit_ty(&mut v.ty);
+        }
+        if let Some(tail) = tail {
+            self.visit_row_variable(tail);
+        }
+    }
+
+    fn visit_existential(&mut self, _: &mut Symbol, _: &mut Kind, ty: &mut Type) {
+        self.visit_ty(ty);
+    }
+
+    fn visit_universal(&mut self, _: &mut Symbol, _: &mut Kind, ty: &mut Type) {
+        self.visit_ty(ty);
+    }
+
+    fn visit_abstraction(&mut self, _: &mut Symbol, _: &mut Kind, ty: &mut Type) {
+        self.visit_ty(ty);
+    }
+
+    fn visit_recursive(&mut self, ty: &mut Type) {
+        self.visit_ty(ty);
+    }
+
+    fn visit_ty(&mut self, ty: &mut Type) {
+        self.walk_ty(ty);
+    }
+
+    fn walk_ty(&mut self, ty: &mut Type) {
+        use TypeKind::*;
+        match &mut ty.kind {
             Int => {}
             Bool => {}
             Unit => {}
@@ -68,12 +167,13 @@ pub trait TypeVisitor<'t>: Sized {
             Function(ty1, ty2) => self.visit_function(ty1, ty2),
             Sum(var) => self.visit_sum(var),
             Product(tys) => self.visit_product(tys),
-            Record(rows) => self.visit_record(rows),
+            Record(rows, tail) => self.visit_record(rows, tail),
             Existential(s, k, ty) => self.visit_existential(s, k, ty),
             Universal(s, k, ty) => self.visit_universal(s, k, ty),
             Abstraction(s, k, ty) => self.visit_abstraction(s, k, ty),
             Application(ty1, ty2) => self.visit_application(ty1, ty2),
             Recursive(ty) => self.visit_recursive(ty),
+            Path(m, s) => self.visit_path(m, s),
         }
     }
 }
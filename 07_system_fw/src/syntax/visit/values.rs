@@ -0,0 +1,414 @@
+//! Resolving value variables to the bindings they refer to
+use super::exprs::{ExprVisitor, PatternVisitor};
+use super::*;
+use crate::diagnostics::Diagnostic;
+use ast::{Arm, Decl, DeclKind, Expr, ExprKind, FnArm, Pattern, Sig};
+use std::collections::BTreeSet;
+use util::span::Span;
+
+/// Collect the variables bound by a pattern, along with the span of the
+/// pattern binding each one. Every alternative of an or-pattern binds the
+/// same variables, so only the first is visited
+#[derive(Default, Debug)]
+pub struct PatternBinders {
+    span: Span,
+    pub names: Vec<(Symbol, Span)>,
+}
+
+impl<'t> PatternVisitor<'t> for PatternBinders {
+    fn visit_variable(&mut self, s: Symbol) {
+        self.names.push((s, self.span));
+    }
+
+    fn visit_record(&mut self, labels: &'t [Symbol]) {
+        for s in labels {
+            self.names.push((*s, self.span));
+        }
+    }
+
+    fn visit_or(&mut self, alts: &'t [Pattern]) {
+        if let Some(p) = alts.first() {
+            self.visit_pat(p);
+        }
+    }
+
+    fn visit_pat(&mut self, pat: &'t Pattern) {
+        self.span = pat.span;
+        self.walk_pat(pat);
+    }
+}
+
+/// Told about each binding of a value variable, and each use of one, by a
+/// [`Scoped`] walk
+pub trait Bindings {
+    /// Binding number `id`, of `name`, is made at `span`. It is local
+    /// unless it is made by a top level declaration
+    fn bind(&mut self, _id: usize, _name: Symbol, _span: Span, _local: bool) {}
+
+    /// `name` is used at `span`, referring to the binding `id` if there is
+    /// one in scope
+    fn reference(&mut self, name: Symbol, span: Span, id: Option<usize>);
+}
+
+/// Walk expressions and declarations, keeping track of the value variables
+/// in scope so that each use of one can be resolved to its binding.
+/// Declarations bind their names for those that follow, and a function is
+/// in scope in its own body, as are all of the names of an `and`
+pub struct Scoped<B> {
+    scope: Vec<(Symbol, usize)>,
+    next: usize,
+    /// Greater than zero when inside of an expression
+    depth: usize,
+    span: Span,
+    pub bindings: B,
+}
+
+impl<B: Bindings> Scoped<B> {
+    pub fn new(bindings: B) -> Scoped<B> {
+        Scoped {
+            scope: Vec::new(),
+            next: 0,
+            depth: 0,
+            span: Span::default(),
+            bindings,
+        }
+    }
+
+    fn bind(&mut self, name: Symbol, span: Span, local: bool) {
+        let id = self.next;
+        self.next += 1;
+        self.scope.push((name, id));
+        self.bindings.bind(id, name, span, local);
+    }
+
+    fn bind_pattern(&mut self, pat: &Pattern, local: bool) {
+        let mut binders = PatternBinders::default();
+        binders.visit_pat(pat);
+        for (name, span) in binders.names {
+            self.bind(name, span, local);
+        }
+    }
+
+    /// Bind the names a declaration makes, without visiting it
+    fn bind_decl(&mut self, d: &Decl) {
+        match &d.kind {
+            DeclKind::Value(_, pat, _) => self.bind_pattern(pat, self.depth > 0),
+            DeclKind::Function(_, name, _) => self.bind(*name, d.span, self.depth > 0),
+            DeclKind::And(d1, d2) => {
+                self.bind_decl(d1);
+                self.bind_decl(d2);
+            }
+            _ => {}
+        }
+    }
+
+    /// Visit declarations in order, leaving their bindings in scope
+    pub fn visit_decls(&mut self, decls: &[Decl]) {
+        for d in decls {
+            self.visit_decl(d);
+        }
+    }
+
+    /// Run `f`, then forget any bindings it made
+    fn scope<F: FnOnce(&mut Self)>(&mut self, f: F) {
+        let n = self.scope.len();
+        f(self);
+        self.scope.truncate(n);
+    }
+}
+
+impl<'t, B: Bindings> ExprVisitor<'t> for Scoped<B> {
+    fn visit_var(&mut self, s: Symbol) {
+        let id = self.scope.iter().rev().find(|(name, _)| *name == s).map(|(_, id)| *id);
+        self.bindings.reference(s, self.span, id);
+    }
+
+    fn visit_abs(&mut self, pat: &'t Pattern, body: &'t Expr) {
+        self.scope(|s| {
+            s.bind_pattern(pat, true);
+            s.visit_expr(body);
+        });
+    }
+
+    fn visit_arm(&mut self, arm: &'t Arm) {
+        self.scope(|s| {
+            s.bind_pattern(&arm.pat, true);
+            if let Some(guard) = &arm.guard {
+                s.visit_expr(guard);
+            }
+            s.visit_expr(&arm.expr);
+        });
+    }
+
+    fn visit_fn_arm(&mut self, arm: &'t FnArm) {
+        self.scope(|s| {
+            for p in &arm.pats {
+                s.bind_pattern(p, true);
+            }
+            s.visit_expr(&arm.expr);
+        });
+    }
+
+    fn visit_open(&mut self, package: &'t Expr, _: Symbol, var: Symbol, body: &'t Expr) {
+        self.visit_expr(package);
+        let span = self.span;
+        self.scope(|s| {
+            s.bind(var, span, true);
+            s.visit_expr(body);
+        });
+    }
+
+    fn visit_let(&mut self, decls: &'t [Decl], body: &'t Expr) {
+        self.scope(|s| {
+            s.visit_decls(decls);
+            s.visit_expr(body);
+        });
+    }
+
+    /// The components of a structure are only reachable through paths
+    /// once it is declared
+    fn visit_structure(&mut self, _: Symbol, _: Option<&'t Sig>, decls: &'t [Decl]) {
+        self.scope(|s| s.visit_decls(decls));
+    }
+
+    fn visit_decl(&mut self, d: &'t Decl) {
+        match &d.kind {
+            // The expression is evaluated before its pattern is bound
+            DeclKind::Value(_, pat, e) => {
+                self.visit_expr(e);
+                self.bind_pattern(pat, self.depth > 0);
+            }
+            _ => {
+                self.bind_decl(d);
+                self.walk_decl(d);
+            }
+        }
+    }
+
+    fn visit_expr(&mut self, e: &'t Expr) {
+        self.span = e.span;
+        self.depth += 1;
+        self.walk_expr(e);
+        self.depth -= 1;
+    }
+}
+
+/// Report each variable that is used outside of the scope of any binding
+#[derive(Default, Debug)]
+pub struct ScopeChecker {
+    pub errors: Vec<Diagnostic>,
+}
+
+impl Bindings for ScopeChecker {
+    fn reference(&mut self, name: Symbol, span: Span, id: Option<usize>) {
+        if id.is_none() {
+            self.errors
+                .push(Diagnostic::error(span, format!("unbound variable {}", name)));
+        }
+    }
+}
+
+/// Collect the variables used outside of the scope of any binding
+#[derive(Default, Debug)]
+pub struct FreeVars {
+    pub free: BTreeSet<Symbol>,
+}
+
+impl Bindings for FreeVars {
+    fn reference(&mut self, name: Symbol, _: Span, id: Option<usize>) {
+        if id.is_none() {
+            self.free.insert(name);
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Occurrence {
+    pub name: Symbol,
+    pub span: Span,
+    pub local: bool,
+    pub uses: usize,
+}
+
+/// Count the uses of each binding, indexed by binding number
+#[derive(Default, Debug)]
+pub struct Occurrences {
+    pub bindings: Vec<Occurrence>,
+}
+
+impl Bindings for Occurrences {
+    fn bind(&mut self, _: usize, name: Symbol, span: Span, local: bool) {
+        self.bindings.push(Occurrence {
+            name,
+            span,
+            local,
+            uses: 0,
+        });
+    }
+
+    fn reference(&mut self, _: Symbol, _: Span, id: Option<usize>) {
+        if let Some(id) = id {
+            self.bindings[id].uses += 1;
+        }
+    }
+}
+
+impl Occurrences {
+    /// A warning for each local binding that is never used. Top level
+    /// declarations may be used by whatever comes after the program
+    pub fn unused(&self) -> Vec<Diagnostic> {
+        self.bindings
+            .iter()
+            .filter(|b| b.local && b.uses == 0)
+            .map(|b| Diagnostic::warn(b.span, format!("unused variable {}", b.name)))
+            .collect()
+    }
+}
+
+/// The variables used in `e` that it does not bind
+pub fn free_vars(e: &Expr) -> BTreeSet<Symbol> {
+    let mut s = Scoped::new(FreeVars::default());
+    s.visit_expr(e);
+    s.bindings.free
+}
+
+/// An error for each use of a variable in `decls` that is not in scope
+pub fn check_scope(decls: &[Decl]) -> Vec<Diagnostic> {
+    let mut s = Scoped::new(ScopeChecker::default());
+    s.visit_decls(decls);
+    s.bindings.errors
+}
+
+/// A warning for each local binding in `decls` that is never used
+pub fn unused_bindings(decls: &[Decl]) -> Vec<Diagnostic> {
+    let mut s = Scoped::new(Occurrences::default());
+    s.visit_decls(decls);
+    s.bindings.unused()
+}
+
+/// The outermost subexpression of `e` that keeps it from being a syntactic
+/// value, if there is one. Literals, variables, functions, and constructors,
+/// records, tuples and packages of syntactic values are syntactic values,
+/// which cannot have any effects when evaluated
+pub fn non_value(e: &Expr) -> Option<&Expr> {
+    use ExprKind::*;
+    match &e.kind {
+        Unit | Int(_) | Var(_) | Constr(_) | Path(..) | Abs(..) | TyAbs(..) => None,
+        App(con, arg) if matches!(con.kind, Constr(_)) => non_value(arg),
+        Record(fields) => fields.iter().find_map(|f| non_value(&f.expr)),
+        Tuple(exprs) => exprs.iter().find_map(non_value),
+        Pack(_, e, _) => non_value(e),
+        _ => Some(e),
+    }
+}
+
+/// Whether `e` is a syntactic value, see [`non_value`]
+pub fn is_syntactic_value(e: &Expr) -> bool {
+    non_value(e).is_none()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::syntax::parser::Parser;
+
+    fn program(input: &str) -> Vec<Decl> {
+        Parser::new(input).parse_program().unwrap().decls
+    }
+
+    fn expr(input: &str) -> Expr {
+        Parser::new(input).parse_expr().unwrap()
+    }
+
+    const NESTED: &str = "fun map f xs = case xs of \
+                            | Nil => Nil \
+                            | Cons (x, rest) => Cons (f x, map f rest) \
+                          end; \
+                          val y = let val a = 1; fun g z = (a, z) in \
+                            case g a of | (b, c) if b => c | (b, _) => y end \
+                          end";
+
+    #[test]
+    fn scope() {
+        let errors = check_scope(&program(NESTED));
+        // `y` is only bound after its own declaration
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].primary.info, "unbound variable y");
+
+        let decls = program("fun even n = odd n and fun odd n = even n; val x = even 1");
+        assert!(check_scope(&decls).is_empty());
+        let decls = program("val f = fn x => let val y = x in y end; val z = y");
+        assert_eq!(check_scope(&decls).len(), 1);
+    }
+
+    #[test]
+    fn free() {
+        let e = expr("fn x => case x of | Some y => f y z | None => w x end");
+        assert_eq!(free_vars(&e).into_iter().collect::<Vec<_>>(), ["f", "w", "z"]);
+
+        // Labels of projections and record fields are not variables
+        let e = expr("let val r = {a = p, b = q} in r.a end");
+        assert_eq!(free_vars(&e).into_iter().collect::<Vec<_>>(), ["p", "q"]);
+
+        let e = expr("let fun loop n = loop n; val {k} = loop 0; val m = k in (m, k, n) end");
+        assert_eq!(free_vars(&e).into_iter().collect::<Vec<_>>(), ["n"]);
+    }
+
+    #[test]
+    fn syntactic_values() {
+        for input in &[
+            "fn x => x",
+            "Some (1, {a = fn y => y})",
+            "pack int, 0 as exists ('t :: *) of 't",
+        ] {
+            assert!(is_syntactic_value(&expr(input)), "{}", input);
+        }
+        // The application of `f` is responsible, not the tuple or `Some`
+        let e = expr("(fn x => x, Some (f 1))");
+        match non_value(&e).map(|e| &e.kind) {
+            Some(ExprKind::App(f, _)) => assert_eq!(f.kind, ExprKind::Var("f".into())),
+            e => panic!("expected an application, not {:?}", e),
+        }
+        assert!(!is_syntactic_value(&expr("let val x = 1 in x end")));
+    }
+
+    #[test]
+    fn occurrences() {
+        let mut s = Scoped::new(Occurrences::default());
+        let decls = program(NESTED);
+        s.visit_decls(&decls);
+        let uses = s
+            .bindings
+            .bindings
+            .iter()
+            .map(|b| (b.name.as_str(), b.uses))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            uses,
+            [
+                ("map", 1),
+                ("f", 2),
+                ("xs", 1),
+                ("x", 1),
+                ("rest", 1),
+                ("a", 2),
+                ("g", 1),
+                ("z", 1),
+                ("b", 1),
+                ("c", 1),
+                ("b", 0),
+                ("y", 0),
+            ]
+        );
+
+        let warnings = s.bindings.unused();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].primary.info, "unused variable b");
+
+        // The inner binding is used, so the outer one is not
+        let decls = program("val f = fn x => fn x => x");
+        let warnings = unused_bindings(&decls);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].primary.info, "unused variable x");
+    }
+}
@@ -1,3 +1,5 @@
+use crate::symbol::Symbol;
+use crate::syntax::ast;
 use std::convert::TryFrom;
 use std::fmt;
 
@@ -274,3 +276,231 @@ impl MutTypeVisitor for Subst {
         }
     }
 }
+
+/// Compare two surface types up to a consistent renaming of their bound
+/// type variables, so `forall ('a :: *) of 'a -> 'a` is equal to
+/// `forall ('b :: *) of 'b -> 'b`. Free variables and defined names are
+/// compared by name, and spans are ignored
+pub fn alpha_eq(a: &ast::Type, b: &ast::Type) -> bool {
+    AlphaEq::default().eq(a, b)
+}
+
+/// Pairs of type variables bound by the binders we are currently inside
+/// of on each side, innermost last
+#[derive(Default)]
+struct AlphaEq {
+    binders: Vec<(Symbol, Symbol)>,
+}
+
+impl AlphaEq {
+    /// Two variables are equal if they were bound by the same pair of
+    /// binders, or are both free and have the same name
+    fn var(&self, x: Symbol, y: Symbol) -> bool {
+        let i = self.binders.iter().rposition(|(l, _)| *l == x);
+        let j = self.binders.iter().rposition(|(_, r)| *r == y);
+        match (i, j) {
+            (None, None) => x == y,
+            (i, j) => i == j,
+        }
+    }
+
+    fn bind(&mut self, x: Symbol, y: Symbol, a: &ast::Type, b: &ast::Type) -> bool {
+        self.binders.push((x, y));
+        let eq = self.eq(a, b);
+        self.binders.pop();
+        eq
+    }
+
+    fn eq(&mut self, a: &ast::Type, b: &ast::Type) -> bool {
+        use ast::TypeKind::*;
+        match (&a.kind, &b.kind) {
+            (Int, Int) | (Bool, Bool) | (Unit, Unit) | (Infer, Infer) => true,
+            (Defined(x), Defined(y)) => x == y,
+            (Path(m1, x), Path(m2, y)) => m1 == m2 && x == y,
+            (Variable(x), Variable(y)) => self.var(*x, *y),
+            (Function(a1, a2), Function(b1, b2)) | (Application(a1, a2), Application(b1, b2)) => {
+                self.eq(a1, b1) && self.eq(a2, b2)
+            }
+            (Sum(xs), Sum(ys)) => {
+                xs.len() == ys.len()
+                    && xs.iter().zip(ys).all(|(x, y)| {
+                        x.label == y.label
+                            && match (&x.ty, &y.ty) {
+                                (Some(s), Some(t)) => self.eq(s, t),
+                                (None, None) => true,
+                                _ => false,
+                            }
+                    })
+            }
+            (Product(xs), Product(ys)) => xs.len() == ys.len() && xs.iter().zip(ys).all(|(x, y)| self.eq(x, y)),
+            (Record(xs, r1), Record(ys, r2)) => {
+                xs.len() == ys.len()
+                    && xs
+                        .iter()
+                        .zip(ys)
+                        .all(|(x, y)| x.label == y.label && self.eq(&x.ty, &y.ty))
+                    && match (r1, r2) {
+                        (Some(r1), Some(r2)) => self.var(r1.name, r2.name),
+                        (None, None) => true,
+                        _ => false,
+                    }
+            }
+            (Existential(x, k1, s), Existential(y, k2, t))
+            | (Universal(x, k1, s), Universal(y, k2, t))
+            | (Abstraction(x, k1, s), Abstraction(y, k2, t)) => k1 == k2 && self.bind(*x, *y, s, t),
+            (Recursive(s), Recursive(t)) => self.eq(s, t),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::syntax::ast::{Kind, Row, RowVar, TypeKind};
+    use crate::syntax::parser::Parser;
+    use crate::syntax::visit::{SubstNamedVar, TypeMutVisitor};
+    use util::span::Span;
+
+    fn ty(input: &str) -> ast::Type {
+        Parser::new(input).parse_type().unwrap()
+    }
+
+    /// A small xorshift generator, so that failures are reproducible
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % n
+        }
+
+        fn pick<'a>(&mut self, xs: &[&'a str]) -> &'a str {
+            xs[self.below(xs.len() as u64) as usize]
+        }
+
+        fn kind(&mut self) -> Box<Kind> {
+            match self.below(2) {
+                0 => Box::new(Kind::Star),
+                _ => Box::new(Kind::Arrow(Box::new(Kind::Star), Box::new(Kind::Star))),
+            }
+        }
+
+        fn ty(&mut self, depth: u32) -> ast::Type {
+            use TypeKind::*;
+            let vars = ["a", "b", "c"];
+            let n = if depth == 0 { 5 } else { 13 };
+            let kind = match self.below(n) {
+                0 => Int,
+                1 => Bool,
+                2 => Defined(self.pick(&["t", "u"]).into()),
+                3 | 4 => Variable(self.pick(&vars).into()),
+                5 => Function(Box::new(self.ty(depth - 1)), Box::new(self.ty(depth - 1))),
+                6 => Application(Box::new(self.ty(depth - 1)), Box::new(self.ty(depth - 1))),
+                7 => Product(vec![self.ty(depth - 1), self.ty(depth - 1)]),
+                8 => Record(
+                    ["x", "y"]
+                        .iter()
+                        .map(|label| Row {
+                            label: (*label).into(),
+                            ty: self.ty(depth - 1),
+                            span: Span::zero(),
+                        })
+                        .collect(),
+                    match self.below(2) {
+                        0 => Some(RowVar {
+                            name: self.pick(&vars).into(),
+                            span: Span::zero(),
+                        }),
+                        _ => None,
+                    },
+                ),
+                9 => Universal(self.pick(&vars).into(), self.kind(), Box::new(self.ty(depth - 1))),
+                10 => Existential(self.pick(&vars).into(), self.kind(), Box::new(self.ty(depth - 1))),
+                11 => Abstraction(self.pick(&vars).into(), self.kind(), Box::new(self.ty(depth - 1))),
+                _ => Recursive(Box::new(self.ty(depth - 1))),
+            };
+            ast::Type::new(kind, Span::zero())
+        }
+    }
+
+    /// Give every bound variable a new name, none of which can occur free
+    struct Rename(usize);
+
+    impl Rename {
+        fn binder(&mut self, s: &mut Symbol, body: &mut ast::Type) {
+            self.0 += 1;
+            let fresh = Symbol::intern(&format!("r{}", self.0));
+            let var = ast::Type::new(TypeKind::Variable(fresh), Span::zero());
+            SubstNamedVar::new(*s, var).visit_ty(body);
+            *s = fresh;
+            self.visit_ty(body);
+        }
+    }
+
+    impl TypeMutVisitor for Rename {
+        fn visit_existential(&mut self, s: &mut Symbol, _: &mut Kind, ty: &mut ast::Type) {
+            self.binder(s, ty);
+        }
+
+        fn visit_universal(&mut self, s: &mut Symbol, _: &mut Kind, ty: &mut ast::Type) {
+            self.binder(s, ty);
+        }
+
+        fn visit_abstraction(&mut self, s: &mut Symbol, _: &mut Kind, ty: &mut ast::Type) {
+            self.binder(s, ty);
+        }
+    }
+
+    #[test]
+    fn alpha_eq_random() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..500 {
+            let t = rng.ty(4);
+            assert!(alpha_eq(&t, &t), "{:?}", t);
+            let mut renamed = t.clone();
+            Rename(0).visit_ty(&mut renamed);
+            assert!(alpha_eq(&t, &renamed), "{:?}\n{:?}", t, renamed);
+            assert!(alpha_eq(&renamed, &t), "{:?}\n{:?}", renamed, t);
+        }
+    }
+
+    #[test]
+    fn alpha_eq_binders() {
+        let eq = |a: &str, b: &str| alpha_eq(&ty(a), &ty(b));
+        assert!(eq("forall ('a :: *) of 'a -> 'a", "forall ('b :: *) of 'b -> 'b"));
+        assert!(eq(
+            "fn ('f :: * -> *) => exists ('a :: *) of 'a 'f",
+            "fn ('g :: * -> *) => exists ('f :: *) of 'f 'g"
+        ));
+        assert!(eq(
+            "forall ('a :: *) of forall ('a :: *) of 'a",
+            "forall ('a :: *) of forall ('b :: *) of 'b"
+        ));
+        assert!(!eq(
+            "forall ('a :: *) of forall ('a :: *) of 'a",
+            "forall ('a :: *) of forall ('b :: *) of 'a"
+        ));
+        assert!(!eq(
+            "forall ('a :: *) of forall ('b :: *) of 'a -> 'b",
+            "forall ('b :: *) of forall ('a :: *) of 'a -> 'b"
+        ));
+
+        // The kinds of binders must agree
+        assert!(!eq("forall ('a :: *) of int", "forall ('a :: * -> *) of int"));
+        assert!(!eq("fn ('a :: *) => 'a", "fn ('a :: (* -> *) -> *) => 'a"));
+        // As must the binder forms
+        assert!(!eq("forall ('a :: *) of 'a", "exists ('a :: *) of 'a"));
+
+        // Free variables and defined names are compared by name
+        assert!(eq("'a -> t", "'a -> t"));
+        assert!(eq("M.t", "M.t"));
+        assert!(!eq("M.t", "N.t"));
+        assert!(!eq("'a", "'b"));
+        assert!(!eq("t", "u"));
+        assert!(!eq("forall ('a :: *) of 'a", "forall ('b :: *) of 'a"));
+        assert!(!eq("forall ('b :: *) of 'a", "forall ('a :: *) of 'a"));
+    }
+}
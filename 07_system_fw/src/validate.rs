@@ -0,0 +1,276 @@
+//! Checks for repeated names, right after parsing
+//!
+//! A record type, record expression or record pattern may not use the same
+//! label twice, and a sum type may not have two constructors of the same
+//! name, as there would be no telling which one is meant. Two datatypes in
+//! the same scope may declare constructors of the same name, but as with
+//! values, the second one shadows the first, which is likely to be a
+//! mistake, so it is warned about
+use crate::diagnostics::Diagnostic;
+use crate::elaborate::{ElabError, ElabErrorKind};
+use crate::symbol::Symbol;
+use crate::syntax::ast::{Decl, DeclKind, Expr, Field, Pattern, Program, Row, RowVar, Sig, Type, TypeKind, Variant};
+use crate::syntax::visit::{ExprVisitor, PatternVisitor, TypeVisitor};
+use std::collections::HashMap;
+use util::span::Span;
+
+#[derive(Default, Debug)]
+pub struct ProgramValidation {
+    span: Span,
+    pub errors: Vec<ElabError>,
+    pub warnings: Vec<Diagnostic>,
+}
+
+impl ProgramValidation {
+    /// The errors and warnings for the repeated names in `program`
+    pub fn check(program: &Program) -> ProgramValidation {
+        let mut v = ProgramValidation::default();
+        v.visit_decls(&program.decls);
+        v
+    }
+
+    /// A warning for each shadowed constructor in `program`, followed by an
+    /// error for each other repeated name
+    pub fn validate(program: &Program) -> Vec<Diagnostic> {
+        let v = ProgramValidation::check(program);
+        let errors = v.errors.into_iter().map(Diagnostic::from);
+        v.warnings.into_iter().chain(errors).collect()
+    }
+
+    /// Report the second use of each label in `labels`, a record of `what`
+    fn labels<I: IntoIterator<Item = (Symbol, Span)>>(&mut self, labels: I, what: &'static str) {
+        let mut seen: HashMap<Symbol, Span> = HashMap::new();
+        for (label, span) in labels {
+            match seen.get(&label) {
+                Some(&first) => self
+                    .errors
+                    .push(ElabError::new(ElabErrorKind::DuplicateLabel(label, what, first), span)),
+                None => {
+                    seen.insert(label, span);
+                }
+            }
+        }
+    }
+
+    /// Visit a sequence of declarations in the same scope, warning about each
+    /// datatype constructor that shadows one declared before it
+    fn visit_decls(&mut self, decls: &[Decl]) {
+        let mut constructors: HashMap<Symbol, (Symbol, Span)> = HashMap::new();
+        let mut datatypes = Vec::new();
+        for d in decls {
+            flatten_datatypes(d, &mut datatypes);
+        }
+        for (name, variants) in datatypes {
+            // Repeats within a datatype are reported as errors by visit_sum
+            let mut own = HashMap::new();
+            for v in variants {
+                if own.insert(v.label, v.span).is_some() {
+                    continue;
+                }
+                if let Some((prev, first)) = constructors.insert(v.label, (name, v.span)) {
+                    self.warnings.push(
+                        Diagnostic::warn(
+                            v.span,
+                            format!(
+                                "constructor {} of datatype {} shadows the constructor of the same name of datatype {}",
+                                v.label, name, prev
+                            ),
+                        )
+                        .message(first, format!("{} is first declared here", v.label)),
+                    );
+                }
+            }
+        }
+        for d in decls {
+            self.visit_decl(d);
+        }
+    }
+}
+
+/// The name and variants of each datatype declared by `d`, including those
+/// joined by `and`
+fn flatten_datatypes<'d>(d: &'d Decl, out: &mut Vec<(Symbol, &'d [Variant])>) {
+    match &d.kind {
+        DeclKind::Datatype(_, name, ty) => {
+            if let TypeKind::Sum(variants) = &ty.kind {
+                out.push((*name, variants));
+            }
+        }
+        DeclKind::And(d1, d2) => {
+            flatten_datatypes(d1, out);
+            flatten_datatypes(d2, out);
+        }
+        _ => {}
+    }
+}
+
+impl<'t> ExprVisitor<'t> for ProgramValidation {
+    fn visit_record(&mut self, fields: &'t [Field]) {
+        self.labels(fields.iter().map(|f| (f.label, f.span)), "expression");
+        for f in fields {
+            self.visit_expr(&f.expr);
+        }
+    }
+
+    fn visit_let(&mut self, decls: &'t [Decl], body: &'t Expr) {
+        self.visit_decls(decls);
+        self.visit_expr(body);
+    }
+
+    fn visit_structure(&mut self, _: Symbol, sig: Option<&'t Sig>, decls: &'t [Decl]) {
+        if let Some(sig) = sig {
+            self.visit_sig(sig);
+        }
+        self.visit_decls(decls);
+    }
+
+    fn visit_pattern(&mut self, pat: &'t Pattern) {
+        self.visit_pat(pat);
+    }
+
+    fn visit_type(&mut self, ty: &'t Type) {
+        self.visit_ty(ty);
+    }
+
+    fn visit_expr(&mut self, e: &'t Expr) {
+        self.span = e.span;
+        self.walk_expr(e);
+    }
+}
+
+impl<'t> PatternVisitor<'t> for ProgramValidation {
+    /// The labels of a record pattern have no spans of their own
+    fn visit_record(&mut self, labels: &'t [Symbol]) {
+        let span = self.span;
+        self.labels(labels.iter().map(|&s| (s, span)), "pattern");
+    }
+
+    fn visit_type(&mut self, ty: &'t Type) {
+        self.visit_ty(ty);
+    }
+
+    fn visit_pat(&mut self, pat: &'t Pattern) {
+        self.span = pat.span;
+        self.walk_pat(pat);
+    }
+}
+
+impl<'t> TypeVisitor<'t> for ProgramValidation {
+    fn visit_record(&mut self, rows: &'t [Row], tail: Option<&'t RowVar>) {
+        self.labels(rows.iter().map(|r| (r.label, r.span)), "type");
+        for r in rows {
+            self.visit_ty(&r.ty);
+        }
+        if let Some(tail) = tail {
+            self.visit_row_variable(tail);
+        }
+    }
+
+    fn visit_sum(&mut self, variants: &'t [Variant]) {
+        let mut seen: HashMap<Symbol, Span> = HashMap::new();
+        for v in variants {
+            match seen.get(&v.label) {
+                Some(&first) => self.errors.push(ElabError::new(
+                    ElabErrorKind::DuplicateConstructor(v.label, first),
+                    v.span,
+                )),
+                None => {
+                    seen.insert(v.label, v.span);
+                }
+            }
+            if let Some(ty) = &v.ty {
+                self.visit_ty(ty);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::diagnostics::Level;
+    use crate::syntax::parser::Parser;
+
+    /// The level, message and secondary messages of each diagnostic
+    fn validate(input: &str) -> Vec<(Level, String, Vec<String>)> {
+        let program = Parser::new(input).parse_program().unwrap();
+        ProgramValidation::validate(&program)
+            .into_iter()
+            .map(|d| (d.level, d.primary.info, d.other.into_iter().map(|a| a.info).collect()))
+            .collect()
+    }
+
+    fn error(message: &str, first: &str) -> (Level, String, Vec<String>) {
+        (Level::Error, message.into(), vec![first.into()])
+    }
+
+    #[test]
+    fn records() {
+        assert_eq!(
+            validate("type t = {x: int, y: bool, x: bool}"),
+            [error(
+                "label x is used more than once in a record type",
+                "x is first used here"
+            )]
+        );
+        assert_eq!(
+            validate("val r = {x = 1, y = {z = 2, z = 3}, x = 4}"),
+            [
+                error(
+                    "label x is used more than once in a record expression",
+                    "x is first used here"
+                ),
+                error(
+                    "label z is used more than once in a record expression",
+                    "z is first used here"
+                ),
+            ]
+        );
+        assert_eq!(
+            validate("val f = fn {a, b, a} => b"),
+            [error(
+                "label a is used more than once in a record pattern",
+                "a is first used here"
+            )]
+        );
+        assert_eq!(
+            validate("val f = fn (r : {a: int, b: {a: int} -> int}) => case r of {a, b} => b end"),
+            []
+        );
+    }
+
+    #[test]
+    fn constructors() {
+        assert_eq!(
+            validate("datatype t = Some of int | None | Some of bool"),
+            [error(
+                "constructor Some is declared more than once in the same datatype",
+                "Some is first declared here"
+            )]
+        );
+
+        // Constructors may be shadowed, but are warned about, and only once
+        // for each repeat within a datatype
+        let input = "datatype 'a option = None | Some of 'a; \
+                     datatype maybe = Some of int | Some of bool; \
+                     val x = let datatype u = None in None end; \
+                     structure M = struct datatype v = None end";
+        assert_eq!(
+            validate(input),
+            [
+                (
+                    Level::Warn,
+                    "constructor Some of datatype maybe shadows the constructor of the same name of datatype option"
+                        .into(),
+                    vec!["Some is first declared here".into()]
+                ),
+                error(
+                    "constructor Some is declared more than once in the same datatype",
+                    "Some is first declared here"
+                ),
+            ]
+        );
+        let input = "datatype a = A | B and datatype b = B | C";
+        assert_eq!(validate(input).len(), 1);
+    }
+}
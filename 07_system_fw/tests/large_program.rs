@@ -0,0 +1,66 @@
+//! Parse and check a program of 200 datatypes, each with a function
+//! matching on it, reporting the time taken and allocations made by each
+//! phase. The allocator counts every allocation in the process, so the
+//! benchmark is kept in a target of its own, away from the unit tests that
+//! would otherwise run alongside it. Run with
+//! `cargo test --release --test large_program -- --ignored --nocapture`
+//!
+//! With every AST node in a `Box` of its own, parsing makes 18809
+//! allocations and checking 1947534, taking about 2ms and 1.8s. These are
+//! the numbers an arena for the AST is to be measured against. Interning
+//! names as `Symbol`s took checking down to 1725370 allocations, and about
+//! 1.8s to 2.1s against 2.4s to 2.8s measured alongside, while parsing went
+//! up to 19232 for the table of the interner
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+use system_fw::driver::check;
+use system_fw::syntax::parser::Parser;
+
+/// Counts the allocations made by the benchmark
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+#[test]
+#[ignore]
+fn large_program() {
+    let input = (0..200)
+        .map(|i| {
+            format!(
+                "datatype 'a t{0} = A{0} | B{0} of 'a * 'a t{0}\n\
+                 fun f{0} x = case x of A{0} => 0 | B{0} (n, rest) => n end\n\
+                 val v{0} = f{0} (B{0} ({0}, A{0}))",
+                i
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let measure = |phase: &str, start: Instant, allocations: usize| {
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+        println!("{}: {:?}, {} allocations", phase, start.elapsed(), allocations);
+    };
+    let (start, allocations) = (Instant::now(), ALLOCATIONS.load(Ordering::Relaxed));
+    let program = Parser::new(&input).parse_program().unwrap();
+    measure("parsing", start, allocations);
+
+    let (start, allocations) = (Instant::now(), ALLOCATIONS.load(Ordering::Relaxed));
+    let checked = check(program, true).unwrap_or_else(|errors| panic!("{:?}", errors));
+    measure("checking", start, allocations);
+    assert_eq!(checked.elaborated.decls.len(), checked.program.decls.len());
+}
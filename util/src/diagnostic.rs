@@ -42,6 +42,11 @@ impl Diagnostic<'_> {
         ))
     }
 
+    /// Remove all remaining error messages, to be reported some other way
+    pub fn take(&mut self) -> Vec<Spanned<String>> {
+        std::mem::take(&mut self.messages)
+    }
+
     #[must_use]
     /// Emit all remaining error message, if there are any
     pub fn emit(mut self) -> String {
@@ -59,7 +64,7 @@ impl Diagnostic<'_> {
                 msg.span.start.line,
                 msg.span.start.col,
                 msg.data,
-                &lines[msg.span.start.line as usize],
+                lines.get(msg.span.start.line as usize).unwrap_or(&""),
                 (0..msg.span.start.col).map(|_| ' ').collect::<String>(),
                 squiggly
             ));
@@ -22,11 +22,47 @@ impl fmt::Display for Location {
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+/// Identifies the source file that a span is in, as an index into a
+/// [`SourceMap`]. Code read from a single string has the default id
+pub struct FileId(pub u32);
+
 #[derive(Copy, Clone, Debug, PartialEq, PartialOrd, Default)]
 /// A span of code
 pub struct Span {
     pub start: Location,
     pub end: Location,
+    pub file: FileId,
+}
+
+#[derive(Clone, Debug, Default)]
+/// The names and contents of the source files that spans point into
+pub struct SourceMap {
+    files: Vec<(String, String)>,
+}
+
+impl SourceMap {
+    /// Add a file, returning the id of the spans in it
+    pub fn add<N: Into<String>, S: Into<String>>(&mut self, name: N, source: S) -> FileId {
+        self.files.push((name.into(), source.into()));
+        FileId(self.files.len() as u32 - 1)
+    }
+
+    pub fn name(&self, file: FileId) -> &str {
+        &self.files[file.0 as usize].0
+    }
+
+    pub fn source(&self, file: FileId) -> &str {
+        &self.files[file.0 as usize].1
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
 }
 
 /// Data with associated code span
@@ -37,7 +73,22 @@ pub struct Spanned<T> {
 
 impl Span {
     pub fn new(start: Location, end: Location) -> Span {
-        Span { start, end }
+        Span {
+            start,
+            end,
+            file: FileId(0),
+        }
+    }
+
+    /// The same span, in another file
+    pub fn in_file(self, file: FileId) -> Span {
+        Span { file, ..self }
+    }
+
+    /// The text of `src` that this span covers, or nothing if the span does
+    /// not lie within `src`, as for [`Span::dummy`]
+    pub fn slice<'a>(&self, src: &'a str) -> &'a str {
+        src.get(self.start.abs as usize..self.end.abs as usize).unwrap_or("")
     }
 
     pub const fn dummy() -> Span {
@@ -46,7 +97,11 @@ impl Span {
             col: std::u32::MAX,
             abs: std::u32::MAX,
         };
-        Span { start: max, end: max }
+        Span {
+            start: max,
+            end: max,
+            file: FileId(0),
+        }
     }
 
     pub const fn zero() -> Span {
@@ -55,7 +110,11 @@ impl Span {
             col: 0,
             abs: 0,
         };
-        Span { start: max, end: max }
+        Span {
+            start: max,
+            end: max,
+            file: FileId(0),
+        }
     }
 }
 
@@ -149,6 +208,7 @@ impl std::ops::Add<Span> for Span {
         Span {
             start: self.start,
             end: rhs.end,
+            file: self.file,
         }
     }
 }